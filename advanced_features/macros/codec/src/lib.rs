@@ -0,0 +1,380 @@
+// A Simple Binary Serialization Format
+
+// Instead of pulling in serde, we hand-roll a small binary codec so we can see the mechanics of turning values into bytes and back.
+// The format is compact and has no field names or type information, both sides must agree on the shape of the data:
+    // 1. Unsigned integers are written as varints: 7 bits of the value per byte, with the high bit set when more bytes follow.
+    // 2. Signed integers are zigzag encoded first, so that small negative numbers also become small varints.
+    // 3. Strings and byte vectors are written as a varint length followed by the raw (UTF-8) bytes.
+    // 4. Structs are just their fields written one after the other, which is what #[derive(Serialize, Deserialize)] generates.
+
+// The codec started out as a module of the multithreaded webserver. It lives in its own crate now so that minigrep can save its
+// search index with it too, and the webserver's src/codec.rs only re-exports this crate.
+
+use std::fmt;
+
+pub use codec_derive::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEof,
+    VarintOverflow,
+    InvalidUtf8,
+    InvalidBool(u8),
+    InvalidTag(u64),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of input"),
+            DecodeError::VarintOverflow => write!(f, "varint is too long for a u64"),
+            DecodeError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            DecodeError::InvalidBool(b) => write!(f, "invalid bool byte {b}"),
+            DecodeError::InvalidTag(t) => write!(f, "invalid enum tag {t}"),
+            DecodeError::TrailingBytes(n) => write!(f, "{n} trailing bytes after value"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// The traits take the output buffer and the input slice as parameters rather than returning new buffers,
+// so that nested values all append to (or consume from) the same buffer without extra allocations.
+
+// Deserialize takes a &mut &[u8]: a mutable reference to a slice. Reading a value moves the slice forward past the bytes that were consumed,
+// which is the same trick the std::io::Read implementation for &[u8] uses.
+
+pub trait Serialize {
+    fn serialize(&self, out: &mut Vec<u8>);
+}
+
+pub trait Deserialize: Sized {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.serialize(&mut out);
+    out
+}
+
+// from_bytes expects the whole slice to be exactly one value, anything left over is reported as an error.
+pub fn from_bytes<T: Deserialize>(mut bytes: &[u8]) -> Result<T, DecodeError> {
+    let value = T::deserialize(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes(bytes.len()));
+    }
+    Ok(value)
+}
+
+// Varints
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub fn read_varint(input: &mut &[u8]) -> Result<u64, DecodeError> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+
+    loop {
+        let (&byte, rest) = input.split_first().ok_or(DecodeError::UnexpectedEof)?;
+        *input = rest;
+
+        // A u64 needs at most 10 bytes, and the 10th byte may only carry the single top bit
+        if shift == 63 && byte > 1 {
+            return Err(DecodeError::VarintOverflow);
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+// Zigzag encoding maps signed integers to unsigned ones: 0 => 0, -1 => 1, 1 => 2, -2 => 3, ...
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < len {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+// Implementations for the primitive types
+
+// The unsigned integer impls only differ in the type, so a declarative macro writes them for us.
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl Serialize for $t {
+                fn serialize(&self, out: &mut Vec<u8>) {
+                    write_varint(out, *self as u64);
+                }
+            }
+
+            impl Deserialize for $t {
+                fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                    let value = read_varint(input)?;
+                    <$t>::try_from(value).map_err(|_| DecodeError::VarintOverflow)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),*) => {
+        $(
+            impl Serialize for $t {
+                fn serialize(&self, out: &mut Vec<u8>) {
+                    write_varint(out, zigzag(*self as i64));
+                }
+            }
+
+            impl Deserialize for $t {
+                fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                    let value = unzigzag(read_varint(input)?);
+                    <$t>::try_from(value).map_err(|_| DecodeError::VarintOverflow)
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned!(u16, u32, u64, usize);
+impl_signed!(i8, i16, i32, i64);
+
+// A single byte doesn't benefit from varint encoding, so u8 is written as is.
+impl Serialize for u8 {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Deserialize for u8 {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(input, 1)?[0])
+    }
+}
+
+impl Serialize for bool {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Deserialize for bool {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::deserialize(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(DecodeError::InvalidBool(b)),
+        }
+    }
+}
+
+// Floats are written as their IEEE 754 bits in little endian order.
+impl Serialize for f64 {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Deserialize for f64 {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let bytes = take(input, 8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+impl Serialize for str {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        write_varint(out, self.len() as u64);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Serialize for String {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        self.as_str().serialize(out);
+    }
+}
+
+impl Deserialize for String {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::deserialize(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+// Vec<u8> falls under this impl as well, each byte costs exactly one byte because of the u8 impl above.
+impl<T: Serialize> Serialize for [T] {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        write_varint(out, self.len() as u64);
+        for item in self {
+            item.serialize(out);
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        self.as_slice().serialize(out);
+    }
+}
+
+impl<T: Deserialize> Deserialize for Vec<T> {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::deserialize(input)?;
+
+        // Don't trust the length prefix for the allocation, a corrupt length could ask for gigabytes.
+        // Every element takes at least one byte, so the remaining input bounds the capacity.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::deserialize(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Serialize> Serialize for Option<T> {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.serialize(out);
+            }
+        }
+    }
+}
+
+impl<T: Deserialize> Deserialize for Option<T> {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::deserialize(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::deserialize(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag as u64)),
+        }
+    }
+}
+
+impl<A: Serialize, B: Serialize> Serialize for (A, B) {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        self.0.serialize(out);
+        self.1.serialize(out);
+    }
+}
+
+impl<A: Deserialize, B: Deserialize> Deserialize for (A, B) {
+    fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::deserialize(input)?, B::deserialize(input)?))
+    }
+}
+
+impl<T: Serialize + ?Sized> Serialize for &T {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        (**self).serialize(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point(i32, i32);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle { center: Point, radius: u32 },
+        Polygon(Vec<Point>),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        name: String,
+        shapes: Vec<Shape>,
+        visible: bool,
+        scale: Option<f64>,
+    }
+
+    #[test]
+    fn varint_boundaries() {
+        for value in [0, 1, 127, 128, 300, 16_383, 16_384, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            assert_eq!(value, read_varint(&mut out.as_slice()).unwrap());
+        }
+
+        assert_eq!(vec![0xac, 0x02], to_bytes(&300u32));
+        assert_eq!(10, to_bytes(&u64::MAX).len());
+    }
+
+    #[test]
+    fn varint_errors() {
+        assert_eq!(Err(DecodeError::UnexpectedEof), from_bytes::<u64>(&[0x80]));
+        assert_eq!(Err(DecodeError::VarintOverflow), from_bytes::<u64>(&[0xff; 11]));
+        assert_eq!(Err(DecodeError::VarintOverflow), from_bytes::<u16>(&[0x80, 0x80, 0x04]));
+    }
+
+    #[test]
+    fn signed_values_use_zigzag() {
+        assert_eq!(vec![1], to_bytes(&-1i32));
+        assert_eq!(vec![2], to_bytes(&1i32));
+        for value in [i64::MIN, -300, -1, 0, 1, 300, i64::MAX] {
+            assert_eq!(value, from_bytes::<i64>(&to_bytes(&value)).unwrap());
+        }
+    }
+
+    #[test]
+    fn strings_are_length_prefixed() {
+        assert_eq!(vec![3, b'a', b'b', b'c'], to_bytes("abc"));
+        assert_eq!("héllo", from_bytes::<String>(&to_bytes("héllo")).unwrap());
+        assert_eq!(Err(DecodeError::InvalidUtf8), from_bytes::<String>(&[1, 0xff]));
+        assert_eq!(Err(DecodeError::UnexpectedEof), from_bytes::<String>(&[5, b'a']));
+    }
+
+    #[test]
+    fn derived_nested_round_trip() {
+        let drawing = Drawing {
+            name: String::from("house"),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle { center: Point(-3, 4), radius: 5 },
+                Shape::Polygon(vec![Point(0, 0), Point(10, 0), Point(5, 8)]),
+            ],
+            visible: true,
+            scale: Some(1.5),
+        };
+
+        let bytes = to_bytes(&drawing);
+        assert_eq!(drawing, from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(Err(DecodeError::InvalidTag(7)), from_bytes::<Shape>(&[7]));
+        assert_eq!(Err(DecodeError::InvalidBool(2)), from_bytes::<bool>(&[2]));
+        assert_eq!(Err(DecodeError::TrailingBytes(1)), from_bytes::<u8>(&[1, 2]));
+    }
+}
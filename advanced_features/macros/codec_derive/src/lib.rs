@@ -0,0 +1,150 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, Index};
+
+// Custom derive macros for the binary codec in ../codec, used by the multithreaded webserver and minigrep
+
+// Just like hello_macro_derive, the generated code names the traits directly (Serialize, Deserialize and DecodeError),
+// so the user has to bring them into scope with a use statement before deriving. The macro crate can't know the path of the crate that defines the traits.
+
+// Every field is written in declaration order, so a nested struct is simply a field whose type also implements the traits.
+// Enums write the variant index as a varint first and then the fields of that variant.
+
+#[proc_macro_derive(Serialize)]
+pub fn serialize_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_serialize(&ast)
+}
+
+#[proc_macro_derive(Deserialize)]
+pub fn deserialize_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_deserialize(&ast)
+}
+
+fn impl_serialize(ast: &DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+
+    let body = match &ast.data {
+        Data::Struct(data) => {
+            // For structs we access the fields through self, either by name or by position for tuple structs
+            let writes = match &data.fields {
+                Fields::Named(fields) => fields
+                    .named
+                    .iter()
+                    .map(|f| {
+                        let ident = &f.ident;
+                        quote! { Serialize::serialize(&self.#ident, out); }
+                    })
+                    .collect::<Vec<_>>(),
+                Fields::Unnamed(fields) => (0..fields.unnamed.len())
+                    .map(|i| {
+                        let index = Index::from(i);
+                        quote! { Serialize::serialize(&self.#index, out); }
+                    })
+                    .collect(),
+                Fields::Unit => Vec::new(),
+            };
+            quote! { #(#writes)* }
+        }
+        Data::Enum(data) => {
+            // For enums we match on self, bind every field to a variable and write the variant index before them
+            let arms = data.variants.iter().enumerate().map(|(i, variant)| {
+                let vname = &variant.ident;
+                let tag = i as u64;
+                match &variant.fields {
+                    Fields::Named(fields) => {
+                        let idents: Vec<_> = fields.named.iter().map(|f| &f.ident).collect();
+                        quote! {
+                            #name::#vname { #(#idents),* } => {
+                                Serialize::serialize(&#tag, out);
+                                #( Serialize::serialize(#idents, out); )*
+                            }
+                        }
+                    }
+                    Fields::Unnamed(fields) => {
+                        let idents: Vec<_> = (0..fields.unnamed.len())
+                            .map(|i| format_ident!("field{}", i))
+                            .collect();
+                        quote! {
+                            #name::#vname ( #(#idents),* ) => {
+                                Serialize::serialize(&#tag, out);
+                                #( Serialize::serialize(#idents, out); )*
+                            }
+                        }
+                    }
+                    Fields::Unit => quote! {
+                        #name::#vname => Serialize::serialize(&#tag, out),
+                    },
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => panic!("Serialize can't be derived for unions"),
+    };
+
+    let gen = quote! {
+        impl Serialize for #name {
+            fn serialize(&self, out: &mut Vec<u8>) {
+                #body
+            }
+        }
+    };
+    gen.into()
+}
+
+fn impl_deserialize(ast: &DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+
+    // Reading a value back mirrors the writes above: the fields are read in the same order they were written
+    let read_fields = |path: proc_macro2::TokenStream, fields: &Fields| match fields {
+        Fields::Named(fields) => {
+            let reads = fields.named.iter().map(|f| {
+                let ident = &f.ident;
+                quote! { #ident: Deserialize::deserialize(input)? }
+            });
+            quote! { #path { #(#reads),* } }
+        }
+        Fields::Unnamed(fields) => {
+            let reads = fields
+                .unnamed
+                .iter()
+                .map(|_| quote! { Deserialize::deserialize(input)? });
+            quote! { #path ( #(#reads),* ) }
+        }
+        Fields::Unit => quote! { #path },
+    };
+
+    let body = match &ast.data {
+        Data::Struct(data) => read_fields(quote! { #name }, &data.fields),
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(i, variant)| {
+                let vname = &variant.ident;
+                let tag = i as u64;
+                let value = read_fields(quote! { #name::#vname }, &variant.fields);
+                quote! { #tag => #value, }
+            });
+            quote! {
+                let tag: u64 = Deserialize::deserialize(input)?;
+                match tag {
+                    #(#arms)*
+                    other => return Err(DecodeError::InvalidTag(other)),
+                }
+            }
+        }
+        Data::Union(_) => panic!("Deserialize can't be derived for unions"),
+    };
+
+    let gen = quote! {
+        impl Deserialize for #name {
+            fn deserialize(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                Ok({ #body })
+            }
+        }
+    };
+    gen.into()
+}
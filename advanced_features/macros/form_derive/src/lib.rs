@@ -0,0 +1,62 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Type};
+
+// Custom derive macro for the FromForm trait of the multithreaded webserver (see projects/multithreaded_webserver/src/form.rs)
+
+// Like codec_derive, the generated code names FromForm, FormData and FormError directly, so they have to be in scope where the derive is used.
+
+// Each field is looked up in the form by its name and parsed with FromStr:
+    // 1. A field of type Option<T> is None when the form doesn't have it.
+    // 2. Any other field is required, and a missing one is reported as FormError::Missing with the field name.
+
+#[proc_macro_derive(FromForm)]
+pub fn from_form_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_from_form(&ast)
+}
+
+// We only look at the last segment of the path, so both Option<T> and std::option::Option<T> are recognised
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+fn impl_from_form(ast: &DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+
+    let fields = match &ast.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => panic!("FromForm can only be derived for structs with named fields"),
+        },
+        _ => panic!("FromForm can only be derived for structs"),
+    };
+
+    let inits = fields.iter().map(|f| {
+        let ident = f.ident.as_ref().unwrap();
+        let key = ident.to_string();
+        if is_option(&f.ty) {
+            quote! { #ident: form.parse_optional(#key)? }
+        } else {
+            quote! { #ident: form.parse_field(#key)? }
+        }
+    });
+
+    let gen = quote! {
+        impl FromForm for #name {
+            fn from_form(form: &FormData) -> Result<Self, FormError> {
+                Ok(#name {
+                    #(#inits),*
+                })
+            }
+        }
+    };
+    gen.into()
+}
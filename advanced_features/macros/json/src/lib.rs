@@ -0,0 +1,503 @@
+// A Streaming JSON Writer
+
+// The webserver and the kvstore used to build JSON with format! and a json_escape function: fine for an error message,
+// but a big answer, like a thousand rows of a query, is then built as one String before the first byte of it is sent.
+// A JSON library would usually build a tree of values first (serde_json::Value) and print that, which holds the whole
+// answer in memory twice. JsonWriter does neither, it writes every token straight into an io::Write as it's given:
+    // 1. begin_object/end_object and begin_array/end_array open and close the containers, key() names the next value of
+    //    an object, and string(), u64(), i64(), f64(), bool() and null() write the values.
+    // 2. It keeps a stack of the open containers, so it knows where the commas go, and how far to indent when it's pretty.
+    // 3. Strings are escaped on the way out, the runs of characters that need no escaping are written as they are.
+// Several values at the top level are written one per line, which is JSON Lines, the format of a log with a JSON object
+// per line.
+
+// Using it wrong, like a value in an object without a key first, or closing an array that's an object, is a bug in the
+// caller, not something that depends on the data, so it panics. Errors of the writer itself are returned as io::Error.
+
+// ToJson is the trait for anything that knows how to write itself, like Serialize of the codec crate, and
+// #[derive(ToJson)] writes it for structs and enums (../json_derive):
+    // 1. A struct with named fields is an object, a tuple struct an array, and a struct with one field the JSON of that field.
+    // 2. A unit variant of an enum is its name as a string. Any other variant is an object with its name as the only key,
+    //    like {"Circle": {"radius": 2}}. That's what serde does by default too.
+    // 3. Option is the value or null, Vec and slices are arrays, and maps with string keys are objects.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::BuildHasher,
+    io::{self, Write},
+    rc::Rc,
+    sync::Arc,
+};
+
+pub use json_derive::ToJson;
+
+// An open array or object
+#[derive(Debug, Clone, Copy)]
+struct Scope {
+    object: bool,
+    // Nothing was written in it yet: no comma before the first value, and nothing between the brackets
+    empty: bool,
+}
+
+pub struct JsonWriter<W: Write> {
+    out: W,
+    // The spaces of one level when pretty printing, None for the compact form
+    indent: Option<usize>,
+    scopes: Vec<Scope>,
+    // key() was called, the next value is that key's
+    after_key: bool,
+    // How many values were written at the top level
+    values: usize,
+}
+
+impl<W: Write> JsonWriter<W> {
+    // Compact, without any whitespace: {"a":[1,2]}
+    pub fn new(out: W) -> JsonWriter<W> {
+        JsonWriter { out, indent: None, scopes: Vec::new(), after_key: false, values: 0 }
+    }
+
+    // Every value on a line of its own, indented by two spaces a level
+    pub fn pretty(out: W) -> JsonWriter<W> {
+        JsonWriter { indent: Some(2), ..JsonWriter::new(out) }
+    }
+
+    pub fn begin_object(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.scopes.push(Scope { object: true, empty: true });
+        self.out.write_all(b"{")
+    }
+
+    pub fn end_object(&mut self) -> io::Result<()> {
+        self.end(true, b"}")
+    }
+
+    pub fn begin_array(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.scopes.push(Scope { object: false, empty: true });
+        self.out.write_all(b"[")
+    }
+
+    pub fn end_array(&mut self) -> io::Result<()> {
+        self.end(false, b"]")
+    }
+
+    // The name of the next value, inside an object
+    pub fn key(&mut self, name: &str) -> io::Result<()> {
+        match self.scopes.last() {
+            Some(scope) if scope.object && !self.after_key => {}
+            Some(scope) if scope.object => panic!("JsonWriter: key {name:?} right after another key"),
+            _ => panic!("JsonWriter: key {name:?} outside of an object"),
+        }
+        self.separate()?;
+        self.write_string(name)?;
+        self.out.write_all(if self.indent.is_some() { b": " } else { b":" })?;
+        self.after_key = true;
+        Ok(())
+    }
+
+    // key() and the value, the usual way to write an object's fields
+    pub fn field<T: ToJson + ?Sized>(&mut self, name: &str, value: &T) -> io::Result<()> {
+        self.key(name)?;
+        value.to_json(self)
+    }
+
+    pub fn value<T: ToJson + ?Sized>(&mut self, value: &T) -> io::Result<()> {
+        value.to_json(self)
+    }
+
+    pub fn string(&mut self, text: &str) -> io::Result<()> {
+        self.before_value()?;
+        self.write_string(text)
+    }
+
+    pub fn u64(&mut self, n: u64) -> io::Result<()> {
+        self.before_value()?;
+        write!(self.out, "{n}")
+    }
+
+    pub fn i64(&mut self, n: i64) -> io::Result<()> {
+        self.before_value()?;
+        write!(self.out, "{n}")
+    }
+
+    // JSON has no NaN or infinity, those are written as null
+    pub fn f64(&mut self, n: f64) -> io::Result<()> {
+        if !n.is_finite() {
+            return self.null();
+        }
+        self.before_value()?;
+        write!(self.out, "{n}")
+    }
+
+    pub fn bool(&mut self, b: bool) -> io::Result<()> {
+        self.before_value()?;
+        self.out.write_all(if b { b"true" } else { b"false" })
+    }
+
+    pub fn null(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.out.write_all(b"null")
+    }
+
+    // JSON that's encoded already, written as it is. Nothing checks that it's valid
+    pub fn raw(&mut self, json: &str) -> io::Result<()> {
+        self.before_value()?;
+        self.out.write_all(json.as_bytes())
+    }
+
+    // The writer back, flushed. Panics when an array or an object is still open
+    pub fn finish(mut self) -> io::Result<W> {
+        assert!(self.scopes.is_empty(), "JsonWriter: finished with {} arrays or objects still open", self.scopes.len());
+        if self.values > 0 && self.indent.is_some() {
+            self.out.write_all(b"\n")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    // Commas, line breaks and indents
+    fn before_value(&mut self) -> io::Result<()> {
+        if self.after_key {
+            self.after_key = false;
+            return Ok(());
+        }
+        match self.scopes.last() {
+            Some(scope) if scope.object => panic!("JsonWriter: a value in an object needs a key first"),
+            Some(_) => self.separate(),
+            None => {
+                self.values += 1;
+                if self.values > 1 {
+                    self.out.write_all(b"\n")?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Before a key, or a value in an array
+    fn separate(&mut self) -> io::Result<()> {
+        let depth = self.scopes.len();
+        let scope = self.scopes.last_mut().expect("inside an array or an object");
+        if !scope.empty {
+            self.out.write_all(b",")?;
+        }
+        scope.empty = false;
+        self.newline(depth)
+    }
+
+    fn end(&mut self, object: bool, bracket: &[u8]) -> io::Result<()> {
+        let name = if object { "an object" } else { "an array" };
+        match self.scopes.pop() {
+            Some(scope) if scope.object == object && !self.after_key => {
+                if !scope.empty {
+                    self.newline(self.scopes.len())?;
+                }
+                self.out.write_all(bracket)
+            }
+            Some(scope) if scope.object == object => panic!("JsonWriter: {name} ended right after a key"),
+            _ => panic!("JsonWriter: ending {name} that isn't open"),
+        }
+    }
+
+    fn newline(&mut self, depth: usize) -> io::Result<()> {
+        match self.indent {
+            Some(indent) => write!(self.out, "\n{:width$}", "", width = indent * depth),
+            None => Ok(()),
+        }
+    }
+
+    fn write_string(&mut self, text: &str) -> io::Result<()> {
+        self.out.write_all(b"\"")?;
+        let bytes = text.as_bytes();
+        // The start of the run of bytes that don't need escaping
+        let mut start = 0;
+        for (i, &b) in bytes.iter().enumerate() {
+            let escaped: &[u8] = match b {
+                b'"' => b"\\\"",
+                b'\\' => b"\\\\",
+                b'\n' => b"\\n",
+                b'\r' => b"\\r",
+                b'\t' => b"\\t",
+                // The other control characters have no short escape
+                0..=0x1f => {
+                    self.out.write_all(&bytes[start..i])?;
+                    write!(self.out, "\\u{b:04x}")?;
+                    start = i + 1;
+                    continue;
+                }
+                _ => continue,
+            };
+            self.out.write_all(&bytes[start..i])?;
+            self.out.write_all(escaped)?;
+            start = i + 1;
+        }
+        self.out.write_all(&bytes[start..])?;
+        self.out.write_all(b"\"")
+    }
+}
+
+pub trait ToJson {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()>;
+}
+
+pub fn to_writer<W: Write, T: ToJson + ?Sized>(out: W, value: &T) -> io::Result<W> {
+    let mut json = JsonWriter::new(out);
+    value.to_json(&mut json)?;
+    json.finish()
+}
+
+pub fn to_string<T: ToJson + ?Sized>(value: &T) -> String {
+    let bytes = to_writer(Vec::new(), value).expect("writing to a Vec can't fail");
+    String::from_utf8(bytes).expect("JsonWriter only writes UTF-8")
+}
+
+pub fn to_string_pretty<T: ToJson + ?Sized>(value: &T) -> String {
+    let mut json = JsonWriter::pretty(Vec::new());
+    value.to_json(&mut json).expect("writing to a Vec can't fail");
+    String::from_utf8(json.finish().expect("writing to a Vec can't fail")).expect("JsonWriter only writes UTF-8")
+}
+
+// Implementations for the standard types
+
+// The same macro trick as the codec crate: the integer impls only differ in the type and the method they call
+macro_rules! impl_integer {
+    ($method:ident as $wide:ty: $($t:ty),*) => {
+        $(
+            impl ToJson for $t {
+                fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+                    json.$method(*self as $wide)
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(u64 as u64: u8, u16, u32, u64, usize);
+impl_integer!(i64 as i64: i8, i16, i32, i64, isize);
+impl_integer!(f64 as f64: f32, f64);
+
+impl ToJson for bool {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.bool(*self)
+    }
+}
+
+impl ToJson for str {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.string(self)
+    }
+}
+
+impl ToJson for String {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.string(self)
+    }
+}
+
+impl ToJson for char {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.string(self.encode_utf8(&mut [0; 4]))
+    }
+}
+
+impl ToJson for () {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.null()
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        match self {
+            Some(value) => value.to_json(json),
+            None => json.null(),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.begin_array()?;
+        for value in self {
+            value.to_json(json)?;
+        }
+        json.end_array()
+    }
+}
+
+impl<T: ToJson, const N: usize> ToJson for [T; N] {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        self.as_slice().to_json(json)
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        self.as_slice().to_json(json)
+    }
+}
+
+// Pointers are written as what they point to
+macro_rules! impl_pointer {
+    ($($t:ty),*) => {
+        $(
+            impl<T: ToJson + ?Sized> ToJson for $t {
+                fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+                    (**self).to_json(json)
+                }
+            }
+        )*
+    };
+}
+
+impl_pointer!(&T, &mut T, Box<T>, Rc<T>, Arc<T>);
+
+// Tuples are arrays, like (name, count) pairs
+macro_rules! impl_tuple {
+    ($($t:ident $index:tt),*) => {
+        impl<$($t: ToJson),*> ToJson for ($($t,)*) {
+            fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+                json.begin_array()?;
+                $(self.$index.to_json(json)?;)*
+                json.end_array()
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+
+// Maps with string keys are objects. A HashMap's keys come out in whatever order it keeps them
+impl<K: AsRef<str>, V: ToJson> ToJson for BTreeMap<K, V> {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.begin_object()?;
+        for (key, value) in self {
+            json.field(key.as_ref(), value)?;
+        }
+        json.end_object()
+    }
+}
+
+impl<K: AsRef<str>, V: ToJson, S: BuildHasher> ToJson for HashMap<K, V, S> {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.begin_object()?;
+        for (key, value) in self {
+            json.field(key.as_ref(), value)?;
+        }
+        json.end_object()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(ToJson)]
+    struct Point(i32, i32);
+
+    #[derive(ToJson)]
+    struct Meters(f64);
+
+    #[derive(ToJson)]
+    enum Shape {
+        Empty,
+        Circle { center: Point, radius: Meters },
+        Polygon(Vec<Point>),
+    }
+
+    #[derive(ToJson)]
+    struct Drawing {
+        name: String,
+        shapes: Vec<Shape>,
+        visible: bool,
+        scale: Option<f64>,
+    }
+
+    fn drawing() -> Drawing {
+        Drawing {
+            name: String::from("a \"house\"\n"),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle { center: Point(0, -1), radius: Meters(2.5) },
+                Shape::Polygon(vec![Point(0, 0), Point(1, 1)]),
+            ],
+            visible: true,
+            scale: None,
+        }
+    }
+
+    #[test]
+    fn writes_compact_json() {
+        let expected = concat!(
+            r#"{"name":"a \"house\"\n","shapes":["Empty",{"Circle":{"center":[0,-1],"radius":2.5}},"#,
+            r#"{"Polygon":[[0,0],[1,1]]}],"visible":true,"scale":null}"#
+        );
+        assert_eq!(expected, to_string(&drawing()));
+
+        assert_eq!(r#""tab\tbell\u0007 é""#, to_string("tab\tbell\u{7} é"));
+        assert_eq!("[null,0.5,null]", to_string(&[f64::NAN, 0.5, f64::INFINITY]));
+        let counters = BTreeMap::from([("hits", 3u64), ("misses", 1)]);
+        assert_eq!(r#"{"hits":3,"misses":1}"#, to_string(&counters));
+        assert_eq!(r#"[["a",1],[]]"#, to_string(&(("a", 1u8), Vec::<u8>::new())));
+    }
+
+    #[test]
+    fn writes_pretty_json() {
+        let expected = r#"{
+  "name": "a \"house\"\n",
+  "shapes": [
+    "Empty",
+    {
+      "Circle": {
+        "center": [
+          0,
+          -1
+        ],
+        "radius": 2.5
+      }
+    },
+    {
+      "Polygon": []
+    }
+  ],
+  "visible": true,
+  "scale": {}
+}
+"#;
+        let mut drawing = drawing();
+        drawing.shapes[2] = Shape::Polygon(Vec::new());
+        // An empty object, written by hand for the scale
+        let mut json = JsonWriter::pretty(Vec::new());
+        json.begin_object().unwrap();
+        json.field("name", &drawing.name).unwrap();
+        json.field("shapes", &drawing.shapes).unwrap();
+        json.field("visible", &drawing.visible).unwrap();
+        json.key("scale").unwrap();
+        json.begin_object().unwrap();
+        json.end_object().unwrap();
+        json.end_object().unwrap();
+        assert_eq!(expected, String::from_utf8(json.finish().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn streams_json_lines() {
+        let mut json = JsonWriter::new(Vec::new());
+        for n in 1..=3u32 {
+            json.begin_object().unwrap();
+            json.field("n", &n).unwrap();
+            json.end_object().unwrap();
+        }
+        json.raw("[true]").unwrap();
+        assert_eq!("{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n[true]", String::from_utf8(json.finish().unwrap()).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "needs a key first")]
+    fn values_in_objects_need_keys() {
+        let mut json = JsonWriter::new(Vec::new());
+        json.begin_object().unwrap();
+        json.u64(1).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, Index};
+
+// A custom derive macro for the ToJson trait of the streaming JSON writer in ../json
+
+// Like codec_derive, the generated code names ToJson and JsonWriter directly, so the user brings both into scope with
+// use json::{JsonWriter, ToJson} before deriving.
+
+// Structs with named fields become objects with a key per field, in declaration order, tuple structs become arrays and a
+// struct with a single unnamed field is written as that field, so a newtype like Meters(f64) is just a number.
+// A unit variant of an enum is written as its name, any other variant as an object with the name as the only key.
+
+#[proc_macro_derive(ToJson)]
+pub fn to_json_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_to_json(&ast)
+}
+
+// The writes for a list of fields that are bound to variables, by name or as field0, field1..
+fn write_fields(fields: &Fields, bindings: &[proc_macro2::TokenStream]) -> proc_macro2::TokenStream {
+    match fields {
+        Fields::Named(fields) => {
+            // r#type is written as "type"
+            let keys = fields.named.iter().map(|f| f.ident.as_ref().unwrap().to_string().trim_start_matches("r#").to_string());
+            quote! {
+                json.begin_object()?;
+                #( json.field(#keys, #bindings)?; )*
+                json.end_object()
+            }
+        }
+        Fields::Unnamed(_) if bindings.len() == 1 => {
+            let binding = &bindings[0];
+            quote! { ToJson::to_json(#binding, json) }
+        }
+        Fields::Unnamed(_) => quote! {
+            json.begin_array()?;
+            #( ToJson::to_json(#bindings, json)?; )*
+            json.end_array()
+        },
+        Fields::Unit => quote! { json.null() },
+    }
+}
+
+fn impl_to_json(ast: &DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+
+    let body = match &ast.data {
+        Data::Struct(data) => {
+            // The fields are borrowed through self, by name or by position
+            let bindings: Vec<_> = match &data.fields {
+                Fields::Named(fields) => fields
+                    .named
+                    .iter()
+                    .map(|f| {
+                        let ident = &f.ident;
+                        quote! { &self.#ident }
+                    })
+                    .collect(),
+                Fields::Unnamed(fields) => (0..fields.unnamed.len())
+                    .map(|i| {
+                        let index = Index::from(i);
+                        quote! { &self.#index }
+                    })
+                    .collect(),
+                Fields::Unit => Vec::new(),
+            };
+            write_fields(&data.fields, &bindings)
+        }
+        Data::Enum(data) => {
+            // We match on self and bind every field of the variant to a variable, which is already a reference
+            let arms = data.variants.iter().map(|variant| {
+                let vname = &variant.ident;
+                let key = vname.to_string();
+                let (pattern, idents) = match &variant.fields {
+                    Fields::Named(fields) => {
+                        let idents: Vec<_> = fields.named.iter().map(|f| f.ident.clone().unwrap()).collect();
+                        (quote! { #name::#vname { #(#idents),* } }, idents)
+                    }
+                    Fields::Unnamed(fields) => {
+                        let idents: Vec<_> = (0..fields.unnamed.len()).map(|i| format_ident!("field{}", i)).collect();
+                        (quote! { #name::#vname ( #(#idents),* ) }, idents)
+                    }
+                    Fields::Unit => return quote! { #name::#vname => json.string(#key), },
+                };
+                let bindings: Vec<_> = idents.iter().map(|ident| quote! { #ident }).collect();
+                let value = write_fields(&variant.fields, &bindings);
+                quote! {
+                    #pattern => {
+                        json.begin_object()?;
+                        json.key(#key)?;
+                        #value?;
+                        json.end_object()
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => panic!("ToJson can't be derived for unions"),
+    };
+
+    let gen = quote! {
+        impl ToJson for #name {
+            fn to_json<W: ::std::io::Write>(&self, json: &mut JsonWriter<W>) -> ::std::io::Result<()> {
+                #body
+            }
+        }
+    };
+    gen.into()
+}
@@ -0,0 +1,93 @@
+// Trie vs Sorted Vec Autocomplete Benchmark
+
+// Run with: cargo bench --bench trie
+
+// The #[bench] attribute is still nightly only, so this is a plain binary (harness = false in Cargo.toml) that times the two approaches with Instant.
+// The baseline keeps the words in a sorted Vec: binary search finds the first word with the prefix, then we scan forward while words still match.
+
+use std::{collections::HashMap, hint::black_box, time::Instant};
+
+use std_collections::trie::Trie;
+
+// A small deterministic word generator (a linear congruential generator picking syllables), so every run benchmarks the same data
+fn words(count: usize) -> Vec<String> {
+    let syllables = ["ka", "ri", "to", "ne", "su", "mo", "la", "chi", "ve", "dan"];
+    let mut state: u64 = 42;
+    let mut next = move || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as usize
+    };
+
+    (0..count)
+        .map(|_| {
+            let len = 1 + next() % 4;
+            (0..len).map(|_| syllables[next() % syllables.len()]).collect()
+        })
+        .collect()
+}
+
+struct SortedVec {
+    words: Vec<(String, u64)>,
+}
+
+impl SortedVec {
+    fn new(input: &[String]) -> SortedVec {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for word in input {
+            *counts.entry(word).or_insert(0) += 1;
+        }
+        let mut words: Vec<(String, u64)> = counts.into_iter().map(|(w, c)| (w.to_string(), c)).collect();
+        words.sort();
+        SortedVec { words }
+    }
+
+    fn suggest(&self, prefix: &str, k: usize) -> Vec<(String, u64)> {
+        let start = self.words.partition_point(|(w, _)| w.as_str() < prefix);
+        let mut matches: Vec<(String, u64)> = self.words[start..]
+            .iter()
+            .take_while(|(w, _)| w.starts_with(prefix))
+            .cloned()
+            .collect();
+        matches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        matches.truncate(k);
+        matches
+    }
+}
+
+fn time<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let per_iter = start.elapsed() / iterations;
+    println!("{name:<30} {per_iter:>12?} per iteration");
+}
+
+fn main() {
+    let input = words(50_000);
+    let prefixes = ["k", "ka", "kari", "su", "chive", "dan", "x"];
+
+    time("build trie", 10, || {
+        black_box(input.iter().map(|w| w.as_str()).collect::<Trie>());
+    });
+    time("build sorted vec", 10, || {
+        black_box(SortedVec::new(&input));
+    });
+
+    let trie: Trie = input.iter().map(|w| w.as_str()).collect();
+    let sorted = SortedVec::new(&input);
+
+    // Both structures must agree before their speed means anything
+    for prefix in prefixes {
+        assert_eq!(trie.suggest(prefix, 5), sorted.suggest(prefix, 5));
+    }
+
+    for prefix in prefixes {
+        time(&format!("trie suggest({prefix:?}, 5)"), 200, || {
+            black_box(trie.suggest(black_box(prefix), 5));
+        });
+        time(&format!("sorted vec suggest({prefix:?}, 5)"), 200, || {
+            black_box(sorted.suggest(black_box(prefix), 5));
+        });
+    }
+}
@@ -0,0 +1,333 @@
+// ArrayVec: A Vector on the Stack
+
+// A Vec keeps its elements on the heap, so even a Vec of three things is an allocation, and a free when it's dropped.
+// When the most there can ever be is known and small, the elements can live right inside the value instead: an
+// ArrayVec<T, N> is an array of N slots and a length, and it's as big as that, wherever it's stored.
+
+    // let mut parts: ArrayVec<&str, 3> = ArrayVec::new();
+    // parts.extend(["GET", "/", "HTTP/1.1"]);
+    // assert_eq!(Err(CapacityError("four")), parts.try_push("four"));
+
+// N is a const generic: a value that's part of the type, like the 3 in [T; 3]. ArrayVec<u8, 16> and ArrayVec<u8, 32>
+// are different types, and the compiler knows the size of both.
+    // 1. push panics when it's full, like indexing past the end of a slice, and try_push gives the value back instead.
+    // 2. It derefs to a slice, so iter(), sort(), binary_search(), indexing and the rest of [T] work as they are.
+    // 3. Only the first len slots hold values, the rest are uninitialized memory. MaybeUninit<T> is a T that may not be
+    //    there yet, and the unsafe blocks below are where this code promises the compiler a slot holds one.
+// The arrayvec crate is the full version of this, with the same name.
+
+use std::{
+    fmt,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    ptr, slice,
+};
+
+// The value that didn't fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError<T>(pub T);
+
+impl<T> fmt::Display for CapacityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the ArrayVec is full")
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for CapacityError<T> {}
+
+pub struct ArrayVec<T, const N: usize> {
+    // The first len are initialized
+    slots: [MaybeUninit<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> ArrayVec<T, N> {
+    pub const fn new() -> ArrayVec<T, N> {
+        ArrayVec { slots: [const { MaybeUninit::uninit() }; N], len: 0 }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn try_push(&mut self, value: T) -> Result<(), CapacityError<T>> {
+        if self.is_full() {
+            return Err(CapacityError(value));
+        }
+        self.slots[self.len].write(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.try_push(value).is_err() {
+            panic!("push to a full ArrayVec of capacity {N}");
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        // SAFETY: the slot was below len, so it holds a value, and lowering len first means nothing reads it again
+        Some(unsafe { self.slots[self.len].assume_init_read() })
+    }
+
+    // Moves the ones after index up a slot, so it's O(n) like Vec::insert. Panics when index > len or it's full
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(index <= self.len, "insertion index {index} is past the length {}", self.len);
+        assert!(!self.is_full(), "insert into a full ArrayVec of capacity {N}");
+        let base = self.slots.as_mut_ptr();
+        // SAFETY: index..len are initialized and move to index+1..len+1, which is within capacity since it isn't full.
+        // ptr::copy is memmove, the two ranges may overlap
+        unsafe { ptr::copy(base.add(index), base.add(index + 1), self.len - index) };
+        self.slots[index].write(value);
+        self.len += 1;
+    }
+
+    // Moves the ones after index down a slot. Panics when index >= len
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index {index} is past the length {}", self.len);
+        let base = self.slots.as_mut_ptr();
+        // SAFETY: the slot at index is initialized, it's read out and the initialized index+1..len move over it
+        unsafe {
+            let value = self.slots[index].assume_init_read();
+            ptr::copy(base.add(index + 1), base.add(index), self.len - index - 1);
+            self.len -= 1;
+            value
+        }
+    }
+
+    // Drops the ones from len on
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        // SAFETY: the first len slots are initialized, and MaybeUninit<T> has the same layout as T
+        unsafe { slice::from_raw_parts(self.slots.as_ptr().cast::<T>(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        // SAFETY: as in as_slice
+        unsafe { slice::from_raw_parts_mut(self.slots.as_mut_ptr().cast::<T>(), self.len) }
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayVec<T, N> {
+    fn drop(&mut self) {
+        // The array of MaybeUninit drops nothing by itself
+        self.clear();
+    }
+}
+
+impl<T, const N: usize> Default for ArrayVec<T, N> {
+    fn default() -> Self {
+        ArrayVec::new()
+    }
+}
+
+impl<T, const N: usize> Deref for ArrayVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> DerefMut for ArrayVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for ArrayVec<T, N> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for ArrayVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for ArrayVec<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for ArrayVec<T, N> {}
+
+// Panics when there are more than N, like push
+impl<T, const N: usize> Extend<T> for ArrayVec<T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, const N: usize> FromIterator<T> for ArrayVec<T, N> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut array = ArrayVec::new();
+        array.extend(iter);
+        array
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a ArrayVec<T, N> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> slice::Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a mut ArrayVec<T, N> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> slice::IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+// The values by value, front to back. Those that weren't taken are dropped with it
+pub struct IntoIter<T, const N: usize> {
+    array: ArrayVec<T, N>,
+    // The first one not taken yet, the ones before it have been moved out
+    front: usize,
+}
+
+impl<T, const N: usize> IntoIterator for ArrayVec<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    fn into_iter(self) -> IntoIter<T, N> {
+        IntoIter { array: self, front: 0 }
+    }
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.front == self.array.len {
+            return None;
+        }
+        self.front += 1;
+        // SAFETY: front..len are initialized and front moves past the slot, so it's read once
+        Some(unsafe { self.array.slots[self.front - 1].assume_init_read() })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.array.len - self.front;
+        (left, Some(left))
+    }
+}
+
+impl<T, const N: usize> DoubleEndedIterator for IntoIter<T, N> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.front == self.array.len {
+            return None;
+        }
+        self.array.pop()
+    }
+}
+
+impl<T, const N: usize> ExactSizeIterator for IntoIter<T, N> {}
+
+impl<T, const N: usize> Drop for IntoIter<T, N> {
+    fn drop(&mut self) {
+        let (front, len) = (self.front, self.array.len);
+        // The ArrayVec's own Drop would drop the ones that were moved out as well
+        self.array.len = 0;
+        for slot in &mut self.array.slots[front..len] {
+            // SAFETY: front..len haven't been taken, and len is 0 now so nothing drops them again
+            unsafe { slot.assume_init_drop() };
+        }
+    }
+}
+
+// The Vec with the same elements, for when it has to grow after all
+impl<T, const N: usize> From<ArrayVec<T, N>> for Vec<T> {
+    fn from(array: ArrayVec<T, N>) -> Vec<T> {
+        let mut vec = Vec::with_capacity(array.len());
+        vec.extend(array);
+        vec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{mem, rc::Rc};
+
+    #[test]
+    fn pushes_pops_and_edits_in_place() {
+        let mut parts: ArrayVec<&str, 3> = ArrayVec::new();
+        parts.push("GET");
+        parts.extend(["/", "HTTP/1.1"]);
+        assert_eq!(Err(CapacityError("four")), parts.try_push("four"));
+        assert_eq!(["GET", "/", "HTTP/1.1"], parts.as_slice());
+        assert_eq!(Some("HTTP/1.1"), parts.pop());
+
+        parts.insert(0, "POST");
+        assert_eq!("POST GET /", parts.join(" "));
+        assert_eq!("GET", parts.remove(1));
+        parts.sort();
+        assert_eq!(vec!["/", "POST"], Vec::from(parts.clone()));
+        assert_eq!((2, 3, false), (parts.len(), parts.capacity(), parts.is_full()));
+        // The slots and the length, nothing on the heap
+        assert_eq!(mem::size_of::<[u32; 8]>() + mem::size_of::<usize>(), mem::size_of::<ArrayVec<u32, 8>>());
+    }
+
+    #[test]
+    fn drops_every_value_once() {
+        let counted = Rc::new(());
+        let mut values: ArrayVec<Rc<()>, 4> = (0..4).map(|_| Rc::clone(&counted)).collect();
+        values.truncate(3);
+        assert_eq!(4, Rc::strong_count(&counted));
+
+        // Two taken from the iterator, one dropped with it
+        let mut iter = values.into_iter();
+        let first = iter.next().unwrap();
+        let last = iter.next_back().unwrap();
+        assert_eq!((1, 4), (iter.len(), Rc::strong_count(&counted)));
+        drop(iter);
+        assert_eq!(3, Rc::strong_count(&counted));
+        drop((first, last));
+        assert_eq!(1, Rc::strong_count(&counted));
+    }
+
+    #[test]
+    #[should_panic(expected = "push to a full ArrayVec of capacity 1")]
+    fn push_panics_when_full() {
+        let mut one: ArrayVec<u8, 1> = ArrayVec::new();
+        one.push(1);
+        one.push(2);
+    }
+}
@@ -0,0 +1,427 @@
+// Bit Vectors
+
+// A Vec<bool> spends a whole byte on every bool. BitVec packs 64 of them into each u64 instead, which is 8 times smaller,
+// and lets whole words be combined at once: the AND of two BitVecs of 640 bits is 10 machine instructions, not 640.
+// Bit i lives in word i / 64, at position i % 64 counting from the least significant bit.
+
+// The bits past len in the last word are always kept at 0. Everything that counts bits or compares BitVecs relies on that,
+// so the operations that could set them (pop, truncate, !) clear them again.
+
+// Bit-twiddling idioms used below:
+    // 1. word & (1 << i) tests bit i, word | (1 << i) sets it, word & !(1 << i) clears it.
+    // 2. word.count_ones() counts the set bits in one instruction (popcnt) on most CPUs.
+    // 3. word.trailing_zeros() is the position of the lowest set bit, and word & (word - 1) clears it.
+    //    Repeating both walks through the set bits of a word without looking at the zeros between them.
+    // 4. (1 << n) - 1 is a mask of the lowest n bits.
+
+use std::{
+    fmt,
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not},
+};
+
+const WORD_BITS: usize = 64;
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct BitVec {
+    words: Vec<u64>,
+    len: usize,
+}
+
+// A BitVec from a list of 0s and 1s, like vec!: bits![1, 0, 1] or bits![0; 100]
+#[macro_export]
+macro_rules! bits {
+    () => {
+        $crate::bitvec::BitVec::new()
+    };
+    ($bit:expr; $len:expr) => {
+        $crate::bitvec::BitVec::repeat($bit != 0, $len)
+    };
+    ($($bit:expr),+ $(,)?) => {
+        [$($bit != 0),+].into_iter().collect::<$crate::bitvec::BitVec>()
+    };
+}
+
+impl BitVec {
+    pub fn new() -> BitVec {
+        BitVec::default()
+    }
+
+    pub fn with_capacity(bits: usize) -> BitVec {
+        BitVec { words: Vec::with_capacity(bits.div_ceil(WORD_BITS)), len: 0 }
+    }
+
+    pub fn repeat(bit: bool, len: usize) -> BitVec {
+        let mut bits = BitVec { words: vec![if bit { !0 } else { 0 }; len.div_ceil(WORD_BITS)], len };
+        bits.clear_tail();
+        bits
+    }
+
+    // The bits of the bytes, the lowest bit of each byte first, which is the order DEFLATE writes bits in.
+    // huffman.rs reads its compressed data back with it.
+    // len can cut off the padding at the end of the last byte.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> BitVec {
+        assert!(len <= bytes.len() * 8, "{} bytes don't hold {len} bits", bytes.len());
+        let mut bits = BitVec::with_capacity(len);
+        for chunk in bytes.chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            bits.words.push(u64::from_le_bytes(word));
+        }
+        bits.words.truncate(len.div_ceil(WORD_BITS));
+        bits.len = len;
+        bits.clear_tail();
+        bits
+    }
+
+    // The other way around, the last byte is padded with 0s
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.truncate(self.len.div_ceil(8));
+        bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(WORD_BITS) {
+            self.words.push(0);
+        }
+        self.len += 1;
+        self.set(self.len - 1, bit);
+    }
+
+    pub fn pop(&mut self) -> Option<bool> {
+        let bit = self.get(self.len.checked_sub(1)?)?;
+        self.truncate(self.len - 1);
+        Some(bit)
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.len = len;
+            self.words.truncate(len.div_ceil(WORD_BITS));
+            self.clear_tail();
+        }
+    }
+
+    // Appends the lowest count bits of value, lowest first. Huffman codes and bit fields are pushed this way.
+    pub fn push_bits(&mut self, value: u64, count: usize) {
+        for i in 0..count {
+            self.push(value & (1 << i) != 0);
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<bool> {
+        if index >= self.len {
+            return None;
+        }
+        Some(self.words[index / WORD_BITS] & (1 << (index % WORD_BITS)) != 0)
+    }
+
+    // Panics when the index is out of bounds, like indexing a Vec
+    pub fn set(&mut self, index: usize, bit: bool) {
+        assert!(index < self.len, "index {index} is out of bounds for a BitVec of {} bits", self.len);
+        let mask = 1 << (index % WORD_BITS);
+        let word = &mut self.words[index / WORD_BITS];
+        if bit {
+            *word |= mask;
+        } else {
+            *word &= !mask;
+        }
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn count_zeros(&self) -> usize {
+        self.len - self.count_ones()
+    }
+
+    // Rank and Select
+
+    // rank(i) is the number of 1s before position i, select(k) is the position of the k-th 1 (counting from 0).
+    // They undo each other: rank(select(k)) == k. Succinct data structures (compressed tries, wavelet trees) are built
+    // on these two, usually with a small index of precomputed ranks on the side; here they just count word by word.
+    pub fn rank(&self, index: usize) -> usize {
+        let index = index.min(self.len);
+        let full: usize = self.words[..index / WORD_BITS].iter().map(|word| word.count_ones() as usize).sum();
+        let rest = index % WORD_BITS;
+        if rest == 0 {
+            full
+        } else {
+            full + (self.words[index / WORD_BITS] & ((1 << rest) - 1)).count_ones() as usize
+        }
+    }
+
+    pub fn select(&self, mut k: usize) -> Option<usize> {
+        for (i, &word) in self.words.iter().enumerate() {
+            let ones = word.count_ones() as usize;
+            if k < ones {
+                // Clear the k lowest set bits, the k-th one is then the lowest that's left
+                let mut word = word;
+                for _ in 0..k {
+                    word &= word - 1;
+                }
+                return Some(i * WORD_BITS + word.trailing_zeros() as usize);
+            }
+            k -= ones;
+        }
+        None
+    }
+
+    // Iterators
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { bits: self, index: 0 }
+    }
+
+    // The positions of the 1s, skipping over the 0s a word at a time
+    pub fn iter_ones(&self) -> Ones<'_> {
+        Ones { words: &self.words, index: 0, current: self.words.first().copied().unwrap_or(0) }
+    }
+
+    // The words themselves, for code that wants to do its own twiddling
+    pub fn as_words(&self) -> &[u64] {
+        &self.words
+    }
+
+    fn clear_tail(&mut self) {
+        let rest = self.len % WORD_BITS;
+        if rest != 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1 << rest) - 1;
+            }
+        }
+    }
+
+    fn assert_same_len(&self, other: &BitVec) {
+        assert_eq!(self.len, other.len, "bitwise operations need BitVecs of the same length");
+    }
+}
+
+pub struct Iter<'a> {
+    bits: &'a BitVec,
+    index: usize,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        let bit = self.bits.get(self.index)?;
+        self.index += 1;
+        Some(bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.bits.len - self.index;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a BitVec {
+    type Item = bool;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+pub struct Ones<'a> {
+    words: &'a [u64],
+    index: usize,
+    // What's left of words[index], the bits already returned are cleared
+    current: u64,
+}
+
+impl Iterator for Ones<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.current == 0 {
+            self.index += 1;
+            self.current = *self.words.get(self.index)?;
+        }
+        let bit = self.current.trailing_zeros() as usize;
+        self.current &= self.current - 1;
+        Some(self.index * WORD_BITS + bit)
+    }
+}
+
+impl FromIterator<bool> for BitVec {
+    fn from_iter<I: IntoIterator<Item = bool>>(iter: I) -> BitVec {
+        let mut bits = BitVec::new();
+        bits.extend(iter);
+        bits
+    }
+}
+
+impl Extend<bool> for BitVec {
+    fn extend<I: IntoIterator<Item = bool>>(&mut self, iter: I) {
+        for bit in iter {
+            self.push(bit);
+        }
+    }
+}
+
+// Debug shows the bits themselves, bits![1, 1, 0] prints as BitVec[110]
+impl fmt::Debug for BitVec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("BitVec[")?;
+        for bit in self {
+            f.write_str(if bit { "1" } else { "0" })?;
+        }
+        f.write_str("]")
+    }
+}
+
+// Bitwise Operators
+
+// Word by word. The operators on references leave both sides alone and return a new BitVec,
+// the assigning ones (a &= &b) change a in place. Both panic when the lengths differ, there's no sensible answer then.
+macro_rules! impl_bitwise {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $op:tt) => {
+        impl $assign_trait<&BitVec> for BitVec {
+            fn $assign_method(&mut self, other: &BitVec) {
+                self.assert_same_len(other);
+                for (word, other) in self.words.iter_mut().zip(&other.words) {
+                    *word = *word $op *other;
+                }
+            }
+        }
+
+        impl $trait<&BitVec> for &BitVec {
+            type Output = BitVec;
+
+            fn $method(self, other: &BitVec) -> BitVec {
+                let mut out = self.clone();
+                $assign_trait::$assign_method(&mut out, other);
+                out
+            }
+        }
+    };
+}
+
+impl_bitwise!(BitAnd, bitand, BitAndAssign, bitand_assign, &);
+impl_bitwise!(BitOr, bitor, BitOrAssign, bitor_assign, |);
+impl_bitwise!(BitXor, bitxor, BitXorAssign, bitxor_assign, ^);
+
+impl Not for &BitVec {
+    type Output = BitVec;
+
+    fn not(self) -> BitVec {
+        let mut out = BitVec { words: self.words.iter().map(|word| !word).collect(), len: self.len };
+        // The 0s past len turned into 1s
+        out.clear_tail();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_get_set_pop() {
+        let mut bits = BitVec::new();
+        for i in 0..130 {
+            bits.push(i % 3 == 0);
+        }
+        assert_eq!(130, bits.len());
+        assert_eq!(Some(true), bits.get(129));
+        assert_eq!(Some(false), bits.get(128));
+        assert_eq!(None, bits.get(130));
+
+        bits.set(128, true);
+        assert_eq!(Some(true), bits.get(128));
+        assert_eq!(45, bits.count_ones());
+
+        assert_eq!(Some(true), bits.pop());
+        assert_eq!(Some(true), bits.pop());
+        assert_eq!(128, bits.len());
+        assert_eq!(2, bits.as_words().len());
+        assert_eq!(43, bits.count_ones());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn set_out_of_bounds() {
+        bits![0; 10].set(10, true);
+    }
+
+    #[test]
+    fn the_bits_macro() {
+        let bits = bits![1, 0, 1, 1];
+        assert_eq!("BitVec[1011]", format!("{bits:?}"));
+        assert_eq!(BitVec::repeat(true, 70), bits![1; 70]);
+        assert_eq!(70, bits![1; 70].count_ones());
+        assert!(bits![].is_empty());
+    }
+
+    #[test]
+    fn rank_and_select_undo_each_other() {
+        let bits: BitVec = (0..300).map(|i| i % 7 == 0 || i % 11 == 0).collect();
+        let ones: Vec<usize> = bits.iter_ones().collect();
+        assert_eq!(ones.len(), bits.count_ones());
+        for (k, &position) in ones.iter().enumerate() {
+            assert_eq!(Some(position), bits.select(k));
+            assert_eq!(k, bits.rank(position));
+        }
+        assert_eq!(None, bits.select(ones.len()));
+        assert_eq!(bits.count_ones(), bits.rank(300));
+        assert_eq!(bits.count_ones(), bits.rank(1000));
+    }
+
+    #[test]
+    fn iterators() {
+        let bits = bits![0, 1, 1, 0, 0, 1];
+        assert_eq!(vec![false, true, true, false, false, true], bits.iter().collect::<Vec<_>>());
+        assert_eq!(vec![1, 2, 5], bits.iter_ones().collect::<Vec<_>>());
+        assert_eq!(0, bits![0; 200].iter_ones().count());
+        assert_eq!(None, BitVec::new().iter_ones().next());
+
+        let mut far = bits![0; 200];
+        far.set(199, true);
+        assert_eq!(vec![199], far.iter_ones().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let a = bits![1, 1, 0, 0];
+        let b = bits![1, 0, 1, 0];
+        assert_eq!(bits![1, 0, 0, 0], &a & &b);
+        assert_eq!(bits![1, 1, 1, 0], &a | &b);
+        assert_eq!(bits![0, 1, 1, 0], &a ^ &b);
+        assert_eq!(bits![0, 0, 1, 1], !&a);
+        // The tail stays 0, so the complement of the complement is equal again
+        assert_eq!(a, !&!&a);
+        assert_eq!(2, (!&a).count_ones());
+
+        let mut c = a.clone();
+        c |= &b;
+        c &= &bits![0, 1, 1, 1];
+        assert_eq!(bits![0, 1, 1, 0], c);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let bits = bits![1, 0, 0, 0, 0, 0, 0, 0, 1, 1];
+        assert_eq!(vec![0b0000_0001, 0b0000_0011], bits.to_bytes());
+        assert_eq!(bits, BitVec::from_bytes(&bits.to_bytes(), 10));
+
+        let long: BitVec = (0..1000).map(|i| i % 5 == 1).collect();
+        assert_eq!(long, BitVec::from_bytes(&long.to_bytes(), 1000));
+        // The padding bits are cut off again
+        assert_eq!(bits![1, 0, 1], BitVec::from_bytes(&[0b1111_0101], 3));
+    }
+}
@@ -0,0 +1,421 @@
+// Graphs
+
+// A graph is a set of nodes connected by edges. We store it as an adjacency list: every node keeps a Vec of its outgoing edges.
+// Nodes are referred to by NodeId, a key into the Slab (src/slab.rs) that holds them, rather than by references.
+// Holding references between nodes would fight the borrow checker (every node is borrowed by its neighbours), while keys are just Copy numbers.
+// The Slab never moves a node, so removing one leaves every other NodeId as it was. Its key is given to the next node added.
+
+// The graph is generic over the data stored on the nodes (N) and on the edges (E), for example city names and road lengths.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet, VecDeque},
+    fmt,
+    ops::Add,
+};
+
+use crate::slab::Slab;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+struct Node<N, E> {
+    weight: N,
+    edges: Vec<(NodeId, E)>,
+}
+
+pub struct Graph<N, E> {
+    nodes: Slab<Node<N, E>>,
+    directed: bool,
+}
+
+impl<N, E> Graph<N, E> {
+    pub fn new_directed() -> Graph<N, E> {
+        Graph { nodes: Slab::new(), directed: true }
+    }
+
+    pub fn new_undirected() -> Graph<N, E> {
+        Graph { nodes: Slab::new(), directed: false }
+    }
+
+    pub fn is_directed(&self) -> bool {
+        self.directed
+    }
+
+    pub fn add_node(&mut self, weight: N) -> NodeId {
+        NodeId(self.nodes.insert(Node { weight, edges: Vec::new() }))
+    }
+
+    // Also removes the edges to it. Edges are only stored at the node they leave from, so that's a look at every edge there is
+    pub fn remove_node(&mut self, id: NodeId) -> Option<N> {
+        let node = self.nodes.try_remove(id.0)?;
+        for (_, other) in self.nodes.iter_mut() {
+            other.edges.retain(|(to, _)| *to != id);
+        }
+        Some(node.weight)
+    }
+
+    // An undirected edge is stored twice, once in each direction, so that neighbors() works the same for both kinds of graph.
+    // That's why E has to be Clone here.
+    pub fn add_edge(&mut self, from: NodeId, to: NodeId, weight: E)
+    where
+        E: Clone,
+    {
+        assert!(self.nodes.contains(from.0) && self.nodes.contains(to.0), "node does not exist");
+
+        if !self.directed && from != to {
+            self.nodes[to.0].edges.push((from, weight.clone()));
+        }
+        self.nodes[from.0].edges.push((to, weight));
+    }
+
+    pub fn node(&self, id: NodeId) -> &N {
+        &self.nodes[id.0].weight
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut N {
+        &mut self.nodes[id.0].weight
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        let stored: usize = self.nodes.values().map(|node| node.edges.len()).sum();
+        if self.directed {
+            stored
+        } else {
+            // Self loops are only stored once, every other undirected edge twice
+            let loops = self
+                .nodes
+                .iter()
+                .map(|(i, node)| node.edges.iter().filter(|(to, _)| to.0 == i).count())
+                .sum::<usize>();
+            (stored - loops) / 2 + loops
+        }
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes.keys().map(NodeId)
+    }
+
+    // The returned iterator borrows the graph, so the lifetime of the edges it yields is tied to &self
+    pub fn neighbors(&self, id: NodeId) -> impl Iterator<Item = (NodeId, &E)> {
+        self.nodes[id.0].edges.iter().map(|(to, weight)| (*to, weight))
+    }
+
+    pub fn bfs(&self, start: NodeId) -> Bfs<'_, N, E> {
+        let mut visited = HashSet::new();
+        visited.insert(start);
+        Bfs { graph: self, queue: VecDeque::from([start]), visited }
+    }
+
+    pub fn dfs(&self, start: NodeId) -> Dfs<'_, N, E> {
+        Dfs { graph: self, stack: vec![start], visited: HashSet::new() }
+    }
+
+    // Topological Sort
+
+    // Kahn's algorithm: repeatedly take a node that no remaining edge points to. If we run out of such nodes before all nodes are placed,
+    // the leftover nodes all sit on (or behind) a cycle and no valid order exists.
+    pub fn topological_sort(&self) -> Result<Vec<NodeId>, CycleError> {
+        if !self.directed {
+            return Err(CycleError::Undirected);
+        }
+
+        let mut in_degree = vec![0usize; self.nodes.key_bound()];
+        for node in self.nodes.values() {
+            for (to, _) in &node.edges {
+                in_degree[to.0] += 1;
+            }
+        }
+
+        let mut ready: VecDeque<NodeId> = self.node_ids().filter(|n| in_degree[n.0] == 0).collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+
+        while let Some(node) = ready.pop_front() {
+            order.push(node);
+            for (to, _) in &self.nodes[node.0].edges {
+                in_degree[to.0] -= 1;
+                if in_degree[to.0] == 0 {
+                    ready.push_back(*to);
+                }
+            }
+        }
+
+        if order.len() == self.nodes.len() {
+            Ok(order)
+        } else {
+            let stuck = self.node_ids().find(|n| in_degree[n.0] > 0).unwrap();
+            Err(CycleError::Cycle(stuck))
+        }
+    }
+}
+
+impl<N, E> Graph<N, E>
+where
+    E: Copy + Ord + Default + Add<Output = E>,
+{
+    // Dijkstra's Shortest Path
+
+    // The BinaryHeap in std is a max-heap, wrapping the entries in Reverse turns it into the min-heap Dijkstra needs:
+    // we always continue from the closest node we haven't finished yet. Edge weights must not be negative.
+    // Returns the distance from start to every node, by NodeId::index(). None for nodes that can't be reached, or were removed.
+    pub fn dijkstra(&self, start: NodeId) -> Vec<Option<E>> {
+        self.dijkstra_with_previous(start).0
+    }
+
+    pub fn shortest_path(&self, start: NodeId, goal: NodeId) -> Option<(E, Vec<NodeId>)> {
+        let (dist, previous) = self.dijkstra_with_previous(start);
+        let cost = dist[goal.0]?;
+
+        // Walk the previous links back from the goal, then flip the path around
+        let mut path = vec![goal];
+        let mut current = goal;
+        while let Some(prev) = previous[current.0] {
+            path.push(prev);
+            current = prev;
+        }
+        path.reverse();
+        Some((cost, path))
+    }
+
+    fn dijkstra_with_previous(&self, start: NodeId) -> (Vec<Option<E>>, Vec<Option<NodeId>>) {
+        let mut dist: Vec<Option<E>> = vec![None; self.nodes.key_bound()];
+        let mut previous = vec![None; self.nodes.key_bound()];
+        let mut heap = BinaryHeap::new();
+
+        dist[start.0] = Some(E::default());
+        heap.push(Reverse((E::default(), start)));
+
+        while let Some(Reverse((cost, node))) = heap.pop() {
+            // A node can be in the heap several times with different costs, skip the stale entries
+            if dist[node.0].is_some_and(|best| cost > best) {
+                continue;
+            }
+
+            for (next, weight) in &self.nodes[node.0].edges {
+                let candidate = cost + *weight;
+                let better = match dist[next.0] {
+                    Some(best) => candidate < best,
+                    None => true,
+                };
+                if better {
+                    dist[next.0] = Some(candidate);
+                    previous[next.0] = Some(node);
+                    heap.push(Reverse((candidate, *next)));
+                }
+            }
+        }
+
+        (dist, previous)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CycleError {
+    Cycle(NodeId),
+    Undirected,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CycleError::Cycle(node) => write!(f, "graph has a cycle through node {}", node.0),
+            CycleError::Undirected => write!(f, "undirected graphs have no topological order"),
+        }
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+// Traversal Iterators
+
+// Both iterators hold a shared reference to the graph, so the graph can't be modified while a traversal is in progress.
+// The borrow checker enforces this for us: add_node takes &mut self, which conflicts with the &'a Graph the iterator holds.
+
+pub struct Bfs<'a, N, E> {
+    graph: &'a Graph<N, E>,
+    queue: VecDeque<NodeId>,
+    visited: HashSet<NodeId>,
+}
+
+impl<N, E> Iterator for Bfs<'_, N, E> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.queue.pop_front()?;
+        for (next, _) in &self.graph.nodes[node.0].edges {
+            if self.visited.insert(*next) {
+                self.queue.push_back(*next);
+            }
+        }
+        Some(node)
+    }
+}
+
+pub struct Dfs<'a, N, E> {
+    graph: &'a Graph<N, E>,
+    stack: Vec<NodeId>,
+    visited: HashSet<NodeId>,
+}
+
+impl<N, E> Iterator for Dfs<'_, N, E> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        while let Some(node) = self.stack.pop() {
+            if !self.visited.insert(node) {
+                continue;
+            }
+            // Push the neighbours in reverse so that the first neighbour is visited first
+            for (next, _) in self.graph.nodes[node.0].edges.iter().rev() {
+                if !self.visited.contains(next) {
+                    self.stack.push(*next);
+                }
+            }
+            return Some(node);
+        }
+        None
+    }
+}
+
+// Display as DOT
+
+// DOT is the text format of Graphviz, so `dot -Tpng` can turn the output into a picture.
+impl<N: fmt::Display, E: fmt::Display> fmt::Display for Graph<N, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (keyword, arrow) = if self.directed { ("digraph", "->") } else { ("graph", "--") };
+
+        writeln!(f, "{keyword} {{")?;
+        for (i, node) in self.nodes.iter() {
+            writeln!(f, "    {i} [label=\"{}\"];", escape(&node.weight.to_string()))?;
+        }
+        for (from, node) in self.nodes.iter() {
+            for (to, weight) in &node.edges {
+                // Undirected edges are stored in both directions but should only be drawn once
+                if !self.directed && to.0 < from {
+                    continue;
+                }
+                writeln!(f, "    {from} {arrow} {} [label=\"{}\"];", to.0, escape(&weight.to_string()))?;
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a -> b -> d, a -> c -> d, d -> e
+    fn diamond() -> (Graph<&'static str, u32>, Vec<NodeId>) {
+        let mut g = Graph::new_directed();
+        let ids: Vec<NodeId> = ["a", "b", "c", "d", "e"].into_iter().map(|n| g.add_node(n)).collect();
+        g.add_edge(ids[0], ids[1], 1);
+        g.add_edge(ids[0], ids[2], 4);
+        g.add_edge(ids[1], ids[3], 5);
+        g.add_edge(ids[2], ids[3], 1);
+        g.add_edge(ids[3], ids[4], 2);
+        (g, ids)
+    }
+
+    fn names(g: &Graph<&'static str, u32>, ids: impl Iterator<Item = NodeId>) -> Vec<&'static str> {
+        ids.map(|id| *g.node(id)).collect()
+    }
+
+    #[test]
+    fn traversal_orders() {
+        let (g, ids) = diamond();
+        assert_eq!(vec!["a", "b", "c", "d", "e"], names(&g, g.bfs(ids[0])));
+        assert_eq!(vec!["a", "b", "d", "e", "c"], names(&g, g.dfs(ids[0])));
+        assert_eq!(vec!["d", "e"], names(&g, g.bfs(ids[3])));
+    }
+
+    #[test]
+    fn dijkstra_finds_cheapest_path() {
+        let (g, ids) = diamond();
+        let dist = g.dijkstra(ids[0]);
+        assert_eq!(vec![Some(0), Some(1), Some(4), Some(5), Some(7)], dist);
+
+        let (cost, path) = g.shortest_path(ids[0], ids[4]).unwrap();
+        assert_eq!(7, cost);
+        assert_eq!(vec!["a", "c", "d", "e"], names(&g, path.into_iter()));
+
+        assert_eq!(None, g.shortest_path(ids[4], ids[0]));
+    }
+
+    #[test]
+    fn topological_sort_and_cycles() {
+        let (mut g, ids) = diamond();
+        let order = g.topological_sort().unwrap();
+        let position = |id: NodeId| order.iter().position(|n| *n == id).unwrap();
+        for from in g.node_ids() {
+            for (to, _) in g.neighbors(from) {
+                assert!(position(from) < position(to));
+            }
+        }
+
+        g.add_edge(ids[4], ids[1], 1);
+        assert!(matches!(g.topological_sort(), Err(CycleError::Cycle(_))));
+    }
+
+    #[test]
+    fn removing_a_node_keeps_the_other_ids() {
+        let (mut g, ids) = diamond();
+        assert_eq!(Some("c"), g.remove_node(ids[2]));
+        assert_eq!(None, g.remove_node(ids[2]));
+        assert_eq!((4, 3), (g.node_count(), g.edge_count()));
+        assert_eq!(vec!["a", "b", "d", "e"], names(&g, g.node_ids()));
+        assert_eq!(vec![Some(0), Some(1), None, Some(6), Some(8)], g.dijkstra(ids[0]));
+        assert_eq!(vec!["a", "b", "d", "e"], names(&g, g.topological_sort().unwrap().into_iter()));
+
+        // The next node gets the free id
+        let f = g.add_node("f");
+        assert_eq!(ids[2], f);
+        g.add_edge(ids[0], f, 1);
+        assert_eq!(vec!["a", "b", "f", "d", "e"], names(&g, g.bfs(ids[0])));
+    }
+
+    #[test]
+    fn undirected_edges_go_both_ways() {
+        let mut g: Graph<&str, u32> = Graph::new_undirected();
+        let a = g.add_node("a");
+        let b = g.add_node("b");
+        g.add_edge(a, b, 3);
+        g.add_edge(b, b, 1);
+
+        assert_eq!(2, g.edge_count());
+        assert_eq!(vec![a], g.neighbors(b).map(|(n, _)| n).filter(|n| *n != b).collect::<Vec<_>>());
+        assert_eq!(Some((3, vec![b, a])), g.shortest_path(b, a));
+        assert_eq!(Err(CycleError::Undirected), g.topological_sort());
+    }
+
+    #[test]
+    fn dot_output() {
+        let mut g = Graph::new_directed();
+        let a = g.add_node("start \"here\"");
+        let b = g.add_node("end");
+        g.add_edge(a, b, 2.5);
+
+        let expected = "digraph {\n    0 [label=\"start \\\"here\\\"\"];\n    1 [label=\"end\"];\n    0 -> 1 [label=\"2.5\"];\n}";
+        assert_eq!(expected, g.to_string());
+
+        let mut u = Graph::new_undirected();
+        let x = u.add_node(1);
+        let y = u.add_node(2);
+        u.add_edge(x, y, "road");
+        assert_eq!("graph {\n    0 [label=\"1\"];\n    1 [label=\"2\"];\n    0 -- 1 [label=\"road\"];\n}", u.to_string());
+    }
+}
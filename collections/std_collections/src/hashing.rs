@@ -0,0 +1,313 @@
+// Checksums and Hash Functions
+
+// A hash function turns any amount of bytes into a fixed size number. Different jobs need different properties from that number:
+    // 1. CRC-32 is a checksum: it's designed to catch accidental damage, like a flipped bit on disk or a cut off network transfer.
+    //    gzip, zip and PNG store one next to the data. It's easy to forge on purpose, so it says nothing about tampering.
+    // 2. FNV-1a is about as simple as a hash function gets: one xor and one multiply per byte. It's fast for short keys
+    //    and spreads them out well, but anyone who knows it can craft many keys with the same hash.
+    // 3. SipHash is what the standard HashMap uses. It takes a secret 128 bit key, and without the key an attacker can't
+    //    predict which keys collide, so they can't send a server thousands of keys that all land in the same bucket (a HashDoS attack).
+    //    The 2-4 in SipHash-2-4 is the number of rounds: 2 per 8 byte block of input, and 4 at the end.
+
+// Fnv1a and SipHasher implement the std::hash::Hasher trait, and FnvBuildHasher and SipBuildHasher the BuildHasher trait,
+// which is all HashMap needs to use them instead of its default: HashMap::with_hasher(SipBuildHasher::new(key)).
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, BuildHasherDefault, Hasher},
+};
+
+// CRC-32
+
+// The table holds the CRC of every possible byte, so the checksum only needs one lookup per byte instead of 8 shifts.
+// It's computed at compile time: a const block can't use for loops, hence the whiles.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            // 0xEDB88320 is the CRC-32 polynomial with its bits reversed, because this CRC processes the low bit of every byte first
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+// For data that arrives in pieces, like the records of a log file. Feeding the pieces one by one gives the same result as crc32() of all of them.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.state = data.iter().fold(self.state, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8));
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+// FNV-1a
+
+const FNV32_OFFSET: u32 = 0x811c9dc5;
+const FNV32_PRIME: u32 = 0x01000193;
+const FNV64_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV64_PRIME: u64 = 0x100000001b3;
+
+pub fn fnv1a_32(data: &[u8]) -> u32 {
+    data.iter().fold(FNV32_OFFSET, |hash, &b| (hash ^ b as u32).wrapping_mul(FNV32_PRIME))
+}
+
+pub fn fnv1a_64(data: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::default();
+    hasher.write(data);
+    hasher.finish()
+}
+
+// The 64 bit version as a Hasher
+#[derive(Debug, Clone, Copy)]
+pub struct Fnv1a {
+    hash: u64,
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a { hash: FNV64_OFFSET }
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        self.hash = bytes.iter().fold(self.hash, |hash, &b| (hash ^ b as u64).wrapping_mul(FNV64_PRIME));
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+// FNV has no key, so every Fnv1a starts out the same and BuildHasherDefault can make them
+pub type FnvBuildHasher = BuildHasherDefault<Fnv1a>;
+
+// SipHash-2-4
+
+// The state is four u64s. The input is mixed in 8 bytes at a time, in little endian order, and the last block also carries the input's length.
+// Bytes that don't fill a whole block yet wait in tail, because Hasher::write may be called with any number of bytes at a time.
+#[derive(Debug, Clone, Copy)]
+pub struct SipHasher {
+    v: [u64; 4],
+    tail: u64,
+    // Bytes in tail
+    ntail: usize,
+    length: usize,
+}
+
+impl SipHasher {
+    pub fn new(key: [u8; 16]) -> SipHasher {
+        let (k0, k1) = split_key(key);
+        SipHasher::with_keys(k0, k1)
+    }
+
+    pub fn with_keys(k0: u64, k1: u64) -> SipHasher {
+        // The constants spell "somepseudorandomlygeneratedbytes" in ASCII, they only make sure the four words start out different
+        SipHasher {
+            v: [k0 ^ 0x736f6d6570736575, k1 ^ 0x646f72616e646f6d, k0 ^ 0x6c7967656e657261, k1 ^ 0x7465646279746573],
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    // One SipRound: additions, rotations and xors, nothing else. That's what makes SipHash fast in software.
+    fn round(&mut self) {
+        let [v0, v1, v2, v3] = &mut self.v;
+        *v0 = v0.wrapping_add(*v1);
+        *v1 = v1.rotate_left(13) ^ *v0;
+        *v0 = v0.rotate_left(32);
+        *v2 = v2.wrapping_add(*v3);
+        *v3 = v3.rotate_left(16) ^ *v2;
+        *v0 = v0.wrapping_add(*v3);
+        *v3 = v3.rotate_left(21) ^ *v0;
+        *v2 = v2.wrapping_add(*v1);
+        *v1 = v1.rotate_left(17) ^ *v2;
+        *v2 = v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, block: u64) {
+        self.v[3] ^= block;
+        self.round();
+        self.round();
+        self.v[0] ^= block;
+    }
+}
+
+impl Hasher for SipHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length += bytes.len();
+        for &b in bytes {
+            self.tail |= (b as u64) << (8 * self.ntail);
+            self.ntail += 1;
+            if self.ntail == 8 {
+                self.compress(self.tail);
+                self.tail = 0;
+                self.ntail = 0;
+            }
+        }
+    }
+
+    // finish takes &self, so the finalization runs on a copy, and the hasher can still be written to afterwards
+    fn finish(&self) -> u64 {
+        let mut state = *self;
+        // Only the lowest byte of the length is used
+        let last = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(last);
+        state.v[2] ^= 0xff;
+        for _ in 0..4 {
+            state.round();
+        }
+        state.v[0] ^ state.v[1] ^ state.v[2] ^ state.v[3]
+    }
+}
+
+// The 16 byte key is used as two little endian u64s
+fn split_key(key: [u8; 16]) -> (u64, u64) {
+    (u64::from_le_bytes(key[..8].try_into().unwrap()), u64::from_le_bytes(key[8..].try_into().unwrap()))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SipBuildHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipBuildHasher {
+    pub fn new(key: [u8; 16]) -> SipBuildHasher {
+        let (k0, k1) = split_key(key);
+        SipBuildHasher { k0, k1 }
+    }
+
+    // A key nobody can guess. The standard library already seeds its RandomState from the operating system,
+    // so two of its hashes make a random key without us having to read /dev/urandom.
+    pub fn random() -> SipBuildHasher {
+        let random = RandomState::new();
+        SipBuildHasher { k0: random.hash_one(0u8), k1: random.hash_one(1u8) }
+    }
+}
+
+impl BuildHasher for SipBuildHasher {
+    type Hasher = SipHasher;
+
+    fn build_hasher(&self) -> SipHasher {
+        SipHasher::with_keys(self.k0, self.k1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const QUICK_FOX: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    #[test]
+    fn crc32_check_values() {
+        // "123456789" is the check value every CRC catalogue lists for its algorithms
+        assert_eq!(0xCBF43926, crc32(b"123456789"));
+        assert_eq!(0x414FA339, crc32(QUICK_FOX));
+        assert_eq!(0, crc32(b""));
+
+        let mut crc = Crc32::new();
+        for piece in QUICK_FOX.chunks(5) {
+            crc.update(piece);
+        }
+        assert_eq!(crc32(QUICK_FOX), crc.finish());
+    }
+
+    #[test]
+    fn fnv1a_test_vectors() {
+        // From the FNV reference test suite
+        assert_eq!(0x811c9dc5, fnv1a_32(b""));
+        assert_eq!(0xe40c292c, fnv1a_32(b"a"));
+        assert_eq!(0xbf9cf968, fnv1a_32(b"foobar"));
+        assert_eq!(0xcbf29ce484222325, fnv1a_64(b""));
+        assert_eq!(0xaf63dc4c8601ec8c, fnv1a_64(b"a"));
+        assert_eq!(0x85944171f73967e8, fnv1a_64(b"foobar"));
+    }
+
+    fn reference_key() -> [u8; 16] {
+        std::array::from_fn(|i| i as u8)
+    }
+
+    fn siphash(message: &[u8]) -> u64 {
+        let mut hasher = SipHasher::new(reference_key());
+        hasher.write(message);
+        hasher.finish()
+    }
+
+    #[test]
+    fn siphash_test_vectors() {
+        // The reference vectors use the key 00 01 .. 0f and the messages (), (00), (00 01), ... (00 01 .. 3e)
+        let message: Vec<u8> = (0..64).collect();
+        assert_eq!(0x726fdb47dd0e0e31, siphash(&message[..0]));
+        assert_eq!(0x74f839c593dc67fd, siphash(&message[..1]));
+        // The example worked through in the SipHash paper, 15 bytes
+        assert_eq!(0xa129ca6149be45e5, siphash(&message[..15]));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn siphash_agrees_with_the_standard_library() {
+        // std still has its SipHash-2-4 under a deprecated name, which makes a good oracle for every length and split
+        let message: Vec<u8> = (0..100u8).map(|i| i.wrapping_mul(37)).collect();
+        for len in 0..message.len() {
+            let mut expected = std::hash::SipHasher::new_with_keys(7, 11);
+            expected.write(&message[..len]);
+
+            let mut ours = SipHasher::with_keys(7, 11);
+            let (a, b) = message[..len].split_at(len / 3);
+            ours.write(a);
+            ours.write(b);
+            assert_eq!(expected.finish(), ours.finish(), "length {len}");
+        }
+    }
+
+    #[test]
+    fn hash_maps_with_our_hashers() {
+        let mut fnv: HashMap<&str, i32, FnvBuildHasher> = HashMap::default();
+        let mut sip = HashMap::with_hasher(SipBuildHasher::new(reference_key()));
+        for (i, word) in ["apple", "banana", "cherry"].iter().enumerate() {
+            fnv.insert(word, i as i32);
+            sip.insert(word.to_string(), i as i32);
+        }
+        assert_eq!(Some(&1), fnv.get("banana"));
+        assert_eq!(Some(&2), sip.get("cherry"));
+
+        // Same key, same hashes; different keys, (almost certainly) different hashes
+        let a = SipBuildHasher::new(reference_key());
+        assert_eq!(a.hash_one("hello"), SipBuildHasher::new(reference_key()).hash_one("hello"));
+        assert_ne!(a.hash_one("hello"), SipBuildHasher::random().hash_one("hello"));
+    }
+}
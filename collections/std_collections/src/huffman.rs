@@ -0,0 +1,332 @@
+// Huffman Coding
+
+// In plain text every byte takes 8 bits, whether it's the 'e' that shows up every few letters or a 'q' that hardly ever does.
+// Huffman coding gives frequent bytes short codes and rare ones long codes, so the total comes out smaller:
+    // 1. Count how often each byte occurs.
+    // 2. Build a tree: put every byte in a min-heap as a leaf weighted by its count, then keep taking out the two lightest
+    //    nodes and putting back a parent weighing their sum, until one node is left. The heap is std's BinaryHeap with Reverse.
+    // 3. A byte's code is the path from the root to its leaf, 0 for left and 1 for right. Frequent bytes were merged last,
+    //    so they sit close to the root. No code is the start of another (they all end at a leaf), so the codes can be
+    //    written back to back without separators and still be read back unambiguously.
+
+// Canonical Codes
+
+// The decoder needs the same codes, and sending the whole tree is wasteful. Only the code lengths actually matter:
+// sort the bytes by (code length, byte value) and hand out the codes in counting order, shifting left whenever the length
+// grows. Any code lengths from a Huffman tree give valid codes this way, and the header only has to carry the lengths.
+// DEFLATE sends its Huffman codes like this too.
+
+// The compressed format:
+    // 1. The length of the original data, as 8 little endian bytes.
+    // 2. If it's not 0: the number of different bytes minus 1, then (byte, code length) for each of them.
+    // 3. The codes of all the bytes, most significant bit first, packed into a BitVec and padded with 0s to a whole byte.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    error::Error,
+    fmt,
+};
+
+use crate::bitvec::BitVec;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HuffmanError {
+    // The data ends before the header or the codes are complete
+    Truncated,
+    // Code lengths that can't come from a Huffman tree
+    InvalidHeader,
+    // A run of bits that isn't the code of any byte
+    InvalidCode,
+}
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HuffmanError::Truncated => write!(f, "the compressed data is cut off"),
+            HuffmanError::InvalidHeader => write!(f, "the code lengths in the header are invalid"),
+            HuffmanError::InvalidCode => write!(f, "the compressed data contains an invalid code"),
+        }
+    }
+}
+
+impl Error for HuffmanError {}
+
+pub fn frequencies(data: &[u8]) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    counts
+}
+
+// Code lengths from the tree. The nodes live in a Vec and refer to each other by index, which keeps the borrow checker
+// out of the way: a parent only stores the indices of its two children.
+fn code_lengths(counts: &[u64; 256]) -> [u8; 256] {
+    let mut lengths = [0u8; 256];
+    // Leaves are 0..256, parents are pushed after them as (left, right)
+    let mut parents: Vec<(usize, usize)> = Vec::new();
+    // The node index breaks ties between equal weights, so the same input always builds the same tree
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> =
+        (0..256).filter(|&b| counts[b] > 0).map(|b| Reverse((counts[b], b))).collect();
+
+    match heap.len() {
+        0 => return lengths,
+        // A tree with a single leaf has no edges, but every byte needs at least one bit
+        1 => {
+            let Reverse((_, b)) = heap.pop().unwrap();
+            lengths[b] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    while heap.len() > 1 {
+        let Reverse((w1, a)) = heap.pop().unwrap();
+        let Reverse((w2, b)) = heap.pop().unwrap();
+        parents.push((a, b));
+        heap.push(Reverse((w1 + w2, 256 + parents.len() - 1)));
+    }
+
+    // Walk down from the root, which is the last parent, counting the depth
+    let mut stack = vec![(256 + parents.len() - 1, 0u8)];
+    while let Some((node, depth)) = stack.pop() {
+        if node < 256 {
+            lengths[node] = depth;
+        } else {
+            let (left, right) = parents[node - 256];
+            stack.push((left, depth + 1));
+            stack.push((right, depth + 1));
+        }
+    }
+    lengths
+}
+
+// The canonical code of every byte as (code, length), from the lengths alone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Codebook {
+    lengths: [u8; 256],
+    codes: [u64; 256],
+}
+
+impl Codebook {
+    // The deepest trees come from counts that grow like the Fibonacci numbers, a code longer than 64 bits
+    // would take over 10^13 bytes of input
+    pub fn from_frequencies(counts: &[u64; 256]) -> Codebook {
+        Codebook::from_lengths(code_lengths(counts)).expect("a Huffman tree always gives valid code lengths")
+    }
+
+    // Checks the Kraft inequality: a length l uses up 2^-l of the code space, and together they can't use more than all of it.
+    // That's exactly the condition for prefix-free codes with these lengths to exist.
+    pub fn from_lengths(lengths: [u8; 256]) -> Result<Codebook, HuffmanError> {
+        let max = lengths.iter().copied().max().unwrap_or(0) as u32;
+        if max > 64 {
+            return Err(HuffmanError::InvalidHeader);
+        }
+        let used: u128 = lengths.iter().filter(|&&l| l > 0).map(|&l| 1u128 << (max - l as u32)).sum();
+        if max > 0 && used > 1u128 << max {
+            return Err(HuffmanError::InvalidHeader);
+        }
+
+        let mut symbols: Vec<usize> = (0..256).filter(|&b| lengths[b] > 0).collect();
+        symbols.sort_by_key(|&b| (lengths[b], b));
+
+        let mut codes = [0u64; 256];
+        let (mut code, mut prev_len) = (0u64, 0u8);
+        for (i, &b) in symbols.iter().enumerate() {
+            let len = lengths[b];
+            if i > 0 {
+                code += 1;
+            }
+            // Shifting a u64 by 64 overflows, but only a lone code of length 64 can get here with code == 0
+            code = code.checked_shl((len - prev_len) as u32).unwrap_or(0);
+            codes[b] = code;
+            prev_len = len;
+        }
+        Ok(Codebook { lengths, codes })
+    }
+
+    pub fn code(&self, byte: u8) -> Option<(u64, u8)> {
+        match self.lengths[byte as usize] {
+            0 => None,
+            len => Some((self.codes[byte as usize], len)),
+        }
+    }
+
+    // Panics on a byte without a code, the codebook has to come from the same data
+    pub fn encode(&self, data: &[u8], out: &mut BitVec) {
+        for &b in data {
+            let (code, len) = self.code(b).expect("byte without a Huffman code");
+            for i in (0..len).rev() {
+                out.push(code & (1 << i) != 0);
+            }
+        }
+    }
+
+    // Reads count bytes. Canonical codes of the same length are consecutive numbers, so instead of walking a tree the decoder
+    // keeps, for each length, the first code and how many codes there are: after reading len bits, the code belongs to
+    // a byte exactly when it's in first..first + count, and its position there says which byte.
+    pub fn decode(&self, bits: impl IntoIterator<Item = bool>, count: usize) -> Result<Vec<u8>, HuffmanError> {
+        let mut symbols: Vec<u8> = (0..=255u8).filter(|&b| self.lengths[b as usize] > 0).collect();
+        symbols.sort_by_key(|&b| (self.lengths[b as usize], b));
+        let mut counts = [0u64; 65];
+        for &b in &symbols {
+            counts[self.lengths[b as usize] as usize] += 1;
+        }
+
+        let mut bits = bits.into_iter();
+        let mut out = Vec::with_capacity(count);
+        while out.len() < count {
+            let (mut code, mut first, mut index) = (0u64, 0u64, 0u64);
+            let mut len = 1;
+            loop {
+                if len > 64 {
+                    return Err(HuffmanError::InvalidCode);
+                }
+                code |= bits.next().ok_or(HuffmanError::Truncated)? as u64;
+                if code - first < counts[len] {
+                    out.push(symbols[(index + code - first) as usize]);
+                    break;
+                }
+                index += counts[len];
+                first = (first + counts[len]) << 1;
+                code <<= 1;
+                len += 1;
+            }
+        }
+        Ok(out)
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = (data.len() as u64).to_le_bytes().to_vec();
+    if data.is_empty() {
+        return out;
+    }
+
+    let codebook = Codebook::from_frequencies(&frequencies(data));
+    let used: Vec<u8> = (0..=255u8).filter(|&b| codebook.code(b).is_some()).collect();
+    out.push((used.len() - 1) as u8);
+    for &b in &used {
+        out.extend([b, codebook.lengths[b as usize]]);
+    }
+
+    let mut bits = BitVec::with_capacity(data.len() * 4);
+    codebook.encode(data, &mut bits);
+    out.extend(bits.to_bytes());
+    out
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, HuffmanError> {
+    let (len, rest) = data.split_first_chunk::<8>().ok_or(HuffmanError::Truncated)?;
+    let len = u64::from_le_bytes(*len) as usize;
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+
+    let (&used, rest) = rest.split_first().ok_or(HuffmanError::Truncated)?;
+    let header_len = 2 * (used as usize + 1);
+    if rest.len() < header_len {
+        return Err(HuffmanError::Truncated);
+    }
+    let (header, payload) = rest.split_at(header_len);
+
+    let mut lengths = [0u8; 256];
+    for pair in header.chunks(2) {
+        // A byte listed twice, or with no code at all, can't come from compress()
+        if pair[1] == 0 || lengths[pair[0] as usize] != 0 {
+            return Err(HuffmanError::InvalidHeader);
+        }
+        lengths[pair[0] as usize] = pair[1];
+    }
+
+    let codebook = Codebook::from_lengths(lengths)?;
+    codebook.decode(&BitVec::from_bytes(payload, payload.len() * 8), len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rand_lite::{Rng, Xoshiro256};
+
+    const TEXT: &str = "It was the best of times, it was the worst of times, it was the age of wisdom, \
+        it was the age of foolishness, it was the epoch of belief, it was the epoch of incredulity, \
+        it was the season of Light, it was the season of Darkness.";
+
+    #[test]
+    fn frequent_bytes_get_short_codes() {
+        let codebook = Codebook::from_frequencies(&frequencies(TEXT.as_bytes()));
+        let (_, space) = codebook.code(b' ').unwrap();
+        let (_, z) = codebook.code(b'D').unwrap();
+        assert!(space < z);
+        assert_eq!(None, codebook.code(b'z'));
+    }
+
+    #[test]
+    fn canonical_codes_from_lengths() {
+        // The example from RFC 1951 section 3.2.2: lengths (3, 3, 3, 3, 3, 2, 4, 4) for A..H
+        let mut lengths = [0u8; 256];
+        for (b, len) in (b'A'..).zip([3, 3, 3, 3, 3, 2, 4, 4]) {
+            lengths[b as usize] = len;
+        }
+        let codebook = Codebook::from_lengths(lengths).unwrap();
+        let codes: Vec<(u64, u8)> = (b'A'..=b'H').map(|b| codebook.code(b).unwrap()).collect();
+        assert_eq!(
+            vec![(0b010, 3), (0b011, 3), (0b100, 3), (0b101, 3), (0b110, 3), (0b00, 2), (0b1110, 4), (0b1111, 4)],
+            codes
+        );
+
+        // Three codes of length 1 don't fit in one bit
+        let mut too_many = [0u8; 256];
+        too_many[..3].fill(1);
+        assert_eq!(Err(HuffmanError::InvalidHeader), Codebook::from_lengths(too_many));
+    }
+
+    #[test]
+    fn text_round_trips_and_shrinks() {
+        // Long enough that the header doesn't matter much, English text takes a bit over 4 bits per letter
+        let text = TEXT.repeat(10);
+        let compressed = compress(text.as_bytes());
+        assert!(compressed.len() < text.len() * 3 / 5, "{} bytes for {}", compressed.len(), text.len());
+        assert_eq!(text.as_bytes(), decompress(&compressed).unwrap());
+    }
+
+    #[test]
+    fn edge_cases_round_trip() {
+        for data in [&b""[..], b"a", b"aaaaaaaaaaaa", b"ab"] {
+            assert_eq!(data, decompress(&compress(data)).unwrap());
+        }
+        let every_byte: Vec<u8> = (0..=255).collect();
+        assert_eq!(every_byte, decompress(&compress(&every_byte)).unwrap());
+    }
+
+    #[test]
+    fn random_inputs_round_trip() {
+        let mut rng = Xoshiro256::seed_from_u64(4141);
+        for _ in 0..100 {
+            let len = rng.gen_range(0..2000);
+            // Some inputs only use a few bytes, some use all of them, and the skewed ones make deep trees
+            let alphabet: u32 = rng.gen_range(1..=256);
+            let skewed = rng.gen_bool(0.5);
+            let data: Vec<u8> = (0..len)
+                .map(|_| {
+                    let b = rng.gen_range(0..alphabet);
+                    (if skewed { b * b / alphabet } else { b }) as u8
+                })
+                .collect();
+            assert_eq!(data, decompress(&compress(&data)).unwrap());
+        }
+    }
+
+    #[test]
+    fn damaged_input_is_an_error() {
+        let compressed = compress(TEXT.as_bytes());
+        assert_eq!(Err(HuffmanError::Truncated), decompress(&compressed[..5]));
+        assert_eq!(Err(HuffmanError::Truncated), decompress(&compressed[..compressed.len() - 10]));
+
+        // A byte listed twice in the header
+        let mut header = compress(b"ab");
+        header[11] = header[9];
+        assert_eq!(Err(HuffmanError::InvalidHeader), decompress(&header));
+    }
+}
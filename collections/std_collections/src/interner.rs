@@ -0,0 +1,195 @@
+// String Interning
+
+// A program that sees the same strings over and over (the words of a text, the names of headers, the identifiers of a
+// compiler) can keep every distinct one once, and hand out a small number for it instead: a Symbol.
+    // 1. intern(s) returns the Symbol of s, adding s the first time it's seen. Only that first time allocates.
+    // 2. resolve(symbol) gives the text back, it's an index into a Vec, so O(1).
+    // 3. A Symbol is a Copy u32: comparing or hashing two of them is comparing two integers, whatever the length of the text.
+// Symbols are numbered from 0 in the order the strings were interned, so they also work as indexes into a Vec of whatever
+// the program keeps per string. A Symbol only means something to the interner that made it.
+
+// The map from text to Symbol and the Vec from Symbol to text share their strings: both hold an Arc<str> of the same
+// allocation. That's a reference count per string, where an interner with an arena and unsafe code would get by without.
+
+// An interner only ever grows. Interning text that comes from outside, say every header name a client makes up, lets the
+// client fill memory, so the webserver only keeps a fixed set of names in one and leaves the others as they are.
+
+// Like a HashMap it takes the BuildHasher as a parameter, RandomState unless it's given another. A fixed set of strings
+// that's interned once can use a faster one, like FNV (std_collections/src/hashing.rs): nobody can add keys that collide.
+
+// SyncInterner is the same behind an RwLock, for threads sharing it. Interning a string that's there already only takes the
+// read lock, so the threads only wait for each other while a new one is added.
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fmt,
+    hash::BuildHasher,
+    sync::{Arc, RwLock},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StringInterner<S = RandomState> {
+    symbols: HashMap<Arc<str>, Symbol, S>,
+    strings: Vec<Arc<str>>,
+}
+
+impl StringInterner {
+    pub fn new() -> StringInterner {
+        StringInterner::default()
+    }
+}
+
+impl<S: BuildHasher> StringInterner<S> {
+    pub fn with_hasher(hasher: S) -> StringInterner<S> {
+        StringInterner { symbols: HashMap::with_hasher(hasher), strings: Vec::new() }
+    }
+
+    pub fn intern(&mut self, text: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(text) {
+            return symbol;
+        }
+        let symbol = Symbol(u32::try_from(self.strings.len()).expect("more than u32::MAX strings interned"));
+        let text: Arc<str> = Arc::from(text);
+        self.strings.push(Arc::clone(&text));
+        self.symbols.insert(text, symbol);
+        symbol
+    }
+
+    // The Symbol of text when it was interned before, without adding it
+    pub fn get(&self, text: &str) -> Option<Symbol> {
+        self.symbols.get(text).copied()
+    }
+
+    // Panics for a Symbol from another interner that's past the end of this one
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    // In the order of the Symbols
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &str)> {
+        self.strings.iter().enumerate().map(|(i, text)| (Symbol(i as u32), &**text))
+    }
+}
+
+// Interned in the order they come, so the first one is Symbol 0
+impl<'a, S: BuildHasher + Default> FromIterator<&'a str> for StringInterner<S> {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        let mut interner = StringInterner::with_hasher(S::default());
+        for text in iter {
+            interner.intern(text);
+        }
+        interner
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncInterner {
+    inner: RwLock<StringInterner>,
+}
+
+impl SyncInterner {
+    pub fn new() -> SyncInterner {
+        SyncInterner::default()
+    }
+
+    pub fn intern(&self, text: &str) -> Symbol {
+        if let Some(symbol) = self.get(text) {
+            return symbol;
+        }
+        // Another thread may have added it between the two locks, intern() then finds it
+        self.inner.write().unwrap().intern(text)
+    }
+
+    pub fn get(&self, text: &str) -> Option<Symbol> {
+        self.inner.read().unwrap().get(text)
+    }
+
+    // A clone of the Arc, the text can't be borrowed past the read lock
+    pub fn resolve(&self, symbol: Symbol) -> Arc<str> {
+        Arc::clone(&self.inner.read().unwrap().strings[symbol.index()])
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<StringInterner> for SyncInterner {
+    fn from(interner: StringInterner) -> Self {
+        SyncInterner { inner: RwLock::new(interner) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::FnvBuildHasher;
+    use std::thread;
+
+    #[test]
+    fn interns_each_string_once() {
+        let mut interner: StringInterner = ["host", "accept"].into_iter().collect();
+        let host = interner.intern("host");
+        assert_eq!((0, Some(host)), (host.index(), interner.get("host")));
+        let cookie = interner.intern("cookie");
+        assert_eq!((2, 3), (cookie.index(), interner.len()));
+        assert_eq!(("cookie", None), (interner.resolve(cookie), interner.get("Cookie")));
+        assert_eq!(vec!["host", "accept", "cookie"], interner.iter().map(|(_, text)| text).collect::<Vec<_>>());
+
+        // The map and the Vec share one allocation per string
+        assert_eq!(2, Arc::strong_count(&interner.strings[cookie.index()]));
+
+        let fnv: StringInterner<FnvBuildHasher> = interner.iter().map(|(_, text)| text).collect();
+        assert_eq!((Some(cookie), None), (fnv.get("cookie"), fnv.get("accept-encoding")));
+    }
+
+    #[test]
+    fn threads_agree_on_the_symbols() {
+        let interner = SyncInterner::from(StringInterner::from_iter(["zero"]));
+        let words = ["alpha", "beta", "gamma", "delta", "zero"];
+        let seen: Vec<Vec<Symbol>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|t| {
+                    let interner = &interner;
+                    s.spawn(move || (0..100).map(|i| interner.intern(words[(i + t) % words.len()])).collect())
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        assert_eq!(5, interner.len());
+        for (t, symbols) in seen.iter().enumerate() {
+            for (i, symbol) in symbols.iter().enumerate() {
+                assert_eq!(words[(i + t) % words.len()], &*interner.resolve(*symbol));
+            }
+        }
+        assert_eq!(Some(Symbol(0)), interner.get("zero"));
+    }
+}
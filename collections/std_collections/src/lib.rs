@@ -0,0 +1,16 @@
+// Collections built on top of the standard library ones, kept in a library crate so they can be tested and reused.
+// main.rs walks through the std collections themselves and uses these at the end.
+
+pub mod arrayvec;
+pub mod bitvec;
+pub mod graph;
+pub mod hashing;
+pub mod huffman;
+pub mod interner;
+pub mod probabilistic;
+pub mod rand_lite;
+pub mod rope;
+pub mod slab;
+pub mod small_string;
+pub mod trie;
+pub mod unicode_ext;
@@ -13,6 +13,14 @@
 use std::collections::HashMap;
 use unicode_segmentation::UnicodeSegmentation;
 
+use std_collections::{
+    graph::Graph,
+    hashing::{FnvBuildHasher, SipBuildHasher},
+    rope::Rope,
+    trie::Trie,
+    unicode_ext,
+};
+
 fn main() {
     println!("Hello, world!");
 
@@ -114,6 +122,12 @@ fn main() {
     main2();
 
     main3();
+
+    main4();
+
+    main5();
+
+    main6();
 }
 
 // Strings
@@ -244,6 +258,12 @@ fn main2() {
         println!("{g}");
     }
 
+    // std_collections::unicode_ext has the safe versions of the rest, all on grapheme clusters: truncating or reversing
+    // never cuts a letter in half, and display_width counts the columns a terminal draws rather than bytes or chars
+    println!("{}", unicode_ext::truncate("नमस्ते", 2));
+    println!("{}", unicode_ext::reverse("नमस्ते"));
+    println!("{} columns", unicode_ext::display_width("東京"));
+
     // Programmers have to put more thought into handling UTF-8 data upfront. This trade-off exposes more of the complexity of strings than is apparent in other programming languages, but it prevents you from having to handle errors involving non-ASCII characters later in your development life cycle.
 }
 
@@ -341,4 +361,88 @@ fn main3() {
     // By default, HashMap uses a hashing function called SipHash that can provide resistance to Denial of Service (DoS) attacks involving hash tables1.
     // This is not the fastest hashing algorithm available, but the trade-off for better security that comes with the drop in performance is worth it
     //  You can switch to another function by specifying a different hasher. A hasher is a type that implements the BuildHasher trait. crates.io has libraries which provide hashers implementing many common hashing algorithms.
+
+    // src/hashing.rs implements two of them from scratch, FNV-1a and SipHash itself. FNV-1a is faster for short keys but has no secret key,
+    // so only use it when the keys don't come from someone who might want to slow the map down on purpose.
+    let mut fast: HashMap<&str, i32, FnvBuildHasher> = HashMap::default();
+    fast.insert("Blue", 10);
+
+    let mut keyed = HashMap::with_hasher(SipBuildHasher::random());
+    keyed.insert(String::from("Yellow"), 50);
+
+    println!("FNV map: {:?}, SipHash map: {:?}", fast, keyed);
+}
+
+
+// Building Bigger Structures out of Collections
+
+// A graph can be built from nothing more than two vectors: one holding the nodes and one holding a vector of outgoing edges per node. Refer src/graph.rs for the implementation.
+// Nodes are referred to by index (NodeId) instead of by reference, which keeps the borrow checker happy while nodes point at each other.
+
+fn main4() {
+    let mut roads = Graph::new_undirected();
+    let home = roads.add_node("Home");
+    let park = roads.add_node("Park");
+    let shop = roads.add_node("Shop");
+    let school = roads.add_node("School");
+
+    roads.add_edge(home, park, 4);
+    roads.add_edge(home, shop, 1);
+    roads.add_edge(shop, park, 2);
+    roads.add_edge(park, school, 3);
+
+    // Breadth first search visits the nodes closest (in number of edges) to the start first
+    let visited: Vec<&str> = roads.bfs(home).map(|id| *roads.node(id)).collect();
+    println!("BFS from Home: {:?}", visited);
+
+    // Dijkstra's algorithm takes the edge weights into account, going through the shop is shorter than the direct road to the park
+    if let Some((cost, path)) = roads.shortest_path(home, school) {
+        let path: Vec<&str> = path.into_iter().map(|id| *roads.node(id)).collect();
+        println!("Shortest way to school: {:?} ({cost})", path);
+    }
+
+    // Display prints the graph in the DOT format, which graphviz can draw
+    println!("{roads}");
+
+    // Topological sort orders tasks so that every task comes after the ones it depends on
+    let mut tasks = Graph::new_directed();
+    let wake = tasks.add_node("wake up");
+    let dress = tasks.add_node("get dressed");
+    let shoes = tasks.add_node("put on shoes");
+    tasks.add_edge(dress, shoes, ());
+    tasks.add_edge(wake, dress, ());
+
+    match tasks.topological_sort() {
+        Ok(order) => println!("Order: {:?}", order.into_iter().map(|id| *tasks.node(id)).collect::<Vec<_>>()),
+        Err(e) => println!("Can't order the tasks: {e}"),
+    }
+}
+
+// Autocomplete with a Trie
+
+// A trie stores words letter by letter so that words with a common prefix share the same path, refer src/trie.rs.
+// Since letters are grapheme clusters (see the strings section above), the trie is built on the unicode-segmentation crate as well.
+// suggest() returns the k most frequently inserted words with a given prefix.
+
+fn main5() {
+    let text = "the cat sat on the mat then the cat ran to the theatre";
+    let trie: Trie = text.split_whitespace().collect();
+
+    println!("Suggestions for 'th': {:?}", trie.suggest("th", 3));
+    println!("Suggestions for 'ca': {:?}", trie.suggest("ca", 3));
+}
+
+// Editing Text with a Rope
+
+// We saw above that a String can't be indexed by char in O(1), and inserting in the middle shifts everything after it.
+// A Rope (src/rope.rs) is a balanced tree of small string chunks that takes char indices and makes insert and delete O(log n).
+
+fn main6() {
+    let mut rope = Rope::from("Здравствуйте, world");
+    rope.insert(14, "big ");
+    rope.delete(0, 12);
+    rope.insert(0, "Hello");
+
+    println!("{rope}");
+    println!("char 6 is {:?}, there are {} chars", rope.char_at(6), rope.len_chars());
 }
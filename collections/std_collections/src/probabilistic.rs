@@ -0,0 +1,267 @@
+// Probabilistic Data Structures
+
+// A HashSet answers "have I seen this?" exactly, but it has to keep every item it has seen. When the items are many and
+// a slightly wrong answer once in a while is fine, two classic structures answer from a few kilobytes instead:
+    // 1. A BloomFilter answers "have I seen this?" with either "definitely not" or "probably". A key-value store asks it before
+    //    looking for a key on disk: "definitely not" saves the disk read, and a wrong "probably" only costs one read for nothing.
+    // 2. A HyperLogLog answers "how many different items have I seen?" to within a couple of percent, in a fixed amount of memory
+    //    whether there were a thousand or a billion of them.
+// Both hash the items with SipHash from hashing.rs, under fixed keys so that two filters built the same way can be combined.
+
+use std::hash::{Hash, Hasher};
+
+use crate::{bitvec::BitVec, hashing::SipHasher};
+
+fn sip_hash<T: Hash + ?Sized>(item: &T, k0: u64, k1: u64) -> u64 {
+    let mut hasher = SipHasher::with_keys(k0, k1);
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Bloom Filters
+
+// The filter is an array of m bits (a BitVec from bitvec.rs), all 0 at first. Adding an item sets k bits, chosen by k hash functions; an item is probably
+// in the filter when all k of its bits are set. Other items may have set those bits between them, that's a false positive,
+// but a bit is never cleared, so an item that was added is always found.
+
+// For n items and a false positive rate p, the best sizes are:
+    // m = -n * ln(p) / ln(2)^2 bits, about 9.6 bits per item for 1%
+    // k = m / n * ln(2) hash functions, about 7 for 1%
+// Computing k real hashes per item would be slow. Kirsch and Mitzenmacher showed that h1 + i * h2, for i in 0..k,
+// is as good as k independent hashes for a Bloom filter, so every item is only hashed twice.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: BitVec,
+    k: u32,
+    items: usize,
+}
+
+impl BloomFilter {
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> BloomFilter {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "the false positive rate must be between 0 and 1"
+        );
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let m = (-n * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(1.0) as u64;
+        let k = ((m as f64 / n) * ln2).round().max(1.0) as u32;
+        BloomFilter::with_size(m, k)
+    }
+
+    pub fn with_size(bits: u64, hashes: u32) -> BloomFilter {
+        assert!(bits > 0 && hashes > 0, "a Bloom filter needs at least one bit and one hash function");
+        BloomFilter { bits: BitVec::repeat(false, bits as usize), k: hashes, items: 0 }
+    }
+
+    fn positions<T: Hash + ?Sized>(&self, item: &T) -> impl Iterator<Item = usize> {
+        let h1 = sip_hash(item, 0x0123456789abcdef, 0xfedcba9876543210);
+        // An even h2 would only ever reach half of the positions when m is even
+        let h2 = sip_hash(item, 0x9e3779b97f4a7c15, 0xd1b54a32d192ed03) | 1;
+        let m = self.bits.len() as u64;
+        (0..self.k as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        for bit in self.positions(item) {
+            self.bits.set(bit, true);
+        }
+        self.items += 1;
+    }
+
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        self.positions(item).all(|bit| self.bits.get(bit) == Some(true))
+    }
+
+    // How many times insert was called, an item added twice counts twice
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.bits.len() as u64
+    }
+
+    pub fn hashes(&self) -> u32 {
+        self.k
+    }
+
+    // The chance that an item that was never added is found anyway, given how many bits are set now
+    pub fn false_positive_rate(&self) -> f64 {
+        (self.bits.count_ones() as f64 / self.bits.len() as f64).powi(self.k as i32)
+    }
+
+    // The filter of everything in both. Only filters of the same size and number of hashes set the same bits for an item.
+    pub fn union(&mut self, other: &BloomFilter) {
+        assert!(
+            self.bits.len() == other.bits.len() && self.k == other.k,
+            "only Bloom filters of the same shape can be combined"
+        );
+        self.bits |= &other.bits;
+        self.items += other.items;
+    }
+}
+
+// HyperLogLog
+
+// Flip coins until the first head: a run of 10 tails happens about once every 2^10 tries. So if the longest run seen is 10,
+// there were probably around a thousand tries. A hash is a row of 64 coin flips, and hashing the same item again flips the
+// same coins, so the longest run of leading zeros among the hashes says roughly how many different items there were.
+
+// One longest run is a very rough guess. HyperLogLog splits the items into m = 2^p groups by the first p bits of their hash,
+// keeps the longest run (+1) of every group in a register, and combines the registers with a harmonic mean, which isn't
+// thrown off by the few unlucky groups. The standard error is 1.04 / sqrt(m): 1.6% for p = 12, with 4 KiB of registers.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+    p: u32,
+}
+
+impl HyperLogLog {
+    pub fn new(precision: u32) -> HyperLogLog {
+        assert!((4..=16).contains(&precision), "the precision of a HyperLogLog must be between 4 and 16");
+        HyperLogLog { registers: vec![0; 1 << precision], p: precision }
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let hash = sip_hash(item, 0x243f6a8885a308d3, 0x13198a2e03707344);
+        let index = (hash >> (64 - self.p)) as usize;
+        // The bits after the index, with a 1 at the end so that a hash of all zeros still stops counting
+        let rest = (hash << self.p) | (1 << (self.p - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        // The constant corrects the bias of the harmonic mean, its value comes from the HyperLogLog paper
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+
+        // With few items most registers are still 0 and the estimate is poor. Counting the empty registers works better
+        // then: it's the same calculation as how many balls were thrown if that many of m bins are still empty.
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+
+    // Registers only ever keep maximums, so merging two counters is the maximum of every register, and the result
+    // counts the items seen by either of them, just as if one counter had seen them all
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(self.p, other.p, "only HyperLogLogs with the same precision can be merged");
+        for (register, &other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(other);
+        }
+    }
+
+    pub fn precision(&self) -> u32 {
+        self.p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_filter_sizes() {
+        let filter = BloomFilter::new(1000, 0.01);
+        // 9586 bits and 7 hashes for 1000 items at 1%
+        assert_eq!(9586, filter.bits());
+        assert_eq!(7, filter.hashes());
+        assert!(filter.is_empty());
+        assert_eq!(0.0, filter.false_positive_rate());
+    }
+
+    #[test]
+    fn bloom_filter_errors_stay_within_bounds() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        for i in 0..10_000 {
+            filter.insert(&i);
+        }
+
+        // Never a false negative
+        assert!((0..10_000).all(|i| filter.contains(&i)));
+
+        // False positives close to the 1% it was sized for. Out of 100,000 tries that's 1000 expected,
+        // with a standard deviation of about 31, so 1300 would be almost 10 standard deviations off.
+        let false_positives = (10_000..110_000).filter(|i| filter.contains(i)).count();
+        assert!((700..1300).contains(&false_positives), "{false_positives} false positives");
+        assert!((0.007..0.013).contains(&filter.false_positive_rate()));
+    }
+
+    #[test]
+    fn bloom_filter_union() {
+        let (mut a, mut b) = (BloomFilter::new(100, 0.01), BloomFilter::new(100, 0.01));
+        a.insert("apple");
+        b.insert("banana");
+        a.union(&b);
+        assert!(a.contains("apple") && a.contains("banana"));
+        assert_eq!(2, a.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "same shape")]
+    fn bloom_filters_of_different_shapes_dont_combine() {
+        BloomFilter::new(100, 0.01).union(&BloomFilter::new(100, 0.1));
+    }
+
+    fn relative_error(estimate: u64, actual: u64) -> f64 {
+        (estimate as f64 - actual as f64).abs() / actual as f64
+    }
+
+    #[test]
+    fn hyperloglog_estimates_within_error_bounds() {
+        // p = 12 has a standard error of 1.6%, 5% is over 3 standard errors
+        for n in [1_000u64, 10_000, 100_000] {
+            let mut hll = HyperLogLog::new(12);
+            for i in 0..n {
+                hll.insert(&i);
+                // Duplicates don't change anything
+                hll.insert(&i);
+            }
+            let estimate = hll.estimate();
+            assert!(relative_error(estimate, n) < 0.05, "estimated {estimate} for {n}");
+        }
+
+        let mut small = HyperLogLog::new(12);
+        assert_eq!(0, small.estimate());
+        for word in ["a", "b", "c", "a", "b"] {
+            small.insert(word);
+        }
+        assert_eq!(3, small.estimate());
+    }
+
+    #[test]
+    fn merged_hyperloglogs_count_the_union() {
+        let (mut a, mut b, mut all) = (HyperLogLog::new(10), HyperLogLog::new(10), HyperLogLog::new(10));
+        for i in 0..30_000u32 {
+            all.insert(&i);
+            // Overlapping halves: 0..20000 and 10000..30000
+            if i < 20_000 {
+                a.insert(&i);
+            }
+            if i >= 10_000 {
+                b.insert(&i);
+            }
+        }
+        a.merge(&b);
+        assert_eq!(all, a);
+        assert!(relative_error(a.estimate(), 30_000) < 0.1);
+    }
+}
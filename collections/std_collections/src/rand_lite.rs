@@ -0,0 +1,356 @@
+// Random Numbers Without the rand Crate
+
+// A computer can't flip a coin, so most "random" numbers are pseudo-random: a small state is scrambled by a fixed function
+// every time a number is asked for. The same seed always gives the same sequence, which is exactly what a test or a simulation
+// wants, and a seed nobody can guess gives numbers nobody can predict (well enough for games and IDs, NOT for cryptography).
+
+// Two classic generators, both tiny and fast:
+    // 1. Pcg32 is a linear congruential generator (state = state * a + c) whose output is scrambled by a shift and a rotation,
+    //    which hides the weak low bits an LCG has on its own. 64 bits of state, 32 bits out per step.
+    // 2. Xoshiro256 (xoshiro256** to be exact) mixes 256 bits of state with xors, shifts and rotations, 64 bits out per step.
+    //    Its state must not be all zeros, so it's seeded through SplitMix64, which turns any u64 into well spread out state.
+
+// Everything else is built on the Rng trait, which only needs next_u32 and next_u64:
+    // 1. gen_range picks from a range without the bias of `next_u64() % n`, see uniform() below.
+    // 2. shuffle puts a slice in random order, sample picks k different indices out of n.
+    // 3. thread_rng() is a generator per thread seeded from the OS, for when the exact sequence doesn't matter.
+
+use std::{
+    cell::RefCell,
+    collections::{hash_map::RandomState, HashSet},
+    hash::BuildHasher,
+    ops::{Range, RangeInclusive},
+};
+
+pub trait Rng {
+    fn next_u32(&mut self) -> u32;
+    fn next_u64(&mut self) -> u64;
+
+    // Panics when the range is empty, there's nothing to pick from
+    fn gen_range<T, R>(&mut self, range: R) -> T
+    where
+        R: SampleRange<T>,
+        Self: Sized,
+    {
+        range.sample(self)
+    }
+
+    // The top 53 bits, as many as an f64 can hold exactly, scaled into [0, 1)
+    fn gen_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn gen_bool(&mut self, p: f64) -> bool {
+        self.gen_f64() < p
+    }
+
+    // Fisher-Yates: walking from the back, every element is swapped with one at or before it, chosen uniformly.
+    // Every one of the n! orders comes out equally likely, which "swap each element with any other" doesn't manage.
+    fn shuffle<T>(&mut self, items: &mut [T])
+    where
+        Self: Sized,
+    {
+        for i in (1..items.len()).rev() {
+            let j = self.gen_range(0..=i);
+            items.swap(i, j);
+        }
+    }
+
+    fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T>
+    where
+        Self: Sized,
+    {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[self.gen_range(0..items.len())])
+        }
+    }
+
+    // k different indices out of 0..n, in random order, without making a list of all n.
+    // This is Floyd's algorithm: for j in n-k..n pick t from 0..=j, and take j itself if t was already taken.
+    // It needs exactly k random numbers and never retries.
+    fn sample(&mut self, n: usize, k: usize) -> Vec<usize>
+    where
+        Self: Sized,
+    {
+        assert!(k <= n, "can't sample {k} different values out of {n}");
+        let mut taken = HashSet::with_capacity(k);
+        let mut picked = Vec::with_capacity(k);
+        for j in n - k..n {
+            let t = self.gen_range(0..=j);
+            let pick = if taken.insert(t) { t } else { taken.insert(j); j };
+            picked.push(pick);
+        }
+        // Floyd's picks come out in a biased order, the set is uniform but the sequence isn't
+        self.shuffle(&mut picked);
+        picked
+    }
+}
+
+// Uniform Ranges
+
+// `next_u64() % n` favours the small results whenever n doesn't divide 2^64. Lemire's method multiplies instead:
+// the top 64 bits of x * n are uniform in 0..n, except for a few values of x at the bottom, which are rejected and drawn again.
+// The check costs a division only in the rare case where the low bits say a rejection is possible at all.
+fn uniform<R: Rng + ?Sized>(rng: &mut R, n: u64) -> u64 {
+    let mut m = rng.next_u64() as u128 * n as u128;
+    if (m as u64) < n {
+        let threshold = n.wrapping_neg() % n;
+        while (m as u64) < threshold {
+            m = rng.next_u64() as u128 * n as u128;
+        }
+    }
+    (m >> 64) as u64
+}
+
+pub trait SampleRange<T> {
+    fn sample<R: Rng + ?Sized>(self, rng: &mut R) -> T;
+}
+
+// Every integer type goes through u64: the range is shifted to start at 0 with wrapping arithmetic, which also works for signed types
+macro_rules! impl_sample_range {
+    ($($t:ty),*) => {$(
+        impl SampleRange<$t> for Range<$t> {
+            fn sample<R: Rng + ?Sized>(self, rng: &mut R) -> $t {
+                assert!(self.start < self.end, "cannot sample from an empty range");
+                let span = (self.end as u64).wrapping_sub(self.start as u64);
+                (self.start as u64).wrapping_add(uniform(rng, span)) as $t
+            }
+        }
+
+        impl SampleRange<$t> for RangeInclusive<$t> {
+            fn sample<R: Rng + ?Sized>(self, rng: &mut R) -> $t {
+                let (start, end) = self.into_inner();
+                assert!(start <= end, "cannot sample from an empty range");
+                let span = (end as u64).wrapping_sub(start as u64).wrapping_add(1);
+                // 0 means the span wrapped around: the range is every u64 (or i64), so any value will do
+                let offset = if span == 0 { rng.next_u64() } else { uniform(rng, span) };
+                (start as u64).wrapping_add(offset) as $t
+            }
+        }
+    )*};
+}
+
+impl_sample_range!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+// Generators
+
+#[derive(Debug, Clone)]
+pub struct Pcg32 {
+    state: u64,
+    // Must be odd. Different increments give different, independent sequences (streams) from the same seed.
+    increment: u64,
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0, increment: (stream << 1) | 1 };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    pub fn seed_from_u64(seed: u64) -> Pcg32 {
+        Pcg32::new(seed, 0xda3e39cb94b95bdb)
+    }
+
+    fn step(&mut self) -> u64 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+        old
+    }
+}
+
+impl Rng for Pcg32 {
+    // XSH RR: xorshift the high bits down, keep 32 of them, and rotate by an amount taken from the top 5 bits
+    fn next_u32(&mut self) -> u32 {
+        let old = self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+// SplitMix64 is a counter run through a good mixing function, any seed (even 0) comes out as random looking bits
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Xoshiro256 {
+    pub fn seed_from_u64(seed: u64) -> Xoshiro256 {
+        let mut state = seed;
+        Xoshiro256 { s: std::array::from_fn(|_| splitmix64(&mut state)) }
+    }
+
+    // Seeded from the OS. RandomState already gets its keys from there, so hashing anything with a new one gives random bits,
+    // the same trick SipBuildHasher::random uses in hashing.rs.
+    pub fn from_entropy() -> Xoshiro256 {
+        Xoshiro256::seed_from_u64(RandomState::new().hash_one(0u8))
+    }
+
+    // The whole state, for the reference vectors in the tests. All zeros would make the generator return 0 forever.
+    pub fn from_state(s: [u64; 4]) -> Xoshiro256 {
+        assert!(s != [0; 4], "the state of xoshiro256 must not be all zeros");
+        Xoshiro256 { s }
+    }
+}
+
+impl Rng for Xoshiro256 {
+    fn next_u32(&mut self) -> u32 {
+        // The high bits are the best ones
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+}
+
+// The Thread-Local Generator
+
+// Each thread gets its own Xoshiro256 the first time it asks, so there's no lock to fight over.
+// ThreadRng is only a handle to it: it can't be sent to another thread, and every call borrows the generator for a moment.
+thread_local! {
+    static THREAD_RNG: RefCell<Xoshiro256> = RefCell::new(Xoshiro256::from_entropy());
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadRng {
+    // Not Send, the generator belongs to the thread that made the handle
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+pub fn thread_rng() -> ThreadRng {
+    ThreadRng { _not_send: std::marker::PhantomData }
+}
+
+impl Rng for ThreadRng {
+    fn next_u32(&mut self) -> u32 {
+        THREAD_RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        THREAD_RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+}
+
+// For a single number, without keeping a handle around
+pub fn random_u64() -> u64 {
+    thread_rng().next_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcg32_reference_output() {
+        // From the pcg32 demo program: seed 42, stream 54
+        let mut rng = Pcg32::new(42, 54);
+        let expected = [0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e];
+        for value in expected {
+            assert_eq!(value, rng.next_u32());
+        }
+    }
+
+    #[test]
+    fn xoshiro_and_splitmix_reference_output() {
+        let mut rng = Xoshiro256::from_state([1, 2, 3, 4]);
+        assert_eq!([0x2d00, 0, 0x5a007080], [rng.next_u64(), rng.next_u64(), rng.next_u64()]);
+
+        let mut state = 0;
+        assert_eq!(0xe220a8397b1dcdaf, splitmix64(&mut state));
+        assert_eq!(0x6e789e6aa1b965f4, splitmix64(&mut state));
+
+        // Same seed, same sequence
+        let (mut a, mut b) = (Xoshiro256::seed_from_u64(7), Xoshiro256::seed_from_u64(7));
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+
+    #[test]
+    fn ranges_stay_in_bounds_and_hit_every_value() {
+        let mut rng = Pcg32::seed_from_u64(1);
+        let mut seen = [0u32; 6];
+        for _ in 0..6000 {
+            let roll: u8 = rng.gen_range(1..=6);
+            seen[roll as usize - 1] += 1;
+        }
+        // Each face expects 1000, a fair die stays well within 850..1150
+        assert!(seen.iter().all(|&count| (850..1150).contains(&count)), "{seen:?}");
+
+        for _ in 0..1000 {
+            let n: i32 = rng.gen_range(-5..5);
+            assert!((-5..5).contains(&n));
+        }
+        assert_eq!(3, rng.gen_range(3..4));
+        assert_eq!(i8::MIN, rng.gen_range(i8::MIN..=i8::MIN));
+        // The full range wraps the span around to 0, it must still work
+        rng.gen_range(0..=u64::MAX);
+        rng.gen_range(i64::MIN..=i64::MAX);
+
+        let x = rng.gen_f64();
+        assert!((0.0..1.0).contains(&x));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty range")]
+    fn empty_ranges_panic() {
+        Pcg32::seed_from_u64(1).gen_range(5..5);
+    }
+
+    #[test]
+    fn shuffles_and_samples() {
+        let mut rng = Xoshiro256::seed_from_u64(42);
+        let mut deck: Vec<u32> = (0..52).collect();
+        rng.shuffle(&mut deck);
+        assert_ne!((0..52).collect::<Vec<u32>>(), deck);
+        deck.sort();
+        assert_eq!((0..52).collect::<Vec<u32>>(), deck);
+
+        let picked = rng.sample(10, 4);
+        assert_eq!(4, picked.len());
+        assert_eq!(4, picked.iter().collect::<HashSet<_>>().len());
+        assert!(picked.iter().all(|&i| i < 10));
+        let mut all = rng.sample(5, 5);
+        all.sort();
+        assert_eq!(vec![0, 1, 2, 3, 4], all);
+
+        assert_eq!(None, rng.choose::<u8>(&[]));
+        assert_eq!(Some(&7), rng.choose(&[7]));
+    }
+
+    #[test]
+    fn every_thread_has_its_own_generator() {
+        let here = random_u64();
+        let there = std::thread::spawn(random_u64).join().unwrap();
+        assert_ne!(here, there);
+        let mut rng = thread_rng();
+        let roll: u32 = rng.gen_range(1..=100);
+        assert!((1..=100).contains(&roll));
+    }
+}
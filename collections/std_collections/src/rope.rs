@@ -0,0 +1,467 @@
+// Rope: A String for Efficient Edits
+
+// Inserting into the middle of a String has to shift every byte after the insertion point, and finding the n-th char means walking the string from the start.
+// For a text editor holding a large document that's too slow, so editors use a rope instead: a balanced binary tree whose leaves hold small string chunks.
+
+// Every internal node remembers how many chars (and newlines) are in its subtree, so finding char index i only walks one path from the root: O(log n).
+// Insert and delete split the tree at the char index and join the pieces back together, which is O(log n) as well.
+
+// The tree is kept balanced the same way an AVL tree is: a node's two subtrees never differ in height by more than one.
+// Indices are char indices, not byte indices, so (unlike String slicing) it's impossible to cut a char in half.
+
+use std::fmt;
+
+// Leaves hold at most MAX_LEAF bytes, small enough that editing inside one leaf (a plain String) is cheap.
+const MAX_LEAF: usize = 1024;
+
+enum Node {
+    Leaf(String),
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        // Cached totals of the whole subtree, so we never have to walk it to count
+        chars: usize,
+        lines: usize,
+        height: usize,
+    },
+}
+
+impl Node {
+    fn chars(&self) -> usize {
+        match self {
+            Node::Leaf(s) => s.chars().count(),
+            Node::Branch { chars, .. } => *chars,
+        }
+    }
+
+    fn newlines(&self) -> usize {
+        match self {
+            Node::Leaf(s) => s.bytes().filter(|b| *b == b'\n').count(),
+            Node::Branch { lines, .. } => *lines,
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 0,
+            Node::Branch { height, .. } => *height,
+        }
+    }
+
+    // Creates a branch without rebalancing, only used when the two sides are already within one level of each other
+    fn branch(left: Node, right: Node) -> Node {
+        Node::Branch {
+            chars: left.chars() + right.chars(),
+            lines: left.newlines() + right.newlines(),
+            height: 1 + left.height().max(right.height()),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    // Builds a balanced tree out of a string by cutting it into leaves and building each half of the leaves recursively
+    fn from_str(s: &str) -> Node {
+        let mut leaves = Vec::new();
+        let mut rest = s;
+        while rest.len() > MAX_LEAF {
+            let cut = floor_char_boundary(rest, MAX_LEAF / 2);
+            leaves.push(Node::Leaf(rest[..cut].to_string()));
+            rest = &rest[cut..];
+        }
+        leaves.push(Node::Leaf(rest.to_string()));
+        Node::from_leaves(leaves)
+    }
+
+    // Splitting the leaves in half at every level keeps the two sides within one level of each other
+    fn from_leaves(mut leaves: Vec<Node>) -> Node {
+        if leaves.len() == 1 {
+            return leaves.pop().unwrap();
+        }
+        let right = leaves.split_off(leaves.len() / 2);
+        Node::branch(Node::from_leaves(leaves), Node::from_leaves(right))
+    }
+}
+
+// The largest index <= index that is the start of a char. str::floor_char_boundary does this but isn't stable yet.
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn byte_index(s: &str, char_index: usize) -> usize {
+    s.char_indices().nth(char_index).map_or(s.len(), |(i, _)| i)
+}
+
+// Joining Trees
+
+// join(left, right) builds a balanced tree containing left followed by right.
+// If the heights are close we can just put them under a new branch. Otherwise we walk down the spine of the taller tree until we find a subtree of similar height,
+// attach there, and rebalance on the way back up. This is the same idea as joining AVL trees.
+fn join(left: Node, right: Node) -> Node {
+    // Keep leaves from getting tiny by merging small neighbours into one leaf
+    if let (Node::Leaf(a), Node::Leaf(b)) = (&left, &right) {
+        if a.len() + b.len() <= MAX_LEAF {
+            return Node::Leaf(format!("{a}{b}"));
+        }
+    }
+    if left.chars() == 0 {
+        return right;
+    }
+    if right.chars() == 0 {
+        return left;
+    }
+
+    let (lh, rh) = (left.height(), right.height());
+    if lh > rh + 1 {
+        if let Node::Branch { left: ll, right: lr, .. } = left {
+            return balance(*ll, join(*lr, right));
+        }
+        unreachable!()
+    } else if rh > lh + 1 {
+        if let Node::Branch { left: rl, right: rr, .. } = right {
+            return balance(join(left, *rl), *rr);
+        }
+        unreachable!()
+    }
+
+    Node::branch(left, right)
+}
+
+// Rotations fix a node whose subtrees differ in height by two, which is the most join() can produce
+fn balance(left: Node, right: Node) -> Node {
+    let (lh, rh) = (left.height(), right.height());
+
+    if lh > rh + 1 {
+        if let Node::Branch { left: a, right: b, .. } = left {
+            if a.height() >= b.height() {
+                return Node::branch(*a, Node::branch(*b, right));
+            }
+            if let Node::Branch { left: b1, right: b2, .. } = *b {
+                return Node::branch(Node::branch(*a, *b1), Node::branch(*b2, right));
+            }
+        }
+        unreachable!()
+    } else if rh > lh + 1 {
+        if let Node::Branch { left: a, right: b, .. } = right {
+            if b.height() >= a.height() {
+                return Node::branch(Node::branch(left, *a), *b);
+            }
+            if let Node::Branch { left: a1, right: a2, .. } = *a {
+                return Node::branch(Node::branch(left, *a1), Node::branch(*a2, *b));
+            }
+        }
+        unreachable!()
+    }
+
+    Node::branch(left, right)
+}
+
+// split(node, i) returns the trees holding chars [0, i) and [i, len). Only the path down to index i is taken apart, everything else is reused as is.
+fn split(node: Node, index: usize) -> (Node, Node) {
+    match node {
+        Node::Leaf(mut s) => {
+            let at = byte_index(&s, index);
+            let tail = s.split_off(at);
+            (Node::Leaf(s), Node::Leaf(tail))
+        }
+        Node::Branch { left, right, .. } => {
+            let left_chars = left.chars();
+            if index < left_chars {
+                let (a, b) = split(*left, index);
+                (a, join(b, *right))
+            } else if index > left_chars {
+                let (a, b) = split(*right, index - left_chars);
+                (join(*left, a), b)
+            } else {
+                (*left, *right)
+            }
+        }
+    }
+}
+
+pub struct Rope {
+    root: Node,
+}
+
+impl Rope {
+    pub fn new() -> Rope {
+        Rope { root: Node::Leaf(String::new()) }
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.root.chars()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len_chars() == 0
+    }
+
+    // Number of lines, counted like str::lines: a trailing newline doesn't start another line
+    pub fn len_lines(&self) -> usize {
+        let len = self.len_chars();
+        if len == 0 {
+            return 0;
+        }
+        let unterminated = self.char_at(len - 1) != Some('\n');
+        self.root.newlines() + unterminated as usize
+    }
+
+    // The tree is replaced by value, so we temporarily swap an empty leaf into self.root to take ownership of the old tree
+    fn take_root(&mut self) -> Node {
+        std::mem::replace(&mut self.root, Node::Leaf(String::new()))
+    }
+
+    pub fn insert(&mut self, char_index: usize, text: &str) {
+        assert!(char_index <= self.len_chars(), "char index {char_index} out of bounds");
+        if text.is_empty() {
+            return;
+        }
+
+        let (left, right) = split(self.take_root(), char_index);
+        self.root = join(join(left, Node::from_str(text)), right);
+    }
+
+    // Removes the chars in the range start..end
+    pub fn delete(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.len_chars(), "range {start}..{end} out of bounds");
+
+        let (left, rest) = split(self.take_root(), start);
+        let (_removed, right) = split(rest, end - start);
+        self.root = join(left, right);
+    }
+
+    pub fn char_at(&self, char_index: usize) -> Option<char> {
+        let mut node = &self.root;
+        let mut index = char_index;
+        loop {
+            match node {
+                Node::Leaf(s) => return s.chars().nth(index),
+                Node::Branch { left, right, .. } => {
+                    let left_chars = left.chars();
+                    if index < left_chars {
+                        node = left;
+                    } else {
+                        index -= left_chars;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // The leaves in order, as borrowed &str slices. Concatenated they form the whole text.
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks { stack: vec![&self.root] }
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(|chunk| chunk.chars())
+    }
+
+    // A line can span several chunks, so lines are returned as owned Strings
+    pub fn lines(&self) -> Lines<'_> {
+        Lines { chunks: self.chunks(), current: String::new(), done: false }
+    }
+
+    // Copies the chars start..end out into a String
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        assert!(start <= end && end <= self.len_chars(), "range {start}..{end} out of bounds");
+        self.chars().skip(start).take(end - start).collect()
+    }
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&str> for Rope {
+    fn from(s: &str) -> Rope {
+        Rope { root: Node::from_str(s) }
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Chunks<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        while let Some(node) = self.stack.pop() {
+            match node {
+                Node::Leaf(s) if s.is_empty() => continue,
+                Node::Leaf(s) => return Some(s),
+                Node::Branch { left, right, .. } => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+            }
+        }
+        None
+    }
+}
+
+pub struct Lines<'a> {
+    chunks: Chunks<'a>,
+    current: String,
+    done: bool,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.done {
+            return None;
+        }
+        loop {
+            if let Some(pos) = self.current.find('\n') {
+                let mut line: String = self.current.drain(..=pos).collect();
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                return Some(line);
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.current.push_str(chunk),
+                None => {
+                    self.done = true;
+                    if self.current.is_empty() {
+                        return None;
+                    }
+                    return Some(std::mem::take(&mut self.current));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks the invariants every node must uphold: cached counts are right, and the tree is balanced
+    fn check(node: &Node) -> usize {
+        match node {
+            Node::Leaf(_) => 0,
+            Node::Branch { left, right, chars, lines, height } => {
+                let (lh, rh) = (check(left), check(right));
+                assert!(lh.abs_diff(rh) <= 1, "unbalanced: {lh} vs {rh}");
+                assert_eq!(*chars, left.chars() + right.chars());
+                assert_eq!(*lines, left.newlines() + right.newlines());
+                assert_eq!(*height, 1 + lh.max(rh));
+                *height
+            }
+        }
+    }
+
+    #[test]
+    fn basic_edits() {
+        let mut rope = Rope::from("Hello world");
+        rope.insert(5, ",");
+        rope.insert(12, "!");
+        assert_eq!("Hello, world!", rope.to_string());
+
+        rope.delete(0, 7);
+        assert_eq!("world!", rope.to_string());
+        assert_eq!(Some('w'), rope.char_at(0));
+        assert_eq!(None, rope.char_at(6));
+    }
+
+    #[test]
+    fn char_indices_not_bytes() {
+        let mut rope = Rope::from("Здравствуйте");
+        rope.insert(2, "-");
+        assert_eq!("Зд-равствуйте", rope.to_string());
+        assert_eq!("д-р", rope.slice(1, 4));
+        assert_eq!(13, rope.len_chars());
+    }
+
+    #[test]
+    fn large_text_is_balanced() {
+        let text = "0123456789".repeat(2000);
+        let rope = Rope::from(text.as_str());
+        check(&rope.root);
+        assert_eq!(text, rope.to_string());
+    }
+
+    #[test]
+    fn lines_span_chunks() {
+        let text = format!("{}\nshort\r\n{}\n", "a".repeat(3000), "b".repeat(2000));
+        let rope = Rope::from(text.as_str());
+        assert!(rope.chunks().count() > 1);
+        let lines: Vec<String> = rope.lines().collect();
+        let expected: Vec<&str> = text.lines().collect();
+        assert_eq!(expected, lines);
+        assert_eq!(3, rope.len_lines());
+    }
+
+    // A small xorshift generator, so the randomized test below is reproducible without depending on the rand crate
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    // Property test: apply the same random edits to a Rope and to a plain String (the reference implementation) and check they always agree
+    #[test]
+    fn random_edits_match_string() {
+        let pieces = ["a", "bc", "ü", "日本", "\n", "line\n", "🦀", &"x".repeat(700)];
+
+        for seed in 1..=20 {
+            let mut rng = XorShift(seed);
+            let mut rope = Rope::new();
+            let mut reference = String::new();
+
+            for _ in 0..300 {
+                let len = reference.chars().count();
+                if rng.below(3) > 0 || len == 0 {
+                    let at = rng.below(len + 1);
+                    let piece = pieces[rng.below(pieces.len())];
+                    rope.insert(at, piece);
+                    let byte = byte_index(&reference, at);
+                    reference.insert_str(byte, piece);
+                } else {
+                    let start = rng.below(len);
+                    let end = start + rng.below((len - start).min(800) + 1);
+                    rope.delete(start, end);
+                    let (bs, be) = (byte_index(&reference, start), byte_index(&reference, end));
+                    reference.replace_range(bs..be, "");
+                }
+
+                assert_eq!(reference.chars().count(), rope.len_chars());
+                check(&rope.root);
+            }
+
+            assert_eq!(reference, rope.to_string());
+            assert_eq!(reference.lines().count(), rope.len_lines());
+            let len = reference.chars().count();
+            let (a, b) = (len / 3, len / 2);
+            assert_eq!(reference.chars().skip(a).take(b - a).collect::<String>(), rope.slice(a, b));
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// Slab: Stable Keys into a Vec
+
+// A server keeps track of its open connections, a graph of its nodes: things that come and go, and that other code refers
+// to by a number. An index into a Vec is the cheapest number there is, but removing from the middle of a Vec moves everything
+// after it, and every index past the hole now means something else.
+
+// A Slab<T> never moves its values. Removing one leaves a vacant entry behind, and the next insert fills it again:
+    // 1. insert returns the key of the entry, a usize, and the key stays valid until that value is removed.
+    // 2. The vacant entries form a free list: each holds the key of the next vacant one, and the Slab the first. Inserting
+    //    takes the head of the list, removing pushes the entry on it, so both are O(1) and the Vec only grows when it's full.
+    // 3. Keys are reused, the last one removed is the first one handed out again. Whoever keeps a key after removing its
+    //    value can end up with somebody else's (the ABA problem), NodeIds and connection ids are only kept while they're live.
+// vacant_entry() hands out the key before the value exists, for a value that has to know its own key. The slab crate is the
+// full version of this, tokio keeps its tasks and I/O resources in one.
+
+use std::{
+    fmt, mem,
+    ops::{Index, IndexMut},
+};
+
+#[derive(Clone)]
+enum Entry<T> {
+    Occupied(T),
+    // The key of the next vacant entry, the length of the Vec at the end of the list
+    Vacant(usize),
+}
+
+#[derive(Clone)]
+pub struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    // The head of the free list
+    next_free: usize,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    pub const fn new() -> Slab<T> {
+        Slab { entries: Vec::new(), next_free: 0, len: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Slab<T> {
+        Slab { entries: Vec::with_capacity(capacity), next_free: 0, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    // Every key is below it, so a Vec this long has a place for each of them. Graph keeps its distances in one
+    pub fn key_bound(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn insert(&mut self, value: T) -> usize {
+        let entry = self.vacant_entry();
+        let key = entry.key();
+        entry.insert(value);
+        key
+    }
+
+    pub fn vacant_entry(&mut self) -> VacantEntry<'_, T> {
+        VacantEntry { key: self.next_free, slab: self }
+    }
+
+    pub fn get(&self, key: usize) -> Option<&T> {
+        match self.entries.get(key) {
+            Some(Entry::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        match self.entries.get_mut(key) {
+            Some(Entry::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, key: usize) -> bool {
+        self.get(key).is_some()
+    }
+
+    // None when the key is vacant, or was never handed out
+    pub fn try_remove(&mut self, key: usize) -> Option<T> {
+        let entry = self.entries.get_mut(key)?;
+        if let Entry::Vacant(_) = entry {
+            return None;
+        }
+        let Entry::Occupied(value) = mem::replace(entry, Entry::Vacant(self.next_free)) else { unreachable!() };
+        self.next_free = key;
+        self.len -= 1;
+        Some(value)
+    }
+
+    // Panics when the key is vacant, like indexing a Vec out of bounds
+    pub fn remove(&mut self, key: usize) -> T {
+        self.try_remove(key).unwrap_or_else(|| panic!("no value in the slab at key {key}"))
+    }
+
+    // Removes the values f returns false for. It's how to remove while going through them, iter() borrows the Slab
+    pub fn retain(&mut self, mut f: impl FnMut(usize, &mut T) -> bool) {
+        for key in 0..self.entries.len() {
+            if let Entry::Occupied(value) = &mut self.entries[key] {
+                if !f(key, value) {
+                    self.remove(key);
+                }
+            }
+        }
+    }
+
+    // Removes every value, with its key. The keys start from 0 again afterwards
+    pub fn drain(&mut self) -> impl Iterator<Item = (usize, T)> + '_ {
+        self.next_free = 0;
+        self.len = 0;
+        self.entries.drain(..).enumerate().filter_map(|(key, entry)| match entry {
+            Entry::Occupied(value) => Some((key, value)),
+            Entry::Vacant(_) => None,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next_free = 0;
+        self.len = 0;
+    }
+
+    // The values with their keys, in the order of the keys
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (usize, &T)> {
+        self.entries.iter().enumerate().filter_map(|(key, entry)| match entry {
+            Entry::Occupied(value) => Some((key, value)),
+            Entry::Vacant(_) => None,
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = (usize, &mut T)> {
+        self.entries.iter_mut().enumerate().filter_map(|(key, entry)| match entry {
+            Entry::Occupied(value) => Some((key, value)),
+            Entry::Vacant(_) => None,
+        })
+    }
+
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+// A key that's handed out already, for the value that goes there. Dropping it without inserting leaves the Slab as it was
+pub struct VacantEntry<'a, T> {
+    slab: &'a mut Slab<T>,
+    key: usize,
+}
+
+impl<'a, T> VacantEntry<'a, T> {
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    pub fn insert(self, value: T) -> &'a mut T {
+        let slab = self.slab;
+        if self.key == slab.entries.len() {
+            slab.entries.push(Entry::Occupied(value));
+            slab.next_free = self.key + 1;
+        } else {
+            let Entry::Vacant(next) = mem::replace(&mut slab.entries[self.key], Entry::Occupied(value)) else {
+                unreachable!("the head of the free list is vacant")
+            };
+            slab.next_free = next;
+        }
+        slab.len += 1;
+        match &mut slab.entries[self.key] {
+            Entry::Occupied(value) => value,
+            Entry::Vacant(_) => unreachable!(),
+        }
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Slab::new()
+    }
+}
+
+impl<T> Index<usize> for Slab<T> {
+    type Output = T;
+
+    fn index(&self, key: usize) -> &T {
+        self.get(key).unwrap_or_else(|| panic!("no value in the slab at key {key}"))
+    }
+}
+
+impl<T> IndexMut<usize> for Slab<T> {
+    fn index_mut(&mut self, key: usize) -> &mut T {
+        self.get_mut(key).unwrap_or_else(|| panic!("no value in the slab at key {key}"))
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Slab<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_stable_and_reused() {
+        let mut slab = Slab::new();
+        let (a, b, c) = (slab.insert("a"), slab.insert("b"), slab.insert("c"));
+        assert_eq!((0, 1, 2), (a, b, c));
+
+        assert_eq!("b", slab.remove(b));
+        assert_eq!((None, Some(&"c")), (slab.get(b), slab.get(c)));
+        assert_eq!(None, slab.try_remove(b));
+        assert_eq!(None, slab.try_remove(10));
+
+        // The last one removed is the first one reused, and the Vec only grows when nothing is vacant
+        slab.remove(a);
+        assert_eq!(a, slab.insert("d"));
+        assert_eq!(b, slab.insert("e"));
+        assert_eq!(3, slab.insert("f"));
+        assert_eq!((4, 4), (slab.len(), slab.key_bound()));
+        slab[c] = "C";
+        let entries: Vec<(usize, &str)> = slab.iter().map(|(key, value)| (key, *value)).collect();
+        assert_eq!(vec![(0, "d"), (1, "e"), (2, "C"), (3, "f")], entries);
+    }
+
+    #[test]
+    fn vacant_entries_know_their_key() {
+        let mut slab: Slab<(usize, &str)> = Slab::with_capacity(4);
+        slab.insert((0, "first"));
+        let entry = slab.vacant_entry();
+        let key = entry.key();
+        assert_eq!(&mut (1, "second"), entry.insert((key, "second")));
+
+        // Dropped without inserting
+        let unused = slab.vacant_entry().key();
+        assert_eq!((unused, 2), (slab.insert((unused, "third")), slab.len() - 1));
+        assert!(slab.iter().all(|(key, (own, _))| key == *own));
+    }
+
+    #[test]
+    fn removing_while_iterating() {
+        let mut slab: Slab<u32> = (0..10).fold(Slab::new(), |mut slab, n| {
+            slab.insert(n);
+            slab
+        });
+        slab.retain(|key, value| {
+            *value *= 10;
+            key % 3 != 0
+        });
+        assert_eq!(vec![10, 20, 40, 50, 70, 80], slab.values().copied().collect::<Vec<_>>());
+
+        // Collecting the keys first works too, iter() can't be held while removing
+        let odd: Vec<usize> = slab.iter().filter(|(_, value)| *value % 20 != 0).map(|(key, _)| key).collect();
+        for key in odd {
+            slab.remove(key);
+        }
+        assert_eq!(vec![(2, 20), (4, 40), (8, 80)], slab.iter().map(|(key, value)| (key, *value)).collect::<Vec<_>>());
+
+        // Every vacant key is reused before the Vec grows
+        let mut reused: Vec<usize> = (0..7).map(|n| slab.insert(n)).collect();
+        reused.sort();
+        assert_eq!(vec![0, 1, 3, 5, 6, 7, 9], reused);
+        assert_eq!(10, slab.insert(100));
+
+        assert_eq!(11, slab.drain().count());
+        assert!(slab.is_empty());
+        assert_eq!(0, slab.insert(1));
+    }
+}
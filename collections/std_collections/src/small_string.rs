@@ -0,0 +1,227 @@
+// SmallString: A String That Starts Out Inline
+
+// Most of the strings a program handles are short: header names, keys, words. SmallString<N> keeps up to N bytes inside the
+// value, like ArrayVec does (src/arrayvec.rs), and only moves them to a String on the heap when it grows past that. It
+// "spills", in the words of the smallvec crate, and stays spilled from then on.
+
+    // let mut name: SmallString<8> = SmallString::from("Host");
+    // assert!(name.is_inline());
+    // name.push_str("-And-Then-Some");
+    // assert!(name.spilled());
+
+// It derefs to str, so it reads like a &str: len(), starts_with(), eq_ignore_ascii_case() and the rest. It compares,
+// hashes and orders like one too, so an inline and a spilled SmallString with the same text are equal and hash the same.
+// The inline bytes are an ArrayVec<u8, N> that's only ever pushed whole UTF-8 strings, so they're always valid UTF-8.
+
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use crate::arrayvec::ArrayVec;
+
+#[derive(Clone)]
+enum Repr<const N: usize> {
+    Inline(ArrayVec<u8, N>),
+    Heap(String),
+}
+
+#[derive(Clone)]
+pub struct SmallString<const N: usize> {
+    repr: Repr<N>,
+}
+
+impl<const N: usize> SmallString<N> {
+    pub const fn new() -> SmallString<N> {
+        SmallString { repr: Repr::Inline(ArrayVec::new()) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.repr {
+            // SAFETY: only whole strs are ever pushed to the inline bytes
+            Repr::Inline(bytes) => unsafe { std::str::from_utf8_unchecked(bytes) },
+            Repr::Heap(string) => string,
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self.repr, Repr::Inline(_))
+    }
+
+    pub fn spilled(&self) -> bool {
+        !self.is_inline()
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        match &mut self.repr {
+            Repr::Inline(bytes) if bytes.len() + text.len() <= N => bytes.extend(text.bytes()),
+            Repr::Inline(_) => {
+                let mut string = String::with_capacity(self.len() + text.len());
+                string.push_str(self.as_str());
+                string.push_str(text);
+                self.repr = Repr::Heap(string);
+            }
+            Repr::Heap(string) => string.push_str(text),
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+
+    pub fn clear(&mut self) {
+        self.repr = Repr::Inline(ArrayVec::new());
+    }
+
+    pub fn into_string(self) -> String {
+        match self.repr {
+            Repr::Inline(_) => self.as_str().to_string(),
+            Repr::Heap(string) => string,
+        }
+    }
+}
+
+impl<const N: usize> Default for SmallString<N> {
+    fn default() -> Self {
+        SmallString::new()
+    }
+}
+
+impl<const N: usize> From<&str> for SmallString<N> {
+    fn from(text: &str) -> Self {
+        let mut small = SmallString::new();
+        small.push_str(text);
+        small
+    }
+}
+
+// Keeps the allocation when it doesn't fit inline
+impl<const N: usize> From<String> for SmallString<N> {
+    fn from(string: String) -> Self {
+        if string.len() <= N {
+            SmallString::from(string.as_str())
+        } else {
+            SmallString { repr: Repr::Heap(string) }
+        }
+    }
+}
+
+impl<const N: usize> From<SmallString<N>> for String {
+    fn from(small: SmallString<N>) -> String {
+        small.into_string()
+    }
+}
+
+impl<const N: usize> Deref for SmallString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> AsRef<str> for SmallString<N> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// So a HashMap<SmallString<N>, V> can be looked up with a &str
+impl<const N: usize> Borrow<str> for SmallString<N> {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> fmt::Write for SmallString<N> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.push_str(text);
+        Ok(())
+    }
+}
+
+impl<const N: usize> fmt::Display for SmallString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> fmt::Debug for SmallString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> PartialEq for SmallString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> Eq for SmallString<N> {}
+
+impl<const N: usize> PartialEq<str> for SmallString<N> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<const N: usize> PartialEq<&str> for SmallString<N> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl<const N: usize> PartialOrd for SmallString<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for SmallString<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+// The same hash as the str, which Borrow<str> needs
+impl<const N: usize> Hash for SmallString<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, fmt::Write};
+
+    #[test]
+    fn stays_inline_until_it_spills() {
+        // Ü and ï are two bytes each
+        let mut name: SmallString<10> = SmallString::from("Host");
+        assert!(name.is_inline());
+        name.push('-');
+        name.push_str("Ünï");
+        assert_eq!(("Host-Ünï", true), (name.as_str(), name.is_inline()));
+        name.push_str("code");
+        assert_eq!(("Host-Ünïcode", true), (name.as_str(), name.spilled()));
+
+        // Spilled and inline with the same text are the same
+        let mut counts: HashMap<SmallString<8>, u32> = HashMap::new();
+        counts.insert(SmallString::from(String::from("content-type, spilled")), 1);
+        counts.insert(SmallString::from("accept"), 2);
+        assert_eq!((Some(&1), Some(&2)), (counts.get("content-type, spilled"), counts.get("accept")));
+        assert_eq!(SmallString::<2>::from("abc"), SmallString::<2>::from(String::from("abc")));
+
+        let mut written: SmallString<16> = SmallString::new();
+        write!(written, "{}/OK", 200).unwrap();
+        assert_eq!(("200/OK", String::from("200/OK")), (written.as_str(), written.clone().into_string()));
+        assert!(written.eq_ignore_ascii_case("200/ok"));
+        written.clear();
+        assert!(written.is_empty() && written.is_inline());
+    }
+}
@@ -0,0 +1,268 @@
+// Trie (Prefix Tree) for Autocomplete
+
+// A trie stores words by sharing their common prefixes: "car", "cart" and "care" all go through the same c -> a -> r path.
+// Looking up every word with a given prefix is then just walking down to the prefix node and collecting everything below it.
+
+// Remember from the strings section that a "letter" is really a grapheme cluster, not a char or a byte.
+// The edges of this trie are grapheme clusters (using the unicode-segmentation crate), so a prefix never ends halfway through a letter like "स्".
+
+// Every node also counts how many times its word was inserted, which is the "frequency" used to rank suggestions.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap},
+};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Default)]
+struct Node {
+    // A BTreeMap keeps the children sorted, so iterating the trie yields the words in lexicographic order
+    children: BTreeMap<String, Node>,
+    // How many times this exact word was inserted, 0 means the node is only part of a longer word
+    count: u64,
+}
+
+#[derive(Default)]
+pub struct Trie {
+    root: Node,
+    len: usize,
+}
+
+impl Trie {
+    pub fn new() -> Trie {
+        Trie::default()
+    }
+
+    // Number of distinct words
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, word: &str) {
+        let mut node = &mut self.root;
+        for g in word.graphemes(true) {
+            node = node.children.entry(g.to_string()).or_default();
+        }
+        if node.count == 0 {
+            self.len += 1;
+        }
+        node.count += 1;
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.frequency(word) > 0
+    }
+
+    pub fn frequency(&self, word: &str) -> u64 {
+        self.find(word).map_or(0, |node| node.count)
+    }
+
+    // Removes the word completely (whatever its frequency) and returns whether it was there.
+    pub fn remove(&mut self, word: &str) -> bool {
+        let graphemes: Vec<&str> = word.graphemes(true).collect();
+        let removed = Trie::remove_from(&mut self.root, &graphemes);
+        if removed {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    // Recursion makes the cleanup easy: after removing the word below a child, the parent can check whether that child is now empty and drop it,
+    // so removed words don't leave dead branches behind.
+    fn remove_from(node: &mut Node, graphemes: &[&str]) -> bool {
+        match graphemes.split_first() {
+            None => {
+                let was_word = node.count > 0;
+                node.count = 0;
+                was_word
+            }
+            Some((first, rest)) => {
+                let child = match node.children.get_mut(*first) {
+                    Some(child) => child,
+                    None => return false,
+                };
+                let removed = Trie::remove_from(child, rest);
+                if removed && child.count == 0 && child.children.is_empty() {
+                    node.children.remove(*first);
+                }
+                removed
+            }
+        }
+    }
+
+    fn find(&self, prefix: &str) -> Option<&Node> {
+        let mut node = &self.root;
+        for g in prefix.graphemes(true) {
+            node = node.children.get(g)?;
+        }
+        Some(node)
+    }
+
+    // All the words starting with prefix, in lexicographic order, along with their frequencies
+    pub fn iter_prefix(&self, prefix: &str) -> PrefixIter<'_> {
+        let stack = match self.find(prefix) {
+            Some(node) => vec![(prefix.to_string(), node)],
+            None => Vec::new(),
+        };
+        PrefixIter { stack }
+    }
+
+    pub fn iter(&self) -> PrefixIter<'_> {
+        self.iter_prefix("")
+    }
+
+    // Top-k Suggestions
+
+    // Instead of sorting every completion, we keep a min-heap of the best k seen so far: when it grows past k, the worst one is popped.
+    // That's O(n log k) instead of O(n log n), which matters when a short prefix matches most of the dictionary.
+    // Ties in frequency are broken alphabetically so the result is deterministic.
+    pub fn suggest(&self, prefix: &str, k: usize) -> Vec<(String, u64)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for (word, count) in self.iter_prefix(prefix) {
+            heap.push(Reverse(Ranked { count, word }));
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+
+        let mut best: Vec<(String, u64)> = heap
+            .into_iter()
+            .map(|Reverse(r)| (r.word, r.count))
+            .collect();
+        best.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        best
+    }
+}
+
+// Orders suggestions by frequency, and for equal frequencies the alphabetically earlier word ranks higher
+#[derive(PartialEq, Eq)]
+struct Ranked {
+    count: u64,
+    word: String,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.count
+            .cmp(&other.count)
+            .then_with(|| other.word.cmp(&self.word))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// A depth first walk with an explicit stack. Each entry is the word spelled so far and the node it leads to.
+// The iterator holds &'a Node references into the trie, so the trie can't be changed while we iterate.
+pub struct PrefixIter<'a> {
+    stack: Vec<(String, &'a Node)>,
+}
+
+impl Iterator for PrefixIter<'_> {
+    type Item = (String, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((word, node)) = self.stack.pop() {
+            // Children are pushed in reverse so that the smallest one is popped first
+            for (g, child) in node.children.iter().rev() {
+                self.stack.push((format!("{word}{g}"), child));
+            }
+            if node.count > 0 {
+                return Some((word, node.count));
+            }
+        }
+        None
+    }
+}
+
+impl<'a> FromIterator<&'a str> for Trie {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        let mut trie = Trie::new();
+        for word in iter {
+            trie.insert(word);
+        }
+        trie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_contains_remove() {
+        let mut trie: Trie = ["car", "cart", "care", "dog"].into_iter().collect();
+        assert_eq!(4, trie.len());
+        assert!(trie.contains("car"));
+        assert!(!trie.contains("ca"));
+        assert!(!trie.contains("cars"));
+
+        assert!(trie.remove("car"));
+        assert!(!trie.remove("car"));
+        assert!(!trie.contains("car"));
+        assert!(trie.contains("cart"));
+        assert_eq!(3, trie.len());
+
+        // Removing the last word under a branch prunes it
+        assert!(trie.remove("dog"));
+        assert!(!trie.root.children.contains_key("d"));
+    }
+
+    #[test]
+    fn prefix_iteration_is_sorted() {
+        let trie: Trie = ["tea", "ten", "to", "ted", "inn"].into_iter().collect();
+        let words: Vec<String> = trie.iter_prefix("te").map(|(w, _)| w).collect();
+        assert_eq!(vec!["tea", "ted", "ten"], words);
+        assert_eq!(5, trie.iter().count());
+        assert_eq!(0, trie.iter_prefix("x").count());
+    }
+
+    #[test]
+    fn suggestions_rank_by_frequency() {
+        let mut trie = Trie::new();
+        for word in ["hello", "help", "help", "helm", "help", "hello", "hero"] {
+            trie.insert(word);
+        }
+
+        assert_eq!(3, trie.frequency("help"));
+        assert_eq!(
+            vec![(String::from("help"), 3), (String::from("hello"), 2)],
+            trie.suggest("hel", 2)
+        );
+        // helm and hero both have frequency 1, the alphabetical one wins the last slot
+        assert_eq!(
+            vec![
+                (String::from("help"), 3),
+                (String::from("hello"), 2),
+                (String::from("helm"), 1)
+            ],
+            trie.suggest("he", 3)
+        );
+        assert!(trie.suggest("he", 0).is_empty());
+    }
+
+    #[test]
+    fn prefixes_are_grapheme_aware() {
+        let trie: Trie = ["नमस्ते", "नमक"].into_iter().collect();
+        assert_eq!(2, trie.iter_prefix("नम").count());
+
+        // "cafe\u{301}" is "café" written with a combining accent: the last letter is the two chars e + \u{301}.
+        // In chars "cafe" is a prefix of it, but in graphemes it is not, the letter "e" is different from the letter "é".
+        let trie: Trie = ["cafe\u{301}", "cafeteria"].into_iter().collect();
+        let words: Vec<String> = trie.iter_prefix("cafe").map(|(w, _)| w).collect();
+        assert_eq!(vec!["cafeteria"], words);
+        assert_eq!(1, trie.iter_prefix("cafe\u{301}").count());
+    }
+}
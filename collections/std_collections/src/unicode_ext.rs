@@ -0,0 +1,259 @@
+// Unicode-Aware Strings
+
+// The strings section of main.rs shows the pitfalls: a String is bytes, &s[0..1] of "Здравствуйте" panics because it cuts a
+// char in half, and even chars aren't letters, since "नमस्ते" is six chars but three or four grapheme clusters, depending on
+// the Unicode version. The std methods that look safe aren't quite either: s.chars().rev() puts a combining accent on the
+// wrong letter, and s.len() counts bytes where a terminal counts columns. These are the safe versions, on grapheme clusters
+// from the unicode-segmentation crate:
+    // 1. truncate, reverse and slice count graphemes, so nothing is ever cut in half, a char or an "e" and its accent.
+    // 2. display_width counts the columns a terminal draws: wide CJK and emoji take two, combining marks and zero-width
+    //    characters none. common's table.rs lines up its columns with it, and fit_width cuts a cell down to its column.
+    // 3. fold_case is case folding, lowercasing for comparing: "STRASSE" and "straße" fold to the same string, which
+    //    to_lowercase doesn't do.
+    // 4. skeleton maps characters that look alike to one of them, so "pаypal" with a Cyrillic а is confusable with "paypal".
+    //    is_mixed_script finds the usual way to hide that, Latin and Cyrillic or Greek letters in one word.
+// The tables here are the parts of the Unicode data that show up in practice, not all of it: the East Asian Width table, the
+// CaseFolding.txt and the confusables.txt of UTS #39 are thousands of lines each.
+
+use std::ops::{Bound, RangeBounds};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+// Graphemes
+
+pub fn grapheme_count(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+// The first n graphemes, a slice of the text
+pub fn truncate(text: &str, n: usize) -> &str {
+    match text.grapheme_indices(true).nth(n) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+// The graphemes in reverse, each of them with its chars in the order they were
+pub fn reverse(text: &str) -> String {
+    text.graphemes(true).rev().collect()
+}
+
+// The graphemes in the range, like &text[range] for bytes. None when the range goes past the end, like str::get. A bound of
+// usize::MAX that would need one more is past the end of any text, and checked_add() makes it None instead of an overflow
+pub fn slice(text: &str, range: impl RangeBounds<usize>) -> Option<&str> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => Some(end.checked_add(1)?),
+        Bound::Excluded(&end) => Some(end),
+        Bound::Unbounded => None,
+    };
+    // The byte offset of every grapheme, and of the end of the text
+    let offsets: Vec<usize> = text.grapheme_indices(true).map(|(i, _)| i).chain([text.len()]).collect();
+    let end = end.unwrap_or(offsets.len() - 1);
+    if start > end || end >= offsets.len() {
+        return None;
+    }
+    Some(&text[offsets[start]..offsets[end]])
+}
+
+// Widths
+
+// The ranges where the wide characters are, the blocks of the East Asian Width table that actually show up in text
+const WIDE: &[(char, char)] = &[
+    ('\u{1100}', '\u{115F}'),   // Hangul Jamo
+    ('\u{2E80}', '\u{303E}'),   // CJK radicals and punctuation
+    ('\u{3041}', '\u{33FF}'),   // Hiragana, Katakana, ...
+    ('\u{3400}', '\u{4DBF}'),   // CJK Extension A
+    ('\u{4E00}', '\u{9FFF}'),   // CJK Unified Ideographs
+    ('\u{A000}', '\u{A4CF}'),   // Yi
+    ('\u{AC00}', '\u{D7A3}'),   // Hangul syllables
+    ('\u{F900}', '\u{FAFF}'),   // CJK compatibility ideographs
+    ('\u{FE30}', '\u{FE4F}'),   // CJK compatibility forms
+    ('\u{FF00}', '\u{FF60}'),   // Fullwidth forms
+    ('\u{FFE0}', '\u{FFE6}'),
+    ('\u{1F300}', '\u{1F64F}'), // Emoji: symbols and pictographs, emoticons
+    ('\u{1F680}', '\u{1F6FF}'), // Transport and map symbols
+    ('\u{1F900}', '\u{1F9FF}'), // Supplemental symbols and pictographs
+    ('\u{1FA70}', '\u{1FAFF}'), // Symbols and pictographs extended-A
+    ('\u{20000}', '\u{3FFFD}'), // CJK Extensions B and later
+];
+
+// Drawn as nothing: zero width spaces and joiners, the direction marks, and the byte order mark
+const ZERO_WIDTH: &[(char, char)] = &[('\u{200B}', '\u{200F}'), ('\u{2060}', '\u{2064}'), ('\u{FEFF}', '\u{FEFF}')];
+
+// The emoji variation selector asks for the emoji picture of a char that's text otherwise, like ❤ in ❤️
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+
+fn in_ranges(ranges: &[(char, char)], c: char) -> bool {
+    ranges.iter().any(|&(low, high)| (low..=high).contains(&c))
+}
+
+// A grapheme is as wide as its first char, the combining marks after it go on top of it
+pub fn grapheme_width(grapheme: &str) -> usize {
+    let first = grapheme.chars().next().unwrap_or('\0');
+    if first.is_control() || in_ranges(ZERO_WIDTH, first) {
+        0
+    } else if in_ranges(WIDE, first) || grapheme.contains(EMOJI_PRESENTATION) {
+        2
+    } else {
+        1
+    }
+}
+
+// The number of terminal columns the text takes up
+pub fn display_width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+// The longest start of the text that fits in width columns. Whole graphemes only, so it may be a column short of width
+// when the next one is wide
+pub fn fit_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, grapheme) in text.grapheme_indices(true) {
+        used += grapheme_width(grapheme);
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+// Case Folding
+
+// The chars whose folding isn't their lowercase: ß is already lowercase but folds to ss, the final sigma is the same letter
+// as σ, and the long s and the ligatures are ways of writing letters that are there already
+fn fold_special(c: char) -> Option<&'static str> {
+    match c {
+        'ß' | 'ẞ' => Some("ss"),
+        'ς' => Some("σ"),
+        'ſ' => Some("s"),
+        'ﬀ' => Some("ff"),
+        'ﬁ' => Some("fi"),
+        'ﬂ' => Some("fl"),
+        'ﬃ' => Some("ffi"),
+        'ﬄ' => Some("ffl"),
+        'ﬅ' | 'ﬆ' => Some("st"),
+        _ => None,
+    }
+}
+
+pub fn fold_case(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match fold_special(c) {
+            Some(special) => folded.push_str(special),
+            None => folded.extend(c.to_lowercase()),
+        }
+    }
+    folded
+}
+
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    fold_case(a) == fold_case(b)
+}
+
+// Confusables
+
+// Letters of other scripts that look like a Latin one, and the ASCII look-alikes of UTS #39: 1, I and | are all l
+const CONFUSABLES: &[(char, char)] = &[
+    // Cyrillic
+    ('а', 'a'), ('в', 'B'), ('е', 'e'), ('о', 'o'), ('р', 'p'), ('с', 'c'), ('у', 'y'), ('х', 'x'), ('і', 'i'), ('ј', 'j'),
+    ('ѕ', 's'), ('ԁ', 'd'), ('һ', 'h'), ('ԛ', 'q'), ('ԝ', 'w'), ('А', 'A'), ('В', 'B'), ('Е', 'E'), ('К', 'K'), ('М', 'M'),
+    ('Н', 'H'), ('О', 'O'), ('Р', 'P'), ('С', 'C'), ('Т', 'T'), ('Х', 'X'), ('І', 'l'), ('Ј', 'J'), ('Ѕ', 'S'),
+    // Greek
+    ('ο', 'o'), ('ν', 'v'), ('α', 'a'), ('ι', 'i'), ('Α', 'A'), ('Β', 'B'), ('Ε', 'E'), ('Ζ', 'Z'), ('Η', 'H'), ('Ι', 'l'),
+    ('Κ', 'K'), ('Μ', 'M'), ('Ν', 'N'), ('Ο', 'O'), ('Ρ', 'P'), ('Τ', 'T'), ('Υ', 'Y'), ('Χ', 'X'),
+    // Latin and ASCII
+    ('ı', 'i'), ('ɡ', 'g'), ('1', 'l'), ('I', 'l'), ('|', 'l'), ('0', 'O'),
+];
+
+// Every char replaced by the one it looks like, two strings that look the same have the same skeleton
+pub fn skeleton(text: &str) -> String {
+    text.chars()
+        .map(|c| CONFUSABLES.iter().find(|&&(from, _)| from == c).map_or(c, |&(_, to)| to))
+        .collect()
+}
+
+// Different strings that a reader can't tell apart
+pub fn is_confusable(a: &str, b: &str) -> bool {
+    a != b && skeleton(a) == skeleton(b)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Some(Script::Latin),
+        '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+        '\u{0400}'..='\u{052F}' => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+// Letters of more than one of Latin, Greek and Cyrillic. Other scripts, digits and punctuation don't count
+pub fn is_mixed_script(text: &str) -> bool {
+    let mut scripts = text.chars().filter_map(script);
+    match scripts.next() {
+        Some(first) => scripts.any(|script| script != first),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graphemes_are_never_cut() {
+        // An e with a combining accent, and a family: a man, a woman and a girl joined by zero width joiners
+        let accent = "cafe\u{0301}!";
+        let family = "a 👨\u{200D}👩\u{200D}👧";
+        assert_eq!((6, 5), (accent.chars().count(), grapheme_count(accent)));
+        assert_eq!((7, 3), (family.chars().count(), grapheme_count(family)));
+        assert_eq!("cafe\u{0301}", truncate(accent, 4));
+        assert_eq!("!e\u{0301}fac", reverse(accent));
+        assert_eq!("👨\u{200D}👩\u{200D}👧 a", reverse(family));
+
+        assert_eq!(Some("fe\u{0301}!"), slice(accent, 2..));
+        assert_eq!(Some("afe\u{0301}"), slice(accent, 1..=3));
+        assert_eq!(Some(""), slice(accent, 5..));
+        assert_eq!(None, slice(accent, 3..6));
+        assert_eq!(None, slice(accent, 6..));
+        assert_eq!(None, slice(accent, ..=usize::MAX));
+        assert_eq!(None, slice(accent, (Bound::Excluded(usize::MAX), Bound::Unbounded)));
+    }
+
+    #[test]
+    fn widths_count_columns() {
+        assert_eq!(5, display_width("nai\u{0308}ve"));
+        assert_eq!(4, display_width("東京"));
+        assert_eq!(2, display_width("🦀"));
+        assert_eq!(2, display_width("❤\u{FE0F}"));
+        assert_eq!(2, display_width("a\u{200B}b"));
+        assert_eq!("東", fit_width("東京", 3));
+        assert_eq!("ab", fit_width("ab", 5));
+        assert_eq!("", fit_width("東京", 1));
+    }
+
+    #[test]
+    fn case_folding_and_confusables() {
+        assert!(eq_ignore_case("STRASSE", "straße"));
+        assert!(eq_ignore_case("ΟΔΥΣΣΕΥΣ", "Οδυσσευς"));
+        assert_eq!("office", fold_case("Oﬃce"));
+
+        // The first а is Cyrillic
+        assert!(is_confusable("pаypal", "paypal") && is_mixed_script("pаypal"));
+        assert!(is_confusable("I0", "l0") && !is_confusable("paypal", "paypal"));
+        assert!(!is_mixed_script("paypal") && !is_mixed_script("Здравствуйте, 2024!"));
+        assert!(!is_confusable("rust", "ruby"));
+    }
+}
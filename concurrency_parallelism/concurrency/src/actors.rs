@@ -0,0 +1,268 @@
+// Actors
+
+// "Do not communicate by sharing memory; instead, share memory by communicating." Channels already give us that,
+// and an actor is the pattern built around them: some state that is owned by exactly one thread, and that the rest of the program
+// can only change by sending it messages. There is no Mutex around the state, because nothing else can reach it.
+    // 1. An Actor says what kind of message it accepts (type Msg) and how it handles one.
+    // 2. spawn() moves the actor to its own thread and returns an Addr, the only way to talk to it. Addr is cheap to clone.
+    // 3. send() is fire and forget. ask() also wants an answer: the message carries a Reply, the sending half of a one-off channel,
+    //    and ask() waits on the receiving half.
+
+// Supervision: if handle() panics, the actor's state may be half updated, so instead of carrying on with it the actor is thrown away
+// and a fresh one is built by the factory closure given to spawn. The message that caused the panic is lost, the ones after it are not.
+// After too many panics in a row the actor gives up for good.
+
+// Shutdown: Addr::stop() queues a stop request behind the messages already sent, so they are all handled first.
+// An actor also stops when every Addr to it is dropped, since no message can arrive any more.
+
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+pub trait Actor: Send + 'static {
+    type Msg: Send + 'static;
+
+    fn handle(&mut self, msg: Self::Msg, ctx: &mut Context);
+
+    // Called on a new actor before its first message, which also happens after every restart
+    fn started(&mut self) {}
+
+    // Called when the actor stops normally, not when it panicked
+    fn stopped(&mut self) {}
+}
+
+// What an actor can do to its own life while handling a message
+pub struct Context {
+    stopping: bool,
+}
+
+impl Context {
+    // Stops after the current message, without handling the ones still waiting
+    pub fn stop(&mut self) {
+        self.stopping = true;
+    }
+}
+
+enum Envelope<M> {
+    Msg(M),
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActorError {
+    // The actor isn't running any more
+    Stopped,
+    // The actor dropped the Reply without answering, usually because it panicked on the message
+    NoReply,
+}
+
+impl fmt::Display for ActorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActorError::Stopped => write!(f, "the actor has stopped"),
+            ActorError::NoReply => write!(f, "the actor did not reply"),
+        }
+    }
+}
+
+impl std::error::Error for ActorError {}
+
+// How an actor's thread ended, returned by joining its JoinHandle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Stopped,
+    AddressesDropped,
+    TooManyPanics,
+}
+
+pub struct Addr<A: Actor> {
+    sender: Sender<Envelope<A::Msg>>,
+}
+
+// Deriving Clone would require A: Clone, but only the Sender is cloned
+impl<A: Actor> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Addr { sender: self.sender.clone() }
+    }
+}
+
+impl<A: Actor> Addr<A> {
+    pub fn send(&self, msg: A::Msg) -> Result<(), ActorError> {
+        self.sender.send(Envelope::Msg(msg)).map_err(|_| ActorError::Stopped)
+    }
+
+    // make_msg builds the message around the Reply, like addr.ask(|reply| CounterMsg::Get(reply))
+    pub fn ask<R>(&self, make_msg: impl FnOnce(Reply<R>) -> A::Msg) -> Result<R, ActorError> {
+        let (sender, receiver) = mpsc::channel();
+        self.send(make_msg(Reply { sender }))?;
+        receiver.recv().map_err(|_| ActorError::NoReply)
+    }
+
+    pub fn stop(&self) -> Result<(), ActorError> {
+        self.sender.send(Envelope::Stop).map_err(|_| ActorError::Stopped)
+    }
+}
+
+pub struct Reply<R> {
+    sender: Sender<R>,
+}
+
+impl<R> Reply<R> {
+    // The asker may have given up waiting, which isn't the actor's problem, so a failed send is ignored
+    pub fn send(self, value: R) {
+        let _ = self.sender.send(value);
+    }
+}
+
+pub const DEFAULT_MAX_RESTARTS: u32 = 3;
+
+pub fn spawn<A: Actor>(factory: impl FnMut() -> A + Send + 'static) -> (Addr<A>, JoinHandle<Exit>) {
+    spawn_supervised(factory, DEFAULT_MAX_RESTARTS)
+}
+
+// max_restarts counts panics in a row, a message handled without panicking resets the count
+pub fn spawn_supervised<A: Actor>(
+    mut factory: impl FnMut() -> A + Send + 'static,
+    max_restarts: u32,
+) -> (Addr<A>, JoinHandle<Exit>) {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || run(&mut factory, &receiver, max_restarts));
+    (Addr { sender }, handle)
+}
+
+fn run<A: Actor>(factory: &mut impl FnMut() -> A, receiver: &Receiver<Envelope<A::Msg>>, max_restarts: u32) -> Exit {
+    let mut actor = factory();
+    actor.started();
+    let mut panics = 0;
+
+    loop {
+        let msg = match receiver.recv() {
+            Ok(Envelope::Msg(msg)) => msg,
+            Ok(Envelope::Stop) => {
+                actor.stopped();
+                return Exit::Stopped;
+            }
+            Err(_) => {
+                actor.stopped();
+                return Exit::AddressesDropped;
+            }
+        };
+
+        let mut ctx = Context { stopping: false };
+        // AssertUnwindSafe: we promise not to look at the actor again if this panics, it's replaced by a new one
+        match panic::catch_unwind(AssertUnwindSafe(|| actor.handle(msg, &mut ctx))) {
+            Ok(()) => panics = 0,
+            Err(_) => {
+                panics += 1;
+                if panics > max_restarts {
+                    return Exit::TooManyPanics;
+                }
+                actor = factory();
+                actor.started();
+                continue;
+            }
+        }
+
+        if ctx.stopping {
+            actor.stopped();
+            return Exit::Stopped;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter {
+        count: i64,
+    }
+
+    enum CounterMsg {
+        Add(i64),
+        Get(Reply<i64>),
+        Crash,
+        CrashBeforeReplying(Reply<i64>),
+        Quit,
+    }
+
+    impl Actor for Counter {
+        type Msg = CounterMsg;
+
+        fn handle(&mut self, msg: CounterMsg, ctx: &mut Context) {
+            match msg {
+                CounterMsg::Add(n) => self.count += n,
+                CounterMsg::Get(reply) => reply.send(self.count),
+                CounterMsg::Crash => panic!("counter crashed on purpose"),
+                CounterMsg::CrashBeforeReplying(_reply) => panic!("counter crashed before replying"),
+                CounterMsg::Quit => ctx.stop(),
+            }
+        }
+    }
+
+    fn counter() -> Counter {
+        Counter { count: 0 }
+    }
+
+    #[test]
+    fn messages_are_handled_in_order() {
+        let (addr, handle) = spawn(counter);
+        for n in 1..=10 {
+            addr.send(CounterMsg::Add(n)).unwrap();
+        }
+        assert_eq!(Ok(55), addr.ask(CounterMsg::Get));
+
+        // Many senders on many threads, one owner of the count
+        let senders: Vec<_> = (0..4)
+            .map(|_| {
+                let addr = addr.clone();
+                thread::spawn(move || (0..100).for_each(|_| addr.send(CounterMsg::Add(1)).unwrap()))
+            })
+            .collect();
+        senders.into_iter().for_each(|s| s.join().unwrap());
+        assert_eq!(Ok(455), addr.ask(CounterMsg::Get));
+
+        drop(addr);
+        assert_eq!(Exit::AddressesDropped, handle.join().unwrap());
+    }
+
+    #[test]
+    fn panics_restart_the_actor_with_fresh_state() {
+        let (addr, handle) = spawn(counter);
+        addr.send(CounterMsg::Add(5)).unwrap();
+        addr.send(CounterMsg::Crash).unwrap();
+        assert_eq!(Ok(0), addr.ask(CounterMsg::Get));
+
+        // The Reply is dropped when the panic unwinds
+        assert_eq!(Err(ActorError::NoReply), addr.ask(CounterMsg::CrashBeforeReplying));
+        addr.stop().unwrap();
+        assert_eq!(Exit::Stopped, handle.join().unwrap());
+    }
+
+    #[test]
+    fn gives_up_after_too_many_panics() {
+        let (addr, handle) = spawn_supervised(counter, 2);
+        for _ in 0..3 {
+            addr.send(CounterMsg::Crash).unwrap();
+        }
+        assert_eq!(Exit::TooManyPanics, handle.join().unwrap());
+        assert_eq!(Err(ActorError::Stopped), addr.send(CounterMsg::Add(1)));
+    }
+
+    #[test]
+    fn stop_handles_earlier_messages_first() {
+        let (addr, handle) = spawn(counter);
+        let (tx, rx) = mpsc::channel();
+        addr.send(CounterMsg::Add(1)).unwrap();
+        addr.send(CounterMsg::Get(Reply { sender: tx })).unwrap();
+        addr.send(CounterMsg::Quit).unwrap();
+        // Never handled, the actor stopped on Quit
+        addr.send(CounterMsg::Add(1)).ok();
+
+        assert_eq!(Exit::Stopped, handle.join().unwrap());
+        assert_eq!(Ok(1), rx.recv());
+    }
+}
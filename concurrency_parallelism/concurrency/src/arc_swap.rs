@@ -0,0 +1,260 @@
+// ArcSwap: An Arc That Can Be Replaced While It's Read
+
+// Configuration that can change while the program runs is usually kept as an Arc<Config> that gets replaced: a reader clones
+// the Arc and keeps a snapshot that never changes under it, a writer builds a new Config and puts it in the Arc's place.
+// An RwLock<Arc<Config>> does that, but every reader takes the lock, and all of them write to the same lock word to do it.
+// They wait for a writer too, and a writer waits for all of them. ArcSwap<T> gets by without a lock on the read side.
+
+// The current value is an AtomicPtr, the pointer of an Arc (Arc::into_raw). Loading it is a load of the pointer and one more
+// strong count (Arc::increment_strong_count). The hard part is the moment in between: a writer could swap the pointer and
+// drop the old Arc right after a reader loaded the pointer, and the reader would count a reference to freed memory.
+// So a writer doesn't drop (or hand back) the old value until no reader can be in that moment any more:
+    // 1. A reader says it's there by adding 1 to one of two counters, the one the generation is at, before it loads the
+    //    pointer. It subtracts the 1 again once its own strong count is in.
+    // 2. A writer swaps the pointer first. Every reader that comes after that loads the new one.
+    // 3. Then it moves the generation on and waits until the counter of the old one is 0, and does it once more for the other.
+    //    The new readers count on the other counter, so the writer only waits for the few that came before the swap.
+// That's a very small version of RCU (read-copy-update), the way the Linux kernel reads its routing tables. The arc-swap crate
+// is the real thing, it keeps the readers' counts in per-thread slots.
+
+// Readers never block and never wait for each other. Writers take a Mutex among themselves, and wait for the readers that
+// were in the middle of a load, which is a few instructions long. It's made for data that's read all the time and written
+// rarely: on every request, and whenever somebody edits a file.
+
+use std::{
+    fmt,
+    hint,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicPtr, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub struct ArcSwap<T> {
+    // From Arc::into_raw, the ArcSwap owns one strong count of it
+    ptr: AtomicPtr<T>,
+    generation: AtomicUsize,
+    readers: [AtomicUsize; 2],
+    writer: Mutex<()>,
+    // Send and Sync when Arc<T> is, AtomicPtr<T> would be both for any T
+    _owns: PhantomData<Arc<T>>,
+}
+
+// Every atomic operation here is SeqCst. The argument above needs the swap of the pointer and the readers' counts in one
+// order that every thread agrees on, and SeqCst is the ordering that guarantees one.
+const ORDER: Ordering = Ordering::SeqCst;
+
+impl<T> ArcSwap<T> {
+    pub fn new(value: Arc<T>) -> ArcSwap<T> {
+        ArcSwap {
+            ptr: AtomicPtr::new(Arc::into_raw(value).cast_mut()),
+            generation: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: Mutex::new(()),
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn from_pointee(value: T) -> ArcSwap<T> {
+        ArcSwap::new(Arc::new(value))
+    }
+
+    // The current value. It stays what it is for as long as the Arc is kept, whatever is stored after
+    pub fn load(&self) -> Arc<T> {
+        let readers = &self.readers[self.generation.load(ORDER) % 2];
+        readers.fetch_add(1, ORDER);
+        let ptr = self.ptr.load(ORDER);
+        // SAFETY: the pointer came from Arc::into_raw, and the Arc it belongs to isn't dropped while this reader is counted
+        unsafe { Arc::increment_strong_count(ptr) };
+        readers.fetch_sub(1, ORDER);
+        // SAFETY: the strong count taken above is the one this Arc owns
+        unsafe { Arc::from_raw(ptr) }
+    }
+
+    pub fn store(&self, value: Arc<T>) {
+        drop(self.swap(value));
+    }
+
+    // Returns the one it replaced
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let _writer = self.writer.lock().unwrap();
+        self.replace(value)
+    }
+
+    // Stores new only when current is what's stored now, the same Arc and not just an equal value. Ok with the one it
+    // replaced, or Err with new when it was something else
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let _writer = self.writer.lock().unwrap();
+        if self.ptr.load(ORDER).cast_const() != Arc::as_ptr(current) {
+            return Err(new);
+        }
+        Ok(self.replace(new))
+    }
+
+    // Copy, update: a new value made from the current one by f, stored unless another writer got there first, in which
+    // case f runs again on theirs. f can run more than once, so it shouldn't do anything else. Returns the one it replaced
+    pub fn rcu(&self, mut f: impl FnMut(&T) -> T) -> Arc<T> {
+        let mut current = self.load();
+        loop {
+            match self.compare_and_swap(&current, Arc::new(f(&current))) {
+                Ok(replaced) => return replaced,
+                Err(_) => current = self.load(),
+            }
+        }
+    }
+
+    // Only with the writer lock held
+    fn replace(&self, value: Arc<T>) -> Arc<T> {
+        let old = self.ptr.swap(Arc::into_raw(value).cast_mut(), ORDER);
+        for _ in 0..2 {
+            let readers = &self.readers[self.generation.fetch_add(1, ORDER) % 2];
+            while readers.load(ORDER) != 0 {
+                hint::spin_loop();
+            }
+        }
+        // SAFETY: the strong count the ArcSwap owned, and no reader is still about to count one of its own
+        unsafe { Arc::from_raw(old) }
+    }
+}
+
+impl<T> Drop for ArcSwap<T> {
+    fn drop(&mut self) {
+        // SAFETY: &mut self, so nobody is loading, and the ArcSwap's own strong count is given back
+        drop(unsafe { Arc::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+impl<T> From<Arc<T>> for ArcSwap<T> {
+    fn from(value: Arc<T>) -> Self {
+        ArcSwap::new(value)
+    }
+}
+
+impl<T: Default> Default for ArcSwap<T> {
+    fn default() -> Self {
+        ArcSwap::from_pointee(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ArcSwap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ArcSwap").field(&self.load()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::atomic::AtomicBool, thread};
+
+    // Counts its drops, to check that every value is dropped once, and only once nobody has it
+    #[derive(Debug)]
+    struct Counted<'a> {
+        version: usize,
+        // Always version * 2, a reader that sees anything else is looking at freed memory
+        check: usize,
+        drops: &'a AtomicUsize,
+    }
+
+    impl<'a> Counted<'a> {
+        fn new(version: usize, drops: &'a AtomicUsize) -> Arc<Counted<'a>> {
+            Arc::new(Counted { version, check: version * 2, drops })
+        }
+    }
+
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            assert_eq!(self.version * 2, self.check, "dropped twice");
+            self.check = usize::MAX;
+            self.drops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn loads_swaps_and_compares() {
+        let drops = AtomicUsize::new(0);
+        let cell = ArcSwap::new(Counted::new(1, &drops));
+        let first = cell.load();
+        assert_eq!(1, first.version);
+
+        // A snapshot stays what it was
+        cell.store(Counted::new(2, &drops));
+        assert_eq!((1, 2), (first.version, cell.load().version));
+        assert_eq!(0, drops.load(Ordering::SeqCst));
+        drop(first);
+        assert_eq!(1, drops.load(Ordering::SeqCst));
+
+        let stale = Counted::new(2, &drops);
+        let Err(refused) = cell.compare_and_swap(&stale, Counted::new(3, &drops)) else { panic!("swapped with an equal value") };
+        assert_eq!(3, refused.version);
+        let current = cell.load();
+        assert_eq!(2, cell.compare_and_swap(&current, refused).unwrap().version);
+        assert_eq!(3, cell.rcu(|old| Counted { version: old.version + 1, check: (old.version + 1) * 2, drops: &drops }).version);
+
+        drop((stale, current, cell));
+        assert_eq!(5, drops.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn readers_and_writers_at_once() {
+        const READERS: usize = 6;
+        const VERSIONS: usize = 1000;
+
+        let drops = AtomicUsize::new(0);
+        let cell = ArcSwap::new(Counted::new(0, &drops));
+        let done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            for _ in 0..READERS {
+                s.spawn(|| {
+                    let mut last = 0;
+                    let mut loads = 0;
+                    while !done.load(Ordering::SeqCst) || loads == 0 {
+                        let value = cell.load();
+                        assert_eq!(value.version * 2, value.check);
+                        // One writer, counting up: nobody sees an older version after a newer one
+                        assert!(value.version >= last, "{} after {last}", value.version);
+                        last = value.version;
+                        loads += 1;
+                    }
+                });
+            }
+            s.spawn(|| {
+                for version in 1..=VERSIONS {
+                    cell.store(Counted::new(version, &drops));
+                }
+                done.store(true, Ordering::SeqCst);
+            });
+        });
+        assert_eq!(VERSIONS, cell.load().version);
+        assert_eq!(VERSIONS, drops.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn concurrent_updates_are_never_lost() {
+        const WRITERS: usize = 4;
+        const UPDATES: usize = 500;
+
+        let cell = ArcSwap::from_pointee(0);
+        thread::scope(|s| {
+            for _ in 0..WRITERS {
+                s.spawn(|| {
+                    for _ in 0..UPDATES {
+                        cell.rcu(|n| n + 1);
+                    }
+                });
+            }
+            // Reading at the same time, the count only goes up
+            s.spawn(|| {
+                let mut last = 0;
+                while last < WRITERS * UPDATES {
+                    let n = *cell.load();
+                    assert!(n >= last);
+                    last = n;
+                }
+            });
+        });
+        assert_eq!(WRITERS * UPDATES, *cell.load());
+    }
+}
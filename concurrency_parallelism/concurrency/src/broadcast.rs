@@ -0,0 +1,351 @@
+// A Bounded Broadcast Channel
+
+// mpsc hands every value to one receiver. A broadcast channel hands every value to all of them, like a chat room where
+// everybody hears everything that's said. The values live once, in a ring buffer shared by all the receivers, and every
+// receiver has its own cursor into it: the sequence number of the next value it hasn't read yet.
+    // 1. Every value that's sent gets the next sequence number, and a count of the receivers that still have to read it.
+    // 2. A receiver reads the value at its cursor, clones it (the last one to read it takes it without a clone), counts
+    //    itself off and moves its cursor on.
+    // 3. The values at the front that nobody has to read any more are dropped, which makes room for new ones.
+// A receiver only sees what's sent after it subscribed, and a value sent while there are no receivers is simply dropped.
+
+// The buffer holds capacity values. What happens when it's full is up to the channel, because one receiver that reads
+// slowly is holding up everybody else either way:
+    // 1. Overflow::Block: send waits until the slowest receiver has read the oldest value. Nothing is ever lost, but one
+    //    receiver that stops reading stops the senders too, so it's for receivers that are known to keep up.
+    // 2. Overflow::Skip: the oldest value is dropped to make room, and the next recv of a receiver that hadn't read it yet
+    //    returns Err(Lagged(n)), with how many it missed, before going on with the oldest value that's left. For live
+    //    updates, where the newest value is what matters.
+// The state is one Mutex, with a Condvar for the receivers waiting for a value and one for the senders waiting for room,
+// the same pieces as the WaitGroup and the Barrier in sync.rs. The tokio crate has the same channel for async code.
+
+use std::{
+    collections::VecDeque,
+    error, fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    Block,
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    // The receiver fell behind and this many values were dropped before it read them. The next recv goes on after them
+    Lagged(u64),
+    // Every Sender is gone and every value was read
+    Closed,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecvError::Lagged(missed) => write!(f, "the receiver fell behind and missed {missed} values"),
+            RecvError::Closed => write!(f, "the channel is closed"),
+        }
+    }
+}
+
+impl error::Error for RecvError {}
+
+struct Slot<T> {
+    value: T,
+    // The receivers that haven't read it yet
+    unread: usize,
+}
+
+struct State<T> {
+    slots: VecDeque<Slot<T>>,
+    // The sequence number of slots[0]
+    head: u64,
+    receivers: usize,
+    senders: usize,
+}
+
+impl<T> State<T> {
+    fn tail(&self) -> u64 {
+        self.head + self.slots.len() as u64
+    }
+
+    // Drops the values at the front that every receiver has read. Receivers read in order, so those are all at the front
+    fn trim(&mut self) -> bool {
+        let before = self.slots.len();
+        while self.slots.front().is_some_and(|slot| slot.unread == 0) {
+            self.slots.pop_front();
+            self.head += 1;
+        }
+        self.slots.len() < before
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    // Receivers wait on it for a value, or for the last Sender to go
+    available: Condvar,
+    // Senders of a Block channel wait on it for room
+    space: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Clone> Sender<T> {
+    // A channel without any receivers yet, subscribe() adds them
+    pub fn new(capacity: usize, overflow: Overflow) -> Sender<T> {
+        assert!(capacity > 0, "a broadcast channel needs room for at least one value");
+        let state = State { slots: VecDeque::with_capacity(capacity), head: 0, receivers: 0, senders: 1 };
+        let shared = Shared { state: Mutex::new(state), available: Condvar::new(), space: Condvar::new(), capacity, overflow };
+        Sender { shared: Arc::new(shared) }
+    }
+
+    // A receiver of every value sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.lock();
+        state.receivers += 1;
+        Receiver { shared: Arc::clone(&self.shared), next: state.tail() }
+    }
+
+    // Sends the value to every receiver and returns how many there are. With Overflow::Block it waits while the buffer
+    // is full of values that some receiver hasn't read yet
+    pub fn send(&self, value: T) -> usize {
+        let shared = &*self.shared;
+        let mut state = shared.lock();
+        loop {
+            if state.receivers == 0 {
+                return 0;
+            }
+            if state.slots.len() < shared.capacity {
+                break;
+            }
+            match shared.overflow {
+                Overflow::Block => state = shared.space.wait(state).unwrap(),
+                // The receivers that hadn't read it find out from their cursor, which is now before head
+                Overflow::Skip => {
+                    state.slots.pop_front();
+                    state.head += 1;
+                }
+            }
+        }
+        let receivers = state.receivers;
+        state.slots.push_back(Slot { value, unread: receivers });
+        shared.available.notify_all();
+        receivers
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.lock().receivers
+    }
+
+    // The values some receiver still has to read
+    pub fn len(&self) -> usize {
+        self.shared.lock().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.shared.lock().senders += 1;
+        Sender { shared: Arc::clone(&self.shared) }
+    }
+}
+
+// The last Sender going away closes the channel: the receivers read what's left, and then get Err(Closed)
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.available.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // The sequence number of the next value to read
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    // Waits for the next value
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(result) = take(&mut self.next, &self.shared, &mut state) {
+                return result;
+            }
+            state = self.shared.available.wait(state).unwrap();
+        }
+    }
+
+    // Like recv, but Ok(None) when no value came within the timeout
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<T>, RecvError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(result) = take(&mut self.next, &self.shared, &mut state) {
+                return result.map(Some);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            state = self.shared.available.wait_timeout(state, left).unwrap().0;
+        }
+    }
+
+    // Ok(None) when there's no value right now
+    pub fn try_recv(&mut self) -> Result<Option<T>, RecvError> {
+        let mut state = self.shared.lock();
+        take(&mut self.next, &self.shared, &mut state).transpose()
+    }
+}
+
+// The value at the receiver's cursor. None when there's nothing to read, but more may come
+fn take<T: Clone>(next: &mut u64, shared: &Shared<T>, state: &mut State<T>) -> Option<Result<T, RecvError>> {
+    if *next < state.head {
+        let missed = state.head - *next;
+        *next = state.head;
+        return Some(Err(RecvError::Lagged(missed)));
+    }
+    let index = (*next - state.head) as usize;
+    let Some(slot) = state.slots.get_mut(index) else {
+        return (state.senders == 0).then_some(Err(RecvError::Closed));
+    };
+    *next += 1;
+    slot.unread -= 1;
+    if index > 0 || slot.unread > 0 {
+        return Some(Ok(slot.value.clone()));
+    }
+    // The last receiver of the oldest value, it's ours to keep and its slot is free again
+    let slot = state.slots.pop_front().expect("the slot we just read");
+    state.head += 1;
+    state.trim();
+    shared.space.notify_all();
+    Some(Ok(slot.value))
+}
+
+impl<T> Receiver<T> {
+    // How many values are waiting for this receiver
+    pub fn len(&self) -> usize {
+        let state = self.shared.lock();
+        (state.tail() - self.next.max(state.head)) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// The values it hadn't read yet don't wait for it any more, which may free room for a Sender that's blocked
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receivers -= 1;
+        let from = self.next.saturating_sub(state.head) as usize;
+        for slot in state.slots.iter_mut().skip(from) {
+            slot.unread -= 1;
+        }
+        if state.trim() {
+            self.shared.space.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn every_receiver_gets_every_value() {
+        let sender = Sender::new(4, Overflow::Skip);
+        assert_eq!(0, sender.send("nobody hears this"));
+        assert!(sender.is_empty());
+
+        let mut early = sender.subscribe();
+        assert_eq!(1, sender.send("a"));
+        let mut late = sender.subscribe();
+        assert_eq!(2, sender.send("b"));
+
+        assert_eq!((Ok("a"), Ok("b")), (early.recv(), early.recv()));
+        assert_eq!(Ok(Some("b")), late.try_recv());
+        assert_eq!(Ok(None), late.try_recv());
+        // Everybody read both, so nothing is left in the buffer
+        assert_eq!(0, sender.len());
+        assert_eq!(Ok(None), early.recv_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn slow_receivers_skip_ahead() {
+        let sender = Sender::new(3, Overflow::Skip);
+        let mut fast = sender.subscribe();
+        let mut slow = sender.subscribe();
+        for i in 0..5 {
+            sender.send(i);
+            assert_eq!(Ok(i), fast.recv());
+        }
+        assert_eq!(3, slow.len());
+        assert_eq!(Err(RecvError::Lagged(2)), slow.recv());
+        assert_eq!(vec![2, 3, 4], std::iter::from_fn(|| slow.try_recv().unwrap()).collect::<Vec<_>>());
+        assert_eq!(0, sender.len());
+    }
+
+    #[test]
+    fn slow_receivers_block_the_sender() {
+        let sender = Sender::new(2, Overflow::Block);
+        let mut fast = sender.subscribe();
+        let mut slow = sender.subscribe();
+        let producer = {
+            let sender = sender.clone();
+            thread::spawn(move || (0..6).map(|i| sender.send(i)).sum::<usize>())
+        };
+        // The sender can't get more than 2 values ahead of slow, however fast reads
+        assert_eq!((Ok(0), Ok(1)), (fast.recv(), fast.recv()));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!((2, Ok(None)), (slow.len(), fast.try_recv()));
+        assert_eq!(Ok(0), slow.recv());
+        assert_eq!(Ok(2), fast.recv());
+        assert_eq!((Ok(1), Ok(2)), (slow.recv(), slow.recv()));
+        // Dropping the slow receiver lets the rest through
+        drop(slow);
+        assert_eq!(vec![Ok(3), Ok(4), Ok(5)], (0..3).map(|_| fast.recv()).collect::<Vec<_>>());
+        assert!((9..=12).contains(&producer.join().unwrap()));
+    }
+
+    #[test]
+    fn closes_after_the_last_sender() {
+        let sender = Sender::new(8, Overflow::Block);
+        let mut receiver = sender.subscribe();
+        let value = Arc::new(());
+        sender.clone().send(Arc::clone(&value));
+        sender.send(Arc::clone(&value));
+        drop(sender);
+
+        // What was sent is still read, then it's closed
+        assert!(receiver.recv().is_ok());
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).unwrap().is_some());
+        assert_eq!(Err(RecvError::Closed), receiver.recv().map(drop));
+        // Read by everybody, so dropped
+        assert_eq!(1, Arc::strong_count(&value));
+
+        let receiver = thread::spawn(move || receiver.recv().map(drop));
+        assert_eq!(Err(RecvError::Closed), receiver.join().unwrap());
+    }
+}
@@ -0,0 +1,8 @@
+// Concurrency primitives built from the ideas in main.rs, kept in a library crate so other projects (like the multithreaded webserver) can use them.
+
+pub mod actors;
+pub mod arc_swap;
+pub mod broadcast;
+pub mod proc;
+pub mod ring;
+pub mod sync;
@@ -11,6 +11,11 @@
 
 use std::{sync::{mpsc, Arc, Mutex}, thread, time::Duration};
 
+use concurrency::{
+    actors::{self, Actor, Context, Reply},
+    sync::{Barrier, WaitGroup},
+};
+
 fn main() {
     // Creating a New Thread with spawn()
 
@@ -50,6 +55,10 @@ fn main() {
 
     main9();
 
+    main10();
+
+    main11();
+
 }
 
 // Waiting for All Threads to Finish Using join Handles
@@ -444,3 +453,141 @@ fn main9() {
 // As marker traits, they don’t even have any methods to implement, they’re just useful for enforcing invariants related to concurrency.
 
 // For now, building new concurrent types not made up of Send and Sync parts requires careful thought to uphold the safety guarantees.
+
+
+// Waiting for Work Instead of Threads: WaitGroup and Barrier
+
+// The counter example above keeps every JoinHandle in a Vec only to join them all at the end.
+// A WaitGroup (src/sync.rs) does the same job without the Vec: each thread gets a clone, and wait() returns once every clone is dropped.
+
+fn main10() {
+    let counter = Arc::new(Mutex::new(0));
+    let wg = WaitGroup::new();
+
+    for _ in 0..10 {
+        let counter = Arc::clone(&counter);
+        let wg = wg.clone();
+        thread::spawn(move || {
+            *counter.lock().unwrap() += 1;
+            // The clone is dropped here, when the closure ends
+            drop(wg);
+        });
+    }
+
+    wg.wait();
+    println!("Result with a WaitGroup: {}", *counter.lock().unwrap());
+
+    // A Barrier makes threads wait for each other at a point in their work, here between the two phases of a computation.
+    // Every worker fills its part of phase one, and only after all of them are done does anyone read the other parts in phase two.
+    // Scoped threads can borrow the barrier and the results, and the scope joins them for us, so there's no join loop here either.
+
+    let workers = 4;
+    let barrier = Barrier::new(workers);
+    let squares: Vec<Mutex<u64>> = (0..workers).map(|_| Mutex::new(0)).collect();
+
+    thread::scope(|s| {
+        for id in 0..workers {
+            let (barrier, squares) = (&barrier, &squares);
+            s.spawn(move || {
+                *squares[id].lock().unwrap() = (id as u64 + 1).pow(2);
+
+                if barrier.wait() {
+                    println!("Phase one done, the last thread to arrive was worker {id}");
+                }
+
+                let total: u64 = squares.iter().map(|square| *square.lock().unwrap()).sum();
+                println!("Worker {id} sees a total of {total}");
+            });
+        }
+    });
+}
+
+
+// Actors: the LimitTracker, Without RefCell
+
+// The LimitTracker in smart_pointers/refcell_smart_pointer borrows a Messenger, and its mock messenger needs a RefCell
+// to record messages through &self. As actors (src/actors.rs) the same design needs neither: the tracker owns its value,
+// the notifier owns its list of sent messages, each on its own thread, and they talk by sending messages.
+// The notifier's Addr plays the part of the &dyn Messenger.
+
+struct Notifier {
+    sent: Vec<String>,
+}
+
+enum NotifierMsg {
+    Send(String),
+    Sent(Reply<Vec<String>>),
+}
+
+impl Actor for Notifier {
+    type Msg = NotifierMsg;
+
+    fn handle(&mut self, msg: NotifierMsg, _ctx: &mut Context) {
+        match msg {
+            NotifierMsg::Send(text) => {
+                println!("Notifier: {text}");
+                self.sent.push(text);
+            }
+            NotifierMsg::Sent(reply) => reply.send(self.sent.clone()),
+        }
+    }
+}
+
+struct LimitTracker {
+    messenger: actors::Addr<Notifier>,
+    value: usize,
+    max: usize,
+}
+
+enum LimitMsg {
+    SetValue(usize),
+    Value(Reply<usize>),
+}
+
+impl Actor for LimitTracker {
+    type Msg = LimitMsg;
+
+    fn handle(&mut self, msg: LimitMsg, _ctx: &mut Context) {
+        match msg {
+            LimitMsg::SetValue(value) => {
+                self.value = value;
+                let percentage_of_max = self.value as f64 / self.max as f64;
+
+                let warning = if percentage_of_max >= 1.0 {
+                    "Error: You are over your quota!"
+                } else if percentage_of_max >= 0.9 {
+                    "Urgent warning: You've used up over 90% of your quota!"
+                } else if percentage_of_max >= 0.75 {
+                    "Warning: You've used up over 75% of your quota!"
+                } else {
+                    return;
+                };
+                // If the notifier is gone there is nobody left to tell
+                let _ = self.messenger.send(NotifierMsg::Send(warning.to_string()));
+            }
+            LimitMsg::Value(reply) => reply.send(self.value),
+        }
+    }
+}
+
+fn main11() {
+    let (notifier, notifier_thread) = actors::spawn(|| Notifier { sent: vec![] });
+
+    // The factory runs again after a panic, so it clones the Addr instead of moving it
+    let messenger = notifier.clone();
+    let (tracker, tracker_thread) = actors::spawn(move || LimitTracker { messenger: messenger.clone(), value: 0, max: 100 });
+
+    for value in [10, 80, 95, 120] {
+        tracker.send(LimitMsg::SetValue(value)).unwrap();
+    }
+    println!("Tracker value: {}", tracker.ask(LimitMsg::Value).unwrap());
+
+    // Stopping the tracker first makes sure all its warnings have been sent before we ask the notifier
+    tracker.stop().unwrap();
+    println!("Tracker stopped: {:?}", tracker_thread.join().unwrap());
+
+    let sent = notifier.ask(NotifierMsg::Sent).unwrap();
+    println!("The notifier sent {} messages", sent.len());
+    notifier.stop().unwrap();
+    notifier_thread.join().unwrap();
+}
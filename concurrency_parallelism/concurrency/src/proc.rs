@@ -0,0 +1,393 @@
+// Running Programs with a Deadline
+
+// Command::output() runs a program and waits for it to finish, however long that takes. If it hangs, so do we.
+// Exec runs it with a few threads around it instead:
+    // 1. One thread per pipe reads stdout and stderr as the program writes them, and sends every chunk over one channel.
+    //    The chunks can be looked at while the program still runs, and both pipes are always drained: a program that fills
+    //    its stderr pipe while we only read stdout would block forever, and us with it.
+    // 2. Another thread writes stdin, for the same reason: a program that only reads its input after writing a lot of output
+    //    would otherwise wait on us while we wait on it.
+    // 3. A watchdog thread waits for the program to finish, up to the timeout. If it doesn't finish in time, the watchdog kills it.
+// The result is an Output when the program ran, or an ExitStatusError that says what went wrong: it couldn't start, it timed out,
+// it exited with an error code, or a signal killed it. The last three still carry whatever the program wrote, for the error message.
+
+// The watchdog needs the Child to kill it while the waiting thread needs it to wait for it, so it's shared in a Mutex.
+// Child::wait would hold the lock until the program exits, and the watchdog could never get it, so the waiting is done
+// with try_wait and a short sleep instead, after the pipes are closed, which is when the program is almost always done anyway.
+// Killing only reaches the program itself. If it started programs of its own that still hold its stdout open, wait goes on
+// until they're done too, that's why the tests below use sh -c "exec ..." for anything long-running.
+
+use std::{
+    ffi::OsStr,
+    fmt,
+    io::{self, Read, Write},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chunk {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub elapsed: Duration,
+}
+
+impl Output {
+    // Lossy, a program can write anything
+    pub fn stdout_str(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    pub fn stderr_str(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+#[derive(Debug)]
+pub enum ExitStatusError {
+    // The program couldn't be started, usually because it doesn't exist
+    Spawn { program: String, source: io::Error },
+    // Reading from or waiting for the program failed
+    Io(io::Error),
+    // Still running when the timeout was up, so it was killed
+    TimedOut { program: String, timeout: Duration, output: Output },
+    // Exited with a code other than 0
+    Code { program: String, code: i32, output: Output },
+    // Killed by a signal it didn't get from us (Unix only)
+    Signal { program: String, signal: i32, output: Output },
+}
+
+impl ExitStatusError {
+    // Everything the program wrote before it failed, when it got to run at all
+    pub fn output(&self) -> Option<&Output> {
+        match self {
+            ExitStatusError::TimedOut { output, .. } | ExitStatusError::Code { output, .. } | ExitStatusError::Signal { output, .. } => {
+                Some(output)
+            }
+            ExitStatusError::Spawn { .. } | ExitStatusError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ExitStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitStatusError::Spawn { program, source } => write!(f, "couldn't start {program}: {source}"),
+            ExitStatusError::Io(e) => write!(f, "I/O error while running a program: {e}"),
+            ExitStatusError::TimedOut { program, timeout, .. } => write!(f, "{program} didn't finish within {timeout:?} and was killed"),
+            ExitStatusError::Code { program, code, .. } => write!(f, "{program} exited with code {code}"),
+            ExitStatusError::Signal { program, signal, .. } => write!(f, "{program} was killed by signal {signal}"),
+        }?;
+        // The last line on stderr is usually the one that says why
+        let last_line = self.output().and_then(|output| output.stderr_str().lines().rev().find(|line| !line.trim().is_empty()).map(String::from));
+        match last_line {
+            Some(line) => write!(f, ": {line}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for ExitStatusError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExitStatusError::Spawn { source, .. } | ExitStatusError::Io(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExitStatusError {
+    fn from(e: io::Error) -> Self {
+        ExitStatusError::Io(e)
+    }
+}
+
+pub struct Exec {
+    command: Command,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+}
+
+impl Exec {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Exec {
+        Exec { command: Command::new(program), stdin: None, timeout: None }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.command.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.command.args(args);
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(mut self, key: K, value: V) -> Self {
+        self.command.env(key, value);
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.command.current_dir(dir);
+        self
+    }
+
+    // Without it the program's stdin is empty, it never inherits ours
+    pub fn stdin<B: Into<Vec<u8>>>(mut self, input: B) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn spawn(mut self) -> Result<Running, ExitStatusError> {
+        let program = self.command.get_program().to_string_lossy().into_owned();
+        let stdin = if self.stdin.is_some() { Stdio::piped() } else { Stdio::null() };
+        let mut child = self
+            .command
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|source| ExitStatusError::Spawn { program: program.clone(), source })?;
+
+        // A program that exits without reading its input closes the pipe, and the write fails: that's fine, so it's ignored
+        if let (Some(input), Some(mut pipe)) = (self.stdin.take(), child.stdin.take()) {
+            thread::spawn(move || {
+                let _ = pipe.write_all(&input);
+            });
+        }
+
+        let (tx, chunks) = mpsc::channel();
+        let readers = vec![
+            read_pipe(child.stdout.take().unwrap(), tx.clone(), Chunk::Stdout),
+            read_pipe(child.stderr.take().unwrap(), tx, Chunk::Stderr),
+        ];
+
+        let child = Arc::new(Mutex::new(child));
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let (done, watchdog) = match self.timeout {
+            Some(timeout) => {
+                let (done, finished) = mpsc::channel::<()>();
+                let child = Arc::clone(&child);
+                let timed_out = Arc::clone(&timed_out);
+                let watchdog = thread::spawn(move || {
+                    if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                        timed_out.store(true, Ordering::SeqCst);
+                        // It may have exited in the meantime, then there's nothing to kill
+                        let _ = child.lock().unwrap().kill();
+                    }
+                });
+                (Some(done), Some(watchdog))
+            }
+            None => (None, None),
+        };
+
+        Ok(Running {
+            program,
+            child,
+            chunks,
+            readers,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            timeout: self.timeout,
+            timed_out,
+            done,
+            watchdog,
+            started: Instant::now(),
+        })
+    }
+
+    // Whatever the exit status, as long as the program ran and didn't time out
+    pub fn output(self) -> Result<Output, ExitStatusError> {
+        self.spawn()?.wait()
+    }
+
+    // Like output, but exiting with anything other than success is an error too
+    pub fn run(self) -> Result<Output, ExitStatusError> {
+        self.spawn()?.wait_success()
+    }
+}
+
+fn read_pipe<R, F>(mut pipe: R, chunks: Sender<Chunk>, wrap: F) -> JoinHandle<io::Result<()>>
+where
+    R: Read + Send + 'static,
+    F: Fn(Vec<u8>) -> Chunk + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                // Nobody's listening anymore, but the pipe still has to be drained so the program doesn't block
+                Ok(n) => {
+                    let _ = chunks.send(wrap(buf[..n].to_vec()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    })
+}
+
+pub struct Running {
+    program: String,
+    child: Arc<Mutex<Child>>,
+    chunks: Receiver<Chunk>,
+    readers: Vec<JoinHandle<io::Result<()>>>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    timeout: Option<Duration>,
+    timed_out: Arc<AtomicBool>,
+    // Dropping it tells the watchdog the program is done
+    done: Option<Sender<()>>,
+    watchdog: Option<JoinHandle<()>>,
+    started: Instant,
+}
+
+impl Running {
+    pub fn id(&self) -> u32 {
+        self.child.lock().unwrap().id()
+    }
+
+    // The next piece of output, as soon as the program writes it. None once both pipes are closed.
+    // Everything handed out here is also kept for the Output that wait returns.
+    pub fn next_chunk(&mut self) -> Option<Chunk> {
+        let chunk = self.chunks.recv().ok()?;
+        match &chunk {
+            Chunk::Stdout(bytes) => self.stdout.extend_from_slice(bytes),
+            Chunk::Stderr(bytes) => self.stderr.extend_from_slice(bytes),
+        }
+        Some(chunk)
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        self.child.lock().unwrap().kill()
+    }
+
+    pub fn wait(mut self) -> Result<Output, ExitStatusError> {
+        while self.next_chunk().is_some() {}
+        for reader in self.readers.drain(..) {
+            reader.join().expect("pipe reader panicked")?;
+        }
+
+        let status = loop {
+            if let Some(status) = self.child.lock().unwrap().try_wait()? {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+        drop(self.done.take());
+        if let Some(watchdog) = self.watchdog.take() {
+            watchdog.join().expect("watchdog panicked");
+        }
+
+        let output = Output { status, stdout: self.stdout, stderr: self.stderr, elapsed: self.started.elapsed() };
+        match self.timeout {
+            Some(timeout) if self.timed_out.load(Ordering::SeqCst) => Err(ExitStatusError::TimedOut { program: self.program, timeout, output }),
+            _ => Ok(output),
+        }
+    }
+
+    pub fn wait_success(self) -> Result<Output, ExitStatusError> {
+        let program = self.program.clone();
+        let output = self.wait()?;
+        if output.status.success() {
+            return Ok(output);
+        }
+        if let Some(code) = output.status.code() {
+            return Err(ExitStatusError::Code { program, code, output });
+        }
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&output.status) {
+            return Err(ExitStatusError::Signal { program, signal, output });
+        }
+        Err(ExitStatusError::Io(io::Error::other(format!("{program} ended with {}", output.status))))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Exec {
+        Exec::new("sh").arg("-c").arg(script)
+    }
+
+    #[test]
+    fn captures_output_and_stdin() {
+        let output = sh("echo out; echo err >&2").run().unwrap();
+        assert_eq!("out\n", output.stdout_str());
+        assert_eq!("err\n", output.stderr_str());
+
+        let output = Exec::new("cat").stdin("fed through stdin").run().unwrap();
+        assert_eq!("fed through stdin", output.stdout_str());
+
+        // More than a pipe holds, on both pipes at once
+        let output = sh("head -c 200000 /dev/zero; head -c 200000 /dev/zero >&2").run().unwrap();
+        assert_eq!((200_000, 200_000), (output.stdout.len(), output.stderr.len()));
+    }
+
+    #[test]
+    fn typed_errors() {
+        let error = sh("echo 'no such thing' >&2; exit 3").run().unwrap_err();
+        assert!(matches!(error, ExitStatusError::Code { code: 3, .. }));
+        assert_eq!("sh exited with code 3: no such thing", error.to_string());
+        // output() doesn't mind the exit code
+        assert_eq!(Some(3), sh("exit 3").output().unwrap().status.code());
+
+        let error = sh("kill -9 $$").run().unwrap_err();
+        assert!(matches!(error, ExitStatusError::Signal { signal: 9, .. }));
+
+        let error = Exec::new("surely-not-a-program-on-this-machine").run().unwrap_err();
+        assert!(matches!(error, ExitStatusError::Spawn { .. }));
+    }
+
+    #[test]
+    fn kills_on_timeout() {
+        let started = Instant::now();
+        let error = sh("echo started; exec sleep 10").timeout(Duration::from_millis(100)).run().unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        match error {
+            ExitStatusError::TimedOut { output, .. } => assert_eq!("started\n", output.stdout_str()),
+            other => panic!("expected a timeout, got {other}"),
+        }
+
+        // A program that finishes in time isn't bothered by the watchdog
+        assert!(sh("true").timeout(Duration::from_secs(10)).run().is_ok());
+    }
+
+    #[test]
+    fn streams_chunks_while_running() {
+        let mut running = sh("echo first; sleep 0.2; echo second >&2").spawn().unwrap();
+        let started = Instant::now();
+        assert_eq!(Some(Chunk::Stdout(b"first\n".to_vec())), running.next_chunk());
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        let output = running.wait().unwrap();
+        assert_eq!("first\n", output.stdout_str());
+        assert_eq!("second\n", output.stderr_str());
+    }
+}
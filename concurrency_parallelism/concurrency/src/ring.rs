@@ -0,0 +1,296 @@
+// A Lock-Free Single-Producer Single-Consumer Ring Buffer
+
+// A channel or a Mutex<VecDeque> works for any number of senders and receivers, and pays for that generality with locking.
+// When exactly one thread sends and exactly one thread receives, we can do without locks: a fixed array used as a circle, plus two counters.
+    // tail: how many values the producer has written. Only the producer changes it.
+    // head: how many values the consumer has read. Only the consumer changes it.
+// The value number i lives in slot i % capacity, and tail - head is the number of values waiting. The counters only ever grow
+// (wrapping around after usize::MAX, which wrapping_sub handles), so full and empty can't be confused.
+
+// Why is this safe without a lock? Each slot is only touched by one side at a time, and the counters say whose turn it is:
+    // 1. The producer writes a slot, then publishes it by storing tail with Release ordering.
+    // 2. The consumer loads tail with Acquire ordering. Acquire/Release pairs up, so everything the producer wrote before its store
+    //    (the value in the slot) is visible to the consumer after its load.
+    // 3. The same goes the other way for head, which tells the producer that a slot has been read and can be reused.
+// With Relaxed ordering instead, the consumer could see the new tail before the value in the slot and read garbage.
+
+// And why is it also free of Arc? RingBuffer::split borrows the buffer mutably and hands out one Producer and one Consumer.
+// The &mut borrow guarantees there is never a second producer or consumer, and thread::scope lets both halves go to other threads
+// without reference counting, because the scope ends before the buffer can be dropped.
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+pub struct RingBuffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    producer_alive: AtomicBool,
+    consumer_alive: AtomicBool,
+}
+
+// UnsafeCell makes the buffer !Sync, because the compiler can't know that producer and consumer never touch the same slot at once.
+// The head/tail protocol above is what guarantees it, so we promise it ourselves. T: Send is needed because values move between threads.
+unsafe impl<T: Send> Sync for RingBuffer<T> {}
+
+impl<T> RingBuffer<T> {
+    pub fn new(capacity: usize) -> RingBuffer<T> {
+        assert!(capacity > 0, "a ring buffer needs room for at least one value");
+        RingBuffer {
+            slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            producer_alive: AtomicBool::new(true),
+            consumer_alive: AtomicBool::new(true),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn split(&mut self) -> (Producer<'_, T>, Consumer<'_, T>) {
+        self.producer_alive.store(true, Ordering::Relaxed);
+        self.consumer_alive.store(true, Ordering::Relaxed);
+        (Producer { ring: self }, Consumer { ring: self })
+    }
+}
+
+// The values that were pushed but never popped still have to be dropped, MaybeUninit won't do it for us
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        let mut i = head;
+        while i != tail {
+            unsafe { self.slots[i % self.slots.len()].get_mut().assume_init_drop() };
+            i = i.wrapping_add(1);
+        }
+    }
+}
+
+// Waiting without a lock means polling. We spin for a very short wait, then give the CPU to other threads,
+// and for a long wait sleep a little, so that an idle consumer doesn't keep a core busy.
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    fn snooze(&mut self) {
+        if self.step < 6 {
+            for _ in 0..1 << self.step {
+                std::hint::spin_loop();
+            }
+        } else if self.step < 10 {
+            thread::yield_now();
+        } else {
+            thread::sleep(Duration::from_micros(100));
+        }
+        self.step = (self.step + 1).min(10);
+    }
+}
+
+pub struct Producer<'a, T> {
+    ring: &'a RingBuffer<T>,
+}
+
+impl<T> Producer<'_, T> {
+    // Hands the value back when the buffer is full
+    pub fn try_push(&mut self, value: T) -> Result<(), T> {
+        let ring = self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == ring.capacity() {
+            return Err(value);
+        }
+
+        // The slot is free: the consumer is done with it (head is past it) and no one else can write, we are the only producer
+        unsafe { (*ring.slots[tail % ring.capacity()].get()).write(value) };
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    // Waits for room in the buffer. Fails only when the consumer is gone, since then no room will ever come.
+    pub fn push(&mut self, mut value: T) -> Result<(), T> {
+        let mut backoff = Backoff { step: 0 };
+        loop {
+            match self.try_push(value) {
+                Ok(()) => return Ok(()),
+                Err(v) if !self.ring.consumer_alive.load(Ordering::Acquire) => return Err(v),
+                Err(v) => value = v,
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T> Drop for Producer<'_, T> {
+    fn drop(&mut self) {
+        self.ring.producer_alive.store(false, Ordering::Release);
+    }
+}
+
+pub struct Consumer<'a, T> {
+    ring: &'a RingBuffer<T>,
+}
+
+impl<T> Consumer<'_, T> {
+    pub fn try_pop(&mut self) -> Option<T> {
+        let ring = self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        // Reading moves the value out, the slot is logically uninitialized again until the producer writes it
+        let value = unsafe { (*ring.slots[head % ring.capacity()].get()).assume_init_read() };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    // Waits for a value. None means the producer is gone and every value it pushed has been read.
+    pub fn pop(&mut self) -> Option<T> {
+        let mut backoff = Backoff { step: 0 };
+        loop {
+            if let Some(value) = self.try_pop() {
+                return Some(value);
+            }
+            if !self.ring.producer_alive.load(Ordering::Acquire) {
+                // The producer may have pushed a last value right before it was dropped, the Acquire above makes it visible
+                return self.try_pop();
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T> Iterator for Consumer<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.pop()
+    }
+}
+
+impl<T> Drop for Consumer<'_, T> {
+    fn drop(&mut self) {
+        self.ring.consumer_alive.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn fifo_until_full() {
+        let mut ring = RingBuffer::new(3);
+        let (mut producer, mut consumer) = ring.split();
+
+        assert_eq!(Ok(()), producer.try_push(1));
+        assert_eq!(Ok(()), producer.try_push(2));
+        assert_eq!(Ok(()), producer.try_push(3));
+        assert_eq!(Err(4), producer.try_push(4));
+
+        assert_eq!(Some(1), consumer.try_pop());
+        assert_eq!(Ok(()), producer.try_push(4));
+        assert_eq!(vec![2, 3, 4], std::iter::from_fn(|| consumer.try_pop()).collect::<Vec<_>>());
+        assert_eq!(None, consumer.try_pop());
+    }
+
+    #[test]
+    fn counters_wrap_around() {
+        let mut ring = RingBuffer::new(2);
+        // Start just before the counters overflow
+        *ring.head.get_mut() = usize::MAX - 1;
+        *ring.tail.get_mut() = usize::MAX - 1;
+
+        let (mut producer, mut consumer) = ring.split();
+        for i in 0..10 {
+            producer.try_push(i).unwrap();
+            producer.try_push(i + 100).unwrap();
+            assert!(producer.try_push(0).is_err());
+            assert_eq!(Some(i), consumer.try_pop());
+            assert_eq!(Some(i + 100), consumer.try_pop());
+        }
+    }
+
+    #[test]
+    fn blocking_ends_when_the_other_side_is_dropped() {
+        let mut ring = RingBuffer::new(1);
+        let (mut producer, consumer) = ring.split();
+        producer.try_push("kept").unwrap();
+        drop(consumer);
+        assert_eq!(Err("lost"), producer.push("lost"));
+        drop(producer);
+
+        // Values pushed before the producer went away are still delivered
+        let (_, mut consumer) = ring.split();
+        assert_eq!(Some("kept"), consumer.pop());
+        assert_eq!(None, consumer.pop());
+    }
+
+    #[test]
+    fn leftover_values_are_dropped() {
+        let value = Arc::new(());
+        {
+            let mut ring = RingBuffer::new(4);
+            let (mut producer, mut consumer) = ring.split();
+            for _ in 0..4 {
+                producer.try_push(Arc::clone(&value)).unwrap();
+            }
+            drop(consumer.try_pop());
+            // One popped and dropped, three still in the buffer
+            assert_eq!(4, Arc::strong_count(&value));
+        }
+        assert_eq!(1, Arc::strong_count(&value));
+    }
+
+    // The real test of the orderings: a tiny buffer forces the two threads to hand slots back and forth constantly.
+    // Any value read too early or overwritten too soon breaks the sequence. Run it with --release as well, where reordering is more likely.
+    #[test]
+    fn stress_two_threads() {
+        const COUNT: u64 = 200_000;
+
+        for capacity in [1, 2, 7, 64] {
+            let mut ring = RingBuffer::new(capacity);
+            let (mut producer, consumer) = ring.split();
+
+            let received = thread::scope(|s| {
+                s.spawn(move || {
+                    for i in 0..COUNT {
+                        // A Vec owns heap memory, so a torn or double read would also show up as a crash or a wrong value
+                        producer.push(vec![i; 2]).unwrap();
+                    }
+                });
+                s.spawn(move || {
+                    let mut expected = 0;
+                    for value in consumer {
+                        assert_eq!(vec![expected; 2], value);
+                        expected += 1;
+                    }
+                    expected
+                })
+                .join()
+                .unwrap()
+            });
+
+            assert_eq!(COUNT, received);
+            assert!(ring.is_empty());
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// WaitGroup and Barrier
+
+// Waiting for threads with `for handle in handles { handle.join().unwrap(); }` means keeping every JoinHandle around,
+// and it only works for whole threads. These two primitives wait for work instead, and are built from a Mutex and a Condvar:
+// the Mutex guards a counter, and the Condvar lets threads sleep until another thread changes that counter.
+
+// Condvar::wait can wake up without anyone calling notify (a "spurious wakeup"), so the condition is always checked again in a loop.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+// WaitGroup: every clone is one participant, and wait() blocks until all the clones are dropped.
+// A worker simply drops its clone when it's done (usually by returning from its thread), so forgetting to "mark done" is impossible.
+
+struct WaitGroupInner {
+    count: Mutex<usize>,
+    done: Condvar,
+}
+
+pub struct WaitGroup {
+    inner: Arc<WaitGroupInner>,
+}
+
+impl WaitGroup {
+    pub fn new() -> WaitGroup {
+        WaitGroup { inner: Arc::new(WaitGroupInner { count: Mutex::new(1), done: Condvar::new() }) }
+    }
+
+    // Takes self, because the waiting handle is a participant too: it leaves first, then waits for everyone else
+    pub fn wait(self) {
+        let inner = Arc::clone(&self.inner);
+        drop(self);
+
+        let mut count = inner.count.lock().unwrap();
+        while *count > 0 {
+            count = inner.done.wait(count).unwrap();
+        }
+    }
+}
+
+impl Default for WaitGroup {
+    fn default() -> WaitGroup {
+        WaitGroup::new()
+    }
+}
+
+impl Clone for WaitGroup {
+    fn clone(&self) -> WaitGroup {
+        *self.inner.count.lock().unwrap() += 1;
+        WaitGroup { inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl Drop for WaitGroup {
+    fn drop(&mut self) {
+        let mut count = self.inner.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.inner.done.notify_all();
+        }
+    }
+}
+
+// Barrier: n threads call wait(), and none of them continues until all n have arrived. Then the barrier is ready for the next round.
+
+// Reusing it is the tricky part. When the last thread arrives it resets the count for the next round and wakes the others,
+// but a woken thread can't look at the count to know that its round is over, because a fast thread may already be counting the next round.
+// So every round has a generation number: a thread waits until the generation changes from the one it arrived in.
+
+struct BarrierState {
+    arrived: usize,
+    generation: u64,
+}
+
+pub struct Barrier {
+    state: Mutex<BarrierState>,
+    all_arrived: Condvar,
+    parties: usize,
+}
+
+impl Barrier {
+    pub fn new(parties: usize) -> Barrier {
+        Barrier { state: Mutex::new(BarrierState { arrived: 0, generation: 0 }), all_arrived: Condvar::new(), parties }
+    }
+
+    // Returns true for exactly one thread per round, the last one to arrive, which is handy for work that should happen once per round
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let generation = state.generation;
+        state.arrived += 1;
+
+        if state.arrived >= self.parties {
+            state.arrived = 0;
+            state.generation += 1;
+            self.all_arrived.notify_all();
+            return true;
+        }
+
+        while state.generation == generation {
+            state = self.all_arrived.wait(state).unwrap();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn wait_group_waits_for_every_clone() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let wg = WaitGroup::new();
+
+        for i in 0..8 {
+            let wg = wg.clone();
+            let finished = Arc::clone(&finished);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(i * 5));
+                finished.fetch_add(1, Ordering::SeqCst);
+                drop(wg);
+            });
+        }
+
+        wg.wait();
+        assert_eq!(8, finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn wait_group_alone_returns_at_once() {
+        WaitGroup::new().wait();
+    }
+
+    #[test]
+    fn barrier_is_reusable() {
+        const THREADS: usize = 6;
+        const ROUNDS: usize = 50;
+
+        let barrier = Barrier::new(THREADS);
+        let arrived = AtomicUsize::new(0);
+        let leaders = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for round in 0..ROUNDS {
+                        arrived.fetch_add(1, Ordering::SeqCst);
+                        if barrier.wait() {
+                            leaders.fetch_add(1, Ordering::SeqCst);
+                        }
+                        // Nobody gets past the barrier before everyone of this round has arrived
+                        assert!(arrived.load(Ordering::SeqCst) >= (round + 1) * THREADS);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(ROUNDS, leaders.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,71 @@
+// The Guess Type
+
+// Chapter 9 (error_handling/errors) ends with a Guess type: the check that a number is in range happens once, in the constructor,
+// and every function that takes a Guess can rely on it. There, an out of range value is a bug and new() panics.
+// Here the value comes from a player, who types whatever they like, so the constructor returns a Result instead, and the
+// game tells the player what was wrong with it. The range is a parameter too, since --max changes it.
+
+use std::{cmp::Ordering, fmt, num::ParseIntError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuessError {
+    NotANumber(String, ParseIntError),
+    OutOfRange { value: u32, max: u32 },
+}
+
+impl fmt::Display for GuessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuessError::NotANumber(text, e) => write!(f, "{text:?} is not a number ({e})"),
+            GuessError::OutOfRange { value, max } => write!(f, "The guess must be between 1 and {max}, got {value}."),
+        }
+    }
+}
+
+impl std::error::Error for GuessError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guess {
+    // Private, so the only way to get a Guess is through new() and its check
+    value: u32,
+}
+
+impl Guess {
+    pub fn new(value: u32, max: u32) -> Result<Guess, GuessError> {
+        if value < 1 || value > max {
+            return Err(GuessError::OutOfRange { value, max });
+        }
+        Ok(Guess { value })
+    }
+
+    // A line the player typed, with the newline and the spaces around it
+    pub fn parse(text: &str, max: u32) -> Result<Guess, GuessError> {
+        let text = text.trim();
+        let value = text.parse().map_err(|e| GuessError::NotANumber(text.to_string(), e))?;
+        Guess::new(value, max)
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    // How the guess compares to the secret number: Less is "too small"
+    pub fn cmp_secret(&self, secret: u32) -> Ordering {
+        self.value.cmp(&secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_numbers_in_range_are_guesses() {
+        assert_eq!(42, Guess::parse(" 42\n", 100).unwrap().value());
+        assert_eq!(Ordering::Less, Guess::parse("42", 100).unwrap().cmp_secret(43));
+        assert_eq!(Err(GuessError::OutOfRange { value: 0, max: 100 }), Guess::new(0, 100));
+        assert_eq!("The guess must be between 1 and 10, got 11.", Guess::parse("11", 10).unwrap_err().to_string());
+        assert!(matches!(Guess::parse("-3", 100), Err(GuessError::NotANumber(text, _)) if text == "-3"));
+        assert!(Guess::parse("", 100).unwrap_err().to_string().starts_with("\"\" is not a number"));
+    }
+}
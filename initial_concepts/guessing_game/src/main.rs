@@ -1,10 +1,80 @@
-use rand::Rng;
-use std::{cmp::Ordering, io};
+use common::argparse::{ArgError, Parser};
+use std_collections::rand_lite::{self, Rng};
+use std::{cmp::Ordering, env, io, net::TcpListener, process};
+
+// The Guess type of chapter 9, for guesses typed by a player, see guess.rs
+mod guess;
+// Hosting a game for several players over TCP, see multiplayer.rs
+mod multiplayer;
+
+use guess::Guess;
+
+struct Options {
+    max: u32,
+    tries: Option<u32>,
+    // The address to host a multiplayer game on, and how many can play at once
+    serve: Option<String>,
+    players: usize,
+}
+
+// The range and the number of tries can be changed on the command line, e.g. cargo run -- --max 1000 --tries 10
+// The options are declared with the argument parser that minigrep started out with (projects/common/src/argparse.rs).
+fn options() -> Result<Options, ArgError> {
+    let matches = Parser::new("guessing_game", "guess the secret number")
+        .option("max", "N", "The secret number is between 1 and N")
+        .default("100")
+        .option("tries", "N", "Give up after N wrong guesses")
+        .option("serve", "ADDR", "Host a game for several players on ADDR, like 127.0.0.1:7879, instead of playing alone")
+        .option("players", "N", "With --serve, how many players can play at once")
+        .default("8")
+        .parse(env::args().skip(1))?;
+
+    let max: u32 = matches.get("max")?.unwrap();
+    if max == 0 {
+        return Err(ArgError::Invalid(String::from("--max must be at least 1")));
+    }
+    let players: usize = matches.get("players")?.unwrap();
+    if players == 0 {
+        return Err(ArgError::Invalid(String::from("--players must be at least 1")));
+    }
+    let serve = matches.value("serve").map(String::from);
+    let tries = matches.get("tries")?;
+    if serve.is_some() && tries.is_some() {
+        return Err(ArgError::Invalid(String::from("--tries is only for playing alone, a multiplayer game never ends")));
+    }
+    Ok(Options { max, tries, serve, players })
+}
 
 fn main() {
+    let Options { max, tries, serve, players } = match options() {
+        Ok(options) => options,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+
+    if let Some(addr) = serve {
+        let listener = TcpListener::bind(&addr).unwrap_or_else(|e| {
+            eprintln!("couldn't listen on {addr}: {e}");
+            process::exit(1);
+        });
+        println!("Hosting a game for up to {players} players on {addr}, join with: nc {}", addr.replace(':', " "));
+        if let Err(e) = multiplayer::serve(listener, max, players) {
+            eprintln!("the game stopped: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
     println!("Guess the number!");
 
-    let secret_number: u32 = rand::thread_rng().gen_range(1..=100);
+    let secret_number: u32 = rand_lite::thread_rng().gen_range(1..=max);
+    let mut wrong_guesses = 0;
 
     loop {
         println!("Please input your guess: ");
@@ -19,12 +89,16 @@ fn main() {
 
         // let guess: u32 = guess.trim().parse().expect("Please type a number!");
         // graceful error handling using a match expr on a Result object
-        let guess: u32 = match guess.trim().parse() {
-            Ok(num) => num,
-            Err(_) => continue,
+        // A Guess is also in range, and the player is told what was wrong instead of being asked again without a word
+        let guess = match Guess::parse(&guess, max) {
+            Ok(guess) => guess,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
         };
 
-        match guess.cmp(&secret_number) {
+        match guess.cmp_secret(secret_number) {
             Ordering::Less => println!("Too small!"),
             Ordering::Greater => println!("Too big!"),
             Ordering::Equal => {
@@ -32,5 +106,11 @@ fn main() {
                 break;
             }
         }
+
+        wrong_guesses += 1;
+        if Some(wrong_guesses) == tries {
+            println!("Out of tries, the number was {secret_number}.");
+            break;
+        }
     }
 }
@@ -0,0 +1,431 @@
+// Multiplayer over TCP
+
+// cargo run -- --serve 127.0.0.1:7879 hosts a game that several players join over the network, each with a plain TCP client:
+// $ nc 127.0.0.1 7879
+// Everybody guesses the same secret number. Whoever finds it wins the round, and a new round starts with a new number.
+
+// It's put together from pieces of the other projects:
+    // 1. Every player's connection runs on the webserver's ThreadPool (projects/multithreaded_webserver/src/lib.rs).
+    //    A connection keeps its worker for as long as the player stays, so --players sets the number of workers,
+    //    and a player who connects when they're all taken waits until someone leaves.
+    // 2. What one player does, everybody else hears about through the pub/sub Broker (projects/multithreaded_webserver/src/broker.rs).
+    //    Each connection subscribes to the game topic, and a second thread writes what comes in to its player.
+    // 3. A guess is a Guess (guess.rs), so the game itself never sees a number out of range.
+    // 4. The state everybody shares, the secret number, who is playing and their stats, is a Game behind a Mutex.
+    //    The Game knows nothing about sockets, which is what lets the tests play it directly.
+
+// The protocol is lines of text. A player first sends their name, then guesses, or one of the commands:
+// who (the players in the lobby), stats (a table of everybody's stats) and quit.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{self, prelude::*, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use common::table::{Align, Table};
+use multithreaded_webserver::{broker::Broker, ThreadPool};
+use std_collections::rand_lite::{Rng, Xoshiro256};
+
+use crate::guess::Guess;
+
+const TOPIC: &str = "game";
+// How many announcements a slow player can fall behind before the oldest ones are dropped
+const BACKLOG: usize = 64;
+// How often a player's writer thread checks whether the player left
+const POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub guesses: u32,
+    pub wins: u32,
+    // The fewest guesses a round was won with
+    pub best: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    TooSmall,
+    TooBig,
+    // The guess was right after this many guesses of the player in this round, and the next round has started
+    Correct { guesses: u32, round: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    EmptyName,
+    NameTaken(String),
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::EmptyName => write!(f, "The name can't be empty."),
+            JoinError::NameTaken(name) => write!(f, "{name} is already playing, pick another name."),
+        }
+    }
+}
+
+pub struct Game {
+    max: u32,
+    secret: u32,
+    round: u32,
+    rng: Xoshiro256,
+    // The players in the lobby, and their guesses in the current round
+    players: BTreeMap<String, u32>,
+    // Kept after a player leaves, so they get their stats back when they join again
+    stats: HashMap<String, Stats>,
+}
+
+impl Game {
+    pub fn new(max: u32, rng: Xoshiro256) -> Game {
+        assert!(max > 0, "the secret number needs a range of at least 1");
+        let mut game = Game { max, secret: 0, round: 0, rng, players: BTreeMap::new(), stats: HashMap::new() };
+        game.next_round();
+        game
+    }
+
+    fn next_round(&mut self) {
+        self.secret = self.rng.gen_range(1..=self.max);
+        self.round += 1;
+        self.players.values_mut().for_each(|guesses| *guesses = 0);
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn join(&mut self, name: &str) -> Result<(), JoinError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(JoinError::EmptyName);
+        }
+        if self.players.contains_key(name) {
+            return Err(JoinError::NameTaken(name.to_string()));
+        }
+        self.players.insert(name.to_string(), 0);
+        self.stats.entry(name.to_string()).or_default();
+        Ok(())
+    }
+
+    pub fn leave(&mut self, name: &str) {
+        self.players.remove(name);
+    }
+
+    // The names of the players in the lobby, sorted
+    pub fn players(&self) -> Vec<&str> {
+        self.players.keys().map(String::as_str).collect()
+    }
+
+    pub fn stats(&self, name: &str) -> Option<Stats> {
+        self.stats.get(name).copied()
+    }
+
+    // Only players in the lobby can guess, the connection makes sure of that
+    pub fn guess(&mut self, name: &str, guess: Guess) -> Outcome {
+        let guesses = self.players.get_mut(name).expect("only players who joined can guess");
+        *guesses += 1;
+        let guesses = *guesses;
+        let stats = self.stats.get_mut(name).unwrap();
+        stats.guesses += 1;
+
+        match guess.cmp_secret(self.secret) {
+            Ordering::Less => Outcome::TooSmall,
+            Ordering::Greater => Outcome::TooBig,
+            Ordering::Equal => {
+                stats.wins += 1;
+                stats.best = Some(stats.best.map_or(guesses, |best| best.min(guesses)));
+                let round = self.round;
+                self.next_round();
+                Outcome::Correct { guesses, round }
+            }
+        }
+    }
+
+    // Everybody who ever joined, most wins first
+    pub fn stats_table(&self) -> Table {
+        let mut rows: Vec<(&String, &Stats)> = self.stats.iter().collect();
+        rows.sort_by(|(a_name, a), (b_name, b)| b.wins.cmp(&a.wins).then(a.best.cmp(&b.best)).then(a_name.cmp(b_name)));
+
+        let mut table = Table::new(["Player", "Wins", "Guesses", "Best round"]);
+        for column in 1..4 {
+            table = table.align(column, Align::Right);
+        }
+        for (name, stats) in rows {
+            let best = stats.best.map_or_else(|| String::from("-"), |best| best.to_string());
+            table.add_row([name.clone(), stats.wins.to_string(), stats.guesses.to_string(), best]);
+        }
+        table
+    }
+}
+
+// What is published on the broker: the text for every player except the one who caused it
+#[derive(Debug, Clone)]
+struct Announcement {
+    from: String,
+    text: String,
+}
+
+// Accepts players until the listener fails. Each one is served on a worker of the pool.
+pub fn serve(listener: TcpListener, max: u32, players: usize) -> io::Result<()> {
+    let game = Arc::new(Mutex::new(Game::new(max, Xoshiro256::from_entropy())));
+    let broker = Arc::new(Broker::new(BACKLOG));
+    let pool = ThreadPool::new(players);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let (game, broker) = (Arc::clone(&game), Arc::clone(&broker));
+        pool.execute(move || {
+            if let Err(e) = play(stream, &game, &broker) {
+                eprintln!("player disconnected: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+// Both threads of a connection write to the player, so the stream they write to is shared, and every line is written whole
+fn send(writer: &Mutex<TcpStream>, line: &str) -> io::Result<()> {
+    writer.lock().unwrap().write_all(format!("{line}\n").as_bytes())
+}
+
+fn play(stream: TcpStream, game: &Mutex<Game>, broker: &Broker<Announcement>) -> io::Result<()> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut lines = BufReader::new(stream).lines();
+
+    send(&writer, "Welcome to the guessing game! What's your name?")?;
+    let name = loop {
+        let Some(line) = lines.next() else { return Ok(()) };
+        let name = line?.trim().to_string();
+        let joined = game.lock().unwrap().join(&name);
+        match joined {
+            Ok(()) => break name,
+            Err(e) => send(&writer, &e.to_string())?,
+        }
+    };
+
+    // Subscribing before announcing the player means they can't miss anything that happens from now on
+    let subscription = broker.subscribe(TOPIC);
+    broker.publish(TOPIC, Announcement { from: name.clone(), text: format!("* {name} joined") });
+    let left = Arc::new(AtomicBool::new(false));
+    let forwarder = {
+        let (name, writer, left) = (name.clone(), Arc::clone(&writer), Arc::clone(&left));
+        thread::spawn(move || {
+            while !left.load(AtomicOrdering::Relaxed) {
+                let Some(announcement) = subscription.recv_timeout(POLL) else {
+                    if subscription.is_closed() {
+                        break;
+                    }
+                    continue;
+                };
+                if announcement.from != name && send(&writer, &announcement.text).is_err() {
+                    break;
+                }
+            }
+        })
+    };
+
+    let result = commands(&name, &mut lines, &writer, game, broker);
+
+    // The player is gone, whether they said quit or the connection broke
+    game.lock().unwrap().leave(&name);
+    broker.publish(TOPIC, Announcement { from: name.clone(), text: format!("* {name} left") });
+    left.store(true, AtomicOrdering::Relaxed);
+    let _ = forwarder.join();
+    result
+}
+
+fn commands<B: BufRead>(
+    name: &str,
+    lines: &mut io::Lines<B>,
+    writer: &Mutex<TcpStream>,
+    game: &Mutex<Game>,
+    broker: &Broker<Announcement>,
+) -> io::Result<()> {
+    let (max, round, stats) = {
+        let game = game.lock().unwrap();
+        (game.max(), game.round(), game.stats(name).unwrap_or_default())
+    };
+    send(writer, &format!("Hi {name}! This is round {round}, guess a number between 1 and {max}, or type who, stats or quit."))?;
+    // Someone who played before under this name gets their stats back
+    if stats.guesses > 0 {
+        send(writer, &format!("Welcome back, you won {} rounds so far.", stats.wins))?;
+    }
+
+    let announce = |text: String| {
+        broker.publish(TOPIC, Announcement { from: name.to_string(), text });
+    };
+    for line in lines {
+        let line = line?;
+        match line.trim() {
+            "quit" => return send(writer, "Bye!"),
+            "who" => {
+                let players = game.lock().unwrap().players().join(", ");
+                send(writer, &format!("Playing: {players}"))?;
+            }
+            "stats" => {
+                let table = game.lock().unwrap().stats_table();
+                send(writer, table.to_string().trim_end())?;
+            }
+            text => match Guess::parse(text, max) {
+                Err(e) => send(writer, &e.to_string())?,
+                Ok(guess) => {
+                    let outcome = game.lock().unwrap().guess(name, guess);
+                    let value = guess.value();
+                    match outcome {
+                        Outcome::TooSmall => {
+                            send(writer, "Too small!")?;
+                            announce(format!("* {name} guessed {value}: too small"));
+                        }
+                        Outcome::TooBig => {
+                            send(writer, "Too big!")?;
+                            announce(format!("* {name} guessed {value}: too big"));
+                        }
+                        Outcome::Correct { guesses, round } => {
+                            send(writer, &format!("Correct! You won round {round} in {guesses} guesses. New round, new number."))?;
+                            announce(format!("* {name} won round {round} with {value}, in {guesses} guesses. New round, new number."));
+                        }
+                    }
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A seeded game, so every run plays the same numbers
+    fn game() -> Game {
+        Game::new(100, Xoshiro256::seed_from_u64(7))
+    }
+
+    // Guesses like a player who knows binary search, until the guess is right
+    fn find_secret(game: &mut Game, name: &str) -> (u32, Outcome) {
+        let (mut low, mut high) = (1, game.max());
+        loop {
+            let middle = (low + high) / 2;
+            match game.guess(name, Guess::new(middle, game.max()).unwrap()) {
+                Outcome::TooSmall => low = middle + 1,
+                Outcome::TooBig => high = middle - 1,
+                correct => return (middle, correct),
+            }
+        }
+    }
+
+    #[test]
+    fn players_join_guess_and_win() {
+        let mut game = game();
+        game.join("alice").unwrap();
+        game.join(" bob\n").unwrap();
+        assert_eq!(Err(JoinError::NameTaken(String::from("alice"))), game.join("alice"));
+        assert_eq!(Err(JoinError::EmptyName), game.join("  "));
+        assert_eq!(vec!["alice", "bob"], game.players());
+
+        game.guess("bob", Guess::new(1, 100).unwrap());
+        let (_, outcome) = find_secret(&mut game, "alice");
+        let Outcome::Correct { guesses, round: 1 } = outcome else { panic!("{outcome:?}") };
+        assert_eq!(2, game.round());
+        assert_eq!(Some(Stats { guesses, wins: 1, best: Some(guesses) }), game.stats("alice"));
+
+        // bob's guesses of the first round don't count in the second
+        let (_, outcome) = find_secret(&mut game, "bob");
+        assert!(matches!(outcome, Outcome::Correct { round: 2, .. }), "{outcome:?}");
+        assert_eq!(1, game.stats("bob").unwrap().wins);
+
+        // Stats outlive leaving the lobby
+        game.leave("alice");
+        assert_eq!(vec!["bob"], game.players());
+        assert_eq!(1, game.stats("alice").unwrap().wins);
+        // The header, its rule and a row per player
+        assert_eq!(4, game.stats_table().to_string().lines().count());
+    }
+
+    struct Client {
+        lines: io::Lines<BufReader<TcpStream>>,
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn connect(addr: &str, name: &str) -> Client {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut client = Client { lines: BufReader::new(stream.try_clone().unwrap()).lines(), stream };
+            assert!(client.line().starts_with("Welcome"));
+            client.send(name);
+            assert!(client.line().starts_with(&format!("Hi {name}! This is round")));
+            client
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.stream, "{line}").unwrap();
+        }
+
+        fn line(&mut self) -> String {
+            self.lines.next().unwrap().unwrap()
+        }
+
+        // Skips lines until one that starts with prefix
+        fn wait_for(&mut self, prefix: &str) -> String {
+            loop {
+                let line = self.line();
+                if line.starts_with(prefix) {
+                    return line;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_game_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener, 100, 3));
+
+        let mut alice = Client::connect(&addr, "alice");
+        let mut bob = Client::connect(&addr, "bob");
+        assert_eq!("* bob joined", alice.line());
+
+        bob.send("101");
+        assert_eq!("The guess must be between 1 and 100, got 101.", bob.line());
+        bob.send("who");
+        assert_eq!("Playing: alice, bob", bob.line());
+
+        // alice plays until she wins, and bob hears about each guess
+        let (mut low, mut high) = (1, 100);
+        let winning = loop {
+            let middle = (low + high) / 2;
+            alice.send(&middle.to_string());
+            match alice.line().as_str() {
+                "Too small!" => low = middle + 1,
+                "Too big!" => high = middle - 1,
+                line => {
+                    assert!(line.starts_with("Correct! You won round 1"), "{line}");
+                    break middle;
+                }
+            }
+            assert!(bob.line().starts_with(&format!("* alice guessed {middle}: too")));
+        };
+        assert!(bob.wait_for("* alice won").starts_with(&format!("* alice won round 1 with {winning}")));
+
+        bob.send("stats");
+        assert!(bob.wait_for("Player").contains("Best round"));
+        bob.send("quit");
+        assert_eq!("Bye!", bob.wait_for("Bye"));
+        assert_eq!("* bob left", alice.wait_for("* bob"));
+    }
+}
@@ -4,6 +4,8 @@
 //! to crates.io, and also various ways to document the crate.
 //! 
 
+// Semantic Versioning, the version numbers every published crate carries
+pub mod semver;
 
 /// Adds one to a given number
 /// 
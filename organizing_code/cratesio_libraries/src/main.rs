@@ -137,6 +137,7 @@ fn main() {
 
 // When you’ve made changes to your crate and are ready to release a new version, you change the version value specified in your Cargo.toml file and republish. 
 // Use the Semantic Versioning rules to decide what an appropriate next version number is.
+// src/semver.rs implements those rules: Version orders version numbers, and VersionReq matches them against requirements like "^1.2" the way Cargo does.
 // cargo publish to upload the new version
 
 // Deprecating Versions from Crates.io with cargo yank
@@ -0,0 +1,668 @@
+//! # Semantic Versioning
+//!
+//! Every crate on crates.io has a version number like `1.4.2`, and Cargo decides which versions of a dependency it
+//! may use by the rules of [Semantic Versioning](https://semver.org):
+//!
+//! * MAJOR goes up for changes that break the public API,
+//! * MINOR goes up for new features that don't break anything,
+//! * PATCH goes up for bug fixes.
+//!
+//! A version can also carry a pre-release (`1.0.0-beta.2`), which comes *before* the plain version,
+//! and build metadata (`1.0.0+20240101`), which doesn't change its precedence at all.
+//!
+//! [`Version`] parses and orders versions, and [`VersionReq`] is what goes on the right hand side of
+//! a dependency in Cargo.toml, like `serde = "1.0"` or `rand = ">=0.7, <0.9"`.
+
+use std::{cmp::Ordering, error::Error, fmt, str::FromStr};
+
+/// Why a version or a requirement couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SemverError {
+    /// The input was empty, or only whitespace.
+    Empty,
+    /// A character that isn't allowed where it appeared.
+    UnexpectedCharacter(char),
+    /// A number is missing, as in `1..2` or `1.2.`.
+    MissingNumber,
+    /// A number with a leading zero, like `01`. Only `0` itself may start with a zero.
+    LeadingZero(String),
+    /// A number too big for a `u64`.
+    Overflow(String),
+    /// An empty pre-release or build identifier, as in `1.0.0-alpha..1`.
+    EmptyIdentifier,
+    /// A version with fewer or more than three numbers.
+    WrongNumberOfParts,
+    /// A wildcard after an operator (`>=1.*`) or before a number (`1.*.2`).
+    InvalidWildcard,
+    /// A pre-release on a partial version, like `^1.2-beta`.
+    PrereleaseOnPartial,
+}
+
+impl fmt::Display for SemverError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SemverError::Empty => write!(f, "empty version"),
+            SemverError::UnexpectedCharacter(c) => write!(f, "unexpected character {c:?}"),
+            SemverError::MissingNumber => write!(f, "expected a number"),
+            SemverError::LeadingZero(n) => write!(f, "number {n} has a leading zero"),
+            SemverError::Overflow(n) => write!(f, "number {n} is too large"),
+            SemverError::EmptyIdentifier => write!(f, "empty identifier"),
+            SemverError::WrongNumberOfParts => write!(f, "a version has exactly three numbers, MAJOR.MINOR.PATCH"),
+            SemverError::InvalidWildcard => write!(f, "a wildcard can only replace the last numbers, without an operator"),
+            SemverError::PrereleaseOnPartial => write!(f, "a pre-release needs a full MAJOR.MINOR.PATCH version"),
+        }
+    }
+}
+
+impl Error for SemverError {}
+
+// Parsing Numbers and Identifiers
+
+fn parse_number(s: &str) -> Result<u64, SemverError> {
+    if s.is_empty() {
+        return Err(SemverError::MissingNumber);
+    }
+    if let Some(c) = s.chars().find(|c| !c.is_ascii_digit()) {
+        return Err(SemverError::UnexpectedCharacter(c));
+    }
+    if s.len() > 1 && s.starts_with('0') {
+        return Err(SemverError::LeadingZero(s.to_string()));
+    }
+    s.parse().map_err(|_| SemverError::Overflow(s.to_string()))
+}
+
+/// One dot-separated part of a pre-release, like `beta` or `2` in `1.0.0-beta.2`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Identifier {
+    Numeric(u64),
+    AlphaNumeric(String),
+}
+
+// Numbers are compared as numbers, so beta.2 < beta.11, and they come before words, so 1.0.0-1 < 1.0.0-alpha
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Identifier::Numeric(a), Identifier::Numeric(b)) => a.cmp(b),
+            (Identifier::Numeric(_), Identifier::AlphaNumeric(_)) => Ordering::Less,
+            (Identifier::AlphaNumeric(_), Identifier::Numeric(_)) => Ordering::Greater,
+            (Identifier::AlphaNumeric(a), Identifier::AlphaNumeric(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Identifier::Numeric(n) => write!(f, "{n}"),
+            Identifier::AlphaNumeric(s) => f.write_str(s),
+        }
+    }
+}
+
+// Identifiers are ASCII letters, digits and hyphens. Pre-release identifiers made only of digits are numbers, and can't have
+// leading zeros; build metadata is never compared, so it keeps its identifiers as they are.
+fn split_identifiers(s: &str) -> Result<Vec<&str>, SemverError> {
+    s.split('.')
+        .map(|id| {
+            if id.is_empty() {
+                return Err(SemverError::EmptyIdentifier);
+            }
+            match id.chars().find(|&c| !(c.is_ascii_alphanumeric() || c == '-')) {
+                Some(c) => Err(SemverError::UnexpectedCharacter(c)),
+                None => Ok(id),
+            }
+        })
+        .collect()
+}
+
+fn parse_prerelease(s: &str) -> Result<Vec<Identifier>, SemverError> {
+    split_identifiers(s)?
+        .into_iter()
+        .map(|id| {
+            if id.bytes().all(|b| b.is_ascii_digit()) {
+                parse_number(id).map(Identifier::Numeric)
+            } else {
+                Ok(Identifier::AlphaNumeric(id.to_string()))
+            }
+        })
+        .collect()
+}
+
+// No pre-release at all is the release itself, which comes after all of its pre-releases
+fn cmp_prerelease(a: &[Identifier], b: &[Identifier]) -> Ordering {
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        // Slices compare item by item, and a shorter one that is a prefix of the other comes first: alpha < alpha.1
+        (false, false) => a.cmp(b),
+    }
+}
+
+// Splits "core-pre+build", a '-' inside the build metadata doesn't start a pre-release
+fn split_version(s: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (rest, build) = match s.split_once('+') {
+        Some((rest, build)) => (rest, Some(build)),
+        None => (s, None),
+    };
+    match rest.split_once('-') {
+        Some((core, pre)) => (core, Some(pre), build),
+        None => (rest, None, build),
+    }
+}
+
+// Versions
+
+/// A version number: `MAJOR.MINOR.PATCH`, an optional `-pre.release` and optional `+build.metadata`.
+///
+/// Versions are ordered by their precedence, and build metadata only breaks ties between versions that are otherwise the same.
+/// Use [`Version::cmp_precedence`] to compare exactly like the Semantic Versioning spec does.
+///
+/// # Example:
+///
+/// ```
+/// use cratesio_libraries::semver::Version;
+///
+/// let beta = Version::parse("1.0.0-beta.11").unwrap();
+/// assert!(Version::parse("1.0.0-beta.2").unwrap() < beta);
+/// assert!(beta < Version::parse("1.0.0").unwrap());
+/// assert_eq!("1.0.0-beta.11", beta.to_string());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<Identifier>,
+    pub build: Vec<String>,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Version {
+        Version { major, minor, patch, pre: Vec::new(), build: Vec::new() }
+    }
+
+    /// Parses a version like `1.2.3`, `1.2.3-rc.1` or `1.2.3+sha.5114f85`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SemverError`] for anything that isn't a valid Semantic Version. Partial versions like `1.2`
+    /// and a leading `v` are rejected, they're only allowed in requirements.
+    pub fn parse(s: &str) -> Result<Version, SemverError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(SemverError::Empty);
+        }
+
+        let (core, pre, build) = split_version(s);
+        let numbers = core.split('.').map(parse_number).collect::<Result<Vec<u64>, _>>()?;
+        let [major, minor, patch] = numbers[..] else {
+            return Err(SemverError::WrongNumberOfParts);
+        };
+        let pre = pre.map(parse_prerelease).transpose()?.unwrap_or_default();
+        let build = match build {
+            Some(build) => split_identifiers(build)?.into_iter().map(String::from).collect(),
+            None => Vec::new(),
+        };
+        Ok(Version { major, minor, patch, pre, build })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    /// Compares like the Semantic Versioning spec says: the build metadata is ignored,
+    /// so `1.0.0+a` and `1.0.0+b` have the same precedence even though they aren't equal.
+    pub fn cmp_precedence(&self, other: &Version) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| cmp_prerelease(&self.pre, &other.pre))
+    }
+}
+
+// Ord has to agree with Eq, which does look at the build metadata, so it's compared last
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_precedence(other).then_with(|| self.build.cmp(&other.build))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn write_identifiers<T: fmt::Display>(f: &mut fmt::Formatter, separator: char, ids: &[T]) -> fmt::Result {
+    for (i, id) in ids.iter().enumerate() {
+        write!(f, "{}{id}", if i == 0 { separator } else { '.' })?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        write_identifiers(f, '-', &self.pre)?;
+        write_identifiers(f, '+', &self.build)
+    }
+}
+
+impl FromStr for Version {
+    type Err = SemverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Version::parse(s)
+    }
+}
+
+// Requirements
+
+/// The operator in front of a version in a requirement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `=1.2.3`, exactly this version. `=1.2` is any 1.2.x.
+    Exact,
+    /// `>1.2.3`
+    Greater,
+    /// `>=1.2.3`
+    GreaterEq,
+    /// `<1.2.3`
+    Less,
+    /// `<=1.2.3`
+    LessEq,
+    /// `~1.2.3`, patch updates only: `>=1.2.3, <1.3.0`.
+    Tilde,
+    /// `^1.2.3`, or just `1.2.3`: updates that don't change the left-most non-zero number, `>=1.2.3, <2.0.0`.
+    Caret,
+    /// `1.*` or `1.2.*`, any version starting with the numbers given.
+    Wildcard,
+}
+
+/// One operator and a version that may leave out the minor and patch numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparator {
+    pub op: Op,
+    pub major: u64,
+    pub minor: Option<u64>,
+    pub patch: Option<u64>,
+    pub pre: Vec<Identifier>,
+}
+
+/// A set of comparators that must all match, like `>=1.2, <1.5`.
+///
+/// A bare version is a caret requirement, as in Cargo.toml: `"1.2.3"` means `^1.2.3`, any version that should be
+/// compatible with 1.2.3. `*` matches every version.
+///
+/// Pre-releases are opt-in: a version like `1.3.0-beta` only matches when a comparator mentions a pre-release of
+/// 1.3.0 itself, so `^1.2` never hands out an unfinished 1.3.0.
+///
+/// # Example:
+///
+/// ```
+/// use cratesio_libraries::semver::{Version, VersionReq};
+///
+/// let req = VersionReq::parse("^0.2.3").unwrap();
+/// assert!(req.matches(&Version::parse("0.2.9").unwrap()));
+/// // Below 1.0, the minor number is the one that signals breaking changes
+/// assert!(!req.matches(&Version::parse("0.3.0").unwrap()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    pub comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+    /// Parses a comma separated list of comparators.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SemverError`] if any of the comparators is invalid.
+    pub fn parse(s: &str) -> Result<VersionReq, SemverError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(SemverError::Empty);
+        }
+        if s == "*" {
+            return Ok(VersionReq { comparators: Vec::new() });
+        }
+        let comparators = s.split(',').map(|c| Comparator::parse(c.trim())).collect::<Result<_, _>>()?;
+        Ok(VersionReq { comparators })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparators.iter().all(|c| c.matches(version))
+            && (!version.is_prerelease() || self.comparators.iter().any(|c| c.allows_prerelease_of(version)))
+    }
+}
+
+impl Comparator {
+    fn parse(s: &str) -> Result<Comparator, SemverError> {
+        let (op, rest) = [(">=", Op::GreaterEq), ("<=", Op::LessEq), (">", Op::Greater), ("<", Op::Less), ("=", Op::Exact), ("~", Op::Tilde), ("^", Op::Caret)]
+            .into_iter()
+            .find_map(|(prefix, op)| s.strip_prefix(prefix).map(|rest| (Some(op), rest.trim_start())))
+            .unwrap_or((None, s));
+        if rest.is_empty() {
+            return Err(SemverError::MissingNumber);
+        }
+
+        let (core, pre, _build) = split_version(rest);
+        let mut numbers = Vec::new();
+        let mut wildcard = false;
+        for part in core.split('.') {
+            if matches!(part, "*" | "x" | "X") {
+                wildcard = true;
+            } else if wildcard {
+                return Err(SemverError::InvalidWildcard);
+            } else {
+                numbers.push(parse_number(part)?);
+            }
+        }
+        if core.split('.').count() > 3 {
+            return Err(SemverError::WrongNumberOfParts);
+        }
+
+        let op = match (op, wildcard) {
+            (Some(_), true) => return Err(SemverError::InvalidWildcard),
+            (None, true) => Op::Wildcard,
+            (op, false) => op.unwrap_or(Op::Caret),
+        };
+        let pre = match pre {
+            Some(_) if numbers.len() < 3 => return Err(SemverError::PrereleaseOnPartial),
+            Some(pre) => parse_prerelease(pre)?,
+            None => Vec::new(),
+        };
+        // A lone * was handled by VersionReq::parse, inside a list like "*, <2" it has no numbers at all
+        let Some(&major) = numbers.first() else {
+            return Err(SemverError::InvalidWildcard);
+        };
+        Ok(Comparator { op, major, minor: numbers.get(1).copied(), patch: numbers.get(2).copied(), pre })
+    }
+
+    // Every match below goes number by number: the first one that differs decides, and a number that was left out matches anything
+    pub fn matches(&self, v: &Version) -> bool {
+        match self.op {
+            Op::Exact | Op::Wildcard => self.matches_exact(v),
+            Op::Greater => self.matches_greater(v),
+            Op::GreaterEq => self.matches_exact(v) || self.matches_greater(v),
+            Op::Less => self.matches_less(v),
+            Op::LessEq => self.matches_exact(v) || self.matches_less(v),
+            Op::Tilde => self.matches_tilde(v),
+            Op::Caret => self.matches_caret(v),
+        }
+    }
+
+    fn matches_exact(&self, v: &Version) -> bool {
+        v.major == self.major
+            && self.minor.is_none_or(|minor| v.minor == minor)
+            && self.patch.is_none_or(|patch| v.patch == patch)
+            && (self.patch.is_none() || v.pre == self.pre)
+    }
+
+    // >1.2 means past all of 1.2.x, so a missing number makes the comparison stop as "not greater"
+    fn matches_greater(&self, v: &Version) -> bool {
+        if v.major != self.major {
+            return v.major > self.major;
+        }
+        let Some(minor) = self.minor else { return false };
+        if v.minor != minor {
+            return v.minor > minor;
+        }
+        let Some(patch) = self.patch else { return false };
+        if v.patch != patch {
+            return v.patch > patch;
+        }
+        cmp_prerelease(&v.pre, &self.pre) == Ordering::Greater
+    }
+
+    fn matches_less(&self, v: &Version) -> bool {
+        if v.major != self.major {
+            return v.major < self.major;
+        }
+        let Some(minor) = self.minor else { return false };
+        if v.minor != minor {
+            return v.minor < minor;
+        }
+        let Some(patch) = self.patch else { return false };
+        if v.patch != patch {
+            return v.patch < patch;
+        }
+        cmp_prerelease(&v.pre, &self.pre) == Ordering::Less
+    }
+
+    fn matches_tilde(&self, v: &Version) -> bool {
+        if v.major != self.major {
+            return false;
+        }
+        let Some(minor) = self.minor else { return true };
+        if v.minor != minor {
+            return false;
+        }
+        let Some(patch) = self.patch else { return true };
+        v.patch > patch || (v.patch == patch && cmp_prerelease(&v.pre, &self.pre) != Ordering::Less)
+    }
+
+    // The left-most non-zero number may not change: ^1.2.3 allows 1.x.y, ^0.2.3 allows 0.2.y, and ^0.0.3 only 0.0.3
+    fn matches_caret(&self, v: &Version) -> bool {
+        if v.major != self.major {
+            return false;
+        }
+        let Some(minor) = self.minor else { return true };
+        let Some(patch) = self.patch else {
+            return if self.major > 0 { v.minor >= minor } else { v.minor == minor };
+        };
+
+        if self.major > 0 {
+            if v.minor != minor {
+                return v.minor > minor;
+            }
+        } else if minor > 0 {
+            if v.minor != minor {
+                return false;
+            }
+        } else if v.minor != minor || v.patch != patch {
+            return false;
+        }
+        v.patch > patch || (v.patch == patch && cmp_prerelease(&v.pre, &self.pre) != Ordering::Less)
+    }
+
+    fn allows_prerelease_of(&self, v: &Version) -> bool {
+        !self.pre.is_empty() && self.major == v.major && self.minor == Some(v.minor) && self.patch == Some(v.patch)
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.op {
+            Op::Exact => "=",
+            Op::Greater => ">",
+            Op::GreaterEq => ">=",
+            Op::Less => "<",
+            Op::LessEq => "<=",
+            Op::Tilde => "~",
+            Op::Caret => "^",
+            Op::Wildcard => "",
+        };
+        write!(f, "{op}{}", self.major)?;
+        match (self.minor, self.patch) {
+            (Some(minor), Some(patch)) => write!(f, ".{minor}.{patch}")?,
+            (Some(minor), None) => write!(f, ".{minor}")?,
+            _ => {}
+        }
+        if self.op == Op::Wildcard {
+            f.write_str(".*")?;
+        }
+        write_identifiers(f, '-', &self.pre)
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.comparators.is_empty() {
+            return f.write_str("*");
+        }
+        for (i, comparator) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{comparator}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = SemverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        VersionReq::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    fn req(s: &str) -> VersionReq {
+        VersionReq::parse(s).unwrap()
+    }
+
+    fn assert_matches(requirement: &str, yes: &[&str], no: &[&str]) {
+        let r = req(requirement);
+        for version in yes {
+            assert!(r.matches(&v(version)), "{requirement} should match {version}");
+        }
+        for version in no {
+            assert!(!r.matches(&v(version)), "{requirement} should not match {version}");
+        }
+    }
+
+    #[test]
+    fn parses_versions() {
+        let version = v("1.22.333-alpha.7.x-y+build.007");
+        assert_eq!((1, 22, 333), (version.major, version.minor, version.patch));
+        assert_eq!(
+            vec![Identifier::AlphaNumeric(String::from("alpha")), Identifier::Numeric(7), Identifier::AlphaNumeric(String::from("x-y"))],
+            version.pre
+        );
+        assert_eq!(vec!["build", "007"], version.build);
+        assert_eq!("1.22.333-alpha.7.x-y+build.007", version.to_string());
+        assert_eq!(Version::new(0, 0, 0), "  0.0.0 ".parse().unwrap());
+        // A hyphen in the build metadata doesn't start a pre-release
+        assert!(v("1.0.0+build-5").pre.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        let err = |s: &str| Version::parse(s).unwrap_err();
+        assert_eq!(SemverError::Empty, err(" "));
+        assert_eq!(SemverError::WrongNumberOfParts, err("1.2"));
+        assert_eq!(SemverError::WrongNumberOfParts, err("1.2.3.4"));
+        assert_eq!(SemverError::MissingNumber, err("1..3"));
+        assert_eq!(SemverError::UnexpectedCharacter('v'), err("v1.2.3"));
+        assert_eq!(SemverError::LeadingZero(String::from("01")), err("1.01.0"));
+        assert_eq!(SemverError::LeadingZero(String::from("01")), err("1.0.0-alpha.01"));
+        assert_eq!(SemverError::Overflow(String::from("99999999999999999999")), err("99999999999999999999.0.0"));
+        assert_eq!(SemverError::EmptyIdentifier, err("1.0.0-alpha..1"));
+        assert_eq!(SemverError::EmptyIdentifier, err("1.0.0+"));
+        assert_eq!(SemverError::UnexpectedCharacter('_'), err("1.0.0-a_b"));
+    }
+
+    #[test]
+    fn precedence_follows_the_spec() {
+        // The example list from semver.org, in increasing order
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "2.0.0",
+            "2.1.0",
+            "2.1.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+
+        // Build metadata has no precedence, but still makes the versions different
+        assert_eq!(Ordering::Equal, v("1.0.0+a").cmp_precedence(&v("1.0.0+b")));
+        assert_ne!(v("1.0.0+a"), v("1.0.0+b"));
+        assert!(v("1.0.0+a") < v("1.0.0+b"));
+    }
+
+    #[test]
+    fn caret_requirements() {
+        assert_matches("^1.2.3", &["1.2.3", "1.2.4", "1.9.0"], &["1.2.2", "2.0.0", "0.9.9"]);
+        assert_matches("1.2.3", &["1.2.3", "1.3.0"], &["2.0.0"]);
+        assert_matches("^1.2", &["1.2.0", "1.9.9"], &["1.1.9", "2.0.0"]);
+        assert_matches("^1", &["1.0.0", "1.9.9"], &["2.0.0", "0.9.0"]);
+        assert_matches("^0.2.3", &["0.2.3", "0.2.9"], &["0.3.0", "0.2.2"]);
+        assert_matches("^0.0.3", &["0.0.3"], &["0.0.4", "0.0.2"]);
+        assert_matches("^0.0", &["0.0.0", "0.0.9"], &["0.1.0"]);
+        assert_matches("^0", &["0.0.1", "0.9.9"], &["1.0.0"]);
+    }
+
+    #[test]
+    fn tilde_and_wildcard_requirements() {
+        assert_matches("~1.2.3", &["1.2.3", "1.2.9"], &["1.3.0", "1.2.2"]);
+        assert_matches("~1.2", &["1.2.0", "1.2.9"], &["1.3.0", "1.1.0"]);
+        assert_matches("~1", &["1.0.0", "1.9.0"], &["2.0.0"]);
+        assert_matches("*", &["0.0.1", "99.0.0"], &["1.0.0-beta"]);
+        assert_matches("1.*", &["1.0.0", "1.9.9"], &["2.0.0"]);
+        assert_matches("1.2.x", &["1.2.0", "1.2.9"], &["1.3.0"]);
+    }
+
+    #[test]
+    fn comparison_requirements() {
+        assert_matches("=1.2.3", &["1.2.3", "1.2.3+build"], &["1.2.4", "1.2.3-alpha"]);
+        assert_matches("=1.2", &["1.2.0", "1.2.9"], &["1.3.0"]);
+        assert_matches(">1.2.3", &["1.2.4", "2.0.0"], &["1.2.3"]);
+        assert_matches(">1.2", &["1.3.0"], &["1.2.9"]);
+        assert_matches(">=1.2", &["1.2.0", "5.0.0"], &["1.1.9"]);
+        assert_matches("<1.2", &["1.1.9", "0.1.0"], &["1.2.0"]);
+        assert_matches("<=1.2", &["1.2.9"], &["1.3.0"]);
+        assert_matches(">= 1.2, < 1.5", &["1.2.0", "1.4.9"], &["1.5.0", "1.1.0"]);
+    }
+
+    #[test]
+    fn prereleases_need_to_be_asked_for() {
+        assert_matches("^1.2", &[], &["1.3.0-beta"]);
+        assert_matches(">=1.0.0-alpha, <2", &["1.0.0-alpha", "1.0.0-beta", "1.5.0"], &["1.5.0-beta", "2.0.0-alpha"]);
+        assert_matches("~1.2.3-beta.2", &["1.2.3-beta.2", "1.2.3-beta.11", "1.2.3", "1.2.4"], &["1.2.3-beta.1", "1.2.4-alpha"]);
+    }
+
+    #[test]
+    fn rejects_invalid_requirements() {
+        let err = |s: &str| VersionReq::parse(s).unwrap_err();
+        assert_eq!(SemverError::Empty, err(""));
+        assert_eq!(SemverError::MissingNumber, err(">="));
+        assert_eq!(SemverError::InvalidWildcard, err(">=1.*"));
+        assert_eq!(SemverError::InvalidWildcard, err("1.*.2"));
+        assert_eq!(SemverError::InvalidWildcard, err("*, <2"));
+        assert_eq!(SemverError::PrereleaseOnPartial, err("^1.2-beta"));
+        assert_eq!(SemverError::WrongNumberOfParts, err("1.2.3.4"));
+        assert_eq!(SemverError::MissingNumber, err("1.2,"));
+    }
+
+    #[test]
+    fn requirements_display_normalized() {
+        assert_eq!("^1.2.3", req("1.2.3").to_string());
+        assert_eq!(">=1.2, <1.5.0-rc.1", req(">= 1.2 ,<1.5.0-rc.1").to_string());
+        assert_eq!("1.*", req("1.x").to_string());
+        assert_eq!("*", req("*").to_string());
+    }
+}
@@ -0,0 +1,22 @@
+// A Chat Server
+
+// The last project of the book is a webserver, where a connection asks for a page, gets it, and is done. In a chat the connections
+// stay open, and what comes in on one has to go out on others, so the threads serving them have to talk to each other.
+// This project puts together the concurrency pieces of the repo to do that:
+    // 1. The ThreadPool of the webserver runs the connections, with the graceful shutdown of chapter 20 (server.rs)
+    // 2. A bounded broadcast channel carries the messages of a room to everybody in it, and a slow client can't hold the
+    //    others up (server.rs)
+    // 3. A read timeout kicks the clients that stay silent for too long (server.rs)
+    // 4. A Mutex guards the one piece of state everybody shares, who is in which room (lobby.rs)
+// The protocol is lines of text, see protocol.rs, so any TCP client works as a chat client:
+// $ cargo run
+// $ nc 127.0.0.1 7880
+
+// The commands and how to read them
+pub mod protocol;
+// The nicknames and rooms
+pub mod lobby;
+// The connections
+pub mod server;
+
+pub use server::{ChatServer, Config, ShutdownHandle};
@@ -0,0 +1,126 @@
+// The Lobby
+
+// Who is connected under which nickname, and in which room. It's the state every connection shares, so the server keeps it
+// behind a Mutex, and it doesn't know about sockets or the broker: each method changes the state and says what changed,
+// and the connection tells the others about it.
+// A room is just a name that members have. It exists while somebody is in it, and nobody has to create it.
+
+use std::{collections::HashMap, fmt};
+
+pub const DEFAULT_ROOM: &str = "lobby";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LobbyError {
+    NickTaken(String),
+    NotConnected(String),
+}
+
+impl fmt::Display for LobbyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LobbyError::NickTaken(nick) => write!(f, "{nick} is taken, pick another nickname"),
+            LobbyError::NotConnected(nick) => write!(f, "{nick} isn't connected"),
+        }
+    }
+}
+
+impl std::error::Error for LobbyError {}
+
+#[derive(Debug, Default)]
+pub struct Lobby {
+    // Nickname to room
+    members: HashMap<String, String>,
+}
+
+impl Lobby {
+    pub fn new() -> Lobby {
+        Lobby::default()
+    }
+
+    // A new member starts out in DEFAULT_ROOM
+    pub fn register(&mut self, nick: &str) -> Result<(), LobbyError> {
+        if self.members.contains_key(nick) {
+            return Err(LobbyError::NickTaken(nick.to_string()));
+        }
+        self.members.insert(nick.to_string(), DEFAULT_ROOM.to_string());
+        Ok(())
+    }
+
+    // Returns the room, where the others hear about the new name
+    pub fn rename(&mut self, nick: &str, new_nick: &str) -> Result<String, LobbyError> {
+        if self.members.contains_key(new_nick) {
+            return Err(LobbyError::NickTaken(new_nick.to_string()));
+        }
+        let room = self.members.remove(nick).ok_or_else(|| LobbyError::NotConnected(nick.to_string()))?;
+        self.members.insert(new_nick.to_string(), room.clone());
+        Ok(room)
+    }
+
+    // Moves the member to room, and returns the room they were in
+    pub fn join(&mut self, nick: &str, room: &str) -> Result<String, LobbyError> {
+        let current = self.members.get_mut(nick).ok_or_else(|| LobbyError::NotConnected(nick.to_string()))?;
+        Ok(std::mem::replace(current, room.to_string()))
+    }
+
+    // Returns the room the member was in, None if there was no such member
+    pub fn leave(&mut self, nick: &str) -> Option<String> {
+        self.members.remove(nick)
+    }
+
+    pub fn room_of(&self, nick: &str) -> Option<&str> {
+        self.members.get(nick).map(String::as_str)
+    }
+
+    // The members of a room, sorted
+    pub fn members(&self, room: &str) -> Vec<&str> {
+        let mut members: Vec<&str> =
+            self.members.iter().filter(|(_, in_room)| *in_room == room).map(|(nick, _)| nick.as_str()).collect();
+        members.sort_unstable();
+        members
+    }
+
+    // Every room with somebody in it and how many, sorted by name
+    pub fn rooms(&self) -> Vec<(&str, usize)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for room in self.members.values() {
+            *counts.entry(room.as_str()).or_default() += 1;
+        }
+        let mut rooms: Vec<(&str, usize)> = counts.into_iter().collect();
+        rooms.sort_unstable();
+        rooms
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_move_between_rooms() {
+        let mut lobby = Lobby::new();
+        lobby.register("alice").unwrap();
+        lobby.register("bob").unwrap();
+        assert_eq!(Err(LobbyError::NickTaken(String::from("bob"))), lobby.register("bob"));
+        assert_eq!(vec![("lobby", 2)], lobby.rooms());
+
+        assert_eq!(Ok(String::from("lobby")), lobby.join("bob", "rust"));
+        assert_eq!(Ok(String::from("rust")), lobby.rename("bob", "robert"));
+        assert_eq!(Err(LobbyError::NickTaken(String::from("alice"))), lobby.rename("robert", "alice"));
+        assert_eq!(vec![("lobby", 1), ("rust", 1)], lobby.rooms());
+        assert_eq!(vec!["robert"], lobby.members("rust"));
+        assert_eq!(Some("rust"), lobby.room_of("robert"));
+
+        assert_eq!(Some(String::from("rust")), lobby.leave("robert"));
+        assert_eq!(None, lobby.leave("robert"));
+        assert_eq!(Err(LobbyError::NotConnected(String::from("robert"))), lobby.join("robert", "lobby"));
+        assert_eq!(1, lobby.len());
+    }
+}
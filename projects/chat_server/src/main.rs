@@ -0,0 +1,66 @@
+// Chat Server
+
+// $ cargo run -- --addr 127.0.0.1:7880 --idle 600
+// Then connect with a few terminals running nc 127.0.0.1 7880 (or telnet), and chat.
+// Typing shutdown here, closing stdin (Ctrl-D), Ctrl-C or a kill stops the server: every client is told, and the server waits
+// for them to go. All of them go through the same shutdown hooks, see common::signals and multithreaded_webserver::accept.
+
+use std::{env, process, time::Duration};
+
+use chat_server::{ChatServer, Config};
+use common::signals::Shutdown;
+use common::argparse::{ArgError, Parser};
+use multithreaded_webserver::accept;
+
+fn config() -> Result<(String, Config), ArgError> {
+    let matches = Parser::new("chat_server", "a line based chat over TCP")
+        .option("addr", "ADDR", "The address to listen on")
+        .default("127.0.0.1:7880")
+        .option("threads", "N", "How many clients can be connected at once")
+        .default("32")
+        .option("idle", "SECS", "Disconnect the clients that send nothing for this long")
+        .default("300")
+        .parse(env::args().skip(1))?;
+
+    let threads: usize = matches.get("threads")?.unwrap();
+    if threads == 0 {
+        return Err(ArgError::Invalid(String::from("--threads must be at least 1")));
+    }
+    let idle_timeout = Duration::from_secs(matches.get("idle")?.unwrap());
+    let addr = matches.value("addr").unwrap().to_string();
+    Ok((addr, Config { threads, idle_timeout, ..Config::default() }))
+}
+
+fn main() {
+    let (addr, config) = match config() {
+        Ok(config) => config,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    if let Err(e) = common::log::init_from_env("LOG") {
+        eprintln!("{e}");
+    }
+
+    let server = ChatServer::bind(&addr, config).unwrap_or_else(|e| {
+        eprintln!("couldn't listen on {addr}: {e}");
+        process::exit(1);
+    });
+    println!("chatting on {}, type shutdown, press Ctrl-D or Ctrl-C to stop", server.local_addr());
+
+    let handle = server.shutdown_handle();
+    let hooks = Shutdown::new().hook("flush the log", || {
+        // There's nowhere left to report it
+        let _ = common::log::flush();
+    });
+    if let Err(e) = accept::serve_until_stopped(|| server.run(), move || handle.shutdown(), hooks, true) {
+        eprintln!("the server stopped: {e}");
+        process::exit(1);
+    }
+    println!("every client is gone, bye");
+}
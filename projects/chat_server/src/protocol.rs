@@ -0,0 +1,107 @@
+// The Protocol
+
+// Every line a client sends is one command. A line that starts with / is a command for the server, anything else is a message
+// to the room the client is in, the way IRC does it:
+    // /nick NAME    pick a nickname, or change it. Nothing else works before the first one.
+    // /join ROOM    leave the current room for another one, which exists as soon as somebody is in it. "#rust" and "rust" are the same room.
+    // /rooms        the rooms and how many are in each
+    // /who          who is in the current room
+    // /help         this list
+    // /quit         leave
+// Nicknames and room names are 1 to 16 letters, digits, _ or -, so they can't contain the spaces and # the replies use around them.
+
+use std::fmt;
+
+pub const MAX_NAME: usize = 16;
+
+pub const HELP: &str = "commands: /nick NAME, /join ROOM, /rooms, /who, /help, /quit, anything else is said in the room";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Nick(String),
+    Join(String),
+    Rooms,
+    Who,
+    Help,
+    Quit,
+    Say(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    UnknownCommand(String),
+    // The command, for the message
+    MissingArgument(&'static str),
+    InvalidName(String),
+    EmptyMessage,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::UnknownCommand(command) => write!(f, "unknown command /{command}, try /help"),
+            ProtocolError::MissingArgument(command) => write!(f, "/{command} needs a name"),
+            ProtocolError::InvalidName(name) => {
+                write!(f, "{name:?} isn't a valid name, use 1 to {MAX_NAME} letters, digits, _ or -")
+            }
+            ProtocolError::EmptyMessage => write!(f, "there's nothing to say"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+pub fn valid_name(name: &str) -> bool {
+    (1..=MAX_NAME).contains(&name.chars().count()) && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+fn name(command: &'static str, argument: &str) -> Result<String, ProtocolError> {
+    match argument.trim() {
+        "" => Err(ProtocolError::MissingArgument(command)),
+        name if valid_name(name) => Ok(name.to_string()),
+        name => Err(ProtocolError::InvalidName(name.to_string())),
+    }
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, ProtocolError> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let Some(command) = line.strip_prefix('/') else {
+            return match line.trim() {
+                "" => Err(ProtocolError::EmptyMessage),
+                _ => Ok(Command::Say(line.to_string())),
+            };
+        };
+        let (command, argument) = command.split_once(' ').unwrap_or((command, ""));
+        match command {
+            "nick" => Ok(Command::Nick(name("nick", argument)?)),
+            "join" => Ok(Command::Join(name("join", argument.trim().trim_start_matches('#'))?)),
+            "rooms" => Ok(Command::Rooms),
+            "who" => Ok(Command::Who),
+            "help" => Ok(Command::Help),
+            "quit" => Ok(Command::Quit),
+            _ => Err(ProtocolError::UnknownCommand(command.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_and_messages() {
+        assert_eq!(Ok(Command::Nick(String::from("ferris"))), Command::parse("/nick ferris\r\n"));
+        assert_eq!(Ok(Command::Join(String::from("rust"))), Command::parse("/join #rust"));
+        assert_eq!(Ok(Command::Join(String::from("rust"))), Command::parse("/join  rust "));
+        assert_eq!(Ok(Command::Who), Command::parse("/who"));
+        assert_eq!(Ok(Command::Say(String::from("  hi /all"))), Command::parse("  hi /all\n"));
+
+        assert_eq!(Err(ProtocolError::MissingArgument("nick")), Command::parse("/nick"));
+        assert_eq!(Err(ProtocolError::InvalidName(String::from("two words"))), Command::parse("/nick two words"));
+        assert_eq!(Err(ProtocolError::UnknownCommand(String::from("kick"))), Command::parse("/kick bob"));
+        assert_eq!(Err(ProtocolError::EmptyMessage), Command::parse("   "));
+        assert!(!valid_name("a_very_long_nickname"));
+        assert!(valid_name("zoë-2"));
+    }
+}
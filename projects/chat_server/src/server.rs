@@ -0,0 +1,496 @@
+// The Server
+
+// One connection is one client, and it's served by two threads:
+    // 1. A worker of the ThreadPool reads the client's lines, runs the commands and answers them. It holds on to the worker
+    //    until the client leaves, so Config::threads is also the number of clients that can chat at once.
+    // 2. A thread of its own forwards what is said in the client's room. Every room with somebody in it has a broadcast
+    //    channel (concurrency/src/broadcast.rs), and this thread holds a receiver of the current one. /join swaps it for another.
+// A client that reads slower than the room talks falls Config::backlog lines behind at most. Then, with Overflow::Skip, it
+// misses the oldest lines and is told how many, or with Overflow::Block the others wait for it before they can say more.
+// Both write to the client, through one Mutex<Box<dyn Stream>>, so their lines never end up mixed together.
+
+// A client that sends nothing for Config::idle_timeout is kicked. That's the read timeout of its connection: a read that waits
+// longer fails, and the worker says why and closes it. Nothing has to be rescheduled for every line, the next read simply
+// starts waiting again. The connections come from a Network (multithreaded_webserver/src/net.rs), TCP unless bind_on is
+// given another one, and a read timeout runs on the clock of the network it's on: the simulated network of the sim feature
+// has one a test advances, which is how the test below kicks a client without waiting for it.
+
+// The accept loop and the shutdown are an Acceptor's (multithreaded_webserver/src/accept.rs), the kvstore serves its clients
+// with one too: ShutdownHandle::shutdown() says goodbye to every client and closes their connections, which ends their
+// workers' loops, and then wakes up the accept loop. run() returns once every worker is done.
+// A client that stopped reading mustn't hold that up. Its forwarder may be stuck in a write with the writer locked, so the
+// goodbye is best-effort: skipped when the writer is busy, and given up after GOODBYE_TIMEOUT otherwise. The connection is
+// closed through a handle of its own, which needs no lock, and the Acceptor says the goodbyes after its lock is let go.
+
+use std::{
+    collections::HashMap,
+    io::{self, prelude::*, BufReader},
+    net::{Shutdown, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use concurrency::broadcast::{Overflow, Receiver, RecvError, Sender};
+use multithreaded_webserver::{
+    accept::{Acceptor, Stopper},
+    net::{Network, Stream, Tcp},
+};
+
+use crate::{
+    lobby::{Lobby, DEFAULT_ROOM},
+    protocol::{Command, HELP},
+};
+
+// How often a forwarding thread checks whether its client left
+const POLL: Duration = Duration::from_millis(100);
+// How long a goodbye waits for a client that doesn't read it
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub threads: usize,
+    pub idle_timeout: Duration,
+    // How many lines a client that reads slowly can fall behind
+    pub backlog: usize,
+    // And what happens when it's that far behind: Skip drops the oldest lines for it, Block holds up the room
+    pub overflow: Overflow,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { threads: 32, idle_timeout: Duration::from_secs(300), backlog: 256, overflow: Overflow::Skip }
+    }
+}
+
+type Writer = Arc<Mutex<Box<dyn Stream>>>;
+
+// The two handles of a connection: the one its lines are written to, and the one that closes it without waiting for the writer.
+// The socket's lock is only held for calls that don't block
+#[derive(Clone)]
+struct Client {
+    writer: Writer,
+    socket: Arc<Mutex<Box<dyn Stream>>>,
+}
+
+impl Client {
+    // The last line to the client, if it can be written without waiting on somebody else, and then the connection is closed
+    fn goodbye(&self, line: &str) {
+        if let Ok(mut writer) = self.writer.try_lock() {
+            // Without a timeout, it's still written in full, only a client that doesn't read could make it wait
+            if self.socket.lock().unwrap().set_write_timeout(Some(GOODBYE_TIMEOUT)).is_ok() {
+                // Best-effort, the connection is closed either way
+                let _ = writer.write_all(format!("{line}\n").as_bytes());
+            }
+        }
+        // Closed already when the client left first
+        let _ = self.socket.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+struct Shared {
+    config: Config,
+    lobby: Mutex<Lobby>,
+    // A channel for every room somebody is subscribed to
+    rooms: Mutex<HashMap<String, Sender<String>>>,
+}
+
+// Lines are written whole, and with their newline, while holding the lock
+fn send(writer: &Mutex<Box<dyn Stream>>, line: &str) -> io::Result<()> {
+    writer.lock().unwrap().write_all(format!("{line}\n").as_bytes())
+}
+
+pub struct ChatServer {
+    acceptor: Acceptor<Client>,
+    shared: Arc<Shared>,
+}
+
+impl ChatServer {
+    pub fn bind(addr: &str, config: Config) -> io::Result<ChatServer> {
+        ChatServer::bind_on(Arc::new(Tcp), addr, config)
+    }
+
+    // Listens on the network instead of TCP, like the simulated one of the tests
+    pub fn bind_on(network: Arc<dyn Network>, addr: &str, config: Config) -> io::Result<ChatServer> {
+        assert!(config.threads > 0, "the server needs at least one thread");
+        let acceptor = Acceptor::bind(network, addr)?;
+        let shared = Shared { lobby: Mutex::new(Lobby::new()), rooms: Mutex::new(HashMap::new()), config };
+        Ok(ChatServer { acceptor, shared: Arc::new(shared) })
+    }
+
+    // The address it listens on, with the port the system picked when it was bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.acceptor.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { stopper: self.acceptor.stopper() }
+    }
+
+    // Serves clients until shutdown() is called, or accepting a connection fails
+    pub fn run(self) -> io::Result<()> {
+        let shared = Arc::clone(&self.shared);
+        let open = |stream: &dyn Stream| {
+            let (writer, socket) = (stream.try_clone()?, stream.try_clone()?);
+            Ok(Client { writer: Arc::new(Mutex::new(writer)), socket: Arc::new(Mutex::new(socket)) })
+        };
+        self.acceptor.run(self.shared.config.threads, open, move |stream, client| serve_client(stream, client, &shared))
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    stopper: Stopper<Client>,
+}
+
+impl ShutdownHandle {
+    // Calling it again does nothing
+    pub fn shutdown(&self) {
+        self.stopper.stop(|client| client.goodbye("* the server is shutting down, bye"));
+    }
+}
+
+struct Session<'a> {
+    shared: &'a Shared,
+    writer: Writer,
+    nick: Option<String>,
+    // What the forwarding thread reads from: the current room, nothing before the client has a nickname
+    subscription: Arc<Mutex<Option<Receiver<String>>>>,
+}
+
+impl Session<'_> {
+    fn reply(&self, line: &str) -> io::Result<()> {
+        send(&self.writer, &format!("* {line}"))
+    }
+
+    // Nobody hears it in a room without anybody in it. The Sender is cloned out of the map, so a send that waits for
+    // a slow client with Overflow::Block doesn't hold up the other rooms
+    fn publish(&self, room: &str, line: String) {
+        let sender = self.shared.rooms.lock().unwrap().get(room).cloned();
+        if let Some(sender) = sender {
+            sender.send(line);
+        }
+    }
+
+    // None leaves the current room without joining another one
+    fn subscribe(&self, room: Option<&str>) {
+        let mut rooms = self.shared.rooms.lock().unwrap();
+        let receiver = room.map(|room| {
+            let (backlog, overflow) = (self.shared.config.backlog, self.shared.config.overflow);
+            rooms.entry(room.to_string()).or_insert_with(|| Sender::new(backlog, overflow)).subscribe()
+        });
+        // Dropping the old receiver may leave its room empty, and then its channel goes too
+        *self.subscription.lock().unwrap() = receiver;
+        rooms.retain(|_, sender| sender.receiver_count() > 0);
+    }
+
+    // false once the client asked to leave
+    fn handle(&mut self, command: Command) -> io::Result<bool> {
+        let (nick, command) = match (&self.nick, command) {
+            (_, Command::Help) => return self.reply(HELP).map(|_| true),
+            (_, Command::Quit) => return self.reply("bye").map(|_| false),
+            (None, Command::Nick(nick)) => {
+                if let Err(e) = self.shared.lobby.lock().unwrap().register(&nick) {
+                    return self.reply(&e.to_string()).map(|_| true);
+                }
+                common::info!("{nick} joined");
+                // Subscribed first, so the client hears about their own arrival like everybody else
+                self.subscribe(Some(DEFAULT_ROOM));
+                self.publish(DEFAULT_ROOM, format!("* {nick} joined #{DEFAULT_ROOM}"));
+                self.nick = Some(nick);
+                return Ok(true);
+            }
+            (None, _) => return self.reply("pick a nickname first, with /nick NAME").map(|_| true),
+            (Some(nick), command) => (nick.clone(), command),
+        };
+
+        match command {
+            Command::Nick(new_nick) => {
+                let renamed = self.shared.lobby.lock().unwrap().rename(&nick, &new_nick);
+                match renamed {
+                    Ok(room) => {
+                        self.publish(&room, format!("* {nick} is now known as {new_nick}"));
+                        self.nick = Some(new_nick);
+                    }
+                    Err(e) => self.reply(&e.to_string())?,
+                }
+            }
+            Command::Join(room) => {
+                let previous = self.shared.lobby.lock().unwrap().join(&nick, &room).unwrap();
+                if previous == room {
+                    return self.reply(&format!("you're already in #{room}")).map(|_| true);
+                }
+                self.subscribe(Some(&room));
+                self.publish(&previous, format!("* {nick} left #{previous}"));
+                self.publish(&room, format!("* {nick} joined #{room}"));
+            }
+            Command::Rooms => {
+                let rooms: Vec<String> = self
+                    .shared
+                    .lobby
+                    .lock()
+                    .unwrap()
+                    .rooms()
+                    .into_iter()
+                    .map(|(room, members)| format!("#{room} ({members})"))
+                    .collect();
+                self.reply(&format!("rooms: {}", rooms.join(", ")))?;
+            }
+            Command::Who => {
+                let line = {
+                    let lobby = self.shared.lobby.lock().unwrap();
+                    let room = lobby.room_of(&nick).unwrap();
+                    format!("in #{room}: {}", lobby.members(room).join(", "))
+                };
+                self.reply(&line)?;
+            }
+            Command::Say(text) => {
+                let room = self.shared.lobby.lock().unwrap().room_of(&nick).unwrap().to_string();
+                self.publish(&room, format!("<{nick}> {text}"));
+            }
+            Command::Help | Command::Quit => unreachable!("answered above"),
+        }
+        Ok(true)
+    }
+
+    fn leave(&mut self) {
+        self.subscribe(None);
+        if let Some(nick) = self.nick.take() {
+            if let Some(room) = self.shared.lobby.lock().unwrap().leave(&nick) {
+                self.publish(&room, format!("* {nick} left #{room}"));
+            }
+            common::info!("{nick} left");
+        }
+    }
+}
+
+fn serve_client(stream: Box<dyn Stream>, client: Client, shared: &Shared) -> io::Result<()> {
+    let writer = Arc::clone(&client.writer);
+    let mut session = Session { shared, writer, nick: None, subscription: Arc::new(Mutex::new(None)) };
+    session.reply("welcome! pick a nickname with /nick NAME, /help lists the commands")?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let forwarder = {
+        let (subscription, writer, done) = (Arc::clone(&session.subscription), Arc::clone(&session.writer), Arc::clone(&done));
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                let mut current = subscription.lock().unwrap();
+                let Some(room) = current.as_mut() else {
+                    drop(current);
+                    thread::sleep(POLL);
+                    continue;
+                };
+                let line = match room.recv_timeout(POLL) {
+                    Ok(line) => line,
+                    Err(RecvError::Lagged(missed)) => Some(format!("* you fell behind and missed {missed} lines")),
+                    // Only when the room's channel is gone, which it isn't while we're subscribed
+                    Err(RecvError::Closed) => None,
+                };
+                drop(current);
+                if line.is_some_and(|line| send(&writer, &line).is_err()) {
+                    break;
+                }
+            }
+        })
+    };
+
+    let timeout = shared.config.idle_timeout;
+    stream.set_read_timeout(Some(timeout))?;
+    let mut lines = BufReader::new(stream).lines();
+    let result = loop {
+        // The client closed the connection, or shutdown() did
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            // A read timeout is WouldBlock on Unix and TimedOut on Windows
+            Some(Err(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                client.goodbye(&format!("* disconnected after {timeout:?} without a word"));
+                break Ok(());
+            }
+            Some(Err(e)) => break Err(e),
+            None => break Ok(()),
+        };
+        let keep_going = match Command::parse(&line) {
+            Ok(command) => session.handle(command),
+            Err(e) => session.reply(&e.to_string()).map(|_| true),
+        };
+        match keep_going {
+            Ok(true) => {}
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    session.leave();
+    done.store(true, Ordering::Relaxed);
+    let _ = forwarder.join();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multithreaded_webserver::sim::SimNet;
+
+    struct Client {
+        lines: io::Lines<BufReader<Box<dyn Stream>>>,
+        stream: Box<dyn Stream>,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr) -> Client {
+            Client::connect_on(&Tcp, addr)
+        }
+
+        fn connect_on(network: &dyn Network, addr: SocketAddr) -> Client {
+            let stream = network.connect(addr, None).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut client = Client { lines: BufReader::new(stream.try_clone().unwrap()).lines(), stream };
+            assert!(client.line().starts_with("* welcome!"));
+            client
+        }
+
+        // Connects and picks a nickname
+        fn join(addr: SocketAddr, nick: &str) -> Client {
+            Client::join_on(&Tcp, addr, nick)
+        }
+
+        fn join_on(network: &dyn Network, addr: SocketAddr, nick: &str) -> Client {
+            let mut client = Client::connect_on(network, addr);
+            client.send(&format!("/nick {nick}"));
+            assert_eq!(format!("* {nick} joined #lobby"), client.line());
+            client
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.stream, "{line}").unwrap();
+        }
+
+        fn line(&mut self) -> String {
+            self.lines.next().unwrap().unwrap()
+        }
+
+        // Skips the lines before this one, a read timing out fails the test
+        fn wait_for(&mut self, expected: &str) {
+            while self.line() != expected {}
+        }
+
+        // None once the server closed the connection
+        fn next(&mut self) -> Option<String> {
+            self.lines.next().and_then(Result::ok)
+        }
+    }
+
+    fn start_on(network: Arc<dyn Network>, config: Config) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+        let server = ChatServer::bind_on(network, "127.0.0.1:0", config).unwrap();
+        let (addr, handle) = (server.local_addr(), server.shutdown_handle());
+        (addr, handle, thread::spawn(move || server.run()))
+    }
+
+    #[test]
+    fn rooms_keep_conversations_apart() {
+        let server = ChatServer::bind("127.0.0.1:0", Config::default()).unwrap();
+        let (addr, handle, shared) = (server.local_addr(), server.shutdown_handle(), Arc::clone(&server.shared));
+        let server = thread::spawn(move || server.run());
+        let mut alice = Client::connect(addr);
+        alice.send("hello?");
+        assert_eq!("* pick a nickname first, with /nick NAME", alice.line());
+        alice.send("/nick alice");
+        assert_eq!("* alice joined #lobby", alice.line());
+
+        let mut bob = Client::join(addr, "bob");
+        assert_eq!("* bob joined #lobby", alice.line());
+        let mut carol = Client::connect(addr);
+        carol.send("/nick bob");
+        assert_eq!("* bob is taken, pick another nickname", carol.line());
+
+        bob.send("hi alice");
+        assert_eq!("<bob> hi alice", alice.line());
+        assert_eq!("<bob> hi alice", bob.line());
+
+        bob.send("/join #rust");
+        assert_eq!("* bob joined #rust", bob.line());
+        assert_eq!("* bob left #lobby", alice.line());
+        alice.send("/rooms");
+        assert_eq!("* rooms: #lobby (1), #rust (1)", alice.line());
+
+        // Only the lobby hears this, bob's next line is his own message in #rust
+        alice.send("anyone here?");
+        assert_eq!("<alice> anyone here?", alice.line());
+        bob.send("/nick robert");
+        assert_eq!("* bob is now known as robert", bob.line());
+        bob.send("/who");
+        assert_eq!("* in #rust: robert", bob.line());
+
+        bob.send("/quit");
+        assert_eq!("* bye", bob.line());
+        assert_eq!(None, bob.next());
+        // #rust's channel went with its last member
+        let mut rooms: Vec<String> = shared.rooms.lock().unwrap().keys().cloned().collect();
+        rooms.sort();
+        assert_eq!(vec!["lobby"], rooms);
+
+        handle.shutdown();
+        assert_eq!(Some(String::from("* the server is shutting down, bye")), alice.next());
+        assert_eq!(None, alice.next());
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn idle_clients_are_kicked() {
+        let net = SimNet::new(1);
+        let config = Config { idle_timeout: Duration::from_millis(300), ..Config::default() };
+        let (addr, handle, server) = start_on(Arc::new(net.clone()), config);
+        let mut quiet = Client::join_on(&net, addr, "quiet");
+        let mut chatty = Client::join_on(&net, addr, "chatty");
+        assert_eq!("* chatty joined #lobby", quiet.line());
+
+        // Both wait for their next line on the simulated clock. Talking 200ms in starts chatty's wait over
+        net.wait_for_blocked_reads(2);
+        net.advance(Duration::from_millis(200));
+        chatty.send("/who");
+        chatty.wait_for("* in #lobby: chatty, quiet");
+
+        // So when quiet's 300ms are up, chatty still has 200ms to go
+        net.wait_for_blocked_reads(2);
+        net.advance(Duration::from_millis(100));
+        assert_eq!(Some(String::from("* disconnected after 300ms without a word")), quiet.next());
+        assert_eq!(None, quiet.next());
+        chatty.wait_for("* quiet left #lobby");
+        chatty.send("/who");
+        chatty.wait_for("* in #lobby: chatty");
+
+        handle.shutdown();
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn a_client_that_stopped_reading_doesnt_hold_up_shutdown() {
+        let net = SimNet::new(2);
+        let (addr, handle, server) = start_on(Arc::new(net.clone()), Config::default());
+        let mut stuck = Client::join_on(&net, addr, "stuck");
+        // In a room of its own, so that nothing is written to fine when it leaves
+        stuck.send("/join #alone");
+        assert_eq!("* stuck joined #alone", stuck.line());
+        let mut fine = Client::join_on(&net, addr, "fine");
+
+        // As if stuck's forwarder were in the middle of a write that never ends
+        let clients = handle.stopper.open();
+        let writers: Vec<&Writer> = clients.iter().map(|client| &client.writer).collect();
+        let busy = writers[0].lock().unwrap();
+        // And fine's forwarder done with the line it just wrote, which it may still be holding the writer for
+        while writers[1].try_lock().is_err() {
+            thread::yield_now();
+        }
+        handle.shutdown();
+        // No goodbye for it, the connection is closed all the same
+        assert_eq!(None, stuck.next());
+        assert_eq!(Some(String::from("* the server is shutting down, bye")), fine.next());
+        assert_eq!(None, fine.next());
+        drop(busy);
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,506 @@
+// A Small Command Line Argument Parser
+
+// Config::build started out reading args[1] and args[2], then grew flags that can go anywhere, options with values (--replace V or --replace=V)
+// and options whose value is optional (--backup or --backup=SUFFIX). Every new flag meant another arm in a hand written match,
+// and the help text, if there was one, would have to be kept in sync with that match by hand.
+
+// This module describes the command line once and derives both the parsing and the help text from that description:
+    // 1. A Parser is built with flag(), option() and positional(). Modifiers like short(), default() and default_missing()
+    //    apply to the argument declared just before them.
+    //    An option given more than once keeps every value, value() returns the last one and values() all of them, like grep -e A -e B.
+    // 2. parse() returns Matches, where values are looked up by name. get::<T>() parses a value into any type that implements FromStr,
+    //    so "--max abc" is reported as an ArgError naming the option, instead of a panic somewhere later.
+    // 3. A Parser can have subcommands, each of them a Parser of its own, like `cargo build` and `cargo test`.
+    // 4. -h and --help are always there. They come back as ArgError::Help carrying the help text, so the caller decides where to print it.
+    //    env_help() adds --print-env-help the same way, with the listing of common::env_config instead of the help text.
+
+// minigrep's Config::build, the guessing game's options and the command lines of the servers are declared with it.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    UnknownArgument(String),
+    // An option that needs a value was the last argument
+    MissingValue(String),
+    // A positional argument without a default wasn't given
+    MissingArgument(String),
+    // More positional arguments than were declared
+    UnexpectedArgument(String),
+    MissingSubcommand,
+    InvalidValue { name: String, value: String, reason: String },
+    // For checks across arguments that the parser can't know about, made by the caller after parsing
+    Invalid(String),
+    // Not a failure: -h or --help was given, and this is the help text to show
+    Help(String),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgError::UnknownArgument(arg) => write!(f, "unknown argument '{arg}'"),
+            ArgError::MissingValue(name) => write!(f, "--{name} needs a value"),
+            ArgError::MissingArgument(name) => write!(f, "missing argument <{}>", name.to_uppercase()),
+            ArgError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{arg}'"),
+            ArgError::MissingSubcommand => write!(f, "missing command"),
+            ArgError::InvalidValue { name, value, reason } => write!(f, "invalid value '{value}' for {name}: {reason}"),
+            ArgError::Invalid(message) => write!(f, "{message}"),
+            ArgError::Help(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Flag,
+    Option,
+    Positional,
+}
+
+#[derive(Debug, Clone)]
+struct Arg {
+    name: String,
+    kind: Kind,
+    short: Option<char>,
+    value_name: String,
+    help: String,
+    default: Option<String>,
+    // The value of an option given without =value, which makes its value optional
+    default_missing: Option<String>,
+    // A positional that may be left out without a default
+    optional: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parser {
+    name: String,
+    about: String,
+    args: Vec<Arg>,
+    subcommands: Vec<Parser>,
+    // What --print-env-help prints, it's only an option when there's something to print
+    env_help: Option<String>,
+}
+
+impl Parser {
+    pub fn new(name: &str, about: &str) -> Parser {
+        Parser { name: name.to_string(), about: about.to_string(), args: Vec::new(), subcommands: Vec::new(), env_help: None }
+    }
+
+    // --name, true when present
+    pub fn flag(self, name: &str, help: &str) -> Parser {
+        self.arg(name, Kind::Flag, "", help)
+    }
+
+    // --name VALUE or --name=VALUE
+    pub fn option(self, name: &str, value_name: &str, help: &str) -> Parser {
+        self.arg(name, Kind::Option, value_name, help)
+    }
+
+    // Filled in order from the arguments that aren't flags or options. Required unless it has a default.
+    pub fn positional(self, name: &str, help: &str) -> Parser {
+        let value_name = name.to_uppercase();
+        self.arg(name, Kind::Positional, &value_name, help)
+    }
+
+    pub fn short(mut self, short: char) -> Parser {
+        self.last().short = Some(short);
+        self
+    }
+
+    pub fn default(mut self, value: &str) -> Parser {
+        self.last().default = Some(value.to_string());
+        self
+    }
+
+    // Only for options: --name alone means this value, and the next argument is left alone
+    pub fn default_missing(mut self, value: &str) -> Parser {
+        let arg = self.last();
+        assert_eq!(Kind::Option, arg.kind, "default_missing only applies to options");
+        arg.default_missing = Some(value.to_string());
+        self
+    }
+
+    // A positional that can be left out, value() is None then. Only the last positionals can be optional, they're filled in order.
+    pub fn optional(mut self) -> Parser {
+        let arg = self.last();
+        assert_eq!(Kind::Positional, arg.kind, "only positionals can be optional, options already are");
+        arg.optional = true;
+        self
+    }
+
+    pub fn subcommand(mut self, subcommand: Parser) -> Parser {
+        self.subcommands.push(subcommand);
+        self
+    }
+
+    // --print-env-help returns ArgError::Help with this listing, before the required arguments are looked for, like --help
+    pub fn env_help(mut self, listing: &str) -> Parser {
+        self.env_help = Some(listing.to_string());
+        self
+    }
+
+    fn arg(mut self, name: &str, kind: Kind, value_name: &str, help: &str) -> Parser {
+        assert!(self.find(name).is_none(), "argument {name} declared twice");
+        self.args.push(Arg {
+            name: name.to_string(),
+            kind,
+            short: None,
+            value_name: value_name.to_string(),
+            help: help.to_string(),
+            default: None,
+            default_missing: None,
+            optional: false,
+        });
+        self
+    }
+
+    fn last(&mut self) -> &mut Arg {
+        self.args.last_mut().expect("declare an argument before modifying it")
+    }
+
+    fn find(&self, name: &str) -> Option<&Arg> {
+        self.args.iter().find(|arg| arg.kind != Kind::Positional && arg.name == name)
+    }
+
+    fn find_short(&self, short: char) -> Option<&Arg> {
+        self.args.iter().find(|arg| arg.short == Some(short))
+    }
+
+    // Parses the arguments after the program name, so usually parse(env::args().skip(1))
+    pub fn parse<I, S>(&self, args: I) -> Result<Matches, ArgError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let args: Vec<String> = args.into_iter().map(Into::into).collect();
+        self.parse_from(&args)
+    }
+
+    fn parse_from(&self, args: &[String]) -> Result<Matches, ArgError> {
+        let mut matches = Matches::default();
+        let mut positionals = self.args.iter().filter(|arg| arg.kind == Kind::Positional);
+        let mut only_positionals = false;
+        let mut i = 0;
+
+        while i < args.len() {
+            let raw = &args[i];
+            i += 1;
+
+            if !only_positionals && (raw == "-h" || raw == "--help") {
+                return Err(ArgError::Help(self.help()));
+            }
+            if let Some(listing) = self.env_help.as_ref().filter(|_| !only_positionals && raw == "--print-env-help") {
+                return Err(ArgError::Help(listing.clone()));
+            }
+
+            let (arg, inline_value) = if only_positionals || raw == "-" || !raw.starts_with('-') {
+                // The first word that names a subcommand hands the rest of the arguments over to it
+                if let Some(sub) = self.subcommands.iter().find(|sub| !only_positionals && sub.name == *raw) {
+                    let sub_matches = sub.parse_from(&args[i..])?;
+                    matches.subcommand = Some((sub.name.clone(), Box::new(sub_matches)));
+                    break;
+                }
+                let arg = positionals.next().ok_or_else(|| ArgError::UnexpectedArgument(raw.clone()))?;
+                matches.values.insert(arg.name.clone(), vec![raw.clone()]);
+                continue;
+            } else if raw == "--" {
+                only_positionals = true;
+                continue;
+            } else if let Some(long) = raw.strip_prefix("--") {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                (self.find(name).ok_or_else(|| ArgError::UnknownArgument(raw.clone()))?, value)
+            } else {
+                let mut chars = raw[1..].chars();
+                let arg = match (chars.next(), chars.next()) {
+                    (Some(short), None) => self.find_short(short),
+                    _ => None,
+                };
+                (arg.ok_or_else(|| ArgError::UnknownArgument(raw.clone()))?, None)
+            };
+
+            match arg.kind {
+                Kind::Flag if inline_value.is_some() => return Err(ArgError::UnknownArgument(raw.clone())),
+                Kind::Flag => {
+                    matches.flags.push(arg.name.clone());
+                }
+                _ => {
+                    let value = match (inline_value, &arg.default_missing) {
+                        (Some(value), _) => value,
+                        (None, Some(missing)) => missing.clone(),
+                        (None, None) => {
+                            let value = args.get(i).ok_or_else(|| ArgError::MissingValue(arg.name.clone()))?;
+                            i += 1;
+                            value.clone()
+                        }
+                    };
+                    // Given twice, both are kept and value() returns the last one
+                    matches.values.entry(arg.name.clone()).or_default().push(value);
+                }
+            }
+        }
+
+        for arg in &self.args {
+            if matches.values.contains_key(&arg.name) || arg.kind == Kind::Flag {
+                continue;
+            }
+            match (&arg.default, arg.kind) {
+                (Some(default), _) => {
+                    matches.values.insert(arg.name.clone(), vec![default.clone()]);
+                }
+                (None, Kind::Positional) if !arg.optional => return Err(ArgError::MissingArgument(arg.name.clone())),
+                (None, _) => {}
+            }
+        }
+
+        if !self.subcommands.is_empty() && matches.subcommand.is_none() {
+            return Err(ArgError::MissingSubcommand);
+        }
+        Ok(matches)
+    }
+
+    // Help Text
+
+    pub fn help(&self) -> String {
+        // There's always at least --help, so there are always options
+        let mut help = format!("{} - {}\n\nUsage: {} [OPTIONS]", self.name, self.about, self.name);
+        for arg in self.args.iter().filter(|arg| arg.kind == Kind::Positional) {
+            if arg.default.is_some() || arg.optional {
+                help.push_str(&format!(" [{}]", arg.value_name));
+            } else {
+                help.push_str(&format!(" <{}>", arg.value_name));
+            }
+        }
+        if !self.subcommands.is_empty() {
+            help.push_str(" <COMMAND>");
+        }
+        help.push('\n');
+
+        let positionals: Vec<(String, String)> = self
+            .args
+            .iter()
+            .filter(|arg| arg.kind == Kind::Positional)
+            .map(|arg| (format!("<{}>", arg.value_name), with_default(&arg.help, &arg.default)))
+            .collect();
+        push_section(&mut help, "Arguments", &positionals);
+
+        let commands: Vec<(String, String)> =
+            self.subcommands.iter().map(|sub| (sub.name.clone(), sub.about.clone())).collect();
+        push_section(&mut help, "Commands", &commands);
+
+        let mut options: Vec<(String, String)> = self
+            .args
+            .iter()
+            .filter(|arg| arg.kind != Kind::Positional)
+            .map(|arg| {
+                let short = match arg.short {
+                    Some(short) => format!("-{short}, "),
+                    None => String::from("    "),
+                };
+                let value = match (arg.kind, &arg.default_missing) {
+                    (Kind::Flag, _) => String::new(),
+                    (_, Some(_)) => format!("[=<{}>]", arg.value_name),
+                    (_, None) => format!(" <{}>", arg.value_name),
+                };
+                (format!("{short}--{}{value}", arg.name), with_default(&arg.help, &arg.default))
+            })
+            .collect();
+        if self.env_help.is_some() {
+            options.push((String::from("    --print-env-help"), String::from("Print the environment variables it reads")));
+        }
+        options.push((String::from("-h, --help"), String::from("Print this help")));
+        push_section(&mut help, "Options", &options);
+
+        help
+    }
+}
+
+fn with_default(help: &str, default: &Option<String>) -> String {
+    match default {
+        Some(default) => format!("{help} [default: {default}]"),
+        None => help.to_string(),
+    }
+}
+
+// Lines up the descriptions in a column after the longest name
+fn push_section(help: &mut String, title: &str, rows: &[(String, String)]) {
+    if rows.is_empty() {
+        return;
+    }
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    help.push_str(&format!("\n{title}:\n"));
+    for (name, description) in rows {
+        help.push_str(&format!("  {name:width$}  {description}\n"));
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Matches {
+    flags: Vec<String>,
+    values: HashMap<String, Vec<String>>,
+    subcommand: Option<(String, Box<Matches>)>,
+}
+
+impl Matches {
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    // How many times a flag was given, for things like -v -v
+    pub fn occurrences(&self, name: &str) -> usize {
+        self.flags.iter().filter(|flag| *flag == name).count()
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).and_then(|values| values.last()).map(String::as_str)
+    }
+
+    // Every value of an option, in the order they were given. Empty when it wasn't given and has no default.
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.values.get(name).map_or(Vec::new(), |values| values.iter().map(String::as_str).collect())
+    }
+
+    pub fn get<T>(&self, name: &str) -> Result<Option<T>, ArgError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.value(name) {
+            None => Ok(None),
+            Some(value) => value.parse().map(Some).map_err(|e: T::Err| ArgError::InvalidValue {
+                name: name.to_string(),
+                value: value.to_string(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    pub fn subcommand(&self) -> Option<(&str, &Matches)> {
+        self.subcommand.as_ref().map(|(name, matches)| (name.as_str(), matches.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grep() -> Parser {
+        Parser::new("grep", "search files")
+            .flag("count", "Only print the number of matches")
+            .short('c')
+            .option("max", "N", "Stop after N matches")
+            .short('m')
+            .option("backup", "SUFFIX", "Keep a copy of every changed file")
+            .default_missing(".bak")
+            .option("color", "WHEN", "When to color the output")
+            .default("auto")
+            .positional("query", "What to look for")
+            .positional("path", "Where to look")
+            .default(".")
+    }
+
+    #[test]
+    fn flags_options_and_positionals_in_any_order() {
+        let matches = grep().parse(["-m", "3", "frog", "--count", "--backup", "poem.txt", "--color=never"]).unwrap();
+        assert!(matches.flag("count"));
+        assert_eq!(Some(3), matches.get::<u32>("max").unwrap());
+        assert_eq!(Some(".bak"), matches.value("backup"));
+        assert_eq!(Some("never"), matches.value("color"));
+        assert_eq!(Some("frog"), matches.value("query"));
+        assert_eq!(Some("poem.txt"), matches.value("path"));
+    }
+
+    #[test]
+    fn defaults_and_missing_values() {
+        let matches = grep().parse(["frog"]).unwrap();
+        assert!(!matches.flag("count"));
+        assert_eq!(None, matches.value("max"));
+        assert_eq!(None, matches.value("backup"));
+        assert_eq!(Some("auto"), matches.value("color"));
+        assert_eq!(Some("."), matches.value("path"));
+
+        assert_eq!(Some(".orig"), grep().parse(["--backup=.orig", "frog"]).unwrap().value("backup"));
+        // "--" ends the options, so a query may start with a dash
+        assert_eq!(Some("-x"), grep().parse(["--", "-x"]).unwrap().value("query"));
+    }
+
+    #[test]
+    fn repeated_options_and_optional_positionals() {
+        let matches = grep().parse(["-m", "1", "frog", "--max=2"]).unwrap();
+        assert_eq!(Some("2"), matches.value("max"));
+        assert_eq!(vec!["1", "2"], matches.values("max"));
+        assert_eq!(vec!["auto"], matches.values("color"));
+        assert!(matches.values("backup").is_empty());
+
+        let parser = Parser::new("cp", "copy").positional("from", "Source").positional("to", "Destination").optional();
+        assert_eq!(None, parser.parse(["a"]).unwrap().value("to"));
+        assert_eq!(Some("b"), parser.parse(["a", "b"]).unwrap().value("to"));
+        assert!(parser.help().contains("Usage: cp [OPTIONS] <FROM> [TO]"));
+    }
+
+    #[test]
+    fn typed_errors() {
+        let parse = |args: &[&str]| grep().parse(args.iter().copied()).unwrap_err();
+        assert_eq!(ArgError::MissingArgument(String::from("query")), parse(&[]));
+        assert_eq!(ArgError::MissingValue(String::from("max")), parse(&["frog", "--max"]));
+        assert_eq!(ArgError::UnknownArgument(String::from("--colour")), parse(&["frog", "--colour"]));
+        assert_eq!(ArgError::UnknownArgument(String::from("--count=yes")), parse(&["frog", "--count=yes"]));
+        assert_eq!(ArgError::UnexpectedArgument(String::from("extra")), parse(&["a", "b", "extra"]));
+
+        let err = grep().parse(["frog", "-m", "lots"]).unwrap().get::<u32>("max").unwrap_err();
+        assert_eq!("invalid value 'lots' for max: invalid digit found in string", err.to_string());
+    }
+
+    #[test]
+    fn help_lists_every_argument() {
+        let help = match grep().parse(["frog", "--help"]) {
+            Err(ArgError::Help(help)) => help,
+            other => panic!("expected help, got {other:?}"),
+        };
+        let expected = "\
+grep - search files
+
+Usage: grep [OPTIONS] <QUERY> [PATH]
+
+Arguments:
+  <QUERY>  What to look for
+  <PATH>   Where to look [default: .]
+
+Options:
+  -c, --count              Only print the number of matches
+  -m, --max <N>            Stop after N matches
+      --backup[=<SUFFIX>]  Keep a copy of every changed file
+      --color <WHEN>       When to color the output [default: auto]
+  -h, --help               Print this help
+";
+        assert_eq!(expected, help);
+
+        let listing = "Environment variables:\n  IGNORE_CASE=1\n";
+        let with_env = grep().env_help(listing);
+        assert!(with_env.help().contains("      --print-env-help     Print the environment variables it reads\n"));
+        assert_eq!(Err(ArgError::Help(listing.to_string())), with_env.parse(["--print-env-help"]));
+        assert!(matches!(grep().parse(["--print-env-help"]), Err(ArgError::UnknownArgument(_))));
+    }
+
+    #[test]
+    fn subcommands() {
+        let kv = Parser::new("kv", "a key-value store")
+            .option("data", "DIR", "Where the data lives")
+            .default("data")
+            .subcommand(Parser::new("get", "Print a value").positional("key", "The key to look up"))
+            .subcommand(Parser::new("set", "Store a value").positional("key", "The key").positional("value", "The value"));
+
+        let matches = kv.parse(["--data", "/tmp/kv", "set", "name", "ferris"]).unwrap();
+        assert_eq!(Some("/tmp/kv"), matches.value("data"));
+        let (name, set) = matches.subcommand().unwrap();
+        assert_eq!(("set", Some("name"), Some("ferris")), (name, set.value("key"), set.value("value")));
+
+        assert_eq!(Err(ArgError::MissingSubcommand), kv.parse(["--data", "x"]));
+        assert_eq!(Err(ArgError::MissingArgument(String::from("key"))), kv.parse(["get"]));
+        assert!(matches!(kv.parse(["get", "--help"]), Err(ArgError::Help(help)) if help.starts_with("get - Print a value")));
+    }
+}
@@ -0,0 +1,247 @@
+// Byte Search
+
+// Looking for a byte, or a run of bytes, is most of what some code does: minigrep looks for the query in every file and for the
+// line breaks around every match, the webserver for the end of every line of a request head and for the boundaries in a
+// multipart body. haystack.iter().position(..) looks at one byte at a time. memchr here looks at 8 at once, in a u64. That's
+// SWAR, "SIMD within a register":
+    // 1. The byte we're looking for is repeated in all 8 bytes of a u64 and XORed with 8 bytes of the haystack. The bytes that
+    //    matched are now 0.
+    // 2. A few arithmetic operations set the top bit of every byte that is 0, and only of those (see zero_bytes).
+    // 3. When the result isn't 0, its trailing zeros divided by 8 are the index of the first match in the chunk, its leading
+    //    zeros that of the last one.
+// The chunks are read with u64::from_le_bytes, so the first byte of a chunk is the lowest byte of the u64 on any machine.
+// What's left after the last whole chunk is searched one byte at a time.
+
+// memmem finds a whole needle. It looks for the needle's rarest byte with memchr (a guess, see frequency), and only compares the
+// needle where that byte is. In text that skips most of the haystack 8 bytes at a time. A needle like "aab" in a haystack of
+// "aaaa..." has nothing rare to look for, though, and then it's a comparison at every byte, O(haystack * needle). The memchr
+// crate, which minigrep's memchr backend uses, switches to the Two-Way algorithm for those.
+
+// With the simd feature memchr looks at 32 bytes at once, with the portable SIMD vectors of std::simd. The compiler turns those
+// into SSE2 or AVX2 instructions on x86, NEON on ARM. std::simd is still unstable, so the feature only builds on nightly:
+    // $ cargo +nightly test --features simd
+
+// Looking for several needles at once is Aho-Corasick's job (minigrep/src/aho_corasick.rs).
+
+const LO7: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+
+// The top bit of every byte of x that is 0. The sum can't carry into the next byte, (b & 0x7f) + 0x7f is at most 0xfe, so
+// unlike the shorter (x - 0x0101..) & !x & 0x8080.. it never marks a byte that isn't 0, before or after a real one.
+fn zero_bytes(x: u64) -> u64 {
+    !(((x & LO7) + LO7) | x | LO7)
+}
+
+fn repeat(byte: u8) -> u64 {
+    u64::from_ne_bytes([byte; 8])
+}
+
+fn load(chunk: &[u8]) -> u64 {
+    u64::from_le_bytes(chunk.try_into().expect("a chunk of 8 bytes"))
+}
+
+// The index of the first needle in haystack
+#[cfg(not(feature = "simd"))]
+pub fn memchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    memchr_swar(needle, haystack)
+}
+
+#[cfg(feature = "simd")]
+pub fn memchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    memchr_simd(needle, haystack)
+}
+
+// memchr 8 bytes at a time, whichever of the two memchr is. Public for the benchmarks
+pub fn memchr_swar(needle: u8, haystack: &[u8]) -> Option<usize> {
+    let pattern = repeat(needle);
+    // Two u64s a round, the loop itself costs about as much as checking one
+    let mut chunks = haystack.chunks_exact(16);
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        let (low, high) = (zero_bytes(load(&chunk[..8]) ^ pattern), zero_bytes(load(&chunk[8..]) ^ pattern));
+        if low | high != 0 {
+            let within = if low != 0 { low.trailing_zeros() / 8 } else { 8 + high.trailing_zeros() / 8 };
+            return Some(i * 16 + within as usize);
+        }
+    }
+    let rest = chunks.remainder();
+    let start = haystack.len() - rest.len();
+    rest.iter().position(|&b| b == needle).map(|i| start + i)
+}
+
+#[cfg(feature = "simd")]
+fn memchr_simd(needle: u8, haystack: &[u8]) -> Option<usize> {
+    use std::simd::{cmp::SimdPartialEq, u8x32};
+
+    let pattern = u8x32::splat(needle);
+    let mut chunks = haystack.chunks_exact(32);
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        // A bit for every lane that matched, lane 0 is the lowest bit
+        let matches = u8x32::from_slice(chunk).simd_eq(pattern).to_bitmask();
+        if matches != 0 {
+            return Some(i * 32 + matches.trailing_zeros() as usize);
+        }
+    }
+    let rest = chunks.remainder();
+    let start = haystack.len() - rest.len();
+    memchr_swar(needle, rest).map(|i| start + i)
+}
+
+// The index of the last needle in haystack
+pub fn memrchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    let pattern = repeat(needle);
+    let mut chunks = haystack.rchunks_exact(8);
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        let found = zero_bytes(load(chunk) ^ pattern);
+        if found != 0 {
+            let start = haystack.len() - (i + 1) * 8;
+            return Some(start + 7 - (found.leading_zeros() / 8) as usize);
+        }
+    }
+    // The bytes that didn't fill a chunk are at the front
+    chunks.remainder().iter().rposition(|&b| b == needle)
+}
+
+// The index of the first needle in haystack. Some(0) for an empty needle, which is found everywhere
+pub fn memmem(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    find_from(haystack, needle, rarest(needle))
+}
+
+// memmem for one needle in many haystacks, the rarest byte is only picked once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finder {
+    needle: Vec<u8>,
+    rare: usize,
+}
+
+impl Finder {
+    pub fn new(needle: &[u8]) -> Finder {
+        Finder { needle: needle.to_vec(), rare: rarest(needle) }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        find_from(haystack, &self.needle, self.rare)
+    }
+}
+
+// How often a byte turns up in text and code, roughly, from 0 (never) to 255 (all the time). Only the order matters
+fn frequency(byte: u8) -> u8 {
+    match byte {
+        b' ' => 255,
+        b'e' | b't' | b'a' | b'o' | b'i' | b'n' | b's' | b'r' | b'h' | b'l' => 200,
+        b'a'..=b'z' => 150,
+        b'\n' | b'\r' | b'\t' | b',' | b'.' | b'_' | b':' | b';' | b'(' | b')' | b'/' | b'-' | b'=' | b'"' => 120,
+        b'A'..=b'Z' | b'0'..=b'9' => 100,
+        _ => 50,
+    }
+}
+
+// The index of the needle's rarest byte, 0 for an empty needle
+fn rarest(needle: &[u8]) -> usize {
+    (0..needle.len()).min_by_key(|&i| frequency(needle[i])).unwrap_or(0)
+}
+
+fn find_from(haystack: &[u8], needle: &[u8], rare: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    let last_start = haystack.len().checked_sub(needle.len())?;
+    // Where the needle would start, if it's at the next rare byte
+    let mut start = 0;
+    while start <= last_start {
+        let at = start + memchr(needle[rare], &haystack[start + rare..=last_start + rare])?;
+        if &haystack[at..at + needle.len()] == needle {
+            return Some(at);
+        }
+        start = at + 1;
+    }
+    None
+}
+
+// HTTP
+
+// The index of the first "\r\n" in haystack, the '\r'
+pub fn find_crlf(haystack: &[u8]) -> Option<usize> {
+    let mut from = 1;
+    while from <= haystack.len() {
+        let newline = from + memchr(b'\n', &haystack[from..])?;
+        if haystack[newline - 1] == b'\r' {
+            return Some(newline - 1);
+        }
+        from = newline + 1;
+    }
+    None
+}
+
+// Where the body starts: just after the empty line that ends a request or response head. Lines may end in "\r\n" or only in
+// "\n", so that's after the first "\n\r\n" or "\n\n". None when haystack doesn't hold the whole head yet
+pub fn find_header_end(haystack: &[u8]) -> Option<usize> {
+    let mut from = 0;
+    while let Some(newline) = memchr(b'\n', &haystack[from..]).map(|i| from + i) {
+        match haystack.get(newline + 1..) {
+            Some([b'\n', ..]) => return Some(newline + 2),
+            Some([b'\r', b'\n', ..]) => return Some(newline + 3),
+            _ => from = newline + 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_bytes_anywhere_in_a_chunk() {
+        // Every length around the chunk sizes, with the byte at every place, alone and twice
+        for len in 0..70 {
+            let mut haystack = vec![b'.'; len];
+            assert_eq!((None, None), (memchr(b'x', &haystack), memrchr(b'x', &haystack)));
+            for at in 0..len {
+                haystack[at] = b'x';
+                assert_eq!((Some(at), Some(at)), (memchr(b'x', &haystack), memrchr(b'x', &haystack)), "{at} of {len}");
+                assert_eq!(Some(at), memchr_swar(b'x', &haystack));
+                for other in at + 1..len {
+                    haystack[other] = b'x';
+                    assert_eq!((Some(at), Some(other)), (memchr(b'x', &haystack), memrchr(b'x', &haystack)));
+                    haystack[other] = b'.';
+                }
+                haystack[at] = b'.';
+            }
+        }
+
+        // Bytes one apart from the needle, and with the top bit set, where a sloppier zero_bytes would see a match
+        let tricky: Vec<u8> = (0..=255).filter(|&b| b != 0x80).collect();
+        assert_eq!((None, None), (memchr(0x80, &tricky), memrchr(0x80, &tricky)));
+        assert_eq!((Some(1), Some(1)), (memchr(1, &[0, 1, 0, 2]), memrchr(1, &[0, 1, 0, 2])));
+    }
+
+    #[test]
+    fn finds_needles() {
+        let haystack = b"GET / HTTP/1.1\r\nHost: x\r\nCookie: a=aab; b=aaab\r\n\r\nbody aaaab";
+        let naive = |needle: &[u8]| haystack.windows(needle.len()).position(|window| window == needle);
+        let needles: [&[u8]; 9] = [b"Host", b"aab", b"aaaab", b"\r\n\r\n", b"b=", b"body aaaab", b"x", b"nowhere", b"ab; c"];
+        for needle in needles {
+            assert_eq!(naive(needle), memmem(haystack, needle), "{:?}", String::from_utf8_lossy(needle));
+            assert_eq!(naive(needle), Finder::new(needle).find(haystack));
+        }
+        assert_eq!(Some(0), memmem(haystack, b""));
+        assert_eq!(None, memmem(b"ab", b"abc"));
+        // The rarest byte is the 'H', the only capital
+        assert_eq!(0, Finder::new(b"Host").rare);
+    }
+
+    #[test]
+    fn finds_the_ends_of_lines_and_heads() {
+        assert_eq!(Some(5), find_crlf(b"GET\r/\r\nHost"));
+        assert_eq!((None, None), (find_crlf(b"\n\r\r"), find_crlf(b"")));
+
+        let head = b"GET / HTTP/1.1\r\nHost: x\r\n\r\nbody\r\n\r\n";
+        assert_eq!(Some(27), find_header_end(head));
+        assert_eq!(b"body", &head[27..31]);
+        assert_eq!(Some(12), find_header_end(b"GET / HTTP\n\nbody"));
+        assert_eq!(None, find_header_end(b"GET / HTTP/1.1\r\nHost: x\r\n\r"));
+    }
+}
@@ -0,0 +1,140 @@
+// Clocks
+
+// Code that calls Instant::now() itself is hard to test: to see what the progress bar does after 100 ms, a test has to sleep
+// for 100 ms, and on a busy machine it may sleep a lot longer. Asking a Clock for the time instead lets a test decide what time it is:
+    // 1. SystemClock is the real time, it's what everything uses unless told otherwise.
+    // 2. FakeClock only moves when advance() is called, so a test can skip an hour in no time, and the result is the same on every run.
+// Both kinds of time are there: Instant for measuring how long something took, SystemTime for dates like a file's modification time.
+// Waiting is the other half. Nothing wakes up a thread when a FakeClock is advanced, so a wait for an instant of some Clock is
+// cut into slices: wait_slice says how long to wait in real time before looking at the clock again, all of it on the SystemClock,
+// at most POLL on a FakeClock. A Condvar or a channel waits for a slice at a time too, and sleep_until is the loop for a sleep.
+
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+// How often a wait on a FakeClock looks whether the clock was advanced past its end
+pub const POLL: Duration = Duration::from_millis(10);
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn system_now(&self) -> SystemTime;
+
+    // How long to wait in real time before looking at the clock again, zero once it's at until
+    fn wait_slice(&self, until: Instant) -> Duration {
+        until.saturating_duration_since(self.now())
+    }
+
+    fn sleep_until(&self, until: Instant) {
+        loop {
+            let slice = self.wait_slice(until);
+            if slice.is_zero() {
+                break;
+            }
+            thread::sleep(slice);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// 2023-11-14 22:13:20 UTC, a date that's the same in every test run
+pub const FAKE_EPOCH: Duration = Duration::from_secs(1_700_000_000);
+
+#[derive(Debug)]
+pub struct FakeClock {
+    // An Instant can't be made up, only measured, so the fake one is the real time the clock was created plus the offset
+    start: Instant,
+    start_system: SystemTime,
+    offset: Mutex<Duration>,
+}
+
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock::at(SystemTime::UNIX_EPOCH + FAKE_EPOCH)
+    }
+
+    pub fn at(system_time: SystemTime) -> FakeClock {
+        FakeClock { start: Instant::now(), start_system: system_time, offset: Mutex::new(Duration::ZERO) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.offset.lock().unwrap() += by;
+    }
+
+    // How far the clock was advanced since it was created
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.lock().unwrap()
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        FakeClock::new()
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.start_system + self.elapsed()
+    }
+
+    fn wait_slice(&self, until: Instant) -> Duration {
+        until.saturating_duration_since(self.now()).min(POLL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_time_only_moves_when_told() {
+        let clock = FakeClock::new();
+        let (before, before_system) = (clock.now(), clock.system_now());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(before, clock.now());
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(Duration::from_secs(3600), clock.now() - before);
+        assert_eq!(SystemTime::UNIX_EPOCH + FAKE_EPOCH + Duration::from_secs(3600), clock.system_now());
+        assert!(clock.system_now() > before_system);
+
+        let real = SystemClock;
+        assert!(real.now() <= SystemClock.now());
+    }
+
+    #[test]
+    fn sleeps_end_when_the_clock_gets_there() {
+        let clock = std::sync::Arc::new(FakeClock::new());
+        let until = clock.now() + Duration::from_secs(60);
+        assert_eq!(POLL, clock.wait_slice(until));
+        // The real time needs no polling, it waits the whole way
+        assert!(SystemClock.wait_slice(SystemClock.now() + Duration::from_secs(60)) > Duration::from_secs(59));
+
+        let sleeper = {
+            let clock = std::sync::Arc::clone(&clock);
+            thread::spawn(move || clock.sleep_until(until))
+        };
+        clock.advance(Duration::from_secs(60));
+        sleeper.join().unwrap();
+        assert_eq!(Duration::ZERO, clock.wait_slice(until));
+    }
+}
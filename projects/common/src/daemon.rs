@@ -0,0 +1,366 @@
+// Daemons and PID Files
+
+// A daemon is a server that runs in the background: started from a terminal, it gives the terminal back at once and keeps
+// running after the terminal is closed. On Unix that takes a few steps, each undoing a tie to where it was started:
+    // 1. fork(), and the parent exits. The shell sees its command finish, and the child isn't a process group leader.
+    // 2. setsid() makes the child the leader of a session of its own, without a controlling terminal. Closing the terminal
+    //    doesn't send it SIGHUP anymore.
+    // 3. fork() again, so that the daemon isn't a session leader and can never get a controlling terminal back.
+    // 4. umask, so the files it creates aren't writable by everyone, and chdir to /, so it doesn't keep a directory busy.
+    // 5. stdin, stdout and stderr, which were the terminal, become /dev/null or the log files it was given.
+// The original process waits on a pipe until the daemon is running or failed, and exits with 0 or prints why and exits with 1.
+// So a daemon that can't start still says so in the terminal, not in a log nobody is watching.
+
+// fork() only copies the thread that calls it. Any other thread, and a lock one of them was holding, is gone in the child,
+// so Daemon::start has to come first in main, before a thread or a signal handler (signals.rs) is started.
+
+// A PID file holds the daemon's process id, for scripts to find it and for a second copy to notice the first one. A file left
+// behind by a daemon that was killed is stale: the process it names doesn't exist anymore, and the file is replaced.
+// kill(pid, 0) sends no signal, and only says whether the process exists. Dropping the PidFile removes it, so the last of
+// the Shutdown hooks is usually `drop(pid_file)`.
+
+// fork, setsid and the rest are functions of the C library, through FFI like term.rs does. Elsewhere than Unix, start() is
+// an error, and a PidFile can't tell whether a process is alive, so it never thinks one is.
+
+use std::{
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+#[derive(Debug)]
+pub enum DaemonError {
+    // Another copy is running, with this process id
+    AlreadyRunning(u32),
+    Io(io::Error),
+    // Why the daemon process didn't start, as it told the process that started it
+    Failed(String),
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DaemonError::AlreadyRunning(pid) => write!(f, "already running with process id {pid}"),
+            DaemonError::Io(e) => write!(f, "{e}"),
+            DaemonError::Failed(why) => write!(f, "the daemon didn't start: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+impl From<io::Error> for DaemonError {
+    fn from(e: io::Error) -> DaemonError {
+        DaemonError::Io(e)
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{fs::File, io, os::fd::FromRawFd};
+
+    #[cfg(target_os = "macos")]
+    type Mode = u16;
+    #[cfg(not(target_os = "macos"))]
+    type Mode = u32;
+
+    extern "C" {
+        fn fork() -> i32;
+        fn setsid() -> i32;
+        fn umask(mask: Mode) -> Mode;
+        fn dup2(old: i32, new: i32) -> i32;
+        fn kill(pid: i32, signal: i32) -> i32;
+        fn pipe(fds: *mut i32) -> i32;
+        fn _exit(status: i32) -> !;
+    }
+
+    const EPERM: i32 = 1;
+
+    pub enum Fork {
+        Parent,
+        Child,
+    }
+
+    pub fn fork_process() -> io::Result<Fork> {
+        // SAFETY: the child only goes on in the thread that called fork, which is why start() has to come before any other
+        match unsafe { fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(Fork::Child),
+            _ => Ok(Fork::Parent),
+        }
+    }
+
+    pub fn new_session() -> io::Result<()> {
+        // SAFETY: no pointers, it can only fail
+        match unsafe { setsid() } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn set_umask(mask: u32) {
+        // SAFETY: no pointers, and it can't fail
+        unsafe { umask(mask as Mode) };
+    }
+
+    // Makes fd the same open file as file, for stdin, stdout and stderr
+    pub fn redirect(file: &File, fd: i32) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+        // SAFETY: both are open file descriptors, dup2 closes fd first
+        match unsafe { dup2(file.as_raw_fd(), fd) } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    // Exists, whether or not we may signal it
+    pub fn alive(pid: u32) -> bool {
+        let Ok(pid) = i32::try_from(pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks, nothing is sent
+        unsafe { kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(EPERM) }
+    }
+
+    pub fn pipe_files() -> io::Result<(File, File)> {
+        let mut fds = [0; 2];
+        // SAFETY: pipe writes two new file descriptors to the array, and nothing else owns them
+        unsafe {
+            if pipe(fds.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])))
+        }
+    }
+
+    // Ends a forked process without running anything the parent set up to run at exit
+    pub fn exit_now(status: i32) -> ! {
+        // SAFETY: _exit doesn't return
+        unsafe { _exit(status) }
+    }
+}
+
+// The Daemon
+
+pub struct Daemon {
+    pid_file: Option<PathBuf>,
+    working_dir: PathBuf,
+    umask: u32,
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+}
+
+impl Default for Daemon {
+    fn default() -> Daemon {
+        Daemon::new()
+    }
+}
+
+impl Daemon {
+    pub fn new() -> Daemon {
+        Daemon { pid_file: None, working_dir: PathBuf::from("/"), umask: 0o027, stdout: None, stderr: None }
+    }
+
+    pub fn pid_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pid_file = Some(path.into());
+        self
+    }
+
+    // Where it goes after forking, / unless the daemon reads files relative to where it was started
+    pub fn working_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.working_dir = dir.into();
+        self
+    }
+
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.umask = umask;
+        self
+    }
+
+    // Appended to, /dev/null without one
+    pub fn stdout(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stdout = Some(path.into());
+        self
+    }
+
+    pub fn stderr(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stderr = Some(path.into());
+        self
+    }
+
+    // Only returns in the daemon, with its PID file when it was given one. The process that called it exits once the daemon
+    // is running, or prints why it didn't start. An error returned here happened before the first fork, in the foreground
+    #[cfg(unix)]
+    pub fn start(self) -> Result<Option<PidFile>, DaemonError> {
+        // Everything relative is relative to where it was started, not to working_dir
+        let here = env::current_dir()?;
+        let pid_file = self.pid_file.map(|path| here.join(path));
+        if let Some(path) = &pid_file {
+            if let Some(pid) = running(path) {
+                return Err(DaemonError::AlreadyRunning(pid));
+            }
+        }
+        let open = |path: &Option<PathBuf>| -> io::Result<File> {
+            match path {
+                Some(path) => OpenOptions::new().create(true).append(true).open(here.join(path)),
+                None => OpenOptions::new().read(true).write(true).open("/dev/null"),
+            }
+        };
+        let (stdin, stdout, stderr) = (File::open("/dev/null")?, open(&self.stdout)?, open(&self.stderr)?);
+        let (mut status, mut report) = sys::pipe_files()?;
+
+        if let sys::Fork::Parent = sys::fork_process()? {
+            drop(report);
+            let mut message = String::new();
+            // Nothing read is the same as a failure without a message, the daemon died before it could write one
+            let _ = status.read_to_string(&mut message);
+            match message.as_str() {
+                "ok" => process::exit(0),
+                "" => eprintln!("{}", DaemonError::Failed(String::from("it exited without saying why"))),
+                why => eprintln!("{}", DaemonError::Failed(why.to_string())),
+            }
+            process::exit(1);
+        }
+        drop(status);
+
+        let mut fail = |e: &dyn fmt::Display| -> ! {
+            // The parent prints it, if it can't read it there's nobody to tell
+            let _ = write!(report, "{e}");
+            sys::exit_now(1)
+        };
+        if let Err(e) = sys::new_session() {
+            fail(&e);
+        }
+        match sys::fork_process() {
+            Ok(sys::Fork::Parent) => sys::exit_now(0),
+            Ok(sys::Fork::Child) => {}
+            Err(e) => fail(&e),
+        }
+        sys::set_umask(self.umask);
+        if let Err(e) = env::set_current_dir(&self.working_dir) {
+            fail(&format!("{}: {e}", self.working_dir.display()));
+        }
+        for (file, fd) in [(&stdin, 0), (&stdout, 1), (&stderr, 2)] {
+            if let Err(e) = sys::redirect(file, fd) {
+                fail(&e);
+            }
+        }
+        let pid_file = match pid_file.map(PidFile::create).transpose() {
+            Ok(pid_file) => pid_file,
+            Err(e) => fail(&e),
+        };
+        // The parent exits as soon as it reads this, whatever happens to the write
+        let _ = report.write_all(b"ok");
+        Ok(pid_file)
+    }
+
+    #[cfg(not(unix))]
+    pub fn start(self) -> Result<Option<PidFile>, DaemonError> {
+        Err(DaemonError::Io(io::Error::new(io::ErrorKind::Unsupported, "daemons are only implemented for Unix")))
+    }
+}
+
+// PID Files
+
+// The process id in the file, when that process is still running. Our own doesn't count, it was left by a process
+// that had the same id before
+fn running(path: &Path) -> Option<u32> {
+    let pid: u32 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    #[cfg(unix)]
+    let alive = sys::alive(pid);
+    #[cfg(not(unix))]
+    let alive = false;
+    (pid != 0 && pid != process::id() && alive).then_some(pid)
+}
+
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    // Writes this process's id into a new file. A stale file is replaced, one of a process that is running is an error.
+    // create_new makes the check and the creation one step, so two copies starting at once can't both get the file
+    pub fn create(path: impl Into<PathBuf>) -> Result<PidFile, DaemonError> {
+        let path = path.into();
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", process::id())?;
+                    return Ok(PidFile { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if let Some(pid) = running(&path) {
+                        return Err(DaemonError::AlreadyRunning(pid));
+                    }
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // Somebody else made a new one right after we removed the stale one
+        match running(&path) {
+            Some(pid) => Err(DaemonError::AlreadyRunning(pid)),
+            None => Err(DaemonError::Io(io::Error::new(io::ErrorKind::AlreadyExists, "the PID file keeps coming back"))),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    // Only when it's still ours, a second copy may have replaced it after deciding it was stale
+    fn drop(&mut self) {
+        let ours = fs::read_to_string(&self.path).is_ok_and(|pid| pid.trim() == process::id().to_string());
+        if ours {
+            // Nothing to be done about it this late, the next start will find it stale
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("common-daemon-{}-{name}", process::id()))
+    }
+
+    #[test]
+    fn pid_files_are_removed_when_dropped() {
+        let path = temp_path("drop.pid");
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(format!("{}\n", process::id()), fs::read_to_string(pid_file.path()).unwrap());
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_files_are_replaced_and_live_ones_refused() {
+        let path = temp_path("stale.pid");
+        // No process has that id, pid_max is much lower
+        fs::write(&path, format!("{}\n", i32::MAX)).unwrap();
+        let pid_file = PidFile::create(&path).unwrap();
+        drop(pid_file);
+
+        fs::write(&path, "not a number").unwrap();
+        drop(PidFile::create(&path).unwrap());
+
+        // init is always running, even when we may not signal it
+        fs::write(&path, "1\n").unwrap();
+        assert!(matches!(PidFile::create(&path), Err(DaemonError::AlreadyRunning(1))));
+        assert!(matches!(Daemon::new().pid_file(&path).start(), Err(DaemonError::AlreadyRunning(1))));
+        // Refusing to start leaves the other one's file alone
+        assert_eq!("1\n", fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+// Settings from the Environment
+
+// minigrep read IGNORE_CASE with env::var(..).is_ok(), and every setting after it would have been its own env::var, its own
+// parse and its own error message, each stopping the program at the first variable that's wrong. Env reads them all the
+// same way:
+
+    // let mut env = Env::new();
+    // let port = env.var::<u16>("PORT").help("The port to listen on").default(7878);
+    // let threads = env.var::<usize>("THREADS").validate("at least 1", |&n| n > 0).default(4);
+    // env.check()?;
+
+    // 1. var::<T>() parses the value into any type that implements FromStr, like Matches::get in argparse.rs.
+    //    validate() adds a check on the parsed value, and the variable ends with default(), get() or required().
+    // 2. A variable that's wrong doesn't stop anything: its default is used, and the problem is kept. check() returns every
+    //    problem at once, so fixing PORT and running again doesn't just bring up THREADS.
+    // 3. Every variable read is remembered with its type, default and help, and help() lists them, for --print-env-help.
+// A variable set to the empty string counts as not set, and one that isn't valid UTF-8 is a problem like a bad number.
+
+use std::{any, collections::HashMap, env, fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvError {
+    // The variable and what's wrong with it, in the order they were read
+    pub problems: Vec<(String, String)>,
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, problem)) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{name}: {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+struct Entry {
+    name: String,
+    value_name: String,
+    default: Option<String>,
+    help: String,
+}
+
+pub struct Env {
+    // None is the process's environment, tests give their own
+    vars: Option<HashMap<String, String>>,
+    entries: Vec<Entry>,
+    problems: Vec<(String, String)>,
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env { vars: None, entries: Vec::new(), problems: Vec::new() }
+    }
+
+    // Reads these instead of the process's environment
+    pub fn from_pairs<I, K, V>(pairs: I) -> Env
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let vars = pairs.into_iter().map(|(name, value)| (name.into(), value.into())).collect();
+        Env { vars: Some(vars), ..Env::new() }
+    }
+
+    pub fn var<T: FromStr + fmt::Display>(&mut self, name: &str) -> Var<'_, T>
+    where
+        T::Err: fmt::Display,
+    {
+        Var { env: self, name: name.to_string(), help: String::new(), checks: Vec::new() }
+    }
+
+    // On when set to anything but 0, false, no or off
+    pub fn flag(&mut self, name: &str, help: &str) -> bool {
+        self.remember(name, "1", None, help);
+        match self.lookup(name) {
+            Some(value) => !["0", "false", "no", "off"].iter().any(|off| value.eq_ignore_ascii_case(off)),
+            None => false,
+        }
+    }
+
+    // Every problem found so far
+    pub fn check(&self) -> Result<(), EnvError> {
+        match self.problems.is_empty() {
+            true => Ok(()),
+            false => Err(EnvError { problems: self.problems.clone() }),
+        }
+    }
+
+    // The variables read so far, lined up like the sections of argparse's help
+    pub fn help(&self) -> String {
+        let rows: Vec<(String, String)> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let help = match &entry.default {
+                    Some(default) => format!("{} [default: {default}]", entry.help),
+                    None => entry.help.clone(),
+                };
+                (format!("{}={}", entry.name, entry.value_name), help.trim_start().to_string())
+            })
+            .collect();
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        let mut help = String::from("Environment variables:\n");
+        for (name, description) in rows {
+            let line = format!("  {name:width$}  {description}");
+            help.push_str(line.trim_end());
+            help.push('\n');
+        }
+        help
+    }
+
+    // The value, without the empty string and with a problem kept for one that isn't UTF-8
+    fn lookup(&mut self, name: &str) -> Option<String> {
+        let value = match &self.vars {
+            Some(vars) => vars.get(name).cloned(),
+            None => match env::var(name) {
+                Ok(value) => Some(value),
+                Err(env::VarError::NotPresent) => None,
+                Err(e) => {
+                    self.problems.push((name.to_string(), e.to_string()));
+                    None
+                }
+            },
+        };
+        value.filter(|value| !value.is_empty())
+    }
+
+    // The same variable read twice is listed once
+    fn remember(&mut self, name: &str, value_name: &str, default: Option<String>, help: &str) {
+        if self.entries.iter().any(|entry| entry.name == name) {
+            return;
+        }
+        let entry = Entry { name: name.to_string(), value_name: value_name.to_string(), default, help: help.to_string() };
+        self.entries.push(entry);
+    }
+}
+
+// What a value has to be, and the check for it
+type Check<T> = (String, Box<dyn Fn(&T) -> bool>);
+
+// A variable being declared, it's read by default(), get() or required()
+pub struct Var<'e, T> {
+    env: &'e mut Env,
+    name: String,
+    help: String,
+    checks: Vec<Check<T>>,
+}
+
+impl<T: FromStr + fmt::Display> Var<'_, T>
+where
+    T::Err: fmt::Display,
+{
+    pub fn help(mut self, help: &str) -> Self {
+        self.help = help.to_string();
+        self
+    }
+
+    // A value the check fails is reported as "must be {what}", and the default is used instead
+    pub fn validate(mut self, what: &str, check: impl Fn(&T) -> bool + 'static) -> Self {
+        self.checks.push((what.to_string(), Box::new(check)));
+        self
+    }
+
+    // The value, or the default when it isn't set or is wrong. The default isn't validated
+    pub fn default(self, default: T) -> T {
+        let shown = default.to_string();
+        self.read(Some(default), Some(shown), false).unwrap()
+    }
+
+    // None when it isn't set or is wrong
+    pub fn get(self) -> Option<T> {
+        self.read(None, None, false)
+    }
+
+    // Like get(), but not being set is a problem too
+    pub fn required(self) -> Option<T> {
+        self.read(None, None, true)
+    }
+
+    fn read(self, default: Option<T>, shown: Option<String>, required: bool) -> Option<T> {
+        let Var { env, name, help, checks } = self;
+        // u16, and String rather than alloc::string::String
+        let type_name = any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+        env.remember(&name, &format!("<{type_name}>"), shown, &help);
+
+        let problem = match env.lookup(&name) {
+            None if required => String::from("isn't set, and has to be"),
+            None => return default,
+            Some(raw) => match raw.parse::<T>() {
+                Err(e) => format!("{raw:?} isn't a {type_name}: {e}"),
+                Ok(value) => match checks.iter().find(|(_, check)| !check(&value)) {
+                    Some((what, _)) => format!("must be {what}, not {raw}"),
+                    None => return Some(value),
+                },
+            },
+        };
+        env.problems.push((name, problem));
+        default
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_typed_values_and_reports_every_problem() {
+        let mut env = Env::from_pairs([("PORT", "80a"), ("THREADS", "0"), ("NAME", "frog"), ("EMPTY", ""), ("VERBOSE", "Off")]);
+        let port = env.var::<u16>("PORT").help("The port to listen on").default(7878);
+        let threads = env.var::<usize>("THREADS").validate("at least 1", |&n| n > 0).default(4);
+        let name = env.var::<String>("NAME").required();
+        let empty = env.var::<u32>("EMPTY").get();
+        let token = env.var::<String>("TOKEN").required();
+        assert_eq!((7878, 4, Some(String::from("frog")), None, None), (port, threads, name, empty, token));
+        assert!(!env.flag("VERBOSE", "Say more") && !env.flag("QUIET", "Say less"));
+
+        let error = env.check().unwrap_err();
+        let names: Vec<&str> = error.problems.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["PORT", "THREADS", "TOKEN"], names);
+        let expected = "PORT: \"80a\" isn't a u16: invalid digit found in string\n\
+                        THREADS: must be at least 1, not 0\n\
+                        TOKEN: isn't set, and has to be";
+        assert_eq!(expected, error.to_string());
+        assert_eq!(Ok(()), Env::from_pairs([("PORT", "80")]).check());
+    }
+
+    #[test]
+    fn lists_what_was_read() {
+        let mut env = Env::from_pairs(Vec::<(String, String)>::new());
+        env.var::<u16>("PORT").help("The port to listen on").default(7878);
+        env.var::<String>("HOST").get();
+        env.flag("IGNORE_CASE", "Search case-insensitively");
+        env.var::<u16>("PORT").default(80);
+        let expected = "Environment variables:\n  \
+                        PORT=<u16>     The port to listen on [default: 7878]\n  \
+                        HOST=<String>\n  \
+                        IGNORE_CASE=1  Search case-insensitively\n";
+        assert_eq!(expected, env.help());
+    }
+}
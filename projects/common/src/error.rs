@@ -0,0 +1,100 @@
+// Error Types
+
+// Box<dyn Error> is the error type of main and run in the book: any error converts into it with ?, which is all a program needs
+// to report it and exit. Two things get in the way once there are threads and more than a few layers of calls:
+    // 1. Box<dyn Error> isn't Send, so it can't be sent back from a worker thread or through a channel. BoxError adds Send + Sync.
+    // 2. "No such file or directory" doesn't say which file, or what the program was trying to do with it.
+    //    context() wraps the error in a ContextError that says so, and keeps the original one as its source().
+
+// fn load(path: &Path) -> Result<String> {
+//     fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))
+// }
+
+// fails with "reading settings.toml: No such file or directory (os error 2)".
+
+use std::{error::Error, fmt};
+
+pub type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+// The second parameter has a default, so Result<T> is Result<T, BoxError> and Result<T, io::Error> still works
+pub type Result<T, E = BoxError> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub struct ContextError {
+    context: String,
+    source: BoxError,
+}
+
+impl ContextError {
+    pub fn new<C: fmt::Display, E: Into<BoxError>>(context: C, source: E) -> ContextError {
+        ContextError { context: context.to_string(), source: source.into() }
+    }
+
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.source)
+    }
+}
+
+impl Error for ContextError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+pub trait Context<T> {
+    fn context<C: fmt::Display>(self, context: C) -> Result<T, ContextError>;
+
+    // The same, but the message is only made when there is an error, so a format! costs nothing on the way that works
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, ContextError>;
+}
+
+impl<T, E: Into<BoxError>> Context<T> for Result<T, E> {
+    fn context<C: fmt::Display>(self, context: C) -> Result<T, ContextError> {
+        self.map_err(|e| ContextError::new(context, e))
+    }
+
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, ContextError> {
+        self.map_err(|e| ContextError::new(f(), e))
+    }
+}
+
+// The causes of an error, from the error itself down to the one that started it
+pub fn chain<'a>(error: &'a (dyn Error + 'static)) -> impl Iterator<Item = &'a (dyn Error + 'static)> {
+    std::iter::successors(Some(error), |&error| error.source())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io, thread};
+
+    fn parse_port(text: &str) -> Result<u16> {
+        let port = text.trim().parse::<u16>().with_context(|| format!("bad port {text:?}"))?;
+        Ok(port)
+    }
+
+    #[test]
+    fn context_wraps_the_source() {
+        assert_eq!(8080, parse_port("8080\n").unwrap());
+        let error = parse_port("80800").unwrap_err();
+        assert_eq!("bad port \"80800\": number too large to fit in target type", error.to_string());
+
+        let error = Err::<(), _>(io::Error::new(io::ErrorKind::NotFound, "no such file")).context("reading settings").unwrap_err();
+        assert_eq!("reading settings", error.context());
+        let causes: Vec<String> = chain(&error).map(|e| e.to_string()).collect();
+        assert_eq!(vec!["reading settings: no such file", "no such file"], causes);
+    }
+
+    #[test]
+    fn box_errors_cross_threads() {
+        let error = thread::spawn(|| parse_port("port")).join().unwrap().unwrap_err();
+        assert!(error.to_string().starts_with("bad port \"port\": invalid digit"), "{error}");
+        assert!(error.downcast_ref::<ContextError>().is_some());
+    }
+}
@@ -0,0 +1,227 @@
+// Glob Patterns
+
+// Shells, .gitignore files and Cargo.toml's exclude lists all describe sets of paths with the same small language:
+    // 1. * matches any run of characters within one path segment, so *.rs matches main.rs but not src/main.rs.
+    // 2. ? matches a single character, also not a '/'.
+    // 3. [abc], [a-z] and [!a-z] (or [^a-z]) match one character from a set, or one that isn't in it.
+    // 4. ** as a whole segment matches any number of segments, none included: src/**/mod.rs matches src/mod.rs and src/a/b/mod.rs.
+    //    **/ at the start matches in any directory, /** at the end matches everything inside a directory.
+    // 5. A backslash takes away the meaning of the next character, \* matches a star.
+// A Glob is compiled once into tokens, then matched against paths. walk.rs matches the lines of .gitignore files with it.
+
+// Matching goes token by token with backtracking at every star. Without care, a pattern like *a*a*a*a*b is exponential,
+// so positions (token, character) that already failed are remembered and never tried twice, which keeps it at O(tokens × chars).
+
+use std::{collections::HashSet, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlobError {
+    // A [ without its ], at this character position
+    UnclosedClass(usize),
+    // A pattern that ends with a single backslash
+    TrailingEscape,
+}
+
+impl fmt::Display for GlobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GlobError::UnclosedClass(at) => write!(f, "unclosed character class at {at}"),
+            GlobError::TrailingEscape => write!(f, "pattern ends with an escape"),
+        }
+    }
+}
+
+impl std::error::Error for GlobError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    // ?
+    Any,
+    // *
+    Star,
+    // ** at the end, or anywhere it isn't a whole segment followed by '/'
+    GlobStar,
+    // **/ : nothing, or any number of whole segments each ending with '/'
+    Segments,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    pattern: String,
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Glob, GlobError> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    let starts_segment = i == 0 || chars[i - 1] == '/';
+                    match chars.get(i + 2) {
+                        Some('/') if starts_segment => {
+                            tokens.push(Token::Segments);
+                            i += 3;
+                        }
+                        None if starts_segment => {
+                            tokens.push(Token::GlobStar);
+                            i += 2;
+                        }
+                        // Like a**b: not a whole segment, so it's just a star
+                        _ => {
+                            tokens.push(Token::Star);
+                            i += 2;
+                        }
+                    }
+                    continue;
+                }
+                '*' => tokens.push(Token::Star),
+                '?' => tokens.push(Token::Any),
+                '\\' => {
+                    i += 1;
+                    tokens.push(Token::Literal(*chars.get(i).ok_or(GlobError::TrailingEscape)?));
+                }
+                '[' => {
+                    let (token, end) = parse_class(&chars, i)?;
+                    tokens.push(token);
+                    i = end;
+                }
+                c => tokens.push(Token::Literal(c)),
+            }
+            i += 1;
+        }
+        Ok(Glob { pattern: pattern.to_string(), tokens })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        let chars: Vec<char> = path.chars().collect();
+        let mut failed = HashSet::new();
+        self.match_from(0, &chars, 0, &mut failed)
+    }
+
+    fn match_from(&self, t: usize, chars: &[char], c: usize, failed: &mut HashSet<(usize, usize)>) -> bool {
+        if failed.contains(&(t, c)) {
+            return false;
+        }
+        let matched = match self.tokens.get(t) {
+            None => c == chars.len(),
+            Some(Token::Literal(expected)) => chars.get(c) == Some(expected) && self.match_from(t + 1, chars, c + 1, failed),
+            Some(Token::Any) => chars.get(c).is_some_and(|&ch| ch != '/') && self.match_from(t + 1, chars, c + 1, failed),
+            Some(Token::Class { negated, ranges }) => {
+                chars.get(c).is_some_and(|&ch| ch != '/' && ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&ch)) != *negated)
+                    && self.match_from(t + 1, chars, c + 1, failed)
+            }
+            // Any number of characters up to the next '/'
+            Some(Token::Star) => {
+                let segment_end = chars[c..].iter().position(|&ch| ch == '/').map_or(chars.len(), |i| c + i);
+                (c..=segment_end).any(|end| self.match_from(t + 1, chars, end, failed))
+            }
+            Some(Token::GlobStar) => (c..=chars.len()).any(|end| self.match_from(t + 1, chars, end, failed)),
+            // Nothing, or up to and including any '/' later on
+            Some(Token::Segments) => {
+                self.match_from(t + 1, chars, c, failed)
+                    || (c..chars.len()).any(|i| chars[i] == '/' && self.match_from(t + 1, chars, i + 1, failed))
+            }
+        };
+        if !matched {
+            failed.insert((t, c));
+        }
+        matched
+    }
+}
+
+// [abc], [a-z0-9], [!x] or [^x]. A ] right after the [ or the ! is a literal, so []] matches a ']'.
+fn parse_class(chars: &[char], open: usize) -> Result<(Token, usize), GlobError> {
+    let mut i = open + 1;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let first = i;
+    let mut ranges = Vec::new();
+    loop {
+        let c = *chars.get(i).ok_or(GlobError::UnclosedClass(open))?;
+        if c == ']' && i > first {
+            return Ok((Token::Class { negated, ranges }, i));
+        }
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&hi)) if hi != ']' => {
+                ranges.push((c, hi));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_glob(pattern: &str, yes: &[&str], no: &[&str]) {
+        let glob = Glob::new(pattern).unwrap();
+        for path in yes {
+            assert!(glob.is_match(path), "{pattern} should match {path}");
+        }
+        for path in no {
+            assert!(!glob.is_match(path), "{pattern} should not match {path}");
+        }
+    }
+
+    #[test]
+    fn stars_and_question_marks() {
+        assert_glob("*.rs", &["main.rs", ".rs", "a.b.rs"], &["src/main.rs", "main.rsx"]);
+        assert_glob("src/*/mod.rs", &["src/a/mod.rs"], &["src/mod.rs", "src/a/b/mod.rs"]);
+        assert_glob("file?.txt", &["file1.txt", "fileA.txt"], &["file.txt", "file10.txt", "file/.txt"]);
+        assert_glob("a*b*c", &["abc", "aXbYc", "abbbc"], &["acb", "a/b/c"]);
+    }
+
+    #[test]
+    fn double_stars() {
+        assert_glob("src/**/mod.rs", &["src/mod.rs", "src/a/mod.rs", "src/a/b/mod.rs"], &["mod.rs", "src/amod.rs"]);
+        assert_glob("**/target", &["target", "a/target", "a/b/target"], &["target/x", "xtarget"]);
+        assert_glob("build/**", &["build/x", "build/a/b"], &["build", "builds/x"]);
+        assert_glob("a**b", &["ab", "axxb"], &["a/b"]);
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert_glob("[abc].txt", &["a.txt", "c.txt"], &["d.txt", "ab.txt"]);
+        assert_glob("v[0-9][0-9]", &["v10", "v99"], &["va1", "v1"]);
+        assert_glob("[!a-z]*", &["Main", "1st"], &["main", "/x"]);
+        assert_glob("[^.]*", &["visible"], &[".hidden"]);
+        assert_glob("[]]", &["]"], &["["]);
+        assert_glob(r"\*literal\?", &["*literal?"], &["xliteral?", "*literalx"]);
+        assert_glob("[a-]", &["a", "-"], &["b"]);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert_eq!(Err(GlobError::UnclosedClass(2)), Glob::new("ab[cd"));
+        assert_eq!(Err(GlobError::TrailingEscape), Glob::new("abc\\"));
+        assert_eq!("src/**/*.rs", Glob::new("src/**/*.rs").unwrap().to_string());
+    }
+
+    #[test]
+    fn no_exponential_backtracking() {
+        let glob = Glob::new("*a*a*a*a*a*a*a*a*a*a*b").unwrap();
+        assert!(!glob.is_match(&"a".repeat(200)));
+    }
+}
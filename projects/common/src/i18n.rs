@@ -0,0 +1,414 @@
+// Messages in Other Languages
+
+// greeting() in testing/adder said "Hello {name}!" with format!, and the webserver's error pages said "Not Found" in English
+// to everyone. Translating them means the text can't be in the code anymore: the code names a message, and a catalog per
+// locale has the text for it.
+
+// A catalog is a text file, one message per line, embedded in the binary with include_str!:
+
+    // # testing/adder/locales/pl.txt
+    // greeting = Cześć, {name}!
+    // new_messages[one] = Masz {count} nową wiadomość
+    // new_messages[few] = Masz {count} nowe wiadomości
+    // new_messages[many] = Masz {count} nowych wiadomości
+
+    // 1. {name} is replaced with the argument of that name, {{ and }} are braces. An argument that wasn't given is left as it is.
+    // 2. A message with [category] variants is plural, and the count argument picks the variant. English has one form for 1 and
+    //    another for everything else, Polish and Russian have three and decide by the last digits, see plural() below.
+    // 3. A message missing from pl-PL is looked for in pl, then in the default locale, and then the key itself is shown. An
+    //    untranslated message still says something, and which one is missing is easy to find.
+
+// The catalogs are loaded into one registry for the whole process, so that t! doesn't have to be handed one:
+
+    // i18n::load("pl", include_str!("../locales/pl.txt"))?;
+    // let hello = t!("greeting", name = "Carol");
+
+// t! translates into the thread's current locale, which with_locale sets for a closure: the webserver sets it for a request
+// from its Accept-Language header, and negotiate picks the best of the loaded locales for that header.
+// Catalogs is the registry as a value, for a program that wants its own instead of the global one.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{OnceLock, RwLock},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Plural {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+impl Plural {
+    fn from_name(name: &str) -> Option<Plural> {
+        match name {
+            "zero" => Some(Plural::Zero),
+            "one" => Some(Plural::One),
+            "two" => Some(Plural::Two),
+            "few" => Some(Plural::Few),
+            "many" => Some(Plural::Many),
+            "other" => Some(Plural::Other),
+            _ => None,
+        }
+    }
+}
+
+// The category of a whole number in a language, after the plural rules of the Unicode CLDR. Only the languages that have
+// catalogs somewhere in the repository are here, every other one is treated like English
+pub fn plural(language: &str, n: u64) -> Plural {
+    let (last, last_two) = (n % 10, n % 100);
+    match language {
+        // 1 plik, 2 pliki, 5 plików, 22 pliki, 12 plików
+        "pl" => match n {
+            1 => Plural::One,
+            _ if (2..=4).contains(&last) && !(12..=14).contains(&last_two) => Plural::Few,
+            _ => Plural::Many,
+        },
+        // 1 файл, 21 файл, 2 файла, 5 файлов, 11 файлов
+        "ru" | "uk" => match (last, last_two) {
+            (1, _) if last_two != 11 => Plural::One,
+            (2..=4, _) if !(12..=14).contains(&last_two) => Plural::Few,
+            _ => Plural::Many,
+        },
+        // 1 soubor, 2 soubory, 5 souborů
+        "cs" | "sk" => match n {
+            1 => Plural::One,
+            2..=4 => Plural::Few,
+            _ => Plural::Other,
+        },
+        _ => match n {
+            1 => Plural::One,
+            _ => Plural::Other,
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogError {
+    pub locale: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} catalog, line {}: {}", self.locale, self.line, self.message)
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Plain(String),
+    Plural(BTreeMap<Plural, String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Catalogs {
+    default: String,
+    // Locale tags in lower case, en or pl-pl
+    locales: BTreeMap<String, HashMap<String, Message>>,
+}
+
+impl Catalogs {
+    pub fn new(default: &str) -> Catalogs {
+        Catalogs { default: default.to_ascii_lowercase(), locales: BTreeMap::new() }
+    }
+
+    pub fn set_default(&mut self, locale: &str) {
+        self.default = locale.to_ascii_lowercase();
+    }
+
+    // Adds the messages of a catalog to the ones the locale has, a message loaded twice keeps the last text
+    pub fn add(&mut self, locale: &str, text: &str) -> Result<(), CatalogError> {
+        let error = |line: usize, message: String| CatalogError { locale: locale.to_string(), line, message };
+        let mut messages: HashMap<String, Message> = HashMap::new();
+        // Where each message starts, for the error about its variants
+        let mut lines: HashMap<String, usize> = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, text) = line.split_once('=').ok_or_else(|| error(i + 1, String::from("expected key = text")))?;
+            let (key, text) = (key.trim(), text.trim().to_string());
+            let (key, category) = match key.strip_suffix(']').and_then(|key| key.split_once('[')) {
+                Some((key, name)) => {
+                    let category = Plural::from_name(name).ok_or_else(|| error(i + 1, format!("no plural category {name}")))?;
+                    (key, Some(category))
+                }
+                None => (key, None),
+            };
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return Err(error(i + 1, format!("{key:?} isn't a key")));
+            }
+            lines.entry(key.to_string()).or_insert(i + 1);
+            match (messages.get_mut(key), category) {
+                (None, None) => {
+                    messages.insert(key.to_string(), Message::Plain(text));
+                }
+                (None, Some(category)) => {
+                    messages.insert(key.to_string(), Message::Plural(BTreeMap::from([(category, text)])));
+                }
+                (Some(Message::Plural(variants)), Some(category)) if !variants.contains_key(&category) => {
+                    variants.insert(category, text);
+                }
+                _ => return Err(error(i + 1, format!("{key} is defined twice"))),
+            }
+        }
+        for (key, message) in &messages {
+            if let Message::Plural(variants) = message {
+                if !variants.contains_key(&Plural::Other) {
+                    return Err(error(lines[key], format!("{key} has no [other] variant, which is used when no other one fits")));
+                }
+            }
+        }
+        self.locales.entry(locale.to_ascii_lowercase()).or_default().extend(messages);
+        Ok(())
+    }
+
+    pub fn locales(&self) -> Vec<&str> {
+        self.locales.keys().map(String::as_str).collect()
+    }
+
+    // The message in the locale, with the arguments filled in
+    pub fn translate(&self, locale: &str, key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+        let Some((locale, message)) = self.find(locale, key) else {
+            return key.to_string();
+        };
+        let text = match message {
+            Message::Plain(text) => text,
+            Message::Plural(variants) => {
+                // The count is whatever was given, a number once it's written out
+                let count = args.iter().find(|(name, _)| *name == "count").and_then(|(_, value)| value.to_string().parse().ok());
+                let category = count.map_or(Plural::Other, |count| plural(language(locale), count));
+                variants.get(&category).or_else(|| variants.get(&Plural::Other)).map_or("", String::as_str)
+            }
+        };
+        interpolate(text, args)
+    }
+
+    // The locale the message is found in, and the message
+    fn find(&self, locale: &str, key: &str) -> Option<(&str, &Message)> {
+        let locale = locale.to_ascii_lowercase();
+        let candidates = [locale.as_str(), language(&locale), self.default.as_str(), language(&self.default)];
+        candidates.iter().find_map(|&locale| {
+            let (locale, messages) = self.locales.get_key_value(locale)?;
+            messages.get(key).map(|message| (locale.as_str(), message))
+        })
+    }
+
+    // The loaded locale that fits the header best, or the default. A tag matches the same locale, or its language alone:
+    // pl-PL is answered in pl when there's no pl-PL, and pl in pl-PL when that's the only Polish there is
+    pub fn negotiate(&self, accept_language: Option<&str>) -> String {
+        for tag in accept_language.map(parse_accept_language).unwrap_or_default() {
+            if tag == "*" {
+                break;
+            }
+            if self.locales.contains_key(&tag) {
+                return tag;
+            }
+            if self.locales.contains_key(language(&tag)) {
+                return language(&tag).to_string();
+            }
+            if let Some(locale) = self.locales.keys().find(|locale| language(locale) == tag) {
+                return locale.clone();
+            }
+        }
+        self.default.clone()
+    }
+}
+
+// pl of pl-PL
+fn language(locale: &str) -> &str {
+    locale.split(['-', '_']).next().unwrap_or(locale)
+}
+
+// The tags of the header, in lower case and best first. q=0 means not wanted at all, and ties keep the header's order
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';').map(str::trim);
+            let tag = params.next().filter(|tag| !tag.is_empty())?;
+            let q = params.find_map(|param| param.strip_prefix("q=")).map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            (q > 0.0).then(|| (tag.to_ascii_lowercase(), q))
+        })
+        .collect();
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+fn interpolate(text: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            out.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        if rest.starts_with('}') {
+            out.push('}');
+            rest = &rest[1..];
+            continue;
+        }
+        let name = rest[1..].split_once('}').map(|(name, _)| name);
+        match name.and_then(|name| args.iter().find(|(arg, _)| *arg == name)) {
+            Some((name, value)) => {
+                out.push_str(&value.to_string());
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                out.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// The Registry of the Process
+
+fn global() -> &'static RwLock<Catalogs> {
+    static CATALOGS: OnceLock<RwLock<Catalogs>> = OnceLock::new();
+    CATALOGS.get_or_init(|| RwLock::new(Catalogs::new("en")))
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn load(locale: &str, text: &str) -> Result<(), CatalogError> {
+    global().write().unwrap_or_else(|poisoned| poisoned.into_inner()).add(locale, text)
+}
+
+// English unless it's set
+pub fn set_default_locale(locale: &str) {
+    global().write().unwrap_or_else(|poisoned| poisoned.into_inner()).set_default(locale);
+}
+
+pub fn translate(locale: &str, key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    global().read().unwrap_or_else(|poisoned| poisoned.into_inner()).translate(locale, key, args)
+}
+
+pub fn negotiate(accept_language: Option<&str>) -> String {
+    global().read().unwrap_or_else(|poisoned| poisoned.into_inner()).negotiate(accept_language)
+}
+
+// The thread's locale, or the default one outside of with_locale
+pub fn locale() -> String {
+    CURRENT
+        .with(|current| current.borrow().clone())
+        .unwrap_or_else(|| global().read().unwrap_or_else(|poisoned| poisoned.into_inner()).default.clone())
+}
+
+// Runs f with t! translating into the locale, the one before is back afterwards, even after a panic
+pub fn with_locale<R>(locale: &str, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<String>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+    let _restore = Restore(CURRENT.with(|current| current.borrow_mut().replace(locale.to_ascii_lowercase())));
+    f()
+}
+
+// t!("greeting", name = "Carol") is the message in the thread's locale, with the arguments filled in
+#[macro_export]
+macro_rules! t {
+    ($key:expr $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::i18n::translate(
+            &$crate::i18n::locale(),
+            $key,
+            &[$((stringify!($name), &$value as &dyn ::std::fmt::Display)),*],
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EN: &str = "# English\ngreeting = Hello {name}!\n\
+                      files[one] = {count} file\nfiles[other] = {count} files\nbraces = {{name}}";
+    const PL: &str = "greeting = Cześć, {name}!\n\
+                      files[one] = {count} plik\nfiles[few] = {count} pliki\n\
+                      files[many] = {count} plików\nfiles[other] = {count} pliku";
+
+    fn catalogs() -> Catalogs {
+        let mut catalogs = Catalogs::new("en");
+        catalogs.add("en", EN).unwrap();
+        catalogs.add("pl", PL).unwrap();
+        catalogs
+    }
+
+    #[test]
+    fn plural_rules() {
+        let en: Vec<Plural> = [0, 1, 2, 11].map(|n| plural("en", n)).to_vec();
+        assert_eq!(vec![Plural::Other, Plural::One, Plural::Other, Plural::Other], en);
+        let pl: Vec<Plural> = [1, 2, 5, 12, 22, 25, 104].map(|n| plural("pl", n)).to_vec();
+        use Plural::{Few, Many, One};
+        assert_eq!(vec![One, Few, Many, Many, Few, Many, Few], pl);
+        let ru: Vec<Plural> = [1, 21, 11, 3, 13, 5, 0].map(|n| plural("ru", n)).to_vec();
+        assert_eq!(vec![One, One, Many, Few, Many, Many, Many], ru);
+    }
+
+    #[test]
+    fn translates_with_fallbacks() {
+        let catalogs = catalogs();
+        let files = |locale: &str, count: u64| catalogs.translate(locale, "files", &[("count", &count)]);
+        assert_eq!(("1 file", "3 files"), (files("en", 1).as_str(), files("en", 3).as_str()));
+        assert_eq!(["1 plik", "2 pliki", "5 plików", "22 pliki"], [1, 2, 5, 22].map(|n| files("pl-PL", n)));
+
+        assert_eq!("Cześć, Carol!", catalogs.translate("PL", "greeting", &[("name", &"Carol")]));
+        assert_eq!("Hello Carol!", catalogs.translate("de", "greeting", &[("name", &"Carol")]));
+        assert_eq!("Hello {name}!", catalogs.translate("en", "greeting", &[]));
+        assert_eq!("{name}", catalogs.translate("pl", "braces", &[("name", &"x")]));
+        assert_eq!("a } b", interpolate("a } b", &[]));
+        assert_eq!("nothing.here", catalogs.translate("pl", "nothing.here", &[]));
+    }
+
+    #[test]
+    fn catalog_errors_say_where() {
+        let mut catalogs = Catalogs::new("en");
+        let error = |text| catalogs.clone().add("xx", text).unwrap_err().to_string();
+        assert_eq!("xx catalog, line 2: expected key = text", error("a = 1\nb"));
+        assert_eq!("xx catalog, line 1: no plural category some", error("a[some] = 1"));
+        assert_eq!("xx catalog, line 2: a is defined twice", error("a = 1\na = 2"));
+        let message = "xx catalog, line 2: a has no [other] variant, which is used when no other one fits";
+        assert_eq!(message, error("b = 1\na[one] = 1\na[few] = 2"));
+        assert!(catalogs.add("xx", "").is_ok());
+    }
+
+    #[test]
+    fn negotiates_the_locale() {
+        let mut catalogs = catalogs();
+        catalogs.add("ru-RU", "greeting = Привет, {name}!").unwrap();
+        assert_eq!(vec!["pl-pl", "en", "*"], parse_accept_language("en;q=0.8, pl-PL, fr;q=0, *;q=0.1"));
+        assert_eq!("pl", catalogs.negotiate(Some("pl-PL,pl;q=0.9,en;q=0.8")));
+        assert_eq!("ru-ru", catalogs.negotiate(Some("ru")));
+        assert_eq!("en", catalogs.negotiate(Some("de, *;q=0.5, pl;q=0.1")));
+        assert_eq!("en", catalogs.negotiate(None));
+    }
+
+    #[test]
+    fn t_uses_the_locale_of_the_thread() {
+        load("en", "test.hello = Hello {name}!").unwrap();
+        load("pl", "test.hello = Cześć, {name}!").unwrap();
+        let name = "Carol";
+        assert_eq!("Hello Carol!", t!("test.hello", name = name));
+        let polish = with_locale("pl", || t!("test.hello", name = name));
+        assert_eq!(("Cześć, Carol!", String::from("en")), (polish.as_str(), locale()));
+    }
+}
@@ -0,0 +1,67 @@
+// Common Utilities
+
+// Some things every program needs, and the projects kept writing their own: minigrep had a Clock for its tests, the webserver
+// had Stopwatch and Deadline, and both reported errors with eprintln! and a Box<dyn Error>. This crate is where they live now,
+// and the binaries depend on it like on any other crate, with a path dependency:
+
+    // [dependencies]
+    // common = { path = "../common" }
+
+// It's a library crate only, the same idea as add_one in organizing_code/workspace_example, but shared by crates that aren't
+// in one workspace. It can't depend on minigrep or the webserver, since they depend on it, so the code moved here instead of being re-exported.
+    // 1. log is a small logger: error!, warn!, info!, debug! and trace! write a line to stderr when their level is enabled.
+    // 2. error has BoxError, a Result that defaults to it, and the Context trait for saying what was being done when an error happened.
+    // 3. time has Stopwatch, Deadline, RateLimiter, Throttle and Debounce, the webserver still has them as time_ext.
+    // 4. clock has the Clock trait, with a real and a fake clock, minigrep still has it as clock.
+    // 5. term has raw mode, the size of the terminal, and the escapes for the cursor, colors and a double-buffered Screen.
+    // 6. signals turns SIGINT, SIGTERM and SIGHUP into messages on a channel, and Shutdown runs the hooks that end a server.
+    // 7. daemon forks a server into the background on Unix, and keeps its process id in a PID file.
+    // 8. env_config reads settings from environment variables into types, and reports every one that's wrong at once.
+    // 9. i18n has message catalogs per locale, plural rules, Accept-Language negotiation and the t! macro.
+    // 10. tracing has spans with trace IDs that follow a request across threads, into its log lines and out as JSON.
+    // 11. bytesearch finds bytes and byte strings 8 bytes at a time, and the ends of lines and heads of HTTP messages.
+    // 12. progress has the Tracker that work reports to, and the progress bar and JSON lines it can report as, minigrep
+    //     still has it as progress.
+    // 13. argparse describes a command line once, and parses it and writes its --help from that. The binaries of every project
+    //     use it, minigrep still has it as argparse.
+    // 14. table lines up text in columns counted the way a terminal draws them, for minigrep --stats and kv-cli --table.
+    // 15. mmap has FileBytes, a file's contents as a slice, mapped into memory with the mmap feature and read otherwise.
+    // 16. vfs has the FileSystem trait, the real one and one in memory for tests, walk goes through a directory tree the way
+    //     .gitignore files say, and glob has the patterns those files are made of. minigrep still has all three.
+
+// std::simd for bytesearch, with the simd feature, which only nightly has
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+// Most programs want a few names from each, and prelude has them all:
+
+    // use common::prelude::*;
+
+// pub use in the prelude re-exports the items, it doesn't copy them: common::prelude::Stopwatch and common::time::Stopwatch
+// are the same type, and so is multithreaded_webserver::time_ext::Stopwatch.
+
+pub mod argparse;
+pub mod bytesearch;
+pub mod clock;
+pub mod daemon;
+pub mod env_config;
+pub mod error;
+pub mod glob;
+pub mod i18n;
+pub mod log;
+pub mod mmap;
+pub mod progress;
+pub mod signals;
+pub mod table;
+pub mod term;
+pub mod time;
+pub mod tracing;
+pub mod vfs;
+pub mod walk;
+
+pub mod prelude {
+    pub use crate::clock::{Clock, SystemClock};
+    pub use crate::error::{BoxError, Context, Result};
+    pub use crate::time::{Deadline, Stopwatch};
+    // #[macro_export] puts the macros at the root of the crate, whichever module they are written in
+    pub use crate::{debug, error, info, trace, warn};
+}
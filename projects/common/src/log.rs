@@ -0,0 +1,268 @@
+// Logging
+
+// eprintln! is fine for the one error a program prints before it exits. A server prints all the time though, and how much
+// of it someone wants to see depends on what they're doing: the warnings in production, every request while debugging.
+// A logger gives every message a level, and only writes the ones at or above the level that was asked for.
+    // 1. The macros error!, warn!, info!, debug! and trace! take the same arguments as format!. A message whose level is off
+    //    isn't even formatted, the check is a single atomic load.
+    // 2. The level comes from set_max_level, or from an environment variable with init_from_env: LOG=debug cargo run.
+    //    It's Info until then.
+    // 3. Every line says its level and where it came from, the module_path!() of the code that logged it:
+    //    WARN  multithreaded_webserver: bad request: missing the method
+    // 4. A line written inside a tracing::Span ends with trace= and its trace ID, the ID of the request it's part of.
+    // 5. The lines go to stderr, or to any writer given to set_output, which is how the tests read them.
+    // 6. keep_recent(n) keeps the last n lines in memory as well, and recent() returns them, for a program that shows
+    //    its own log somewhere, like the webserver does in its metrics for the dashboard.
+
+// The log crate is what real programs use, and its macros look the same. It only defines the interface though, the writing is
+// done by another crate like env_logger. This module is both in one, with no configuration beyond the level.
+
+use std::{
+    collections::VecDeque,
+    env, fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::tracing;
+
+// From the most to the least important: a max level of Warn lets Error and Warn through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // pad, not write_str, so that {:5} lines the messages up
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level {s:?}, expected error, warn, info, debug or trace")),
+        }
+    }
+}
+
+// 0 means nothing is logged at all, otherwise it's a Level as a number
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+// None is stderr
+static OUTPUT: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+static RECENT_CAPACITY: AtomicUsize = AtomicUsize::new(0);
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+pub fn set_max_level(level: Option<Level>) {
+    MAX_LEVEL.store(level.map_or(0, |level| level as u8), Ordering::Relaxed);
+}
+
+pub fn max_level() -> Option<Level> {
+    match MAX_LEVEL.load(Ordering::Relaxed) {
+        0 => None,
+        1 => Some(Level::Error),
+        2 => Some(Level::Warn),
+        3 => Some(Level::Info),
+        4 => Some(Level::Debug),
+        _ => Some(Level::Trace),
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+// Reads the level from the variable: a level's name, or "off". A variable that isn't set leaves the level as it was,
+// one that can't be parsed is an error, and leaves it too.
+pub fn init_from_env(var: &str) -> Result<(), String> {
+    match env::var(var) {
+        Ok(value) if value.eq_ignore_ascii_case("off") => set_max_level(None),
+        Ok(value) => set_max_level(Some(value.parse().map_err(|e| format!("{var}: {e}"))?)),
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+// Sends the lines to this writer instead of stderr, or back to stderr with None
+pub fn set_output(output: Option<Box<dyn Write + Send>>) {
+    *OUTPUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = output;
+}
+
+// 0 keeps none, which is how it starts. A smaller number drops the oldest lines kept so far
+pub fn keep_recent(lines: usize) {
+    RECENT_CAPACITY.store(lines, Ordering::Relaxed);
+    let mut recent = RECENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    while recent.len() > lines {
+        recent.pop_front();
+    }
+}
+
+// The lines kept, the oldest first, without their \n
+pub fn recent() -> Vec<String> {
+    RECENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().cloned().collect()
+}
+
+// With the trace ID at the end, inside a span (tracing.rs)
+pub fn format_line(level: Level, target: &str, args: fmt::Arguments) -> String {
+    match tracing::current() {
+        Some(context) => format!("{level:5} {target}: {args} trace={}\n", context.trace_id),
+        None => format!("{level:5} {target}: {args}\n"),
+    }
+}
+
+// What the macros call once they know the level is enabled. The whole line is written at once, while holding the lock,
+// so the lines of two threads never end up mixed together.
+pub fn write(level: Level, target: &str, args: fmt::Arguments) {
+    let line = format_line(level, target, args);
+    let mut output = OUTPUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    // A logger has nowhere to report its own errors, so a line that can't be written is lost
+    let _ = match output.as_mut() {
+        Some(output) => output.write_all(line.as_bytes()),
+        None => io::stderr().write_all(line.as_bytes()),
+    };
+    drop(output);
+    let capacity = RECENT_CAPACITY.load(Ordering::Relaxed);
+    if capacity > 0 {
+        let mut recent = RECENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if recent.len() == capacity {
+            recent.pop_front();
+        }
+        recent.push_back(line.trim_end().to_string());
+    }
+}
+
+// Writes out what the output buffered, for a program that's about to end (see signals.rs). stderr buffers nothing
+pub fn flush() -> io::Result<()> {
+    match OUTPUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).as_mut() {
+        Some(output) => output.flush(),
+        None => Ok(()),
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, module_path!(), format_args!($($arg)+));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // A writer the test keeps a handle to, to read what was logged
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(Ok(Level::Warn), "WARNING".parse());
+        assert!("loud".parse::<Level>().is_err());
+        assert!(Level::Error < Level::Trace);
+        assert_eq!("INFO  common::log: ready\n", format_line(Level::Info, "common::log", format_args!("ready")));
+        let span = crate::tracing::Span::new("request");
+        let expected = format!("WARN  common::log: slow trace={}\n", span.context().trace_id);
+        assert_eq!(expected, format_line(Level::Warn, "common::log", format_args!("slow")));
+    }
+
+    // The only test that changes the global level and output, tests run in parallel
+    #[test]
+    fn writes_what_the_level_lets_through() {
+        let shared = Shared::default();
+        set_output(Some(Box::new(shared.clone())));
+        keep_recent(1);
+
+        set_max_level(Some(Level::Warn));
+        let mut formatted = 0;
+        let mut count = || {
+            formatted += 1;
+            formatted
+        };
+        crate::warn!("disk {}% full", 91);
+        crate::info!("formatted {} times", count());
+        crate::error!("worker {} panicked", 3);
+        assert_eq!(Some(Level::Warn), max_level());
+        assert_eq!(vec!["ERROR common::log::tests: worker 3 panicked"], recent());
+        keep_recent(0);
+        assert!(recent().is_empty());
+
+        env::set_var("COMMON_TEST_LOG", "off");
+        init_from_env("COMMON_TEST_LOG").unwrap();
+        crate::error!("not even errors");
+        env::set_var("COMMON_TEST_LOG", "chatty");
+        assert!(init_from_env("COMMON_TEST_LOG").is_err());
+        assert_eq!(None, max_level());
+        set_max_level(Some(Level::Info));
+        set_output(None);
+
+        assert_eq!(0, formatted);
+        let logged = String::from_utf8(shared.0.lock().unwrap().clone()).unwrap();
+        assert_eq!("WARN  common::log::tests: disk 91% full\nERROR common::log::tests: worker 3 panicked\n", logged);
+    }
+}
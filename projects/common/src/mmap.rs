@@ -0,0 +1,130 @@
+// Memory-Mapped Files
+
+// fs::read copies the whole file into a Vec before the search can start: for a 2 GiB log that's 2 GiB of memory,
+// and the time to copy it. A memory map asks the operating system to make the file itself show up in our address space.
+// Nothing is read up front, the pages are loaded the first time the search touches them, and they come straight from
+// the page cache without a copy. --mmap searches files that way.
+
+// FileBytes is the file's contents as a &[u8], however they got there:
+    // 1. With the mmap feature (on by default, it needs the memmap2 crate) a regular, non-empty file is mapped.
+    // 2. Otherwise, and for what can't be mapped (empty files, pipes, files in /proc that have no real size), it's read into a Vec.
+// Code that uses FileBytes only sees a slice, so it works the same with and without the feature.
+
+// Mapping a file is unsafe, and it's the only unsafe block here that isn't a system call. The slice we hand out promises
+// that the bytes behind it don't change while it's borrowed, but they are the file's bytes, and any other program can change
+// the file at any time:
+    // 1. If another program writes to the file, the bytes of the slice change under us. Rust assumes a &[u8] never changes,
+    //    so in theory that's undefined behavior; in practice a search sees a mix of old and new contents.
+    // 2. If another program truncates the file, touching the pages past the new end raises SIGBUS and the process dies.
+// grep and ripgrep take the same risk for the speed, and so do we, but only when asked for with --mmap. A program that can't
+// accept it, like one that parses files an attacker can still write to, should read them instead.
+
+use std::{fs::File, io::{self, Read}, ops::Deref, path::Path};
+
+enum Contents {
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+    Read(Vec<u8>),
+}
+
+pub struct FileBytes {
+    contents: Contents,
+}
+
+impl FileBytes {
+    // Maps the file when this build can and the file allows it, reads it otherwise
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileBytes> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if cfg!(feature = "mmap") && metadata.is_file() && metadata.len() > 0 {
+            return FileBytes::map(&file);
+        }
+        FileBytes::read_from(file, metadata.len())
+    }
+
+    // Always reads the whole file into memory, like fs::read
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<FileBytes> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        FileBytes::read_from(file, len)
+    }
+
+    fn read_from(mut file: File, len_hint: u64) -> io::Result<FileBytes> {
+        let mut contents = Vec::with_capacity(len_hint as usize);
+        file.read_to_end(&mut contents)?;
+        Ok(FileBytes { contents: Contents::Read(contents) })
+    }
+
+    #[cfg(feature = "mmap")]
+    fn map(file: &File) -> io::Result<FileBytes> {
+        // SAFETY: the mapping is read-only and private to this FileBytes. Its bytes can still change if another process
+        // modifies or truncates the file while it's mapped, which is the risk --mmap accepts, see the top of this file.
+        let map = unsafe { memmap2::Mmap::map(file)? };
+        Ok(FileBytes { contents: Contents::Mapped(map) })
+    }
+
+    // Never called, open() only maps with the feature, but it has to exist for open() to compile without it
+    #[cfg(not(feature = "mmap"))]
+    fn map(file: &File) -> io::Result<FileBytes> {
+        FileBytes::read_from(file.try_clone()?, 0)
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self.contents, Contents::Read(_))
+    }
+}
+
+impl Deref for FileBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.contents {
+            #[cfg(feature = "mmap")]
+            Contents::Mapped(map) => map,
+            Contents::Read(contents) => contents,
+        }
+    }
+}
+
+// Bytes that were read some other way, like from the in-memory file systems of tests (vfs.rs)
+impl From<Vec<u8>> for FileBytes {
+    fn from(contents: Vec<u8>) -> FileBytes {
+        FileBytes { contents: Contents::Read(contents) }
+    }
+}
+
+impl AsRef<[u8]> for FileBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn maps_or_reads_the_same_bytes() {
+        let path = env::temp_dir().join(format!("common-mmap-{}", std::process::id()));
+        let contents: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &contents).unwrap();
+
+        let mapped = FileBytes::open(&path).unwrap();
+        let read = FileBytes::read(&path).unwrap();
+        assert_eq!(cfg!(feature = "mmap"), mapped.is_mapped());
+        assert!(!read.is_mapped());
+        assert_eq!(&contents[..], &mapped[..]);
+        assert_eq!(&contents[..], read.as_ref());
+        // Unmapped before the file is truncated below, see the top of this file
+        drop(mapped);
+
+        // An empty file can't be mapped, it's read instead
+        fs::write(&path, b"").unwrap();
+        let empty = FileBytes::open(&path).unwrap();
+        assert!(!empty.is_mapped() && empty.is_empty());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, FileBytes::open(&path).err().unwrap().kind());
+    }
+}
@@ -0,0 +1,372 @@
+// Progress Reporting
+
+// Searching a big directory tree takes a while, and so does a batch of jobs on a ThreadPool. Showing progress is nice,
+// but the code doing the work shouldn't have to know whether it runs in a terminal, under a script, or in a test.
+// So the work only talks to a Tracker, and the Tracker passes every update on to a Reporter, which decides what to do with it:
+    // 1. ProgressBar draws a bar on one terminal line, redrawing it in place with \r.
+    //    Redrawing costs a write per update, so it redraws at most every 100 ms, however often the work reports.
+    //    fit() makes the bar a third of the terminal wide, as term::size() measures it.
+    // 2. JsonLines writes one JSON object per update, for other programs to read.
+    // 3. Silent ignores everything, for when nobody is watching.
+
+// A Tracker is cheap to clone and all clones share the same count, so the threads of a ThreadPool can each report the items they finish.
+// Reporters are Send + Sync for the same reason.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    term::{self, Size},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress<'a> {
+    pub task: &'a str,
+    pub done: u64,
+    // None when the amount of work isn't known up front
+    pub total: Option<u64>,
+    pub finished: bool,
+}
+
+pub trait Reporter: Send + Sync {
+    fn report(&self, progress: &Progress);
+}
+
+pub struct Silent;
+
+impl Reporter for Silent {
+    fn report(&self, _progress: &Progress) {}
+}
+
+// The Tracker
+
+struct Shared {
+    reporter: Arc<dyn Reporter>,
+    task: String,
+    total: Option<u64>,
+    done: AtomicU64,
+    finished: AtomicBool,
+}
+
+#[derive(Clone)]
+pub struct Tracker {
+    shared: Arc<Shared>,
+}
+
+impl Tracker {
+    pub fn new(reporter: Arc<dyn Reporter>, task: &str, total: Option<u64>) -> Tracker {
+        Tracker {
+            shared: Arc::new(Shared {
+                reporter,
+                task: task.to_string(),
+                total,
+                done: AtomicU64::new(0),
+                finished: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn silent() -> Tracker {
+        Tracker::new(Arc::new(Silent), "", None)
+    }
+
+    pub fn inc(&self, amount: u64) {
+        let done = self.shared.done.fetch_add(amount, Ordering::Relaxed) + amount;
+        self.report(done, false);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.shared.done.load(Ordering::Relaxed)
+    }
+
+    // Reports the final count. Only the first call does anything, so every clone may call it.
+    pub fn finish(&self) {
+        if !self.shared.finished.swap(true, Ordering::Relaxed) {
+            self.report(self.done(), true);
+        }
+    }
+
+    fn report(&self, done: u64, finished: bool) {
+        let shared = &self.shared;
+        shared.reporter.report(&Progress { task: &shared.task, done, total: shared.total, finished });
+    }
+}
+
+// Choosing a Reporter from the command line, like minigrep --progress or --progress=json
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    Bar,
+    Json,
+}
+
+impl std::str::FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProgressFormat, String> {
+        match s {
+            "bar" => Ok(ProgressFormat::Bar),
+            "json" => Ok(ProgressFormat::Json),
+            _ => Err(String::from("expected bar or json")),
+        }
+    }
+}
+
+impl ProgressFormat {
+    // Both write to stderr, so the progress doesn't end up in the output when stdout is redirected to a file
+    pub fn stderr_reporter(self) -> Arc<dyn Reporter> {
+        match self {
+            ProgressFormat::Bar => Arc::new(ProgressBar::new(io::stderr()).fit(term::size())),
+            ProgressFormat::Json => Arc::new(JsonLines::new(io::stderr())),
+        }
+    }
+}
+
+// A Progress Bar
+
+pub const DEFAULT_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+struct BarState<W> {
+    out: W,
+    last_draw: Option<Instant>,
+    // Length of the line drawn last, a shorter line has to overwrite the rest of it with spaces
+    last_len: usize,
+}
+
+pub struct ProgressBar<W> {
+    state: Mutex<BarState<W>>,
+    width: usize,
+    interval: Duration,
+    // Only there so that tests can see the throttling work without sleeping, see clock.rs
+    clock: Arc<dyn Clock>,
+}
+
+impl<W: Write + Send> ProgressBar<W> {
+    pub fn new(out: W) -> ProgressBar<W> {
+        ProgressBar {
+            state: Mutex::new(BarState { out, last_draw: None, last_len: 0 }),
+            width: 30,
+            interval: DEFAULT_REDRAW_INTERVAL,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Width of the bar itself, between the brackets
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    // A third of the columns of the terminal, the rest is for the task and the numbers. Without a size the width stays
+    pub fn fit(self, size: Option<Size>) -> Self {
+        match size {
+            Some(size) => self.width((size.cols as usize / 3).clamp(10, 60)),
+            None => self,
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.state.into_inner().unwrap().out
+    }
+
+    fn render(&self, progress: &Progress) -> String {
+        let mut line = format!("{} ", progress.task);
+        match progress.total {
+            Some(total) => {
+                let fraction = if total == 0 { 1.0 } else { (progress.done as f64 / total as f64).min(1.0) };
+                let filled = (fraction * self.width as f64) as usize;
+                let _ = write!(
+                    line,
+                    "[{}{}] {}/{} {:.0}%",
+                    "#".repeat(filled),
+                    "-".repeat(self.width - filled),
+                    progress.done,
+                    total,
+                    fraction * 100.0
+                );
+            }
+            None => {
+                let _ = write!(line, "{} done", progress.done);
+            }
+        }
+        line
+    }
+}
+
+impl<W: Write + Send> Reporter for ProgressBar<W> {
+    fn report(&self, progress: &Progress) {
+        let mut state = self.state.lock().unwrap();
+        let now = self.clock.now();
+        // The final state is always drawn, or the bar could stop short of 100%
+        if let Some(last) = state.last_draw {
+            if !progress.finished && now.duration_since(last) < self.interval {
+                return;
+            }
+        }
+        state.last_draw = Some(now);
+
+        let line = self.render(progress);
+        let padding = state.last_len.saturating_sub(line.len());
+        state.last_len = line.len();
+        let end = if progress.finished { "\n" } else { "" };
+        // A progress bar that can't be drawn isn't worth failing the work for
+        let _ = write!(state.out, "\r{line}{}{end}", " ".repeat(padding));
+        let _ = state.out.flush();
+    }
+}
+
+// JSON Lines
+
+pub struct JsonLines<W> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(out: W) -> JsonLines<W> {
+        JsonLines { out: Mutex::new(out) }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> Reporter for JsonLines<W> {
+    fn report(&self, progress: &Progress) {
+        let total = progress.total.map_or(String::from("null"), |total| total.to_string());
+        let line = format!(
+            "{{\"task\":\"{}\",\"done\":{},\"total\":{},\"finished\":{}}}\n",
+            json_escape(progress.task),
+            progress.done,
+            total,
+            progress.finished
+        );
+        // One write_all per line, under the lock, so lines from different threads don't get mixed up
+        let _ = self.out.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use std::thread;
+
+    #[test]
+    fn bar_draws_in_place_and_ends_the_line() {
+        let bar = Arc::new(ProgressBar::new(Vec::new()).width(10).interval(Duration::ZERO));
+        let tracker = Tracker::new(bar.clone(), "files", Some(4));
+        tracker.inc(1);
+        tracker.inc(3);
+        tracker.finish();
+        tracker.finish();
+        drop(tracker);
+
+        let out = String::from_utf8(Arc::try_unwrap(bar).ok().unwrap().into_inner()).unwrap();
+        assert_eq!(
+            "\rfiles [##--------] 1/4 25%\rfiles [##########] 4/4 100%\rfiles [##########] 4/4 100%\n",
+            out
+        );
+    }
+
+    #[test]
+    fn bar_fits_the_terminal() {
+        let width = |size| ProgressBar::new(Vec::new()).fit(size).width;
+        assert_eq!(40, width(Some(Size { cols: 120, rows: 40 })));
+        assert_eq!(10, width(Some(Size { cols: 20, rows: 5 })));
+        assert_eq!(30, width(None));
+    }
+
+    #[test]
+    fn bar_without_a_total_clears_longer_lines() {
+        let bar = ProgressBar::new(Vec::new()).interval(Duration::ZERO);
+        bar.report(&Progress { task: "scanning slowly", done: 100, total: None, finished: false });
+        bar.report(&Progress { task: "done", done: 100, total: None, finished: true });
+        let out = String::from_utf8(bar.into_inner()).unwrap();
+        assert_eq!("\rscanning slowly 100 done\rdone 100 done           \n", out);
+    }
+
+    #[test]
+    fn redraws_are_throttled() {
+        let bar = Arc::new(ProgressBar::new(Vec::new()).interval(Duration::from_secs(3600)));
+        let tracker = Tracker::new(bar.clone(), "jobs", Some(1000));
+        for _ in 0..1000 {
+            tracker.inc(1);
+        }
+        tracker.finish();
+        drop(tracker);
+
+        let out = String::from_utf8(Arc::try_unwrap(bar).ok().unwrap().into_inner()).unwrap();
+        // The first update and the final one
+        assert_eq!(2, out.matches('\r').count(), "{out:?}");
+        assert!(out.ends_with("1000/1000 100%\n"));
+    }
+
+    #[test]
+    fn redraws_again_once_the_interval_is_over() {
+        let clock = Arc::new(FakeClock::new());
+        let bar = Arc::new(ProgressBar::new(Vec::new()).clock(clock.clone()));
+        let tracker = Tracker::new(bar.clone(), "files", Some(10));
+        tracker.inc(1);
+        tracker.inc(1);
+        clock.advance(DEFAULT_REDRAW_INTERVAL);
+        tracker.inc(1);
+        drop(tracker);
+
+        let out = String::from_utf8(Arc::try_unwrap(bar).ok().unwrap().into_inner()).unwrap();
+        assert!(out.contains("1/10") && !out.contains("2/10") && out.contains("3/10"), "{out:?}");
+    }
+
+    #[test]
+    fn json_lines_from_many_threads() {
+        let json = Arc::new(JsonLines::new(Vec::new()));
+        let tracker = Tracker::new(json.clone(), "say \"hi\"", None);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| (0..25).for_each(|_| tracker.inc(1)));
+            }
+        });
+        tracker.finish();
+        drop(tracker);
+
+        let out = String::from_utf8(Arc::try_unwrap(json).ok().unwrap().into_inner()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(101, lines.len());
+        assert!(lines.iter().all(|line| line.starts_with("{\"task\":\"say \\\"hi\\\"\",\"done\":")));
+        assert_eq!("{\"task\":\"say \\\"hi\\\"\",\"done\":100,\"total\":null,\"finished\":true}", lines[100]);
+    }
+}
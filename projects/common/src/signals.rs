@@ -0,0 +1,358 @@
+// Signals and Shutting Down
+
+// Ctrl-C in the terminal sends the program SIGINT, kill sends it SIGTERM, and closing the terminal SIGHUP. Unless the program
+// says otherwise, each of them ends it on the spot: no connection is told goodbye, and nothing buffered is written out.
+// Saying otherwise is harder than it sounds, because a signal handler interrupts whatever the thread was doing, maybe in the
+// middle of holding a lock, so it may only do a few things that can't get stuck. Taking a Mutex or allocating isn't one of them.
+// So the handler here only writes the signal's number into a pipe (the self-pipe trick), and the rest happens in a thread:
+    // 1. A watcher thread reads the pipe, and sends each signal to every Receiver that listen() gave out for it.
+    // 2. A Shutdown is the things to do before the program ends: stop accepting, drain the connections, flush the logs.
+    //    Its hooks run in the order they were added, and one that panics doesn't stop the ones after it.
+    // 3. Shutdown::on_signals runs them when the first of the signals comes. A second one ends the program right away,
+    //    for when draining hangs and whoever pressed Ctrl-C presses it again.
+// The handlers are set with signal() from the C library, through FFI like term.rs does. Windows has no signals, only the console's
+// Ctrl-C and Ctrl-Break, which come as Interrupt, and closing the window, which comes as Terminate. Hangup never comes there.
+// send() delivers a signal as if it had come from outside, for a "shutdown" typed on stdin, and for the tests.
+
+use std::{
+    any::Any,
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    process,
+    sync::{mpsc, Mutex, Once},
+    thread,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+    Hangup,
+}
+
+impl Signal {
+    pub const ALL: [Signal; 3] = [Signal::Interrupt, Signal::Terminate, Signal::Hangup];
+
+    // The same on Linux and macOS
+    pub fn number(self) -> i32 {
+        match self {
+            Signal::Hangup => 1,
+            Signal::Interrupt => 2,
+            Signal::Terminate => 15,
+        }
+    }
+
+    pub fn from_number(number: i32) -> Option<Signal> {
+        Signal::ALL.into_iter().find(|signal| signal.number() == number)
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Terminate => "SIGTERM",
+            Signal::Hangup => "SIGHUP",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::Signal;
+    use std::{
+        io,
+        sync::atomic::{AtomicI32, Ordering},
+    };
+
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+        fn pipe(fds: *mut i32) -> i32;
+        fn read(fd: i32, buf: *mut u8, count: usize) -> isize;
+        fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    }
+
+    const SIG_ERR: usize = usize::MAX;
+    static READ_FD: AtomicI32 = AtomicI32::new(-1);
+    static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+    // Runs in the middle of whatever the thread was doing: one write to the pipe, which is safe there, and nothing else.
+    // A full pipe drops the signal, there are plenty of that one waiting already
+    extern "C" fn handler(signum: i32) {
+        let byte = signum as u8;
+        // SAFETY: write only reads the one byte
+        unsafe { write(WRITE_FD.load(Ordering::Relaxed), &byte, 1) };
+    }
+
+    // Only called by listen(), under its lock, so the pipe is only made once
+    pub fn install(which: Signal) -> io::Result<()> {
+        if WRITE_FD.load(Ordering::Relaxed) < 0 {
+            let mut fds = [0; 2];
+            // SAFETY: pipe writes two file descriptors to the array
+            if unsafe { pipe(fds.as_mut_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            READ_FD.store(fds[0], Ordering::Relaxed);
+            WRITE_FD.store(fds[1], Ordering::Relaxed);
+        }
+        // SAFETY: the handler is an extern "C" fn(i32), what signal expects
+        match unsafe { signal(which.number(), handler as extern "C" fn(i32) as usize) } {
+            SIG_ERR => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    // Blocks until the handler writes a signal, None if the pipe is gone
+    pub fn next() -> Option<Signal> {
+        loop {
+            let mut byte = 0;
+            // SAFETY: read writes at most one byte into it
+            match unsafe { read(READ_FD.load(Ordering::Relaxed), &mut byte, 1) } {
+                1 => return Signal::from_number(byte as i32),
+                // Interrupted by a signal, which is in the pipe now
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::Signal;
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Mutex, OnceLock,
+        },
+    };
+
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+
+    type Channel = (Mutex<mpsc::Sender<Signal>>, Mutex<mpsc::Receiver<Signal>>);
+    static CHANNEL: OnceLock<Channel> = OnceLock::new();
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+    fn channel() -> &'static Channel {
+        CHANNEL.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel();
+            (Mutex::new(sender), Mutex::new(receiver))
+        })
+    }
+
+    // Windows calls it on a thread of its own, so it may take a lock. Returning 1 says the event was handled
+    extern "system" fn handler(event: u32) -> i32 {
+        let signal = match event {
+            0 | 1 => Signal::Interrupt,
+            2 | 5 | 6 => Signal::Terminate,
+            _ => return 0,
+        };
+        // Nobody is listening anymore
+        let _ = channel().0.lock().unwrap().send(signal);
+        1
+    }
+
+    pub fn install(signal: Signal) -> io::Result<()> {
+        // One handler gets both, and a second one would send every signal twice
+        if signal == Signal::Hangup || INSTALLED.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        channel();
+        // SAFETY: the handler is an extern "system" fn(u32) -> i32, what SetConsoleCtrlHandler expects
+        match unsafe { SetConsoleCtrlHandler(Some(handler), 1) } {
+            0 => Err(io::Error::last_os_error()),
+            _ => {
+                INSTALLED.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn next() -> Option<Signal> {
+        channel().1.lock().unwrap().recv().ok()
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use super::Signal;
+    use std::io;
+
+    pub fn install(_signal: Signal) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "signals are only implemented for Unix and Windows"))
+    }
+
+    pub fn next() -> Option<Signal> {
+        None
+    }
+}
+
+// Listening
+
+struct Registry {
+    installed: Vec<Signal>,
+    listeners: Vec<(Vec<Signal>, mpsc::Sender<Signal>)>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry { installed: Vec::new(), listeners: Vec::new() });
+static WATCHER: Once = Once::new();
+
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// A Receiver that gets every one of these signals from now on. Once any signal has a handler, the program doesn't end
+// when that signal comes anymore, whether anybody still listens or not
+pub fn listen(signals: &[Signal]) -> io::Result<mpsc::Receiver<Signal>> {
+    let mut registry = registry();
+    for &signal in signals {
+        if !registry.installed.contains(&signal) {
+            sys::install(signal)?;
+            registry.installed.push(signal);
+        }
+    }
+    WATCHER.call_once(|| {
+        thread::spawn(|| {
+            while let Some(signal) = sys::next() {
+                send(signal);
+            }
+        });
+    });
+    let (sender, receiver) = mpsc::channel();
+    registry.listeners.push((signals.to_vec(), sender));
+    Ok(receiver)
+}
+
+// Delivers the signal to its listeners as if it had come from outside. The ones whose Receiver is gone are forgotten
+pub fn send(signal: Signal) {
+    registry().listeners.retain(|(signals, sender)| !signals.contains(&signal) || sender.send(signal).is_ok());
+}
+
+// Shutting Down
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+pub struct Shutdown {
+    hooks: Vec<(String, Hook)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn hook(mut self, name: &str, hook: impl FnOnce() + Send + 'static) -> Self {
+        self.add(name, hook);
+        self
+    }
+
+    pub fn add(&mut self, name: &str, hook: impl FnOnce() + Send + 'static) {
+        self.hooks.push((name.to_string(), Box::new(hook)));
+    }
+
+    // The other one's hooks, after these
+    pub fn then(mut self, other: Shutdown) -> Self {
+        self.hooks.extend(other.hooks);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    // Every hook, in the order they were added. A panic is logged and the next hook runs anyway
+    pub fn run(self) {
+        for (name, hook) in self.hooks {
+            crate::debug!("shutting down: {name}");
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(hook)) {
+                crate::error!("shutting down: {name} panicked: {}", message(&*panic));
+            }
+        }
+    }
+
+    // Runs the hooks on a thread of their own when the first of the signals comes, and returns it from join()
+    pub fn on_signals(self, signals: &[Signal]) -> io::Result<thread::JoinHandle<Signal>> {
+        let receiver = listen(signals)?;
+        Ok(thread::spawn(move || {
+            // The Sender is in the registry, which is never dropped
+            let signal = receiver.recv().unwrap();
+            crate::info!("{signal}, shutting down");
+            thread::spawn(move || {
+                if let Ok(again) = receiver.recv() {
+                    crate::warn!("{again} again, exiting without waiting");
+                    process::exit(128 + again.number());
+                }
+            });
+            self.run();
+            signal
+        }))
+    }
+}
+
+fn message(panic: &(dyn Any + Send)) -> &str {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "something that isn't a string",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[test]
+    fn hooks_run_in_order_past_panics() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let step = |n| {
+            let ran = Arc::clone(&ran);
+            move || ran.lock().unwrap().push(n)
+        };
+        let servers = Shutdown::new().hook("stop accepting", step(1)).hook("oops", || panic!("a bug")).hook("drain", step(2));
+        let mut logs = Shutdown::new();
+        logs.add("flush", step(3));
+
+        let shutdown = servers.then(logs);
+        assert_eq!(4, shutdown.len());
+        shutdown.run();
+        assert_eq!(vec![1, 2, 3], *ran.lock().unwrap());
+    }
+
+    #[test]
+    fn signals_reach_their_listeners() {
+        let hangups = listen(&[Signal::Hangup]).unwrap();
+        let (sender, ran) = mpsc::channel();
+        let shutdown = Shutdown::new().hook("tell the test", move || sender.send(()).unwrap());
+        let handle = shutdown.on_signals(&[Signal::Terminate]).unwrap();
+
+        send(Signal::Terminate);
+        assert_eq!(Signal::Terminate, handle.join().unwrap());
+        assert_eq!(Ok(()), ran.try_recv());
+        assert!(hangups.try_recv().is_err());
+
+        // A real one, through the handler, the pipe and the watcher thread
+        #[cfg(unix)]
+        {
+            extern "C" {
+                fn raise(signum: i32) -> i32;
+            }
+            // SAFETY: SIGHUP has our handler now, so it doesn't end the test
+            assert_eq!(0, unsafe { raise(Signal::Hangup.number()) });
+            assert_eq!(Ok(Signal::Hangup), hangups.recv_timeout(Duration::from_secs(5)));
+        }
+        assert_eq!((Some(Signal::Interrupt), "SIGINT"), (Signal::from_number(2), Signal::Interrupt.to_string().as_str()));
+    }
+}
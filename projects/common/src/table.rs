@@ -0,0 +1,299 @@
+// Text Tables
+
+// Lining up columns looks like it only needs format!("{:<10}"), until a cell holds "naïve" written with a combining accent,
+// or "東京", or an emoji. The padding of format! counts chars, but a terminal draws:
+    // 1. A grapheme cluster (what a reader sees as one character) in one cell, however many chars it's made of.
+    //    "é" can be one char or an "e" followed by a combining accent, either way it takes up one column.
+    // 2. Wide characters, like CJK ideographs, fullwidth forms and most emoji, in two columns.
+// So widths here are counted in columns, with unicode_ext::display_width from std_collections: the text is split into
+// graphemes, and each grapheme is 0, 1 or 2 columns wide depending on its first char.
+
+// A Table has a header, rows of cells, an alignment and an optional maximum width per column, and a Style for its borders.
+// Cells that are too wide are cut off with an ellipsis. A cell is one line, a newline in it would break the layout.
+// Displaying the Table renders it, so it can go straight into println! or eprint!.
+// header_style paints the header with a common::term::Style, bold say, for a terminal. The escapes go around the padded cells,
+// so they don't count towards the widths.
+
+use std::fmt;
+
+use crate::term;
+use std_collections::unicode_ext;
+
+// Widths
+
+// The widths themselves come from std_collections' unicode_ext.rs, with the other grapheme-safe string helpers
+pub use std_collections::unicode_ext::display_width;
+
+// Cuts the text down to at most width columns, ending it with … when something was cut off.
+// Whole graphemes are dropped, never half of one, so the result may be a column shorter than width next to a wide character.
+pub fn truncate(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    // One column is kept free for the ellipsis
+    format!("{}…", unicode_ext::fit_width(text, width - 1))
+}
+
+// Tables
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    // Columns separated by two spaces, and a line of dashes under the header
+    Plain,
+    // +----+ borders that work in any terminal
+    Ascii,
+    // Box-drawing characters
+    Unicode,
+}
+
+// The characters a style draws its lines with: the three horizontal lines are made of `line` with a left, middle and right piece
+struct Border {
+    vertical: char,
+    line: char,
+    top: [char; 3],
+    middle: [char; 3],
+    bottom: [char; 3],
+}
+
+impl Style {
+    fn border(self) -> Option<Border> {
+        match self {
+            Style::Plain => None,
+            Style::Ascii => Some(Border { vertical: '|', line: '-', top: ['+'; 3], middle: ['+'; 3], bottom: ['+'; 3] }),
+            Style::Unicode => Some(Border {
+                vertical: '│',
+                line: '─',
+                top: ['┌', '┬', '┐'],
+                middle: ['├', '┼', '┤'],
+                bottom: ['└', '┴', '┘'],
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    header: String,
+    align: Align,
+    max_width: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<String>>,
+    style: Style,
+    header_style: term::Style,
+}
+
+impl Table {
+    pub fn new<I, S>(headers: I) -> Table
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let columns = headers.into_iter().map(|h| Column { header: h.into(), align: Align::Left, max_width: None }).collect();
+        Table { columns, rows: Vec::new(), style: Style::Plain, header_style: term::Style::new() }
+    }
+
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn header_style(mut self, style: term::Style) -> Self {
+        self.header_style = style;
+        self
+    }
+
+    // Both panic for a column that doesn't exist, that's a bug in the code building the table
+    pub fn align(mut self, column: usize, align: Align) -> Self {
+        self.columns[column].align = align;
+        self
+    }
+
+    pub fn max_width(mut self, column: usize, width: usize) -> Self {
+        self.columns[column].max_width = Some(width);
+        self
+    }
+
+    // Rows are usually added in a loop, so this one takes &mut self instead of being part of the builder.
+    // A short row is filled up with empty cells, cells past the last column are dropped.
+    pub fn add_row<I, S>(&mut self, cells: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        let mut row: Vec<String> = cells.into_iter().take(self.columns.len()).map(|c| c.to_string()).collect();
+        row.resize(self.columns.len(), String::new());
+        self.rows.push(row);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // Every cell after truncation, with the header as row 0
+    fn cells(&self) -> Vec<Vec<String>> {
+        let header = self.columns.iter().map(|c| c.header.clone()).collect();
+        std::iter::once(header)
+            .chain(self.rows.iter().cloned())
+            .map(|row| {
+                row.iter()
+                    .zip(&self.columns)
+                    .map(|(cell, column)| match column.max_width {
+                        Some(width) => truncate(cell, width),
+                        None => cell.clone(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+fn pad(cell: &str, width: usize, align: Align) -> String {
+    let space = width.saturating_sub(display_width(cell));
+    let (left, right) = match align {
+        Align::Left => (0, space),
+        Align::Right => (space, 0),
+        Align::Center => (space / 2, space - space / 2),
+    };
+    format!("{}{cell}{}", " ".repeat(left), " ".repeat(right))
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells = self.cells();
+        let widths: Vec<usize> = (0..self.columns.len())
+            .map(|i| cells.iter().map(|row| display_width(&row[i])).max().unwrap_or(0))
+            .collect();
+        let line = |row: &[String]| -> Vec<String> {
+            row.iter().zip(&widths).zip(&self.columns).map(|((cell, &w), column)| pad(cell, w, column.align)).collect()
+        };
+        let header = |row: &[String]| -> Vec<String> { line(row).iter().map(|cell| self.header_style.paint(cell)).collect() };
+
+        let Some(border) = self.style.border() else {
+            // Without borders, the spaces after the last column would only be trailing whitespace
+            for (i, row) in cells.iter().enumerate() {
+                let text = line(row).join("  ");
+                match i {
+                    0 => writeln!(f, "{}", self.header_style.paint(text.trim_end()))?,
+                    _ => writeln!(f, "{}", text.trim_end())?,
+                }
+                if i == 0 {
+                    let dashes: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+                    writeln!(f, "{}", dashes.join("  "))?;
+                }
+            }
+            return Ok(());
+        };
+
+        let rule = |[left, middle, right]: [char; 3]| {
+            let segments: Vec<String> = widths.iter().map(|&w| border.line.to_string().repeat(w + 2)).collect();
+            format!("{left}{}{right}", segments.join(&middle.to_string()))
+        };
+        let separator = format!(" {} ", border.vertical);
+
+        writeln!(f, "{}", rule(border.top))?;
+        for (i, row) in cells.iter().enumerate() {
+            let cells = if i == 0 { header(row) } else { line(row) };
+            writeln!(f, "{0} {1} {0}", border.vertical, cells.join(&separator))?;
+            if i == 0 {
+                writeln!(f, "{}", rule(border.middle))?;
+            }
+        }
+        writeln!(f, "{}", rule(border.bottom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths_count_columns_not_chars() {
+        assert_eq!(5, display_width("hello"));
+        // An e followed by a combining acute accent is two chars but one grapheme
+        assert_eq!(5, display_width("nai\u{0308}ve"));
+        assert_eq!(4, display_width("東京"));
+        assert_eq!(2, display_width("🦀"));
+        assert_eq!(0, display_width(""));
+    }
+
+    #[test]
+    fn truncation_adds_an_ellipsis() {
+        assert_eq!("hello", truncate("hello", 5));
+        assert_eq!("hel…", truncate("hello", 4));
+        assert_eq!("…", truncate("hello", 1));
+        assert_eq!("", truncate("hello", 0));
+        // 東 takes 2 columns, a second one wouldn't leave room for the ellipsis
+        assert_eq!("東…", truncate("東京都", 4));
+        assert_eq!("nai\u{0308}…", truncate("nai\u{0308}ve", 4));
+    }
+
+    fn sample(style: Style) -> Table {
+        let mut table = Table::new(["File", "Matches"]).style(style).align(1, Align::Right);
+        table.add_row(["poem.txt", "3"]);
+        table.add_row(["東京.txt", "12"]);
+        table
+    }
+
+    #[test]
+    fn plain_tables() {
+        let expected = "\
+File      Matches
+--------  -------
+poem.txt        3
+東京.txt       12
+";
+        assert_eq!(expected, sample(Style::Plain).to_string());
+    }
+
+    #[test]
+    fn bordered_tables() {
+        let expected = "\
++----------+---------+
+| File     | Matches |
++----------+---------+
+| poem.txt |       3 |
+| 東京.txt |      12 |
++----------+---------+
+";
+        assert_eq!(expected, sample(Style::Ascii).to_string());
+
+        // The whole table is in tests/snapshots/table_unicode.snap, see testing/test_support
+        test_support::assert_snapshot!("table_unicode", sample(Style::Unicode).to_string());
+    }
+
+    #[test]
+    fn rows_are_fitted_to_the_columns() {
+        let mut table = Table::new(["Name", "Note"]).max_width(1, 6).align(0, Align::Center);
+        table.add_row(["a"]);
+        table.add_row(["bcd", "far too long", "dropped"]);
+        assert_eq!(2, table.len());
+        assert_eq!("Name  Note\n----  ------\n a\nbcd   far t…\n", table.to_string());
+    }
+
+    #[test]
+    fn painted_headers_keep_their_widths() {
+        let bold = term::Style::new().bold();
+        let plain = sample(Style::Plain).header_style(bold).to_string();
+        assert!(plain.starts_with("\x1b[1mFile      Matches\x1b[0m\n--------  -------\n"), "{plain:?}");
+        let ascii = sample(Style::Ascii).header_style(bold).to_string();
+        assert!(ascii.contains("\n| \x1b[1mFile    \x1b[0m | \x1b[1mMatches\x1b[0m |\n"), "{ascii:?}");
+    }
+}
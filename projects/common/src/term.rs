@@ -0,0 +1,485 @@
+// Terminals
+
+// A terminal is a program's stdout like a file is, and what makes it more than that are escape sequences: ESC [ and a few
+// characters that move the cursor, change the color, or clear a line instead of printing something. They are the ANSI ones,
+// which every terminal people still use understands. The programs kept writing them inline, "\r\x1b[K" and the like, and
+// this module gives them names:
+    // 1. RawMode gets every key as it's pressed, without echo, for the line editor of kvstore's REPL.
+    // 2. size() is how many columns and rows the terminal has, for the progress bar and anything that fills the screen.
+    // 3. move_to, up, right and the constants like CLEAR_LINE move the cursor and clear the screen.
+    // 4. A Style is colors and attributes like bold, and paint() wraps a text in them. use_color says whether to:
+    //    not into a file or a pipe, where the escapes would only be noise, and not when NO_COLOR is set (no-color.org).
+    // 5. A Screen is double buffered: a frame is drawn into the back buffer, and render() writes only the cells that
+    //    changed since the front one, the frame before, so a full screen redrawn ten times a second doesn't flicker.
+
+// RawMode and size() ask the terminal driver with tcgetattr, tcsetattr and ioctl, functions of the C library, through FFI like
+// advanced_features/unsafe does. The structs are laid out the way Linux does it. Everywhere else raw mode is an error, and size()
+// only knows the COLUMNS and LINES environment variables.
+
+use std::{
+    env,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+// Raw Mode
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::{ffi::c_ulong, io, mem::MaybeUninit};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        iflag: u32,
+        oflag: u32,
+        cflag: u32,
+        lflag: u32,
+        line: u8,
+        cc: [u8; 32],
+        ispeed: u32,
+        ospeed: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Winsize {
+        rows: u16,
+        cols: u16,
+        xpixel: u16,
+        ypixel: u16,
+    }
+
+    extern "C" {
+        fn tcgetattr(fd: i32, termios: *mut Termios) -> i32;
+        fn tcsetattr(fd: i32, actions: i32, termios: *const Termios) -> i32;
+        fn ioctl(fd: i32, request: c_ulong, ...) -> i32;
+    }
+
+    const TCSAFLUSH: i32 = 2;
+    const TIOCGWINSZ: c_ulong = 0x5413;
+    // Input: Ctrl-S and Ctrl-Q stop the output, and Enter is translated to \n
+    const IXON: u32 = 0o2000;
+    const ICRNL: u32 = 0o400;
+    // Local: signals for Ctrl-C and Ctrl-Z, line at a time, echo, and Ctrl-V
+    const ISIG: u32 = 0o1;
+    const ICANON: u32 = 0o2;
+    const ECHO: u32 = 0o10;
+    const IEXTEN: u32 = 0o100000;
+    const VTIME: usize = 5;
+    const VMIN: usize = 6;
+
+    pub fn get(fd: i32) -> io::Result<Termios> {
+        let mut termios = MaybeUninit::<Termios>::uninit();
+        // SAFETY: tcgetattr fills in the whole struct when it returns 0, and the struct has the layout it expects
+        unsafe {
+            if tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(termios.assume_init())
+        }
+    }
+
+    pub fn set(fd: i32, termios: &Termios) -> io::Result<()> {
+        // SAFETY: tcsetattr only reads the struct
+        match unsafe { tcsetattr(fd, TCSAFLUSH, termios) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    // The output is left as it is, so \n still goes to the start of the next line. read() waits for one byte, however long
+    pub fn raw(original: &Termios) -> Termios {
+        let mut raw = *original;
+        raw.iflag &= !(IXON | ICRNL);
+        raw.lflag &= !(ISIG | ICANON | ECHO | IEXTEN);
+        raw.cc[VMIN] = 1;
+        raw.cc[VTIME] = 0;
+        raw
+    }
+
+    pub fn size(fd: i32) -> Option<(u16, u16)> {
+        let mut size = Winsize::default();
+        // SAFETY: TIOCGWINSZ writes a winsize to the pointer, and fails on anything that isn't a terminal
+        let result = unsafe { ioctl(fd, TIOCGWINSZ, &mut size as *mut Winsize) };
+        (result == 0 && size.cols > 0 && size.rows > 0).then_some((size.cols, size.rows))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    #[derive(Clone, Copy)]
+    pub struct Termios;
+
+    pub fn get(_fd: i32) -> io::Result<Termios> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "raw mode is only implemented for Linux"))
+    }
+
+    pub fn set(_fd: i32, _termios: &Termios) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn raw(original: &Termios) -> Termios {
+        *original
+    }
+
+    pub fn size(_fd: i32) -> Option<(u16, u16)> {
+        None
+    }
+}
+
+const STDIN: i32 = 0;
+
+// Raw mode for stdin for as long as it lives. Dropping it puts the terminal back the way it was, also when the program panics
+pub struct RawMode {
+    original: sys::Termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        let original = sys::get(STDIN)?;
+        sys::set(STDIN, &sys::raw(&original))?;
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // There's nothing left to do about a terminal that won't go back
+        let _ = sys::set(STDIN, &self.original);
+    }
+}
+
+// Size
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+// The size of the terminal stdout, stderr or stdin is, whichever is one, then COLUMNS and LINES, None when nothing says
+pub fn size() -> Option<Size> {
+    let from_env = || {
+        let var = |name| env::var(name).ok()?.parse().ok().filter(|&n| n > 0);
+        Some((var("COLUMNS")?, var("LINES")?))
+    };
+    let (cols, rows) = [1, 2, STDIN].into_iter().find_map(sys::size).or_else(from_env)?;
+    Some(Size { cols, rows })
+}
+
+// The Cursor and the Screen
+
+pub const CLEAR_SCREEN: &str = "\x1b[2J";
+pub const CLEAR_LINE: &str = "\x1b[2K";
+// From the cursor to the end of the line, what's left of an older, longer line
+pub const CLEAR_TO_END_OF_LINE: &str = "\x1b[K";
+pub const HIDE_CURSOR: &str = "\x1b[?25l";
+pub const SHOW_CURSOR: &str = "\x1b[?25h";
+// A screen of its own that the terminal throws away again at the end, so the shell's lines are still there afterwards
+pub const ALTERNATE_SCREEN: &str = "\x1b[?1049h";
+pub const MAIN_SCREEN: &str = "\x1b[?1049l";
+pub const RESET: &str = "\x1b[0m";
+
+// Rows and columns count from 0 here, and from 1 in the escape sequence
+pub fn move_to(row: u16, col: u16) -> String {
+    format!("\x1b[{};{}H", row + 1, col + 1)
+}
+
+// Moving by 0 is nothing, not the 1 that ESC [ 0 C would move
+fn relative(n: u16, letter: char) -> String {
+    match n {
+        0 => String::new(),
+        n => format!("\x1b[{n}{letter}"),
+    }
+}
+
+pub fn up(n: u16) -> String {
+    relative(n, 'A')
+}
+
+pub fn down(n: u16) -> String {
+    relative(n, 'B')
+}
+
+pub fn right(n: u16) -> String {
+    relative(n, 'C')
+}
+
+pub fn left(n: u16) -> String {
+    relative(n, 'D')
+}
+
+// Colors and Styles
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    // Bright black, which most terminals show as gray
+    Gray,
+    // One of the 256 colors of the xterm palette, and a color by its red, green and blue, for terminals that have them
+    Ansi256(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    // The parameters of the escape for it in the foreground. The background ones are 10 more, or 48 instead of 38
+    fn codes(self, background: bool) -> String {
+        let offset = if background { 10 } else { 0 };
+        match self {
+            Color::Gray => (90 + offset).to_string(),
+            Color::Ansi256(n) => format!("{};5;{n}", 38 + offset),
+            Color::Rgb(r, g, b) => format!("{};2;{r};{g};{b}", 38 + offset),
+            basic => {
+                let n = match basic {
+                    Color::Black => 0,
+                    Color::Red => 1,
+                    Color::Green => 2,
+                    Color::Yellow => 3,
+                    Color::Blue => 4,
+                    Color::Magenta => 5,
+                    Color::Cyan => 6,
+                    _ => 7,
+                };
+                (30 + offset + n).to_string()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style::new()
+    }
+}
+
+impl Style {
+    // const, so that a Style can be in a const like the blank cell of a Screen
+    pub const fn new() -> Style {
+        Style { fg: None, bg: None, bold: false, dim: false, italic: false, underline: false, reverse: false }
+    }
+
+    pub fn fg(mut self, color: Color) -> Self {
+        self.fg = Some(color);
+        self
+    }
+
+    pub fn bg(mut self, color: Color) -> Self {
+        self.bg = Some(color);
+        self
+    }
+
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    pub fn dim(mut self) -> Self {
+        self.dim = true;
+        self
+    }
+
+    pub fn italic(mut self) -> Self {
+        self.italic = true;
+        self
+    }
+
+    pub fn underline(mut self) -> Self {
+        self.underline = true;
+        self
+    }
+
+    // The foreground and background swapped, what a selected line looks like
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    // The escape that turns the style on, "" for a plain one
+    pub fn codes(&self) -> String {
+        let attributes = [(self.bold, "1"), (self.dim, "2"), (self.italic, "3"), (self.underline, "4"), (self.reverse, "7")];
+        let mut codes: Vec<String> = attributes.iter().filter(|(on, _)| *on).map(|(_, code)| code.to_string()).collect();
+        codes.extend(self.fg.map(|color| color.codes(false)));
+        codes.extend(self.bg.map(|color| color.codes(true)));
+        match codes.is_empty() {
+            true => String::new(),
+            false => format!("\x1b[{}m", codes.join(";")),
+        }
+    }
+
+    // The text in the style, and everything after it back to normal
+    pub fn paint(&self, text: &str) -> String {
+        match self.is_plain() {
+            true => text.to_string(),
+            false => format!("{}{text}{RESET}", self.codes()),
+        }
+    }
+}
+
+// Whether to write colors to a stream, given whether it is a terminal: std::io::IsTerminal says that
+pub fn use_color(is_terminal: bool) -> bool {
+    is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+// The Screen
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    ch: char,
+    style: Style,
+}
+
+const BLANK: Cell = Cell { ch: ' ', style: Style::new() };
+
+// Every char takes one cell, so wide characters like CJK and most emoji put the rest of their row off by one
+pub struct Screen {
+    size: Size,
+    // What the terminal shows, and the frame being drawn
+    front: Vec<Cell>,
+    back: Vec<Cell>,
+    // The first frame clears the screen, whatever was on it before
+    cleared: bool,
+}
+
+impl Screen {
+    pub fn new(size: Size) -> Screen {
+        let cells = size.cols as usize * size.rows as usize;
+        Screen { size, front: vec![BLANK; cells], back: vec![BLANK; cells], cleared: false }
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    // A new size starts over with a cleared screen and nothing drawn, the terminal rewraps the old lines anyway
+    pub fn resize(&mut self, size: Size) {
+        *self = Screen::new(size);
+    }
+
+    // The back buffer blank again, for drawing the next frame from scratch
+    pub fn clear(&mut self) {
+        self.back.fill(BLANK);
+    }
+
+    // Writes the text from row, col on, cut off at the end of the row. Returns how many columns it took
+    pub fn put(&mut self, row: u16, col: u16, text: &str, style: Style) -> u16 {
+        if row >= self.size.rows || col >= self.size.cols {
+            return 0;
+        }
+        let start = row as usize * self.size.cols as usize;
+        let mut written = 0;
+        for (col, ch) in (col..self.size.cols).zip(text.chars().filter(|c| !c.is_control())) {
+            self.back[start + col as usize] = Cell { ch, style };
+            written += 1;
+        }
+        written
+    }
+
+    // What's in the back buffer at a row, as text, for the tests of what draws into a Screen
+    pub fn row(&self, row: u16) -> String {
+        let cols = self.size.cols as usize;
+        self.back[row as usize * cols..][..cols].iter().map(|cell| cell.ch).collect()
+    }
+
+    // The escapes and text that make the terminal show the back buffer, and the back buffer becomes the front. The cursor only
+    // moves when the next changed cell isn't the one after the last, and the style only changes when it's a different one
+    pub fn render(&mut self) -> String {
+        let mut out = String::new();
+        if !self.cleared {
+            out.push_str(CLEAR_SCREEN);
+            self.front.fill(BLANK);
+            self.cleared = true;
+        }
+        let cols = self.size.cols as usize;
+        let mut cursor = None;
+        let mut style = Style::default();
+        for (i, (back, front)) in self.back.iter().zip(&self.front).enumerate() {
+            if back == front {
+                continue;
+            }
+            let (row, col) = ((i / cols) as u16, (i % cols) as u16);
+            if cursor != Some((row, col)) {
+                out.push_str(&move_to(row, col));
+            }
+            if back.style != style {
+                let _ = write!(out, "{RESET}{}", back.style.codes());
+                style = back.style;
+            }
+            out.push(back.ch);
+            // After the last column the cursor stays there, or wraps, depending on the terminal
+            cursor = (col + 1 < self.size.cols).then_some((row, col + 1));
+        }
+        if !style.is_plain() {
+            out.push_str(RESET);
+        }
+        self.front.copy_from_slice(&self.back);
+        out
+    }
+
+    // render() written to out
+    pub fn present(&mut self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(self.render().as_bytes())?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_and_colors() {
+        assert_eq!("plain", Style::new().paint("plain"));
+        assert_eq!("\x1b[1;31mred\x1b[0m", Style::new().bold().fg(Color::Red).paint("red"));
+        assert_eq!("\x1b[4;90;44m", Style::new().underline().fg(Color::Gray).bg(Color::Blue).codes());
+        assert_eq!("\x1b[38;5;208;48;2;0;128;255m", Style::new().fg(Color::Ansi256(208)).bg(Color::Rgb(0, 128, 255)).codes());
+        assert_eq!(("\x1b[3;7H", ""), (move_to(2, 6).as_str(), right(0).as_str()));
+        assert!(!use_color(false));
+    }
+
+    #[test]
+    fn only_what_changed_is_drawn() {
+        let mut screen = Screen::new(Size { cols: 6, rows: 2 });
+        screen.put(0, 0, "ab", Style::new());
+        screen.put(1, 4, "xyz", Style::new().bold());
+        assert_eq!("\x1b[2J\x1b[1;1Hab\x1b[2;5H\x1b[0m\x1b[1mxy\x1b[0m", screen.render());
+        assert_eq!("ab    ", screen.row(0));
+
+        // The same frame again is nothing to write
+        screen.clear();
+        screen.put(0, 0, "ab", Style::new());
+        screen.put(1, 4, "xy", Style::new().bold());
+        assert_eq!("", screen.render());
+
+        // b becomes c, and x goes away
+        screen.clear();
+        screen.put(0, 0, "ac", Style::new());
+        screen.put(1, 5, "y", Style::new().bold());
+        assert_eq!("\x1b[1;2Hc\x1b[2;5H ", screen.render());
+        assert_eq!(0, screen.put(2, 0, "off the screen", Style::new()));
+    }
+}
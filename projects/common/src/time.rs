@@ -0,0 +1,329 @@
+// Time Utilities
+
+// std::time gives us Instant (a point on a clock that never goes backwards) and Duration (a span of time), and everything else is arithmetic on them.
+// That arithmetic is easy to get subtly wrong: deadline - Instant::now() panics on older Rust versions once the deadline has passed,
+// a rate limiter that refills its tokens in whole seconds lets bursts through at the second boundary, and so on.
+// This module does the arithmetic once, for the webserver (which still calls it time_ext) and the other projects to use:
+    // 1. Stopwatch measures elapsed time, and lap() splits it into consecutive intervals.
+    // 2. Deadline is a point in time that work has to finish by. remaining() never goes below zero.
+    // 3. RateLimiter is a token bucket: tokens trickle in at a steady rate up to a maximum (the burst), and every action spends one.
+    // 4. Throttle runs a closure at most once per interval and skips the calls in between.
+    //    Debounce waits until the calls stop coming for a while and then runs the closure once, with the last value it was given.
+
+// All of them use Instant, never SystemTime: the wall clock can jump when it's adjusted, which would make a stopwatch run backwards.
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// Stopwatch
+
+#[derive(Debug, Clone)]
+pub struct Stopwatch {
+    started: Instant,
+    last_lap: Instant,
+    laps: Vec<Duration>,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        let now = Instant::now();
+        Stopwatch { started: now, last_lap: now, laps: Vec::new() }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // The time since the previous lap (or the start), which is also remembered in laps()
+    pub fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let lap = now - self.last_lap;
+        self.last_lap = now;
+        self.laps.push(lap);
+        lap
+    }
+
+    pub fn laps(&self) -> &[Duration] {
+        &self.laps
+    }
+
+    pub fn restart(&mut self) {
+        *self = Stopwatch::start();
+    }
+}
+
+// Deadline
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline {
+    at: Instant,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline { at: Instant::now() + timeout }
+    }
+
+    pub fn at(at: Instant) -> Deadline {
+        Deadline { at }
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.at
+    }
+
+    // Zero once the deadline has passed, so it can be handed straight to wait_timeout or set_read_timeout.
+    // Careful with the latter: set_read_timeout(Some(Duration::ZERO)) is an error, check expired() first.
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.at
+    }
+}
+
+// Rate Limiter
+
+// The bucket holds up to burst tokens and gains rate tokens per second. Tokens are kept as a fraction,
+// so a limiter of 0.5 per second gains half a token every second instead of rounding that down to nothing.
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    // Starts full, so the first burst actions go through straight away
+    pub fn new(rate_per_sec: f64, burst: u32) -> RateLimiter {
+        assert!(rate_per_sec > 0.0 && burst > 0, "a rate limiter needs a positive rate and burst");
+        RateLimiter {
+            rate: rate_per_sec,
+            burst: burst as f64,
+            bucket: Mutex::new(Bucket { tokens: burst as f64, refilled: Instant::now() }),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now()).is_ok()
+    }
+
+    // Err holds how long to wait until a token is there, which is what a Retry-After header wants to know
+    pub fn check(&self) -> Result<(), Duration> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    // Blocks until a token is available
+    pub fn acquire(&self) {
+        while let Err(wait) = self.check() {
+            thread::sleep(wait);
+        }
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let gained = now.saturating_duration_since(bucket.refilled).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + gained).min(self.burst);
+        bucket.refilled = bucket.refilled.max(now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+// Throttle
+
+pub struct Throttle<F> {
+    interval: Duration,
+    last_run: Mutex<Option<Instant>>,
+    f: F,
+}
+
+impl<F> Throttle<F> {
+    pub fn new(interval: Duration, f: F) -> Throttle<F> {
+        Throttle { interval, last_run: Mutex::new(None), f }
+    }
+
+    // Runs f if it hasn't run in the last interval, and returns its result. Skipped calls return None.
+    pub fn call<A, R>(&self, arg: A) -> Option<R>
+    where
+        F: Fn(A) -> R,
+    {
+        self.call_at(Instant::now(), arg)
+    }
+
+    fn call_at<A, R>(&self, now: Instant, arg: A) -> Option<R>
+    where
+        F: Fn(A) -> R,
+    {
+        {
+            let mut last_run = self.last_run.lock().unwrap();
+            if matches!(*last_run, Some(last) if now.saturating_duration_since(last) < self.interval) {
+                return None;
+            }
+            *last_run = Some(now);
+        }
+        // f runs without the lock, so a slow f doesn't block the callers that are only going to be skipped
+        Some((self.f)(arg))
+    }
+}
+
+// Debounce
+
+// Useful when events come in bursts and only the last one matters, like a file that's saved several times in a row.
+// A background thread waits for the burst to end: each call() pushes the deadline back by wait.
+// Dropping the Debounce runs f for a value that is still waiting, then stops the thread.
+
+struct DebounceState<T> {
+    pending: Option<(T, Deadline)>,
+    closed: bool,
+}
+
+struct DebounceShared<T> {
+    state: Mutex<DebounceState<T>>,
+    changed: Condvar,
+}
+
+pub struct Debounce<T: Send + 'static> {
+    shared: Arc<DebounceShared<T>>,
+    wait: Duration,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> Debounce<T> {
+    pub fn new(wait: Duration, mut f: impl FnMut(T) + Send + 'static) -> Debounce<T> {
+        let shared = Arc::new(DebounceShared {
+            state: Mutex::new(DebounceState { pending: None, closed: false }),
+            changed: Condvar::new(),
+        });
+
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || loop {
+                let mut state = shared.state.lock().unwrap();
+                let value = loop {
+                    match &state.pending {
+                        None if state.closed => return,
+                        None => state = shared.changed.wait(state).unwrap(),
+                        Some((_, deadline)) if deadline.expired() || state.closed => break state.pending.take().unwrap().0,
+                        Some((_, deadline)) => {
+                            let remaining = deadline.remaining();
+                            state = shared.changed.wait_timeout(state, remaining).unwrap().0;
+                        }
+                    }
+                };
+                drop(state);
+                f(value);
+            })
+        };
+
+        Debounce { shared, wait, thread: Some(thread) }
+    }
+
+    pub fn call(&self, value: T) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.pending = Some((value, Deadline::after(self.wait)));
+        self.shared.changed.notify_one();
+    }
+}
+
+impl<T: Send + 'static> Drop for Debounce<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.changed.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn stopwatch_laps_add_up() {
+        let mut stopwatch = Stopwatch::start();
+        thread::sleep(Duration::from_millis(5));
+        let first = stopwatch.lap();
+        thread::sleep(Duration::from_millis(5));
+        stopwatch.lap();
+
+        assert!(first >= Duration::from_millis(5));
+        assert_eq!(2, stopwatch.laps().len());
+        assert!(stopwatch.laps().iter().sum::<Duration>() <= stopwatch.elapsed());
+
+        stopwatch.restart();
+        assert!(stopwatch.laps().is_empty());
+    }
+
+    #[test]
+    fn deadlines_never_have_negative_time_left() {
+        let past = Deadline::at(Instant::now() - Duration::from_secs(1));
+        assert!(past.expired());
+        assert_eq!(Duration::ZERO, past.remaining());
+
+        let future = Deadline::after(Duration::from_secs(60));
+        assert!(!future.expired());
+        assert!(future.remaining() > Duration::from_secs(59));
+        assert!(past < future);
+    }
+
+    #[test]
+    fn rate_limiter_allows_a_burst_then_the_rate() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let start = Instant::now();
+
+        let results: Vec<bool> = (0..4).map(|_| limiter.try_acquire_at(start).is_ok()).collect();
+        assert_eq!(vec![true, true, true, false], results);
+
+        // Half a token after a quarter second: still 0.25 s to wait
+        assert_eq!(Err(Duration::from_millis(250)), limiter.try_acquire_at(start + Duration::from_millis(250)));
+        assert_eq!(Ok(()), limiter.try_acquire_at(start + Duration::from_millis(500)));
+
+        // A long pause refills the bucket, but only up to the burst
+        let later = start + Duration::from_secs(60);
+        let results: Vec<bool> = (0..4).map(|_| limiter.try_acquire_at(later).is_ok()).collect();
+        assert_eq!(vec![true, true, true, false], results);
+    }
+
+    #[test]
+    fn throttle_skips_calls_within_the_interval() {
+        let throttle = Throttle::new(Duration::from_secs(1), |n: i32| n * 10);
+        let start = Instant::now();
+        assert_eq!(Some(10), throttle.call_at(start, 1));
+        assert_eq!(None, throttle.call_at(start + Duration::from_millis(999), 2));
+        assert_eq!(Some(30), throttle.call_at(start + Duration::from_secs(1), 3));
+    }
+
+    #[test]
+    fn debounce_keeps_only_the_last_value_of_a_burst() {
+        let (sender, receiver) = mpsc::channel();
+        let debounce = Debounce::new(Duration::from_millis(50), move |n: i32| sender.send(n).unwrap());
+        for n in 1..=5 {
+            debounce.call(n);
+        }
+        assert_eq!(Ok(5), receiver.recv_timeout(Duration::from_secs(5)));
+
+        // A value still waiting when the Debounce is dropped isn't lost
+        debounce.call(6);
+        drop(debounce);
+        assert_eq!(vec![6], receiver.iter().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,339 @@
+// Tracing
+
+// A log line says what happened, but one request to the server is a handful of lines from more than one thread, mixed in
+// with the lines of every other request. Tracing ties them together:
+    // 1. A Span is one piece of work with a name, like "GET /" or "render page". It starts when it's made and ends when it's
+    //    dropped, and keeps how long that took along with key-value fields.
+    // 2. Spans nest: a span made while another one is current on the thread is its child. Every span of a request has the
+    //    same trace ID, its own span ID, and the span ID of its parent.
+    // 3. The current span is a thread-local stack, so work handed to another thread has to take it along: propagate(f)
+    //    wraps a closure to run in the context it was made in. The webserver's ThreadPool::execute does it for every job.
+    // 4. Log lines (log.rs) written while a span is current end with its trace ID, so one grep finds a request's lines.
+    // 5. A Context goes from one process to the next in the W3C traceparent header, 00-<trace id>-<span id>-01, so the
+    //    spans of a proxy and the server behind it end up in one trace.
+// Finished spans are only kept after keep_finished(n), the last n of them. to_json writes them in the Trace Event Format
+// that chrome://tracing and ui.perfetto.dev open: a timeline with a row per thread and a bar per span.
+
+use std::{
+    cell::RefCell,
+    collections::{hash_map::RandomState, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    marker::PhantomData,
+    process,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId(pub u128);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpanId(pub u64);
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+// Never 0, which traceparent reserves for "no ID". RandomState's keys are random for every process, and the counter makes
+// every hash different
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let random = hasher.finish();
+        if random != 0 {
+            return random;
+        }
+    }
+}
+
+// What a span's children need to know about it, and all that crosses a thread or a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+}
+
+impl Context {
+    // A traceparent header, None when it's malformed or its IDs are 0
+    pub fn from_traceparent(header: &str) -> Option<Context> {
+        let parts: Vec<&str> = header.trim().split('-').collect();
+        let [version, trace_id, span_id, flags] = parts[..] else {
+            return None;
+        };
+        let hex = |part: &str, len: usize| part.len() == len && part.bytes().all(|b| b.is_ascii_hexdigit());
+        if !hex(version, 2) || version == "ff" || !hex(trace_id, 32) || !hex(span_id, 16) || !hex(flags, 2) {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok().filter(|&id| id != 0)?;
+        let span_id = u64::from_str_radix(span_id, 16).ok().filter(|&id| id != 0)?;
+        Some(Context { trace_id: TraceId(trace_id), span_id: SpanId(span_id) })
+    }
+
+    // Version 00, and the sampled flag, since every span here is recorded
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Vec<Context>> = const { RefCell::new(Vec::new()) };
+    // A small number for the thread, for the rows of the timeline
+    static THREAD: u64 = {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    };
+}
+
+// The innermost span on this thread
+pub fn current() -> Option<Context> {
+    CURRENT.with(|current| current.borrow().last().copied())
+}
+
+// Takes the context off the stack again when it's dropped, see attach
+pub struct Attached {
+    span_id: SpanId,
+    // It belongs to this thread's stack, so it can't go to another thread
+    _thread: PhantomData<*const ()>,
+}
+
+// Makes the context current on this thread until the guard is dropped
+pub fn attach(context: Context) -> Attached {
+    CURRENT.with(|current| current.borrow_mut().push(context));
+    Attached { span_id: context.span_id, _thread: PhantomData }
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        // Guards dropped out of order take their own context off, not whichever is on top
+        CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            if let Some(i) = current.iter().rposition(|context| context.span_id == self.span_id) {
+                current.remove(i);
+            }
+        });
+    }
+}
+
+// The closure, run with the current context of the thread that called propagate, wherever it runs
+pub fn propagate<F: FnOnce() -> R, R>(f: F) -> impl FnOnce() -> R {
+    let context = current();
+    move || {
+        let _attached = context.map(attach);
+        f()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanRecord {
+    pub name: String,
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+    pub parent_id: Option<SpanId>,
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub fields: Vec<(String, String)>,
+    pub thread: u64,
+    pub thread_name: Option<String>,
+}
+
+static KEEP: AtomicUsize = AtomicUsize::new(0);
+static FINISHED: Mutex<VecDeque<SpanRecord>> = Mutex::new(VecDeque::new());
+
+// Keeps the last n spans that end, 0 keeps none. Contexts are passed on and logged either way
+pub fn keep_finished(n: usize) {
+    KEEP.store(n, Ordering::Relaxed);
+    let mut finished = FINISHED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    while finished.len() > n {
+        finished.pop_front();
+    }
+}
+
+// The spans kept, in the order they ended
+pub fn finished() -> Vec<SpanRecord> {
+    FINISHED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().cloned().collect()
+}
+
+pub struct Span {
+    context: Context,
+    parent_id: Option<SpanId>,
+    name: String,
+    start: SystemTime,
+    started: Instant,
+    fields: Vec<(String, String)>,
+    _attached: Attached,
+}
+
+impl Span {
+    // A child of the current span, or the first span of a new trace
+    pub fn new(name: &str) -> Span {
+        Span::with_parent(name, current())
+    }
+
+    // A child of a span somewhere else, like the one in a traceparent header
+    pub fn with_parent(name: &str, parent: Option<Context>) -> Span {
+        let trace_id = parent.map_or_else(|| TraceId(((random_u64() as u128) << 64) | random_u64() as u128), |p| p.trace_id);
+        let context = Context { trace_id, span_id: SpanId(random_u64()) };
+        Span {
+            context,
+            parent_id: parent.map(|parent| parent.span_id),
+            name: name.to_string(),
+            start: SystemTime::now(),
+            started: Instant::now(),
+            fields: Vec::new(),
+            _attached: attach(context),
+        }
+    }
+
+    pub fn field(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.record(key, value);
+        self
+    }
+
+    // A field for something only known later, like the status of the response
+    pub fn record(&mut self, key: &str, value: impl fmt::Display) {
+        self.fields.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn context(&self) -> Context {
+        self.context
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let keep = KEEP.load(Ordering::Relaxed);
+        if keep == 0 {
+            return;
+        }
+        let record = SpanRecord {
+            name: std::mem::take(&mut self.name),
+            trace_id: self.context.trace_id,
+            span_id: self.context.span_id,
+            parent_id: self.parent_id,
+            start: self.start,
+            duration: self.started.elapsed(),
+            fields: std::mem::take(&mut self.fields),
+            thread: THREAD.with(|thread| *thread),
+            thread_name: thread::current().name().map(str::to_string),
+        };
+        let mut finished = FINISHED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if finished.len() >= keep {
+            finished.pop_front();
+        }
+        finished.push_back(record);
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// The spans as a Trace Event Format file: a complete event ("ph":"X") per span, times in microseconds, and a metadata event
+// with the name of each thread
+pub fn to_json(spans: &[SpanRecord]) -> String {
+    let pid = process::id();
+    let mut events = Vec::new();
+    let mut threads: Vec<u64> = Vec::new();
+    for span in spans {
+        if !threads.contains(&span.thread) {
+            threads.push(span.thread);
+            let name = span.thread_name.clone().unwrap_or_else(|| format!("thread {}", span.thread));
+            let (name, tid) = (json_string(&name), span.thread);
+            events.push(format!(r#"{{"name":"thread_name","ph":"M","pid":{pid},"tid":{tid},"args":{{"name":{name}}}}}"#));
+        }
+
+        let mut args = vec![format!(r#""trace_id":"{}""#, span.trace_id), format!(r#""span_id":"{}""#, span.span_id)];
+        if let Some(parent_id) = span.parent_id {
+            args.push(format!(r#""parent_id":"{parent_id}""#));
+        }
+        args.extend(span.fields.iter().map(|(key, value)| format!("{}:{}", json_string(key), json_string(value))));
+        let start = span.start.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros();
+        events.push(format!(
+            r#"{{"name":{},"cat":"span","ph":"X","ts":{start},"dur":{},"pid":{pid},"tid":{},"args":{{{}}}}}"#,
+            json_string(&span.name),
+            span.duration.as_micros(),
+            span.thread,
+            args.join(",")
+        ));
+    }
+    format!("{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n", events.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_nest_and_follow_closures_to_other_threads() {
+        keep_finished(1000);
+        let trace_id = {
+            let outer = Span::new("outer").field("user", 7);
+            let inner = Span::new("inner");
+            assert_eq!(Some(inner.context()), current());
+            let job = propagate(|| Span::new("on another thread").context());
+            let other = thread::spawn(job).join().unwrap();
+            assert_eq!(outer.context().trace_id, other.trace_id);
+            drop(inner);
+            assert_eq!(Some(outer.context()), current());
+            outer.context().trace_id
+        };
+        assert_eq!(None, current());
+
+        let spans: Vec<SpanRecord> = finished().into_iter().filter(|span| span.trace_id == trace_id).collect();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(vec!["on another thread", "inner", "outer"], names);
+        // Both are children of inner, and outer starts the trace
+        assert_eq!(Some(spans[1].span_id), spans[0].parent_id);
+        assert_eq!(Some(spans[2].span_id), spans[1].parent_id);
+        assert_eq!((None, vec![(String::from("user"), String::from("7"))]), (spans[2].parent_id, spans[2].fields.clone()));
+        assert_ne!(spans[0].thread, spans[1].thread);
+
+        let json = to_json(&spans);
+        assert!(json.contains(r#""name":"outer","cat":"span","ph":"X""#), "{json}");
+        assert!(json.contains(&format!(r#""span_id":"{}","user":"7""#, spans[2].span_id)), "{json}");
+        assert_eq!(2, json.matches(r#""ph":"M""#).count());
+    }
+
+    #[test]
+    fn contexts_travel_in_traceparent_headers() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = Context::from_traceparent(header).unwrap();
+        assert_eq!(TraceId(0x4bf92f3577b34da6a3ce929d0e0e4736), context.trace_id);
+        assert_eq!(header, context.traceparent());
+
+        let child = Span::with_parent("request", Some(context));
+        assert_eq!(context.trace_id, child.context().trace_id);
+        assert_ne!(context.span_id, child.context().span_id);
+        let short = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7";
+        let zero = "00-00000000000000000000000000000000-00f067aa0ba902b7-01";
+        for bad in ["", short, "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", zero] {
+            assert_eq!(None, Context::from_traceparent(bad), "{bad}");
+        }
+    }
+}
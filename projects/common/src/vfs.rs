@@ -0,0 +1,478 @@
+// File Systems
+
+// A test of what minigrep does with a file it isn't allowed to read needs a file it isn't allowed to read. Making one means
+// chmod, which does nothing when the tests run as root, and means nothing on Windows. The same goes for a disk that fails
+// halfway through a directory, or a file that disappears between listing it and reading it.
+// The code that reads files asks a FileSystem instead, and tests hand it one where anything can happen:
+    // 1. RealFs is std::fs, and the walk from walk.rs. It's what minigrep, the webserver and the kvstore use outside of tests.
+    // 2. MemFs keeps files and directories in a map. Files are added with add_file, and fail() makes any path return the
+    //    error a test wants, PermissionDenied for example. Modification times come from a Clock (clock.rs), so a test
+    //    that advances a FakeClock between two writes sees two different mtimes.
+// Only what the callers need is in the trait: reading and writing whole files, metadata, and listing directories. The kvstore's
+// log and snapshots (projects/kvstore/src/wal.rs) need a little more: a file kept open to append to and sync, a rename that
+// replaces a file in one step, and removing one. A WriteFile is such an open file, a File for RealFs.
+
+// MemFs doesn't read .gitignore files: listing the files below a directory honors the depth limit of the Walk, nothing else.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{
+    clock::{Clock, FakeClock},
+    mmap::FileBytes,
+    walk::Walk,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileInfo {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+// A file open for writing. Writes go to its end, which is the start for a file create() just emptied
+pub trait WriteFile: Write + Send {
+    // flush() hands the bytes to the operating system, which survives the process crashing but not the machine.
+    // sync waits until they are on the disk.
+    fn sync(&mut self) -> io::Result<()>;
+    // Cuts the file short, or makes it longer with zeros
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+pub trait FileSystem: Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    fn metadata(&self, path: &Path) -> io::Result<FileInfo>;
+    // The paths directly inside a directory, sorted
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    // Creates the file, or empties the one that's there
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WriteFile>>;
+    // Opens the file to append to, creating it if it isn't there
+    fn append(&self, path: &Path) -> io::Result<Box<dyn WriteFile>>;
+    // Moves the file, over the one at to if there is one, which is never seen half replaced
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    // The files below walk's root, sorted the way Walk sorts them. This default lists them with read_dir.
+    fn files(&self, walk: &Walk) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut stack = vec![(walk.root().to_path_buf(), 0)];
+        while let Some((path, depth)) = stack.pop() {
+            if !self.metadata(&path)?.is_dir {
+                files.push(path);
+            } else if walk.depth_limit().is_none_or(|max| depth < max) {
+                // Reversed, so that the first entry comes off the stack first
+                stack.extend(self.read_dir(&path)?.into_iter().rev().map(|child| (child, depth + 1)));
+            }
+        }
+        Ok(files)
+    }
+
+    // Only RealFs can map files, everyone else reads them
+    fn map(&self, path: &Path) -> io::Result<FileBytes> {
+        self.read(path).map(FileBytes::from)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl WriteFile for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileInfo { is_dir: metadata.is_dir(), len: metadata.len(), modified: metadata.modified()? })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WriteFile>> {
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn WriteFile>> {
+        Ok(Box::new(OpenOptions::new().create(true).append(true).open(path)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    // The real walk, with its ignore files and symlink policy
+    fn files(&self, walk: &Walk) -> io::Result<Vec<PathBuf>> {
+        walk.clone().files()
+    }
+
+    fn map(&self, path: &Path) -> io::Result<FileBytes> {
+        FileBytes::open(path)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File { contents: Vec<u8>, modified: SystemTime },
+    Dir { modified: SystemTime },
+}
+
+type Nodes = Arc<Mutex<BTreeMap<PathBuf, Node>>>;
+type Failures = Arc<Mutex<HashMap<PathBuf, io::ErrorKind>>>;
+
+// The maps are shared with the MemFiles that create() and append() return
+pub struct MemFs {
+    clock: Arc<dyn Clock>,
+    nodes: Nodes,
+    failures: Failures,
+}
+
+// "a/./b" and "a/b/" are the same path as "a/b", and so is "/a/b": every path is kept relative to the root of the MemFs.
+// ".." isn't resolved, there's no reason for a test to use it.
+fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|component| matches!(component, Component::Normal(_))).collect()
+}
+
+impl MemFs {
+    pub fn new() -> MemFs {
+        MemFs::with_clock(Arc::new(FakeClock::new()))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> MemFs {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::new(), Node::Dir { modified: clock.system_now() });
+        MemFs { clock, nodes: Arc::new(Mutex::new(nodes)), failures: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    // Creates the directories on the way, like mkdir -p, and replaces a file that's already there
+    pub fn add_file<P: AsRef<Path>, C: Into<Vec<u8>>>(&self, path: P, contents: C) -> &Self {
+        let path = normalize(path.as_ref());
+        let now = self.clock.system_now();
+        let mut nodes = self.nodes.lock().unwrap();
+        for dir in path.ancestors().skip(1) {
+            nodes.insert(dir.to_path_buf(), Node::Dir { modified: now });
+        }
+        nodes.insert(path, Node::File { contents: contents.into(), modified: now });
+        self
+    }
+
+    pub fn add_dir<P: AsRef<Path>>(&self, path: P) -> &Self {
+        let path = normalize(path.as_ref());
+        let now = self.clock.system_now();
+        let mut nodes = self.nodes.lock().unwrap();
+        for dir in path.ancestors() {
+            nodes.entry(dir.to_path_buf()).or_insert(Node::Dir { modified: now });
+        }
+        self
+    }
+
+    // Removes the path and everything below it
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> &Self {
+        let path = normalize(path.as_ref());
+        self.nodes.lock().unwrap().retain(|node, _| !node.starts_with(&path) || path.as_os_str().is_empty());
+        self
+    }
+
+    // From now on reading, writing or listing the path fails with this error, and so does writing to a MemFile of it that
+    // is already open. Its metadata can still be looked at and it still shows up in its directory, like a file without
+    // read permission.
+    pub fn fail<P: AsRef<Path>>(&self, path: P, kind: io::ErrorKind) -> &Self {
+        self.failures.lock().unwrap().insert(normalize(path.as_ref()), kind);
+        self
+    }
+
+    fn check(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+        check(&self.failures, &path)?;
+        Ok(path)
+    }
+
+    fn open(&self, key: PathBuf) -> Box<dyn WriteFile> {
+        let (clock, nodes, failures) = (Arc::clone(&self.clock), Arc::clone(&self.nodes), Arc::clone(&self.failures));
+        Box::new(MemFile { key, clock, nodes, failures })
+    }
+}
+
+fn check(failures: &Failures, key: &Path) -> io::Result<()> {
+    match failures.lock().unwrap().get(key) {
+        Some(&kind) => Err(io::Error::new(kind, format!("{}: simulated failure", key.display()))),
+        None => Ok(()),
+    }
+}
+
+// An open file of a MemFs. Every write goes straight into the map, so there's nothing to sync
+pub struct MemFile {
+    key: PathBuf,
+    clock: Arc<dyn Clock>,
+    nodes: Nodes,
+    failures: Failures,
+}
+
+impl MemFile {
+    fn change(&self, f: impl FnOnce(&mut Vec<u8>)) -> io::Result<()> {
+        check(&self.failures, &self.key)?;
+        match self.nodes.lock().unwrap().get_mut(&self.key) {
+            Some(Node::File { contents, modified }) => {
+                f(contents);
+                *modified = self.clock.system_now();
+                Ok(())
+            }
+            // Removed or renamed while it was open. A real file would live on without a name, nobody could read it anyway
+            _ => Err(not_found(&self.key)),
+        }
+    }
+}
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.change(|contents| contents.extend_from_slice(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteFile for MemFile {
+    fn sync(&mut self) -> io::Result<()> {
+        check(&self.failures, &self.key)
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.change(|contents| contents.resize(len as usize, 0))
+    }
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        MemFs::new()
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{}: no such file or directory", path.display()))
+}
+
+fn is_a_directory(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::IsADirectory, format!("{}: is a directory", path.display()))
+}
+
+impl FileSystem for MemFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let key = self.check(path)?;
+        match self.nodes.lock().unwrap().get(&key) {
+            Some(Node::File { contents, .. }) => Ok(contents.clone()),
+            Some(Node::Dir { .. }) => Err(io::Error::new(io::ErrorKind::IsADirectory, format!("{}: is a directory", path.display()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let key = self.check(path)?;
+        let nodes = self.nodes.lock().unwrap();
+        // Like fs::write, the directory has to be there already
+        match key.parent().and_then(|parent| nodes.get(parent)) {
+            Some(Node::Dir { .. }) => {}
+            _ => return Err(not_found(path)),
+        }
+        drop(nodes);
+        self.add_file(key, contents);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        let key = normalize(path);
+        match self.nodes.lock().unwrap().get(&key) {
+            Some(Node::File { contents, modified }) => Ok(FileInfo { is_dir: false, len: contents.len() as u64, modified: *modified }),
+            Some(Node::Dir { modified }) => Ok(FileInfo { is_dir: true, len: 0, modified: *modified }),
+            None => Err(not_found(path)),
+        }
+    }
+
+    // The children are returned under the path as it was given, so that "./src" lists "./src/main.rs" like RealFs does
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let key = self.check(path)?;
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(&key) {
+            Some(Node::Dir { .. }) => Ok(nodes
+                .keys()
+                .filter(|child| child.parent() == Some(key.as_path()) && !child.as_os_str().is_empty())
+                .map(|child| path.join(child.file_name().unwrap()))
+                .collect()),
+            Some(Node::File { .. }) => Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{}: not a directory", path.display()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WriteFile>> {
+        self.write(path, b"")?;
+        Ok(self.open(normalize(path)))
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn WriteFile>> {
+        let key = self.check(path)?;
+        match self.nodes.lock().unwrap().get(&key) {
+            Some(Node::File { .. }) => return Ok(self.open(key)),
+            Some(Node::Dir { .. }) => return Err(is_a_directory(path)),
+            None => {}
+        }
+        self.create(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from_key, to_key) = (self.check(from)?, self.check(to)?);
+        let mut nodes = self.nodes.lock().unwrap();
+        match to_key.parent().and_then(|parent| nodes.get(parent)) {
+            Some(Node::Dir { .. }) => {}
+            _ => return Err(not_found(to)),
+        }
+        match nodes.remove(&from_key) {
+            Some(file @ Node::File { .. }) => {
+                nodes.insert(to_key, file);
+                Ok(())
+            }
+            Some(dir) => {
+                nodes.insert(from_key, dir);
+                Err(is_a_directory(from))
+            }
+            None => Err(not_found(from)),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let key = self.check(path)?;
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(&key) {
+            Some(Node::File { .. }) => {
+                nodes.remove(&key);
+                Ok(())
+            }
+            Some(Node::Dir { .. }) => Err(is_a_directory(path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let key = self.check(path)?;
+        if let Some(Node::File { .. }) = self.nodes.lock().unwrap().get(&key) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{}: is a file", path.display())));
+        }
+        self.add_dir(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn files_directories_and_failures() {
+        let fs = MemFs::new();
+        fs.add_file("src/main.rs", "fn main() {}").add_file("src/lib/mod.rs", "").add_file("README.md", "# hi");
+
+        assert_eq!(b"# hi".to_vec(), fs.read(Path::new("./README.md")).unwrap());
+        assert_eq!(vec![PathBuf::from("/src/lib"), PathBuf::from("/src/main.rs")], fs.read_dir(Path::new("/src")).unwrap());
+        assert!(fs.metadata(Path::new("src/lib")).unwrap().is_dir);
+        assert_eq!(io::ErrorKind::NotFound, fs.read(Path::new("missing.txt")).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::IsADirectory, fs.read(Path::new("src")).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::NotFound, fs.write(Path::new("nowhere/file"), b"").unwrap_err().kind());
+
+        fs.fail("src/main.rs", io::ErrorKind::PermissionDenied);
+        assert_eq!(io::ErrorKind::PermissionDenied, fs.read(Path::new("src/main.rs")).unwrap_err().kind());
+        // Still listed, like a file we may not read
+        assert_eq!(2, fs.read_dir(Path::new("src")).unwrap().len());
+
+        fs.remove("src");
+        assert_eq!(vec![PathBuf::from("README.md")], fs.read_dir(Path::new("")).unwrap());
+    }
+
+    #[test]
+    fn modification_times_follow_the_clock() {
+        let clock = Arc::new(FakeClock::new());
+        let fs = MemFs::with_clock(clock.clone());
+        fs.write(Path::new("notes.txt"), b"one").unwrap();
+        let first = fs.metadata(Path::new("notes.txt")).unwrap();
+
+        clock.advance(Duration::from_secs(60));
+        fs.write(Path::new("notes.txt"), b"two!").unwrap();
+        let second = fs.metadata(Path::new("notes.txt")).unwrap();
+        assert_eq!(Duration::from_secs(60), second.modified.duration_since(first.modified).unwrap());
+        assert_eq!(4, second.len);
+    }
+
+    #[test]
+    fn open_files_append_and_renames_replace() {
+        let fs = MemFs::new();
+        fs.add_dir("data");
+        let mut log = fs.append(Path::new("data/log")).unwrap();
+        log.write_all(b"one ").unwrap();
+        let mut again = fs.append(Path::new("data/log")).unwrap();
+        again.write_all(b"two").unwrap();
+        again.sync().unwrap();
+        assert_eq!(b"one two".to_vec(), fs.read(Path::new("data/log")).unwrap());
+        log.set_len(3).unwrap();
+        assert_eq!(b"one".to_vec(), fs.read(Path::new("data/log")).unwrap());
+
+        fs.create(Path::new("data/new")).unwrap().write_all(b"replaced").unwrap();
+        fs.rename(Path::new("data/new"), Path::new("data/log")).unwrap();
+        assert_eq!(b"replaced".to_vec(), fs.read(Path::new("data/log")).unwrap());
+        assert_eq!(io::ErrorKind::NotFound, fs.metadata(Path::new("data/new")).unwrap_err().kind());
+
+        fs.fail("data/log", io::ErrorKind::StorageFull);
+        assert_eq!(io::ErrorKind::StorageFull, fs.append(Path::new("data/log")).err().unwrap().kind());
+        assert_eq!(io::ErrorKind::StorageFull, log.write_all(b"!").unwrap_err().kind());
+        fs.remove_file(Path::new("data/log")).unwrap_err();
+        fs.create_dir_all(Path::new("data/a/b")).unwrap();
+        assert!(fs.metadata(Path::new("data/a")).unwrap().is_dir);
+    }
+
+    #[test]
+    fn lists_files_like_the_walk() {
+        let fs = MemFs::new();
+        fs.add_file("root/b.txt", "").add_file("root/a/deep/x.txt", "").add_file("root/a/y.txt", "");
+        let files = fs.files(&Walk::new("root")).unwrap();
+        assert_eq!(vec![PathBuf::from("root/a/deep/x.txt"), PathBuf::from("root/a/y.txt"), PathBuf::from("root/b.txt")], files);
+        assert_eq!(vec![PathBuf::from("root/b.txt")], fs.files(&Walk::new("root").max_depth(1)).unwrap());
+    }
+}
@@ -0,0 +1,416 @@
+// Walking a Directory Tree
+
+// minigrep's collect_files used to call itself for every subdirectory and return one big Vec. That's fine for a small project,
+// but it can't stop early, it follows nothing and skips nothing, and a deep enough tree would overflow the stack.
+// Walk goes through the tree with a stack of its own instead of recursion, and hands out entries one at a time as an Iterator:
+    // 1. Every entry comes with its depth and its metadata, which the walk had to look at anyway to know whether it's a directory.
+    // 2. max_depth stops the walk from going deeper than that many levels below the root.
+    // 3. Symlinks decides what happens to symbolic links: List them without looking at what they point to (the default),
+    //    Follow them as if they were what they point to, or Skip them altogether.
+    // 4. With ignore files on (the default) every directory's .gitignore and .ignore files are read, and whatever they match
+    //    is left out, together with the .git directory itself. Patterns are globs from glob.rs.
+// Entries come in the same order as ls -R would list them: sorted by name, each directory's contents right after it.
+
+// A .gitignore supports a subset of what git does:
+    // 1. Blank lines and lines starting with # are skipped, \# and \! escape a leading # or !.
+    // 2. A pattern without a '/' matches the name of a file or directory at any depth: *.log, target
+    // 3. A pattern with a '/' is relative to the directory of the .gitignore: /build, docs/*.html, src/**/generated
+    // 4. A trailing '/' only matches directories: cache/
+    // 5. A leading '!' takes a file back in that an earlier rule left out: !keep.log
+// Rules further down a file win over earlier ones, and a .gitignore in a subdirectory wins over its parents'.
+// Like git, nothing inside an ignored directory can be taken back in, the walk never even looks inside it.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    vec,
+};
+
+use crate::glob::Glob;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symlinks {
+    Skip,
+    #[default]
+    List,
+    Follow,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+    // Has a '/', so it's matched against the path from the ignore file's directory instead of against the name
+    anchored: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    // Lines that aren't valid globs are skipped, as git does
+    pub fn parse(text: &str) -> IgnoreRules {
+        let rules = text.lines().filter_map(parse_rule).collect();
+        IgnoreRules { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Some(true) when the last rule that matches ignores the path, Some(false) when it takes it back in, None when no rule matches
+    pub fn matched(&self, relative: &Path, is_dir: bool) -> Option<bool> {
+        let path = relative.to_string_lossy();
+        let name = relative.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match(if rule.anchored { &path } else { &name }))
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    // Only the escapes for the first character are handled here, the glob takes care of the rest
+    let line = line.strip_prefix('\\').filter(|rest| rest.starts_with(['#', '!'])).unwrap_or(line);
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    let glob = Glob::new(line.strip_prefix('/').unwrap_or(line)).ok()?;
+    Some(Rule { glob, negated, dir_only, anchored })
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    path: PathBuf,
+    depth: usize,
+    metadata: fs::Metadata,
+    is_symlink: bool,
+}
+
+impl DirEntry {
+    fn new(path: PathBuf, depth: usize, symlinks: Symlinks) -> io::Result<DirEntry> {
+        let link_metadata = fs::symlink_metadata(&path)?;
+        let is_symlink = link_metadata.file_type().is_symlink();
+        // A link that points nowhere is still listed, as the link it is
+        let metadata = match (is_symlink, symlinks) {
+            (true, Symlinks::Follow) => fs::metadata(&path).unwrap_or(link_metadata),
+            _ => link_metadata,
+        };
+        Ok(DirEntry { path, depth, metadata, is_symlink })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    // 0 for the root, 1 for what's directly inside it, and so on
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // With Symlinks::Follow, the metadata of what a link points to, otherwise of the link itself
+    pub fn metadata(&self) -> &fs::Metadata {
+        &self.metadata
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.metadata.is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.metadata.is_file()
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.is_symlink
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Walk {
+    root: PathBuf,
+    max_depth: Option<usize>,
+    symlinks: Symlinks,
+    ignore_files: bool,
+}
+
+impl Walk {
+    pub fn new<P: AsRef<Path>>(root: P) -> Walk {
+        Walk { root: root.as_ref().to_path_buf(), max_depth: None, symlinks: Symlinks::default(), ignore_files: true }
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    pub fn ignore_files(mut self, ignore_files: bool) -> Self {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn depth_limit(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    // The paths of all the files, or the first error
+    pub fn files(self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in self {
+            let entry = entry?;
+            if entry.is_file() {
+                files.push(entry.into_path());
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl IntoIterator for Walk {
+    type Item = io::Result<DirEntry>;
+    type IntoIter = WalkIter;
+
+    fn into_iter(self) -> WalkIter {
+        WalkIter { walk: self, stack: Vec::new(), started: false }
+    }
+}
+
+// One directory on the way down from the root, and what's left of its entries
+struct Level {
+    dir: PathBuf,
+    entries: vec::IntoIter<PathBuf>,
+    rules: IgnoreRules,
+    // Only with Symlinks::Follow, to notice a link that leads back up to a directory we're already in
+    canonical: Option<PathBuf>,
+}
+
+pub struct WalkIter {
+    walk: Walk,
+    stack: Vec<Level>,
+    started: bool,
+}
+
+impl WalkIter {
+    fn ignored(&self, entry: &DirEntry) -> bool {
+        if !self.walk.ignore_files {
+            return false;
+        }
+        if entry.is_dir() && entry.path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        // The deepest ignore file with an opinion decides
+        self.stack.iter().rev().find_map(|level| {
+            let relative = entry.path.strip_prefix(&level.dir).ok()?;
+            level.rules.matched(relative, entry.is_dir())
+        }) == Some(true)
+    }
+
+    fn push(&mut self, dir: &Path) -> io::Result<()> {
+        let canonical = if self.walk.symlinks == Symlinks::Follow {
+            let canonical = fs::canonicalize(dir)?;
+            if self.stack.iter().any(|level| level.canonical.as_ref() == Some(&canonical)) {
+                return Err(io::Error::other(format!("{}: symlink loop, it leads back to {}", dir.display(), canonical.display())));
+            }
+            Some(canonical)
+        } else {
+            None
+        };
+
+        let mut entries = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        let mut rules = IgnoreRules::default();
+        if self.walk.ignore_files {
+            for name in [".gitignore", ".ignore"] {
+                match fs::read_to_string(dir.join(name)) {
+                    Ok(text) => rules.rules.extend(IgnoreRules::parse(&text).rules),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        self.stack.push(Level { dir: dir.to_path_buf(), entries: entries.into_iter(), rules, canonical });
+        Ok(())
+    }
+
+    fn descend(&mut self, entry: &DirEntry) -> io::Result<()> {
+        let can_descend = entry.is_dir() && (!entry.is_symlink || self.walk.symlinks == Symlinks::Follow);
+        if can_descend && self.walk.max_depth.is_none_or(|max| entry.depth < max) {
+            self.push(&entry.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for WalkIter {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<io::Result<DirEntry>> {
+        if !self.started {
+            self.started = true;
+            // The root is always followed, even if it's a link: it's what was asked for
+            let root = match DirEntry::new(self.walk.root.clone(), 0, Symlinks::Follow) {
+                Ok(root) => root,
+                Err(e) => return Some(Err(e)),
+            };
+            if root.is_dir() && self.walk.max_depth != Some(0) {
+                if let Err(e) = self.push(&root.path) {
+                    return Some(Err(e));
+                }
+            }
+            return Some(Ok(root));
+        }
+
+        loop {
+            let level = self.stack.last_mut()?;
+            let Some(path) = level.entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let depth = self.stack.len();
+            let entry = match DirEntry::new(path, depth, self.walk.symlinks) {
+                Ok(entry) => entry,
+                // Deleted since the directory was read
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if (entry.is_symlink && self.walk.symlinks == Symlinks::Skip) || self.ignored(&entry) {
+                continue;
+            }
+            return Some(self.descend(&entry).map(|()| entry));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    struct TempTree(PathBuf);
+
+    impl TempTree {
+        fn new(name: &str, files: &[(&str, &str)]) -> TempTree {
+            let root = env::temp_dir().join(format!("common-walk-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for (path, contents) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            TempTree(root)
+        }
+
+        fn relative(&self, walk: Walk) -> Vec<String> {
+            walk.files().unwrap().iter().map(|path| path.strip_prefix(&self.0).unwrap().to_string_lossy().into_owned()).collect()
+        }
+    }
+
+    impl Drop for TempTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn ignore_rules() {
+        let rules = IgnoreRules::parse("# build output\n*.log\n!keep.log\n/target\ncache/\ndocs/**/*.html\n\\#notes\n");
+        let ignored = |path: &str, is_dir| rules.matched(Path::new(path), is_dir);
+        assert_eq!(Some(true), ignored("debug.log", false));
+        assert_eq!(Some(true), ignored("a/b/debug.log", false));
+        assert_eq!(Some(false), ignored("a/keep.log", false));
+        assert_eq!(Some(true), ignored("target", true));
+        assert_eq!(None, ignored("src/target", true));
+        assert_eq!(Some(true), ignored("src/cache", true));
+        assert_eq!(None, ignored("src/cache", false));
+        assert_eq!(Some(true), ignored("docs/api/x/index.html", false));
+        assert_eq!(Some(true), ignored("#notes", false));
+        assert_eq!(None, ignored("main.rs", false));
+        assert!(IgnoreRules::parse("\n# nothing\n").is_empty());
+    }
+
+    #[test]
+    fn walks_in_order_with_ignore_files() {
+        let tree = TempTree::new("ignore", &[
+            (".gitignore", "*.log\n/build\n"),
+            ("a.txt", ""),
+            ("b/c.txt", ""),
+            ("b/debug.log", ""),
+            ("b/.gitignore", "!debug.log\nc.txt\n"),
+            ("build/out.txt", ""),
+            ("src/build/keep.txt", ""),
+            ("z.log", ""),
+            (".git/HEAD", ""),
+        ]);
+
+        assert_eq!(vec![".gitignore", "a.txt", "b/.gitignore", "b/debug.log", "src/build/keep.txt"], tree.relative(Walk::new(&tree.0)));
+        assert_eq!(9, tree.relative(Walk::new(&tree.0).ignore_files(false)).len());
+    }
+
+    #[test]
+    fn depth_limits_and_entries() {
+        let tree = TempTree::new("depth", &[("top.txt", ""), ("one/two/three/deep.txt", ""), ("one/mid.txt", "")]);
+        assert_eq!(vec!["top.txt"], tree.relative(Walk::new(&tree.0).max_depth(1)));
+        assert_eq!(vec!["one/mid.txt", "top.txt"], tree.relative(Walk::new(&tree.0).max_depth(2)));
+
+        let entries: Vec<(usize, bool)> = Walk::new(&tree.0).into_iter().map(|entry| entry.unwrap()).map(|e| (e.depth(), e.is_dir())).collect();
+        // root, one, one/mid.txt, one/two, one/two/three, deep.txt, top.txt
+        assert_eq!(vec![(0, true), (1, true), (2, false), (2, true), (3, true), (4, false), (1, false)], entries);
+
+        assert!(Walk::new(tree.0.join("missing")).files().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_policies() {
+        let tree = TempTree::new("links", &[("real/file.txt", "")]);
+        std::os::unix::fs::symlink(tree.0.join("real"), tree.0.join("link")).unwrap();
+        std::os::unix::fs::symlink(tree.0.join("real/file.txt"), tree.0.join("real/alias.txt")).unwrap();
+        // A link back up to the root, followed it would go on forever
+        std::os::unix::fs::symlink(&tree.0, tree.0.join("real/loop")).unwrap();
+
+        assert_eq!(vec!["real/file.txt"], tree.relative(Walk::new(&tree.0)));
+        let listed = Walk::new(&tree.0).into_iter().filter(|entry| entry.as_ref().unwrap().is_symlink()).count();
+        assert_eq!(3, listed);
+        assert_eq!(0, Walk::new(&tree.0).symlinks(Symlinks::Skip).into_iter().filter(|e| e.as_ref().unwrap().is_symlink()).count());
+
+        let followed: Vec<io::Result<DirEntry>> = Walk::new(&tree.0).symlinks(Symlinks::Follow).into_iter().collect();
+        let files: Vec<String> = followed
+            .iter()
+            .filter_map(|entry| entry.as_ref().ok())
+            .filter(|entry| entry.is_file())
+            .map(|entry| entry.path().strip_prefix(&tree.0).unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(vec!["link/alias.txt", "link/file.txt", "real/alias.txt", "real/file.txt"], files);
+        // Both ways into the loop are reported, and neither is walked
+        assert_eq!(2, followed.iter().filter(|entry| entry.is_err()).count());
+    }
+}
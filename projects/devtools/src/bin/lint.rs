@@ -0,0 +1,51 @@
+// Finds the patterns the chapters warn about, see src/lint.rs
+
+// $ cargo run --bin lint -- ../.. --allow ignored-result
+
+use std::{path::Path, process};
+
+use devtools::lint::{lint_path, Lint};
+use common::argparse::{ArgError, Parser};
+
+fn lints() -> Result<(String, Vec<Lint>), ArgError> {
+    let names: Vec<&str> = Lint::ALL.iter().map(Lint::name).collect();
+    let matches = Parser::new("lint", "finds unwraps outside the tests, guards held by while let and results thrown away")
+        .positional("path", "A file, or a directory to look for .rs files in")
+        .default(".")
+        .option("allow", "LINT", &format!("A lint not to report, can be given more than once: {}", names.join(", ")))
+        .parse(std::env::args().skip(1))?;
+
+    let mut lints = Lint::ALL.to_vec();
+    for allowed in matches.values("allow") {
+        let lint = Lint::from_name(allowed)
+            .ok_or_else(|| ArgError::Invalid(format!("there is no lint called {allowed}, only {}", names.join(", "))))?;
+        lints.retain(|&other| other != lint);
+    }
+    Ok((matches.value("path").unwrap().to_string(), lints))
+}
+
+fn main() {
+    let (path, lints) = match lints() {
+        Ok(lints) => lints,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    };
+
+    let report = lint_path(Path::new(&path), &lints);
+    for finding in &report.findings {
+        println!("{finding}");
+    }
+    for (file, error) in &report.errors {
+        eprintln!("{}: couldn't lint: {error}", file.display());
+    }
+    println!("{} findings in {} files", report.findings.len(), report.files);
+    if !report.findings.is_empty() || !report.errors.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,48 @@
+// Makes a new example project, see src/scaffold.rs
+
+use std::{path::Path, process};
+
+use devtools::scaffold::Scaffold;
+use common::argparse::{ArgError, Parser};
+
+fn main() {
+    let parser = Parser::new("scaffold", "makes the directory of a new project, with a library, its tests and a Cargo.toml")
+        .positional("name", "The name of the crate, and of its directory")
+        .option("dir", "DIR", "Where to make the project")
+        .default(".")
+        .option("about", "TEXT", "What the project is about, for the comment at the top of src/lib.rs")
+        .flag("bin", "Also make a src/main.rs");
+    let matches = match parser.parse(std::env::args().skip(1)) {
+        Ok(matches) => matches,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    };
+
+    let mut scaffold = match Scaffold::new(matches.value("name").unwrap()) {
+        Ok(scaffold) => scaffold.binary(matches.flag("bin")),
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    };
+    if let Some(about) = matches.value("about") {
+        scaffold = scaffold.about(about);
+    }
+    match scaffold.write(Path::new(matches.value("dir").unwrap())) {
+        Ok(files) => {
+            for file in files {
+                println!("created {}", file.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    }
+}
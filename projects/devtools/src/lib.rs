@@ -0,0 +1,10 @@
+// Development Tools
+
+// Programs for working on this repository rather than examples in it, each a binary in src/bin with its logic here,
+// where the tests can reach it:
+    // 1. scaffold makes the directory of a new example project, see scaffold.rs.
+    // 2. lint finds the patterns the chapters warn about in the examples, see lint.rs.
+
+pub mod lint;
+pub mod scaffold;
+pub mod template;
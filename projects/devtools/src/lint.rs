@@ -0,0 +1,302 @@
+// Linting the Examples
+
+// The chapters warn about a few patterns that compile fine and go wrong later. lint finds them in the repository's own code,
+// so the examples keep to what the text says:
+    // 1. unwrap: an .unwrap() outside the tests. It's fine in a test, where a panic is a failure with a message, and in main
+    //    for what can't fail, but a library that unwraps panics in someone else's program. Error handling, chapter 9.
+    //    The tests are the #[cfg(test)] modules, the #[test] functions, and every file in a tests/ directory.
+    // 2. lock-in-while-let: while let Ok(job) = receiver.lock().unwrap().recv() { job() } keeps the MutexGuard for the whole
+    //    body, since the temporaries of the scrutinee live until the end of the loop, so one job runs at a time.
+    //    The web server of chapter 20 explains it, and takes the job out with let first.
+    // 3. ignored-result: let _ = f(); throws away a Result, and with it the error. Sometimes that's right, a logger has nowhere
+    //    to report that it couldn't write, so a comment on the line before that says why makes it fine.
+    //    .ok(); as a statement is the same thing without the let.
+
+// syn parses a file into the same syntax tree a procedural macro gets (see advanced_features/macros/codec_derive), and
+// syn::visit walks it: Linter overrides the visit_ methods of the nodes it's interested in and calls the default ones to go on
+// into their children. Spans carry lines and columns only with proc-macro2's span-locations feature, outside a macro.
+// syn knows the syntax and nothing of the types, so the checks go by names: any method called unwrap counts, and
+// let _ = of any call. That's what a lint without the compiler can do, and why the third lint lets a comment through.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use proc_macro2::Span;
+use syn::{
+    spanned::Spanned,
+    visit::{self, Visit},
+    Attribute, Expr, ExprMethodCall, ExprWhile, ItemFn, ItemImpl, ItemMod, Local, Pat, Stmt,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    Unwrap,
+    LockInWhileLet,
+    IgnoredResult,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 3] = [Lint::Unwrap, Lint::LockInWhileLet, Lint::IgnoredResult];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::Unwrap => "unwrap",
+            Lint::LockInWhileLet => "lock-in-while-let",
+            Lint::IgnoredResult => "ignored-result",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Finding {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub lint: Lint,
+    pub message: String,
+}
+
+// Like the compiler's, so that an editor can jump to it
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}: {}", self.file.display(), self.line, self.column, self.lint.name(), self.message)
+    }
+}
+
+fn is_test(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        attr.path().is_ident("test")
+            || (attr.path().is_ident("cfg") && attr.parse_args::<syn::Ident>().is_ok_and(|ident| ident == "test"))
+    })
+}
+
+struct Linter<'a> {
+    file: &'a Path,
+    lines: Vec<&'a str>,
+    lints: &'a [Lint],
+    // How many test modules and functions we're inside of
+    in_test: usize,
+    findings: Vec<Finding>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, lint: Lint, span: Span, message: &str) {
+        if !self.lints.contains(&lint) {
+            return;
+        }
+        let start = span.start();
+        self.findings.push(Finding {
+            file: self.file.to_path_buf(),
+            line: start.line,
+            column: start.column + 1,
+            lint,
+            message: message.to_string(),
+        });
+    }
+
+    fn commented_above(&self, span: Span) -> bool {
+        let line = span.start().line;
+        line >= 2 && self.lines.get(line - 2).is_some_and(|above| above.trim_start().starts_with("//"))
+    }
+
+    fn visit_test_aware(&mut self, attrs: &[Attribute], visit: impl FnOnce(&mut Self)) {
+        let test = is_test(attrs);
+        self.in_test += usize::from(test);
+        visit(self);
+        self.in_test -= usize::from(test);
+    }
+}
+
+// Whether an expression calls .lock(), .read() or .write() anywhere in it
+#[derive(Default)]
+struct FindsLock(Option<Span>);
+
+impl<'ast> Visit<'ast> for FindsLock {
+    fn visit_expr_method_call(&mut self, call: &'ast ExprMethodCall) {
+        if ["lock", "read", "write"].iter().any(|name| call.method == name) && call.args.is_empty() {
+            self.0.get_or_insert(call.method.span());
+        }
+        visit::visit_expr_method_call(self, call);
+    }
+}
+
+impl<'ast> Visit<'ast> for Linter<'_> {
+    fn visit_item_mod(&mut self, item: &'ast ItemMod) {
+        self.visit_test_aware(&item.attrs, |linter| visit::visit_item_mod(linter, item));
+    }
+
+    fn visit_item_fn(&mut self, item: &'ast ItemFn) {
+        self.visit_test_aware(&item.attrs, |linter| visit::visit_item_fn(linter, item));
+    }
+
+    fn visit_item_impl(&mut self, item: &'ast ItemImpl) {
+        self.visit_test_aware(&item.attrs, |linter| visit::visit_item_impl(linter, item));
+    }
+
+    fn visit_expr_method_call(&mut self, call: &'ast ExprMethodCall) {
+        if call.method == "unwrap" && self.in_test == 0 {
+            let message = "unwrap outside the tests, return the error or expect() with why it can't happen";
+            self.report(Lint::Unwrap, call.method.span(), message);
+        }
+        visit::visit_expr_method_call(self, call);
+    }
+
+    fn visit_expr_while(&mut self, expr: &'ast ExprWhile) {
+        if let Expr::Let(condition) = &*expr.cond {
+            let mut finds = FindsLock::default();
+            finds.visit_expr(&condition.expr);
+            if let Some(span) = finds.0 {
+                let message = "the guard is held for the whole loop body, take the value out with let first";
+                self.report(Lint::LockInWhileLet, span, message);
+            }
+        }
+        visit::visit_expr_while(self, expr);
+    }
+
+    fn visit_local(&mut self, local: &'ast Local) {
+        let call = local.init.as_ref().is_some_and(|init| matches!(&*init.expr, Expr::Call(_) | Expr::MethodCall(_)));
+        if matches!(local.pat, Pat::Wild(_)) && call && !self.commented_above(local.span()) {
+            self.report(Lint::IgnoredResult, local.span(), "let _ = throws the result away, say why in a comment above");
+        }
+        visit::visit_local(self, local);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        if let Stmt::Expr(Expr::MethodCall(call), Some(_)) = stmt {
+            if call.method == "ok" && call.args.is_empty() && !self.commented_above(stmt.span()) {
+                self.report(Lint::IgnoredResult, call.method.span(), ".ok(); throws the error away, say why in a comment above");
+            }
+        }
+        visit::visit_stmt(self, stmt);
+    }
+}
+
+// The findings in one file's source, in the order they are in the file. file is only for the report
+pub fn lint_source(file: &Path, source: &str, lints: &[Lint]) -> syn::Result<Vec<Finding>> {
+    let syntax = syn::parse_file(source)?;
+    let in_test = usize::from(file.components().any(|component| component.as_os_str() == "tests"));
+    let mut linter = Linter { file, lines: source.lines().collect(), lints, in_test, findings: Vec::new() };
+    linter.visit_file(&syntax);
+    linter.findings.sort();
+    Ok(linter.findings)
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+    // The files that couldn't be read or parsed, and why
+    pub errors: Vec<(PathBuf, String)>,
+    pub files: usize,
+}
+
+// Every .rs file below the path, or the path itself. What the .gitignore files ignore is skipped, and so is every target dir,
+// which has generated code in it and is only ignored by the .gitignore at the root of the repository
+pub fn lint_path(path: &Path, lints: &[Lint]) -> Report {
+    let mut report = Report::default();
+    let files = match common::walk::Walk::new(path).files() {
+        Ok(files) => files,
+        Err(e) => {
+            report.errors.push((path.to_path_buf(), e.to_string()));
+            return report;
+        }
+    };
+    let rust = files
+        .into_iter()
+        .filter(|file| file.extension().is_some_and(|extension| extension == "rs"))
+        .filter(|file| !file.components().any(|component| component.as_os_str() == "target"));
+    for file in rust {
+        report.files += 1;
+        let result = fs::read_to_string(&file).map_err(|e| e.to_string()).and_then(|source| {
+            let start = |e: &syn::Error| e.span().start();
+            lint_source(&file, &source, lints).map_err(|e| format!("{}:{}: {e}", start(&e).line, start(&e).column + 1))
+        });
+        match result {
+            Ok(findings) => report.findings.extend(findings),
+            Err(e) => report.errors.push((file, e)),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(source: &str) -> Vec<String> {
+        lint_source(Path::new("src/lib.rs"), source, &Lint::ALL).unwrap().iter().map(Finding::to_string).collect()
+    }
+
+    #[test]
+    fn unwrap_only_outside_the_tests() {
+        let source = "\
+fn parse(s: &str) -> u32 {
+    s.parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    fn helper() -> u32 { \"1\".parse().unwrap() }
+}
+
+#[test]
+fn it_works() { Some(1).unwrap(); }
+";
+        assert_eq!(
+            vec!["src/lib.rs:2:15: unwrap: unwrap outside the tests, return the error or expect() with why it can't happen"],
+            lint(source)
+        );
+    }
+
+    #[test]
+    fn guards_held_by_while_let() {
+        let source = "\
+fn work(receiver: std::sync::Mutex<std::sync::mpsc::Receiver<Job>>) {
+    while let Ok(job) = receiver.lock().expect(\"poisoned\").recv() {
+        job();
+    }
+    loop {
+        let job = receiver.lock().expect(\"poisoned\").recv();
+        while let Some(x) = next() {}
+    }
+}
+";
+        let findings = lint(source);
+        assert_eq!(1, findings.len(), "{findings:?}");
+        assert!(findings[0].starts_with("src/lib.rs:2:34: lock-in-while-let:"), "{findings:?}");
+    }
+
+    #[test]
+    fn results_thrown_away_without_a_reason() {
+        let source = "\
+fn send(tx: Sender<u8>, out: &mut impl Write) {
+    let _ = tx.send(1);
+    // Nobody is listening anymore, which is fine
+    let _ = tx.send(2);
+    out.flush().ok();
+    let _ = 5;
+    let _guard = lock();
+}
+";
+        let findings = lint(source);
+        assert_eq!(2, findings.len(), "{findings:?}");
+        assert!(findings[0].starts_with("src/lib.rs:2:5: ignored-result: let _ ="), "{findings:?}");
+        assert!(findings[1].starts_with("src/lib.rs:5:17: ignored-result: .ok();"), "{findings:?}");
+    }
+
+    #[test]
+    fn only_the_lints_asked_for() {
+        let source = "fn f() { let _ = g().unwrap(); }";
+        let findings = lint_source(Path::new("a.rs"), source, &[Lint::IgnoredResult]).unwrap();
+        assert_eq!(vec![Lint::IgnoredResult], findings.iter().map(|finding| finding.lint).collect::<Vec<_>>());
+        assert_eq!(Some(Lint::LockInWhileLet), Lint::from_name("lock-in-while-let"));
+        assert!(lint_source(Path::new("a.rs"), "fn f( {", &Lint::ALL).is_err());
+        // An integration test is all test
+        assert!(lint_source(Path::new("tests/cli.rs"), "fn f() { g().unwrap(); }", &Lint::ALL).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+// Scaffolding a New Project
+
+// Every project in this repository starts the same way: a Cargo.toml, a src/lib.rs with a heading comment and a tests module,
+// and a test in tests/ that uses the library the way another crate would. `cargo new` gets half of that, scaffold the rest:
+
+    // $ cargo run --bin scaffold -- word_count --about "Counts the words of a file." --bin --dir ../projects
+
+// The files are templates in devtools/templates, built into the binary with include_str!, so scaffold works from anywhere
+// and the templates can be edited as the files they will become. template.rs fills them in.
+// An existing directory is never written into: scaffold is for new projects, and a typo in the name shouldn't overwrite one.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::template::{self, TemplateError};
+
+const CARGO_TOML: &str = include_str!("../templates/Cargo.toml.tmpl");
+const LIB_RS: &str = include_str!("../templates/lib.rs.tmpl");
+const MAIN_RS: &str = include_str!("../templates/main.rs.tmpl");
+const TEST_RS: &str = include_str!("../templates/test.rs.tmpl");
+
+#[derive(Debug)]
+pub enum ScaffoldError {
+    // Cargo would refuse it, or it would make a crate name nobody can `use`
+    InvalidName(String),
+    Exists(PathBuf),
+    Template(TemplateError),
+    Io(io::Error),
+}
+
+impl fmt::Display for ScaffoldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScaffoldError::InvalidName(name) => {
+                write!(f, "{name:?} isn't a crate name: lowercase letters, digits and _, starting with a letter")
+            }
+            ScaffoldError::Exists(dir) => write!(f, "{} already exists", dir.display()),
+            ScaffoldError::Template(e) => write!(f, "a template is broken: {e}"),
+            ScaffoldError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ScaffoldError {}
+
+impl From<io::Error> for ScaffoldError {
+    fn from(e: io::Error) -> Self {
+        ScaffoldError::Io(e)
+    }
+}
+
+impl From<TemplateError> for ScaffoldError {
+    fn from(e: TemplateError) -> Self {
+        ScaffoldError::Template(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scaffold {
+    pub name: String,
+    pub about: String,
+    // Also a src/main.rs that calls the library
+    pub binary: bool,
+}
+
+impl Scaffold {
+    pub fn new(name: &str) -> Result<Scaffold, ScaffoldError> {
+        let mut chars = name.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid {
+            return Err(ScaffoldError::InvalidName(name.to_string()));
+        }
+        Ok(Scaffold { name: name.to_string(), about: String::from("What this project is about."), binary: false })
+    }
+
+    pub fn about(mut self, about: &str) -> Scaffold {
+        self.about = about.to_string();
+        self
+    }
+
+    pub fn binary(mut self, binary: bool) -> Scaffold {
+        self.binary = binary;
+        self
+    }
+
+    // Each file's path in the project, and what goes in it
+    pub fn files(&self) -> Result<Vec<(PathBuf, String)>, ScaffoldError> {
+        let vars = HashMap::from([("name", self.name.clone()), ("about", self.about.clone())]);
+        let mut templates = vec![
+            (PathBuf::from("Cargo.toml"), CARGO_TOML),
+            (PathBuf::from("src/lib.rs"), LIB_RS),
+            (Path::new("tests").join(format!("{}.rs", self.name)), TEST_RS),
+        ];
+        if self.binary {
+            templates.push((PathBuf::from("src/main.rs"), MAIN_RS));
+        }
+        templates.into_iter().map(|(path, text)| Ok((path, template::render(text, &vars)?))).collect()
+    }
+
+    // Makes the project in parent/name, and returns the files it wrote
+    pub fn write(&self, parent: &Path) -> Result<Vec<PathBuf>, ScaffoldError> {
+        let root = parent.join(&self.name);
+        if root.exists() {
+            return Err(ScaffoldError::Exists(root));
+        }
+        // Rendered before anything is written, so a broken template leaves no half-made project behind
+        let files = self.files()?;
+        let mut written = Vec::new();
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap_or(&root))?;
+            fs::write(&path, contents)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_have_to_be_crate_names() {
+        for name in ["word_count", "a", "v2"] {
+            assert!(Scaffold::new(name).is_ok(), "{name}");
+        }
+        for name in ["", "Word", "2fast", "word-count", "word count", "../escape"] {
+            assert!(matches!(Scaffold::new(name), Err(ScaffoldError::InvalidName(_))), "{name}");
+        }
+    }
+
+    #[test]
+    fn renders_every_file() {
+        let files = Scaffold::new("word_count").unwrap().about("Counts words.").files().unwrap();
+        let paths: Vec<_> = files.iter().map(|(path, _)| path.to_str().unwrap()).collect();
+        assert_eq!(vec!["Cargo.toml", "src/lib.rs", "tests/word_count.rs"], paths);
+        assert!(files[0].1.contains("name = \"word_count\""));
+        assert!(files[1].1.starts_with("// Word Count\n\n// Counts words.\n"));
+        assert!(files[1].1.contains("format!(\"Hello from word_count, {name}!\")"));
+        assert!(files[2].1.contains("use word_count::greeting;"));
+
+        let files = Scaffold::new("word_count").unwrap().binary(true).files().unwrap();
+        assert!(files[3].1.contains("word_count::greeting(&name)"));
+    }
+}
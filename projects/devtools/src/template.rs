@@ -0,0 +1,134 @@
+// Templates
+
+// A template is text with {{name}} where a value goes. A filter after a | changes the value on the way in:
+    // 1. {{name | title}} makes words of it, my_project becomes My Project, for the heading of a file.
+    // 2. {{name | camel}} is the name of a type, my_project becomes MyProject.
+    // 3. {{name | upper}} is the name of a constant, MY_PROJECT.
+// A name the template uses but wasn't given is an error, with the line it's on, rather than an empty hole in the output.
+// Generated Rust code has braces of its own, and format! strings even have {{ in them. Only {{ followed by a name and }}
+// is a placeholder, so { and } by themselves come out as they are, and \{{ is a {{ that isn't one.
+
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    Unclosed { line: usize },
+    UnknownVariable { line: usize, name: String },
+    UnknownFilter { line: usize, filter: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::Unclosed { line } => write!(f, "line {line}: {{{{ without }}}}"),
+            TemplateError::UnknownVariable { line, name } => write!(f, "line {line}: nothing to put in for {name}"),
+            TemplateError::UnknownFilter { line, filter } => write!(f, "line {line}: unknown filter {filter}"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+fn words(value: &str) -> impl Iterator<Item = &str> {
+    value.split(['_', '-', ' ']).filter(|word| !word.is_empty())
+}
+
+fn capitalized(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+}
+
+fn apply(filter: &str, value: &str) -> Option<String> {
+    Some(match filter {
+        "title" => words(value).map(capitalized).collect::<Vec<_>>().join(" "),
+        "camel" => words(value).map(capitalized).collect(),
+        "upper" => words(value).map(str::to_uppercase).collect::<Vec<_>>().join("_"),
+        _ => return None,
+    })
+}
+
+// A placeholder is only a name, with filters, between the braces
+fn is_placeholder(inside: &str) -> bool {
+    inside.split('|').all(|part| {
+        let part = part.trim();
+        !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+pub fn render(template: &str, vars: &HashMap<&str, String>) -> Result<String, TemplateError> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let line = template[..template.len() - rest.len() + start].matches('\n').count() + 1;
+        if rest[..start].ends_with('\\') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            // A {{ that was meant as a placeholder and forgot its end, or a brace of the code
+            let line_rest = after.split('\n').next().unwrap_or_default();
+            if is_placeholder(line_rest) {
+                return Err(TemplateError::Unclosed { line });
+            }
+            out.push_str("{{");
+            rest = after;
+            continue;
+        };
+        let inside = &after[..end];
+        if !is_placeholder(inside) {
+            out.push_str("{{");
+            rest = after;
+            continue;
+        }
+        let mut parts = inside.split('|').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let mut value = vars.get(name).cloned().ok_or_else(|| TemplateError::UnknownVariable { line, name: name.to_string() })?;
+        for filter in parts {
+            value = apply(filter, &value).ok_or_else(|| TemplateError::UnknownFilter { line, filter: filter.to_string() })?;
+        }
+        out.push_str(&value);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<&'static str, String> {
+        HashMap::from([("name", String::from("word_count")), ("about", String::from("Counts words."))])
+    }
+
+    #[test]
+    fn fills_in_and_filters() {
+        let template = "// {{name | title}}\n// {{ about }}\nstruct {{name|camel}};\nconst {{name | upper}}: u8 = 1;\n";
+        assert_eq!(
+            "// Word Count\n// Counts words.\nstruct WordCount;\nconst WORD_COUNT: u8 = 1;\n",
+            render(template, &vars()).unwrap()
+        );
+    }
+
+    #[test]
+    fn leaves_rusts_braces_alone() {
+        let template = "fn f() { println!(\"{{}} {{name}} {{ {}\", 1) }\nlet s = \"\\{{name}}\";";
+        assert_eq!("fn f() { println!(\"{{}} word_count {{ {}\", 1) }\nlet s = \"{{name}}\";", render(template, &vars()).unwrap());
+    }
+
+    #[test]
+    fn reports_the_line() {
+        assert_eq!(
+            Err(TemplateError::UnknownVariable { line: 2, name: String::from("author") }),
+            render("{{name}}\nby {{author}}", &vars())
+        );
+        let shout = TemplateError::UnknownFilter { line: 1, filter: String::from("shout") };
+        assert_eq!(Err(shout), render("{{name|shout}}", &vars()));
+        assert_eq!("line 3: {{ without }}", render("\n\n{{name", &vars()).unwrap_err().to_string());
+        assert_eq!("format!(\"{{\")", render("format!(\"{{\")", &vars()).unwrap());
+    }
+}
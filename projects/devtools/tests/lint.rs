@@ -0,0 +1,29 @@
+// The lint binary on a small crate of its own, with one of everything
+
+use test_support::{cargo_bin, TempDir};
+
+#[test]
+fn reports_with_file_and_line_and_fails() {
+    let dir = TempDir::new();
+    dir.write(
+        "src/lib.rs",
+        "pub fn first(v: &[u8]) -> u8 {\n    *v.first().unwrap()\n}\n\npub fn drain(rx: &std::sync::Mutex<Receiver>) {\n    \
+         while let Ok(x) = rx.lock().map(|rx| rx.recv()) {\n        let _ = x;\n    }\n}\n",
+    );
+    dir.write("tests/first.rs", "#[test]\nfn works() { assert_eq!(1, first(&[1]).checked_add(0).unwrap()); }\n");
+    dir.write("target/debug/generated.rs", "fn f() { x.unwrap() }\n");
+
+    let lib = dir.child("src/lib.rs");
+    let failed = cargo_bin!("lint").arg(dir.path()).assert().failure();
+    let expected = format!(
+        "{lib}:2:16: unwrap: unwrap outside the tests, return the error or expect() with why it can't happen\n\
+         {lib}:6:26: lock-in-while-let: the guard is held for the whole loop body, take the value out with let first\n\
+         2 findings in 2 files\n",
+        lib = lib.display()
+    );
+    assert_eq!(expected, failed.stdout_str());
+
+    cargo_bin!("lint").arg(dir.path()).args(["--allow", "unwrap", "--allow", "lock-in-while-let"]).assert().success();
+    let refused = cargo_bin!("lint").args(["--allow", "everything"]).assert().failure();
+    assert!(refused.stderr_str().contains("there is no lint called everything"));
+}
@@ -0,0 +1,32 @@
+// The scaffold binary, run like a user would, and the project it makes built with cargo
+
+use test_support::{cargo_bin, Cmd, TempDir};
+
+#[test]
+fn makes_a_project_that_builds_and_passes_its_tests() {
+    let dir = TempDir::new();
+    let parent = dir.path().to_str().unwrap();
+    cargo_bin!("scaffold").args(["word_count", "--bin", "--dir", parent, "--about", "Counts words."]).assert().success();
+    assert!(dir.read("word_count/src/lib.rs").starts_with("// Word Count\n\n// Counts words.\n"));
+
+    // A second time would overwrite it
+    let refused = cargo_bin!("scaffold").args(["word_count", "--dir", parent]).assert().failure();
+    assert!(refused.stderr_str().contains("already exists"), "{}", refused.stderr_str());
+
+    // Offline, and with a target dir of its own, so it doesn't wait for the lock of the build running this test
+    let project = dir.child("word_count");
+    let cargo = |args: &[&str]| {
+        Cmd::new(env!("CARGO")).args(args).current_dir(&project).env("CARGO_TARGET_DIR", project.join("target")).assert().success()
+    };
+    cargo(&["test", "--offline", "--quiet"]);
+    let run = cargo(&["run", "--offline", "--quiet", "--", "Ferris"]);
+    assert_eq!("Hello from word_count, Ferris!\n", run.stdout_str());
+}
+
+#[test]
+fn refuses_names_that_arent_crate_names() {
+    let dir = TempDir::new();
+    let failed = cargo_bin!("scaffold").args(["Word-Count", "--dir", dir.path().to_str().unwrap()]).assert().failure();
+    assert!(failed.stderr_str().contains("isn't a crate name"));
+    assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+}
@@ -0,0 +1,147 @@
+// The Key-Value Client
+
+// $ cargo run --bin kv-cli -- --addr 127.0.0.1:6380
+// 127.0.0.1:6380> SET greeting "hello world" EX 60
+// OK
+// 127.0.0.1:6380> GET greeting
+// "hello world"
+// Commands are typed like in a shell, quotes and all (resp::split_args), sent as RESP arrays, and the answers are printed
+// the way redis-cli prints them. With --table, the pages SCAN and RANGE answer are tables instead, see Frame::page_table:
+// 127.0.0.1:6380> RANGE 0 [user: (user;
+// key     value
+// ------  ------
+// user:1  ferris
+// (cursor 0)
+
+// At a terminal it waits for each answer before asking for the next command. With a file on stdin it pipelines instead:
+// $ cargo run --bin kv-cli < commands.txt
+// One thread sends every command without waiting, and this one prints the answers as they come. Both at once, because with
+// one thread sending everything first, a long file would fill the socket's buffers both ways: the server would stop reading
+// until somebody read its answers, and nobody would, until everything was sent.
+
+use std::{
+    io::{self, prelude::*, BufReader, BufWriter, IsTerminal},
+    net::TcpStream,
+    process,
+    sync::mpsc,
+    thread,
+};
+
+use kvstore::resp::{self, Frame};
+use common::argparse::{ArgError, Parser};
+
+// The address of the server, and whether --table was given
+fn args() -> Result<(String, bool), ArgError> {
+    let matches = Parser::new("kv-cli", "a command line client for the kvstore server")
+        .option("addr", "ADDR", "The address of the server")
+        .default("127.0.0.1:6380")
+        .flag("table", "Print the pages of SCAN and RANGE as tables")
+        .parse(std::env::args().skip(1))?;
+    Ok((matches.value("addr").unwrap().to_string(), matches.flag("table")))
+}
+
+// The command on the line, None for a blank one. A line with an open quote is an error here, before it gets to the server
+fn command(line: &str) -> Result<Option<Frame>, String> {
+    match resp::split_args(line.as_bytes()) {
+        Some(args) if args.is_empty() => Ok(None),
+        Some(args) => Ok(Some(Frame::command(&args))),
+        None => Err(String::from("unbalanced quotes")),
+    }
+}
+
+// The columns of the table the answer to the command is, with --table. None for the commands whose answer isn't a page
+fn columns(command: &Frame, table: bool) -> Option<&'static [&'static str]> {
+    let Frame::Array(args) = command else { return None };
+    let Some(Frame::Bulk(name)) = args.first() else { return None };
+    match name.to_ascii_uppercase().as_slice() {
+        b"SCAN" if table => Some(&["key"]),
+        b"RANGE" if table => Some(&["key", "value"]),
+        _ => None,
+    }
+}
+
+fn show(answer: &Frame, columns: Option<&[&str]>) -> String {
+    columns.and_then(|columns| answer.page_table(columns)).unwrap_or_else(|| answer.pretty())
+}
+
+// The next answer, and an error if the server hung up instead
+fn answer(input: &mut impl BufRead) -> io::Result<Frame> {
+    resp::read_frame(input)?.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection"))
+}
+
+fn interactive(stream: TcpStream, prompt: &str, table: bool) -> io::Result<()> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut output = stream;
+    let mut lines = io::stdin().lines();
+    loop {
+        print!("{prompt}> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            println!();
+            return Ok(());
+        };
+        let frame = match command(&line) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("(error) {e}");
+                continue;
+            }
+        };
+        let quit = line.trim().eq_ignore_ascii_case("quit");
+        output.write_all(&frame.to_bytes())?;
+        println!("{}", show(&answer(&mut input)?, columns(&frame, table)));
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+fn pipelined(stream: TcpStream, table: bool) -> io::Result<()> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    // One message per command sent, so that this thread knows how many answers to wait for, and how to print each
+    let (sent, commands) = mpsc::channel();
+    let sender = thread::spawn(move || -> io::Result<()> {
+        let mut output = BufWriter::new(stream);
+        for line in io::stdin().lines() {
+            match command(&line?) {
+                Ok(Some(frame)) => {
+                    resp::write_frame(&mut output, &frame)?;
+                    let _ = sent.send(columns(&frame, table));
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("(error) {e}"),
+            }
+        }
+        output.flush()
+    });
+
+    for columns in commands {
+        println!("{}", show(&answer(&mut input)?, columns));
+    }
+    sender.join().unwrap()
+}
+
+fn main() {
+    let (addr, table) = match args() {
+        Ok(args) => args,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    let stream = TcpStream::connect(&addr).unwrap_or_else(|e| {
+        eprintln!("couldn't connect to {addr}: {e}");
+        process::exit(1);
+    });
+
+    let result = if io::stdin().is_terminal() { interactive(stream, &addr, table) } else { pipelined(stream, table) };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
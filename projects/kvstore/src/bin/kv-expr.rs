@@ -0,0 +1,54 @@
+// The Expression REPL
+
+// $ cargo run --bin kv-expr
+// > let age = 30
+// age = 30
+// > age >= 18 AND NOT age > 65
+// true
+// > :dump age * 2
+// 0000  load age
+// 0001  push 2
+// 0002  mul
+// The expressions are the ones of a WHERE (kvstore::expr), compiled to bytecode and run. repl.rs has the rest, :help too.
+// At a terminal the line can be edited with the arrow keys, and Up brings back the earlier ones. With a file on stdin
+// each line is read and answered in turn, so a file of lines is a script.
+
+use std::{io, process};
+
+use kvstore::{
+    line_editor::Editor,
+    repl::{Reply, Session},
+};
+use common::argparse::{ArgError, Parser};
+
+fn run() -> io::Result<()> {
+    let mut editor = Editor::new();
+    let mut session = Session::new();
+    while let Some(line) = editor.read_line("> ")? {
+        match session.line(&line) {
+            Reply::Nothing => {}
+            Reply::Print(text) => println!("{text}"),
+            Reply::Quit => break,
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let parser = Parser::new("kv-expr", "evaluates expressions of kvstore queries, one line at a time (:help for more)");
+    match parser.parse(std::env::args().skip(1)) {
+        Ok(_) => {}
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,225 @@
+// Bytecode
+
+// Expr::eval walks the tree again for every record a query looks at: a Box to follow for every node, and a match on what's in
+// it. A Program is the same expression compiled once into a flat list of operations for a stack machine, the way many
+// interpreters run their code. Each Op takes its operands off the stack and pushes its result:
+    // age >= 18 AND admin
+    //
+    // 0000  load age
+    // 0001  push 18
+    // 0002  ge
+    // 0003  truth
+    // 0004  jump-if-false-or-pop 7
+    // 0005  load admin
+    // 0006  truth
+// AND and OR are jumps, so that the right side is only run when the left one doesn't decide, like in Expr::eval.
+// When the left side is false, AND jumps to the end and leaves it on the stack as the answer; otherwise it pops it,
+// and the right side is the answer. truth turns the side into the bool it stands for first, null is false.
+// The operations on the values are the ones of expr.rs, so a Program and Expr::eval always give the same answer, errors and all.
+
+use std::fmt;
+
+use crate::{
+    expr::{self, BinOp, EvalError, Expr},
+    value::Value,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Push(Value),
+    Load(String),
+    Not,
+    Neg,
+    Truth,
+    // The index of the Op to jump to
+    JumpIfFalseOrPop(usize),
+    JumpIfTrueOrPop(usize),
+    Binary(BinOp),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Push(value) => write!(f, "push {}", Expr::Literal(value.clone()).pretty()),
+            Op::Load(name) => write!(f, "load {name}"),
+            Op::Not => write!(f, "not"),
+            Op::Neg => write!(f, "neg"),
+            Op::Truth => write!(f, "truth"),
+            Op::JumpIfFalseOrPop(target) => write!(f, "jump-if-false-or-pop {target}"),
+            Op::JumpIfTrueOrPop(target) => write!(f, "jump-if-true-or-pop {target}"),
+            Op::Binary(op) => {
+                let name = match op {
+                    BinOp::Or => "or",
+                    BinOp::And => "and",
+                    BinOp::Eq => "eq",
+                    BinOp::Ne => "ne",
+                    BinOp::Lt => "lt",
+                    BinOp::Le => "le",
+                    BinOp::Gt => "gt",
+                    BinOp::Ge => "ge",
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    BinOp::Div => "div",
+                };
+                write!(f, "{name}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    ops: Vec<Op>,
+}
+
+impl Program {
+    pub fn compile(expr: &Expr) -> Program {
+        let mut program = Program { ops: Vec::new() };
+        program.emit(expr);
+        program
+    }
+
+    fn emit(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(value) => self.ops.push(Op::Push(value.clone())),
+            Expr::Field(name) => self.ops.push(Op::Load(name.clone())),
+            Expr::Not(expr) => {
+                self.emit(expr);
+                self.ops.push(Op::Not);
+            }
+            Expr::Neg(expr) => {
+                self.emit(expr);
+                self.ops.push(Op::Neg);
+            }
+            Expr::Binary(left, op @ (BinOp::And | BinOp::Or), right) => {
+                self.emit(left);
+                self.ops.push(Op::Truth);
+                // The target isn't known until the right side is compiled, so the jump is patched afterwards
+                let jump = self.ops.len();
+                self.ops.push(Op::JumpIfFalseOrPop(0));
+                self.emit(right);
+                self.ops.push(Op::Truth);
+                let end = self.ops.len();
+                self.ops[jump] = match op {
+                    BinOp::And => Op::JumpIfFalseOrPop(end),
+                    _ => Op::JumpIfTrueOrPop(end),
+                };
+            }
+            Expr::Binary(left, op, right) => {
+                self.emit(left);
+                self.emit(right);
+                self.ops.push(Op::Binary(*op));
+            }
+        }
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn run(&self, lookup: &dyn Fn(&str) -> Value) -> Result<Value, EvalError> {
+        let mut stack = Vec::new();
+        let mut next = 0;
+        // compile() only makes programs that have what they pop on the stack, and leave one value there
+        let pop = |stack: &mut Vec<Value>| stack.pop().expect("a compiled program never pops an empty stack");
+        while let Some(op) = self.ops.get(next) {
+            next += 1;
+            match op {
+                Op::Push(value) => stack.push(value.clone()),
+                Op::Load(name) => stack.push(lookup(name)),
+                Op::Not => {
+                    let value = pop(&mut stack);
+                    stack.push(expr::not(value)?);
+                }
+                Op::Neg => {
+                    let value = pop(&mut stack);
+                    stack.push(expr::neg(value)?);
+                }
+                Op::Truth => {
+                    let value = pop(&mut stack);
+                    stack.push(Value::Bool(expr::truth(&value)?));
+                }
+                Op::JumpIfFalseOrPop(target) | Op::JumpIfTrueOrPop(target) => {
+                    let decides = matches!(op, Op::JumpIfTrueOrPop(_));
+                    match stack.last() {
+                        Some(Value::Bool(b)) if *b == decides => next = *target,
+                        _ => {
+                            pop(&mut stack);
+                        }
+                    }
+                }
+                Op::Binary(op) => {
+                    let right = pop(&mut stack);
+                    let left = pop(&mut stack);
+                    stack.push(expr::binary(*op, left, right)?);
+                }
+            }
+        }
+        Ok(pop(&mut stack))
+    }
+}
+
+// One Op per line, with its index, which is what the jumps point at
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, op) in self.ops.iter().enumerate() {
+            writeln!(f, "{i:04}  {op}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Value {
+        match name {
+            "age" => Value::Int(30),
+            "name" => Value::from("ferris"),
+            "admin" => Value::Bool(true),
+            _ => Value::Null,
+        }
+    }
+
+    #[test]
+    fn listing() {
+        let program = Program::compile(&Expr::parse("age >= 18 AND admin").unwrap());
+        let listing = "\
+0000  load age
+0001  push 18
+0002  ge
+0003  truth
+0004  jump-if-false-or-pop 7
+0005  load admin
+0006  truth
+";
+        assert_eq!(listing, program.to_string());
+        let program = Program::compile(&Expr::parse("'it''s' + 1.0").unwrap());
+        assert_eq!("0000  push 'it''s'\n0001  push 1.0\n0002  add\n", program.to_string());
+    }
+
+    #[test]
+    fn the_same_answers_as_the_tree() {
+        let texts = [
+            "age >= 18 AND name = 'ferris' AND admin",
+            "age * 2 + 1",
+            "age / 2 + 0.5",
+            "NOT height > 100",
+            "height = null OR age < 0",
+            "-(age - 40) * 3",
+            "age < 'x'",
+            "age AND admin",
+            "admin OR age / 0 = 1",
+            "NOT admin AND age / 0 = 1",
+            "-name",
+            "9223372036854775807 + age",
+            "(age > 1 OR name = 'x') AND (admin AND NOT age = 30)",
+        ];
+        for text in texts {
+            let expr = Expr::parse(text).unwrap();
+            assert_eq!(expr.eval(&lookup), Program::compile(&expr).run(&lookup), "{text}");
+        }
+    }
+}
@@ -0,0 +1,432 @@
+// The Commands
+
+// What the server understands, a small part of what Redis does, with the same names and the same answers:
+    // 1. PING [message]                 PONG, or the message back
+    // 2. GET key                        the value, or null
+    // 3. SET key value [EX secs|PX ms]  OK, and the key expires after the time if there is one
+    // 4. DEL key [key ...]              how many of the keys were there
+    // 5. EXPIRE key secs                1 if the key is there and now expires, 0 if it isn't. 0 seconds or less deletes it
+    // 6. TTL key                        the seconds it has left, -1 if it doesn't expire, -2 if it isn't there
+    // 7. KEYS pattern                   the keys that match a glob pattern, see glob_match()
+    // 8. SCAN cursor [MATCH pattern] [COUNT n]
+    //                                   the next page of keys and the cursor after it, see cursor.rs
+    // 9. RANGE cursor min max [COUNT n] the same for the keys between min and max, with their values
+    // 10. QUIT                          OK, and the server closes the connection
+// Names are case-insensitive. Keys are Strings in the Store, so a key that isn't UTF-8 is an error here.
+
+// SCAN answers like in Redis, [cursor, [key, ...]], and is done when the cursor is 0. A page holds COUNT keys at most, 10 by
+// default, and MATCH leaves out the ones that don't match from it, so a page can have fewer, even none, before the last one.
+// RANGE answers [cursor, [key, value, key, value, ...]]. Its min and max are written like for ZRANGEBYLEX in Redis:
+// [key includes the key, (key doesn't, and - and + are the start and the end of all keys. `RANGE 0 [user: (user;` gets the
+// keys from user: to just before user; which are the ones that start with user: (; is the character after :).
+
+use std::{ops::Bound, time::Duration};
+
+use crate::{
+    cursor::{Cursor, CursorError},
+    resp::Frame,
+    store::Store,
+};
+
+pub const HELP: &str = "PING [message] | GET key | SET key value [EX secs|PX ms] | DEL key [key ...] | EXPIRE key secs | \
+                        TTL key | KEYS pattern | \
+                        SCAN cursor [MATCH pattern] [COUNT n] | RANGE cursor min max [COUNT n] | QUIT";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Ping(Option<Vec<u8>>),
+    Get(String),
+    Set { key: String, value: Vec<u8>, ttl: Option<Duration> },
+    Del(Vec<String>),
+    // Seconds, which can be 0 or less
+    Expire(String, i64),
+    Ttl(String),
+    Keys(String),
+    Scan { cursor: Cursor, pattern: Option<String>, count: usize },
+    Range { cursor: Cursor, start: Bound<String>, end: Bound<String>, count: usize },
+    Quit,
+}
+
+impl Command {
+    // A command is an array of bulk strings. The error is the message of the -ERR to answer with
+    pub fn parse(frame: Frame) -> Result<Command, String> {
+        let Frame::Array(frames) = frame else { return Err(String::from("a command is an array of bulk strings")) };
+        let mut args = Vec::new();
+        for frame in frames {
+            match frame {
+                Frame::Bulk(bytes) => args.push(bytes),
+                _ => return Err(String::from("a command is an array of bulk strings")),
+            }
+        }
+        let Some(name) = args.first() else { return Err(String::from("an empty command")) };
+        let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+        let mut args = args.into_iter().skip(1);
+        let command = match (name.as_str(), args.len()) {
+            ("PING", 0) => Command::Ping(None),
+            ("PING", 1) => Command::Ping(args.next()),
+            ("GET", 1) => Command::Get(key(args.next())?),
+            ("SET", 2 | 4) => {
+                let (key, value) = (key(args.next())?, args.next().unwrap());
+                let ttl = match (args.next(), args.next()) {
+                    (Some(unit), Some(n)) => Some(ttl(&unit, &n)?),
+                    _ => None,
+                };
+                Command::Set { key, value, ttl }
+            }
+            ("DEL", 1..) => Command::Del(args.map(|arg| key(Some(arg))).collect::<Result<_, _>>()?),
+            ("EXPIRE", 2) => Command::Expire(key(args.next())?, integer(&args.next().unwrap())?),
+            ("TTL", 1) => Command::Ttl(key(args.next())?),
+            ("KEYS", 1) => Command::Keys(key(args.next())?),
+            ("SCAN", 1..) => {
+                let cursor = cursor(&args.next().unwrap())?;
+                let mut options = Options::parse(args, &["MATCH", "COUNT"])?;
+                Command::Scan { cursor, pattern: options.take("MATCH").map(Some).map(key).transpose()?, count: options.count()? }
+            }
+            ("RANGE", 3..) => {
+                let cursor = cursor(&args.next().unwrap())?;
+                let (start, end) = (bound(args.next().unwrap(), true)?, bound(args.next().unwrap(), false)?);
+                let count = Options::parse(args, &["COUNT"])?.count()?;
+                Command::Range { cursor, start, end, count }
+            }
+            ("QUIT", 0) => Command::Quit,
+            ("PING" | "GET" | "SET" | "DEL" | "EXPIRE" | "TTL" | "KEYS" | "SCAN" | "RANGE" | "QUIT", _) => {
+                return Err(format!("wrong number of arguments for '{}'", name.to_ascii_lowercase()))
+            }
+            _ => return Err(format!("unknown command '{name}', the commands are: {HELP}")),
+        };
+        Ok(command)
+    }
+
+    // Runs the command on the store, and returns the answer
+    pub fn run(self, store: &Store) -> Frame {
+        match self {
+            Command::Ping(None) => Frame::Simple(String::from("PONG")),
+            Command::Ping(Some(message)) => Frame::Bulk(message),
+            Command::Get(key) => store.get(&key).map_or(Frame::Null, Frame::Bulk),
+            Command::Set { key, value, ttl: None } => {
+                store.set(&key, value);
+                Frame::ok()
+            }
+            Command::Set { key, value, ttl: Some(ttl) } => {
+                store.set_with_ttl(&key, value, ttl);
+                Frame::ok()
+            }
+            Command::Del(keys) => Frame::Integer(keys.iter().filter(|key| store.delete(key).is_some()).count() as i64),
+            Command::Expire(key, seconds) if seconds <= 0 => Frame::Integer(store.delete(&key).is_some() as i64),
+            Command::Expire(key, seconds) => {
+                Frame::Integer(store.expire(&key, Duration::from_secs(seconds as u64)) as i64)
+            }
+            Command::Ttl(key) => match store.ttl(&key) {
+                // Rounded up like Redis does, so a key that is still there never has 0 seconds left
+                Some(ttl) => Frame::Integer(ttl.as_millis().div_ceil(1000) as i64),
+                None if store.contains(&key) => Frame::Integer(-1),
+                None => Frame::Integer(-2),
+            },
+            Command::Keys(pattern) => {
+                let keys = store
+                    .scan_prefix(literal_prefix(&pattern))
+                    .into_iter()
+                    .filter(|(key, _)| glob_match(pattern.as_bytes(), key.as_bytes()))
+                    .map(|(key, _)| Frame::Bulk(key.into_bytes()))
+                    .collect();
+                Frame::Array(keys)
+            }
+            Command::Scan { cursor, pattern, count } => {
+                let pattern = pattern.unwrap_or_else(|| String::from("*"));
+                let page = store.scan_prefix_page(literal_prefix(&pattern), &cursor, count);
+                let keys = page
+                    .entries
+                    .iter()
+                    .filter(|(key, _)| glob_match(pattern.as_bytes(), key.as_bytes()))
+                    .map(|(key, _)| Frame::Bulk(key.clone().into_bytes()))
+                    .collect();
+                Frame::Array(vec![Frame::Bulk(page.next_token().into_bytes()), Frame::Array(keys)])
+            }
+            Command::Range { cursor, start, end, count } => {
+                let range = (start.as_ref().map(String::as_str), end.as_ref().map(String::as_str));
+                let page = store.scan_page(range, &cursor, count);
+                let token = Frame::Bulk(page.next_token().into_bytes());
+                let entries =
+                    page.entries.into_iter().flat_map(|(key, value)| [Frame::Bulk(key.into_bytes()), Frame::Bulk(value)]);
+                Frame::Array(vec![token, Frame::Array(entries.collect())])
+            }
+            Command::Quit => Frame::ok(),
+        }
+    }
+}
+
+// The part of a glob pattern before the first wildcard is a prefix every match has, and scanning only the keys with that
+// prefix is a lot less work than scanning all of them when there is one
+fn literal_prefix(pattern: &str) -> &str {
+    &pattern[..pattern.find(['*', '?', '[', '\\']).unwrap_or(pattern.len())]
+}
+
+// The NAME value pairs after the arguments of SCAN and RANGE, in any order
+struct Options {
+    values: Vec<(String, Vec<u8>)>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = Vec<u8>>, names: &[&str]) -> Result<Options, String> {
+        let mut values = Vec::new();
+        while let Some(name) = args.next() {
+            let name = String::from_utf8_lossy(&name).to_ascii_uppercase();
+            match (names.contains(&name.as_str()), args.next()) {
+                (true, Some(value)) => values.push((name, value)),
+                _ => return Err(String::from("syntax error")),
+            }
+        }
+        Ok(Options { values })
+    }
+
+    // The last one wins, when an option is given twice
+    fn take(&mut self, name: &str) -> Option<Vec<u8>> {
+        let i = self.values.iter().rposition(|(option, _)| option == name)?;
+        Some(self.values.remove(i).1)
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        match self.take("COUNT") {
+            None => Ok(10),
+            Some(count) => match integer(&count)? {
+                n if n < 1 => Err(String::from("syntax error")),
+                n => Ok(n as usize),
+            },
+        }
+    }
+}
+
+fn cursor(arg: &[u8]) -> Result<Cursor, String> {
+    let token = std::str::from_utf8(arg).map_err(|_| String::from("invalid cursor"))?;
+    token.parse().map_err(|e: CursorError| e.to_string())
+}
+
+// [key, (key, - or +. min is the one where - means the start, max the one where + means the end
+fn bound(arg: Vec<u8>, min: bool) -> Result<Bound<String>, String> {
+    let error = || String::from("min or max not valid string range item");
+    match (arg.first(), min) {
+        (Some(b'-'), true) | (Some(b'+'), false) if arg.len() == 1 => Ok(Bound::Unbounded),
+        (Some(b'['), _) => Ok(Bound::Included(key(Some(arg[1..].to_vec()))?)),
+        (Some(b'('), _) => Ok(Bound::Excluded(key(Some(arg[1..].to_vec()))?)),
+        _ => Err(error()),
+    }
+}
+
+fn key(arg: Option<Vec<u8>>) -> Result<String, String> {
+    String::from_utf8(arg.unwrap_or_default()).map_err(|_| String::from("keys and patterns must be UTF-8"))
+}
+
+fn integer(arg: &[u8]) -> Result<i64, String> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| String::from("value is not an integer or out of range"))
+}
+
+fn ttl(unit: &[u8], n: &[u8]) -> Result<Duration, String> {
+    let n = integer(n)?;
+    if n <= 0 {
+        return Err(String::from("invalid expire time in 'set' command"));
+    }
+    match unit.to_ascii_uppercase().as_slice() {
+        b"EX" => Ok(Duration::from_secs(n as u64)),
+        b"PX" => Ok(Duration::from_millis(n as u64)),
+        _ => Err(String::from("syntax error")),
+    }
+}
+
+// The glob patterns of KEYS: * is any number of characters, ? is one, [abc] is one of a b or c, [a-z] one in the range,
+// [^a] any but a, and \ makes the next character mean itself. It works on bytes, like Redis.
+// Backtracking only to the last *: a later * can match everything an earlier one could, so nothing else needs a retry,
+// and the match takes O(pattern × key) at worst instead of exponential time.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to start over when what follows the last * doesn't match: the position after the *, and the next byte for it to eat
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if let Some((matched, len)) = match_one(&pattern[p..], text[t]) {
+            if matched {
+                p += len;
+                t += 1;
+                continue;
+            }
+        } else if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, t));
+            continue;
+        }
+        match star {
+            Some((after, eaten)) => {
+                p = after;
+                t = eaten + 1;
+                star = Some((after, eaten + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+// Whether the piece at the start of the pattern matches the byte, and how long the piece is. None at the end of the
+// pattern, and for a *, which match_one can't decide alone
+fn match_one(pattern: &[u8], byte: u8) -> Option<(bool, usize)> {
+    match *pattern.first()? {
+        b'*' => None,
+        b'?' => Some((true, 1)),
+        b'\\' if pattern.len() > 1 => Some((pattern[1] == byte, 2)),
+        b'[' => {
+            let negated = pattern.get(1) == Some(&b'^');
+            let mut i = 1 + negated as usize;
+            let mut matched = false;
+            // A class that isn't closed takes the rest of the pattern
+            while i < pattern.len() && pattern[i] != b']' {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    matched |= pattern[i + 1] == byte;
+                    i += 2;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+                    let (low, high) = (pattern[i].min(pattern[i + 2]), pattern[i].max(pattern[i + 2]));
+                    matched |= (low..=high).contains(&byte);
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == byte;
+                    i += 1;
+                }
+            }
+            Some((matched != negated, (i + 1).min(pattern.len())))
+        }
+        literal => Some((literal == byte, 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(store: &Store, line: &str) -> Frame {
+        let args = crate::resp::split_args(line.as_bytes()).unwrap();
+        match Command::parse(Frame::command(&args)) {
+            Ok(command) => command.run(store),
+            Err(message) => Frame::error(&message),
+        }
+    }
+
+    fn bulk(text: &str) -> Frame {
+        Frame::Bulk(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn commands_answer_like_redis() {
+        let store = Store::new();
+        assert_eq!(Frame::Simple("PONG".to_string()), run(&store, "ping"));
+        assert_eq!(bulk("hi"), run(&store, "PING hi"));
+        assert_eq!(Frame::ok(), run(&store, "SET greeting \"hello world\""));
+        assert_eq!(bulk("hello world"), run(&store, "get greeting"));
+        assert_eq!(Frame::Null, run(&store, "GET nothing"));
+        assert_eq!(Frame::Integer(-1), run(&store, "TTL greeting"));
+        assert_eq!(Frame::Integer(-2), run(&store, "TTL nothing"));
+
+        assert_eq!(Frame::ok(), run(&store, "SET temp x EX 100"));
+        assert_eq!(Frame::Integer(100), run(&store, "TTL temp"));
+        assert_eq!(Frame::Integer(1), run(&store, "EXPIRE greeting 5"));
+        assert_eq!(Frame::Integer(5), run(&store, "TTL greeting"));
+        assert_eq!(Frame::Integer(0), run(&store, "EXPIRE nothing 5"));
+        assert_eq!(Frame::Integer(1), run(&store, "EXPIRE temp 0"));
+        assert_eq!(Frame::Null, run(&store, "GET temp"));
+
+        run(&store, "SET a 1");
+        assert_eq!(Frame::Integer(2), run(&store, "DEL a greeting nothing"));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn mistakes_are_errors() {
+        let store = Store::new();
+        assert_eq!(Frame::error("wrong number of arguments for 'get'"), run(&store, "GET"));
+        assert_eq!(Frame::error("wrong number of arguments for 'set'"), run(&store, "SET k v EX"));
+        assert_eq!(Frame::error("syntax error"), run(&store, "SET k v XX 10"));
+        assert_eq!(Frame::error("invalid expire time in 'set' command"), run(&store, "SET k v PX 0"));
+        assert_eq!(Frame::error("value is not an integer or out of range"), run(&store, "EXPIRE k soon"));
+        assert_eq!(Frame::error("keys and patterns must be UTF-8"), run(&store, "GET \"\\xff\""));
+        let Frame::Error(message) = run(&store, "FLUSHALL") else { panic!() };
+        assert!(message.starts_with("ERR unknown command 'FLUSHALL'"));
+        assert_eq!(Err(String::from("a command is an array of bulk strings")), Command::parse(Frame::Integer(1)));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn keys_match_glob_patterns() {
+        let store = Store::new();
+        for key in ["user:1", "user:2", "user:10", "users", "admin:1", "h*llo", "hello"] {
+            store.set(key, Vec::new());
+        }
+        let keys = |pattern: &str| match run(&store, &format!("KEYS '{pattern}'")) {
+            Frame::Array(keys) => keys.into_iter().map(|key| key.pretty().replace('"', "")).collect::<Vec<_>>().join(" "),
+            other => panic!("{other:?}"),
+        };
+        assert_eq!("user:1 user:10 user:2", keys("user:*"));
+        assert_eq!("user:1 user:2", keys("user:?"));
+        assert_eq!("admin:1 user:1", keys("*:1"));
+        assert_eq!("user:1 user:2", keys("user:[12]"));
+        assert_eq!("user:2", keys("user:[^1]"));
+        assert_eq!("h*llo", keys("h\\*llo"));
+        assert_eq!("h*llo hello", keys("h[a-z*]llo"));
+        assert_eq!(7, keys("*").split(' ').count());
+        assert_eq!("", keys("nobody*"));
+    }
+
+    #[test]
+    fn scan_and_range_page_through_the_keys() {
+        let store = Store::new();
+        for i in 0..25 {
+            store.set(&format!("user:{i:02}"), i.to_string().into_bytes());
+        }
+        store.set("post:1", Vec::new());
+
+        // Page after page until the cursor is 0 again
+        let (mut token, mut keys) = (String::from("0"), Vec::new());
+        loop {
+            let Frame::Array(reply) = run(&store, &format!("SCAN {token} MATCH user:* COUNT 10")) else { panic!() };
+            let [Frame::Bulk(next), Frame::Array(page)] = &reply[..] else { panic!("{reply:?}") };
+            assert!(page.len() <= 10);
+            keys.extend(page.iter().map(Frame::pretty));
+            token = String::from_utf8(next.clone()).unwrap();
+            if token == "0" {
+                break;
+            }
+        }
+        assert_eq!(25, keys.len());
+        assert_eq!("\"user:00\"", keys[0]);
+
+        // MATCH filters what's in the page, and the default COUNT is 10
+        let Frame::Array(reply) = run(&store, "scan 0 match *5") else { panic!() };
+        assert_eq!(Frame::Array(vec![bulk("user:05")]), reply[1]);
+
+        let reply = run(&store, "RANGE 0 (user:03 [user:05 COUNT 5");
+        assert_eq!(
+            Frame::Array(vec![bulk("0"), Frame::Array(vec![bulk("user:04"), bulk("4"), bulk("user:05"), bulk("5")])]),
+            reply
+        );
+        let Frame::Array(reply) = run(&store, "RANGE 0 - + COUNT 1") else { panic!() };
+        assert_eq!((bulk("k706f73743a31"), 2), (reply[0].clone(), reply[1].pretty().lines().count()));
+        let reply = run(&store, "RANGE k706f73743a31 - [user:00");
+        assert_eq!(Frame::Array(vec![bulk("0"), Frame::Array(vec![bulk("user:00"), bulk("0")])]), reply);
+        assert_eq!(Frame::Array(vec![bulk("0"), Frame::Array(Vec::new())]), run(&store, "RANGE 0 [b (a"));
+
+        assert_eq!(Frame::error("invalid cursor \"12\""), run(&store, "SCAN 12"));
+        assert_eq!(Frame::error("syntax error"), run(&store, "SCAN 0 COUNT 0"));
+        assert_eq!(Frame::error("syntax error"), run(&store, "SCAN 0 LIMIT 5"));
+        assert_eq!(Frame::error("min or max not valid string range item"), run(&store, "RANGE 0 a +"));
+    }
+
+    #[test]
+    fn globs_backtrack_to_the_last_star() {
+        assert!(glob_match(b"*a*b*c", b"xxaxxbxxbxxc"));
+        assert!(glob_match(b"a**", b"a"));
+        assert!(!glob_match(b"*a*b", b"xxaxxbxxc"));
+        assert!(!glob_match(b"?", b""));
+        assert!(glob_match(b"", b""));
+        // Exponential with naive backtracking, and instant with one star to go back to
+        let text = vec![b'a'; 100];
+        assert!(!glob_match(&[b"*a".repeat(20), b"b".to_vec()].concat(), &text));
+    }
+}
@@ -0,0 +1,158 @@
+// Cursors
+
+// scan() copies every key in the range, which is fine for a hundred keys and not for ten million. Paging through them is
+// asking for the first hundred, and then the hundred after those, and so on, with a cursor saying where to go on from.
+// The keys are already in order in the Store's BTreeMap, so no other index is needed for it: a cursor is just the last key
+// of the page before, and the next page starts right after it, with a range that skips straight there.
+
+// An offset (skip the first 200 keys) would do the same with less to remember, but counting to it gets slower with every
+// page, and a key added or removed in front of the offset shifts everything behind it, so a page repeats or skips a key.
+// With the last key, a key that is there from the first page to the last is returned exactly once, whatever else changes.
+// Keys added or removed in between may or may not show up, depending on which side of the cursor they are.
+
+// On the wire a cursor is a token, what SCAN answers and expects back (command.rs). Like in Redis, "0" starts a scan and
+// a scan is done when "0" comes back. Other tokens are a k and the key in hex, so that any key makes a token that is
+// one word without spaces, and an empty key isn't mistaken for the start.
+
+use std::{fmt, ops::Bound, str::FromStr};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cursor {
+    after: Option<String>,
+}
+
+impl Cursor {
+    // The start of a scan
+    pub fn start() -> Cursor {
+        Cursor { after: None }
+    }
+
+    // Goes on with the keys after this one
+    pub fn after(key: &str) -> Cursor {
+        Cursor { after: Some(key.to_string()) }
+    }
+
+    pub fn is_start(&self) -> bool {
+        self.after.is_none()
+    }
+
+    pub fn last_key(&self) -> Option<&str> {
+        self.after.as_deref()
+    }
+
+    // Where the page starts: the start of the range, or right after the last key, whichever is later
+    pub(crate) fn resume<'a>(&'a self, start: Bound<&'a str>) -> Bound<&'a str> {
+        let Some(after) = self.after.as_deref() else { return start };
+        match start {
+            Bound::Included(key) if key > after => start,
+            Bound::Excluded(key) if key >= after => start,
+            _ => Bound::Excluded(after),
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.after {
+            None => write!(f, "0"),
+            Some(key) => {
+                write!(f, "k")?;
+                key.bytes().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorError(pub String);
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid cursor {:?}", self.0)
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+impl FromStr for Cursor {
+    type Err = CursorError;
+
+    fn from_str(token: &str) -> Result<Cursor, CursorError> {
+        let error = || CursorError(token.to_string());
+        if token == "0" {
+            return Ok(Cursor::start());
+        }
+        let hex = token.strip_prefix('k').filter(|hex| hex.len() % 2 == 0).ok_or_else(error)?;
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(error)?;
+        let key = String::from_utf8(bytes).map_err(|_| error())?;
+        Ok(Cursor { after: Some(key) })
+    }
+}
+
+// One page of a scan, and the cursor to get the next one with. None when this was the last page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub entries: Vec<(String, Vec<u8>)>,
+    pub next: Option<Cursor>,
+}
+
+impl Page {
+    pub fn is_last(&self) -> bool {
+        self.next.is_none()
+    }
+
+    // The token of the next page, "0" after the last one
+    pub fn next_token(&self) -> String {
+        self.next.as_ref().map_or_else(|| Cursor::start().to_string(), Cursor::to_string)
+    }
+}
+
+// Whether no key can be in the range between the bounds, like ("b", "a"), or ("a", "a") with one of them Excluded.
+// BTreeMap::range panics on such a range, so it isn't looked up at all
+pub fn is_empty_range(start: Bound<&str>, end: Bound<&str>) -> bool {
+    match (start, end) {
+        (Bound::Included(a), Bound::Included(b)) => a > b,
+        (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => a >= b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_round_trip() {
+        for cursor in [Cursor::start(), Cursor::after(""), Cursor::after("user:42"), Cursor::after("clé 🦀")] {
+            let token = cursor.to_string();
+            assert!(!token.contains(' '));
+            assert_eq!(Ok(cursor), token.parse());
+        }
+        assert_eq!("0", Cursor::start().to_string());
+        assert_eq!("k", Cursor::after("").to_string());
+        assert_eq!("k613a31", Cursor::after("a:1").to_string());
+        for token in ["", "1", "k6", "kzz", "kff", "613a31"] {
+            assert_eq!(Err(CursorError(token.to_string())), token.parse::<Cursor>());
+        }
+    }
+
+    #[test]
+    fn resuming_takes_the_later_start() {
+        let cursor = Cursor::after("m");
+        assert_eq!(Bound::Excluded("m"), cursor.resume(Bound::Unbounded));
+        assert_eq!(Bound::Excluded("m"), cursor.resume(Bound::Included("a")));
+        assert_eq!(Bound::Excluded("m"), cursor.resume(Bound::Included("m")));
+        assert_eq!(Bound::Included("n"), cursor.resume(Bound::Included("n")));
+        assert_eq!(Bound::Excluded("m"), cursor.resume(Bound::Excluded("m")));
+        assert_eq!(Bound::Included("a"), Cursor::start().resume(Bound::Included("a")));
+
+        assert!(is_empty_range(Bound::Excluded("m"), Bound::Included("m")));
+        assert!(is_empty_range(Bound::Included("b"), Bound::Excluded("a")));
+        assert!(!is_empty_range(Bound::Included("m"), Bound::Included("m")));
+        assert!(!is_empty_range(Bound::Excluded("z"), Bound::Unbounded));
+    }
+}
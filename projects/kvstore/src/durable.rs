@@ -0,0 +1,152 @@
+// A Store That Survives Restarts
+
+// DurableStore is a Store with a StorageEngine behind it (engine.rs), in a directory of the engine's files.
+// open() loads everything the engine has into the Store, and from then on reads are answered by the Store, and queries run
+// on it like on any other. set() and delete() hand the change to the engine first, and only change the Store once the engine
+// said it is safe. Holding a lock while both happen keeps the order of the engine's writes the order of the Store's, when
+// several threads write at once.
+
+// Which engine is up to open_with(), and open() takes the log, which was the only one there was. The engine's files are
+// its own business: a directory written by one engine can't be opened with another.
+
+use std::{
+    io,
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use common::progress::Tracker;
+
+use crate::{
+    engine::{EngineKind, StorageEngine},
+    store::Store,
+    wal::Op,
+};
+
+pub struct DurableStore {
+    store: Store,
+    dir: PathBuf,
+    engine: Box<dyn StorageEngine>,
+    writing: Mutex<()>,
+}
+
+impl DurableStore {
+    // Opens the store in dir with the log engine, creating the directory if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<DurableStore> {
+        DurableStore::open_with(dir, EngineKind::Log)
+    }
+
+    pub fn open_with<P: AsRef<Path>>(dir: P, kind: EngineKind) -> io::Result<DurableStore> {
+        let dir = dir.as_ref().to_path_buf();
+        let engine = kind.open(&dir)?;
+        let store = Store::new();
+        for (key, value) in engine.scan(Bound::Unbounded, Bound::Unbounded)? {
+            store.set(&key, value);
+        }
+        Ok(DurableStore { store, dir, engine, writing: Mutex::new(()) })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.store.get(key)
+    }
+
+    pub fn set(&self, key: &str, value: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let _writing = self.writing.lock().unwrap();
+        self.engine.set(key, &value)?;
+        Ok(apply(&self.store, &Op::Set(key.to_string(), value)))
+    }
+
+    pub fn delete(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let _writing = self.writing.lock().unwrap();
+        self.engine.delete(key)?;
+        Ok(apply(&self.store, &Op::Delete(key.to_string())))
+    }
+
+    // The map in memory, for reading: queries run on it like on any Store. Writing to it directly would skip the engine,
+    // and the change would be lost on the next start.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn engine(&self) -> &dyn StorageEngine {
+        self.engine.as_ref()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Lets the engine give back the space old changes take on the disk. Writers wait while it runs.
+    // Every entry the engine writes counts one on the tracker, a Tracker made with store().len() as its total knows how many
+    // there are going to be. It's finished once the compaction is over, whether that went well or not
+    pub fn compact(&self, progress: &Tracker) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        let result = self.engine.compact(progress);
+        progress.finish();
+        result
+    }
+}
+
+// Makes the change in the map, and returns the value it replaced
+pub fn apply(store: &Store, op: &Op) -> Option<Vec<u8>> {
+    match op {
+        Op::Set(key, value) => store.set(key, value.clone()),
+        Op::Delete(key) => store.delete(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Record;
+    use common::progress::Silent;
+    use std::sync::Arc;
+    use test_support::TempDir;
+
+    #[test]
+    fn reopening_finds_everything() {
+        for kind in EngineKind::ALL.into_iter().filter(|kind| kind.is_persistent()) {
+            let dir = TempDir::new();
+            {
+                let store = DurableStore::open_with(dir.path(), kind).unwrap();
+                store.set("a", b"1".to_vec()).unwrap();
+                store.set("b", Record::new().with("name", "ferris").encode()).unwrap();
+                assert_eq!(Some(b"1".to_vec()), store.delete("a").unwrap());
+                store.set("c", b"3".to_vec()).unwrap();
+            }
+            let store = DurableStore::open_with(dir.path(), kind).unwrap();
+            assert_eq!(None, store.get("a"), "{kind}");
+            assert_eq!(Some(Ok(Record::new().with("name", "ferris"))), store.store().get_record("b"), "{kind}");
+            assert_eq!(2, store.store().len(), "{kind}");
+        }
+    }
+
+    #[test]
+    fn compaction_keeps_everything() {
+        for kind in EngineKind::ALL {
+            let dir = TempDir::new();
+            let store = DurableStore::open_with(dir.path(), kind).unwrap();
+            for i in 0..100 {
+                store.set("counter", i.to_string().into_bytes()).unwrap();
+            }
+            let progress = Tracker::new(Arc::new(Silent), "compacting", Some(store.store().len() as u64));
+            store.compact(&progress).unwrap();
+            // The one key there is, written anew by the engines that have files to write
+            assert_eq!(u64::from(kind.is_persistent()), progress.done(), "{kind}");
+            store.set("after", b"yes".to_vec()).unwrap();
+            assert_eq!(kind, store.engine().kind());
+            assert_eq!(Some(b"99".to_vec()), store.engine().get("counter").unwrap(), "{kind}");
+            assert_eq!(Some(b"yes".to_vec()), store.get("after"), "{kind}");
+        }
+    }
+
+    #[test]
+    fn a_write_the_engine_refuses_changes_nothing() {
+        let dir = TempDir::new();
+        let store = DurableStore::open_with(dir.path(), EngineKind::BTree).unwrap();
+        store.set("small", b"1".to_vec()).unwrap();
+        assert!(store.set("small", vec![0; 10_000]).is_err());
+        assert_eq!(Some(b"1".to_vec()), store.get("small"));
+    }
+}
@@ -0,0 +1,147 @@
+// Storage Engines
+
+// A DurableStore answers from the Store in memory, and keeps what it holds somewhere it survives a restart. How, is up to
+// the StorageEngine below it, which can be one of three:
+    // 1. MemoryEngine keeps nothing anywhere: a BTreeMap, gone with the process. For tests, and for data that is only a cache.
+    // 2. LogEngine (engine/log.rs) is the write-ahead log and the snapshots of wal.rs. Every write is an append, the fastest
+    //    there is on a disk, and opening replays the log. The log grows with every write, until compact() starts it over.
+    // 3. BTreeEngine (engine/btree.rs) keeps the keys in a B-tree of pages in one file, the way most databases do. A write
+    //    changes a page or two in place, so the file only grows with the keys, and a get() reads a few pages instead of
+    //    needing the whole map in memory.
+// Which one a DurableStore uses is its EngineKind, and a "log" or "btree" on the command line parses into one.
+
+// The trait takes &self like the Store does, so an engine can be shared between threads, and each engine has a Mutex of its
+// own inside. Everything returns io::Result, even for the engine in memory, because the other two read and write files.
+
+use std::{collections::BTreeMap, fmt, io, ops::Bound, path::Path, str::FromStr, sync::Mutex};
+
+use common::progress::Tracker;
+
+use crate::cursor::is_empty_range;
+
+pub mod btree;
+pub mod log;
+
+pub use btree::BTreeEngine;
+pub use log::LogEngine;
+
+pub trait StorageEngine: Send + Sync {
+    fn kind(&self) -> EngineKind;
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    // Both return the value that was there before. When a persistent engine returns Ok, the change is on the disk and
+    // survives a crash, and when it returns an error or crashes halfway, opening it again finds it as it was before
+    fn set(&self, key: &str, value: &[u8]) -> io::Result<Option<Vec<u8>>>;
+    fn delete(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    // The entries with a key between the bounds, in key order, and none when the bounds are the wrong way round
+    fn scan(&self, start: Bound<&str>, end: Bound<&str>) -> io::Result<Vec<(String, Vec<u8>)>>;
+
+    // Gives back the space that changes left behind in the files, by writing every entry anew. Each one counts one on the
+    // tracker once it's written, so whoever started a long compaction sees how far it got. Nothing to do for most engines
+    fn compact(&self, _progress: &Tracker) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EngineKind {
+    Memory,
+    #[default]
+    Log,
+    BTree,
+}
+
+impl EngineKind {
+    pub const ALL: [EngineKind; 3] = [EngineKind::Memory, EngineKind::Log, EngineKind::BTree];
+
+    // Whether what was written is still there when the engine is opened again
+    pub fn is_persistent(self) -> bool {
+        self != EngineKind::Memory
+    }
+
+    // Opens the engine's files in dir, creating the directory and the files if needed
+    pub fn open(self, dir: &Path) -> io::Result<Box<dyn StorageEngine>> {
+        std::fs::create_dir_all(dir)?;
+        Ok(match self {
+            EngineKind::Memory => Box::new(MemoryEngine::new()),
+            EngineKind::Log => Box::new(LogEngine::open(dir)?),
+            EngineKind::BTree => Box::new(BTreeEngine::open(dir)?),
+        })
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EngineKind::Memory => "memory",
+            EngineKind::Log => "log",
+            EngineKind::BTree => "btree",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<EngineKind, String> {
+        EngineKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == name)
+            .ok_or_else(|| format!("there is no {name} engine, the engines are memory, log and btree"))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryEngine {
+    map: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryEngine {
+    pub fn new() -> MemoryEngine {
+        MemoryEngine::default()
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn kind(&self) -> EngineKind {
+        EngineKind::Memory
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.map.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.map.lock().unwrap().insert(key.to_string(), value.to_vec()))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.map.lock().unwrap().remove(key))
+    }
+
+    fn scan(&self, start: Bound<&str>, end: Bound<&str>) -> io::Result<Vec<(String, Vec<u8>)>> {
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let map = self.map.lock().unwrap();
+        Ok(map.range::<str, _>((start, end)).map(|(key, value)| (key.clone(), value.clone())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_parse_from_their_names() {
+        for kind in EngineKind::ALL {
+            assert_eq!(Ok(kind), kind.to_string().parse());
+        }
+        assert_eq!(Ok(EngineKind::BTree), "btree".parse());
+        assert!("lsm".parse::<EngineKind>().unwrap_err().contains("no lsm engine"));
+        assert_eq!(EngineKind::Log, EngineKind::default());
+    }
+}
@@ -0,0 +1,792 @@
+// The B-Tree Engine
+
+// A B-tree on disk, in one file, `btree`, of pages of 4 KiB. A page is the unit a disk reads and writes anyway, so a node of
+// the tree is one page, and holds as many keys as fit in it: hundreds of short ones. That makes the tree very flat, a million
+// keys are three levels deep, and finding one is reading three pages.
+    // 1. Page 0 is the header: the magic number, which page is the root, and how many pages the file has.
+    // 2. A leaf holds entries, keys and their values, in key order.
+    // 3. A branch holds keys and the pages of its children, one more child than keys. children[i] has the keys from keys[i - 1]
+    //    up to just before keys[i]. It's a B+tree: every value is in a leaf, and the keys in branches are only signposts.
+// Every node page starts with the CRC-32 of the rest of it, so a page that was damaged, or torn by a crash halfway through
+// its write, is an InvalidData error instead of garbage. All the numbers are little-endian.
+
+// set() walks down to the leaf the key belongs in and puts it there. A leaf that gets too big for its page splits in two,
+// a new page for the second half, and the first key of that half goes up into the parent as the signpost between them. That
+// can make the parent split too, all the way up to the root, which splitting gives the tree a new root and a level more.
+// The tree only ever grows at the root, so every leaf is always at the same depth.
+
+// A get() of a key that isn't there still reads a page at every level to find that out. So the engine keeps a Bloom filter
+// (collections/std_collections/src/probabilistic.rs) of its keys in memory, built by a scan when the file is opened: a key
+// the filter has never seen isn't in the tree, and get() answers None without reading a page. It's what LSM-trees keep
+// next to each of their sorted files, where a miss would otherwise read every one of them. A deleted key stays in the
+// filter, a bit can't be cleared, and the filter is built again once it took twice the keys it was sized for.
+
+// It is a simple B-tree, and the simplifications are the usual places where real ones are a lot more work:
+    // 1. An entry has to fit in a quarter of a page, about 1 KiB, so that a split always leaves two halves that fit.
+    //    Real engines move big values to overflow pages of their own. Here a bigger one is an InvalidInput error.
+    // 2. delete() doesn't merge nodes that get small, and pages that empty out stay in the file. compact() rebuilds the
+    //    tree in a new file, with just the keys that are there.
+    // 3. Pages are changed in place, and a crash in the middle of a split would leave the tree half changed. So a write is a
+    //    small transaction, with a rollback journal like SQLite's, `btree.journal`:
+    //    a. The pages a write changes are kept in memory until it's done, and then commit() writes them all.
+    //    b. Before that, it copies the pages as they are in the file into the journal, with a checksum over all of it,
+    //       and syncs it.
+    //    c. Then it writes the new pages over the old ones and syncs the file.
+    //    d. Emptying the journal is the commit, set() returns after it.
+    //    A crash before d. leaves a journal that open() puts back, and the tree is what it was before the write. A journal
+    //    whose checksum doesn't match was cut short in b., when the file hadn't been touched yet, and is thrown away.
+    //    Real databases write every change to a log instead of the old pages, but the log has to be replayed into pages
+    //    at some point, which is a lot more work.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use common::progress::Tracker;
+use std_collections::{hashing::crc32, probabilistic::BloomFilter};
+
+use super::{EngineKind, StorageEngine};
+use crate::cursor::is_empty_range;
+
+pub const PAGE: usize = 4096;
+const MAGIC: &[u8; 4] = b"KVB1";
+const JOURNAL_MAGIC: &[u8; 4] = b"KVJ1";
+// The CRC-32, the kind of node, and how many entries or keys it has
+const NODE_HEADER: usize = 4 + 1 + 2;
+// The share of the keys that aren't there a get() still reads pages for
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+// The most bytes one entry of a leaf takes, its key and value with their lengths
+pub const MAX_ENTRY: usize = (PAGE - NODE_HEADER) / 4;
+const LEAF: u8 = 1;
+const BRANCH: u8 = 2;
+
+type Entry = (String, Vec<u8>);
+// The first key of the new half of a node that split, and its page
+type Split = Option<(String, u32)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Leaf(Vec<Entry>),
+    Branch { keys: Vec<String>, children: Vec<u32> },
+}
+
+fn entry_len(key: &str, value: &[u8]) -> usize {
+    2 + key.len() + 4 + value.len()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Node {
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(entries) => NODE_HEADER + entries.iter().map(|(key, value)| entry_len(key, value)).sum::<usize>(),
+            Node::Branch { keys, .. } => NODE_HEADER + 4 + keys.iter().map(|key| 2 + key.len() + 4).sum::<usize>(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut page = vec![0; 4];
+        match self {
+            Node::Leaf(entries) => {
+                page.push(LEAF);
+                page.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+                for (key, value) in entries {
+                    page.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                    page.extend_from_slice(key.as_bytes());
+                    page.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    page.extend_from_slice(value);
+                }
+            }
+            Node::Branch { keys, children } => {
+                page.push(BRANCH);
+                page.extend_from_slice(&(keys.len() as u16).to_le_bytes());
+                page.extend_from_slice(&children[0].to_le_bytes());
+                for (key, child) in keys.iter().zip(&children[1..]) {
+                    page.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                    page.extend_from_slice(key.as_bytes());
+                    page.extend_from_slice(&child.to_le_bytes());
+                }
+            }
+        }
+        assert!(page.len() <= PAGE, "a node of {} bytes doesn't fit in a page", page.len());
+        page.resize(PAGE, 0);
+        let checksum = crc32(&page[4..]);
+        page[..4].copy_from_slice(&checksum.to_le_bytes());
+        page
+    }
+
+    fn decode(id: u32, page: &[u8]) -> io::Result<Node> {
+        if crc32(&page[4..]) != u32::from_le_bytes(page[..4].try_into().unwrap()) {
+            return Err(invalid(format!("page {id} of the B-tree is damaged, its checksum doesn't match")));
+        }
+        let mut rest = &page[4..];
+        let mut take = |n: usize| -> io::Result<&[u8]> {
+            let (bytes, after) = rest.split_at_checked(n).ok_or_else(|| invalid(format!("page {id} is cut short")))?;
+            rest = after;
+            Ok(bytes)
+        };
+        let kind = take(1)?[0];
+        let count = u16::from_le_bytes(take(2)?.try_into().unwrap()) as usize;
+        let string = |bytes: &[u8]| {
+            String::from_utf8(bytes.to_vec()).map_err(|_| invalid(format!("a key on page {id} isn't UTF-8")))
+        };
+        match kind {
+            LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let len = u16::from_le_bytes(take(2)?.try_into().unwrap()) as usize;
+                    let key = string(take(len)?)?;
+                    let len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+                    entries.push((key, take(len)?.to_vec()));
+                }
+                Ok(Node::Leaf(entries))
+            }
+            BRANCH => {
+                let mut keys = Vec::with_capacity(count);
+                let mut children = vec![u32::from_le_bytes(take(4)?.try_into().unwrap())];
+                for _ in 0..count {
+                    let len = u16::from_le_bytes(take(2)?.try_into().unwrap()) as usize;
+                    keys.push(string(take(len)?)?);
+                    children.push(u32::from_le_bytes(take(4)?.try_into().unwrap()));
+                }
+                Ok(Node::Branch { keys, children })
+            }
+            kind => Err(invalid(format!("page {id} isn't a node, its kind is {kind}"))),
+        }
+    }
+}
+
+// Where in the branch the key goes: the child after every signpost that isn't greater than it
+fn child_index(keys: &[String], key: &str) -> usize {
+    keys.partition_point(|signpost| signpost.as_str() <= key)
+}
+
+// Where to cut a list of items of these sizes so that both halves are about as big, leaving at least `keep` of them
+// on each side
+fn middle(sizes: impl Iterator<Item = usize> + Clone, keep: usize) -> usize {
+    let (count, total) = sizes.clone().fold((0, 0), |(count, total), size| (count + 1, total + size));
+    let mut sum = 0;
+    let at = sizes.take_while(|size| {
+        sum += size;
+        sum <= total / 2
+    });
+    at.count().clamp(keep, count - keep)
+}
+
+// The file, and the header's numbers
+struct Pager {
+    file: File,
+    root: u32,
+    pages: u32,
+    // The pages changed since the last commit, by their number. Reads see them, the file doesn't yet
+    dirty: BTreeMap<u32, Vec<u8>>,
+    // How many pages the file had at the last commit, the ones a rollback has to put back. The others are cut off
+    committed: u32,
+    // For the crash tests: how many more writes reach the disk before every one fails
+    writes_left: Option<usize>,
+    // The keys, and how many it was sized for
+    filter: BloomFilter,
+    filter_capacity: usize,
+    // How many pages were read from the file
+    reads: u64,
+}
+
+impl Pager {
+    fn new(file: File, root: u32, pages: u32) -> Pager {
+        Pager {
+            file,
+            root,
+            pages,
+            dirty: BTreeMap::new(),
+            committed: pages,
+            writes_left: None,
+            filter: BloomFilter::new(1024, FALSE_POSITIVE_RATE),
+            filter_capacity: 1024,
+            reads: 0,
+        }
+    }
+
+    // A new filter, from a scan of every key, with room for as many again
+    fn build_filter(&mut self) -> io::Result<()> {
+        let mut entries = Vec::new();
+        let root = self.root;
+        self.scan(root, Bound::Unbounded, Bound::Unbounded, &mut entries)?;
+        self.filter_capacity = (entries.len() * 2).max(1024);
+        self.filter = BloomFilter::new(self.filter_capacity, FALSE_POSITIVE_RATE);
+        for (key, _) in &entries {
+            self.filter.insert(key.as_str());
+        }
+        Ok(())
+    }
+
+    // An empty tree is one empty leaf. The file is new, so there's nothing to roll back to, and no journal
+    fn create(path: &Path) -> io::Result<Pager> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let mut pager = Pager::new(file, 1, 2);
+        pager.write(1, &Node::Leaf(Vec::new()));
+        pager.write_header();
+        pager.flush()?;
+        pager.file.sync_all()?;
+        Ok(pager)
+    }
+
+    fn open(path: &Path) -> io::Result<Pager> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Pager::create(path),
+            Err(e) => return Err(e),
+        };
+        let mut header = [0; 16];
+        file.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || crc32(&header[8..]) != u32::from_le_bytes(header[4..8].try_into().unwrap()) {
+            return Err(invalid(format!("{} isn't a B-tree, or its header is damaged", path.display())));
+        }
+        let root = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let pages = u32::from_le_bytes(header[12..].try_into().unwrap());
+        if root == 0 || root >= pages || file.metadata()?.len() < pages as u64 * PAGE as u64 {
+            return Err(invalid(format!("{} is cut short", path.display())));
+        }
+        let mut pager = Pager::new(file, root, pages);
+        pager.build_filter()?;
+        Ok(pager)
+    }
+
+    fn write_header(&mut self) {
+        let mut numbers = Vec::with_capacity(8);
+        numbers.extend_from_slice(&self.root.to_le_bytes());
+        numbers.extend_from_slice(&self.pages.to_le_bytes());
+        let mut header = vec![0; PAGE];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&crc32(&numbers).to_le_bytes());
+        header[8..16].copy_from_slice(&numbers);
+        self.dirty.insert(0, header);
+    }
+
+    fn read(&mut self, id: u32) -> io::Result<Node> {
+        if id == 0 || id >= self.pages {
+            return Err(invalid(format!("a branch points to page {id}, which isn't a node")));
+        }
+        match self.dirty.get(&id) {
+            Some(page) => Node::decode(id, page),
+            None => Node::decode(id, &self.read_page(id)?),
+        }
+    }
+
+    // The page as it is in the file
+    fn read_page(&mut self, id: u32) -> io::Result<Vec<u8>> {
+        self.reads += 1;
+        let mut page = vec![0; PAGE];
+        self.file.seek(SeekFrom::Start(id as u64 * PAGE as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(page)
+    }
+
+    fn write(&mut self, id: u32, node: &Node) {
+        self.dirty.insert(id, node.encode());
+    }
+
+    // Every write to the disk asks first, so a crash test can stop them at any one
+    fn spend(&mut self) -> io::Result<()> {
+        match &mut self.writes_left {
+            Some(0) => Err(io::Error::other("the simulated crash happened")),
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn crashed(&self) -> bool {
+        self.writes_left == Some(0)
+    }
+
+    // The changed pages into the file, without a journal, and they are all the tree has now
+    fn flush(&mut self) -> io::Result<()> {
+        for (id, page) in std::mem::take(&mut self.dirty) {
+            self.spend()?;
+            self.file.seek(SeekFrom::Start(id as u64 * PAGE as u64))?;
+            self.file.write_all(&page)?;
+        }
+        self.committed = self.pages;
+        Ok(())
+    }
+
+    // Makes the changed pages the tree's, steps b. to d. at the top
+    fn commit(&mut self, journal_path: &Path) -> io::Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        let originals: Vec<u32> = self.dirty.keys().copied().filter(|&id| id < self.committed).collect();
+        let mut journal = File::create(journal_path)?;
+        let mut written = Vec::with_capacity(12 + originals.len() * (4 + PAGE) + 4);
+        written.extend_from_slice(JOURNAL_MAGIC);
+        written.extend_from_slice(&self.committed.to_le_bytes());
+        written.extend_from_slice(&(originals.len() as u32).to_le_bytes());
+        self.spend()?;
+        journal.write_all(&written)?;
+        for id in originals {
+            let mut frame = id.to_le_bytes().to_vec();
+            frame.extend_from_slice(&self.read_page(id)?);
+            self.spend()?;
+            journal.write_all(&frame)?;
+            written.extend_from_slice(&frame);
+        }
+        self.spend()?;
+        journal.write_all(&crc32(&written).to_le_bytes())?;
+        journal.sync_all()?;
+
+        self.flush()?;
+        self.file.sync_data()?;
+
+        self.spend()?;
+        journal.set_len(0)?;
+        journal.sync_all()
+    }
+
+    fn allocate(&mut self) -> u32 {
+        self.pages += 1;
+        self.pages - 1
+    }
+
+    fn get(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        if !self.filter.contains(key) {
+            return Ok(None);
+        }
+        let mut id = self.root;
+        loop {
+            match self.read(id)? {
+                Node::Branch { keys, children } => id = children[child_index(&keys, key)],
+                Node::Leaf(entries) => {
+                    let found = entries.binary_search_by(|(other, _)| other.as_str().cmp(key));
+                    return Ok(found.ok().map(|i| entries[i].1.clone()));
+                }
+            }
+        }
+    }
+
+    // Puts the entry in and returns the value it replaced. Gets the tree a new root when the old one split
+    fn set(&mut self, key: &str, value: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if entry_len(key, value) > MAX_ENTRY {
+            let message = format!("{key} and its value take {} bytes, more than the {MAX_ENTRY} that fit", entry_len(key, value));
+            return Err(io::Error::new(ErrorKind::InvalidInput, message));
+        }
+        let (old, split) = self.insert(self.root, key, value)?;
+        if let Some((signpost, right)) = split {
+            let root = self.allocate();
+            self.write(root, &Node::Branch { keys: vec![signpost], children: vec![self.root, right] });
+            self.root = root;
+        }
+        self.write_header();
+        self.filter.insert(key);
+        if self.filter.len() > self.filter_capacity {
+            self.build_filter()?;
+        }
+        Ok(old)
+    }
+
+    // The value that was replaced, and how the node split if it did
+    fn insert(&mut self, id: u32, key: &str, value: &[u8]) -> io::Result<(Option<Vec<u8>>, Split)> {
+        match self.read(id)? {
+            Node::Leaf(mut entries) => {
+                let old = match entries.binary_search_by(|(other, _)| other.as_str().cmp(key)) {
+                    Ok(i) => Some(std::mem::replace(&mut entries[i].1, value.to_vec())),
+                    Err(i) => {
+                        entries.insert(i, (key.to_string(), value.to_vec()));
+                        None
+                    }
+                };
+                let node = Node::Leaf(entries);
+                if node.len() <= PAGE {
+                    self.write(id, &node);
+                    return Ok((old, None));
+                }
+                let Node::Leaf(mut left) = node else { unreachable!() };
+                let at = middle(left.iter().map(|(key, value)| entry_len(key, value)), 1);
+                let right = left.split_off(at);
+                let signpost = right[0].0.clone();
+                let page = self.allocate();
+                // The new page first: until the parent points to it, nothing can read it anyway
+                self.write(page, &Node::Leaf(right));
+                self.write(id, &Node::Leaf(left));
+                Ok((old, Some((signpost, page))))
+            }
+            Node::Branch { mut keys, mut children } => {
+                let i = child_index(&keys, key);
+                let (old, split) = self.insert(children[i], key, value)?;
+                let Some((signpost, page)) = split else { return Ok((old, None)) };
+                keys.insert(i, signpost);
+                children.insert(i + 1, page);
+                let node = Node::Branch { keys, children };
+                if node.len() <= PAGE {
+                    self.write(id, &node);
+                    return Ok((old, None));
+                }
+                // The key in the middle goes up, and the children on either side of it go with their half
+                let Node::Branch { keys: mut left_keys, children: mut left_children } = node else { unreachable!() };
+                // At least one key on either side of the one that goes up
+                let at = middle(left_keys.iter().map(|key| 2 + key.len() + 4), 1).min(left_keys.len() - 2);
+                let mut right_keys = left_keys.split_off(at);
+                let up = right_keys.remove(0);
+                let right_children = left_children.split_off(at + 1);
+                let page = self.allocate();
+                self.write(page, &Node::Branch { keys: right_keys, children: right_children });
+                self.write(id, &Node::Branch { keys: left_keys, children: left_children });
+                Ok((old, Some((up, page))))
+            }
+        }
+    }
+
+    fn delete(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut id = self.root;
+        loop {
+            match self.read(id)? {
+                Node::Branch { keys, children } => id = children[child_index(&keys, key)],
+                Node::Leaf(mut entries) => {
+                    let Ok(i) = entries.binary_search_by(|(other, _)| other.as_str().cmp(key)) else { return Ok(None) };
+                    let (_, old) = entries.remove(i);
+                    self.write(id, &Node::Leaf(entries));
+                    return Ok(Some(old));
+                }
+            }
+        }
+    }
+
+    // Only the children whose keys can be in the range are read
+    fn scan(&mut self, id: u32, start: Bound<&str>, end: Bound<&str>, out: &mut Vec<Entry>) -> io::Result<()> {
+        match self.read(id)? {
+            Node::Leaf(entries) => {
+                let range = (start, end);
+                out.extend(entries.into_iter().filter(|(key, _)| std::ops::RangeBounds::contains(&range, key.as_str())));
+            }
+            Node::Branch { keys, children } => {
+                for (i, child) in children.into_iter().enumerate() {
+                    // The child has the keys from keys[i - 1] up to just before keys[i]
+                    let below_start = i < keys.len()
+                        && match start {
+                            Bound::Included(start) | Bound::Excluded(start) => keys[i].as_str() <= start,
+                            Bound::Unbounded => false,
+                        };
+                    let past_end = i > 0
+                        && match end {
+                            Bound::Included(end) => keys[i - 1].as_str() > end,
+                            Bound::Excluded(end) => keys[i - 1].as_str() >= end,
+                            Bound::Unbounded => false,
+                        };
+                    if past_end {
+                        break;
+                    }
+                    if !below_start {
+                        self.scan(child, start, end, out)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Puts back the pages a journal has, if a write didn't get to its commit. A journal that's cut short or damaged is from a
+// crash before the tree was touched, and is only emptied. Doing it twice is harmless, in case this crashes too
+fn roll_back(path: &Path, journal_path: &Path) -> io::Result<()> {
+    let journal = match fs::read(journal_path) {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if journal.is_empty() {
+        return Ok(());
+    }
+    let number = |at: usize| u32::from_le_bytes(journal[at..at + 4].try_into().unwrap());
+    let complete = journal.len() >= 16
+        && &journal[..4] == JOURNAL_MAGIC
+        && journal.len() == 12 + number(8) as usize * (4 + PAGE) + 4
+        && crc32(&journal[..journal.len() - 4]) == number(journal.len() - 4);
+    if complete {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        for frame in journal[12..journal.len() - 4].chunks(4 + PAGE) {
+            let id = u32::from_le_bytes(frame[..4].try_into().unwrap());
+            file.seek(SeekFrom::Start(id as u64 * PAGE as u64))?;
+            file.write_all(&frame[4..])?;
+        }
+        // The pages the write added aren't the tree's
+        file.set_len(number(4) as u64 * PAGE as u64)?;
+        file.sync_all()?;
+    }
+    let journal = OpenOptions::new().write(true).open(journal_path)?;
+    journal.set_len(0)?;
+    journal.sync_all()
+}
+
+pub struct BTreeEngine {
+    path: PathBuf,
+    journal: PathBuf,
+    pager: Mutex<Pager>,
+}
+
+impl BTreeEngine {
+    pub fn open(dir: &Path) -> io::Result<BTreeEngine> {
+        let (path, journal) = (dir.join("btree"), dir.join("btree.journal"));
+        roll_back(&path, &journal)?;
+        let pager = Pager::open(&path)?;
+        Ok(BTreeEngine { path, journal, pager: Mutex::new(pager) })
+    }
+
+    // For the crash tests: lets the next `writes` writes reach the disk, and fails every one after, without cleaning up,
+    // like a process that was killed in the middle. Opening the directory again is the restart
+    pub fn crash_after(&self, writes: usize) {
+        self.pager.lock().unwrap().writes_left = Some(writes);
+    }
+
+    // How many levels the tree has, 1 while the root is a leaf
+    pub fn depth(&self) -> io::Result<usize> {
+        let mut pager = self.pager.lock().unwrap();
+        let (mut id, mut depth) = (pager.root, 1);
+        while let Node::Branch { children, .. } = pager.read(id)? {
+            id = children[0];
+            depth += 1;
+        }
+        Ok(depth)
+    }
+
+    // The size of the file in pages, the header included
+    pub fn pages(&self) -> u32 {
+        self.pager.lock().unwrap().pages
+    }
+
+    // How many pages were read from the file since it was opened, to see what the Bloom filter saves
+    pub fn page_reads(&self) -> u64 {
+        self.pager.lock().unwrap().reads
+    }
+
+    // A write that fails halfway may have changed some pages in the file, which the journal puts back, and then the header's
+    // numbers are read back from it. After a simulated crash nothing is put back, that's for the next open()
+    fn write<T>(&self, change: impl FnOnce(&mut Pager) -> io::Result<T>) -> io::Result<T> {
+        let mut pager = self.pager.lock().unwrap();
+        let result = change(&mut pager).and_then(|value| pager.commit(&self.journal).map(|()| value));
+        if result.is_err() && !pager.crashed() {
+            if let Ok(reopened) = roll_back(&self.path, &self.journal).and_then(|()| Pager::open(&self.path)) {
+                *pager = reopened;
+            }
+        }
+        result
+    }
+}
+
+impl StorageEngine for BTreeEngine {
+    fn kind(&self) -> EngineKind {
+        EngineKind::BTree
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.pager.lock().unwrap().get(key)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.write(|pager| pager.set(key, value))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.write(|pager| pager.delete(key))
+    }
+
+    fn scan(&self, start: Bound<&str>, end: Bound<&str>) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        if !is_empty_range(start, end) {
+            let mut pager = self.pager.lock().unwrap();
+            let root = pager.root;
+            pager.scan(root, start, end, &mut entries)?;
+        }
+        Ok(entries)
+    }
+
+    // Inserts every entry into a new tree in a temporary file, and renames it over the old one, like a snapshot of the log
+    fn compact(&self, progress: &Tracker) -> io::Result<()> {
+        let mut pager = self.pager.lock().unwrap();
+        let mut entries = Vec::new();
+        let root = pager.root;
+        pager.scan(root, Bound::Unbounded, Bound::Unbounded, &mut entries)?;
+
+        let temp = self.path.with_extension("tmp");
+        let result = (|| {
+            let mut compacted = Pager::create(&temp)?;
+            for (key, value) in &entries {
+                compacted.set(key, value)?;
+                progress.inc(1);
+            }
+            // The file is new, there's nothing to roll back to if this crashes
+            compacted.flush()?;
+            compacted.file.sync_all()?;
+            // Without the keys that were deleted
+            compacted.build_filter()?;
+            fs::rename(&temp, &self.path)?;
+            Ok(compacted)
+        })();
+        match result {
+            Ok(compacted) => {
+                *pager = compacted;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::TempDir;
+
+    fn key(i: usize) -> String {
+        format!("key:{i:05}")
+    }
+
+    #[test]
+    fn nodes_round_trip_through_pages() {
+        let leaf = Node::Leaf(vec![(String::from("a"), b"1".to_vec()), (String::new(), Vec::new())]);
+        let branch = Node::Branch { keys: vec![String::from("m"), String::from("t")], children: vec![1, 2, 3] };
+        for node in [leaf, branch] {
+            let page = node.encode();
+            assert_eq!(PAGE, page.len());
+            assert_eq!(node, Node::decode(7, &page).unwrap());
+        }
+        let mut page = Node::Leaf(vec![(String::from("a"), b"1".to_vec())]).encode();
+        page[10] ^= 1;
+        assert!(Node::decode(7, &page).unwrap_err().to_string().contains("page 7"));
+    }
+
+    #[test]
+    fn splits_keep_every_key_findable() {
+        let dir = TempDir::new();
+        let engine = BTreeEngine::open(dir.path()).unwrap();
+        // Out of order, so that the splits happen in the middle of leaves and not only at the right end
+        let order: Vec<usize> = (0..3000).map(|i| i * 7919 % 3000).collect();
+        for &i in &order {
+            assert_eq!(None, engine.set(&key(i), &[i as u8; 400]).unwrap());
+        }
+        assert_eq!(3, engine.depth().unwrap());
+        for &i in &order {
+            assert_eq!(Some(vec![i as u8; 400]), engine.get(&key(i)).unwrap(), "{}", key(i));
+        }
+        assert_eq!(None, engine.get("key:").unwrap());
+
+        let scan = engine.scan(Bound::Excluded(&key(1500)), Bound::Included(&key(1600))).unwrap();
+        assert_eq!((1501..=1600).map(key).collect::<Vec<_>>(), scan.into_iter().map(|(key, _)| key).collect::<Vec<_>>());
+        assert_eq!(3000, engine.scan(Bound::Unbounded, Bound::Unbounded).unwrap().len());
+    }
+
+    #[test]
+    fn compaction_gives_back_the_pages_of_deleted_keys() {
+        let dir = TempDir::new();
+        let engine = BTreeEngine::open(dir.path()).unwrap();
+        for i in 0..2000 {
+            engine.set(&key(i), &[0; 100]).unwrap();
+        }
+        for i in 10..2000 {
+            assert!(engine.delete(&key(i)).unwrap().is_some());
+        }
+        let before = engine.pages();
+        engine.compact(&Tracker::silent()).unwrap();
+        assert!(engine.pages() < before / 10, "{} pages from {before}", engine.pages());
+        assert_eq!(1, engine.depth().unwrap());
+        drop(engine);
+
+        let engine = BTreeEngine::open(dir.path()).unwrap();
+        assert_eq!(10, engine.scan(Bound::Unbounded, Bound::Unbounded).unwrap().len());
+        assert_eq!(len(&dir), engine.pages() as u64 * PAGE as u64);
+    }
+
+    // Crashes at every page write of sets that split leaves, and the root. Each set that returned Ok is there after the
+    // restart, the one that crashed isn't, and the tree still holds together
+    #[test]
+    fn a_crash_at_any_write_leaves_the_last_commit() {
+        let before = TempDir::new();
+        let engine = BTreeEngine::open(before.path()).unwrap();
+        for i in 0..60 {
+            engine.set(&key(i), &[1; 400]).unwrap();
+        }
+        drop(engine);
+        for writes in 0.. {
+            let dir = TempDir::new();
+            fs::copy(before.child("btree"), dir.child("btree")).unwrap();
+            let engine = BTreeEngine::open(dir.path()).unwrap();
+            engine.crash_after(writes);
+            let committed = (60..80).take_while(|&i| engine.set(&key(i), &[2; 400]).is_ok()).count();
+            drop(engine);
+
+            let engine = BTreeEngine::open(dir.path()).unwrap();
+            let entries = engine.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+            let keys: Vec<String> = entries.into_iter().map(|(key, _)| key).collect();
+            assert_eq!((0..60 + committed).map(key).collect::<Vec<_>>(), keys, "crash after {writes} writes");
+            assert_eq!(0, fs::metadata(dir.child("btree.journal")).unwrap().len());
+            assert_eq!(len(&dir), engine.pages() as u64 * PAGE as u64);
+            engine.set("after", b"the crash").unwrap();
+            if committed == 20 {
+                break;
+            }
+        }
+    }
+
+    // The keys that were never set are answered from the filter, without reading the file. A few are false positives
+    #[test]
+    fn missing_keys_skip_the_disk() {
+        let dir = TempDir::new();
+        let engine = BTreeEngine::open(dir.path()).unwrap();
+        for i in 0..2000 {
+            engine.set(&key(i), &[0; 100]).unwrap();
+        }
+        drop(engine);
+
+        let engine = BTreeEngine::open(dir.path()).unwrap();
+        let before = engine.page_reads();
+        for i in 2000..3000 {
+            assert_eq!(None, engine.get(&key(i)).unwrap());
+        }
+        let reads = engine.page_reads() - before;
+        // 1% of false positives, each of them reading the root and a leaf
+        assert!(reads < 60, "{reads} pages read for 1000 missing keys");
+
+        let depth = engine.depth().unwrap() as u64;
+        let before = engine.page_reads();
+        assert_eq!(Some(vec![0; 100]), engine.get(&key(1999)).unwrap());
+        assert_eq!(depth, engine.page_reads() - before);
+        // Deleting a key leaves it in the filter, and get() reads the pages to find it's gone
+        engine.delete(&key(5)).unwrap();
+        assert_eq!(None, engine.get(&key(5)).unwrap());
+    }
+
+    fn len(dir: &TempDir) -> u64 {
+        fs::metadata(dir.child("btree")).unwrap().len()
+    }
+
+    #[test]
+    fn big_entries_and_damaged_files_are_errors() {
+        let dir = TempDir::new();
+        let engine = BTreeEngine::open(dir.path()).unwrap();
+        let error = engine.set("big", &vec![0; MAX_ENTRY]).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+        engine.set("fits", &vec![0; MAX_ENTRY - entry_len("fits", &[])]).unwrap();
+        drop(engine);
+
+        let mut bytes = fs::read(dir.child("btree")).unwrap();
+        bytes[PAGE + 100] ^= 1;
+        dir.write("btree", &bytes);
+        // Opening scans every page for the Bloom filter, so it's opening that finds the damage
+        assert_eq!(ErrorKind::InvalidData, BTreeEngine::open(dir.path()).err().unwrap().kind());
+
+        dir.write("btree", &bytes[..PAGE]);
+        assert_eq!(ErrorKind::InvalidData, BTreeEngine::open(dir.path()).err().unwrap().kind());
+        dir.write("btree", b"not a tree at all");
+        assert!(BTreeEngine::open(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+// The Log Engine
+
+// The engine that was the whole of durable.rs before there were others. It keeps two files in its directory:
+    // 1. `snapshot`, the whole map as it was at the last compaction (see wal.rs for the format).
+    // 2. `wal`, every change since then, one frame each.
+// open() loads the snapshot, replays the log on top of it, and cuts off the log's torn tail if the last run crashed mid-append.
+// set() and delete() append the change to the log, sync it, and only then change the map, which is the engine's own copy
+// of everything, for get() and scan(). Holding the lock while both happen keeps the order of the log the order of the map,
+// when several threads write at once.
+
+// The files are opened through a FileSystem (projects/common/src/vfs.rs): open() is on the disk, with_fs() on whatever
+// the caller has, like a MemFs that makes the log fail halfway in a test.
+
+// compact() writes a new snapshot and then empties the log. A crash between the two leaves a snapshot that already has
+// the log's changes in it, with the old log still there, and open() replays the log once more. That's harmless: redoing a
+// list of sets and deletes on a map that already went through them changes nothing, the last op on each key still wins.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use common::{
+    progress::Tracker,
+    vfs::{FileSystem, RealFs, WriteFile},
+};
+
+use super::{EngineKind, StorageEngine};
+use crate::{
+    cursor::is_empty_range,
+    wal::{self, Op, Wal},
+};
+
+struct State {
+    map: BTreeMap<String, Vec<u8>>,
+    wal: Wal<Box<dyn WriteFile>>,
+}
+
+pub struct LogEngine {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+    state: Mutex<State>,
+}
+
+impl LogEngine {
+    pub fn open(dir: &Path) -> io::Result<LogEngine> {
+        LogEngine::with_fs(Arc::new(RealFs), dir)
+    }
+
+    // The directory has to be there already, EngineKind::open creates it
+    pub fn with_fs(fs: Arc<dyn FileSystem>, dir: &Path) -> io::Result<LogEngine> {
+        let mut map: BTreeMap<_, _> = wal::read_snapshot(&*fs, &dir.join("snapshot"))?.into_iter().collect();
+        for op in wal::recover(&*fs, &dir.join("wal"))?.ops {
+            apply(&mut map, op);
+        }
+        let wal = Wal::open(&*fs, &dir.join("wal"))?;
+        Ok(LogEngine { fs, dir: dir.to_path_buf(), state: Mutex::new(State { map, wal }) })
+    }
+
+    // The size of the log, to decide when compacting is worth it
+    pub fn wal_len(&self) -> u64 {
+        self.state.lock().unwrap().wal.len()
+    }
+
+    fn write(&self, op: Op) -> io::Result<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        let wal = &mut state.wal;
+        if let Err(e) = wal.append(&op).and_then(|()| wal.sync()) {
+            // Part of the frame may be in the file, and the next append would land after it. Cutting the file back to the
+            // last good frame is worth a try, and if that fails too the next open() cuts it off anyway.
+            let len = wal.len();
+            let _ = wal.get_mut().set_len(len);
+            return Err(e);
+        }
+        Ok(apply(&mut state.map, op))
+    }
+}
+
+// Makes the change in the map, and returns the value it replaced
+fn apply(map: &mut BTreeMap<String, Vec<u8>>, op: Op) -> Option<Vec<u8>> {
+    match op {
+        Op::Set(key, value) => map.insert(key, value),
+        Op::Delete(key) => map.remove(&key),
+    }
+}
+
+impl StorageEngine for LogEngine {
+    fn kind(&self) -> EngineKind {
+        EngineKind::Log
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.state.lock().unwrap().map.get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.write(Op::Set(key.to_string(), value.to_vec()))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.write(Op::Delete(key.to_string()))
+    }
+
+    fn scan(&self, start: Bound<&str>, end: Bound<&str>) -> io::Result<Vec<(String, Vec<u8>)>> {
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let state = self.state.lock().unwrap();
+        Ok(state.map.range::<str, _>((start, end)).map(|(key, value)| (key.clone(), value.clone())).collect())
+    }
+
+    // A new snapshot of everything, and an empty log. Writers wait while it runs, so the snapshot misses no change.
+    // The snapshot is encoded and written in one piece, so its entries are counted all at once, when it's on the disk
+    fn compact(&self, progress: &Tracker) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let entries: Vec<_> = state.map.iter().map(|(key, value)| (key.clone(), value.clone())).collect();
+        wal::write_snapshot(&*self.fs, &self.dir.join("snapshot"), &entries)?;
+        progress.inc(entries.len() as u64);
+        self.fs.create(&self.dir.join("wal"))?.sync()?;
+        state.wal = Wal::open(&*self.fs, &self.dir.join("wal"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::vfs::MemFs;
+    use test_support::TempDir;
+
+    #[test]
+    fn compaction_empties_the_log() {
+        let dir = TempDir::new();
+        let engine = LogEngine::open(dir.path()).unwrap();
+        for i in 0..100 {
+            engine.set("counter", i.to_string().as_bytes()).unwrap();
+        }
+        assert!(engine.wal_len() > 1000);
+        engine.compact(&Tracker::silent()).unwrap();
+        assert_eq!(0, engine.wal_len());
+        engine.set("after", b"yes").unwrap();
+        drop(engine);
+
+        let engine = LogEngine::open(dir.path()).unwrap();
+        assert_eq!(Some(b"99".to_vec()), engine.get("counter").unwrap());
+        assert_eq!(Some(b"yes".to_vec()), engine.get("after").unwrap());
+    }
+
+    #[test]
+    fn a_write_that_doesnt_reach_the_log_doesnt_happen() {
+        let fs = Arc::new(MemFs::new());
+        fs.add_dir("data");
+        let engine = LogEngine::with_fs(Arc::clone(&fs) as Arc<dyn FileSystem>, Path::new("data")).unwrap();
+        engine.set("kept", b"1").unwrap();
+        engine.compact(&Tracker::silent()).unwrap();
+        engine.set("logged", b"2").unwrap();
+
+        // A snapshot that can't be written leaves the old one, and the log as it was
+        fs.fail("data/snapshot.tmp", io::ErrorKind::StorageFull);
+        assert_eq!(io::ErrorKind::StorageFull, engine.compact(&Tracker::silent()).unwrap_err().kind());
+        let (snapshot, log) = (fs.read(Path::new("data/snapshot")).unwrap(), fs.read(Path::new("data/wal")).unwrap());
+        assert!(!log.is_empty());
+
+        fs.fail("data/wal", io::ErrorKind::StorageFull);
+        assert_eq!(io::ErrorKind::StorageFull, engine.set("lost", b"3").unwrap_err().kind());
+        assert_eq!(None, engine.get("lost").unwrap());
+        drop(engine);
+
+        let reopened = MemFs::new();
+        reopened.add_file("data/snapshot", snapshot).add_file("data/wal", log);
+        let engine = LogEngine::with_fs(Arc::new(reopened), Path::new("data")).unwrap();
+        let keys: Vec<_> = engine.scan(Bound::Unbounded, Bound::Unbounded).unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(vec!["kept", "logged"], keys);
+    }
+
+    #[test]
+    fn a_damaged_snapshot_is_an_error() {
+        let dir = TempDir::new();
+        LogEngine::open(dir.path()).unwrap().compact(&Tracker::silent()).unwrap();
+        let mut bytes = std::fs::read(dir.child("snapshot")).unwrap();
+        bytes[4] ^= 1;
+        dir.write("snapshot", bytes);
+        let error = LogEngine::open(dir.path()).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+}
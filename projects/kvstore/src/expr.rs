@@ -0,0 +1,698 @@
+// Expressions
+
+// The WHERE of a query is an expression over the fields of a record: `age >= 18 AND (name = 'ferris' OR admin)`.
+// It takes the two steps of any little language:
+    // 1. tokenize() cuts the text into tokens: names, numbers, 'strings' and operators. Keywords like AND are names,
+    //    the parser tells them apart, in any case.
+    // 2. The Parser turns the tokens into an Expr tree by recursive descent, one function per level of precedence.
+    //    From the loosest to the tightest: OR, AND, NOT, the comparisons, + and -, * and /, and a unary minus.
+// Expr::parse_located is Expr::parse that also says where in the text the error is, for pointing at it, and Expr::pretty writes
+// the tree back with only the parentheses it needs, so that parsing what it wrote gives the same tree again.
+// Expr::eval walks the tree with a function that looks up the value of a name. A name that isn't there is Null.
+// Null is how a record without the field answers: it is only equal to null, and < or + on it is null again. A WHERE takes null
+// as false, so `age > 18` leaves out the records without an age, and so does `NOT age > 18`, instead of failing the whole query.
+
+use std::{cmp::Ordering, fmt};
+
+use crate::value::{FromValue, Value};
+
+// Tokens
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "{name}"),
+            Token::Int(i) => write!(f, "{i}"),
+            Token::Float(x) => write!(f, "{x}"),
+            Token::Text(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Token::Symbol(symbol) => write!(f, "{symbol}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedChar(char, usize),
+    UnterminatedText(usize),
+    BadNumber(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedChar(c, at) => write!(f, "unexpected {c:?} at {at}"),
+            ParseError::UnterminatedText(at) => write!(f, "the text starting at {at} has no closing quote"),
+            ParseError::BadNumber(number) => write!(f, "{number} is not a number"),
+            ParseError::UnexpectedToken(token) => write!(f, "unexpected {token}"),
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of input"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// A ParseError and the byte offset in the text of the token it is about, or the length of the text when it ran out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Located {
+    pub error: ParseError,
+    pub at: usize,
+}
+
+impl Located {
+    // The text, and a ^ under where the error is on the next line
+    pub fn caret(&self, text: &str) -> String {
+        let column = text[..self.at.min(text.len())].chars().count();
+        format!("{text}\n{}^", " ".repeat(column))
+    }
+}
+
+impl fmt::Display for Located {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for Located {}
+
+// The longer symbols come first, so that "<=" isn't read as "<" and then "="
+const SYMBOLS: [&str; 15] = ["<=", ">=", "!=", "<>", "=", "<", ">", "+", "-", "*", "/", "(", ")", ",", ";"];
+
+pub fn tokenize(text: &str) -> Result<Vec<Token>, ParseError> {
+    match tokenize_located(text) {
+        Ok(tokens) => Ok(tokens.into_iter().map(|(token, _)| token).collect()),
+        Err(located) => Err(located.error),
+    }
+}
+
+// The tokens with the offsets they start at
+pub fn tokenize_located(text: &str) -> Result<Vec<(Token, usize)>, Located> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let at = text.len() - rest.len();
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push((Token::Name(rest[..end].to_string()), at));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '.')).unwrap_or(rest.len());
+            let number = &rest[..end];
+            let token = match number.parse::<i64>() {
+                Ok(i) => Token::Int(i),
+                Err(_) => match number.parse() {
+                    Ok(x) => Token::Float(x),
+                    Err(_) => return Err(Located { error: ParseError::BadNumber(number.to_string()), at }),
+                },
+            };
+            tokens.push((token, at));
+            rest = &rest[end..];
+        } else if c == '\'' {
+            // Two quotes in a row are a quote in the text: 'it''s'
+            let mut value = String::new();
+            let mut chars = rest.char_indices().skip(1);
+            let end = loop {
+                match chars.next() {
+                    Some((i, '\'')) => match rest[i + 1..].starts_with('\'') {
+                        true => {
+                            value.push('\'');
+                            chars.next();
+                        }
+                        false => break i + 1,
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => return Err(Located { error: ParseError::UnterminatedText(at), at }),
+                }
+            };
+            tokens.push((Token::Text(value), at));
+            rest = &rest[end..];
+        } else {
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) else {
+                return Err(Located { error: ParseError::UnexpectedChar(c, at), at });
+            };
+            tokens.push((Token::Symbol(symbol), at));
+            rest = &rest[symbol.len()..];
+        }
+    }
+    Ok(tokens)
+}
+
+// The Tree
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Or => "OR",
+            BinOp::And => "AND",
+            BinOp::Eq => "=",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+        }
+    }
+
+    // How tightly it binds, the same levels as the functions of the Parser
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Field(String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+}
+
+// Written back with every binary operation in parentheses, so the order the parser chose can be read off
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Literal(Value::Text(s)) => write!(f, "{}", Token::Text(s.clone())),
+            Expr::Literal(value) => write!(f, "{value}"),
+            Expr::Field(name) => write!(f, "{name}"),
+            Expr::Not(expr) => write!(f, "NOT {expr}"),
+            Expr::Neg(expr) => write!(f, "-{expr}"),
+            Expr::Binary(left, op, right) => write!(f, "({left} {} {right})", op.symbol()),
+        }
+    }
+}
+
+// Pretty Printing
+
+// NOT binds looser than a comparison, so NOT a = 1 is NOT (a = 1) and needs no parentheses, and a unary minus binds tightest
+const NOT: u8 = 3;
+const NEG: u8 = 7;
+const PRIMARY: u8 = 8;
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(_, op, _) => op.precedence(),
+            Expr::Not(_) => NOT,
+            Expr::Neg(_) => NEG,
+            // A negative number reads like a minus in front of one
+            Expr::Literal(Value::Int(i)) if *i < 0 => NEG,
+            Expr::Literal(Value::Float(x)) if x.is_sign_negative() => NEG,
+            _ => PRIMARY,
+        }
+    }
+
+    // Written back in parentheses when it binds looser than where it goes
+    fn pretty_at(&self, min: u8) -> String {
+        match self.precedence() < min {
+            true => format!("({})", self.pretty()),
+            false => self.pretty(),
+        }
+    }
+
+    // The expression with only the parentheses it needs. The operators on one level go from left to right, so a - (b - c)
+    // keeps its parentheses and (a - b) - c loses them. A comparison doesn't chain, so one inside another keeps them too.
+    pub fn pretty(&self) -> String {
+        match self {
+            Expr::Literal(Value::Text(s)) => Token::Text(s.clone()).to_string(),
+            // {:?} keeps the .0 that tells a float from an int
+            Expr::Literal(Value::Float(x)) => format!("{x:?}"),
+            Expr::Literal(value) => value.to_string(),
+            Expr::Field(name) => name.clone(),
+            Expr::Not(expr) => format!("NOT {}", expr.pretty_at(NOT)),
+            Expr::Neg(expr) => format!("-{}", expr.pretty_at(NEG)),
+            Expr::Binary(left, op, right) => {
+                let level = op.precedence();
+                let (left_min, right_min) = match level {
+                    4 => (5, 5),
+                    _ => (level, level + 1),
+                };
+                format!("{} {} {}", left.pretty_at(left_min), op.symbol(), right.pretty_at(right_min))
+            }
+        }
+    }
+}
+
+// The Parser
+
+// Works on the tokens of a whole query, so query.rs uses it too: it parses the SELECT around the expression, and calls expr()
+// when it gets to the WHERE.
+pub struct Parser {
+    tokens: Vec<Token>,
+    // Where each token starts, and where the text ends, when the parser was made with them
+    offsets: Vec<usize>,
+    end: usize,
+    next: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Parser {
+        Parser { tokens, offsets: Vec::new(), end: 0, next: 0 }
+    }
+
+    // A parser that knows where its tokens are in the text, from tokenize_located
+    pub fn located(tokens: Vec<(Token, usize)>, text: &str) -> Parser {
+        let (tokens, offsets) = tokens.into_iter().unzip();
+        Parser { tokens, offsets, end: text.len(), next: 0 }
+    }
+
+    // The offset of the token at hand, or the end of the text after the last one. 0 for a parser made by new()
+    pub fn offset(&self) -> usize {
+        match self.offsets.is_empty() {
+            true => 0,
+            false => self.offsets.get(self.next).copied().unwrap_or(self.end),
+        }
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        let token = self.tokens.get(self.next).cloned().ok_or(ParseError::UnexpectedEnd)?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.next == self.tokens.len()
+    }
+
+    // Takes the next token if it is the keyword, in any case
+    pub fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case(keyword) => {
+                self.next += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Takes the next token if it is the symbol
+    pub fn symbol(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Symbol(s)) if *s == symbol => {
+                self.next += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn expect_symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        match self.symbol(symbol) {
+            true => Ok(()),
+            false => Err(self.unexpected()),
+        }
+    }
+
+    // The error for the token at hand
+    pub fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken(token.to_string()),
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, ParseError> {
+        self.or()
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.and()?;
+        while self.keyword("OR") {
+            left = Expr::Binary(Box::new(left), BinOp::Or, Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.not()?;
+        while self.keyword("AND") {
+            left = Expr::Binary(Box::new(left), BinOp::And, Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        match self.keyword("NOT") {
+            true => Ok(Expr::Not(Box::new(self.not()?))),
+            false => self.comparison(),
+        }
+    }
+
+    // A comparison doesn't chain: a < b < c is an error, not (a < b) < c
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.additive()?;
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => BinOp::Eq,
+            Some(Token::Symbol("!=" | "<>")) => BinOp::Ne,
+            Some(Token::Symbol("<")) => BinOp::Lt,
+            Some(Token::Symbol("<=")) => BinOp::Le,
+            Some(Token::Symbol(">")) => BinOp::Gt,
+            Some(Token::Symbol(">=")) => BinOp::Ge,
+            _ => return Ok(left),
+        };
+        self.next += 1;
+        Ok(Expr::Binary(Box::new(left), op, Box::new(self.additive()?)))
+    }
+
+    fn additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("+")) => BinOp::Add,
+                Some(Token::Symbol("-")) => BinOp::Sub,
+                _ => return Ok(left),
+            };
+            self.next += 1;
+            left = Expr::Binary(Box::new(left), op, Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("*")) => BinOp::Mul,
+                Some(Token::Symbol("/")) => BinOp::Div,
+                _ => return Ok(left),
+            };
+            self.next += 1;
+            left = Expr::Binary(Box::new(left), op, Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.symbol("-") {
+            true => Ok(Expr::Neg(Box::new(self.unary()?))),
+            false => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        if self.symbol("(") {
+            let expr = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+        let literal = match self.peek() {
+            Some(Token::Int(i)) => Value::Int(*i),
+            Some(Token::Float(x)) => Value::Float(*x),
+            Some(Token::Text(s)) => Value::Text(s.clone()),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("true") => Value::Bool(true),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("false") => Value::Bool(false),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("null") => Value::Null,
+            // A keyword where a value should be is a mistake, not a field called AND
+            Some(Token::Name(name)) if !is_keyword(name) => {
+                let field = Expr::Field(name.clone());
+                self.next += 1;
+                return Ok(field);
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.next += 1;
+        Ok(Expr::Literal(literal))
+    }
+}
+
+pub fn is_keyword(name: &str) -> bool {
+    ["AND", "OR", "NOT", "SELECT", "FROM", "WHERE", "LIMIT"].iter().any(|keyword| name.eq_ignore_ascii_case(keyword))
+}
+
+// Evaluation
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    TypeMismatch { op: &'static str, left: &'static str, right: &'static str },
+    NotABool(&'static str),
+    NotANumber(&'static str),
+    DivisionByZero,
+    Overflow,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::TypeMismatch { op, left, right } => write!(f, "can't apply {op} to {left} and {right}"),
+            EvalError::NotABool(found) => write!(f, "expected a bool, found {found}"),
+            EvalError::NotANumber(found) => write!(f, "expected a number, found {found}"),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "integer overflow"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, ParseError> {
+        Expr::parse_located(text).map_err(|located| located.error)
+    }
+
+    // A parse stops at the first error, and the parser is still at the token it was about
+    pub fn parse_located(text: &str) -> Result<Expr, Located> {
+        let mut parser = Parser::located(tokenize_located(text)?, text);
+        let located = |parser: &Parser, error| Located { error, at: parser.offset() };
+        let expr = parser.expr().map_err(|error| located(&parser, error))?;
+        match parser.at_end() {
+            true => Ok(expr),
+            false => Err(located(&parser, parser.unexpected())),
+        }
+    }
+
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Value) -> Result<Value, EvalError> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Field(name) => Ok(lookup(name)),
+            Expr::Not(expr) => not(expr.eval(lookup)?),
+            Expr::Neg(expr) => neg(expr.eval(lookup)?),
+            // AND and OR only look at the right side when the left one doesn't already decide
+            Expr::Binary(left, BinOp::And, right) => {
+                Ok(Value::Bool(truth(&left.eval(lookup)?)? && truth(&right.eval(lookup)?)?))
+            }
+            Expr::Binary(left, BinOp::Or, right) => {
+                Ok(Value::Bool(truth(&left.eval(lookup)?)? || truth(&right.eval(lookup)?)?))
+            }
+            Expr::Binary(left, op, right) => binary(*op, left.eval(lookup)?, right.eval(lookup)?),
+        }
+    }
+
+    // Whether the expression holds for the values lookup gives. Null doesn't, anything else but a bool is an error
+    pub fn matches(&self, lookup: &dyn Fn(&str) -> Value) -> Result<bool, EvalError> {
+        truth(&self.eval(lookup)?)
+    }
+}
+
+// The operations on values are pub(crate) for bytecode.rs, which computes the same things without the tree
+
+pub(crate) fn not(value: Value) -> Result<Value, EvalError> {
+    match value {
+        Value::Null => Ok(Value::Null),
+        value => Ok(Value::Bool(!truth(&value)?)),
+    }
+}
+
+pub(crate) fn neg(value: Value) -> Result<Value, EvalError> {
+    match value {
+        Value::Null => Ok(Value::Null),
+        Value::Int(i) => i.checked_neg().map(Value::Int).ok_or(EvalError::Overflow),
+        Value::Float(x) => Ok(Value::Float(-x)),
+        value => Err(EvalError::NotANumber(value.type_name())),
+    }
+}
+
+pub(crate) fn truth(value: &Value) -> Result<bool, EvalError> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::Null => Ok(false),
+        value => Err(EvalError::NotABool(value.type_name())),
+    }
+}
+
+pub(crate) fn binary(op: BinOp, left: Value, right: Value) -> Result<Value, EvalError> {
+    let mismatch = || EvalError::TypeMismatch { op: op.symbol(), left: left.type_name(), right: right.type_name() };
+    let ordering = left.compare(&right);
+    let compared = |test: fn(Ordering) -> bool| match (&left, &right, ordering) {
+        (Value::Null, _, _) | (_, Value::Null, _) => Ok(Value::Null),
+        (_, _, Some(ordering)) => Ok(Value::Bool(test(ordering))),
+        _ => Err(mismatch()),
+    };
+    match op {
+        // Values of two different types are never equal, and null is only equal to null
+        BinOp::Eq | BinOp::Ne => {
+            let equal = match ordering {
+                Some(ordering) => ordering.is_eq(),
+                None => left == right,
+            };
+            Ok(Value::Bool(equal == (op == BinOp::Eq)))
+        }
+        BinOp::Lt => compared(Ordering::is_lt),
+        BinOp::Le => compared(Ordering::is_le),
+        BinOp::Gt => compared(Ordering::is_gt),
+        BinOp::Ge => compared(Ordering::is_ge),
+        BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => match (&left, &right) {
+            (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+            (Value::Text(a), Value::Text(b)) if op == BinOp::Add => Ok(Value::Text(format!("{a}{b}"))),
+            (Value::Int(a), Value::Int(b)) => {
+                let result = match op {
+                    BinOp::Add => a.checked_add(*b),
+                    BinOp::Sub => a.checked_sub(*b),
+                    BinOp::Mul => a.checked_mul(*b),
+                    _ if *b == 0 => return Err(EvalError::DivisionByZero),
+                    _ => a.checked_div(*b),
+                };
+                result.map(Value::Int).ok_or(EvalError::Overflow)
+            }
+            // A float on either side makes it a float operation
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                let (a, b) = (f64::from_value(&left).unwrap(), f64::from_value(&right).unwrap());
+                Ok(Value::Float(match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    _ => a / b,
+                }))
+            }
+            _ => Err(mismatch()),
+        },
+        BinOp::And | BinOp::Or => unreachable!("evaluated in Expr::eval and by the jumps of a Program"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Result<Value, EvalError> {
+        let lookup = |name: &str| match name {
+            "age" => Value::Int(30),
+            "name" => Value::from("ferris"),
+            "admin" => Value::Bool(true),
+            _ => Value::Null,
+        };
+        Expr::parse(text).unwrap().eval(&lookup)
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            Ok(vec![
+                Token::Name("age".to_string()),
+                Token::Symbol(">="),
+                Token::Float(1.5),
+                Token::Name("AND".to_string()),
+                Token::Text("it's".to_string()),
+                Token::Symbol("<>"),
+                Token::Int(2),
+            ]),
+            tokenize("age>=1.5 AND 'it''s' <> 2")
+        );
+        assert_eq!(Err(ParseError::UnterminatedText(4)), tokenize("a = 'b"));
+        assert_eq!(Err(ParseError::UnexpectedChar('#', 2)), tokenize("a # b"));
+        assert_eq!(Err(ParseError::BadNumber("1.2.3".to_string())), tokenize("1.2.3"));
+    }
+
+    #[test]
+    fn precedence() {
+        let parsed = |text: &str| Expr::parse(text).unwrap().to_string();
+        assert_eq!("((a = 1) OR ((b = 2) AND NOT (c = 3)))", parsed("a = 1 or b = 2 and not c = 3"));
+        assert_eq!("((1 + (2 * 3)) - (-4 / x))", parsed("1 + 2 * 3 - -4 / x"));
+        assert_eq!("((1 + 2) * 3)", parsed("(1 + 2) * 3"));
+        assert_eq!(Err(ParseError::UnexpectedToken("<".to_string())), Expr::parse("a < b < c"));
+        assert_eq!(Err(ParseError::UnexpectedToken("AND".to_string())), Expr::parse("a = AND"));
+        assert_eq!(Err(ParseError::UnexpectedEnd), Expr::parse("(a = 1"));
+    }
+
+    #[test]
+    fn pretty_printing() {
+        let pretty = |text: &str| Expr::parse(text).unwrap().pretty();
+        assert_eq!("a = 1 OR b = 2 AND NOT c = 3", pretty("((a = 1) or ((b = 2) and not (c = 3)))"));
+        assert_eq!("(a OR b) AND NOT (c AND d)", pretty("(a or b) and not (c and d)"));
+        assert_eq!("a - (b - c) - d * -(e + 1.0)", pretty("((a - (b - c)) - (d * -(e + 1.0)))"));
+        assert_eq!("(a < b) = true", pretty("(a < b) = true"));
+        assert_eq!("'it''s' + -2", pretty("'it''s' + -(2)"));
+
+        // What it writes parses back into the same tree
+        for text in ["-(-x)", "NOT NOT a", "1 + 2 * 3 - -4 / x", "(1 + 2) * 3", "a = (b > 1)", "x / (y * z)", "2.5 * 1e3"] {
+            let expr = Expr::parse(text).unwrap();
+            assert_eq!(expr, Expr::parse(&expr.pretty()).unwrap(), "{text} pretty printed as {}", expr.pretty());
+        }
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let at = |text: &str| Expr::parse_located(text).unwrap_err().at;
+        assert_eq!(4, at("1 + * 2"));
+        assert_eq!(6, at("(a = 1"));
+        assert_eq!(6, at("a < b < c"));
+        assert_eq!(4, at("a = 'b"));
+        assert_eq!(4, at("1 + 1.2.3"));
+        // The caret counts characters, not bytes
+        let located = Expr::parse_located("'é' + )").unwrap_err();
+        assert_eq!("'é' + )\n      ^", located.caret("'é' + )"));
+        assert_eq!("unexpected )", located.to_string());
+    }
+
+    #[test]
+    fn evaluation() {
+        assert_eq!(Ok(Value::Bool(true)), eval("age >= 18 AND name = 'ferris' AND admin"));
+        assert_eq!(Ok(Value::Int(61)), eval("age * 2 + 1"));
+        assert_eq!(Ok(Value::Float(15.5)), eval("age / 2 + 0.5"));
+        assert_eq!(Ok(Value::from("ferris the crab")), eval("name + ' the crab'"));
+        assert_eq!(Ok(Value::Bool(true)), eval("age != 'thirty'"));
+
+        // The missing field is null
+        assert_eq!(Ok(Value::Null), eval("height > 100"));
+        assert_eq!(Ok(Value::Null), eval("NOT height > 100"));
+        assert_eq!(Ok(Value::Bool(true)), eval("height = null"));
+        assert_eq!(Ok(Value::Null), eval("height + 1"));
+
+        assert_eq!(Err(EvalError::TypeMismatch { op: "<", left: "int", right: "text" }), eval("age < 'x'"));
+        assert_eq!(Err(EvalError::NotABool("int")), eval("age AND admin"));
+        assert_eq!(Err(EvalError::NotANumber("text")), eval("-name"));
+        assert_eq!(Err(EvalError::DivisionByZero), eval("age / 0"));
+        assert_eq!(Err(EvalError::Overflow), eval("9223372036854775807 + age"));
+        // The right side isn't looked at when the left one decides
+        assert_eq!(Ok(Value::Bool(true)), eval("admin OR age / 0 = 1"));
+    }
+}
@@ -0,0 +1,39 @@
+// A Key-Value Store
+
+// The webserver keeps its state in memory and the chat server forgets everything when it stops. This crate is the start of
+// somewhere to put data: a map from string keys to bytes, and on top of it, records of named fields that can be queried.
+    // 1. store.rs is the map itself, keys in order so that a scan over a range of them is cheap. With a StoreConfig it is
+    //    a cache too, with TTLs and a memory budget. txn.rs has its transactions, cursor.rs pages through its keys.
+    // 2. value.rs has the Value of a field and the Record, which is stored encoded with the codec crate.
+    // 3. expr.rs parses and evaluates expressions like `age >= 18 AND name != 'root'`, and bytecode.rs compiles them for
+    //    a stack machine. The kv-expr binary is a REPL for trying them out (repl.rs), with the editing keys of line_editor.rs.
+    // 4. query.rs puts them together: `SELECT name, age WHERE age >= 18 LIMIT 10` returns the rows that match.
+    // 5. durable.rs keeps the map on disk, with one of the storage engines of engine.rs: a log of every change and a
+    //    snapshot now and then (wal.rs), or a B-tree of pages.
+    // 6. server.rs puts the map behind a TCP port, speaking the protocol of Redis (resp.rs) with its commands (command.rs).
+    //    The kvstore binary runs it, and kv-cli talks to it.
+
+pub mod bytecode;
+pub mod command;
+pub mod cursor;
+pub mod durable;
+pub mod engine;
+pub mod expr;
+pub mod line_editor;
+pub mod query;
+pub mod repl;
+pub mod resp;
+pub mod server;
+pub mod store;
+pub mod txn;
+pub mod value;
+pub mod wal;
+
+pub use cursor::{Cursor, Page};
+pub use durable::DurableStore;
+pub use engine::{EngineKind, StorageEngine};
+pub use query::{Query, QueryError, Row, Rows};
+pub use server::KvServer;
+pub use store::{Eviction, Store, StoreConfig};
+pub use txn::{Txn, TxnError};
+pub use value::{FromValue, Record, Value};
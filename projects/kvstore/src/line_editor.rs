@@ -0,0 +1,351 @@
+// Line Editing
+
+// A terminal normally hands a program its input a line at a time, after the user pressed Enter, and does the editing itself:
+// backspace works, the arrow keys print ^[[D. For the arrows to move the cursor and bring back earlier lines, the program has
+// to switch the terminal into raw mode, get every key as it is pressed, and draw the line itself.
+    // 1. common::term::RawMode turns off the terminal's line editing and echo for as long as it lives, and turns them back on
+    //    when it's dropped, also when the program panics.
+    // 2. read_key reads one key: a character, which is 1 to 4 bytes of UTF-8, or the escape sequence an arrow key sends.
+    // 3. Line is the line being edited. press() changes it for a key, and render() is what to write to redraw it.
+    // 4. Editor puts them together and keeps the history that Up and Down go through.
+// When stdin isn't a terminal, a pipe or a file, there's nothing to edit and Editor reads plain lines instead.
+// The keys are the ones of a shell: Ctrl-A and Ctrl-E go to the start and the end, Ctrl-U clears the line, Ctrl-C gives up on it,
+// and Ctrl-D on an empty line is the end of the input.
+
+use std::io::{self, prelude::*, IsTerminal};
+
+use common::term::{self, RawMode};
+
+// Keys
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    ClearLine,
+    Cancel,
+    EndOfInput,
+    // A control key or an escape sequence that means nothing here
+    Other,
+}
+
+fn byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    match input.read(&mut buf)? {
+        0 => Ok(None),
+        _ => Ok(Some(buf[0])),
+    }
+}
+
+// The key after ESC [ or ESC O. The ones with a number end in ~, like ESC [ 3 ~ for Delete
+fn escape(input: &mut impl Read) -> io::Result<Key> {
+    let Some(b'[' | b'O') = byte(input)? else {
+        return Ok(Key::Other);
+    };
+    Ok(match byte(input)? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(digit @ b'0'..=b'9') => match byte(input)? {
+            Some(b'~') => match digit {
+                b'1' | b'7' => Key::Home,
+                b'3' => Key::Delete,
+                b'4' | b'8' => Key::End,
+                _ => Key::Other,
+            },
+            _ => Key::Other,
+        },
+        _ => Key::Other,
+    })
+}
+
+// The next key, None at the end of the input
+pub fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(first) = byte(input)? else {
+        return Ok(None);
+    };
+    let key = match first {
+        b'\r' | b'\n' => Key::Enter,
+        // Most terminals send DEL for backspace, some send Ctrl-H
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x02 => Key::Left,
+        0x06 => Key::Right,
+        0x10 => Key::Up,
+        0x0e => Key::Down,
+        0x15 => Key::ClearLine,
+        0x03 => Key::Cancel,
+        0x04 => Key::EndOfInput,
+        0x1b => escape(input)?,
+        byte if byte < 0x20 => Key::Other,
+        // The first byte of UTF-8 says how many follow: 110xxxxx one, 1110xxxx two, 11110xxx three
+        byte => {
+            let len = match byte.leading_ones() {
+                0 => 1,
+                n @ 2..=4 => n as usize,
+                _ => return Ok(Some(Key::Other)),
+            };
+            let mut buf = [byte, 0, 0, 0];
+            input.read_exact(&mut buf[1..len])?;
+            match std::str::from_utf8(&buf[..len]).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+// The Line
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Editing,
+    Done(String),
+    Cancelled,
+    EndOfInput,
+}
+
+#[derive(Debug, Default)]
+pub struct Line {
+    chars: Vec<char>,
+    // The index in chars the cursor is before
+    cursor: usize,
+    // Which line of the history is shown, and what was typed before going there
+    recalled: Option<usize>,
+    draft: Vec<char>,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn show(&mut self, chars: Vec<char>) {
+        self.cursor = chars.len();
+        self.chars = chars;
+    }
+
+    pub fn press(&mut self, key: Key, history: &[String]) -> Step {
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Step::Done(self.text()),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.chars.len(),
+            Key::ClearLine => self.show(Vec::new()),
+            // Up goes back from the newest line, Down forward again, and past the newest to what was being typed
+            Key::Up if !history.is_empty() => {
+                let index = match self.recalled {
+                    None => {
+                        self.draft = self.chars.clone();
+                        history.len() - 1
+                    }
+                    Some(index) => index.saturating_sub(1),
+                };
+                self.recalled = Some(index);
+                self.show(history[index].chars().collect());
+            }
+            Key::Down => match self.recalled {
+                Some(index) if index + 1 < history.len() => {
+                    self.recalled = Some(index + 1);
+                    self.show(history[index + 1].chars().collect());
+                }
+                Some(_) => {
+                    self.recalled = None;
+                    let draft = std::mem::take(&mut self.draft);
+                    self.show(draft);
+                }
+                None => {}
+            },
+            Key::Cancel => return Step::Cancelled,
+            // Ctrl-D is the end only on an empty line, on anything else it deletes like Delete
+            Key::EndOfInput if self.chars.is_empty() => return Step::EndOfInput,
+            Key::EndOfInput => return self.press(Key::Delete, history),
+            _ => {}
+        }
+        Step::Editing
+    }
+
+    // Back to the start of the terminal line, the prompt and the text, clear what's left of an older longer line, and put the
+    // cursor where it belongs. Counts a char as one column, which wide characters like emoji aren't
+    pub fn render(&self, prompt: &str) -> String {
+        let column = prompt.chars().count() + self.cursor;
+        format!("\r{prompt}{}{}\r{}", self.text(), term::CLEAR_TO_END_OF_LINE, term::right(column as u16))
+    }
+}
+
+// The Editor
+
+pub struct Editor {
+    history: Vec<String>,
+    max_history: usize,
+}
+
+impl Default for Editor {
+    fn default() -> Editor {
+        Editor::new()
+    }
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor { history: Vec::new(), max_history: 1000 }
+    }
+
+    pub fn max_history(mut self, max_history: usize) -> Self {
+        self.max_history = max_history;
+        self
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    // Blank lines and the same line twice in a row aren't worth going back to
+    pub fn remember(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > self.max_history {
+            self.history.remove(0);
+        }
+    }
+
+    // The next line, without its \n, or None at the end of the input. Edited in raw mode when stdin is a terminal,
+    // and without a prompt when it isn't, since nobody is there to read it
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let terminal = io::stdin().is_terminal();
+        let raw = match terminal {
+            true => RawMode::enable().ok(),
+            false => None,
+        };
+        let line = match raw {
+            Some(_raw) => self.edit(prompt, &mut io::stdin().lock(), &mut io::stdout())?,
+            None => {
+                if terminal {
+                    print!("{prompt}");
+                    io::stdout().flush()?;
+                }
+                let mut line = String::new();
+                match io::stdin().read_line(&mut line)? {
+                    0 => None,
+                    _ => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+                }
+            }
+        };
+        if let Some(line) = &line {
+            self.remember(line);
+        }
+        Ok(line)
+    }
+
+    // Reads keys from input until a line is done, drawing it on output
+    pub fn edit(&self, prompt: &str, input: &mut impl Read, output: &mut impl Write) -> io::Result<Option<String>> {
+        let mut line = Line::default();
+        write!(output, "{}", line.render(prompt))?;
+        output.flush()?;
+        while let Some(key) = read_key(input)? {
+            match line.press(key, &self.history) {
+                Step::Editing => write!(output, "{}", line.render(prompt))?,
+                Step::Done(text) => {
+                    writeln!(output)?;
+                    return Ok(Some(text));
+                }
+                Step::Cancelled => {
+                    writeln!(output, "^C")?;
+                    line = Line::default();
+                    write!(output, "{}", line.render(prompt))?;
+                }
+                Step::EndOfInput => break,
+            }
+            output.flush()?;
+        }
+        writeln!(output)?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut input = bytes;
+        std::iter::from_fn(|| read_key(&mut input).unwrap()).collect()
+    }
+
+    #[test]
+    fn reads_keys_and_escape_sequences() {
+        let chars = [Key::Char('a'), Key::Char('é'), Key::Char('🦀')];
+        let editing = [Key::Left, Key::Up, Key::Delete, Key::End, Key::Backspace, Key::Enter];
+        assert_eq!([&chars[..], &editing].concat(), keys("aé🦀\x1b[D\x1b[A\x1b[3~\x1bOF\x7f\r".as_bytes()));
+        assert_eq!(vec![Key::Home, Key::Cancel, Key::EndOfInput, Key::Other], keys(b"\x01\x03\x04\x1b[9~"));
+    }
+
+    #[test]
+    fn edits_the_line() {
+        let history = [String::from("1 + 1"), String::from("let x = 2")];
+        let mut line = Line::default();
+        let mut press = |key| line.press(key, &history);
+        for key in [Key::Char('b'), Key::Char('c'), Key::Home, Key::Char('a'), Key::End, Key::Backspace, Key::Left] {
+            assert_eq!(Step::Editing, press(key));
+        }
+        assert_eq!(Step::Done(String::from("ab")), press(Key::Enter));
+
+        // Up twice is the line before the last, Down past the newest one is what was typed
+        let mut line = Line::default();
+        line.press(Key::Char('x'), &history);
+        line.press(Key::Up, &history);
+        line.press(Key::Up, &history);
+        line.press(Key::Up, &history);
+        assert_eq!("1 + 1", line.text());
+        line.press(Key::Down, &history);
+        assert_eq!("let x = 2", line.text());
+        line.press(Key::Down, &history);
+        assert_eq!(("x".to_string(), 1), (line.text(), line.cursor()));
+        assert_eq!(Step::Cancelled, line.press(Key::Cancel, &history));
+    }
+
+    #[test]
+    fn draws_and_ends() {
+        let editor = Editor::new();
+        let mut output = Vec::new();
+        let line = editor.edit("> ", &mut "ab\x1b[D\r".as_bytes(), &mut output).unwrap();
+        assert_eq!(Some(String::from("ab")), line);
+        let drawn = String::from_utf8(output).unwrap();
+        assert!(drawn.ends_with("\r> ab\x1b[K\r\x1b[3C\n"), "{drawn:?}");
+
+        let mut output = Vec::new();
+        assert_eq!(None, editor.edit("> ", &mut "\x04".as_bytes(), &mut output).unwrap());
+        assert_eq!(None, editor.edit("> ", &mut "a".as_bytes(), &mut output).unwrap());
+    }
+}
@@ -0,0 +1,117 @@
+// The Key-Value Server
+
+// $ cargo run -- --addr 127.0.0.1:6380 --memory 67108864
+// Then talk to it with kv-cli, or redis-cli, or by typing commands into nc:
+// $ cargo run --bin kv-cli
+// $ redis-cli -p 6380
+// Typing shutdown here, closing stdin (Ctrl-D), Ctrl-C or a kill stops the server, through the hooks of common::signals.
+// The data lives in memory only, and is gone with it.
+// $ cargo run -- --daemon --pid-file kvstore.pid --log-file kvstore.log
+// runs it in the background instead (common::daemon), until kill $(cat kvstore.pid). stdin is /dev/null then, so only a
+// signal stops it.
+
+use std::{process, sync::Arc};
+
+use common::{daemon::Daemon, signals::Shutdown};
+use kvstore::{
+    server::{Config, KvServer},
+    Eviction, Store, StoreConfig,
+};
+use common::argparse::{ArgError, Parser};
+use multithreaded_webserver::accept;
+
+fn config() -> Result<(String, StoreConfig, Config, Option<Daemon>), ArgError> {
+    let matches = Parser::new("kvstore", "a key-value store that speaks the Redis protocol")
+        .option("addr", "ADDR", "The address to listen on")
+        .default("127.0.0.1:6380")
+        .option("threads", "N", "How many clients can be connected at once")
+        .default("32")
+        .option("memory", "BYTES", "Evict keys to stay under this many bytes of keys and values")
+        .option("eviction", "POLICY", "Which keys to evict first: lru, lfu or random")
+        .default("lru")
+        .flag("daemon", "Run in the background, on Unix")
+        .option("pid-file", "PATH", "Where the daemon writes its process id")
+        .option("log-file", "PATH", "Where the daemon's output goes, instead of /dev/null")
+        .parse(std::env::args().skip(1))?;
+
+    let threads: usize = matches.get("threads")?.unwrap();
+    if threads == 0 {
+        return Err(ArgError::Invalid(String::from("--threads must be at least 1")));
+    }
+    let eviction = match matches.value("eviction").unwrap() {
+        "lru" => Eviction::Lru,
+        "lfu" => Eviction::Lfu,
+        "random" => Eviction::Random,
+        other => return Err(ArgError::Invalid(format!("--eviction is lru, lfu or random, not {other}"))),
+    };
+    let mut store = StoreConfig::new().eviction(eviction);
+    if let Some(bytes) = matches.get("memory")? {
+        store = store.memory_budget(bytes);
+    }
+    let daemon = matches.flag("daemon").then(|| {
+        let mut daemon = Daemon::new();
+        if let Some(path) = matches.value("pid-file") {
+            daemon = daemon.pid_file(path);
+        }
+        if let Some(path) = matches.value("log-file") {
+            daemon = daemon.stdout(path).stderr(path);
+        }
+        daemon
+    });
+    if daemon.is_none() && (matches.value("pid-file").is_some() || matches.value("log-file").is_some()) {
+        return Err(ArgError::Invalid(String::from("--pid-file and --log-file only go with --daemon")));
+    }
+    let addr = matches.value("addr").unwrap().to_string();
+    Ok((addr, store, Config { threads }, daemon))
+}
+
+fn main() {
+    let (addr, store, config, daemon) = match config() {
+        Ok(config) => config,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    // Before anything else starts a thread, fork only keeps this one
+    let daemonized = daemon.is_some();
+    let pid_file = match daemon.map(Daemon::start).transpose() {
+        Ok(pid_file) => pid_file.flatten(),
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    if let Err(e) = common::log::init_from_env("LOG") {
+        eprintln!("{e}");
+    }
+
+    let store = Arc::new(Store::with_config(store));
+    let server = KvServer::bind(&addr, Arc::clone(&store), config).unwrap_or_else(|e| {
+        eprintln!("couldn't listen on {addr}: {e}");
+        process::exit(1);
+    });
+    if daemonized {
+        println!("serving on {} as process {}", server.local_addr(), process::id());
+    } else {
+        println!("serving on {}, type shutdown, press Ctrl-D or Ctrl-C to stop", server.local_addr());
+    }
+
+    let handle = server.shutdown_handle();
+    let hooks = Shutdown::new()
+        .hook("flush the log", || {
+            // There's nowhere left to report it
+            let _ = common::log::flush();
+        })
+        .hook("remove the PID file", move || drop(pid_file));
+    // A daemon's stdin is /dev/null, which ends at once, so it isn't read
+    if let Err(e) = accept::serve_until_stopped(|| server.run(), move || handle.shutdown(), hooks, !daemonized) {
+        eprintln!("the server stopped: {e}");
+        process::exit(1);
+    }
+    println!("{} keys were forgotten, bye", store.len());
+}
@@ -0,0 +1,557 @@
+// Queries
+
+// A query asks the store for the records that match, and for some of their fields:
+
+    // SELECT name, age FROM users WHERE age >= 18 AND name != 'root' LIMIT 10
+
+    // 1. SELECT names the columns of the rows, or * for all the fields of each record. `key` is the key of the record.
+    // 2. FROM is optional. The store has no tables, but keys like "users:1" are how a kind of record is kept together,
+    //    so FROM users means the keys that start with "users:".
+    // 3. WHERE is an expression of expr.rs. A record is a row when it evaluates to true.
+    // 4. LIMIT stops the scan after that many rows.
+
+// Running a query takes two steps. plan() works out which keys to look at: the prefix of the FROM, narrowed down by the
+// parts of the WHERE that compare `key` with a text, so `WHERE key = 'users:7'` looks at one key instead of all of them.
+// run() scans those keys, decodes each record with the codec, and yields the rows that match, one at a time.
+// Values that aren't records (the store holds any bytes) are skipped: a query is only about records.
+
+use std::{
+    any, fmt,
+    io::{self, Write},
+    ops::Bound,
+    str::FromStr,
+    vec,
+};
+
+use json::{JsonWriter, ToJson};
+
+use crate::{
+    cursor::is_empty_range,
+    expr::{is_keyword, tokenize, BinOp, EvalError, Expr, ParseError, Parser, Token},
+    store::Store,
+    value::{FromValue, Record, Value},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    Parse(ParseError),
+    Eval { key: String, error: EvalError },
+    NoColumn(String),
+    WrongType { column: String, expected: &'static str, found: &'static str },
+    Columns { expected: usize, found: usize },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::Parse(error) => write!(f, "invalid query: {error}"),
+            QueryError::Eval { key, error } => write!(f, "record {key}: {error}"),
+            QueryError::NoColumn(column) => write!(f, "the row has no column {column}"),
+            QueryError::WrongType { column, expected, found } => {
+                write!(f, "column {column} is {found}, not {expected}")
+            }
+            QueryError::Columns { expected, found } => write!(f, "expected {expected} columns, the row has {found}"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl From<ParseError> for QueryError {
+    fn from(error: ParseError) -> QueryError {
+        QueryError::Parse(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    // None for SELECT *
+    columns: Option<Vec<String>>,
+    table: Option<String>,
+    filter: Option<Expr>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        let mut parser = Parser::new(tokenize(text)?);
+        if !parser.keyword("SELECT") {
+            return Err(parser.unexpected().into());
+        }
+
+        let columns = match parser.symbol("*") {
+            true => None,
+            false => {
+                let mut columns = vec![name(&mut parser)?];
+                while parser.symbol(",") {
+                    columns.push(name(&mut parser)?);
+                }
+                Some(columns)
+            }
+        };
+        let table = match parser.keyword("FROM") {
+            true => Some(name(&mut parser)?),
+            false => None,
+        };
+        let filter = match parser.keyword("WHERE") {
+            true => Some(parser.expr()?),
+            false => None,
+        };
+        let limit = match parser.keyword("LIMIT") {
+            true => match parser.next_token()? {
+                Token::Int(limit) => Some(limit as usize),
+                token => return Err(ParseError::UnexpectedToken(token.to_string()).into()),
+            },
+            false => None,
+        };
+
+        parser.symbol(";");
+        match parser.at_end() {
+            true => Ok(Query { columns, table, filter, limit }),
+            false => Err(parser.unexpected().into()),
+        }
+    }
+
+    pub fn plan(&self) -> Plan {
+        let prefix = self.table.as_ref().map(|table| format!("{table}:")).unwrap_or_default();
+        let mut plan = Plan {
+            start: Bound::Included(prefix.clone()),
+            end: Bound::Unbounded,
+            prefix,
+            filter: self.filter.clone(),
+            limit: self.limit,
+        };
+        if let Some(filter) = &self.filter {
+            for (op, key) in key_conditions(filter) {
+                plan.narrow(op, key);
+            }
+        }
+        plan
+    }
+
+    pub fn run(&self, store: &Store) -> Rows {
+        self.plan().run(store, self.columns.clone())
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(text: &str) -> Result<Query, QueryError> {
+        Query::parse(text)
+    }
+}
+
+// A column or table name, which can't be a keyword
+fn name(parser: &mut Parser) -> Result<String, ParseError> {
+    match parser.next_token()? {
+        Token::Name(name) if !is_keyword(&name) => Ok(name),
+        token => Err(ParseError::UnexpectedToken(token.to_string())),
+    }
+}
+
+// The comparisons of `key` with a text that the whole filter depends on: the ones joined to it by AND, and not under an OR
+// or a NOT. They are written with the key on the left, `'b' > key` is key < 'b'.
+fn key_conditions(filter: &Expr) -> Vec<(BinOp, String)> {
+    match filter {
+        Expr::Binary(left, BinOp::And, right) => {
+            let mut conditions = key_conditions(left);
+            conditions.extend(key_conditions(right));
+            conditions
+        }
+        Expr::Binary(left, op, right) => match (&**left, &**right) {
+            (Expr::Field(field), Expr::Literal(Value::Text(key))) if field == "key" => vec![(*op, key.clone())],
+            (Expr::Literal(Value::Text(key)), Expr::Field(field)) if field == "key" => {
+                let flipped = match op {
+                    BinOp::Lt => BinOp::Gt,
+                    BinOp::Le => BinOp::Ge,
+                    BinOp::Gt => BinOp::Lt,
+                    BinOp::Ge => BinOp::Le,
+                    op => *op,
+                };
+                vec![(flipped, key.clone())]
+            }
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+// The Plan
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    // The range of keys to scan, and the prefix every key must have
+    pub start: Bound<String>,
+    pub end: Bound<String>,
+    pub prefix: String,
+    // The whole WHERE, still checked on every record: the range only leaves out records it would reject anyway
+    pub filter: Option<Expr>,
+    pub limit: Option<usize>,
+}
+
+impl Plan {
+    fn narrow(&mut self, op: BinOp, key: String) {
+        let (start, end) = match op {
+            BinOp::Eq => (Bound::Included(key.clone()), Bound::Included(key)),
+            BinOp::Gt => (Bound::Excluded(key), Bound::Unbounded),
+            BinOp::Ge => (Bound::Included(key), Bound::Unbounded),
+            BinOp::Lt => (Bound::Unbounded, Bound::Excluded(key)),
+            BinOp::Le => (Bound::Unbounded, Bound::Included(key)),
+            _ => return,
+        };
+        // The later start and the earlier end. At the same key, an Excluded bound is the narrower one
+        let later_start = match (&self.start, &start) {
+            (_, Bound::Unbounded) => false,
+            (Bound::Unbounded, _) => true,
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => {
+                b > a || (b == a && matches!(start, Bound::Excluded(_)))
+            }
+        };
+        if later_start {
+            self.start = start;
+        }
+        let earlier_end = match (&self.end, &end) {
+            (_, Bound::Unbounded) => false,
+            (Bound::Unbounded, _) => true,
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => {
+                b < a || (b == a && matches!(end, Bound::Excluded(_)))
+            }
+        };
+        if earlier_end {
+            self.end = end;
+        }
+    }
+
+    // Whether no key can be in the range, like for `key > 'b' AND key < 'a'`
+    pub fn is_empty(&self) -> bool {
+        is_empty_range(self.start.as_ref().map(String::as_str), self.end.as_ref().map(String::as_str))
+    }
+
+    fn run(self, store: &Store, columns: Option<Vec<String>>) -> Rows {
+        // scan() returns nothing for an empty range
+        let entries = store.scan((self.start.as_ref().map(String::as_str), self.end.as_ref().map(String::as_str)));
+        Rows { entries: entries.into_iter(), prefix: self.prefix, filter: self.filter, columns, remaining: self.limit }
+    }
+}
+
+// Like `scan ['users:', 'users:9') filter (age >= 18) limit 10`, for tests and for anyone wondering why a query is slow
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = |key: &String| Token::Text(key.clone());
+        match &self.start {
+            Bound::Included(key) => write!(f, "scan [{}", text(key))?,
+            Bound::Excluded(key) => write!(f, "scan ({}", text(key))?,
+            Bound::Unbounded => write!(f, "scan (..")?,
+        }
+        match &self.end {
+            Bound::Included(key) => write!(f, ", {}]", text(key))?,
+            Bound::Excluded(key) => write!(f, ", {})", text(key))?,
+            Bound::Unbounded => write!(f, ", ..)")?,
+        }
+        if let Some(filter) = &self.filter {
+            write!(f, " filter {filter}")?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " limit {limit}")?;
+        }
+        Ok(())
+    }
+}
+
+// Rows
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    key: String,
+    columns: Vec<(String, Value)>,
+}
+
+impl Row {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn value(&self, column: &str) -> Option<&Value> {
+        self.columns.iter().find(|(name, _)| name == column).map(|(_, value)| value)
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.columns.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    // The value of the column as a T: row.get::<i64>("age")
+    pub fn get<T: FromValue>(&self, column: &str) -> Result<T, QueryError> {
+        let value = self.value(column).ok_or_else(|| QueryError::NoColumn(column.to_string()))?;
+        convert(column, value)
+    }
+}
+
+// {"key": "users:1", "columns": {"name": "ferris", "age": 7}}. The columns are an object in the order of the SELECT
+impl ToJson for Row {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.begin_object()?;
+        json.field("key", &self.key)?;
+        json.key("columns")?;
+        json.begin_object()?;
+        for (name, value) in &self.columns {
+            json.field(name, value)?;
+        }
+        json.end_object()?;
+        json.end_object()
+    }
+}
+
+fn convert<T: FromValue>(column: &str, value: &Value) -> Result<T, QueryError> {
+    T::from_value(value).ok_or_else(|| QueryError::WrongType {
+        column: column.to_string(),
+        expected: any::type_name::<T>(),
+        found: value.type_name(),
+    })
+}
+
+// A whole row as a Rust value. The tuples take the columns in the order of the SELECT, so
+// `SELECT name, age` can be read as (String, i64)
+pub trait FromRow: Sized {
+    fn from_row(row: Row) -> Result<Self, QueryError>;
+}
+
+impl FromRow for Row {
+    fn from_row(row: Row) -> Result<Row, QueryError> {
+        Ok(row)
+    }
+}
+
+macro_rules! impl_from_row {
+    ($count:literal: $($t:ident $index:tt),*) => {
+        impl<$($t: FromValue),*> FromRow for ($($t,)*) {
+            fn from_row(row: Row) -> Result<Self, QueryError> {
+                if row.len() != $count {
+                    return Err(QueryError::Columns { expected: $count, found: row.len() });
+                }
+                Ok(($(convert::<$t>(&row.columns[$index].0, &row.columns[$index].1)?,)*))
+            }
+        }
+    };
+}
+
+impl_from_row!(1: A 0);
+impl_from_row!(2: A 0, B 1);
+impl_from_row!(3: A 0, B 1, C 2);
+impl_from_row!(4: A 0, B 1, C 2, D 3);
+
+// The iterator run() returns. The keys were copied out of the store when it started, the records are decoded and filtered
+// as the rows are asked for, so a query with a LIMIT doesn't decode more records than it needs.
+pub struct Rows {
+    entries: vec::IntoIter<(String, Vec<u8>)>,
+    prefix: String,
+    filter: Option<Expr>,
+    columns: Option<Vec<String>>,
+    remaining: Option<usize>,
+}
+
+impl Rows {
+    // The rows as T, like (String, i64): query.run(&store).typed::<(String, i64)>()
+    pub fn typed<T: FromRow>(self) -> impl Iterator<Item = Result<T, QueryError>> {
+        self.map(|row| T::from_row(row?))
+    }
+
+    // The rows as a JSON array into out, each one written as soon as it's scanned, so a query over the whole store
+    // never holds more than one row. Returns how many there were. A row that fails to evaluate ends it with an
+    // InvalidData error, after the rows before it were written
+    pub fn write_json(self, out: impl Write, pretty: bool) -> io::Result<usize> {
+        let mut json = if pretty { JsonWriter::pretty(out) } else { JsonWriter::new(out) };
+        json.begin_array()?;
+        let mut count = 0;
+        for row in self {
+            let row = row.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            row.to_json(&mut json)?;
+            count += 1;
+        }
+        json.end_array()?;
+        json.finish()?;
+        Ok(count)
+    }
+
+    fn row(&self, key: String, record: Record) -> Row {
+        let columns = match &self.columns {
+            None => record.fields().map(|(name, value)| (name.to_string(), value.clone())).collect(),
+            Some(columns) => columns.iter().map(|name| (name.clone(), lookup(&key, &record, name))).collect(),
+        };
+        Row { key, columns }
+    }
+}
+
+// The value of a name in a WHERE or a SELECT. `key` is the key, even if the record has a field called key
+fn lookup(key: &str, record: &Record, name: &str) -> Value {
+    match name {
+        "key" => Value::from(key),
+        name => record.get(name).cloned().unwrap_or(Value::Null),
+    }
+}
+
+impl Iterator for Rows {
+    type Item = Result<Row, QueryError>;
+
+    fn next(&mut self) -> Option<Result<Row, QueryError>> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        loop {
+            // The range starts at the prefix, so the first key without it is the end of the table
+            let (key, bytes) = self.entries.next()?;
+            if !key.starts_with(&self.prefix) {
+                self.entries = Vec::new().into_iter();
+                return None;
+            }
+            let Ok(record) = Record::decode(&bytes) else { continue };
+            if let Some(filter) = &self.filter {
+                match filter.matches(&|name| lookup(&key, &record, name)) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(error) => return Some(Err(QueryError::Eval { key, error })),
+                }
+            }
+            if let Some(remaining) = &mut self.remaining {
+                *remaining -= 1;
+            }
+            return Some(Ok(self.row(key, record)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> Store {
+        let store = Store::new();
+        let users = [("1", "ferris", 7, true), ("2", "corro", 31, false), ("3", "root", 99, true), ("4", "bob", 18, false)];
+        for (id, name, age, admin) in users {
+            let record = Record::new().with("name", name).with("age", age).with("admin", admin);
+            store.put_record(&format!("users:{id}"), &record);
+        }
+        store.put_record("users:5", &Record::new().with("name", "nobody"));
+        store.put_record("posts:1", &Record::new().with("title", "Hello").with("author", "users:1"));
+        store.set("counter", b"42".to_vec());
+        store
+    }
+
+    fn plan(text: &str) -> String {
+        Query::parse(text).unwrap().plan().to_string()
+    }
+
+    #[test]
+    fn parsing() {
+        let query = Query::parse("select name, age from users where age >= 18 limit 2;").unwrap();
+        assert_eq!(Some(vec!["name".to_string(), "age".to_string()]), query.columns);
+        assert_eq!(Some("users".to_string()), query.table);
+        assert_eq!(Some(2), query.limit);
+        assert_eq!(Ok(query), "SELECT name,age FROM users WHERE age>=18 LIMIT 2".parse());
+
+        let error = |text: &str| Query::parse(text).unwrap_err().to_string();
+        assert_eq!("invalid query: unexpected name", error("name FROM users"));
+        assert_eq!("invalid query: unexpected FROM", error("SELECT FROM users"));
+        assert_eq!("invalid query: unexpected -", error("SELECT * LIMIT -1"));
+        assert_eq!("invalid query: unexpected end of input", error("SELECT * WHERE"));
+        assert_eq!("invalid query: unexpected users", error("SELECT * WHERE a = 1 users"));
+    }
+
+    #[test]
+    fn plans_narrow_the_scan() {
+        assert_eq!("scan ['', ..)", plan("SELECT *"));
+        assert_eq!("scan ['users:', ..) filter (age > 1) limit 3", plan("SELECT * FROM users WHERE age > 1 LIMIT 3"));
+        assert_eq!("scan ['users:3', 'users:3'] filter (key = 'users:3')", plan("SELECT * FROM users WHERE key = 'users:3'"));
+        assert_eq!(
+            "scan ('users:1', 'users:4') filter ((('users:4' > key) AND (key > 'users:1')) AND (key >= 'users:0'))",
+            plan("SELECT * FROM users WHERE 'users:4' > key AND key > 'users:1' AND key >= 'users:0'")
+        );
+        // Under an OR, a condition on the key doesn't narrow anything
+        assert_eq!("scan ['', ..) filter ((key = 'a') OR (key = 'b'))", plan("SELECT * WHERE key = 'a' OR key = 'b'"));
+        assert!(Query::parse("SELECT * WHERE key > 'b' AND key < 'a'").unwrap().plan().is_empty());
+        assert!(Query::parse("SELECT * WHERE key > 'a' AND key <= 'a'").unwrap().plan().is_empty());
+        assert!(!Query::parse("SELECT * WHERE key >= 'a' AND key <= 'a'").unwrap().plan().is_empty());
+    }
+
+    #[test]
+    fn rows_match_the_filter() {
+        let store = store();
+        let query = Query::parse("SELECT key, name FROM users WHERE age >= 18 AND NOT admin").unwrap();
+        let rows: Vec<Row> = query.run(&store).collect::<Result<_, _>>().unwrap();
+        assert_eq!(vec!["users:2", "users:4"], rows.iter().map(Row::key).collect::<Vec<_>>());
+        assert_eq!(Ok("corro".to_string()), rows[0].get::<String>("name"));
+        assert_eq!(Some(&Value::from("users:4")), rows[1].value("key"));
+        assert_eq!(Err(QueryError::NoColumn("age".to_string())), rows[0].get::<i64>("age"));
+
+        // SELECT * has the fields each record has, and the counter isn't a record
+        let all: Vec<Row> = Query::parse("SELECT *").unwrap().run(&store).map(Result::unwrap).collect();
+        assert_eq!(6, all.len());
+        assert_eq!(vec!["title", "author"], all[0].columns().map(|(name, _)| name).collect::<Vec<_>>());
+        assert_eq!(1, all[5].len());
+
+        let limited = Query::parse("SELECT name FROM users LIMIT 2").unwrap().run(&store).count();
+        assert_eq!(2, limited);
+        let one = Query::parse("SELECT name FROM users WHERE key = 'users:3'").unwrap().run(&store);
+        assert_eq!(vec![("root".to_string(),)], one.typed::<(String,)>().map(Result::unwrap).collect::<Vec<_>>());
+        assert_eq!(0, Query::parse("SELECT * WHERE key > 'z' AND key < 'a'").unwrap().run(&store).count());
+    }
+
+    #[test]
+    fn typed_rows() {
+        let store = store();
+        // The columns are fields, not expressions
+        let query = Query::parse("SELECT name, age, age * 2 FROM users WHERE age < 20");
+        assert_eq!(QueryError::Parse(ParseError::UnexpectedToken("*".to_string())), query.unwrap_err());
+
+        let rows = Query::parse("SELECT name, age FROM users").unwrap().run(&store).typed::<(String, Option<i64>)>();
+        let rows: Vec<(String, Option<i64>)> = rows.collect::<Result<_, _>>().unwrap();
+        assert_eq!(("ferris".to_string(), Some(7)), rows[0]);
+        assert_eq!(("nobody".to_string(), None), rows[4]);
+
+        let mut wrong = Query::parse("SELECT name, age FROM users").unwrap().run(&store).typed::<(String, String)>();
+        assert_eq!(
+            Some(Err(QueryError::WrongType { column: "age".to_string(), expected: "alloc::string::String", found: "int" })),
+            wrong.next()
+        );
+        let mut short = Query::parse("SELECT name FROM users").unwrap().run(&store).typed::<(String, i64)>();
+        assert_eq!(Some(Err(QueryError::Columns { expected: 2, found: 1 })), short.next());
+    }
+
+    #[test]
+    fn errors_name_the_record() {
+        let store = store();
+        let mut rows = Query::parse("SELECT * FROM users WHERE name > 3").unwrap().run(&store);
+        let error = rows.next().unwrap().unwrap_err();
+        assert_eq!("record users:1: can't apply > to text and int", error.to_string());
+    }
+
+    #[test]
+    fn rows_stream_as_json() {
+        let store = store();
+        store.put_record("users:6", &Record::new().with("name", "a \"quoted\" name").with("score", f64::NAN));
+        let query = Query::parse("SELECT name, score FROM users WHERE key >= 'users:4'").unwrap();
+        let mut out = Vec::new();
+        assert_eq!(3, query.run(&store).write_json(&mut out, false).unwrap());
+        let expected = concat!(
+            r#"[{"key":"users:4","columns":{"name":"bob","score":null}},"#,
+            r#"{"key":"users:5","columns":{"name":"nobody","score":null}},"#,
+            r#"{"key":"users:6","columns":{"name":"a \"quoted\" name","score":null}}]"#
+        );
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        let rows = Query::parse("SELECT * FROM users WHERE name > 3").unwrap().run(&store);
+        let error = rows.write_json(&mut out, true).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!("record users:1: can't apply > to text and int", error.to_string());
+        assert_eq!(b"[", &out[..]);
+    }
+}
@@ -0,0 +1,175 @@
+// The Expression REPL
+
+// A Session is what kv-expr does with each line it reads, without the terminal, so that the tests can give it lines too:
+    // > let age = 30
+    // age = 30
+    // > age >= 18 AND name = null
+    // true
+    // > :dump age * 2 + 1
+    // 0000  load age
+    // ...
+// The names an expression uses are the variables bound with let so far, and _ is the value of the line before.
+// A name that isn't bound is null, like a field a record doesn't have. A line with a mistake in it gets the error with a ^
+// under where it is, and changes nothing, so the session goes on with the next line.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    bytecode::Program,
+    expr::{is_keyword, Expr, Located, ParseError},
+    value::Value,
+};
+
+const HELP: &str = "\
+An expression is evaluated and its value printed: age >= 18 AND name != 'root'
+  let NAME = EXPR   evaluates EXPR and binds its value to NAME
+  _                 is the value of the last expression
+  :vars             lists the bound names
+  :pretty EXPR      writes EXPR with only the parentheses it needs
+  :dump EXPR        lists the bytecode EXPR compiles to
+  :help             this
+  :quit             ends the session, so does Ctrl-D
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Nothing,
+    Print(String),
+    Quit,
+}
+
+#[derive(Debug, Default)]
+pub struct Session {
+    vars: BTreeMap<String, Value>,
+}
+
+// A value the way it would be typed, with quotes around text
+fn show(value: &Value) -> String {
+    Expr::Literal(value.clone()).pretty()
+}
+
+// The error, and the line with a ^ under where it is. offset is where the text parsed starts in the line
+fn diagnostic(line: &str, offset: usize, located: Located) -> String {
+    let located = Located { at: located.at + offset, ..located };
+    format!("error: {located}\n{}", located.caret(line))
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    pub fn vars(&self) -> &BTreeMap<String, Value> {
+        &self.vars
+    }
+
+    // text is the end of line, after a let or a command
+    fn parse(line: &str, text: &str) -> Result<Expr, String> {
+        let offset = line.len() - text.len();
+        match text.trim().is_empty() {
+            true => Err(diagnostic(line, offset, Located { error: ParseError::UnexpectedEnd, at: text.len() })),
+            false => Expr::parse_located(text).map_err(|located| diagnostic(line, offset, located)),
+        }
+    }
+
+    fn eval(&mut self, line: &str, text: &str) -> Result<Value, String> {
+        let expr = Session::parse(line, text)?;
+        let vars = &self.vars;
+        let value = Program::compile(&expr).run(&|name| vars.get(name).cloned().unwrap_or(Value::Null));
+        value.map_err(|e| format!("error: {e}"))
+    }
+
+    // let NAME = EXPR, the name checked the way the tokenizer would read it
+    fn bind(&mut self, line: &str, rest: &str) -> Result<String, String> {
+        let Some((name, text)) = rest.split_once('=') else {
+            return Err(String::from("error: let needs a name and a value: let NAME = EXPR"));
+        };
+        let name = name.trim();
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid || is_keyword(name) || ["true", "false", "null"].iter().any(|word| name.eq_ignore_ascii_case(word)) {
+            return Err(format!("error: {name:?} can't be the name of a variable"));
+        }
+        let value = self.eval(line, text)?;
+        let reply = format!("{name} = {}", show(&value));
+        self.vars.insert(name.to_string(), value);
+        Ok(reply)
+    }
+
+    fn command(&mut self, line: &str, command: &str, rest: &str) -> Result<Reply, String> {
+        let reply = match command {
+            "help" => HELP.trim_end().to_string(),
+            "quit" | "q" => return Ok(Reply::Quit),
+            "vars" if self.vars.is_empty() => String::from("(no variables)"),
+            "vars" => self.vars.iter().map(|(name, value)| format!("{name} = {}", show(value))).collect::<Vec<_>>().join("\n"),
+            "pretty" => Session::parse(line, rest)?.pretty(),
+            "dump" => Program::compile(&Session::parse(line, rest)?).to_string().trim_end().to_string(),
+            _ => return Err(format!("error: there is no :{command}, :help lists the commands")),
+        };
+        Ok(Reply::Print(reply))
+    }
+
+    pub fn line(&mut self, line: &str) -> Reply {
+        let trimmed = line.trim_start();
+        let result = if trimmed.is_empty() {
+            return Reply::Nothing;
+        } else if let Some(command) = trimmed.strip_prefix(':') {
+            let (command, rest) = command.split_once(' ').unwrap_or((command, ""));
+            self.command(line, command.trim(), rest)
+        } else if let Some(rest) = trimmed.strip_prefix("let ") {
+            self.bind(line, rest).map(Reply::Print)
+        } else {
+            self.eval(line, line).map(|value| {
+                let reply = show(&value);
+                self.vars.insert(String::from("_"), value);
+                Reply::Print(reply)
+            })
+        };
+        result.unwrap_or_else(Reply::Print)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replies(session: &mut Session, lines: &[&str]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| match session.line(line) {
+                Reply::Print(text) => text,
+                reply => format!("{reply:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn binds_and_evaluates() {
+        let mut session = Session::new();
+        let lines = ["let age = 30", "let name = 'ferris'", "age * 2 + 0.5", "_ > 60 AND name = 'ferris'", "", "missing", ":q"];
+        assert_eq!(vec!["age = 30", "name = 'ferris'", "60.5", "true", "Nothing", "null", "Quit"], replies(&mut session, &lines));
+        assert_eq!(vec!["_ = null\nage = 30\nname = 'ferris'"], replies(&mut session, &[":vars"]));
+    }
+
+    #[test]
+    fn errors_point_at_the_mistake_and_change_nothing() {
+        let mut session = Session::new();
+        let replies = replies(&mut session, &["let x = 1 + * 2", "x", "let 2x = 1", "1 / 0", ":dump", ":nope", "let x ="]);
+        assert_eq!("error: unexpected *\nlet x = 1 + * 2\n            ^", replies[0]);
+        assert_eq!("null", replies[1]);
+        assert_eq!("error: \"2x\" can't be the name of a variable", replies[2]);
+        assert_eq!("error: division by zero", replies[3]);
+        assert_eq!("error: unexpected end of input\n:dump\n     ^", replies[4]);
+        assert_eq!("error: there is no :nope, :help lists the commands", replies[5]);
+        assert_eq!("error: unexpected end of input\nlet x =\n       ^", replies[6]);
+    }
+
+    #[test]
+    fn pretty_and_dump() {
+        let mut session = Session::new();
+        let replies = replies(&mut session, &[":pretty ((1 + 2) * (3))", ":dump NOT a OR b"]);
+        assert_eq!("(1 + 2) * 3", replies[0]);
+        let dump = "0000  load a\n0001  not\n0002  truth\n0003  jump-if-true-or-pop 6\n0004  load b\n0005  truth";
+        assert_eq!(dump, replies[1]);
+    }
+}
@@ -0,0 +1,350 @@
+// The Wire Format
+
+// The server speaks RESP, the protocol of Redis, so that redis-cli and the Redis client libraries can talk to it as well as
+// our own kv-cli. Every value on the wire is a Frame, told apart by its first byte and ended by \r\n:
+    // 1. +OK                    a simple string, one line of text
+    // 2. -ERR unknown command   an error, the same but a failure
+    // 3. :42                    an integer
+    // 4. $5\r\nhello            a bulk string: its length and then exactly that many bytes, which can be anything
+    // 5. $-1                    the null bulk string, what GET answers for a key that isn't there
+    // 6. *2\r\n$3\r\nGET\r\n... an array: how many frames follow, and then the frames
+// A client sends a command as an array of bulk strings, the name and its arguments, and the server answers with one frame.
+// Bulk strings know their length, so keys and values can hold spaces, newlines, or binary data.
+
+// Typing arrays into nc by hand isn't much fun, so a line that doesn't start with one of the bytes above is an inline
+// command, split into arguments on whitespace the way a shell would, quotes included: `SET greeting "hello world"`.
+// That is what redis does too.
+
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+use common::table::Table;
+
+// The longest line and the largest bulk string read_frame() believes, so that a client can't make the server allocate
+// gigabytes by sending a big number. Redis has the same limit on bulk strings, at 512 MiB.
+pub const MAX_LINE: u64 = 64 * 1024;
+pub const MAX_BULK: usize = 64 * 1024 * 1024;
+// Arrays in arrays in arrays: the recursion that reads them has to stop somewhere
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Array(Vec<Frame>),
+}
+
+impl Frame {
+    // A command the way a client sends it, an array of bulk strings
+    pub fn command<A: AsRef<[u8]>>(args: &[A]) -> Frame {
+        Frame::Array(args.iter().map(|arg| Frame::Bulk(arg.as_ref().to_vec())).collect())
+    }
+
+    pub fn ok() -> Frame {
+        Frame::Simple(String::from("OK"))
+    }
+
+    pub fn error(message: &str) -> Frame {
+        Frame::Error(format!("ERR {message}"))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, self).unwrap();
+        bytes
+    }
+
+    // The frame as redis-cli prints it, for people to read:
+    // "hello", (nil), (integer) 1, (error) ERR ..., and arrays as numbered lines
+    pub fn pretty(&self) -> String {
+        match self {
+            Frame::Simple(text) => text.clone(),
+            Frame::Error(message) => format!("(error) {message}"),
+            Frame::Integer(n) => format!("(integer) {n}"),
+            // Debug puts the quotes around it and escapes what isn't printable
+            Frame::Bulk(bytes) => format!("{:?}", String::from_utf8_lossy(bytes)),
+            Frame::Null => String::from("(nil)"),
+            Frame::Array(frames) if frames.is_empty() => String::from("(empty array)"),
+            Frame::Array(frames) => {
+                let width = frames.len().to_string().len();
+                let mut lines = Vec::new();
+                for (i, frame) in frames.iter().enumerate() {
+                    // The lines of an array in the array line up under the first one
+                    let number = format!("{:>width$}) ", i + 1);
+                    for (j, line) in frame.pretty().lines().enumerate() {
+                        let prefix = if j == 0 { number.clone() } else { " ".repeat(number.len()) };
+                        lines.push(format!("{prefix}{line}"));
+                    }
+                }
+                lines.join("\n")
+            }
+        }
+    }
+
+    // A page of SCAN or RANGE, [cursor, [...]], as a table with the columns, for kv-cli --table. The frames in the page fill
+    // the rows in turn, a key a row for SCAN and a key and its value for RANGE, with the cursor to go on from under them.
+    // None for anything else, which stays pretty()
+    pub fn page_table(&self, columns: &[&str]) -> Option<String> {
+        let Frame::Array(answer) = self else { return None };
+        let [Frame::Bulk(cursor), Frame::Array(items)] = answer.as_slice() else { return None };
+        if items.len() % columns.len() != 0 {
+            return None;
+        }
+        let mut table = Table::new(columns.iter().copied());
+        for row in items.chunks(columns.len()) {
+            let mut cells = Vec::new();
+            for item in row {
+                let Frame::Bulk(bytes) = item else { return None };
+                // A cell is one line, so a newline in a value is written \n
+                cells.push(String::from_utf8_lossy(bytes).escape_debug().to_string());
+            }
+            table.add_row(cells);
+        }
+        for column in 0..columns.len() {
+            table = table.max_width(column, 60);
+        }
+        Some(format!("{table}(cursor {})", String::from_utf8_lossy(cursor)))
+    }
+}
+
+// Writes the frame, without flushing: the server flushes once a batch of pipelined commands is answered, see server.rs.
+// A simple string or an error can't hold a line break, it would end the frame early, so they become spaces.
+pub fn write_frame<W: Write>(out: &mut W, frame: &Frame) -> io::Result<()> {
+    let line = |text: &str| text.replace(['\r', '\n'], " ");
+    match frame {
+        Frame::Simple(text) => write!(out, "+{}\r\n", line(text)),
+        Frame::Error(message) => write!(out, "-{}\r\n", line(message)),
+        Frame::Integer(n) => write!(out, ":{n}\r\n"),
+        Frame::Bulk(bytes) => {
+            write!(out, "${}\r\n", bytes.len())?;
+            out.write_all(bytes)?;
+            out.write_all(b"\r\n")
+        }
+        Frame::Null => out.write_all(b"$-1\r\n"),
+        Frame::Array(frames) => {
+            write!(out, "*{}\r\n", frames.len())?;
+            frames.iter().try_for_each(|frame| write_frame(out, frame))
+        }
+    }
+}
+
+// Reads the next frame, or the next inline command as an array of bulk strings. None when the connection was closed
+// cleanly, between two frames. A frame that breaks the protocol is an InvalidData error, and the connection can't be
+// trusted after it: there is no telling where the next frame starts.
+pub fn read_frame<R: BufRead>(input: &mut R) -> io::Result<Option<Frame>> {
+    loop {
+        let Some(line) = read_line(input)? else { return Ok(None) };
+        if !is_inline(&line) {
+            return parse(input, line, 0).map(Some);
+        }
+        match split_args(&line) {
+            // Empty lines are skipped, like pressing enter at a prompt
+            Some(args) if args.is_empty() => continue,
+            Some(args) => return Ok(Some(Frame::Array(args.into_iter().map(Frame::Bulk).collect()))),
+            None => return Err(invalid("unbalanced quotes in the inline command")),
+        }
+    }
+}
+
+// The frames a client reads start with one of these. Anything else is typed by someone
+fn is_inline(line: &[u8]) -> bool {
+    !matches!(line.first(), Some(b'+' | b'-' | b':' | b'$' | b'*'))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("protocol error: {message}"))
+}
+
+// One line, without its \r\n (a bare \n is fine too). None at the end of the input
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    input.take(MAX_LINE).read_until(b'\n', &mut line)?;
+    match line.last() {
+        None => Ok(None),
+        Some(b'\n') => {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            Ok(Some(line))
+        }
+        Some(_) if line.len() as u64 == MAX_LINE => Err(invalid("the line is too long")),
+        Some(_) => Err(ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+fn parse<R: BufRead>(input: &mut R, line: Vec<u8>, depth: usize) -> io::Result<Frame> {
+    let text = String::from_utf8(line).map_err(|_| invalid("the line isn't UTF-8"))?;
+    let (kind, rest) = text.split_at_checked(1).ok_or_else(|| invalid("a line that isn't a frame"))?;
+    let number = || rest.parse::<i64>().map_err(|_| invalid(&format!("{rest:?} isn't a number")));
+    match kind {
+        "+" => Ok(Frame::Simple(rest.to_string())),
+        "-" => Ok(Frame::Error(rest.to_string())),
+        ":" => number().map(Frame::Integer),
+        "$" => match number()? {
+            -1 => Ok(Frame::Null),
+            len if len < 0 || len as usize > MAX_BULK => Err(invalid(&format!("a bulk string can't be {len} bytes long"))),
+            len => {
+                let mut bytes = vec![0; len as usize + 2];
+                input.read_exact(&mut bytes)?;
+                if !bytes.ends_with(b"\r\n") {
+                    return Err(invalid("a bulk string doesn't end with \\r\\n"));
+                }
+                bytes.truncate(len as usize);
+                Ok(Frame::Bulk(bytes))
+            }
+        },
+        "*" => match number()? {
+            -1 => Ok(Frame::Null),
+            len if len < 0 => Err(invalid(&format!("an array can't have {len} elements"))),
+            _ if depth == MAX_DEPTH => Err(invalid("the arrays are nested too deep")),
+            len => {
+                // Not with_capacity(len): the length is only a claim until the frames arrive
+                let mut frames = Vec::new();
+                for _ in 0..len {
+                    let line = read_line(input)?.ok_or(ErrorKind::UnexpectedEof)?;
+                    frames.push(parse(input, line, depth + 1)?);
+                }
+                Ok(Frame::Array(frames))
+            }
+        },
+        _ => Err(invalid(&format!("a frame can't start with {kind:?}"))),
+    }
+}
+
+// Splits a line into arguments like a shell: on whitespace, except inside quotes. In double quotes \" \\ \n \r \t and \xHH
+// are escapes, in single quotes nothing is. None when a quote isn't closed.
+pub fn split_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut bytes = line.iter().copied().peekable();
+    loop {
+        while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+        if bytes.peek().is_none() {
+            return Some(args);
+        }
+        let mut arg = Vec::new();
+        while let Some(byte) = bytes.next_if(|byte| !byte.is_ascii_whitespace()) {
+            match byte {
+                b'"' => loop {
+                    match bytes.next()? {
+                        b'"' => break,
+                        b'\\' => arg.push(match bytes.next()? {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'x' => {
+                                let hex = [bytes.next()?, bytes.next()?];
+                                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+                            }
+                            other => other,
+                        }),
+                        other => arg.push(other),
+                    }
+                },
+                b'\'' => loop {
+                    match bytes.next()? {
+                        b'\'' => break,
+                        other => arg.push(other),
+                    }
+                },
+                other => arg.push(other),
+            }
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(bytes: &[u8]) -> io::Result<Vec<Frame>> {
+        let mut input = bytes;
+        let mut frames = Vec::new();
+        while let Some(frame) = read_frame(&mut input)? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = vec![
+            Frame::ok(),
+            Frame::error("no such key"),
+            Frame::Integer(-3),
+            Frame::Bulk(b"two\r\nlines and \0".to_vec()),
+            Frame::Bulk(Vec::new()),
+            Frame::Null,
+            Frame::Array(vec![Frame::Integer(1), Frame::Array(Vec::new()), Frame::command(&["GET", "k"])]),
+        ];
+        let bytes: Vec<u8> = frames.iter().flat_map(Frame::to_bytes).collect();
+        assert_eq!(frames, read_all(&bytes).unwrap());
+        assert_eq!(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n".to_vec(), Frame::command(&["GET", "k"]).to_bytes());
+        assert_eq!(b"+a b\r\n".to_vec(), Frame::Simple("a\nb".to_string()).to_bytes());
+    }
+
+    #[test]
+    fn inline_commands_split_like_a_shell() {
+        let frames = read_all(b"SET greeting \"hello world\"\r\n\r\nget 'it''s'\nECHO \"\\x41\\n\\\"\"\n").unwrap();
+        assert_eq!(
+            vec![
+                Frame::command(&["SET", "greeting", "hello world"]),
+                Frame::command(&["get", "its"]),
+                Frame::command(&["ECHO", "A\n\""]),
+            ],
+            frames
+        );
+        assert_eq!(None, split_args(b"SET k \"open"));
+        assert_eq!(Some(vec![b"a".to_vec(), Vec::new()]), split_args(b"  a  \"\"  "));
+    }
+
+    #[test]
+    fn broken_frames_are_errors() {
+        for bytes in [&b"$5\r\nhello!!"[..], b"$-2\r\n", b":12a\r\n", b"*1\r\n!\r\n", b"$999999999999\r\n"] {
+            let error = read_all(bytes).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind(), "{:?}", String::from_utf8_lossy(bytes));
+        }
+        // Cut short, in a line or in a bulk string
+        for bytes in [&b"*2\r\n$3\r\nGET\r\n"[..], b"$5\r\nhel", b"GET k"] {
+            assert_eq!(ErrorKind::UnexpectedEof, read_all(bytes).unwrap_err().kind());
+        }
+        let nested = "*1\r\n".repeat(MAX_DEPTH + 1);
+        assert_eq!(ErrorKind::InvalidData, read_all(nested.as_bytes()).unwrap_err().kind());
+        let long = vec![b'a'; MAX_LINE as usize + 1];
+        assert_eq!(ErrorKind::InvalidData, read_all(&long).unwrap_err().kind());
+    }
+
+    #[test]
+    fn pretty_looks_like_redis_cli() {
+        assert_eq!("\"say \\\"hi\\\"\"", Frame::Bulk(b"say \"hi\"".to_vec()).pretty());
+        assert_eq!("(nil)", Frame::Null.pretty());
+        let keys = Frame::Array((1..=10).map(Frame::Integer).collect());
+        assert!(keys.pretty().starts_with(" 1) (integer) 1\n 2)"));
+        assert!(keys.pretty().ends_with("\n10) (integer) 10"));
+        let nested = Frame::Array(vec![Frame::Array(vec![Frame::ok(), Frame::ok()])]);
+        assert_eq!("1) 1) OK\n   2) OK", nested.pretty());
+    }
+
+    #[test]
+    fn pages_become_tables() {
+        let bulk = |text: &str| Frame::Bulk(text.as_bytes().to_vec());
+        let page = Frame::Array(vec![
+            bulk("0"),
+            Frame::Array(vec![bulk("user:1"), bulk("ferris"), bulk("user:2"), bulk("two\nlines")]),
+        ]);
+        let expected = "\
+key     value
+------  ----------
+user:1  ferris
+user:2  two\\nlines
+(cursor 0)";
+        assert_eq!(Some(expected), page.page_table(&["key", "value"]).as_deref());
+        assert!(page.page_table(&["key"]).unwrap().contains("\nferris\n"));
+        // An odd number of frames isn't pairs, and a GET answer isn't a page
+        assert_eq!(None, page.page_table(&["a", "b", "c"]));
+        assert_eq!(None, bulk("ferris").page_table(&["key"]));
+    }
+}
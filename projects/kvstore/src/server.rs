@@ -0,0 +1,213 @@
+// The Server
+
+// A Store behind a TCP port, speaking RESP (resp.rs) so that redis-cli works as a client, as does our own kv-cli.
+// It is served like the chat server (projects/chat_server/src/server.rs), by an Acceptor (multithreaded_webserver/src/accept.rs):
+// one worker each for as long as the client stays, so Config::threads is also how many clients can be connected at once.
+// All of them share one Store, which has its own lock.
+
+// Pipelining is sending many commands without waiting for the answers in between, which saves a round trip per command.
+// Nothing special has to happen for the commands to be read, they are just there in the socket one after another. The trick is
+// in the answers: they go into a BufWriter, and it is only flushed when the BufReader has nothing more to read, at the end of
+// the batch. A thousand pipelined GETs are then answered with a few writes instead of a thousand.
+
+// ShutdownHandle::shutdown() closes every connection, which ends their workers' loops, and then the Acceptor's loop ends too.
+
+use std::{
+    io::{self, prelude::*, BufReader, BufWriter, ErrorKind},
+    net::{Shutdown, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use multithreaded_webserver::{
+    accept::{Acceptor, Stopper},
+    net::{Stream, Tcp},
+};
+
+use crate::{
+    command::Command,
+    resp::{self, Frame},
+    store::Store,
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub threads: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { threads: 32 }
+    }
+}
+
+// Another handle to a connection's stream, to close it with
+type Socket = Arc<Mutex<Box<dyn Stream>>>;
+
+pub struct KvServer {
+    acceptor: Acceptor<Socket>,
+    store: Arc<Store>,
+    config: Config,
+}
+
+impl KvServer {
+    // The store is shared, so the program that starts the server can still use it, or hand it to another server
+    pub fn bind(addr: &str, store: Arc<Store>, config: Config) -> io::Result<KvServer> {
+        assert!(config.threads > 0, "the server needs at least one thread");
+        Ok(KvServer { acceptor: Acceptor::bind(Arc::new(Tcp), addr)?, store, config })
+    }
+
+    // The address it listens on, with the port the system picked when it was bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.acceptor.local_addr()
+    }
+
+    pub fn store(&self) -> &Arc<Store> {
+        &self.store
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { stopper: self.acceptor.stopper() }
+    }
+
+    // Serves clients until shutdown() is called, or accepting a connection fails
+    pub fn run(self) -> io::Result<()> {
+        let store = self.store;
+        let open = |stream: &dyn Stream| Ok(Arc::new(Mutex::new(stream.try_clone()?)));
+        self.acceptor.run(self.config.threads, open, move |stream, _| serve_client(stream, &store))
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    stopper: Stopper<Socket>,
+}
+
+impl ShutdownHandle {
+    // Calling it again does nothing
+    pub fn shutdown(&self) {
+        self.stopper.stop(|socket| {
+            // Closed already when the client left first
+            let _ = socket.lock().unwrap().shutdown(Shutdown::Both);
+        });
+    }
+}
+
+fn serve_client(stream: Box<dyn Stream>, store: &Store) -> io::Result<()> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut output = BufWriter::new(stream);
+    loop {
+        let frame = match resp::read_frame(&mut input) {
+            Ok(Some(frame)) => frame,
+            // The client closed the connection, or shutdown() did
+            Ok(None) => break,
+            // There is no telling where the next frame would start, so the client is told why and the connection closed
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                resp::write_frame(&mut output, &Frame::error(&e.to_string()))?;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let (reply, quit) = match Command::parse(frame) {
+            Ok(Command::Quit) => (Frame::ok(), true),
+            Ok(command) => (command.run(store), false),
+            Err(message) => (Frame::error(&message), false),
+        };
+        resp::write_frame(&mut output, &reply)?;
+        if quit {
+            break;
+        }
+        // The end of a batch of pipelined commands, or a command on its own
+        if input.buffer().is_empty() {
+            output.flush()?;
+        }
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpStream, thread, time::Duration};
+
+    fn start() -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+        let server = KvServer::bind("127.0.0.1:0", Arc::new(Store::new()), Config { threads: 4 }).unwrap();
+        let (addr, handle) = (server.local_addr(), server.shutdown_handle());
+        (addr, handle, thread::spawn(move || server.run()))
+    }
+
+    fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let input = BufReader::new(stream.try_clone().unwrap());
+        (stream, input)
+    }
+
+    fn reply(input: &mut BufReader<TcpStream>) -> Frame {
+        resp::read_frame(input).unwrap().unwrap()
+    }
+
+    #[test]
+    fn clients_share_the_store() {
+        let (addr, handle, server) = start();
+        let (mut alice, mut alice_in) = connect(addr);
+        let (mut bob, mut bob_in) = connect(addr);
+
+        alice.write_all(&Frame::command(&["SET", "name", "ferris"]).to_bytes()).unwrap();
+        assert_eq!(Frame::ok(), reply(&mut alice_in));
+        // An inline command, the way it is typed into nc
+        bob.write_all(b"GET name\r\n").unwrap();
+        assert_eq!(Frame::Bulk(b"ferris".to_vec()), reply(&mut bob_in));
+        bob.write_all(b"QUIT\r\n").unwrap();
+        assert_eq!(Frame::ok(), reply(&mut bob_in));
+        assert!(resp::read_frame(&mut bob_in).unwrap().is_none());
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+        assert!(resp::read_frame(&mut alice_in).map_or(true, |frame| frame.is_none()));
+    }
+
+    #[test]
+    fn pipelined_commands_are_answered_in_order() {
+        let (addr, handle, server) = start();
+        let (mut client, mut input) = connect(addr);
+
+        // All of them in one write, before reading anything
+        let mut batch = Vec::new();
+        for i in 0..1000 {
+            batch.extend(Frame::command(&["SET".to_string(), format!("key:{i:03}"), i.to_string()]).to_bytes());
+            batch.extend(Frame::command(&["GET".to_string(), format!("key:{i:03}")]).to_bytes());
+        }
+        batch.extend(b"DEL key:000 key:001 missing\r\nKEYS key:99?\r\n");
+        client.write_all(&batch).unwrap();
+
+        for i in 0..1000 {
+            assert_eq!(Frame::ok(), reply(&mut input));
+            assert_eq!(Frame::Bulk(i.to_string().into_bytes()), reply(&mut input));
+        }
+        assert_eq!(Frame::Integer(2), reply(&mut input));
+        let keys = (990..1000).map(|i| Frame::Bulk(format!("key:{i}").into_bytes())).collect();
+        assert_eq!(Frame::Array(keys), reply(&mut input));
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn a_protocol_error_closes_the_connection() {
+        let (addr, handle, server) = start();
+        let (mut client, mut input) = connect(addr);
+        client.write_all(b"PING\r\n$3\r\nabcdef\r\nPING\r\n").unwrap();
+        assert_eq!(Frame::Simple("PONG".to_string()), reply(&mut input));
+        let Frame::Error(message) = reply(&mut input) else { panic!("expected an error") };
+        assert!(message.starts_with("ERR protocol error"), "{message}");
+        assert!(resp::read_frame(&mut input).unwrap().is_none());
+
+        // The other clients don't notice
+        let (mut other, mut other_in) = connect(addr);
+        other.write_all(b"PING\r\n").unwrap();
+        assert_eq!(Frame::Simple("PONG".to_string()), reply(&mut other_in));
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,717 @@
+// The Store
+
+// The store is a BTreeMap from keys to bytes behind a Mutex, so it can be shared between threads in an Arc.
+// A BTreeMap keeps the keys in order, which a HashMap doesn't: the keys of one kind of thing share a prefix ("user:1", "user:2"),
+// and scan() gets all of them from a range of the map without looking at any other key.
+// scan_page() gets them a page at a time instead, and goes on from a Cursor (cursor.rs).
+// The store doesn't know what the bytes mean. put_record, add_record and get_record are the only ones that do, for the records
+// of value.rs.
+
+// A store can also be a cache, with a StoreConfig:
+    // 1. A key set with a TTL (time to live) disappears after it. get() checks the deadline, so an expired key is never
+    //    returned, and a TimerWheel (projects/multithreaded_webserver/src/timer.rs) removes it at its deadline, so that
+    //    keys nobody asks for again don't stay in memory forever. The wheel's thread only starts with the first TTL.
+    // 2. With a memory budget, a set() that makes the keys and values take more bytes than the budget evicts other keys
+    //    until they fit again. Which ones is the Eviction policy: the least recently used, the least frequently used, or
+    //    random ones. Random sounds silly, but costs nothing to keep track of and does about as well on many workloads.
+    // 3. Every eviction and expiration is counted in the Metrics given to the config, as kvstore_evictions and
+    //    kvstore_expirations, next to the webserver's own.
+// The time a TTL counts from is the config's Clock (projects/common/src/clock.rs), and the TimerWheel runs on it too,
+// so a test gives the store a FakeClock and advances it past the TTL instead of sleeping through it.
+// begin() starts a transaction (see txn.rs). For those, every write has a version, and while one is open, a write keeps the
+// value it replaced. Expirations and evictions don't: a transaction can't keep a key of a cache alive.
+// This is why there's a Mutex and not a RwLock: with a budget, even get() changes something, the order the policy goes by.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    ops::{Bound, RangeBounds},
+    sync::{Arc, Mutex, OnceLock, Weak},
+    time::{Duration, Instant},
+};
+
+use codec::DecodeError;
+use common::clock::{Clock, SystemClock};
+use multithreaded_webserver::{
+    ids::Uuid,
+    metrics::Metrics,
+    time_ext::Deadline,
+    timer::{TimerToken, TimerWheel},
+};
+use std_collections::rand_lite::{Rng, Xoshiro256};
+
+use crate::{
+    cursor::{is_empty_range, Cursor, Page},
+    txn::Txn,
+    value::Record,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+    #[default]
+    Lru,
+    Lfu,
+    Random,
+}
+
+#[derive(Clone, Default)]
+pub struct StoreConfig {
+    memory_budget: Option<usize>,
+    eviction: Eviction,
+    metrics: Option<Arc<Metrics>>,
+    seed: u64,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl StoreConfig {
+    pub fn new() -> StoreConfig {
+        StoreConfig::default()
+    }
+
+    // The bytes of all the keys and values together. Without a budget nothing is ever evicted
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    pub fn eviction(mut self, eviction: Eviction) -> Self {
+        self.eviction = eviction;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // The seed of the random policy, so a test can know which keys go
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    // The SystemClock unless a test says otherwise
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+}
+
+struct Entry {
+    value: Vec<u8>,
+    expires: Option<(Instant, TimerToken)>,
+    // The tick of the last use, and the number of uses, for the policies
+    used: u64,
+    hits: u64,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.as_ref().is_some_and(|(at, _)| *at <= now)
+    }
+}
+
+// The version of a write, and the value it replaced
+type Replaced = (u64, Option<Vec<u8>>);
+
+pub(crate) struct Inner {
+    map: BTreeMap<String, Entry>,
+    // The bytes of the keys and values in the map
+    size: usize,
+    // A counter that goes up with every use, cheaper than an Instant and never the same twice
+    tick: u64,
+    // Only the one index the policy needs is kept up to date, and none without a budget:
+    // the keys by last use for Lru, by (uses, last use) for Lfu
+    recency: BTreeMap<u64, String>,
+    frequency: BTreeSet<(u64, u64, String)>,
+    rng: Xoshiro256,
+    // For the transactions: the number of writes so far, which is the version of the last one, the snapshots of the open
+    // transactions with how many are open on each, and the values that writes replaced while any of them was open
+    version: u64,
+    snapshots: BTreeMap<u64, usize>,
+    history: HashMap<String, Vec<Replaced>>,
+}
+
+pub(crate) struct Shared {
+    pub(crate) inner: Mutex<Inner>,
+    config: StoreConfig,
+    clock: Arc<dyn Clock>,
+}
+
+pub struct Store {
+    shared: Arc<Shared>,
+    timers: OnceLock<TimerWheel>,
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store::with_config(StoreConfig::default())
+    }
+
+    pub fn with_config(config: StoreConfig) -> Store {
+        let inner = Inner {
+            map: BTreeMap::new(),
+            size: 0,
+            tick: 0,
+            recency: BTreeMap::new(),
+            frequency: BTreeSet::new(),
+            rng: Xoshiro256::seed_from_u64(config.seed),
+            version: 0,
+            snapshots: BTreeMap::new(),
+            history: HashMap::new(),
+        };
+        let clock = config.clock.clone().unwrap_or_else(|| Arc::new(SystemClock));
+        Store { shared: Arc::new(Shared { inner: Mutex::new(inner), config, clock }), timers: OnceLock::new() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.shared.get(key)
+    }
+
+    // Returns the bytes that were there before. A TTL the key had is gone, like in Redis
+    pub fn set(&self, key: &str, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.shared.set(key, value, None)
+    }
+
+    pub fn set_with_ttl(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Option<Vec<u8>> {
+        let at = self.shared.now() + ttl;
+        let token = self.schedule_expiry(key, at);
+        self.shared.set(key, value, Some((at, token)))
+    }
+
+    // Gives a key that is there a TTL, or a new one. false if there is no such key
+    pub fn expire(&self, key: &str, ttl: Duration) -> bool {
+        let at = self.shared.now() + ttl;
+        let token = self.schedule_expiry(key, at);
+        let mut inner = self.shared.inner.lock().unwrap();
+        match inner.live_entry(key, self.shared.now(), &self.shared.config) {
+            Some(entry) => {
+                if let Some((_, old)) = entry.expires.replace((at, token)) {
+                    old.cancel();
+                }
+                true
+            }
+            None => {
+                token.cancel();
+                false
+            }
+        }
+    }
+
+    // Takes the TTL off a key. false if there is no such key, or it had no TTL
+    pub fn persist(&self, key: &str) -> bool {
+        let mut inner = self.shared.inner.lock().unwrap();
+        match inner.live_entry(key, self.shared.now(), &self.shared.config).and_then(|entry| entry.expires.take()) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The time the key has left, None if it has no TTL or isn't there
+    pub fn ttl(&self, key: &str) -> Option<Duration> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        let now = self.shared.now();
+        let (at, _) = inner.live_entry(key, now, &self.shared.config)?.expires.as_ref()?;
+        Some(at.saturating_duration_since(now))
+    }
+
+    pub fn delete(&self, key: &str) -> Option<Vec<u8>> {
+        self.shared.write(&mut self.shared.inner.lock().unwrap(), key, None, None)
+    }
+
+    // A transaction that sees the store as it is now, see txn.rs
+    pub fn begin(&self) -> Txn {
+        Txn::new(Arc::clone(&self.shared))
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        let inner = self.shared.inner.lock().unwrap();
+        inner.map.get(key).is_some_and(|entry| !entry.is_expired(self.shared.now()))
+    }
+
+    // The keys in the map. Expired keys the timer hasn't got to yet are counted too, for the few milliseconds that takes
+    pub fn len(&self) -> usize {
+        self.shared.inner.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The bytes of the keys and values, what the memory budget is compared with
+    pub fn memory_used(&self) -> usize {
+        self.shared.inner.lock().unwrap().size
+    }
+
+    // A copy of the entries with a key in the range, in key order. It is a copy so that the lock isn't held while the caller
+    // looks at them: a query that decodes every record would otherwise keep all the writers waiting.
+    // A scan isn't a use of the keys as far as the eviction policy goes. A range that can't hold any key, like "b".."a",
+    // is empty.
+    pub fn scan<R: RangeBounds<str>>(&self, range: R) -> Vec<(String, Vec<u8>)> {
+        self.entries(range.start_bound(), range.end_bound(), "", usize::MAX)
+    }
+
+    // Every key that starts with the prefix. They are the keys from the prefix itself up to the first one that doesn't match.
+    pub fn scan_prefix(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        self.entries(Bound::Included(prefix), Bound::Unbounded, prefix, usize::MAX)
+    }
+
+    // The next page of a scan over the range: at most count entries, from the cursor on (see cursor.rs).
+    // The lock is held for one page at a time, so paging through a big store doesn't keep the writers out for long
+    pub fn scan_page<R: RangeBounds<str>>(&self, range: R, cursor: &Cursor, count: usize) -> Page {
+        self.page(range.start_bound(), range.end_bound(), "", cursor, count)
+    }
+
+    pub fn scan_prefix_page(&self, prefix: &str, cursor: &Cursor, count: usize) -> Page {
+        self.page(Bound::Included(prefix), Bound::Unbounded, prefix, cursor, count)
+    }
+
+    // One more entry than the page holds says whether there is a next page. A count of 0 would never get anywhere, so it is 1
+    fn page(&self, start: Bound<&str>, end: Bound<&str>, prefix: &str, cursor: &Cursor, count: usize) -> Page {
+        let count = count.max(1);
+        let mut entries = self.entries(cursor.resume(start), end, prefix, count + 1);
+        let next = match entries.len() > count {
+            true => {
+                entries.truncate(count);
+                Some(Cursor::after(&entries[count - 1].0))
+            }
+            false => None,
+        };
+        Page { entries, next }
+    }
+
+    // Up to limit entries in the range that start with the prefix
+    fn entries(&self, start: Bound<&str>, end: Bound<&str>, prefix: &str, limit: usize) -> Vec<(String, Vec<u8>)> {
+        if is_empty_range(start, end) {
+            return Vec::new();
+        }
+        let now = self.shared.now();
+        let inner = self.shared.inner.lock().unwrap();
+        inner
+            .map
+            .range::<str, _>((start, end))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .take(limit)
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    pub fn put_record(&self, key: &str, record: &Record) {
+        self.set(key, record.encode());
+    }
+
+    // Stores the record under a new key, the prefix and a Uuid (projects/multithreaded_webserver/src/ids.rs), and returns
+    // the key. A counter would need to be kept somewhere, and "user:10" sorts before "user:2". A Uuid starts with the
+    // millisecond it was made in, so scan_prefix() finds the records in the order they were added
+    pub fn add_record(&self, prefix: &str, record: &Record) -> String {
+        let key = format!("{prefix}{}", Uuid::new());
+        self.put_record(&key, record);
+        key
+    }
+
+    // None when there is nothing under the key, and an error when there is something that isn't a record
+    pub fn get_record(&self, key: &str) -> Option<Result<Record, DecodeError>> {
+        self.get(key).map(|bytes| Record::decode(&bytes))
+    }
+
+    // The timer holds a Weak, so a store that was dropped isn't kept alive by its timers. It fires at the very Instant the
+    // entry expires: one a microsecond earlier would find the key not expired yet, and leave it there.
+    fn schedule_expiry(&self, key: &str, at: Instant) -> TimerToken {
+        let shared: Weak<Shared> = Arc::downgrade(&self.shared);
+        let key = key.to_string();
+        let timers = self.timers.get_or_init(|| TimerWheel::with_clock(Arc::clone(&self.shared.clock)));
+        timers.schedule_at(Deadline::at(at), move || {
+            if let Some(shared) = shared.upgrade() {
+                shared.expire_if_due(&key);
+            }
+        })
+    }
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::new()
+    }
+}
+
+impl fmt::Debug for Store {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.shared.inner.lock().unwrap();
+        f.debug_struct("Store").field("len", &inner.map.len()).field("size", &inner.size).finish()
+    }
+}
+
+impl Shared {
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn count(&self, counter: &str) {
+        if let Some(metrics) = &self.config.metrics {
+            metrics.add(counter, 1);
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock().unwrap();
+        let value = inner.live_entry(key, self.now(), &self.config)?.value.clone();
+        inner.touch(key, &self.config);
+        Some(value)
+    }
+
+    fn set(&self, key: &str, value: Vec<u8>, expires: Option<(Instant, TimerToken)>) -> Option<Vec<u8>> {
+        self.write(&mut self.inner.lock().unwrap(), key, Some(value), expires)
+    }
+
+    // Every set and delete comes through here, those of a committing transaction too. It returns the value that was there.
+    // While a transaction is open, the value that was there is kept in the history with the version of this write,
+    // for the transactions that have to go on seeing it.
+    pub(crate) fn write(
+        &self,
+        inner: &mut Inner,
+        key: &str,
+        value: Option<Vec<u8>>,
+        expires: Option<(Instant, TimerToken)>,
+    ) -> Option<Vec<u8>> {
+        let old = match inner.remove(key) {
+            Some(old) if old.is_expired(self.now()) => {
+                self.count("kvstore_expirations");
+                None
+            }
+            old => old.map(|old| old.value),
+        };
+        if old.is_some() || value.is_some() {
+            inner.version += 1;
+            if !inner.snapshots.is_empty() {
+                let version = inner.version;
+                inner.history.entry(key.to_string()).or_default().push((version, old.clone()));
+            }
+        }
+
+        let Some(value) = value else { return old };
+        inner.size += key.len() + value.len();
+        inner.map.insert(key.to_string(), Entry { value, expires, used: 0, hits: 0 });
+        inner.touch(key, &self.config);
+
+        if let Some(budget) = self.config.memory_budget {
+            while inner.size > budget {
+                let Some(victim) = inner.victim(key, self.config.eviction) else { break };
+                inner.remove(&victim);
+                self.count("kvstore_evictions");
+            }
+        }
+        old
+    }
+
+    // Called by the timer. The key may have been set again since, without a TTL or with a later one
+    fn expire_if_due(&self, key: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.map.get(key).is_some_and(|entry| entry.is_expired(self.now())) {
+            inner.remove(key);
+            self.count("kvstore_expirations");
+        }
+    }
+}
+
+impl Inner {
+    pub(crate) fn open_snapshot(&mut self) -> u64 {
+        *self.snapshots.entry(self.version).or_default() += 1;
+        self.version
+    }
+
+    // Forgets the snapshot, and the history nobody needs anymore: a transaction on snapshot s only ever looks at the
+    // writes made after s
+    pub(crate) fn close_snapshot(&mut self, snapshot: u64) {
+        if let Some(open) = self.snapshots.get_mut(&snapshot) {
+            *open -= 1;
+            if *open == 0 {
+                self.snapshots.remove(&snapshot);
+            }
+        }
+        match self.snapshots.keys().next().copied() {
+            Some(oldest) => self.history.retain(|_, writes| {
+                writes.retain(|(version, _)| *version > oldest);
+                !writes.is_empty()
+            }),
+            None => self.history.clear(),
+        }
+    }
+
+    // The value as it was at the snapshot: the one the first write after the snapshot replaced, or the one there now
+    // if nothing wrote the key since and it hasn't expired by now
+    pub(crate) fn read_at(&self, key: &str, snapshot: u64, now: Instant) -> Option<Vec<u8>> {
+        let replaced = self.history.get(key).and_then(|writes| writes.iter().find(|(version, _)| *version > snapshot));
+        match replaced {
+            Some((_, value)) => value.clone(),
+            None => self.map.get(key).filter(|entry| !entry.is_expired(now)).map(|entry| entry.value.clone()),
+        }
+    }
+
+    // Whether anything wrote the key after the snapshot
+    pub(crate) fn written_since(&self, key: &str, snapshot: u64) -> bool {
+        self.history.get(key).and_then(|writes| writes.last()).is_some_and(|(version, _)| *version > snapshot)
+    }
+
+    // The entry, unless it expired: then it goes now, without waiting for the timer
+    fn live_entry(&mut self, key: &str, now: Instant, config: &StoreConfig) -> Option<&mut Entry> {
+        if self.map.get(key)?.is_expired(now) {
+            self.remove(key);
+            if let Some(metrics) = &config.metrics {
+                metrics.add("kvstore_expirations", 1);
+            }
+            return None;
+        }
+        self.map.get_mut(key)
+    }
+
+    fn touch(&mut self, key: &str, config: &StoreConfig) {
+        if config.memory_budget.is_none() {
+            return;
+        }
+        self.tick += 1;
+        let tick = self.tick;
+        let Some(entry) = self.map.get_mut(key) else { return };
+        let (used, hits) = (entry.used, entry.hits);
+        entry.used = tick;
+        entry.hits += 1;
+        match config.eviction {
+            Eviction::Lru => {
+                self.recency.remove(&used);
+                self.recency.insert(tick, key.to_string());
+            }
+            Eviction::Lfu => {
+                self.frequency.remove(&(hits, used, key.to_string()));
+                self.frequency.insert((hits + 1, tick, key.to_string()));
+            }
+            Eviction::Random => {}
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.map.remove(key)?;
+        self.size -= key.len() + entry.value.len();
+        self.recency.remove(&entry.used);
+        self.frequency.remove(&(entry.hits, entry.used, key.to_string()));
+        if let Some((_, token)) = &entry.expires {
+            token.cancel();
+        }
+        Some(entry)
+    }
+
+    // The key the policy picks to go next, never the one that is being set. None when that one is all there is
+    fn victim(&mut self, keep: &str, eviction: Eviction) -> Option<String> {
+        match eviction {
+            Eviction::Lru => self.recency.values().find(|key| *key != keep).cloned(),
+            Eviction::Lfu => self.frequency.iter().map(|(_, _, key)| key).find(|key| *key != keep).cloned(),
+            // Walking to the n-th key is O(n), but an eviction is rare next to the gets and sets that don't evict
+            Eviction::Random => {
+                let others = self.map.len().checked_sub(1).filter(|&n| n > 0)?;
+                let n = self.rng.gen_range(0..others);
+                self.map.keys().filter(|key| *key != keep).nth(n).cloned()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use common::clock::FakeClock;
+
+    use super::*;
+
+    #[test]
+    fn set_get_delete() {
+        let store = Store::new();
+        assert!(store.is_empty());
+        assert_eq!(None, store.set("a", b"1".to_vec()));
+        assert_eq!(Some(b"1".to_vec()), store.set("a", b"2".to_vec()));
+        assert_eq!(Some(b"2".to_vec()), store.get("a"));
+        assert!(store.contains("a"));
+        assert_eq!(Some(b"2".to_vec()), store.delete("a"));
+        assert_eq!(None, store.get("a"));
+        assert_eq!(0, store.len());
+    }
+
+    #[test]
+    fn scans_are_in_key_order() {
+        let store = Store::new();
+        for key in ["user:2", "post:1", "user:1", "user:10", "users"] {
+            store.set(key, key.as_bytes().to_vec());
+        }
+        let keys = |entries: Vec<(String, Vec<u8>)>| entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(vec!["user:1", "user:10", "user:2"], keys(store.scan_prefix("user:")));
+        assert_eq!(vec!["post:1", "user:1"], keys(store.scan((Bound::Included("a"), Bound::Excluded("user:10")))));
+        assert_eq!(5, store.scan(..).len());
+
+        store.put_record("user:1", &Record::new().with("name", "ferris"));
+        assert_eq!(Some(Ok(Record::new().with("name", "ferris"))), store.get_record("user:1"));
+        assert!(store.get_record("post:1").unwrap().is_err());
+        assert_eq!(None, store.get_record("nobody"));
+    }
+
+    #[test]
+    fn added_records_get_keys_in_the_order_they_were_added() {
+        let store = Store::new();
+        let keys: Vec<String> = (0..3)
+            .map(|i| {
+                // Two records in the same millisecond are ordered by their random bits
+                thread::sleep(Duration::from_millis(2));
+                store.add_record("post:", &Record::new().with("number", i))
+            })
+            .collect();
+        let scanned: Vec<String> = store.scan_prefix("post:").into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, scanned);
+        assert!(keys[0]["post:".len()..].parse::<Uuid>().is_ok(), "{}", keys[0]);
+        assert_eq!(Some(Ok(Record::new().with("number", 2))), store.get_record(&keys[2]));
+    }
+
+    #[test]
+    fn pages_return_every_key_once() {
+        let store = Store::new();
+        for i in 0..50 {
+            store.set(&format!("key:{i:02}"), Vec::new());
+        }
+        assert!(store.scan((Bound::Excluded("b"), Bound::Included("a"))).is_empty());
+
+        // Keys come and go between the pages: the ones there all along are seen exactly once
+        let mut seen = Vec::new();
+        let mut cursor = Cursor::start();
+        for round in 0.. {
+            let page = store.scan_prefix_page("key:", &cursor, 7);
+            assert!(page.entries.len() <= 7);
+            seen.extend(page.entries.into_iter().map(|(key, _)| key));
+            store.set(&format!("key:{round:02}x"), Vec::new());
+            store.delete(&format!("key:{:02}", 49 - round));
+            match page.next {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        let mut unique = seen.clone();
+        unique.dedup();
+        assert_eq!(seen, unique);
+        for i in 0..40 {
+            assert!(seen.contains(&format!("key:{i:02}")), "key:{i:02} is missing");
+        }
+
+        // A range page, resumed from a token, and the last page has no next
+        store.set("other", Vec::new());
+        let range = (Bound::Included("key:10"), Bound::Excluded("key:20"));
+        let page = store.scan_page(range, &Cursor::start(), 3);
+        assert_eq!(vec!["key:10", "key:11", "key:12"], page.entries.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>());
+        let cursor: Cursor = page.next_token().parse().unwrap();
+        let page = store.scan_page(range, &cursor, 100);
+        assert_eq!((7, true, "0".to_string()), (page.entries.len(), page.is_last(), page.next_token()));
+        assert!(store.scan_prefix_page("zzz", &Cursor::start(), 0).is_last());
+    }
+
+    // The timer thread runs by itself, so the test waits for it to get to the key, with a real deadline in case it never does
+    fn wait_for(what: impl Fn() -> bool) {
+        let deadline = Deadline::after(Duration::from_secs(5));
+        while !what() {
+            assert!(!deadline.expired(), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn keys_expire_lazily_and_in_the_background() {
+        let metrics = Arc::new(Metrics::new());
+        let clock = Arc::new(FakeClock::new());
+        let config = StoreConfig::new().metrics(Arc::clone(&metrics)).clock(Arc::clone(&clock) as Arc<dyn Clock>);
+        let store = Store::with_config(config);
+        store.set_with_ttl("session", b"abc".to_vec(), Duration::from_millis(30));
+        store.set_with_ttl("kept", b"1".to_vec(), Duration::from_millis(30));
+        store.set_with_ttl("longer", b"2".to_vec(), Duration::from_millis(30));
+        assert!(store.persist("kept"));
+        assert!(!store.persist("kept"));
+        assert!(store.expire("longer", Duration::from_secs(60)));
+        assert!(store.ttl("longer").unwrap() > Duration::from_secs(59));
+        assert_eq!(None, store.ttl("kept"));
+        assert_eq!(Some(b"abc".to_vec()), store.get("session"));
+
+        clock.advance(Duration::from_millis(29));
+        assert_eq!(Duration::from_millis(1), store.ttl("session").unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(None, store.get("session"));
+        assert_eq!(vec!["kept", "longer"], store.scan(..).into_iter().map(|(key, _)| key).collect::<Vec<_>>());
+        // Whichever of the timer and the get got there first, it was counted once
+        assert_eq!(1, metrics.counter("kvstore_expirations"));
+
+        // Without anyone asking, and a set without a TTL takes it off
+        store.set_with_ttl("a", Vec::new(), Duration::from_millis(10));
+        store.set_with_ttl("b", Vec::new(), Duration::from_millis(10));
+        store.set("b", Vec::new());
+        clock.advance(Duration::from_millis(10));
+        wait_for(|| store.len() == 3);
+        assert_eq!(2, metrics.counter("kvstore_expirations"));
+        assert!(!store.expire("a", Duration::from_secs(1)));
+    }
+
+    // Keys of one byte with values of nine make ten bytes each, so a budget of 30 holds three
+    fn cache(eviction: Eviction) -> (Store, Arc<Metrics>) {
+        let metrics = Arc::new(Metrics::new());
+        let config = StoreConfig::new().memory_budget(30).eviction(eviction).metrics(Arc::clone(&metrics)).seed(7);
+        (Store::with_config(config), metrics)
+    }
+
+    fn keys(store: &Store) -> String {
+        store.scan(..).into_iter().map(|(key, _)| key).collect()
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used() {
+        let (store, metrics) = cache(Eviction::Lru);
+        for key in ["a", "b", "c"] {
+            store.set(key, vec![0; 9]);
+        }
+        store.get("a");
+        store.set("d", vec![0; 9]);
+        assert_eq!("acd", keys(&store));
+        store.set("e", vec![0; 19]);
+        assert_eq!("de", keys(&store));
+        assert_eq!(30, store.memory_used());
+        assert_eq!(3, metrics.counter("kvstore_evictions"));
+
+        // A value bigger than the whole budget stays, alone
+        store.set("f", vec![0; 99]);
+        assert_eq!("f", keys(&store));
+    }
+
+    #[test]
+    fn lfu_evicts_the_least_frequently_used() {
+        let (store, _) = cache(Eviction::Lfu);
+        for key in ["a", "b", "c"] {
+            store.set(key, vec![0; 9]);
+        }
+        for _ in 0..3 {
+            store.get("a");
+            store.get("b");
+        }
+        store.get("c");
+        store.set("d", vec![0; 9]);
+        assert_eq!("abd", keys(&store));
+        // d was used once, c is gone, so d goes next: a new key has to earn its place
+        store.set("e", vec![0; 9]);
+        assert_eq!("abe", keys(&store));
+    }
+
+    #[test]
+    fn random_eviction_stays_within_budget() {
+        let (store, metrics) = cache(Eviction::Random);
+        for i in 0..100 {
+            store.set(&format!("{i:02}"), vec![0; 8]);
+            assert!(store.memory_used() <= 30);
+        }
+        assert_eq!(3, store.len());
+        assert_eq!(97, metrics.counter("kvstore_evictions"));
+        // The key just set is never the victim
+        assert!(store.contains("99"));
+    }
+}
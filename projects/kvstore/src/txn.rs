@@ -0,0 +1,219 @@
+// Transactions
+
+// Moving money from one account to another is two writes, and nobody may see one without the other, or make a change in
+// between that one of them overwrites. A transaction groups them:
+
+    // let mut txn = store.begin();
+    // let from = balance(txn.get("account:1")) - 10;
+    // let to = balance(txn.get("account:2")) + 10;
+    // txn.set("account:1", from);
+    // txn.set("account:2", to);
+    // txn.commit()?;
+
+// The isolation is snapshot isolation, what PostgreSQL calls REPEATABLE READ:
+    // 1. A transaction reads the store as it was when begin() was called, its snapshot, plus its own writes. Writes
+    //    committed by others since then don't show, so it never sees half of someone else's transaction.
+    // 2. Its writes stay in the Txn until commit(), which makes all of them at once, under the store's lock.
+    // 3. commit() fails with a Conflict when someone else wrote one of the same keys since the snapshot: of two transactions
+    //    that both read a balance and write it back, one would otherwise silently undo the other. The first to commit wins,
+    //    and the other one starts over from a new snapshot.
+// Two transactions that read the same keys but write different ones can both commit, even when each would have written
+// something else had it seen the other's write. That is write skew, and preventing it too takes full serializability.
+
+// Nothing reaches the store before commit(), so rolling back is just not committing. The Txn keeps its state in a ScopeGuard
+// (projects/multithreaded_webserver/src/guard.rs) that closes the snapshot when the Txn goes away, whether it committed,
+// was dropped, returned early with ? or panicked: an open snapshot makes the store keep history for it.
+
+use std::{collections::BTreeMap, fmt, mem, sync::Arc};
+
+use multithreaded_webserver::guard::ScopeGuard;
+
+use crate::store::Shared;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxnError {
+    Conflict(String),
+}
+
+impl fmt::Display for TxnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxnError::Conflict(key) => write!(f, "{key} was written by someone else since the transaction began"),
+        }
+    }
+}
+
+impl std::error::Error for TxnError {}
+
+pub(crate) struct State {
+    shared: Arc<Shared>,
+    snapshot: u64,
+    // None is a delete
+    writes: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+fn close(state: State) {
+    state.shared.inner.lock().unwrap().close_snapshot(state.snapshot);
+}
+
+pub struct Txn {
+    state: ScopeGuard<State, fn(State)>,
+}
+
+impl Txn {
+    pub(crate) fn new(shared: Arc<Shared>) -> Txn {
+        let snapshot = shared.inner.lock().unwrap().open_snapshot();
+        let state = State { shared, snapshot, writes: BTreeMap::new() };
+        Txn { state: ScopeGuard::new(state, close as fn(State)) }
+    }
+
+    // The transaction's own write of the key if it made one, or the value at the snapshot
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        match self.state.writes.get(key) {
+            Some(value) => value.clone(),
+            None => {
+                let now = self.state.shared.now();
+                self.state.shared.inner.lock().unwrap().read_at(key, self.state.snapshot, now)
+            }
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: Vec<u8>) {
+        self.state.writes.insert(key.to_string(), Some(value));
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        self.state.writes.insert(key.to_string(), None);
+    }
+
+    // The keys written so far, in order
+    pub fn written(&self) -> impl Iterator<Item = &str> {
+        self.state.writes.keys().map(String::as_str)
+    }
+
+    // Makes every write, or none of them if another transaction wrote one of the keys first
+    pub fn commit(mut self) -> Result<(), TxnError> {
+        let state = &mut *self.state;
+        let shared = Arc::clone(&state.shared);
+        let mut inner = shared.inner.lock().unwrap();
+        if let Some(key) = state.writes.keys().find(|key| inner.written_since(key, state.snapshot)) {
+            return Err(TxnError::Conflict(key.clone()));
+        }
+        for (key, value) in mem::take(&mut state.writes) {
+            shared.write(&mut inner, &key, value, None);
+        }
+        Ok(())
+    }
+
+    // The same as dropping it, but says so
+    pub fn rollback(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::store::Store;
+
+    use super::*;
+
+    fn value(bytes: Option<Vec<u8>>) -> Option<String> {
+        bytes.map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn reads_see_the_snapshot_and_own_writes() {
+        let store = Store::new();
+        store.set("a", b"1".to_vec());
+        store.set("gone", b"x".to_vec());
+
+        let mut txn = store.begin();
+        store.set("a", b"2".to_vec());
+        store.set("new", b"y".to_vec());
+        store.delete("gone");
+        assert_eq!(Some("1".to_string()), value(txn.get("a")));
+        assert_eq!(None, value(txn.get("new")));
+        assert_eq!(Some("x".to_string()), value(txn.get("gone")));
+
+        txn.set("b", b"3".to_vec());
+        txn.delete("a");
+        assert_eq!((Some("3".to_string()), None), (value(txn.get("b")), value(txn.get("a"))));
+        assert_eq!(None, store.get("b"));
+        assert_eq!(vec!["a", "b"], txn.written().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn the_first_to_commit_wins() {
+        let store = Store::new();
+        store.set("k", b"0".to_vec());
+        let mut first = store.begin();
+        let mut second = store.begin();
+        first.set("k", b"1".to_vec());
+        first.set("other", b"1".to_vec());
+        second.set("k", b"2".to_vec());
+        second.set("mine", b"2".to_vec());
+
+        assert_eq!(Ok(()), first.commit());
+        assert_eq!(Err(TxnError::Conflict("k".to_string())), second.commit());
+        assert_eq!((Some(b"1".to_vec()), None), (store.get("k"), store.get("mine")));
+
+        // A write outside of any transaction counts too, and disjoint keys don't conflict
+        let mut txn = store.begin();
+        txn.set("k", b"3".to_vec());
+        store.set("k", b"4".to_vec());
+        assert!(txn.commit().is_err());
+        let mut txn = store.begin();
+        txn.set("x", Vec::new());
+        store.set("k", b"5".to_vec());
+        assert_eq!(Ok(()), txn.commit());
+    }
+
+    #[test]
+    fn dropping_rolls_back() {
+        let store = Store::new();
+        {
+            let mut txn = store.begin();
+            txn.set("a", b"1".to_vec());
+            store.set("b", b"2".to_vec());
+            store.set("b", b"3".to_vec());
+        }
+        assert_eq!(None, store.get("a"));
+
+        let old = store.begin();
+        let newer = store.begin();
+        store.set("b", b"4".to_vec());
+        old.rollback();
+        assert_eq!(Some(b"3".to_vec()), newer.get("b"));
+        drop(newer);
+        store.set("b", b"5".to_vec());
+        assert_eq!(Some(b"5".to_vec()), store.begin().get("b"));
+    }
+
+    #[test]
+    fn increments_are_not_lost() {
+        let store = Arc::new(Store::new());
+        store.set("counter", b"0".to_vec());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        // Start over on a conflict, from a new snapshot
+                        loop {
+                            let mut txn = store.begin();
+                            let n: u64 = value(txn.get("counter")).unwrap().parse().unwrap();
+                            txn.set("counter", (n + 1).to_string().into_bytes());
+                            if txn.commit().is_ok() {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(Some("200".to_string()), value(store.get("counter")));
+    }
+}
@@ -0,0 +1,242 @@
+// Values and Records
+
+// A record is a list of named fields, like a row of a table, except that two records under different keys don't need to have
+// the same fields. Each field holds a Value, one of the few types a query can compare and compute with.
+// Records go into the store as bytes written by the codec crate, and come out again through Record::decode.
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    io::{self, Write},
+};
+
+use codec::{DecodeError, Deserialize, Serialize};
+use json::{JsonWriter, ToJson};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Text(_) => "text",
+        }
+    }
+
+    // The order of two values of the same kind. An Int and a Float compare as numbers, anything else can't be ordered
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Float(x) => write!(f, "{x}"),
+            Value::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+// In JSON a Value is the plain value, 7 and not {"Int": 7} like #[derive(ToJson)] would write it. A Float that isn't
+// finite becomes null, JSON has no NaN
+impl ToJson for Value {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        match self {
+            Value::Null => json.null(),
+            Value::Bool(b) => json.bool(*b),
+            Value::Int(i) => json.i64(*i),
+            Value::Float(x) => json.f64(*x),
+            Value::Text(s) => json.string(s),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+// So that a literal like 7 can be a value without a suffix
+impl From<i32> for Value {
+    fn from(i: i32) -> Value {
+        Value::Int(i as i64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Value {
+        Value::Float(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Text(s)
+    }
+}
+
+// Typed Values
+
+// A Row of a query holds Values, and Row::get turns one into the Rust type the caller asked for, when it is one.
+// An Int is also a good f64, and Option<T> takes a Null as None, so a field that may be missing can be read without an error.
+
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Option<Value> {
+        Some(value.clone())
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<bool> {
+        match value {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Option<i64> {
+        match value {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Option<f64> {
+        match value {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<String> {
+        match value {
+            Value::Text(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Option<Option<T>> {
+        match value {
+            Value::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+// Records
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    fields: Vec<(String, Value)>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Record::default()
+    }
+
+    // Builds a record one field at a time: Record::new().with("name", "ferris").with("age", 7)
+    pub fn with<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    // Replaces the value of the field, or adds it at the end
+    pub fn set<V: Into<Value>>(&mut self, name: &str, value: V) {
+        let value = value.into();
+        match self.fields.iter_mut().find(|(field, _)| field == name) {
+            Some((_, old)) => *old = value,
+            None => self.fields.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(field, _)| field == name).map(|(_, value)| value)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        codec::to_bytes(self)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Record, DecodeError> {
+        codec::from_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip_through_the_codec() {
+        let mut record = Record::new().with("name", "ferris").with("age", 7).with("weight", 0.5).with("admin", false);
+        record.set("age", 8);
+        assert_eq!(Some(&Value::Int(8)), record.get("age"));
+        assert_eq!(vec!["name", "age", "weight", "admin"], record.fields().map(|(name, _)| name).collect::<Vec<_>>());
+        assert_eq!(Ok(record.clone()), Record::decode(&record.encode()));
+        assert!(Record::decode(&[9]).is_err());
+    }
+
+    #[test]
+    fn comparing_and_converting() {
+        assert_eq!(Some(Ordering::Less), Value::Int(1).compare(&Value::Float(1.5)));
+        assert_eq!(Some(Ordering::Greater), Value::from("b").compare(&Value::from("a")));
+        assert_eq!(None, Value::Int(1).compare(&Value::from("1")));
+        assert_eq!(Some(2.0), f64::from_value(&Value::Int(2)));
+        assert_eq!(None, i64::from_value(&Value::Float(2.0)));
+        assert_eq!(Some(None), Option::<String>::from_value(&Value::Null));
+        assert_eq!(Some(Some(3)), Option::<i64>::from_value(&Value::Int(3)));
+    }
+}
@@ -0,0 +1,252 @@
+// The Write-Ahead Log
+
+// The Store lives in memory, so everything in it is gone when the process stops, or crashes. The classic fix is a write-ahead log:
+// before a change is made in memory, it is appended to a file, and on the next start the file is read back to redo the changes.
+// A change counts as committed once its append returned, and a committed change must survive a crash.
+// A crash can stop an append anywhere though, so the log is written to be read back safely:
+    // 1. Every change is a frame: the length of the payload (4 bytes), the CRC-32 of the payload (4 bytes), and the payload,
+    //    an Op encoded with the codec crate. All the numbers are little-endian.
+    // 2. replay() reads frames until one is cut short, or its checksum doesn't match, or it doesn't decode. That frame and
+    //    everything after it is the torn tail of an append that never returned, and is dropped.
+    // 3. recover() truncates the file to the frames that were good, so that the next append doesn't land after the garbage.
+// A log only grows, and replaying a long one is slow. A snapshot is the whole map written to one file, after which the log
+// can start over empty: that is compaction, see durable.rs. A snapshot is replaced like minigrep's --in-place edits
+// (projects/minigrep/src/replace.rs): written to a temporary file, synced, and renamed over the old one.
+// The files are opened through a FileSystem (projects/common/src/vfs.rs), RealFs unless a test hands the LogEngine a MemFs.
+// Both are read whole when the LogEngine opens, with the mmap feature (on by default) through a memory map, the
+// FileBytes (projects/common/src/mmap.rs): the pages come from the page cache without a copy into a Vec first, which is
+// memory a big snapshot would need twice. What mapping risks is a file that changes while it's mapped. These are the
+// engine's own files, written by nobody while open() reads them, and the log's map is gone before recover() cuts it short.
+// Two servers on one directory would break a lot more than that.
+
+use std::{
+    io::{self, ErrorKind, Write},
+    path::Path,
+};
+
+use codec::{DecodeError, Deserialize, Serialize};
+use common::{
+    mmap::FileBytes,
+    vfs::{FileSystem, WriteFile},
+};
+use std_collections::hashing::crc32;
+
+// The largest payload replay() believes. A torn length can be anything, and this keeps a garbage one from allocating gigabytes
+pub const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+const HEADER: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    Set(String, Vec<u8>),
+    Delete(String),
+}
+
+pub fn frame(op: &Op) -> Vec<u8> {
+    let payload = codec::to_bytes(op);
+    let mut frame = Vec::with_capacity(HEADER + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+// Appends frames to any writer: a file of a FileSystem for real, a FlakyWriter that fails halfway in the tests
+pub struct Wal<W> {
+    out: W,
+    len: u64,
+}
+
+impl<W: Write> Wal<W> {
+    // len is how many bytes the writer already has, the size of the file it appends to
+    pub fn new(out: W, len: u64) -> Wal<W> {
+        Wal { out, len }
+    }
+
+    // Writes the whole frame and flushes it. When this returns Ok the op is committed, as far as this writer goes:
+    // for a File, see sync() as well
+    pub fn append(&mut self, op: &Op) -> io::Result<()> {
+        let frame = frame(op);
+        self.out.write_all(&frame)?;
+        self.out.flush()?;
+        self.len += frame.len() as u64;
+        Ok(())
+    }
+
+    // The bytes appended so far, the ones that were there before included
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl Wal<Box<dyn WriteFile>> {
+    // Opens the log for appending, creating it if it isn't there
+    pub fn open(fs: &dyn FileSystem, path: &Path) -> io::Result<Wal<Box<dyn WriteFile>>> {
+        let file = fs.append(path)?;
+        let len = fs.metadata(path)?.len;
+        Ok(Wal::new(file, len))
+    }
+
+    // append() flushes the frame to the operating system, which survives the process crashing but not the machine.
+    // sync waits until it is on the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.out.sync()
+    }
+}
+
+// Replay
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    pub ops: Vec<Op>,
+    // The bytes of the frames that were good. Everything after them is the torn tail
+    pub valid_len: usize,
+    pub total_len: usize,
+}
+
+impl Replay {
+    pub fn is_torn(&self) -> bool {
+        self.valid_len < self.total_len
+    }
+}
+
+pub fn replay(bytes: &[u8]) -> Replay {
+    let mut ops = Vec::new();
+    let mut at = 0;
+    while let Some(header) = bytes.get(at..at + HEADER) {
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if len > MAX_FRAME {
+            break;
+        }
+        let Some(payload) = bytes.get(at + HEADER..at + HEADER + len) else { break };
+        if crc32(payload) != checksum {
+            break;
+        }
+        let Ok(op) = codec::from_bytes(payload) else { break };
+        ops.push(op);
+        at += HEADER + len;
+    }
+    Replay { ops, valid_len: at, total_len: bytes.len() }
+}
+
+// The bytes of the file, mapped or read, and None when there is no file
+fn open_bytes(fs: &dyn FileSystem, path: &Path) -> io::Result<Option<FileBytes>> {
+    match fs.map(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Reads the log at path and cuts off its torn tail. A log that isn't there is an empty one
+pub fn recover(fs: &dyn FileSystem, path: &Path) -> io::Result<Replay> {
+    // The map ends with the match, before the file is truncated
+    let replay = match open_bytes(fs, path)? {
+        Some(bytes) => replay(&bytes),
+        None => replay(&[]),
+    };
+    if replay.is_torn() {
+        let mut file = fs.append(path)?;
+        file.set_len(replay.valid_len as u64)?;
+        file.sync()?;
+    }
+    Ok(replay)
+}
+
+// Snapshots
+
+// A snapshot is a magic number, the CRC-32 of the body, and the body: every entry of the store, encoded with the codec.
+// Unlike a log, a snapshot is never torn, the rename makes it appear whole or not at all. A snapshot that doesn't check out
+// was damaged on the disk, and loading it is an error rather than quietly starting empty.
+const MAGIC: &[u8; 4] = b"KVS1";
+
+pub fn write_snapshot(fs: &dyn FileSystem, path: &Path, entries: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let body = codec::to_bytes(entries);
+    let temp = path.with_extension("tmp");
+    let result = (|| {
+        let mut file = fs.create(&temp)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&crc32(&body).to_le_bytes())?;
+        file.write_all(&body)?;
+        // Without the sync the rename could reach the disk before the data does, and a crash would leave an empty snapshot
+        file.sync()?;
+        fs.rename(&temp, path)
+    })();
+    if result.is_err() {
+        // The error that matters is the one above, a temporary file that can't be removed either is left for the next try
+        let _ = fs.remove_file(&temp);
+    }
+    result
+}
+
+// The entries of the snapshot at path, and none if there is no snapshot yet
+pub fn read_snapshot(fs: &dyn FileSystem, path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let Some(bytes) = open_bytes(fs, path)? else { return Ok(Vec::new()) };
+    let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, format!("{}: {message}", path.display()));
+    if bytes.len() < 8 || &bytes[..4] != MAGIC {
+        return Err(invalid("not a snapshot"));
+    }
+    let body = &bytes[8..];
+    if crc32(body) != u32::from_le_bytes(bytes[4..8].try_into().unwrap()) {
+        return Err(invalid("the snapshot's checksum doesn't match"));
+    }
+    codec::from_bytes(body).map_err(|e| invalid(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops() -> Vec<Op> {
+        vec![Op::Set("a".to_string(), b"1".to_vec()), Op::Set("b".to_string(), Vec::new()), Op::Delete("a".to_string())]
+    }
+
+    #[test]
+    fn frames_replay_in_order() {
+        let mut wal = Wal::new(Vec::new(), 0);
+        for op in ops() {
+            wal.append(&op).unwrap();
+        }
+        let bytes = wal.into_inner();
+        let replay = replay(&bytes);
+        assert_eq!(ops(), replay.ops);
+        assert!(!replay.is_torn());
+        assert_eq!(bytes.len(), replay.valid_len);
+    }
+
+    #[test]
+    fn the_torn_tail_is_dropped() {
+        let good: Vec<u8> = ops().iter().take(2).flat_map(frame).collect();
+        let last = frame(&ops()[2]);
+
+        // Cut short, in the header and in the payload
+        for cut in [1, HEADER, last.len() - 1] {
+            let bytes = [&good[..], &last[..cut]].concat();
+            assert_eq!((2, good.len()), (replay(&bytes).ops.len(), replay(&bytes).valid_len), "cut at {cut}");
+        }
+        // A flipped bit, and a length no frame has
+        let mut flipped = [&good[..], &last[..]].concat();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(2, replay(&flipped).ops.len());
+        let huge = [&good[..], &u32::MAX.to_le_bytes(), &[0; 4]].concat();
+        assert_eq!(2, replay(&huge).ops.len());
+        assert!(replay(&huge).is_torn());
+    }
+}
@@ -0,0 +1,32 @@
+// The server as a daemon: the command returns once it runs in the background, a second one refuses to start next to it,
+// and SIGTERM stops it through the hooks, the last of which removes the PID file.
+#![cfg(unix)]
+
+use std::{fs, process::Command, thread, time::Duration};
+
+use test_support::{cargo_bin, TempDir};
+
+#[test]
+fn daemonizes_and_stops_on_sigterm() {
+    let dir = TempDir::new();
+    let (pid_file, log_file) = (dir.child("kvstore.pid"), dir.child("kvstore.log"));
+    let daemon = ["--daemon", "--pid-file", "kvstore.pid", "--log-file", "kvstore.log", "--addr", "127.0.0.1:0"];
+    cargo_bin!("kvstore").args(daemon).current_dir(dir.path()).assert().success().stdout("");
+
+    let pid = fs::read_to_string(&pid_file).unwrap().trim().to_string();
+    cargo_bin!("kvstore").args(daemon).current_dir(dir.path()).assert().code(1).stderr_contains(&pid);
+
+    let killed = Command::new("kill").args(["-TERM", &pid]).status().unwrap();
+    assert!(killed.success());
+    // The PID file goes with the last hook, and the goodbye is printed right after them
+    let stopped = || !pid_file.exists() && fs::read_to_string(&log_file).unwrap().contains("bye");
+    for _ in 0..100 {
+        if stopped() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let log = fs::read_to_string(&log_file).unwrap();
+    assert!(stopped(), "the daemon is still running: {log}");
+    assert!(log.contains(&format!("as process {pid}")) && log.contains("keys were forgotten, bye"), "{log}");
+}
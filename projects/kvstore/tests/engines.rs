@@ -0,0 +1,180 @@
+// The same tests for every StorageEngine. Each test is a function of the EngineKind, and the engines! macro at the bottom
+// makes a #[test] of it for each kind, so a failure names the engine it failed with: memory::scans_respect_the_bounds.
+// What an engine promises is in the trait (src/engine.rs), and the persistent ones also promise to still have it all
+// after they are opened again.
+
+use std::{collections::BTreeMap, ops::Bound, sync::Arc, thread};
+
+use common::progress::Tracker;
+use kvstore::{DurableStore, EngineKind, StorageEngine};
+use test_support::TempDir;
+
+fn open(kind: EngineKind, dir: &TempDir) -> Box<dyn StorageEngine> {
+    kind.open(dir.path()).unwrap()
+}
+
+fn everything(engine: &dyn StorageEngine) -> Vec<(String, Vec<u8>)> {
+    engine.scan(Bound::Unbounded, Bound::Unbounded).unwrap()
+}
+
+fn set_get_delete(kind: EngineKind) {
+    let dir = TempDir::new();
+    let engine = open(kind, &dir);
+    assert_eq!(kind, engine.kind());
+    assert_eq!(None, engine.get("a").unwrap());
+    assert_eq!(None, engine.set("a", b"1").unwrap());
+    assert_eq!(Some(b"1".to_vec()), engine.set("a", b"22").unwrap());
+    assert_eq!(Some(b"22".to_vec()), engine.get("a").unwrap());
+    // The empty key and the empty value are like any other
+    assert_eq!(None, engine.set("", b"").unwrap());
+    assert_eq!(Some(Vec::new()), engine.get("").unwrap());
+    assert_eq!(Some(b"22".to_vec()), engine.delete("a").unwrap());
+    assert_eq!(None, engine.delete("a").unwrap());
+    assert_eq!(vec![(String::new(), Vec::new())], everything(engine.as_ref()));
+}
+
+fn scans_respect_the_bounds(kind: EngineKind) {
+    let dir = TempDir::new();
+    let engine = open(kind, &dir);
+    for key in ["b", "a", "d", "c", "cc"] {
+        engine.set(key, key.as_bytes()).unwrap();
+    }
+    let keys = |start, end| engine.scan(start, end).unwrap().into_iter().map(|(key, _)| key).collect::<Vec<_>>().join(" ");
+    assert_eq!("a b c cc d", keys(Bound::Unbounded, Bound::Unbounded));
+    assert_eq!("b c cc", keys(Bound::Included("b"), Bound::Excluded("d")));
+    assert_eq!("cc d", keys(Bound::Excluded("c"), Bound::Included("d")));
+    assert_eq!("a b", keys(Bound::Unbounded, Bound::Excluded("bb")));
+    assert_eq!("", keys(Bound::Included("d"), Bound::Excluded("c")));
+    assert_eq!("", keys(Bound::Excluded("c"), Bound::Excluded("c")));
+}
+
+// Enough keys for the B-tree to split its leaves and its root, in an order that isn't sorted, checked against a BTreeMap
+fn many_keys_match_a_model(kind: EngineKind) {
+    let dir = TempDir::new();
+    let engine = open(kind, &dir);
+    let mut model = BTreeMap::new();
+    for i in 0..1500usize {
+        let key = format!("user:{}", i * 7 % 1000);
+        match i % 5 {
+            4 => assert_eq!(model.remove(&key), engine.delete(&key).unwrap(), "{key}"),
+            _ => {
+                let value = vec![i as u8; i % 300];
+                assert_eq!(model.insert(key.clone(), value.clone()), engine.set(&key, &value).unwrap(), "{key}");
+            }
+        }
+    }
+    assert_eq!(model.clone().into_iter().collect::<Vec<_>>(), everything(engine.as_ref()));
+    for (key, value) in &model {
+        assert_eq!(Some(value), engine.get(key).unwrap().as_ref());
+    }
+
+    engine.compact(&Tracker::silent()).unwrap();
+    assert_eq!(model.into_iter().collect::<Vec<_>>(), everything(engine.as_ref()));
+}
+
+fn reopening_finds_everything(kind: EngineKind) {
+    let dir = TempDir::new();
+    let engine = open(kind, &dir);
+    for i in 0..300 {
+        engine.set(&format!("key:{i:03}"), format!("value {i}").as_bytes()).unwrap();
+    }
+    engine.delete("key:007").unwrap();
+    let before = everything(engine.as_ref());
+    drop(engine);
+
+    let engine = open(kind, &dir);
+    match kind.is_persistent() {
+        true => assert_eq!(before, everything(engine.as_ref())),
+        false => assert!(everything(engine.as_ref()).is_empty()),
+    }
+    // And again after compacting, which rewrites the files
+    engine.compact(&Tracker::silent()).unwrap();
+    engine.set("after", b"compaction").unwrap();
+    let before = everything(engine.as_ref());
+    drop(engine);
+    if kind.is_persistent() {
+        assert_eq!(before, everything(open(kind, &dir).as_ref()));
+    }
+}
+
+fn writers_can_share_an_engine(kind: EngineKind) {
+    let dir = TempDir::new();
+    let engine: Arc<dyn StorageEngine> = Arc::from(open(kind, &dir));
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                for i in 0..100 {
+                    engine.set(&format!("{i:03}:{t}"), &[t; 50]).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let entries = everything(engine.as_ref());
+    assert_eq!(400, entries.len());
+    assert!(entries.iter().all(|(key, value)| value == &[key.as_bytes()[4] - b'0'; 50]));
+}
+
+// A DurableStore on top of the engine: the Store it loads has what the engine has
+fn a_durable_store_loads_the_engine(kind: EngineKind) {
+    let dir = TempDir::new();
+    {
+        let engine = open(kind, &dir);
+        engine.set("user:1", b"ferris").unwrap();
+        engine.set("user:2", b"corro").unwrap();
+    }
+    let store = DurableStore::open_with(dir.path(), kind).unwrap();
+    let expected = if kind.is_persistent() { 2 } else { 0 };
+    assert_eq!(expected, store.store().scan_prefix("user:").len());
+    store.set("user:3", b"crab".to_vec()).unwrap();
+    assert_eq!(Some(b"crab".to_vec()), store.engine().get("user:3").unwrap());
+}
+
+macro_rules! engines {
+    ($($module:ident => $kind:expr),* $(,)?) => {
+        $(
+            mod $module {
+                use super::*;
+
+                #[test]
+                fn set_get_delete() {
+                    super::set_get_delete($kind);
+                }
+
+                #[test]
+                fn scans_respect_the_bounds() {
+                    super::scans_respect_the_bounds($kind);
+                }
+
+                #[test]
+                fn many_keys_match_a_model() {
+                    super::many_keys_match_a_model($kind);
+                }
+
+                #[test]
+                fn reopening_finds_everything() {
+                    super::reopening_finds_everything($kind);
+                }
+
+                #[test]
+                fn writers_can_share_an_engine() {
+                    super::writers_can_share_an_engine($kind);
+                }
+
+                #[test]
+                fn a_durable_store_loads_the_engine() {
+                    super::a_durable_store_loads_the_engine($kind);
+                }
+            }
+        )*
+    };
+}
+
+engines! {
+    memory => EngineKind::Memory,
+    log => EngineKind::Log,
+    btree => EngineKind::BTree,
+}
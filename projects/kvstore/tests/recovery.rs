@@ -0,0 +1,189 @@
+// Crashes at every byte of the log, and what the store remembers after each of them.
+// A FlakyWriter stands in for the disk: it takes a number of bytes and then fails every write, like a process that was killed.
+// The promise to check is the one of wal.rs: every op whose append returned Ok is there after recovery, and nothing else is.
+// The tests of the store at the bottom are the same for every persistent engine, the way tests/engines.rs does it: the
+// log crashes at a byte of its wal, and the B-tree at one of its page writes, with BTreeEngine::crash_after().
+
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+use common::progress::Tracker;
+use kvstore::{
+    durable::DurableStore,
+    engine::BTreeEngine,
+    wal::{self, Op, Wal},
+    EngineKind, StorageEngine,
+};
+use test_support::{FlakyWriter, Script, TempDir};
+
+// Sets and deletes of values of different sizes, the empty one and one longer than a varint byte included
+fn workload() -> Vec<Op> {
+    let mut ops = Vec::new();
+    for i in 0..12 {
+        let key = format!("key:{}", i % 5);
+        ops.push(match i % 4 {
+            3 => Op::Delete(key),
+            _ => Op::Set(key, vec![b'x'; i * 13]),
+        });
+    }
+    ops
+}
+
+// The map the ops leave behind
+fn model(ops: &[Op]) -> BTreeMap<String, Vec<u8>> {
+    let mut map = BTreeMap::new();
+    for op in ops {
+        match op {
+            Op::Set(key, value) => map.insert(key.clone(), value.clone()),
+            Op::Delete(key) => map.remove(key),
+        };
+    }
+    map
+}
+
+// Appends the workload until the writer fails. Returns the ops that were committed, and the bytes that made it to the "disk"
+fn crash_at(writer: FlakyWriter<Vec<u8>>) -> (Vec<Op>, Vec<u8>) {
+    let mut wal = Wal::new(writer, 0);
+    let mut committed = Vec::new();
+    for op in workload() {
+        match wal.append(&op) {
+            Ok(()) => committed.push(op),
+            Err(_) => break,
+        }
+    }
+    (committed, wal.into_inner().into_inner())
+}
+
+fn total_len() -> usize {
+    workload().iter().map(|op| wal::frame(op).len()).sum()
+}
+
+#[test]
+fn no_committed_op_is_lost_at_any_offset() {
+    for offset in 0..=total_len() {
+        let (committed, bytes) = crash_at(FlakyWriter::new(Vec::new()).fail_after(offset, ErrorKind::Other));
+        let replay = wal::replay(&bytes);
+        assert_eq!(committed, replay.ops, "crash after {offset} bytes");
+        assert_eq!(offset == total_len(), !replay.is_torn() && committed.len() == workload().len());
+    }
+}
+
+#[test]
+fn short_writes_and_interruptions_are_not_crashes() {
+    let script = Script::chunks([1, 3, 2]).interrupt().repeat(4);
+    let writer = FlakyWriter::new(Vec::new()).script(script).max_chunk(5).interrupt_every(7);
+    let (committed, bytes) = crash_at(writer);
+    assert_eq!(workload(), committed);
+    assert_eq!(workload(), wal::replay(&bytes).ops);
+}
+
+// Runs the workload on an engine of the kind in dir, and crashes it at the point, a byte of the log or a page write of the
+// B-tree. Returns the ops that were committed, all of them when the point is past the end
+fn crash_engine_at(kind: EngineKind, dir: &Path, point: usize) -> Vec<Op> {
+    match kind {
+        EngineKind::Log => {
+            let (committed, bytes) = crash_at(FlakyWriter::new(Vec::new()).fail_after(point, ErrorKind::Other));
+            fs::write(dir.join("wal"), bytes).unwrap();
+            committed
+        }
+        EngineKind::BTree => {
+            let engine = BTreeEngine::open(dir).unwrap();
+            engine.crash_after(point);
+            let mut committed = Vec::new();
+            for op in workload() {
+                let result = match &op {
+                    Op::Set(key, value) => engine.set(key, value),
+                    Op::Delete(key) => engine.delete(key),
+                };
+                match result {
+                    Ok(_) => committed.push(op),
+                    Err(_) => break,
+                }
+            }
+            committed
+        }
+        EngineKind::Memory => unreachable!("the memory engine has nothing to recover"),
+    }
+}
+
+// The same crashes with real files: the store opens on what the crash left, has exactly the committed ops, and what it
+// writes afterwards is read back on the next start
+fn the_store_recovers_from_a_crash(kind: EngineKind) {
+    // Bytes of the log are a lot more points than page writes, every 11th is enough
+    let step = if kind == EngineKind::Log { 11 } else { 1 };
+    for point in (0..).step_by(step) {
+        let dir = TempDir::new();
+        let committed = crash_engine_at(kind, dir.path(), point);
+
+        let store = DurableStore::open_with(dir.path(), kind).unwrap();
+        let expected = model(&committed);
+        assert_eq!(expected.clone().into_iter().collect::<Vec<_>>(), store.store().scan(..), "crash at {point}");
+        store.set("after", b"the crash".to_vec()).unwrap();
+        drop(store);
+
+        let store = DurableStore::open_with(dir.path(), kind).unwrap();
+        assert_eq!(Some(b"the crash".to_vec()), store.get("after"), "crash at {point}");
+        assert_eq!(expected.len() + 1, store.store().len());
+        if committed.len() == workload().len() {
+            break;
+        }
+    }
+}
+
+// A crash in compact(). For the log, after the snapshot was renamed but before the log was emptied: the log is replayed
+// over a snapshot that already has its changes. For the B-tree, before the new tree was renamed over the old one. Either
+// way, a half-written file from the attempt is ignored.
+fn a_crash_during_compaction_loses_nothing(kind: EngineKind) {
+    let dir = TempDir::new();
+    let store = DurableStore::open_with(dir.path(), kind).unwrap();
+    for op in workload() {
+        match op {
+            Op::Set(key, value) => store.set(&key, value).unwrap(),
+            Op::Delete(key) => store.delete(&key).unwrap(),
+        };
+    }
+    drop(store);
+
+    match kind {
+        EngineKind::Log => {
+            let log = fs::read(dir.child("wal")).unwrap();
+            DurableStore::open_with(dir.path(), kind).unwrap().compact(&Tracker::silent()).unwrap();
+            dir.write("wal", &log);
+            dir.write("snapshot.tmp", b"KVS1 half of a snapshot");
+        }
+        _ => {
+            dir.write("btree.tmp", b"KVB1 half of a tree");
+        }
+    }
+
+    let store = DurableStore::open_with(dir.path(), kind).unwrap();
+    assert_eq!(model(&workload()).into_iter().collect::<Vec<_>>(), store.store().scan(..));
+    store.compact(&Tracker::silent()).unwrap();
+    drop(store);
+    let store = DurableStore::open_with(dir.path(), kind).unwrap();
+    assert_eq!(model(&workload()).into_iter().collect::<Vec<_>>(), store.store().scan(..));
+}
+
+macro_rules! persistent_engines {
+    ($($module:ident => $kind:expr),* $(,)?) => {
+        $(
+            mod $module {
+                use super::*;
+
+                #[test]
+                fn the_store_recovers_from_a_crash() {
+                    super::the_store_recovers_from_a_crash($kind);
+                }
+
+                #[test]
+                fn a_crash_during_compaction_loses_nothing() {
+                    super::a_crash_during_compaction_loses_nothing($kind);
+                }
+            }
+        )*
+    };
+}
+
+persistent_engines! {
+    log => EngineKind::Log,
+    btree => EngineKind::BTree,
+}
@@ -0,0 +1,48 @@
+// Searching the Samples
+
+// minigrep and its index on the sample files of src/samples.rs, without touching the disk: the files are in a MemFs.
+// $ cargo run --example samples
+// $ cargo run --example samples -- "quot* OR safe"
+
+use std::{env, io, path::Path};
+
+use minigrep::{
+    index::Index,
+    run_with,
+    samples::{self, Level},
+    Config,
+};
+
+fn main() {
+    let query = env::args().nth(1).unwrap_or_else(|| String::from("frog OR productive"));
+
+    // Plain search, through the same run_with as the binary
+    println!("minigrep rust samples");
+    let config = Config::build(&["minigrep", "rust", "samples"].map(String::from)).unwrap();
+    run_with(&config, &samples::mem_fs("samples"), &mut io::stdout().lock()).unwrap();
+
+    // The inverted index of index.rs, built from the samples instead of a directory. The files get their ids in the order
+    // they are added, so a posting's file is the sample at the same place in ALL.
+    let mut index = Index::new(Path::new("samples"));
+    for sample in samples::ALL {
+        index.add_file(&Path::new("samples").join(sample.name), sample.text.as_bytes());
+    }
+    println!("\nindex query {query:?}");
+    match index.query(&query) {
+        Ok(postings) => {
+            for posting in postings {
+                let sample = samples::ALL[posting.file as usize];
+                println!("{}:{}: {}", sample.name, posting.line + 1, sample.lines().nth(posting.line as usize).unwrap());
+            }
+        }
+        Err(e) => eprintln!("bad query: {e}"),
+    }
+
+    // And the typed accessors
+    println!("\nwarnings and errors in server.log");
+    for line in samples::log_lines().iter().filter(|line| line.level >= Level::Warn) {
+        println!("{} {:?} {}", line.timestamp, line.level, line.message);
+    }
+    let most = samples::crates().into_iter().max_by_key(|row| row.downloads).unwrap();
+    println!("\nmost downloaded in crates.csv: {} {}", most.name, most.version);
+}
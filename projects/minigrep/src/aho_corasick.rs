@@ -0,0 +1,368 @@
+// Searching for Many Patterns at Once: Aho-Corasick
+
+// To find any of k patterns, the simple way is to search for each of them in turn, which reads the text k times.
+// Aho-Corasick reads the text once, whatever k is, by turning all the patterns into a single automaton:
+    // 1. goto: a trie of the patterns. Every state is a prefix of some pattern, and following a byte leads to the prefix one byte longer.
+    // 2. fail: where to go when the next byte has no goto edge. It's the state for the longest proper suffix of the current prefix
+    //    that is also in the trie, so the bytes already read never have to be read again.
+    // 3. output: the pattern that ends at a state, and a link to the next state along the fail chain that has one.
+    //    At "she", with patterns "she" and "he", the state's own output is "she" and its output link leads to "he".
+// The fail links are filled in breadth first: a state's fail link is always shorter than the state, so it's already known.
+
+// A step reads one byte and depends only on the current state, so the search can stop at the end of one buffer and go on
+// with the next: stream_find_iter finds matches in anything that implements Read, even across the ends of its reads.
+
+// minigrep searches for several -e patterns with it (see backend.rs), and the webserver filters request bodies with it.
+
+use std::{collections::{BTreeMap, VecDeque}, io::{self, Read}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    // The index of the pattern, in the order they were given
+    pub pattern: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    goto: BTreeMap<u8, usize>,
+    fail: usize,
+    // The pattern that is exactly this state's prefix. When a pattern is given twice, the first one keeps it.
+    output: Option<usize>,
+    // The nearest state on the fail chain with an output of its own
+    output_link: Option<usize>,
+}
+
+impl State {
+    fn new() -> State {
+        State { goto: BTreeMap::new(), fail: 0, output: None, output_link: None }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+    states: Vec<State>,
+    lengths: Vec<usize>,
+    ignore_case: bool,
+}
+
+// Options that change how the automaton is built, like ignoring case, which folds the patterns before they go into the trie
+#[derive(Debug, Clone, Default)]
+pub struct AhoCorasickBuilder {
+    ignore_case: bool,
+}
+
+impl AhoCorasickBuilder {
+    pub fn new() -> AhoCorasickBuilder {
+        AhoCorasickBuilder::default()
+    }
+
+    // Only ASCII letters are folded, like search_bytes_case_insensitive in lib.rs
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    pub fn build<I, P>(&self, patterns: I) -> AhoCorasick
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        let mut ac = AhoCorasick { states: vec![State::new()], lengths: Vec::new(), ignore_case: self.ignore_case };
+
+        // goto: insert every pattern into the trie
+        for (i, pattern) in patterns.into_iter().enumerate() {
+            let pattern = pattern.as_ref();
+            ac.lengths.push(pattern.len());
+            // An empty pattern would match between every two bytes, it matches nothing instead
+            if pattern.is_empty() {
+                continue;
+            }
+            let mut state = 0;
+            for &byte in pattern {
+                let byte = ac.fold(byte);
+                state = match ac.states[state].goto.get(&byte) {
+                    Some(&next) => next,
+                    None => {
+                        ac.states.push(State::new());
+                        let next = ac.states.len() - 1;
+                        ac.states[state].goto.insert(byte, next);
+                        next
+                    }
+                };
+            }
+            ac.states[state].output.get_or_insert(i);
+        }
+
+        // fail and output links, breadth first. The children of the root fail back to the root.
+        let mut queue: VecDeque<usize> = ac.states[0].goto.values().copied().collect();
+        while let Some(state) = queue.pop_front() {
+            let edges: Vec<(u8, usize)> = ac.states[state].goto.iter().map(|(&byte, &next)| (byte, next)).collect();
+            for (byte, child) in edges {
+                queue.push_back(child);
+                // The longest suffix of state's prefix that can be followed by byte, which is where child fails to
+                let mut fail = ac.states[state].fail;
+                while fail != 0 && !ac.states[fail].goto.contains_key(&byte) {
+                    fail = ac.states[fail].fail;
+                }
+                let fail = if state == 0 { 0 } else { ac.states[fail].goto.get(&byte).copied().unwrap_or(0) };
+                ac.states[child].fail = fail;
+                ac.states[child].output_link =
+                    if ac.states[fail].output.is_some() { Some(fail) } else { ac.states[fail].output_link };
+            }
+        }
+        ac
+    }
+}
+
+impl AhoCorasick {
+    pub fn new<I, P>(patterns: I) -> AhoCorasick
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        AhoCorasickBuilder::new().build(patterns)
+    }
+
+    pub fn builder() -> AhoCorasickBuilder {
+        AhoCorasickBuilder::new()
+    }
+
+    pub fn patterns_len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    fn fold(&self, byte: u8) -> u8 {
+        if self.ignore_case { byte.to_ascii_lowercase() } else { byte }
+    }
+
+    fn next_state(&self, mut state: usize, byte: u8) -> usize {
+        let byte = self.fold(byte);
+        loop {
+            if let Some(&next) = self.states[state].goto.get(&byte) {
+                return next;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.states[state].fail;
+        }
+    }
+
+    // The longest pattern that ends at this state: its own, or else the first one along the output links
+    fn match_at(&self, state: usize) -> Option<usize> {
+        let state = &self.states[state];
+        state.output.or_else(|| state.output_link.and_then(|link| self.states[link].output))
+    }
+
+    pub fn is_match<H: AsRef<[u8]> + ?Sized>(&self, haystack: &H) -> bool {
+        self.find(haystack).is_some()
+    }
+
+    pub fn find<H: AsRef<[u8]> + ?Sized>(&self, haystack: &H) -> Option<Match> {
+        self.find_iter(haystack).next()
+    }
+
+    // Matches that don't overlap: a match is reported as soon as its last byte is read, and the search starts over after it.
+    // So the match that ends first wins, with "abcd" and "bc" in "abcd" that's "bc".
+    pub fn find_iter<'a, H: AsRef<[u8]> + ?Sized>(&'a self, haystack: &'a H) -> FindIter<'a> {
+        FindIter { ac: self, haystack: haystack.as_ref(), pos: 0 }
+    }
+
+    // Every match, including the ones inside or overlapping others: "she" and "he" both match in "she"
+    pub fn find_overlapping_iter<'a, H: AsRef<[u8]> + ?Sized>(&'a self, haystack: &'a H) -> FindOverlappingIter<'a> {
+        FindOverlappingIter { ac: self, haystack: haystack.as_ref(), pos: 0, state: 0, pending: None }
+    }
+
+    // The same matches as find_iter, read from a stream a buffer at a time. Offsets count from the start of the stream.
+    pub fn stream_find_iter<R: Read>(&self, reader: R) -> StreamFindIter<'_, R> {
+        StreamFindIter { ac: self, reader, buf: vec![0; 8 * 1024], len: 0, pos: 0, offset: 0, state: 0 }
+    }
+}
+
+pub struct FindIter<'a> {
+    ac: &'a AhoCorasick,
+    haystack: &'a [u8],
+    pos: usize,
+}
+
+impl Iterator for FindIter<'_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let mut state = 0;
+        while self.pos < self.haystack.len() {
+            state = self.ac.next_state(state, self.haystack[self.pos]);
+            self.pos += 1;
+            if let Some(pattern) = self.ac.match_at(state) {
+                return Some(Match { pattern, start: self.pos - self.ac.lengths[pattern], end: self.pos });
+            }
+        }
+        None
+    }
+}
+
+pub struct FindOverlappingIter<'a> {
+    ac: &'a AhoCorasick,
+    haystack: &'a [u8],
+    pos: usize,
+    state: usize,
+    // The next state on the output chain of the current position whose output hasn't been reported yet
+    pending: Option<usize>,
+}
+
+impl Iterator for FindOverlappingIter<'_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        loop {
+            if let Some(state) = self.pending {
+                let state = &self.ac.states[state];
+                self.pending = state.output_link;
+                if let Some(pattern) = state.output {
+                    return Some(Match { pattern, start: self.pos - self.ac.lengths[pattern], end: self.pos });
+                }
+                continue;
+            }
+            let &byte = self.haystack.get(self.pos)?;
+            self.state = self.ac.next_state(self.state, byte);
+            self.pos += 1;
+            self.pending = Some(self.state);
+        }
+    }
+}
+
+pub struct StreamFindIter<'a, R> {
+    ac: &'a AhoCorasick,
+    reader: R,
+    buf: Vec<u8>,
+    // buf[pos..len] hasn't been searched yet
+    len: usize,
+    pos: usize,
+    // How many bytes of the stream have been searched
+    offset: usize,
+    // Kept from one read to the next, that's what finds a match split between two reads
+    state: usize,
+}
+
+impl<R: Read> Iterator for StreamFindIter<'_, R> {
+    type Item = io::Result<Match>;
+
+    fn next(&mut self) -> Option<io::Result<Match>> {
+        loop {
+            if self.pos == self.len {
+                match self.reader.read(&mut self.buf) {
+                    Ok(0) => return None,
+                    Ok(n) => (self.len, self.pos) = (n, 0),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            self.state = self.ac.next_state(self.state, self.buf[self.pos]);
+            self.pos += 1;
+            self.offset += 1;
+            if let Some(pattern) = self.ac.match_at(self.state) {
+                self.state = 0;
+                return Some(Ok(Match { pattern, start: self.offset - self.ac.lengths[pattern], end: self.offset }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(matches: impl Iterator<Item = Match>) -> Vec<(usize, usize, usize)> {
+        matches.map(|m| (m.pattern, m.start, m.end)).collect()
+    }
+
+    #[test]
+    fn textbook_example() {
+        // The patterns from the Aho-Corasick paper
+        let ac = AhoCorasick::new(["he", "she", "his", "hers"]);
+        assert_eq!(vec![(1, 1, 4), (0, 2, 4), (3, 2, 6)], spans(ac.find_overlapping_iter("ushers")));
+        // Without overlaps "she" is found first, and the search goes on after it
+        assert_eq!(vec![(1, 1, 4)], spans(ac.find_iter("ushers")));
+        assert_eq!(vec![(2, 0, 3), (1, 3, 6)], spans(ac.find_iter(b"hisshe")));
+        assert!(!ac.is_match("nothing to see"));
+    }
+
+    #[test]
+    fn fail_links_keep_the_suffix() {
+        // After "abcab" fails on 'x', the "ab" at the end is still a prefix of "abx"
+        let ac = AhoCorasick::new(["abcabd", "abx"]);
+        assert_eq!(Some(Match { pattern: 1, start: 3, end: 6 }), ac.find("abcabx"));
+        // The first match to end wins, and "bc" ends before "abcd"
+        assert_eq!(vec![(1, 1, 3)], spans(AhoCorasick::new(["abcd", "bc"]).find_iter("abcd")));
+    }
+
+    #[test]
+    fn ignoring_case() {
+        let ac = AhoCorasick::builder().ignore_case(true).build(["Rust", "CRAB"]);
+        assert_eq!(vec![(0, 0, 4), (1, 9, 13), (0, 14, 18)], spans(ac.find_iter("rUsT and crab rust")));
+        assert!(!AhoCorasick::new(["Rust"]).is_match("rust"));
+    }
+
+    #[test]
+    fn duplicate_and_empty_patterns() {
+        let ac = AhoCorasick::new(["", "ab", "ab"]);
+        assert_eq!(3, ac.patterns_len());
+        assert_eq!(vec![(1, 0, 2), (1, 2, 4)], spans(ac.find_iter("abab")));
+        assert_eq!(None, AhoCorasick::new([""; 0]).find("abc"));
+    }
+
+    // A reader that hands out a few bytes at a time, so matches end up split between reads
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn streams_match_like_slices() {
+        let ac = AhoCorasick::new(["needle", "eedl", "hay"]);
+        let haystack = "haystack with a needle, another needle and more hay".repeat(50);
+        let expected: Vec<Match> = ac.find_iter(&haystack).collect();
+        assert_eq!(200, expected.len());
+
+        let streamed: Vec<Match> = ac.stream_find_iter(Trickle(haystack.as_bytes())).collect::<io::Result<_>>().unwrap();
+        assert_eq!(expected, streamed);
+    }
+
+    #[test]
+    fn agrees_with_searching_for_each_pattern() {
+        use std_collections::rand_lite::{Rng, Xoshiro256};
+
+        let mut rng = Xoshiro256::seed_from_u64(7);
+        for _ in 0..200 {
+            // A small alphabet makes for plenty of overlapping prefixes and suffixes
+            let patterns: Vec<Vec<u8>> =
+                (0..rng.gen_range(1..6)).map(|_| (0..rng.gen_range(1..5)).map(|_| rng.gen_range(b'a'..=b'c')).collect()).collect();
+            let text: Vec<u8> = (0..60).map(|_| rng.gen_range(b'a'..=b'c')).collect();
+            let ac = AhoCorasick::new(&patterns);
+
+            let mut expected = Vec::new();
+            for end in 1..=text.len() {
+                // Within one end, longer patterns come first along the output links
+                let mut here: Vec<(usize, usize, usize)> = Vec::new();
+                for (i, pattern) in patterns.iter().enumerate() {
+                    let first = patterns.iter().position(|p| p == pattern).unwrap();
+                    if first == i && text[..end].ends_with(pattern) {
+                        here.push((i, end - pattern.len(), end));
+                    }
+                }
+                here.sort_by_key(|&(_, start, _)| start);
+                expected.extend(here);
+            }
+            assert_eq!(expected, spans(ac.find_overlapping_iter(&text)), "{patterns:?} in {text:?}");
+        }
+    }
+}
@@ -0,0 +1,421 @@
+// Search Backends
+
+// run used to pick a search function with an if/else chain: one branch for a regex, one for IGNORE_CASE, one for a plain query.
+// Every new way of searching added another branch, and there was no way to compare them against each other.
+// Now each way of searching is a type that implements the SearchBackend trait, and run only ever holds a Box<dyn SearchBackend>:
+    // 1. Naive checks every window of every line, like search() at the top of lib.rs. It's always there and handles IGNORE_CASE.
+    // 2. Regex runs the engine from regex_lite.rs, for --regex.
+    // 3. Memchr looks for the query in the whole file at once with the memchr crate's SIMD substring search, and only then
+    //    finds the line around each match. Most lines of a big file don't match, and this never looks at them one by one.
+    // 4. AhoCorasick looks for every -e pattern in a single pass over each line, with the automaton from aho_corasick.rs.
+    // 5. Bytesearch works like Memchr with the search from common::bytesearch, 8 bytes at a time. It needs no other crate,
+    //    so it's what a build without memchr searches with.
+// Several patterns can also go to the regex backend, which joins them into one alternation; the others only take a single query.
+
+// Which backends exist is decided twice:
+    // 1. At compile time by cargo features, memchr and aho-corasick. Memchr needs the memchr crate, Aho-Corasick only the module,
+    //    but minigrep without it is smaller still. `cargo build --no-default-features` leaves both out. Naive, Regex and
+    //    Bytesearch are always there.
+    // 2. At run time by --backend, or by choose() when it isn't given.
+// BackendKind always has every variant, so `--backend memchr` is understood by every build, and a build without the feature
+// says so clearly instead of "invalid value".
+
+use std::{error::Error, fmt, str::FromStr};
+
+use common::bytesearch::{self, Finder};
+
+use crate::{aho_corasick::AhoCorasick, regex_lite::Regex, search_bytes, search_bytes_case_insensitive, search_bytes_regex, split_lines};
+
+pub trait SearchBackend {
+    fn name(&self) -> &'static str;
+
+    fn is_match(&self, line: &[u8]) -> bool;
+
+    // The lines of contents that match. Going line by line works for every backend, one that can do better overrides it.
+    fn search<'a>(&self, contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+        split_lines(contents, separator).filter(|line| self.is_match(line)).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Naive,
+    Regex,
+    Memchr,
+    AhoCorasick,
+    Bytesearch,
+}
+
+impl BackendKind {
+    pub const ALL: [BackendKind; 5] =
+        [BackendKind::Naive, BackendKind::Regex, BackendKind::Memchr, BackendKind::AhoCorasick, BackendKind::Bytesearch];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Naive => "naive",
+            BackendKind::Regex => "regex",
+            BackendKind::Memchr => "memchr",
+            BackendKind::AhoCorasick => "aho-corasick",
+            BackendKind::Bytesearch => "bytesearch",
+        }
+    }
+
+    // Whether this build has the backend compiled in
+    pub fn is_available(self) -> bool {
+        match self {
+            BackendKind::Naive | BackendKind::Regex | BackendKind::Bytesearch => true,
+            BackendKind::Memchr => cfg!(feature = "memchr"),
+            BackendKind::AhoCorasick => cfg!(feature = "aho-corasick"),
+        }
+    }
+
+    // The backend to use when --backend isn't given: the fastest one that can do what was asked
+    pub fn choose(patterns: usize, regex: bool, ignore_case: bool) -> BackendKind {
+        if regex {
+            BackendKind::Regex
+        } else if patterns > 1 {
+            // Without Aho-Corasick the regex backend still can, unless the case has to be ignored
+            if BackendKind::AhoCorasick.is_available() || ignore_case { BackendKind::AhoCorasick } else { BackendKind::Regex }
+        } else if ignore_case {
+            BackendKind::Naive
+        } else if BackendKind::Memchr.is_available() {
+            BackendKind::Memchr
+        } else {
+            BackendKind::Bytesearch
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BackendKind, String> {
+        BackendKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| String::from("expected naive, regex, memchr, aho-corasick or bytesearch"))
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendError {
+    // The build left this backend out, see the features in Cargo.toml
+    NotCompiled(BackendKind),
+    // The backend can't search the way that was asked, like a case-insensitive memchr search
+    Unsupported(BackendKind, &'static str),
+    Regex(crate::regex_lite::Error),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendError::NotCompiled(kind) => write!(f, "the {kind} backend isn't part of this build, enable the \"{kind}\" feature"),
+            BackendError::Unsupported(kind, what) => write!(f, "the {kind} backend can't {what}"),
+            BackendError::Regex(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for BackendError {}
+
+impl From<crate::regex_lite::Error> for BackendError {
+    fn from(e: crate::regex_lite::Error) -> BackendError {
+        BackendError::Regex(e)
+    }
+}
+
+// Builds the backend of the given kind for one or more queries, a line matches when any of them is in it.
+// With regex set the queries are patterns, which only the regex backend understands; it also takes plain queries, they're escaped first.
+pub fn build(kind: BackendKind, queries: &[String], regex: bool, ignore_case: bool) -> Result<Box<dyn SearchBackend>, BackendError> {
+    assert!(!queries.is_empty(), "a search needs at least one query");
+    if !kind.is_available() {
+        return Err(BackendError::NotCompiled(kind));
+    }
+    if regex && kind != BackendKind::Regex {
+        return Err(BackendError::Unsupported(kind, "search for a regular expression"));
+    }
+    if queries.len() > 1 && !matches!(kind, BackendKind::Regex | BackendKind::AhoCorasick) {
+        return Err(BackendError::Unsupported(kind, "search for several patterns"));
+    }
+    // The regex engine has no case-insensitive mode, and memmem only compares bytes as they are
+    if ignore_case && matches!(kind, BackendKind::Regex | BackendKind::Memchr | BackendKind::Bytesearch) {
+        return Err(BackendError::Unsupported(kind, "ignore case"));
+    }
+
+    let query = &queries[0];
+    match kind {
+        BackendKind::Naive => Ok(Box::new(Naive { query: query.as_bytes().to_vec(), ignore_case })),
+        BackendKind::Regex => {
+            let pattern = if let [query] = queries {
+                if regex { query.clone() } else { crate::regex_lite::escape(query) }
+            } else {
+                // Every pattern in a group of its own, so that a | inside one of them stays inside
+                let groups: Vec<String> =
+                    queries.iter().map(|query| format!("({})", if regex { query.clone() } else { crate::regex_lite::escape(query) })).collect();
+                groups.join("|")
+            };
+            Ok(Box::new(RegexBackend(Regex::new(&pattern)?)))
+        }
+        BackendKind::Memchr => build_memchr(query),
+        BackendKind::AhoCorasick => Ok(Box::new(AhoCorasickBackend {
+            automaton: AhoCorasick::builder().ignore_case(ignore_case).build(queries),
+            // grep -e '' matches every line, the automaton on its own would match none
+            matches_every_line: queries.iter().any(String::is_empty),
+        })),
+        BackendKind::Bytesearch => Ok(Box::new(Bytesearch { finder: Finder::new(query.as_bytes()) })),
+    }
+}
+
+#[cfg(feature = "memchr")]
+fn build_memchr(query: &str) -> Result<Box<dyn SearchBackend>, BackendError> {
+    Ok(Box::new(Memchr::new(query.as_bytes())))
+}
+
+// Unreachable, is_available() already said no, but build() still has to compile without the feature
+#[cfg(not(feature = "memchr"))]
+fn build_memchr(_query: &str) -> Result<Box<dyn SearchBackend>, BackendError> {
+    Err(BackendError::NotCompiled(BackendKind::Memchr))
+}
+
+// Naive
+
+pub struct Naive {
+    query: Vec<u8>,
+    ignore_case: bool,
+}
+
+impl SearchBackend for Naive {
+    fn name(&self) -> &'static str {
+        "naive"
+    }
+
+    fn is_match(&self, line: &[u8]) -> bool {
+        !self.search(line, b'\n').is_empty()
+    }
+
+    // The searches from lib.rs, they already go line by line
+    fn search<'a>(&self, contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+        if self.ignore_case {
+            search_bytes_case_insensitive(&self.query, contents, separator)
+        } else {
+            search_bytes(&self.query, contents, separator)
+        }
+    }
+}
+
+// Regex
+
+pub struct RegexBackend(Regex);
+
+impl SearchBackend for RegexBackend {
+    fn name(&self) -> &'static str {
+        "regex"
+    }
+
+    fn is_match(&self, line: &[u8]) -> bool {
+        self.0.is_match(&String::from_utf8_lossy(line))
+    }
+
+    fn search<'a>(&self, contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+        search_bytes_regex(&self.0, contents, separator)
+    }
+}
+
+// Searching the Whole File
+
+// Memchr and Bytesearch look for the query in all of contents at once. After a match the line around it is found with
+// memrchr/memchr for the separator, and the search carries on after the end of that line, so every line is reported once.
+
+// A query that contains the separator, or '\r' when lines end with "\r\n", would match across the end of a line,
+// where a line by line search never would. Those queries, and the empty query that matches every line, go line by line.
+fn needs_lines(query: &[u8], separator: u8) -> bool {
+    query.is_empty() || query.contains(&separator) || (separator == b'\n' && query.contains(&b'\r'))
+}
+
+// Each backend passes its own functions for finding the query and the separator
+fn search_whole(
+    contents: &[u8],
+    separator: u8,
+    find: impl Fn(&[u8]) -> Option<usize>,
+    memchr: fn(u8, &[u8]) -> Option<usize>,
+    memrchr: fn(u8, &[u8]) -> Option<usize>,
+) -> Vec<&[u8]> {
+    let mut results = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = find(&contents[pos..]) {
+        let found = pos + found;
+        let start = memrchr(separator, &contents[..found]).map_or(0, |i| i + 1);
+        let end = memchr(separator, &contents[found..]).map_or(contents.len(), |i| found + i);
+        let line = &contents[start..end];
+        results.push(if separator == b'\n' { line.strip_suffix(b"\r").unwrap_or(line) } else { line });
+        if end == contents.len() {
+            break;
+        }
+        pos = end + 1;
+    }
+    results
+}
+
+// Memchr
+
+// memmem::Finder preprocesses the query once and then finds it anywhere in a haystack, with SIMD instructions where the CPU
+// has them.
+
+#[cfg(feature = "memchr")]
+pub struct Memchr {
+    finder: memchr::memmem::Finder<'static>,
+}
+
+#[cfg(feature = "memchr")]
+impl Memchr {
+    pub fn new(query: &[u8]) -> Memchr {
+        Memchr { finder: memchr::memmem::Finder::new(query).into_owned() }
+    }
+}
+
+#[cfg(feature = "memchr")]
+impl SearchBackend for Memchr {
+    fn name(&self) -> &'static str {
+        "memchr"
+    }
+
+    fn is_match(&self, line: &[u8]) -> bool {
+        self.finder.find(line).is_some()
+    }
+
+    fn search<'a>(&self, contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+        if needs_lines(self.finder.needle(), separator) {
+            return split_lines(contents, separator).filter(|line| self.is_match(line)).collect();
+        }
+        search_whole(contents, separator, |haystack| self.finder.find(haystack), memchr::memchr, memchr::memrchr)
+    }
+}
+
+// Bytesearch
+
+// The same search with common::bytesearch: Finder looks for the query's rarest byte 8 bytes at a time, and compares the rest
+// of the query where it finds one.
+
+pub struct Bytesearch {
+    finder: Finder,
+}
+
+impl SearchBackend for Bytesearch {
+    fn name(&self) -> &'static str {
+        "bytesearch"
+    }
+
+    fn is_match(&self, line: &[u8]) -> bool {
+        self.finder.find(line).is_some()
+    }
+
+    fn search<'a>(&self, contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+        if needs_lines(self.finder.needle(), separator) {
+            return split_lines(contents, separator).filter(|line| self.is_match(line)).collect();
+        }
+        search_whole(contents, separator, |haystack| self.finder.find(haystack), bytesearch::memchr, bytesearch::memrchr)
+    }
+}
+
+// Aho-Corasick
+
+pub struct AhoCorasickBackend {
+    automaton: AhoCorasick,
+    matches_every_line: bool,
+}
+
+impl SearchBackend for AhoCorasickBackend {
+    fn name(&self) -> &'static str {
+        "aho-corasick"
+    }
+
+    fn is_match(&self, line: &[u8]) -> bool {
+        self.matches_every_line || self.automaton.is_match(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &[u8] = b"Rust:\r\nsafe, fast, productive.\nPick three.\nDuct tape.\ntrust the duct\n";
+
+    fn available() -> impl Iterator<Item = BackendKind> {
+        BackendKind::ALL.into_iter().filter(|kind| kind.is_available())
+    }
+
+    #[test]
+    fn backends_agree() {
+        for query in ["duct", "Rust", "t", "", ":", "nowhere", "e.\nP"] {
+            let expected = search_bytes(query.as_bytes(), CONTENTS, b'\n');
+            for kind in available() {
+                let backend = build(kind, &[query.to_string()], false, false).unwrap();
+                assert_eq!(kind.name(), backend.name());
+                assert_eq!(expected, backend.search(CONTENTS, b'\n'), "{kind} searching for {query:?}");
+            }
+        }
+
+        let entries = b"src/main.rs\0README.md\0src/lib.rs";
+        for kind in available() {
+            let backend = build(kind, &[String::from("src/")], false, false).unwrap();
+            assert_eq!(vec![&b"src/main.rs"[..], b"src/lib.rs"], backend.search(entries, 0), "{kind}");
+        }
+    }
+
+    #[test]
+    fn choosing_a_backend() {
+        assert_eq!(BackendKind::Regex, BackendKind::choose(1, true, false));
+        assert_eq!(BackendKind::Naive, BackendKind::choose(1, false, true));
+        let fastest = if cfg!(feature = "memchr") { BackendKind::Memchr } else { BackendKind::Bytesearch };
+        assert_eq!(fastest, BackendKind::choose(1, false, false));
+        let multi = if cfg!(feature = "aho-corasick") { BackendKind::AhoCorasick } else { BackendKind::Regex };
+        assert_eq!(multi, BackendKind::choose(2, false, false));
+        assert_eq!(BackendKind::AhoCorasick, BackendKind::choose(2, false, true));
+
+        assert_eq!(Ok(BackendKind::Memchr), "memchr".parse());
+        assert!("grep".parse::<BackendKind>().is_err());
+    }
+
+    #[test]
+    fn unsupported_combinations() {
+        let one = |query: &str| vec![query.to_string()];
+        assert!(matches!(build(BackendKind::Naive, &one("a+"), true, false), Err(BackendError::Unsupported(..))));
+        assert!(matches!(build(BackendKind::Regex, &one("a"), false, true), Err(BackendError::Unsupported(..))));
+        assert!(matches!(build(BackendKind::Regex, &one("("), true, false), Err(BackendError::Regex(_))));
+        let two = vec![String::from("a"), String::from("b")];
+        assert!(matches!(build(BackendKind::Naive, &two, false, false), Err(BackendError::Unsupported(..))));
+
+        let naive = build(BackendKind::Naive, &one("rUsT"), false, true).unwrap();
+        assert_eq!(vec![&b"Rust:"[..], b"trust the duct"], naive.search(CONTENTS, b'\n'));
+
+        if !BackendKind::Memchr.is_available() {
+            assert_eq!(Some(BackendError::NotCompiled(BackendKind::Memchr)), build(BackendKind::Memchr, &one("a"), false, false).err());
+        }
+    }
+
+    #[test]
+    fn several_patterns() {
+        let queries = vec![String::from("duct"), String::from("Pick"), String::from("a|b")];
+        let expected = vec![&b"safe, fast, productive."[..], b"Pick three.", b"trust the duct"];
+        for kind in [BackendKind::Regex, BackendKind::AhoCorasick].into_iter().filter(|kind| kind.is_available()) {
+            assert_eq!(expected, build(kind, &queries, false, false).unwrap().search(CONTENTS, b'\n'), "{kind}");
+        }
+
+        if BackendKind::AhoCorasick.is_available() {
+            let ignoring_case = build(BackendKind::AhoCorasick, &[String::from("RUST"), String::from("TAPE")], false, true).unwrap();
+            assert_eq!(vec![&b"Rust:"[..], b"Duct tape.", b"trust the duct"], ignoring_case.search(CONTENTS, b'\n'));
+            let empty = build(BackendKind::AhoCorasick, &[String::from("zzz"), String::new()], false, false).unwrap();
+            assert_eq!(5, empty.search(CONTENTS, b'\n').len());
+        }
+
+        // Patterns stay patterns, each in its own group
+        let regexes = build(BackendKind::Regex, &[String::from("^P"), String::from("e.$")], true, false).unwrap();
+        assert_eq!(vec![&b"safe, fast, productive."[..], b"Pick three.", b"Duct tape."], regexes.search(CONTENTS, b'\n'));
+    }
+}
@@ -0,0 +1,94 @@
+// Comparing the Search Backends
+
+// Runs every backend this build has over the same large corpus and prints how long each one took.
+// $ cargo run --release --bin bench_backends
+// $ cargo run --release --bin bench_backends -- 100 ferris
+// The first argument is the size of the corpus in MiB (20 by default), the second the query ("ferris" by default).
+
+// Benchmarks in a debug build mostly measure the missing optimizations, so always pass --release.
+// The corpus is made up of random words from a fixed seed: every run searches the same text, and the query is rare in it,
+// like most real searches, where the time goes into the lines that don't match.
+
+use std::{env, hint::black_box, process, time::{Duration, Instant}};
+
+use minigrep::{
+    backend::{self, BackendKind},
+    table::{Align, Table},
+};
+use std_collections::rand_lite::{Rng, Xoshiro256};
+
+const WORDS: [&str; 16] = [
+    "the", "crab", "borrow", "checker", "lifetime", "trait", "struct", "enum",
+    "match", "iterator", "closure", "vector", "string", "slice", "module", "crate",
+];
+const RUNS: u32 = 5;
+
+fn corpus(bytes: usize, query: &str) -> Vec<u8> {
+    let mut rng = Xoshiro256::seed_from_u64(2015);
+    let mut text = Vec::with_capacity(bytes + 100);
+    while text.len() < bytes {
+        let words = rng.gen_range(4..16);
+        for i in 0..words {
+            if i > 0 {
+                text.push(b' ');
+            }
+            // About one line in a thousand has a match
+            let word = if rng.gen_bool(0.0001) { query } else { *rng.choose(&WORDS).unwrap() };
+            text.extend_from_slice(word.as_bytes());
+        }
+        text.push(b'\n');
+    }
+    text
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mib: usize = match args.get(1).map(|arg| arg.parse()) {
+        None => 20,
+        Some(Ok(mib)) => mib,
+        Some(Err(e)) => {
+            eprintln!("bench_backends: the corpus size must be a number of MiB: {e}");
+            process::exit(1);
+        }
+    };
+    let query = args.get(2).map_or("ferris", String::as_str);
+
+    let contents = corpus(mib * 1024 * 1024, query);
+    println!("Searching {} MiB for {query:?}, best of {RUNS} runs\n", mib);
+
+    let mut table = Table::new(["Backend", "Matches", "Time", "MiB/s"])
+        .align(1, Align::Right)
+        .align(2, Align::Right)
+        .align(3, Align::Right);
+
+    for kind in BackendKind::ALL {
+        // Left out by the cargo features of this build
+        if !kind.is_available() {
+            table.add_row([kind.name(), "-", "not built", "-"].map(String::from));
+            continue;
+        }
+        let backend = backend::build(kind, &[query.to_string()], false, false).unwrap_or_else(|e| {
+            eprintln!("bench_backends: {e}");
+            process::exit(1);
+        });
+
+        let mut best = Duration::MAX;
+        let mut matches = 0;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            // black_box keeps the optimizer from noticing that only the number of results is used
+            matches = black_box(backend.search(black_box(&contents), b'\n')).len();
+            best = best.min(start.elapsed());
+        }
+
+        let throughput = contents.len() as f64 / (1024.0 * 1024.0) / best.as_secs_f64();
+        table.add_row([
+            backend.name().to_string(),
+            matches.to_string(),
+            format!("{:.2} ms", best.as_secs_f64() * 1000.0),
+            format!("{throughput:.0}"),
+        ]);
+    }
+
+    print!("{table}");
+}
@@ -0,0 +1,416 @@
+// Diffing Files
+
+// A diff is the shortest list of edits that turns one sequence into another: which lines to keep, which to delete and which to insert.
+// "Shortest" matters, a diff that deletes every old line and inserts every new one is correct but useless to read.
+
+// Myers' Algorithm
+
+// Picture a grid with the old lines along the x axis and the new lines along the y axis. Starting at the top left corner:
+    // 1. A step right deletes an old line, a step down inserts a new line. Each one costs an edit.
+    // 2. Where old[x] == new[y] there's also a diagonal, which keeps the line for free. A run of diagonals is called a snake.
+    // 3. The shortest diff is the path to the bottom right corner with the fewest right and down steps.
+// Myers searches by the number of edits d = 0, 1, 2, ... and, for every diagonal k = x - y, only remembers the furthest x any path
+// with d edits has reached on it. A path with d edits can only end on the diagonals -d, -d+2, .. d, so each round is cheap,
+// and the search stops at the first d that reaches the corner: O((N + M) * D) time, fast when the files are mostly the same.
+// To recover the path, a copy of the furthest points is kept for every round and walked backwards from the corner.
+
+// On top of the edits:
+    // 1. hunks() groups the edits that are close together, with a few unchanged lines around them for context.
+    // 2. unified() prints them in the unified format of `diff -u` and `git diff`.
+    // 3. merge3() combines two sets of changes made to the same file, which is what git does when two branches are merged.
+
+// diff() works on slices of anything that can be compared, the functions on text split it into lines first.
+// The lines keep their line endings, so a line whose "\n" went missing at the end of the file counts as changed.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp {
+    // The positions are indices into the old and the new slice
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+impl DiffOp {
+    pub fn is_equal(&self) -> bool {
+        matches!(self, DiffOp::Equal { .. })
+    }
+}
+
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffOp> {
+    // Most diffs are a small change in the middle of a big file. Lines that are the same at the start and at the end
+    // are kept without asking Myers, who would find the same thing but with a lot more memory.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+    let mut ops: Vec<DiffOp> = (0..prefix).map(|i| DiffOp::Equal { old: i, new: i }).collect();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    myers(&old[prefix..old_end], &new[prefix..new_end], prefix, prefix, &mut ops);
+    ops.extend((0..suffix).map(|i| DiffOp::Equal { old: old_end + i, new: new_end + i }));
+    ops
+}
+
+// a and b are the parts of the slices between the common prefix and suffix, which start at old_start and new_start
+fn myers<T: PartialEq>(a: &[T], b: &[T], old_start: usize, new_start: usize, ops: &mut Vec<DiffOp>) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    // v[k + max] is the furthest x reached on diagonal k. Diagonals go from -max to max, the + max makes them valid indices.
+    let index = |k: isize| (k + max) as usize;
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            // Come down from the diagonal above (an insertion) or right from the one below (a deletion), whichever got further
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walking back from the corner finds the edits in reverse
+    let mut reversed = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) { k + 1 } else { k - 1 };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            reversed.push(DiffOp::Equal { old: old_start + x as usize, new: new_start + y as usize });
+        }
+        if d > 0 {
+            if x == prev_x {
+                y -= 1;
+                reversed.push(DiffOp::Insert { new: new_start + y as usize });
+            } else {
+                x -= 1;
+                reversed.push(DiffOp::Delete { old: old_start + x as usize });
+            }
+        }
+    }
+    ops.extend(reversed.into_iter().rev());
+}
+
+// Hunks
+
+// A run of edits with its context. The starts are indices, unified() prints them counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    pub ops: Vec<DiffOp>,
+}
+
+// Edits separated by at most 2 * context unchanged lines go in the same hunk, otherwise their context would overlap
+pub fn hunks(ops: &[DiffOp], context: usize) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    // How many old and new lines come before ops[i]
+    let (mut old, mut new) = (0, 0);
+    let mut i = 0;
+
+    while let Some(first) = ops[i..].iter().position(|op| !op.is_equal()).map(|p| p + i) {
+        let start = first.saturating_sub(context).max(i);
+        let mut end = first;
+        loop {
+            while end < ops.len() && !ops[end].is_equal() {
+                end += 1;
+            }
+            let unchanged = ops[end..].iter().take_while(|op| op.is_equal()).count();
+            if end + unchanged == ops.len() || unchanged > 2 * context {
+                break;
+            }
+            end += unchanged;
+        }
+        let stop = (end + context).min(ops.len());
+
+        for op in &ops[i..start] {
+            (old, new) = advance(op, old, new);
+        }
+        let (old_start, new_start) = (old, new);
+        for op in &ops[start..stop] {
+            (old, new) = advance(op, old, new);
+        }
+        hunks.push(Hunk { old: old_start..old, new: new_start..new, ops: ops[start..stop].to_vec() });
+        i = stop;
+    }
+    hunks
+}
+
+fn advance(op: &DiffOp, old: usize, new: usize) -> (usize, usize) {
+    match op {
+        DiffOp::Equal { .. } => (old + 1, new + 1),
+        DiffOp::Delete { .. } => (old + 1, new),
+        DiffOp::Insert { .. } => (old, new + 1),
+    }
+}
+
+// Unified Format
+
+// Each hunk starts with @@ -start,length +start,length @@, the length is left out when it's 1.
+// An empty range gives the line before it as its start, so inserting at the top of a file is -0,0.
+fn range(lines: &Range<usize>) -> String {
+    match lines.len() {
+        0 => format!("{},0", lines.start),
+        1 => format!("{}", lines.start + 1),
+        len => format!("{},{len}", lines.start + 1),
+    }
+}
+
+// Nothing at all when the texts are the same, like diff itself
+pub fn unified(old_name: &str, new_name: &str, old: &str, new: &str, context: usize) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let hunks = hunks(&diff(&old_lines, &new_lines), context);
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+    for hunk in hunks {
+        out.push_str(&format!("@@ -{} +{} @@\n", range(&hunk.old), range(&hunk.new)));
+        for op in hunk.ops {
+            let (sign, line) = match op {
+                DiffOp::Equal { old, .. } => (' ', old_lines[old]),
+                DiffOp::Delete { old } => ('-', old_lines[old]),
+                DiffOp::Insert { new } => ('+', new_lines[new]),
+            };
+            out.push(sign);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+// Three-Way Merge
+
+// Two people changed the same file, both starting from base. A line that neither of them touched is stable,
+// and the stable lines cut the three versions into chunks that can be merged one at a time:
+    // 1. Only one side changed the chunk: take that side.
+    // 2. Both sides made the same change: take it once.
+    // 3. The sides made different changes: that's a conflict, both versions are kept between the markers that git uses too.
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Merged {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> Merged {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    // For every base line, where it ended up in ours and in theirs, if it's still there
+    let matches = |other: &[&str]| {
+        let mut matched = vec![None; base.len()];
+        for op in diff(&base, other) {
+            if let DiffOp::Equal { old, new } = op {
+                matched[old] = Some(new);
+            }
+        }
+        matched
+    };
+    let (in_ours, in_theirs) = (matches(&ours), matches(&theirs));
+
+    let mut merged = Merged { text: String::new(), conflicts: 0 };
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    // The end of the files works like one more stable line, so the last chunk is merged as well
+    let stable = (0..base.len())
+        .filter_map(|i| Some((i, in_ours[i]?, in_theirs[i]?)))
+        .chain([(base.len(), ours.len(), theirs.len())]);
+
+    for (next_b, next_o, next_t) in stable {
+        let (base_chunk, ours_chunk, theirs_chunk) = (&base[b..next_b], &ours[o..next_o], &theirs[t..next_t]);
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            merged.text.extend(theirs_chunk.iter().copied());
+        } else if theirs_chunk == base_chunk {
+            merged.text.extend(ours_chunk.iter().copied());
+        } else {
+            merged.conflicts += 1;
+            merged.push_conflict(ours_chunk, theirs_chunk);
+        }
+
+        if next_b < base.len() {
+            merged.text.push_str(base[next_b]);
+        }
+        (b, o, t) = (next_b + 1, next_o + 1, next_t + 1);
+    }
+    merged
+}
+
+impl Merged {
+    fn push_conflict(&mut self, ours: &[&str], theirs: &[&str]) {
+        for (marker, lines) in [("<<<<<<< ours\n", ours), ("=======\n", theirs)] {
+            self.push_line(marker);
+            self.text.extend(lines.iter().copied());
+        }
+        self.push_line(">>>>>>> theirs\n");
+    }
+
+    // A chunk from the end of a file may not end with a newline, the marker still has to go on a line of its own
+    fn push_line(&mut self, line: &str) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.text.push_str(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Applies the edits to old, which must give back new
+    fn apply<'a>(old: &[&'a str], new: &[&'a str], ops: &[DiffOp]) -> Vec<&'a str> {
+        ops.iter()
+            .filter_map(|op| match *op {
+                DiffOp::Equal { old: i, new: j } => {
+                    assert_eq!(old[i], new[j]);
+                    Some(old[i])
+                }
+                DiffOp::Delete { .. } => None,
+                DiffOp::Insert { new: j } => Some(new[j]),
+            })
+            .collect()
+    }
+
+    fn edits(ops: &[DiffOp]) -> usize {
+        ops.iter().filter(|op| !op.is_equal()).count()
+    }
+
+    #[test]
+    fn shortest_edit_script() {
+        // The example from Myers' paper: ABCABBA to CBABAC takes 5 edits
+        let old: Vec<&str> = "A B C A B B A".split(' ').collect();
+        let new: Vec<&str> = "C B A B A C".split(' ').collect();
+        let ops = diff(&old, &new);
+        assert_eq!(5, edits(&ops));
+        assert_eq!(new, apply(&old, &new, &ops));
+
+        assert_eq!(Vec::<DiffOp>::new(), diff::<u8>(&[], &[]));
+        assert_eq!(vec![DiffOp::Insert { new: 0 }, DiffOp::Insert { new: 1 }], diff(&[], &[1, 2]));
+        assert_eq!(vec![DiffOp::Delete { old: 0 }], diff(&[1], &[]));
+    }
+
+    #[test]
+    fn random_diffs_are_minimal_and_correct() {
+        // xorshift, so the test is the same on every run
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut next = move |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+
+        for _ in 0..200 {
+            let old: Vec<u64> = (0..next(12)).map(|_| next(3)).collect();
+            let new: Vec<u64> = (0..next(12)).map(|_| next(3)).collect();
+            let ops = diff(&old, &new);
+
+            let rebuilt: Vec<u64> = ops
+                .iter()
+                .filter_map(|op| match *op {
+                    DiffOp::Equal { old: i, .. } => Some(old[i]),
+                    DiffOp::Delete { .. } => None,
+                    DiffOp::Insert { new: j } => Some(new[j]),
+                })
+                .collect();
+            assert_eq!(new, rebuilt);
+
+            // The fewest edits is len(old) + len(new) - 2 * the longest common subsequence
+            let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in 0..old.len() {
+                for j in 0..new.len() {
+                    lcs[i + 1][j + 1] = if old[i] == new[j] { lcs[i][j] + 1 } else { lcs[i][j + 1].max(lcs[i + 1][j]) };
+                }
+            }
+            assert_eq!(old.len() + new.len() - 2 * lcs[old.len()][new.len()], edits(&ops), "{old:?} -> {new:?}");
+        }
+    }
+
+    #[test]
+    fn hunks_share_context() {
+        let old: Vec<u32> = (0..20).collect();
+        let mut new = old.clone();
+        new[2] = 100;
+        new[5] = 101;
+        new[15] = 102;
+
+        // With 1 line of context, the changes at 2 and 5 are 2 lines apart and share a hunk, 15 is on its own
+        let hunks = hunks(&diff(&old, &new), 1);
+        assert_eq!(2, hunks.len());
+        assert_eq!((1..7, 1..7), (hunks[0].old.clone(), hunks[0].new.clone()));
+        assert_eq!((14..17, 14..17), (hunks[1].old.clone(), hunks[1].new.clone()));
+        assert_eq!(8, hunks[0].ops.len());
+    }
+
+    #[test]
+    fn unified_output() {
+        let old = "one\ntwo\nthree\nfour\nfive\nsix\n";
+        let new = "zero\none\ntwo\nthree\nfour\nFIVE\nsix\n";
+        let expected = "\
+--- a.txt
++++ b.txt
+@@ -1 +1,2 @@
++zero
+ one
+@@ -4,3 +5,3 @@
+ four
+-five
++FIVE
+ six
+";
+        assert_eq!(expected, unified("a.txt", "b.txt", old, new, 1));
+        assert_eq!("", unified("a.txt", "a.txt", old, old, 3));
+        // Without context, an insertion only has the line before it to go by
+        assert_eq!("--- a\n+++ b\n@@ -0,0 +1 @@\n+zero\n@@ -5 +6 @@\n-five\n+FIVE\n", unified("a", "b", old, new, 0));
+
+        let expected = "--- a\n+++ b\n@@ -1 +1 @@\n-end\n\\ No newline at end of file\n+end\n";
+        assert_eq!(expected, unified("a", "b", "end", "end\n", 3));
+    }
+
+    #[test]
+    fn merges_changes_from_both_sides() {
+        let base = "a\nb\nc\nd\ne\n";
+        let ours = "A\nb\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\nE\nf\n";
+        assert_eq!(Merged { text: String::from("A\nb\nc\nd\nE\nf\n"), conflicts: 0 }, merge3(base, ours, theirs));
+
+        // The same change on both sides is not a conflict
+        assert_eq!(0, merge3(base, ours, ours).conflicts);
+        assert_eq!(ours, merge3(base, ours, ours).text);
+    }
+
+    #[test]
+    fn conflicting_changes_keep_both_sides() {
+        let merged = merge3("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc\n");
+        assert_eq!(1, merged.conflicts);
+        assert_eq!("a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nc\n", merged.text);
+
+        // Without a newline at the end, the closing marker still starts a new line
+        let merged = merge3("x", "y", "z");
+        assert_eq!("<<<<<<< ours\ny\n=======\nz\n>>>>>>> theirs\n", merged.text);
+    }
+}
@@ -0,0 +1,54 @@
+// Fuzz Targets
+
+// Functions that take any bytes at all and must never panic, for a fuzzer to call (see testing/test_support/src/fuzz.rs).
+// They're only public with the `fuzz` feature: cargo build --features fuzz.
+
+// regex_lite: the first line of the input is the pattern, the rest the text it's matched against. A pattern that doesn't
+// compile is fine, it only has to come back as an Error. One that does is run through every way of matching,
+// because the Pike VM sees patterns the parser let through that no test wrote by hand.
+
+use crate::regex_lite::Regex;
+
+pub fn regex_lite(data: &[u8]) {
+    let Ok(input) = std::str::from_utf8(data) else { return };
+    let (pattern, text) = input.split_once('\n').unwrap_or((input, ""));
+    let Ok(regex) = Regex::new(pattern) else { return };
+
+    let found = regex.find(text);
+    if let Some(captures) = regex.captures(text) {
+        assert_eq!(found.map(|(start, end)| &text[start..end]), captures.get(0));
+        for group in 0..captures.len() {
+            assert!(captures.get(group).is_none_or(|matched| text.contains(matched)));
+        }
+        assert_eq!(None, captures.get(captures.len()));
+    }
+    assert_eq!(found.is_some(), regex.find_iter(text).next().is_some());
+    regex.replace_all(text, "<$0|$1|${12}|$$>");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn regressions(target: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fuzz_regressions").join(target)
+    }
+
+    #[test]
+    fn regex_lite_regressions() {
+        test_support::fuzz::replay(regressions("regex_lite"), regex_lite);
+    }
+
+    #[test]
+    fn regex_lite_mutations() {
+        let seeds: [&[u8]; 5] = [
+            b"^(a|b)*c$\nababc",
+            b"[a-z0-9_]+@\\w+\\.(com|org)\nmail ferris@rust.org today",
+            b"(x?)+y\nxxxxy",
+            b"[^\"]*\\d{1}\n\"quoted\" 42",
+            b"((a)(b(c)))|.\n\xc3\xa9abc",
+        ];
+        test_support::fuzz::mutate(&seeds, 5_000, 2015, regex_lite);
+    }
+}
@@ -0,0 +1,504 @@
+// A Full-Text Index
+
+// Every search so far reads every file from the start. That's fine once, but searching the same directory over and over
+// (say, a big log archive or a code base) reads the same bytes every time. An inverted index does that work once:
+    // 1. Every line is split into terms: runs of letters and digits, lowercased. "Don't panic!" has the terms "don", "t" and "panic".
+    // 2. For every term the index keeps its postings, the (file, line) pairs where it appears, sorted.
+    // 3. A query only looks up its terms, and combines their postings: AND intersects two sorted lists, OR merges them.
+    // 4. A term ending in * is a prefix query. The terms are also kept in the Trie from std_collections, which finds every term
+    //    starting with a prefix without looking at the others, and the prefix matches all of their postings.
+// Every term is interned (std_collections/src/interner.rs): its text is kept once, and its Symbol is the index of its postings
+// in a Vec. A line is lowercased into one buffer that's reused for all its terms, so only a term that's new allocates.
+// The index can be saved to a file with the binary codec and loaded back, so the next run of minigrep skips straight to step 3.
+// It only knows words, so it's always case insensitive and can't find "e pa" inside "the panic" like the plain search does.
+
+// The query language is small:
+    // 1. Words separated by spaces must all be on the same line: `rust safe` is rust AND safe.
+    // 2. OR binds looser than the spaces: `rust safe OR fast` is (rust AND safe) OR fast.
+    // 3. `word*` matches any term that starts with word.
+
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use codec::{DecodeError, Deserialize, Serialize};
+use std_collections::{interner::StringInterner, trie::Trie};
+
+use crate::{collect_files, is_binary, progress::Tracker, split_lines};
+
+// Terms
+
+// Non-ASCII letters count as letters too, so "café" is a single term
+pub fn terms(line: &str) -> impl Iterator<Item = String> + '_ {
+    raw_terms(line).map(str::to_lowercase)
+}
+
+fn raw_terms(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| !c.is_alphanumeric()).filter(|term| !term.is_empty())
+}
+
+// The same as terms(), lowercased into buf. str::to_lowercase knows more than the chars one by one (a Σ at the end of a word
+// is ς), so only ASCII takes the short way
+fn lowercase_into<'b>(term: &str, buf: &'b mut String) -> &'b str {
+    buf.clear();
+    if term.is_ascii() {
+        buf.push_str(term);
+        buf.make_ascii_lowercase();
+    } else {
+        buf.push_str(&term.to_lowercase());
+    }
+    buf
+}
+
+// Postings
+
+// A line of a file in the index, both counted from 0. Deriving Ord sorts them by file first, then by line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Posting {
+    pub file: u32,
+    pub line: u32,
+}
+
+// Both lists are sorted, so a single pass over each is enough: always step the one that's behind
+fn intersect(a: &[Posting], b: &[Posting]) -> Vec<Posting> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out
+}
+
+fn union(a: &[Posting], b: &[Posting]) -> Vec<Posting> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => {
+                out.push(a[i]);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                out.push(b[j]);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+    out
+}
+
+// Queries
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Term(String),
+    Prefix(String),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum QueryError {
+    Empty,
+    // OR at the start or the end of the query, or twice in a row
+    DanglingOr,
+    // A word without a single letter or digit in it, or a prefix that isn't a single term like `*` or `a-b*`
+    InvalidTerm(String),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::Empty => write!(f, "the query is empty"),
+            QueryError::DanglingOr => write!(f, "OR needs a word on both sides"),
+            QueryError::InvalidTerm(word) => write!(f, "'{word}' can't be searched for in the index"),
+        }
+    }
+}
+
+impl Error for QueryError {}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query, QueryError> {
+        let mut alternatives = Vec::new();
+        let mut words = Vec::new();
+        for word in query.split_whitespace() {
+            if word == "OR" {
+                if words.is_empty() {
+                    return Err(QueryError::DanglingOr);
+                }
+                alternatives.push(Query::all(std::mem::take(&mut words)));
+            } else {
+                words.push(Query::word(word)?);
+            }
+        }
+
+        if words.is_empty() {
+            return Err(if alternatives.is_empty() { QueryError::Empty } else { QueryError::DanglingOr });
+        }
+        alternatives.push(Query::all(words));
+        Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Query::Or(alternatives) })
+    }
+
+    // A word is split into terms the same way the lines are, so `don't` looks for "don" AND "t" on the same line
+    fn word(word: &str) -> Result<Query, QueryError> {
+        let invalid = || QueryError::InvalidTerm(word.to_string());
+        if let Some(prefix) = word.strip_suffix('*') {
+            if prefix.is_empty() || !prefix.chars().all(char::is_alphanumeric) {
+                return Err(invalid());
+            }
+            return Ok(Query::Prefix(prefix.to_lowercase()));
+        }
+
+        let terms: Vec<Query> = terms(word).map(Query::Term).collect();
+        match terms.len() {
+            0 => Err(invalid()),
+            _ => Ok(Query::all(terms)),
+        }
+    }
+
+    fn all(mut queries: Vec<Query>) -> Query {
+        if queries.len() == 1 { queries.pop().unwrap() } else { Query::And(queries) }
+    }
+}
+
+// The Index
+
+// Bumped whenever the layout of Stored changes, an index saved by an older minigrep is then rebuilt instead of misread
+const INDEX_VERSION: u32 = 1;
+
+// What goes into the file. The Trie isn't saved, it's quick to rebuild from the terms and the codec doesn't know how to write it.
+// Paths are stored as strings, a path that isn't valid UTF-8 comes back with U+FFFD in it and can't be opened any more.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    version: u32,
+    root: String,
+    files: Vec<String>,
+    postings: Vec<(String, Vec<Posting>)>,
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    Io(io::Error),
+    Decode(DecodeError),
+    Version(u32),
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexError::Io(e) => write!(f, "{e}"),
+            IndexError::Decode(e) => write!(f, "the index file is damaged: {e}"),
+            IndexError::Version(v) => write!(f, "the index file is version {v}, this minigrep writes version {INDEX_VERSION}"),
+        }
+    }
+}
+
+impl Error for IndexError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IndexError::Io(e) => Some(e),
+            IndexError::Decode(e) => Some(e),
+            IndexError::Version(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for IndexError {
+    fn from(e: io::Error) -> Self {
+        IndexError::Io(e)
+    }
+}
+
+impl From<DecodeError> for IndexError {
+    fn from(e: DecodeError) -> Self {
+        IndexError::Decode(e)
+    }
+}
+
+pub struct Index {
+    root: PathBuf,
+    files: Vec<PathBuf>,
+    // The postings of a term are at the index of its Symbol
+    symbols: StringInterner,
+    postings: Vec<Vec<Posting>>,
+    terms: Trie,
+}
+
+impl Index {
+    pub fn new(root: &Path) -> Index {
+        Index {
+            root: root.to_path_buf(),
+            files: Vec::new(),
+            symbols: StringInterner::new(),
+            postings: Vec::new(),
+            terms: Trie::new(),
+        }
+    }
+
+    fn postings_mut(&mut self, term: &str) -> &mut Vec<Posting> {
+        let symbol = self.symbols.intern(term);
+        if symbol.index() == self.postings.len() {
+            self.terms.insert(term);
+            self.postings.push(Vec::new());
+        }
+        &mut self.postings[symbol.index()]
+    }
+
+    fn postings(&self, term: &str) -> &[Posting] {
+        self.symbols.get(term).map_or(&[], |symbol| &self.postings[symbol.index()])
+    }
+
+    // Indexes a file, or every file below a directory. Like the plain search, binary files are skipped
+    // and a file that can't be read doesn't stop the rest of the directory.
+    pub fn build(root: &Path, progress: &Tracker) -> io::Result<Index> {
+        let mut index = Index::new(root);
+        let files = if root.is_dir() { collect_files(root)? } else { vec![root.to_path_buf()] };
+        for file in files {
+            progress.inc(1);
+            match fs::read(&file) {
+                Ok(contents) if is_binary(&contents) => {}
+                Ok(contents) => index.add_file(&file, &contents),
+                Err(e) => eprintln!("minigrep: {}: {e}", file.display()),
+            }
+        }
+        progress.finish();
+        Ok(index)
+    }
+
+    // Files get their ids in the order they are added, and lines are added in order too,
+    // so pushing onto the end keeps every postings list sorted without ever sorting it
+    pub fn add_file(&mut self, path: &Path, contents: &[u8]) {
+        let file = self.files.len() as u32;
+        self.files.push(path.to_path_buf());
+
+        let mut lowercase = String::new();
+        for (line, text) in split_lines(contents, b'\n').enumerate() {
+            let posting = Posting { file, line: line as u32 };
+            for term in raw_terms(&String::from_utf8_lossy(text)) {
+                let list = self.postings_mut(lowercase_into(term, &mut lowercase));
+                // A term that appears twice on a line is still one posting
+                if list.last() != Some(&posting) {
+                    list.push(posting);
+                }
+            }
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn file(&self, id: u32) -> &Path {
+        &self.files[id as usize]
+    }
+
+    pub fn files(&self) -> usize {
+        self.files.len()
+    }
+
+    // The number of different terms
+    pub fn len(&self) -> usize {
+        self.postings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.postings.is_empty()
+    }
+
+    pub fn search(&self, query: &Query) -> Vec<Posting> {
+        match query {
+            Query::Term(term) => self.postings(term).to_vec(),
+            Query::Prefix(prefix) => self
+                .terms
+                .iter_prefix(prefix)
+                .fold(Vec::new(), |all, (term, _)| union(&all, self.postings(&term))),
+            // Intersecting the shortest lists first keeps the intermediate results small
+            Query::And(queries) => {
+                let mut lists: Vec<Vec<Posting>> = queries.iter().map(|q| self.search(q)).collect();
+                lists.sort_by_key(Vec::len);
+                let mut lists = lists.into_iter();
+                let first = lists.next().unwrap_or_default();
+                lists.fold(first, |all, list| intersect(&all, &list))
+            }
+            Query::Or(queries) => queries.iter().fold(Vec::new(), |all, q| union(&all, &self.search(q))),
+        }
+    }
+
+    pub fn query(&self, query: &str) -> Result<Vec<Posting>, QueryError> {
+        Ok(self.search(&Query::parse(query)?))
+    }
+
+    // Saving and Loading
+
+    // The terms are sorted, so the same index always makes the same bytes, whatever order they were interned in
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut postings: Vec<(String, Vec<Posting>)> =
+            self.symbols.iter().map(|(symbol, term)| (term.to_string(), self.postings[symbol.index()].clone())).collect();
+        postings.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let stored = Stored {
+            version: INDEX_VERSION,
+            root: self.root.to_string_lossy().into_owned(),
+            files: self.files.iter().map(|file| file.to_string_lossy().into_owned()).collect(),
+            postings,
+        };
+        codec::to_bytes(&stored)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Index, IndexError> {
+        // The version comes first, so it can be checked before trying to decode a layout that may have changed
+        let version = u32::deserialize(&mut &bytes[..])?;
+        if version != INDEX_VERSION {
+            return Err(IndexError::Version(version));
+        }
+
+        let stored: Stored = codec::from_bytes(bytes)?;
+        let mut index = Index::new(Path::new(&stored.root));
+        index.files = stored.files.into_iter().map(PathBuf::from).collect();
+        for (term, list) in stored.postings {
+            // Only a damaged file has a term twice, the first one counts
+            let postings = index.postings_mut(&term);
+            if postings.is_empty() {
+                *postings = list;
+            }
+        }
+        Ok(index)
+    }
+
+    // Written to a temporary file first and renamed into place, so a crash halfway leaves the old index behind instead of half a new one
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        fs::write(&temp, self.to_bytes())?;
+        fs::rename(&temp, path)
+    }
+
+    pub fn load(path: &Path) -> Result<Index, IndexError> {
+        Index::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn poems() -> Index {
+        let mut index = Index::new(Path::new("poems"));
+        index.add_file(Path::new("poems/rust.txt"), b"Rust:\nsafe, fast, productive.\nPick three.\nTrust me.");
+        index.add_file(Path::new("poems/frog.txt"), b"How dreary to be somebody!\nHow public, like a frog\nTo tell your name the livelong day");
+        index
+    }
+
+    fn lines(index: &Index, query: &str) -> Vec<(u32, u32)> {
+        index.query(query).unwrap().iter().map(|p| (p.file, p.line)).collect()
+    }
+
+    #[test]
+    fn lines_split_into_lowercase_terms() {
+        let terms: Vec<String> = terms("Don't PANIC, café 42!").collect();
+        assert_eq!(vec!["don", "t", "panic", "café", "42"], terms);
+    }
+
+    #[test]
+    fn queries_parse_with_or_looser_than_and() {
+        let term = |t: &str| Query::Term(t.to_string());
+        assert_eq!(Ok(term("rust")), Query::parse("  Rust "));
+        assert_eq!(
+            Ok(Query::Or(vec![Query::And(vec![term("rust"), term("safe")]), Query::Prefix(String::from("fa"))])),
+            Query::parse("rust safe OR fa*")
+        );
+        assert_eq!(Ok(Query::And(vec![term("don"), term("t")])), Query::parse("don't"));
+        // Only the uppercase OR is an operator, "or" is a word like any other
+        assert_eq!(Ok(Query::And(vec![term("this"), term("or"), term("that")])), Query::parse("this or that"));
+    }
+
+    #[test]
+    fn bad_queries() {
+        assert_eq!(Err(QueryError::Empty), Query::parse("   "));
+        assert_eq!(Err(QueryError::DanglingOr), Query::parse("OR rust"));
+        assert_eq!(Err(QueryError::DanglingOr), Query::parse("rust OR"));
+        assert_eq!(Err(QueryError::DanglingOr), Query::parse("rust OR OR safe"));
+        assert_eq!(Err(QueryError::InvalidTerm(String::from("--"))), Query::parse("rust --"));
+        assert_eq!(Err(QueryError::InvalidTerm(String::from("*"))), Query::parse("*"));
+        assert_eq!(Err(QueryError::InvalidTerm(String::from("a-b*"))), Query::parse("a-b*"));
+    }
+
+    #[test]
+    fn boolean_and_prefix_searches() {
+        let index = poems();
+        assert_eq!(vec![(0, 0)], lines(&index, "RUST"));
+        assert_eq!(vec![(0, 1)], lines(&index, "safe fast"));
+        assert_eq!(Vec::<(u32, u32)>::new(), lines(&index, "safe frog"));
+        assert_eq!(vec![(0, 1), (1, 1)], lines(&index, "frog OR productive"));
+        // "trust" has "rust" in it, but a prefix has to be at the start of the term
+        assert_eq!(vec![(0, 0)], lines(&index, "rus*"));
+        assert_eq!(vec![(0, 2), (0, 3), (1, 0), (1, 2)], lines(&index, "t*"));
+        assert_eq!(vec![(1, 0), (1, 1)], lines(&index, "how"));
+        assert_eq!("poems/frog.txt", index.file(1).to_str().unwrap());
+    }
+
+    #[test]
+    fn set_operations_on_sorted_postings() {
+        let p = |file, line| Posting { file, line };
+        let a = [p(0, 1), p(0, 5), p(2, 0)];
+        let b = [p(0, 5), p(1, 3), p(2, 0), p(2, 9)];
+        assert_eq!(vec![p(0, 5), p(2, 0)], intersect(&a, &b));
+        assert_eq!(vec![p(0, 1), p(0, 5), p(1, 3), p(2, 0), p(2, 9)], union(&a, &b));
+    }
+
+    #[test]
+    fn saved_index_loads_back() {
+        let index = poems();
+        let bytes = index.to_bytes();
+        assert_eq!(bytes, poems().to_bytes(), "the same index always saves the same bytes");
+
+        let loaded = Index::from_bytes(&bytes).unwrap();
+        assert_eq!(index.len(), loaded.len());
+        assert_eq!(Path::new("poems"), loaded.root());
+        assert_eq!(lines(&index, "t* OR frog"), lines(&loaded, "t* OR frog"));
+
+        let mut old = bytes.clone();
+        old[0] = 0;
+        assert!(matches!(Index::from_bytes(&old), Err(IndexError::Version(0))));
+        assert!(matches!(Index::from_bytes(&bytes[..bytes.len() - 1]), Err(IndexError::Decode(_))));
+    }
+
+    #[test]
+    fn builds_and_saves_a_directory() {
+        let dir = env::temp_dir().join(format!("minigrep-index-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "alpha beta\ngamma").unwrap();
+        fs::write(dir.join("sub/b.txt"), "beta\r\ndelta").unwrap();
+        fs::write(dir.join("binary"), b"beta\0").unwrap();
+
+        let index = Index::build(&dir, &Tracker::silent()).unwrap();
+        let saved = dir.join("index.bin");
+        index.save(&saved).unwrap();
+        let loaded = Index::load(&saved);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(2, loaded.files());
+        let hits = loaded.query("beta").unwrap();
+        let found: Vec<_> = hits.iter().map(|p| (loaded.file(p.file).strip_prefix(&dir).unwrap().to_path_buf(), p.line)).collect();
+        assert_eq!(vec![(PathBuf::from("a.txt"), 0), (PathBuf::from("sub/b.txt"), 0)], found);
+    }
+}
@@ -5,7 +5,63 @@
     // 2. Write code such that the test passes, and refactor the code and make sure the test continues to pass
     // 3. Repeat.
 
-use std::{env, fs, error::Error};
+use std::{
+    fs, error::Error,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+// The regular expression engine lives in its own module, see regex_lite.rs
+pub mod regex_lite;
+// And so does replace mode, see replace.rs
+pub mod replace;
+// The command line is described with the parser of common's argparse.rs. It started out here, and moved to projects/common
+// with table.rs and mmap.rs once the servers needed them, they're re-exported so that minigrep::argparse is still where it was
+pub use common::argparse;
+// Searching a directory can report how far along it is. The progress module lives in projects/common now, since the
+// webserver's ThreadPool reports with it too, and is re-exported so that minigrep::progress is still where it was
+pub use common::progress;
+// Repeated searches of the same files can go through an inverted index, see index.rs
+pub mod index;
+// The diffs that replace mode prints come from the Myers diff in diff.rs
+pub mod diff;
+// --stats lines its numbers up with the tables in common's table.rs
+pub use common::table;
+// How a file is searched is up to a SearchBackend, see backend.rs
+pub mod backend;
+// Several -e patterns are searched for at once by the automaton in aho_corasick.rs
+pub mod aho_corasick;
+// --mmap maps the files into memory instead of reading them, see common's mmap.rs
+pub use common::mmap;
+// UTF-16 files and byte order marks are dealt with by textio.rs
+pub mod textio;
+// Directories are searched through the walker in common's walk.rs, which skips what .gitignore files list
+pub use common::walk;
+// And the patterns in those files are the globs of common's glob.rs
+pub use common::glob;
+// Tests can give run_with files that only exist in memory (common's vfs.rs), and a clock they control (the clock module).
+// The three moved to projects/common when the kvstore wanted its files in memory too, and are re-exported so that minigrep::clock
+// and minigrep::vfs are still where they were
+pub use common::clock;
+pub use common::vfs;
+// Poems, a log and a CSV table compiled into the crate, for tests and examples that need some text, see samples.rs
+pub mod samples;
+// The fuzz targets for the parsers are public with the fuzz feature, and tested either way, see fuzz.rs
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+
+use argparse::{ArgError, Parser};
+use common::env_config::Env;
+use backend::BackendKind;
+use index::{Index, Query};
+use mmap::FileBytes;
+use textio::InvalidPolicy;
+use progress::{ProgressFormat, Tracker};
+use regex_lite::Regex;
+use replace::{diff, replace_lines, write_in_place, FileError};
+use table::{Align, Table};
+use vfs::{FileSystem, RealFs};
+use walk::{Symlinks, Walk};
 
 
 /*
@@ -186,21 +242,120 @@ pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a st
 // Before that, we need to add a variable to Config in order to get the state of the environment variable
 // Lets add an ignore_case boolean to the Config struct,
 
+// Later on, the text and null_data flags were added for searching binary files, see "Searching Bytes" at the bottom of this file,
+// and regex, replace, dry_run and backup_suffix for replace mode, see replace.rs.
+// index is the file the inverted index is kept in, see index.rs, and stats asks for a summary of the matches per file.
+// backend picks how the files are searched, see backend.rs, when it's None the fastest backend that can do the search is used.
+// patterns are the queries given with -e, a line matches when it contains any of them, and mmap maps the files instead of reading them.
+// no_ignore, max_depth and follow change how a directory is walked, see walk.rs.
+
 pub struct Config {
     pub query: String,
     pub file_path: String,
     pub ignore_case: bool,
+    pub text: bool,
+    pub null_data: bool,
+    pub regex: bool,
+    pub replace: Option<String>,
+    pub dry_run: bool,
+    pub backup_suffix: Option<String>,
+    pub progress: Option<ProgressFormat>,
+    pub index: Option<String>,
+    pub stats: bool,
+    pub backend: Option<BackendKind>,
+    pub patterns: Vec<String>,
+    pub mmap: bool,
+    pub no_ignore: bool,
+    pub max_depth: Option<usize>,
+    pub follow: bool,
 }
 
 impl Config {
-    pub fn build(args: &[String]) -> Result<Config, &'static str> {
+    // Flags can go anywhere on the command line, whatever is left are the query and the file path.
+    // Once there were enough flags the hand written match turned into the parser in argparse.rs, which also writes --help.
+    pub fn parser() -> Parser {
+        Parser::new("minigrep", "print the lines of a file that contain a query")
+            .flag("text", "Search binary files as if they were text")
+            .short('a')
+            .flag("null-data", "Lines end with a NUL byte instead of a newline")
+            .short('z')
+            .flag("regex", "The query is a regular expression")
+            .short('E')
+            .option("replace", "REPLACEMENT", "Replace every match in place, $1 refers to a capture group")
+            .flag("dry-run", "With --replace, print a diff instead of changing the files")
+            .option("backup", "SUFFIX", "With --replace, keep the original file with this suffix")
+            .default_missing(".bak")
+            .option("progress", "FORMAT", "Report progress on stderr, as a bar or as json lines")
+            .default_missing("bar")
+            .flag("stats", "Print the number of matching lines per file on stderr when done")
+            .option("index", "PATH", "Search through the index in PATH, building it first if the file doesn't exist")
+            .option("backend", "NAME", "Search with naive, regex, memchr, aho-corasick or bytesearch instead of the fastest backend")
+            .option("regexp", "PATTERN", "Search for PATTERN, give it more than once to match any of them; the query is left out then")
+            .short('e')
+            .flag("mmap", "Map the files into memory instead of reading them, faster for big files")
+            .flag("no-ignore", "Also search the files that .gitignore and .ignore files leave out")
+            .option("max-depth", "N", "Don't go more than N directories deep below the directory searched")
+            .flag("follow", "Follow symbolic links, to directories as well")
+            .short('L')
+            .positional("query", "What to search for")
+            .positional("file_path", "The file, or a directory to search recursively")
+            .optional()
+    }
 
-        if args.len() < 3 {
-            return Err("not enough arguments");
+    pub fn build(args: &[String]) -> Result<Config, ArgError> {
+        // The environment is read first, so that --print-env-help can list it
+        let mut env = Env::new();
+        let ignore_case = env.flag("IGNORE_CASE", "Search case-insensitively");
+        let matches = Config::parser().env_help(&env.help()).parse(args.iter().skip(1).cloned())?;
+        env.check().map_err(|e| ArgError::Invalid(e.to_string()))?;
+
+        let replace = matches.value("replace").map(String::from);
+        let dry_run = matches.flag("dry-run");
+        let backup_suffix = matches.value("backup").map(String::from);
+        if replace.is_none() && (dry_run || backup_suffix.is_some()) {
+            return Err(ArgError::Invalid(String::from("--dry-run and --backup only make sense with --replace")));
         }
 
-        let query = args[1].clone();
-        let file_path = args[2].clone();
+        // The query is required, so parse() already made sure it's there. With -e the patterns come from the options,
+        // and the only word left is the file: minigrep -e frog -e toad poem.txt
+        let patterns: Vec<String> = matches.values("regexp").into_iter().map(String::from).collect();
+        let first = matches.value("query").unwrap().to_string();
+        let (query, file_path) = match (patterns.is_empty(), matches.value("file_path")) {
+            (true, Some(file_path)) => (first, file_path.to_string()),
+            (true, None) => return Err(ArgError::MissingArgument(String::from("file_path"))),
+            (false, None) => (String::new(), first),
+            (false, Some(extra)) => return Err(ArgError::UnexpectedArgument(extra.to_string())),
+        };
+        let (text, null_data, regex) = (matches.flag("text"), matches.flag("null-data"), matches.flag("regex"));
+        let progress = matches.get("progress")?;
+        let index = matches.value("index").map(String::from);
+        let stats = matches.flag("stats");
+        let mmap = matches.flag("mmap");
+        let (no_ignore, max_depth, follow) = (matches.flag("no-ignore"), matches.get("max-depth")?, matches.flag("follow"));
+        // The index only knows words, it can't run a regex or tell where in a line a match is
+        if index.is_some() && (regex || null_data || replace.is_some() || !patterns.is_empty()) {
+            return Err(ArgError::Invalid(String::from("--index can't be combined with --regex, --null-data, --replace or -e")));
+        }
+        if replace.is_some() && !patterns.is_empty() {
+            return Err(ArgError::Invalid(String::from("--replace takes a single query, not -e")));
+        }
+        let backend = matches.get("backend")?;
+        // Replace mode always goes through the regex engine, and the index does its own searching
+        if backend.is_some() && (replace.is_some() || index.is_some()) {
+            return Err(ArgError::Invalid(String::from("--backend can't be combined with --replace or --index")));
+        }
+        // A $3 of a group the pattern doesn't have would quietly become nothing in every file, better to say so before any
+        // file is touched. A pattern that doesn't compile is left to run(), which says where the mistake is
+        if let Some(replacement) = &replace {
+            let pattern = if regex { query.clone() } else { regex_lite::escape(&query) };
+            if let Ok(re) = Regex::new(&pattern) {
+                re.check_replacement(replacement).map_err(|reason| ArgError::InvalidValue {
+                    name: String::from("replace"),
+                    value: replacement.clone(),
+                    reason,
+                })?;
+            }
+        }
         // Read this value from the env variable
         /*
         The env::var function returns a Result that will be the successful Ok variant that contains the value of the environment variable if 
@@ -209,9 +364,30 @@ impl Config {
         We’re using the is_ok method on the Result to check whether the environment variable is set, which means the program should do a case-insensitive search. 
         If the IGNORE_CASE environment variable isn’t set to anything, is_ok will return false and the program will perform a case-sensitive search.
         */
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // It's read at the top of build now, through common::env_config, where IGNORE_CASE=0 also means case-sensitive
+
+        Ok(Config { query, file_path, ignore_case, text, null_data, regex, replace, dry_run, backup_suffix, progress, index, stats, backend, patterns, mmap, no_ignore, max_depth, follow })
+    }
 
-        Ok(Config { query, file_path, ignore_case })
+    // The files to search: file_path itself, or what the walk finds below it when it's a directory
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        self.files_in(&RealFs)
+    }
+
+    pub fn files_in(&self, fs: &dyn FileSystem) -> io::Result<Vec<PathBuf>> {
+        let root = Path::new(&self.file_path);
+        if !fs.metadata(root).is_ok_and(|info| info.is_dir) {
+            return Ok(vec![root.to_path_buf()]);
+        }
+        let mut walk = Walk::new(root).ignore_files(!self.no_ignore);
+        if let Some(max_depth) = self.max_depth {
+            walk = walk.max_depth(max_depth);
+        }
+        if self.follow {
+            walk = walk.symlinks(Symlinks::Follow);
+        }
+        fs.files(&walk)
     }
 }
 
@@ -219,18 +395,187 @@ impl Config {
 // value and use that to decide whether to call the search function or the search_case_insensitive function.
 
 
+// run has since moved on to the byte-slice searches below, so that it can also look into files that aren't valid UTF-8,
+// and to searching whole directories when file_path is one.
+
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
 
-    let contents = fs::read_to_string(config.file_path)?;
+    if let Some(index_path) = &config.index {
+        return run_index(&config, Path::new(index_path));
+    }
+
+    // The regex engine has no case-insensitive mode, and replace mode always goes through it
+    if let Some(replacement) = &config.replace {
+        if config.ignore_case {
+            return Err("IGNORE_CASE can't be combined with --replace".into());
+        }
+        let pattern = if config.regex { config.query.clone() } else { regex_lite::escape(&config.query) };
+        return run_replace(&config, &Regex::new(&pattern)?, replacement);
+    }
+
+    let stdout = io::stdout();
+    run_with(&config, &RealFs, &mut stdout.lock())
+}
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
+// The search itself, on any FileSystem and into any writer, which is what the tests use it for.
+// --index and --replace keep their own code above, they work on the real files only.
+
+pub fn run_with(config: &Config, fs: &dyn FileSystem, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if config.index.is_some() || config.replace.is_some() {
+        return Err("--index and --replace only work through run".into());
+    }
+
+    let queries = if config.patterns.is_empty() { vec![config.query.clone()] } else { config.patterns.clone() };
+    let kind = config.backend.unwrap_or_else(|| BackendKind::choose(queries.len(), config.regex, config.ignore_case));
+    let backend = backend::build(kind, &queries, config.regex, config.ignore_case)?;
+
+    let separator = if config.null_data { b'\0' } else { b'\n' };
+    let recursive = fs.metadata(Path::new(&config.file_path)).is_ok_and(|info| info.is_dir);
+    let files = config.files_in(fs)?;
+
+    let progress = match config.progress {
+        Some(format) => Tracker::new(format.stderr_reporter(), "searching", Some(files.len() as u64)),
+        None => Tracker::silent(),
     };
+    let mut stats = Table::new(["File", "Matches"]).align(1, Align::Right).max_width(0, 60);
+    let mut total = 0;
+
+    for file in files {
+        progress.inc(1);
+        // Without the mmap feature, or for files that can't be mapped, map() reads them like read()
+        let contents = if config.mmap { fs.map(&file) } else { fs.read(&file).map(FileBytes::from) };
+        let contents = match contents {
+            Ok(contents) => contents,
+            // One unreadable file shouldn't stop a search through a whole directory
+            Err(e) if recursive => {
+                eprintln!("minigrep: {}: {e}", file.display());
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // UTF-16 text is full of NUL bytes, so it's turned into UTF-8 before the binary check. Invalid sequences become U+FFFD,
+        // so this can't fail.
+        let contents = textio::to_utf8(&contents, InvalidPolicy::Replace)?;
+
+        // With --null-data NUL bytes separate the lines, so they say nothing about the file being binary
+        if !config.text && !config.null_data && is_binary(&contents) {
+            eprintln!("minigrep: {}: binary file skipped (use --text to search it)", file.display());
+            continue;
+        }
 
-    for line in results {
-        println!("{line}");
+        let results = backend.search(&contents, separator);
+
+        if !results.is_empty() {
+            stats.add_row([file.display().to_string(), results.len().to_string()]);
+            total += results.len();
+        }
+
+        // The lines are written out as raw bytes, they might not be valid UTF-8
+        for line in results {
+            if recursive {
+                write!(out, "{}:", file.display())?;
+            }
+            out.write_all(line)?;
+            out.write_all(&[separator])?;
+        }
+    }
+    progress.finish();
+
+    // On stderr, so the matching lines on stdout can still be piped into another program
+    if config.stats {
+        stats.add_row([String::from("Total"), total.to_string()]);
+        if common::term::use_color(io::stderr().is_terminal()) {
+            stats = stats.header_style(common::term::Style::new().bold());
+        }
+        eprint!("{stats}");
+    }
+
+    Ok(())
+}
+
+// Replace mode works on text, the regex engine matches chars and a replacement could otherwise cut a multi-byte char in half.
+// Files that aren't UTF-8 are skipped, whatever --text says, because rewriting them would damage them.
+
+fn run_replace(config: &Config, re: &Regex, replacement: &str) -> Result<(), Box<dyn Error>> {
+    if config.null_data {
+        return Err("--null-data can't be combined with --replace".into());
+    }
+
+    let files = config.files()?;
+
+    for file in files {
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("minigrep: {}: not valid UTF-8, skipped", file.display());
+                continue;
+            }
+            Err(e) => return Err(FileError { path: file, other: None, source: e }.into()),
+        };
+
+        let (new_contents, edits) = replace_lines(re, &contents, replacement);
+        if edits.is_empty() {
+            continue;
+        }
+
+        if config.dry_run {
+            print!("{}", diff(&file, &contents, &new_contents));
+        } else {
+            write_in_place(&file, &new_contents, config.backup_suffix.as_deref())?;
+            println!("{}: {} lines changed", file.display(), edits.len());
+        }
+    }
+
+    Ok(())
+}
+
+// Index mode loads the index from its file, or builds and saves it when there is none yet, or when it was built for another path.
+// The index doesn't notice when files change: delete the index file to have it rebuilt.
+// The matching lines are then read back from the files, which only touches the files that have a match.
+
+fn run_index(config: &Config, index_path: &Path) -> Result<(), Box<dyn Error>> {
+    // Checked before building, a typo in the query shouldn't cost a walk over the whole directory
+    let query = Query::parse(&config.query)?;
+    let root = Path::new(&config.file_path);
+
+    let index = match Index::load(index_path) {
+        Ok(index) if index.root() == root => index,
+        _ => {
+            let progress = match config.progress {
+                Some(format) => Tracker::new(format.stderr_reporter(), "indexing", None),
+                None => Tracker::silent(),
+            };
+            let index = Index::build(root, &progress)?;
+            index.save(index_path)?;
+            index
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let postings = index.search(&query);
+    // The postings are sorted by file, so each file is read once for all of its lines
+    for file_postings in postings.chunk_by(|a, b| a.file == b.file) {
+        let file = index.file(file_postings[0].file);
+        let contents = match fs::read(file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("minigrep: {}: {e}", file.display());
+                continue;
+            }
+        };
+
+        let lines: Vec<&[u8]> = split_lines(&contents, b'\n').collect();
+        for posting in file_postings {
+            // A file that got shorter since it was indexed has no such line any more
+            let Some(line) = lines.get(posting.line as usize) else { continue };
+            if root.is_dir() {
+                write!(out, "{}:", file.display())?;
+            }
+            out.write_all(line)?;
+            out.write_all(b"\n")?;
+        }
     }
 
     Ok(())
@@ -259,4 +604,244 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
 // The standard library provides the eprintln! macro that prints to the standard error stream, so let’s change the two places we were calling println! to print errors to use eprintln! instead.
 // ==> Check main.rs for the modifications!
 
+// Searching with a Regular Expression
 
+// The same line-by-line loop as search(), but a line matches when the regex matches anywhere in it.
+// The Regex is compiled once by the caller and reused for every line, compiling it per line would be wasted work.
+
+pub fn search_regex<'a>(re: &Regex, contents: &'a str) -> Vec<&'a str> {
+    contents.lines().filter(|line| re.is_match(line)).collect()
+}
+
+#[cfg(test)]
+mod tests3 {
+    use super::*;
+
+    #[test]
+    fn regex_search() {
+        let re = Regex::new(r"^[A-Z]\w+[.:]$").unwrap();
+        let contents = "Rust:\nsafe, fast, productive.\nPick three.\nDuct tape.";
+        assert_eq!(vec!["Rust:"], search_regex(&re, contents));
+
+        let re = Regex::new("fast|tape").unwrap();
+        assert_eq!(vec!["safe, fast, productive.", "Duct tape."], search_regex(&re, contents));
+    }
+}
+
+
+// Searching Bytes
+
+// Not every file is text. Searching a compiled program or an image would print garbage to the terminal, and fs::read_to_string
+// refuses files that aren't valid UTF-8 anyway. So run reads the raw bytes with fs::read, and the searches below work on &[u8].
+
+// How do we know a file is binary? Text files practically never contain a NUL byte, while most binary formats are full of them.
+// Like grep, we only look at the first chunk of the file, which is enough in practice and keeps the check cheap for big files.
+    // 1. By default binary files are skipped with a notice on stderr.
+    // 2. --text searches them anyway, as if they were text.
+    // 3. --null-data makes NUL the line separator instead of '\n', for output like `find -print0` where every entry ends with a NUL.
+
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+pub fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+// The byte version of str::lines: a separator at the very end doesn't start another (empty) line,
+// and with '\n' as the separator a '\r' before it is dropped as well, so Windows line endings behave the same
+pub fn split_lines(contents: &[u8], separator: u8) -> impl Iterator<Item = &[u8]> {
+    let empty = contents.is_empty();
+    let contents = contents.strip_suffix(&[separator]).unwrap_or(contents);
+    contents
+        .split(move |&b| b == separator)
+        .filter(move |_| !empty)
+        .map(move |line| if separator == b'\n' { line.strip_suffix(b"\r").unwrap_or(line) } else { line })
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+pub fn search_bytes<'a>(query: &[u8], contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+    split_lines(contents, separator).filter(|line| contains_bytes(line, query)).collect()
+}
+
+// Bytes have no idea what language they're in, so only ASCII letters are compared case-insensitively here.
+// search_case_insensitive above goes through to_lowercase and also handles letters like 'É'.
+pub fn search_bytes_case_insensitive<'a>(query: &[u8], contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+    let query = query.to_ascii_lowercase();
+    split_lines(contents, separator)
+        .filter(|line| contains_bytes(&line.to_ascii_lowercase(), &query))
+        .collect()
+}
+
+// All the files below a directory, sorted so that the output is the same on every run
+// Regex matching needs text, so each line is converted first, with any invalid bytes turned into U+FFFD
+pub fn search_bytes_regex<'a>(re: &Regex, contents: &'a [u8], separator: u8) -> Vec<&'a [u8]> {
+    split_lines(contents, separator).filter(|line| re.is_match(&String::from_utf8_lossy(line))).collect()
+}
+
+// collect_files used to recurse into every directory itself, now it's a Walk with the defaults:
+// .gitignore files are honored and symbolic links are left alone, see walk.rs
+pub fn collect_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    Walk::new(dir).files()
+}
+
+#[cfg(test)]
+mod tests4 {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_anywhere() {
+        let config = Config::build(&args(&["minigrep", "--text", "frog", "poem.txt", "--null-data"])).unwrap();
+        assert_eq!("frog", config.query);
+        assert_eq!("poem.txt", config.file_path);
+        assert!(config.text && config.null_data);
+
+        assert!(Config::build(&args(&["minigrep", "--text", "frog"])).is_err());
+        assert!(Config::build(&args(&["minigrep", "--txet", "frog", "poem.txt"])).is_err());
+        assert!(Config::build(&args(&[])).is_err());
+    }
+
+    #[test]
+    fn replace_flags() {
+        let config = Config::build(&args(&["minigrep", "--regex", "(a)", "--replace", "<$1>", "f.txt", "--dry-run"])).unwrap();
+        assert_eq!(Some(String::from("<$1>")), config.replace);
+        assert!(config.regex && config.dry_run);
+        assert_eq!(None, config.backup_suffix);
+
+        let config = Config::build(&args(&["minigrep", "--replace=x", "--backup=.orig", "a", "f.txt"])).unwrap();
+        assert_eq!(Some(String::from("x")), config.replace);
+        assert_eq!(Some(String::from(".orig")), config.backup_suffix);
+        assert_eq!(("a", "f.txt"), (&config.query[..], &config.file_path[..]));
+
+        assert!(Config::build(&args(&["minigrep", "a", "f.txt", "--replace"])).is_err());
+        // (a) has one group, and there are no named ones
+        assert!(Config::build(&args(&["minigrep", "--regex", "(a)", "--replace=$1$0$$2", "f.txt"])).is_ok());
+        let err = Config::build(&args(&["minigrep", "--regex", "(a)", "--replace=$2", "f.txt"])).err().unwrap();
+        assert!(err.to_string().contains("no group 2"), "{err}");
+        assert!(Config::build(&args(&["minigrep", "--regex", "(a)", "--replace=${name}", "f.txt"])).is_err());
+        assert!(Config::build(&args(&["minigrep", "--replace=$1", "a", "f.txt"])).is_err());
+        assert!(Config::build(&args(&["minigrep", "--dry-run", "a", "f.txt"])).is_err());
+    }
+
+    #[test]
+    fn progress_flag() {
+        assert_eq!(None, Config::build(&args(&["minigrep", "a", "dir"])).unwrap().progress);
+        assert_eq!(Some(ProgressFormat::Bar), Config::build(&args(&["minigrep", "--progress", "a", "dir"])).unwrap().progress);
+        assert_eq!(Some(ProgressFormat::Json), Config::build(&args(&["minigrep", "a", "dir", "--progress=json"])).unwrap().progress);
+        assert!(matches!(
+            Config::build(&args(&["minigrep", "--progress=xml", "a", "dir"])),
+            Err(ArgError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn index_flag() {
+        let config = Config::build(&args(&["minigrep", "--index", "/tmp/idx", "rust OR safe", "dir"])).unwrap();
+        assert_eq!(Some(String::from("/tmp/idx")), config.index);
+        assert_eq!(None, Config::build(&args(&["minigrep", "a", "dir"])).unwrap().index);
+        assert!(Config::build(&args(&["minigrep", "--index=idx", "--regex", "a", "dir"])).is_err());
+        assert!(Config::build(&args(&["minigrep", "--stats", "a", "dir"])).unwrap().stats);
+    }
+
+    #[test]
+    fn backend_flag() {
+        assert_eq!(None, Config::build(&args(&["minigrep", "a", "dir"])).unwrap().backend);
+        let config = Config::build(&args(&["minigrep", "--backend", "naive", "a", "dir"])).unwrap();
+        assert_eq!(Some(BackendKind::Naive), config.backend);
+        assert!(matches!(
+            Config::build(&args(&["minigrep", "--backend=grep", "a", "dir"])),
+            Err(ArgError::InvalidValue { .. })
+        ));
+        assert!(Config::build(&args(&["minigrep", "--backend=regex", "--replace=b", "a", "dir"])).is_err());
+    }
+
+    #[test]
+    fn several_patterns() {
+        let config = Config::build(&args(&["minigrep", "-e", "frog", "--regexp=toad", "poem.txt"])).unwrap();
+        assert_eq!(vec!["frog", "toad"], config.patterns);
+        assert_eq!("poem.txt", config.file_path);
+
+        assert!(Config::build(&args(&["minigrep", "frog"])).is_err());
+        assert!(matches!(
+            Config::build(&args(&["minigrep", "-e", "frog", "toad", "poem.txt"])),
+            Err(ArgError::UnexpectedArgument(_))
+        ));
+        assert!(Config::build(&args(&["minigrep", "-e", "frog", "--replace=x", "poem.txt"])).is_err());
+        assert!(Config::build(&args(&["minigrep", "--mmap", "-e", "frog", "poem.txt"])).unwrap().mmap);
+    }
+
+    #[test]
+    fn searches_an_in_memory_file_system() {
+        let fs = samples::mem_fs("samples");
+        fs.add_file("samples/locked.txt", "frog\n").fail("samples/locked.txt", io::ErrorKind::PermissionDenied);
+
+        // The unreadable file is reported on stderr and skipped, the others are still searched
+        let mut out = Vec::new();
+        run_with(&Config::build(&args(&["minigrep", "frog", "samples"])).unwrap(), &fs, &mut out).unwrap();
+        assert_eq!("samples/poem.txt:How public, like a frog\n", String::from_utf8(out).unwrap());
+
+        // A single file that can't be read is an error
+        let config = Config::build(&args(&["minigrep", "frog", "samples/missing.txt"])).unwrap();
+        let error = run_with(&config, &fs, &mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, error.downcast_ref::<io::Error>().unwrap().kind());
+    }
+
+    #[test]
+    fn walk_options() {
+        let config = Config::build(&args(&["minigrep", "--no-ignore", "--max-depth", "2", "-L", "frog", "src"])).unwrap();
+        assert!(config.no_ignore && config.follow);
+        assert_eq!(Some(2), config.max_depth);
+        assert!(Config::build(&args(&["minigrep", "--max-depth=deep", "frog", "src"])).is_err());
+        // A file is searched as it is, whatever the walk options say
+        assert_eq!(vec![PathBuf::from("poem.txt")], Config::build(&args(&["minigrep", "frog", "poem.txt"])).unwrap().files().unwrap());
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(!is_binary(b"plain text\n"));
+        assert!(is_binary(b"\x7fELF\x02\x01\x01\0\0\0"));
+        // A NUL after the first chunk isn't seen
+        let mut late = vec![b'a'; BINARY_CHECK_LEN];
+        late.push(0);
+        assert!(!is_binary(&late));
+    }
+
+    #[test]
+    fn byte_lines() {
+        let lines: Vec<&[u8]> = split_lines(b"one\r\ntwo\n\nthree\n", b'\n').collect();
+        assert_eq!(vec![&b"one"[..], b"two", b"", b"three"], lines);
+        assert_eq!(0, split_lines(b"", b'\n').count());
+        assert_eq!(1, split_lines(b"\n", b'\n').count());
+    }
+
+    #[test]
+    fn searches_bytes() {
+        // Not valid UTF-8, but the matching line is still found
+        let contents = b"caf\xe9 au lait\nRust\n\xff\xfe rust";
+        assert_eq!(vec![&b"Rust"[..]], search_bytes(b"Rust", contents, b'\n'));
+        assert_eq!(vec![&b"Rust"[..], b"\xff\xfe rust"], search_bytes_case_insensitive(b"RUST", contents, b'\n'));
+
+        let entries = b"src/main.rs\0README.md\0src/lib.rs\0";
+        assert_eq!(vec![&b"src/main.rs"[..], b"src/lib.rs"], search_bytes(b"src/", entries, 0));
+
+        let re = Regex::new(r"^\w+$").unwrap();
+        assert_eq!(vec![&b"Rust"[..]], search_bytes_regex(&re, contents, b'\n'));
+    }
+
+    #[test]
+    fn collects_files_recursively() {
+        let dir = std::env::temp_dir().join(format!("minigrep-walk-{}", std::process::id()));
+        fs::create_dir_all(dir.join("b/c")).unwrap();
+        fs::write(dir.join("b/c/deep.txt"), "x").unwrap();
+        fs::write(dir.join("a.txt"), "x").unwrap();
+
+        let files = collect_files(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(vec![dir.join("a.txt"), dir.join("b/c/deep.txt")], files);
+    }
+}
@@ -291,19 +291,33 @@ impl ConfigX {
 
 
 // We add a use minigrep::Config line to bring the Config type from the library crate into the binary crate’s scope
-use minigrep::Config;
+use minigrep::{argparse::ArgError, Config};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let config = Config::build(&args).unwrap_or_else(|err| {
+        // --help isn't a problem, it's the user asking for the help text, which goes to stdout
+        if let ArgError::Help(help) = err {
+            print!("{help}");
+            process::exit(0);
+        }
         eprintln!("Problem parsing arguments: {err}");
+        eprintln!("Try 'minigrep --help' for more information.");
         process::exit(1);
     });
 
     // println!("Searching for {}", config.query);
     // println!("In file {}", config.file_path);
 
+    // Those two lines are still there for debugging, through the logger of projects/common: MINIGREP_LOG=debug minigrep ...
+    // They go to stderr like every log line, so they never end up in the output that gets piped somewhere.
+    if let Err(e) = common::log::init_from_env("MINIGREP_LOG") {
+        eprintln!("Problem parsing arguments: {e}");
+        process::exit(1);
+    }
+    common::debug!("searching for {:?} in {}", config.query, config.file_path);
+
     if let Err(e) = minigrep::run(config) {
         eprintln!("Application error: {e}");
         process::exit(1);
@@ -0,0 +1,938 @@
+// A Small Regular Expression Engine
+
+// Real grep searches for regular expressions rather than plain strings. The regex crate does this very well, but writing a small engine ourselves shows how it works.
+
+// Supported syntax:
+    // Literals: abc, and escaped metacharacters like \. \* \( \\
+    // Any char: .
+    // Classes: [abc] [a-z0-9] [^"] and the shorthands \d \w \s (plus \D \W \S)
+    // Repetition: * (zero or more), + (one or more), ? (zero or one)
+    // Alternation and groups: cat|dog, (ab)+, where groups also capture what they matched
+    // Anchors: ^ (start of the text) and $ (end of the text)
+
+// The engine works in three steps:
+    // 1. Parsing turns the pattern string into a tree (Ast) and reports syntax errors with their position.
+    // 2. Compiling turns the tree into a program of simple instructions, which is a non-deterministic finite automaton (NFA).
+    //    This is Ken Thompson's construction: every piece of syntax becomes a small fragment of instructions, and fragments are glued together with jumps and splits.
+    // 3. Matching runs the program on the input. Instead of trying one path and backtracking when it fails (which can take exponential time),
+    //    we follow every possible path at once, one input char at a time. That's the Pike VM, and it always runs in O(pattern length * input length).
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    UnexpectedEnd,
+    UnclosedGroup(usize),
+    UnopenedGroup(usize),
+    UnclosedClass(usize),
+    InvalidRange(usize),
+    NothingToRepeat(usize),
+    TooDeep(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnexpectedEnd => write!(f, "pattern ends with an unfinished escape"),
+            Error::UnclosedGroup(pos) => write!(f, "group opened at {pos} is never closed"),
+            Error::UnopenedGroup(pos) => write!(f, "unmatched ')' at {pos}"),
+            Error::UnclosedClass(pos) => write!(f, "character class opened at {pos} is never closed"),
+            Error::InvalidRange(pos) => write!(f, "invalid range in character class at {pos}"),
+            Error::NothingToRepeat(pos) => write!(f, "repetition operator at {pos} has nothing to repeat"),
+            Error::TooDeep(pos) => write!(f, "group at {pos} is nested more than {MAX_NESTING} deep"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Parsing
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ast {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Group(Box<Ast>, usize),
+    Concat(Vec<Ast>),
+    Alternate(Vec<Ast>),
+    Star(Box<Ast>),
+    Plus(Box<Ast>),
+    Question(Box<Ast>),
+}
+
+// A recursive descent parser, with one function per precedence level:
+// alternation (lowest) is made of concatenations, which are made of repeated atoms (highest).
+// Every group goes one level deeper into the recursion, and so does compiling it later. A pattern of ten thousand '('
+// would overflow the stack, which aborts the whole program instead of returning an error, so nesting has a limit.
+const MAX_NESTING: usize = 100;
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn parse_alternation(&mut self) -> Result<Ast, Error> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Ast::Alternate(branches) })
+    }
+
+    fn parse_concat(&mut self) -> Result<Ast, Error> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            items.push(self.parse_repeat()?);
+        }
+        Ok(match items.len() {
+            0 => Ast::Empty,
+            1 => items.pop().unwrap(),
+            _ => Ast::Concat(items),
+        })
+    }
+
+    fn parse_repeat(&mut self) -> Result<Ast, Error> {
+        let atom = self.parse_atom()?;
+        match self.peek() {
+            Some(op @ ('*' | '+' | '?')) => {
+                // Repeating an anchor is meaningless, and a second operator (a**) is probably a typo
+                if matches!(atom, Ast::Start | Ast::End) {
+                    return Err(Error::NothingToRepeat(self.pos));
+                }
+                self.pos += 1;
+                if matches!(self.peek(), Some('*' | '+' | '?')) {
+                    return Err(Error::NothingToRepeat(self.pos));
+                }
+                let atom = Box::new(atom);
+                Ok(match op {
+                    '*' => Ast::Star(atom),
+                    '+' => Ast::Plus(atom),
+                    _ => Ast::Question(atom),
+                })
+            }
+            _ => Ok(atom),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast, Error> {
+        let start = self.pos;
+        match self.next().unwrap() {
+            '.' => Ok(Ast::Any),
+            '^' => Ok(Ast::Start),
+            '$' => Ok(Ast::End),
+            '*' | '+' | '?' => Err(Error::NothingToRepeat(start)),
+            '[' => self.parse_class(start),
+            '\\' => self.parse_escape(),
+            '(' => {
+                if self.depth == MAX_NESTING {
+                    return Err(Error::TooDeep(start));
+                }
+                self.groups += 1;
+                let index = self.groups;
+                self.depth += 1;
+                let inner = self.parse_alternation()?;
+                self.depth -= 1;
+                if self.next() != Some(')') {
+                    return Err(Error::UnclosedGroup(start));
+                }
+                Ok(Ast::Group(Box::new(inner), index))
+            }
+            c => Ok(Ast::Literal(c)),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Ast, Error> {
+        let c = self.next().ok_or(Error::UnexpectedEnd)?;
+        Ok(match shorthand_class(c) {
+            Some(class) => Ast::Class(class),
+            None => Ast::Literal(escaped_char(c)),
+        })
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<Ast, Error> {
+        let mut class = Class { ranges: Vec::new(), negated: false };
+        if self.peek() == Some('^') {
+            self.pos += 1;
+            class.negated = true;
+        }
+
+        // A ']' right at the start is a literal, so that []abc] works like in other regex engines
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or(Error::UnclosedClass(start))?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                let e = self.next().ok_or(Error::UnclosedClass(start))?;
+                if let Some(shorthand) = shorthand_class(e) {
+                    // A negated shorthand like \D inside a class would need set subtraction, we only merge the positive ones
+                    if shorthand.negated {
+                        return Err(Error::InvalidRange(self.pos - 1));
+                    }
+                    class.ranges.extend(shorthand.ranges);
+                    continue;
+                }
+                escaped_char(e)
+            } else {
+                c
+            };
+
+            // a-z is a range, but a '-' right before the closing ']' is just a dash
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|c| *c != ']') {
+                self.pos += 1;
+                let mut hi = self.next().ok_or(Error::UnclosedClass(start))?;
+                if hi == '\\' {
+                    hi = escaped_char(self.next().ok_or(Error::UnclosedClass(start))?);
+                }
+                if hi < lo {
+                    return Err(Error::InvalidRange(self.pos - 1));
+                }
+                class.ranges.push((lo, hi));
+            } else {
+                class.ranges.push((lo, lo));
+            }
+        }
+        Ok(Ast::Class(class))
+    }
+}
+
+fn escaped_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        other => other,
+    }
+}
+
+fn shorthand_class(c: char) -> Option<Class> {
+    let (ranges, negated) = match c {
+        'd' => (vec![('0', '9')], false),
+        'D' => (vec![('0', '9')], true),
+        'w' => (vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], false),
+        'W' => (vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], true),
+        's' => (vec![(' ', ' '), ('\t', '\r')], false),
+        'S' => (vec![(' ', ' '), ('\t', '\r')], true),
+        _ => return None,
+    };
+    Some(Class { ranges, negated })
+}
+
+// Compiling
+
+// Split(x, y) means "continue at both x and y", with x preferred. That preference is what makes * greedy and the left side of | win.
+// Save(n) records the current position in capture slot n: slots 0 and 1 hold the whole match, 2 and 3 the first group, and so on.
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    AssertStart,
+    AssertEnd,
+    Split(usize, usize),
+    Jmp(usize),
+    Save(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    // Jump targets are often not known when an instruction is emitted, so we emit a placeholder and patch it afterwards
+    fn patch(&mut self, at: usize, inst: Inst) {
+        self.program[at] = inst;
+    }
+
+    fn compile(&mut self, ast: &Ast) {
+        match ast {
+            Ast::Empty => {}
+            Ast::Literal(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Ast::Any => {
+                self.emit(Inst::Any);
+            }
+            Ast::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Ast::Start => {
+                self.emit(Inst::AssertStart);
+            }
+            Ast::End => {
+                self.emit(Inst::AssertEnd);
+            }
+            Ast::Group(inner, index) => {
+                self.emit(Inst::Save(index * 2));
+                self.compile(inner);
+                self.emit(Inst::Save(index * 2 + 1));
+            }
+            Ast::Concat(items) => {
+                for item in items {
+                    self.compile(item);
+                }
+            }
+            // split L1, next; L1: first; jmp end; next: (the remaining branches)
+            Ast::Alternate(branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(branch);
+                        jumps.push(self.emit(Inst::Jmp(0)));
+                        let next = self.program.len();
+                        self.patch(split, Inst::Split(split + 1, next));
+                    } else {
+                        self.compile(branch);
+                    }
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.patch(jump, Inst::Jmp(end));
+                }
+            }
+            // L: split body, end; body: inner; jmp L; end:
+            Ast::Star(inner) => {
+                let split = self.emit(Inst::Split(0, 0));
+                self.compile(inner);
+                self.emit(Inst::Jmp(split));
+                let end = self.program.len();
+                self.patch(split, Inst::Split(split + 1, end));
+            }
+            // L: inner; split L, end; end:
+            Ast::Plus(inner) => {
+                let start = self.program.len();
+                self.compile(inner);
+                let split = self.emit(Inst::Split(start, 0));
+                self.patch(split, Inst::Split(start, split + 1));
+            }
+            // split body, end; body: inner; end:
+            Ast::Question(inner) => {
+                let split = self.emit(Inst::Split(0, 0));
+                self.compile(inner);
+                let end = self.program.len();
+                self.patch(split, Inst::Split(split + 1, end));
+            }
+        }
+    }
+}
+
+// Matching with the Pike VM
+
+type Slots = Vec<Option<usize>>;
+
+// The list of threads alive at one input position. Each thread is a program counter plus its capture slots.
+// `seen` makes sure a pc is only added once per position: the first (highest priority) thread to reach it wins,
+// and it also stops infinite loops through empty repetitions like (a*)*.
+struct ThreadList {
+    threads: Vec<(usize, Slots)>,
+    seen: Vec<bool>,
+    // The work left for add_thread, kept here so that its memory is reused
+    stack: Vec<Follow>,
+}
+
+// What add_thread still has to do: follow the instruction at a pc, or put back a capture slot that a Save changed
+enum Follow {
+    Pc(usize),
+    Restore(usize, Option<usize>),
+}
+
+impl ThreadList {
+    fn new(size: usize) -> ThreadList {
+        ThreadList { threads: Vec::new(), seen: vec![false; size], stack: Vec::new() }
+    }
+
+    fn clear(&mut self) {
+        self.threads.clear();
+        self.seen.iter_mut().for_each(|s| *s = false);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    slots: usize,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, groups: 0, depth: 0 };
+        let ast = parser.parse_alternation()?;
+        if parser.pos < parser.chars.len() {
+            // parse_alternation only stops early at a ')' that has no matching '('
+            return Err(Error::UnopenedGroup(parser.pos));
+        }
+
+        let mut compiler = Compiler { program: Vec::new() };
+        compiler.emit(Inst::Save(0));
+        compiler.compile(&ast);
+        compiler.emit(Inst::Save(1));
+        compiler.emit(Inst::Match);
+
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            program: compiler.program,
+            slots: (parser.groups + 1) * 2,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // How many groups a match has, the whole match as group 0 included, like Captures::len
+    pub fn captures_len(&self) -> usize {
+        self.slots / 2
+    }
+
+    // Makes sure every group the replacement refers to is one the pattern has, with the syntax of Captures::expand.
+    // expand() turns a group that isn't there into nothing, which is a typo nobody notices until the files are rewritten.
+    // There are no named groups in this engine, so ${name} never refers to one
+    pub fn check_replacement(&self, replacement: &str) -> Result<(), String> {
+        let mut rest = replacement;
+        while let Some(i) = rest.find('$') {
+            rest = &rest[i + 1..];
+            let group = if let Some(braced) = rest.strip_prefix('{') {
+                let Some(close) = braced.find('}') else { continue };
+                rest = &braced[close + 1..];
+                match braced[..close].parse::<usize>() {
+                    Ok(group) => group,
+                    Err(_) => return Err(format!("the pattern has no group named {}", &braced[..close])),
+                }
+            } else if let Some(after) = rest.strip_prefix('$') {
+                rest = after;
+                continue;
+            } else {
+                match rest.chars().next().and_then(|c| c.to_digit(10)) {
+                    Some(digit) => {
+                        rest = &rest[1..];
+                        digit as usize
+                    }
+                    None => continue,
+                }
+            };
+            if group >= self.captures_len() {
+                return Err(format!("the pattern has no group {group}, its groups go up to {}", self.captures_len() - 1));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    // Byte offsets of the leftmost match, with the usual leftmost-first (Perl style) choice between alternatives
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0).map(|slots| (slots[0].unwrap(), slots[1].unwrap()))
+    }
+
+    pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+        self.find_at(text, 0).map(|slots| Captures { text, slots })
+    }
+
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> FindIter<'r, 't> {
+        FindIter { regex: self, text, pos: 0 }
+    }
+
+    // Replaces every match, where the replacement can refer to the groups of the match, see Captures::expand
+    pub fn replace_all(&self, text: &str, replacement: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        let mut pos = 0;
+        while pos <= text.len() {
+            let Some(slots) = self.find_at(text, pos) else { break };
+            let (start, end) = (slots[0].unwrap(), slots[1].unwrap());
+            out.push_str(&text[last..start]);
+            Captures { text, slots }.expand(replacement, &mut out);
+            last = end;
+            // The same step over an empty match as in FindIter
+            pos = if end == start { end + text[end..].chars().next().map_or(1, |c| c.len_utf8()) } else { end };
+        }
+        out.push_str(&text[last..]);
+        out
+    }
+
+    // Follows every Split and Jmp from pc right away, so that the list only contains threads waiting on input (or Match).
+    // This is a depth first search, x before y at every Split for the priorities. It keeps its own stack instead of calling
+    // itself: "a?a?a?..." is a chain of Splits as long as the pattern, and recursing down it would overflow the real stack.
+    fn add_thread(&self, list: &mut ThreadList, pc: usize, pos: usize, text: &str, slots: &mut Slots) {
+        let mut stack = std::mem::take(&mut list.stack);
+        stack.push(Follow::Pc(pc));
+        while let Some(follow) = stack.pop() {
+            let pc = match follow {
+                Follow::Pc(pc) => pc,
+                Follow::Restore(n, old) => {
+                    slots[n] = old;
+                    continue;
+                }
+            };
+            if list.seen[pc] {
+                continue;
+            }
+            list.seen[pc] = true;
+
+            match &self.program[pc] {
+                Inst::Jmp(target) => stack.push(Follow::Pc(*target)),
+                // Pushed in reverse, so that x comes off the stack first
+                Inst::Split(x, y) => {
+                    stack.push(Follow::Pc(*y));
+                    stack.push(Follow::Pc(*x));
+                }
+                // The slot is put back once everything reachable from pc + 1 was followed with the new value
+                Inst::Save(n) => {
+                    stack.push(Follow::Restore(*n, slots[*n]));
+                    slots[*n] = Some(pos);
+                    stack.push(Follow::Pc(pc + 1));
+                }
+                Inst::AssertStart => {
+                    if pos == 0 {
+                        stack.push(Follow::Pc(pc + 1));
+                    }
+                }
+                Inst::AssertEnd => {
+                    if pos == text.len() {
+                        stack.push(Follow::Pc(pc + 1));
+                    }
+                }
+                _ => list.threads.push((pc, slots.clone())),
+            }
+        }
+        list.stack = stack;
+    }
+
+    fn find_at(&self, text: &str, start: usize) -> Option<Slots> {
+        let mut current = ThreadList::new(self.program.len());
+        let mut next = ThreadList::new(self.program.len());
+        let mut matched: Option<Slots> = None;
+        let mut pos = start;
+
+        loop {
+            // Start a new attempt at this position, unless we already found a match further left.
+            // It's added after the existing threads, so attempts that started earlier keep their higher priority.
+            if matched.is_none() {
+                let mut slots = vec![None; self.slots];
+                self.add_thread(&mut current, 0, pos, text, &mut slots);
+            }
+            if current.threads.is_empty() && matched.is_some() {
+                break;
+            }
+
+            let c = text[pos..].chars().next();
+            let next_pos = pos + c.map_or(0, |c| c.len_utf8());
+
+            for (pc, mut slots) in current.threads.drain(..) {
+                let advance = match (&self.program[pc], c) {
+                    (Inst::Match, _) => {
+                        // Every thread after this one has a lower priority, so they are cut off
+                        matched = Some(slots);
+                        break;
+                    }
+                    (Inst::Char(expected), Some(c)) => *expected == c,
+                    (Inst::Any, Some(c)) => c != '\n',
+                    (Inst::Class(class), Some(c)) => class.matches(c),
+                    _ => false,
+                };
+                if advance {
+                    self.add_thread(&mut next, pc + 1, next_pos, text, &mut slots);
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            pos = next_pos;
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        matched
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+// The text matched by the whole pattern (group 0) and by each parenthesised group
+pub struct Captures<'t> {
+    text: &'t str,
+    slots: Slots,
+}
+
+impl<'t> Captures<'t> {
+    pub fn get(&self, group: usize) -> Option<&'t str> {
+        let start = (*self.slots.get(group * 2)?)?;
+        let end = (*self.slots.get(group * 2 + 1)?)?;
+        Some(&self.text[start..end])
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Appends the replacement to out, with $0 to $9 (or ${12} for bigger numbers) replaced by the text of that group.
+    // A group that didn't take part in the match is replaced by nothing, and $$ is a literal dollar sign.
+    pub fn expand(&self, replacement: &str, out: &mut String) {
+        let mut rest = replacement;
+        while let Some(i) = rest.find('$') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            let (number, len) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(close) => (braced[..close].parse::<usize>().ok(), close + 2),
+                    None => (None, 0),
+                }
+            } else if rest.starts_with('$') {
+                out.push('$');
+                rest = &rest[1..];
+                continue;
+            } else {
+                (rest.chars().next().and_then(|c| c.to_digit(10)).map(|d| d as usize), 1)
+            };
+
+            match number {
+                Some(group) => {
+                    out.push_str(self.get(group).unwrap_or(""));
+                    rest = &rest[len..];
+                }
+                // Anything else after a '$' is kept as it is
+                None => out.push('$'),
+            }
+        }
+        out.push_str(rest);
+    }
+}
+
+// Escapes the metacharacters of a plain string, so that the regex matches exactly that string
+pub fn escape(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if "\\.*+?()|[]^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub struct FindIter<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    pos: usize,
+}
+
+impl<'t> Iterator for FindIter<'_, 't> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        if self.pos > self.text.len() {
+            return None;
+        }
+        let slots = self.regex.find_at(self.text, self.pos)?;
+        let (start, end) = (slots[0].unwrap(), slots[1].unwrap());
+
+        // After an empty match we must step over one char, or we would find the same empty match forever
+        self.pos = if end == start {
+            end + self.text[end..].chars().next().map_or(1, |c| c.len_utf8())
+        } else {
+            end
+        };
+        Some(&self.text[start..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'t>(pattern: &str, text: &'t str) -> Option<&'t str> {
+        Regex::new(pattern).unwrap().find(text).map(|(s, e)| &text[s..e])
+    }
+
+    #[test]
+    fn literals_and_dot() {
+        assert_eq!(Some("duct"), find("duct", "safe, fast, productive."));
+        assert_eq!(Some("fast"), find("f..t", "safe, fast"));
+        assert_eq!(None, find("a.c", "a\nc"));
+        assert_eq!(Some("a.c"), find(r"a\.c", "abc a.c"));
+        assert_eq!(Some(""), find("", "anything"));
+    }
+
+    #[test]
+    fn repetition_is_greedy() {
+        assert_eq!(Some("aaa"), find("a+", "baaab"));
+        assert_eq!(Some(""), find("a*", "baaab"));
+        assert_eq!(Some("baaa"), find("ba*", "baaab"));
+        assert_eq!(Some("colour"), find("colou?r", "colour"));
+        assert_eq!(Some("color"), find("colou?r", "color"));
+        assert_eq!(Some("<a><b>"), find("<.*>", "<a><b>"));
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(Some("2024"), find("[0-9]+", "year 2024!"));
+        assert_eq!(Some("2024"), find(r"\d+", "year 2024!"));
+        assert_eq!(Some("hello_42"), find(r"\w+", "  hello_42 "));
+        assert_eq!(Some("year"), find("[^ ]+", "year 2024"));
+        assert_eq!(Some("a-b"), find("[a-]+b", "a-b"));
+        assert_eq!(Some("]]"), find("[]]+", "x]]x"));
+        assert_eq!(Some("ünï"), find("[ü-ÿ]n[ì-ï]", "ünï"));
+        assert_eq!(Some("\t "), find(r"\s+", "a\t b"));
+    }
+
+    #[test]
+    fn alternation_and_groups() {
+        assert_eq!(Some("dog"), find("cat|dog", "hotdog"));
+        assert_eq!(Some("ababab"), find("(ab)+", "xabababa"));
+        // Leftmost-first: the first alternative wins even though the second is longer
+        assert_eq!(Some("a"), find("a|ab", "ab"));
+        assert_eq!(Some("abcd"), find("(a|ab)(c|bcd)", "abcd"));
+        assert_eq!(Some("grey"), find("gr(a|e)y", "grey gray"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(Some("Rust"), find("^Rust", "Rust: trust"));
+        assert_eq!(None, find("^rust", "trust"));
+        assert_eq!(Some("three."), find(r"three\.$", "Pick three."));
+        assert_eq!(Some(""), find("^$", ""));
+        assert_eq!(None, find("a^b", "ab"));
+    }
+
+    #[test]
+    fn captures() {
+        let re = Regex::new(r"(\w+)@(\w+)\.com").unwrap();
+        let caps = re.captures("mail ferris@rust.com now").unwrap();
+        assert_eq!(3, caps.len());
+        assert_eq!(Some("ferris@rust.com"), caps.get(0));
+        assert_eq!(Some("ferris"), caps.get(1));
+        assert_eq!(Some("rust"), caps.get(2));
+        assert_eq!(None, caps.get(3));
+
+        // A group that didn't take part in the match has no value
+        let caps = Regex::new("(a)|(b)").unwrap().captures("b").unwrap();
+        assert_eq!(None, caps.get(1));
+        assert_eq!(Some("b"), caps.get(2));
+    }
+
+    #[test]
+    fn find_iter_handles_empty_matches() {
+        let re = Regex::new(r"\d+").unwrap();
+        assert_eq!(vec!["1", "22", "333"], re.find_iter("a1b22c333").collect::<Vec<_>>());
+
+        let re = Regex::new("x*").unwrap();
+        assert_eq!(vec!["", "xx", "", ""], re.find_iter("axxbé").collect::<Vec<_>>()[..4]);
+    }
+
+    #[test]
+    fn nested_empty_loops_terminate() {
+        assert_eq!(Some("aaa"), find("(a*)*", "aaa"));
+        assert_eq!(Some(""), find("(a?)+", "b"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(Error::UnclosedGroup(0), Regex::new("(ab").unwrap_err());
+        assert_eq!(Error::UnopenedGroup(2), Regex::new("ab)").unwrap_err());
+        assert_eq!(Error::UnclosedClass(1), Regex::new("a[bc").unwrap_err());
+        assert_eq!(Error::InvalidRange(3), Regex::new("[z-a]").unwrap_err());
+        assert_eq!(Error::NothingToRepeat(0), Regex::new("*a").unwrap_err());
+        assert_eq!(Error::NothingToRepeat(2), Regex::new("a**").unwrap_err());
+        assert_eq!(Error::UnexpectedEnd, Regex::new("ab\\").unwrap_err());
+        assert_eq!(Error::TooDeep(MAX_NESTING), Regex::new(&"(".repeat(10_000)).unwrap_err());
+    }
+
+    // Both used to overflow the stack: the parser on deep nesting, the VM on a long chain of Splits
+    #[test]
+    fn long_patterns() {
+        let nested = format!("{}a{}", "(".repeat(MAX_NESTING), ")".repeat(MAX_NESTING));
+        assert_eq!(Some("a"), find(&nested, "bab"));
+        let optional = "a?".repeat(20_000);
+        assert_eq!(Some("aaab"), find(&format!("{optional}b"), "aaab"));
+    }
+
+    // Fuzz-style testing
+
+    // A tiny xorshift random generator, so the random tests are reproducible without pulling in the rand crate
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn random_pattern(rng: &mut Rng, depth: usize) -> String {
+        let atoms = ["a", "b", ".", "[ab]", "[^a]", "^", "$"];
+        let mut out = String::new();
+        for _ in 0..1 + rng.below(3) {
+            let mut piece = if depth > 0 && rng.below(4) == 0 {
+                let inner = random_pattern(rng, depth - 1);
+                if rng.below(2) == 0 {
+                    format!("({inner})")
+                } else {
+                    format!("({inner}|{})", random_pattern(rng, depth - 1))
+                }
+            } else {
+                atoms[rng.below(atoms.len())].to_string()
+            };
+            if piece != "^" && piece != "$" {
+                piece.push_str(["", "", "*", "+", "?"][rng.below(5)]);
+            }
+            out.push_str(&piece);
+        }
+        out
+    }
+
+    // The oracle: a slow but obviously correct backtracking matcher over the Ast that returns every possible end position.
+    fn ends(ast: &Ast, text: &[char], pos: usize) -> Vec<usize> {
+        let step = |ok: bool| if ok { vec![pos + 1] } else { vec![] };
+        let mut result = match ast {
+            Ast::Empty => vec![pos],
+            Ast::Literal(c) => step(text.get(pos) == Some(c)),
+            Ast::Any => step(text.get(pos).is_some_and(|c| *c != '\n')),
+            Ast::Class(class) => step(text.get(pos).is_some_and(|c| class.matches(*c))),
+            Ast::Start => if pos == 0 { vec![pos] } else { vec![] },
+            Ast::End => if pos == text.len() { vec![pos] } else { vec![] },
+            Ast::Group(inner, _) => ends(inner, text, pos),
+            Ast::Concat(items) => items.iter().fold(vec![pos], |positions, item| {
+                positions.iter().flat_map(|p| ends(item, text, *p)).collect()
+            }),
+            Ast::Alternate(branches) => branches.iter().flat_map(|b| ends(b, text, pos)).collect(),
+            Ast::Question(inner) => {
+                let mut v = vec![pos];
+                v.extend(ends(inner, text, pos));
+                v
+            }
+            Ast::Star(inner) | Ast::Plus(inner) => {
+                let mut reached = if matches!(ast, Ast::Star(_)) { vec![pos] } else { vec![] };
+                let mut frontier = ends(inner, text, pos);
+                while let Some(p) = frontier.pop() {
+                    if !reached.contains(&p) {
+                        reached.push(p);
+                        frontier.extend(ends(inner, text, p));
+                    }
+                }
+                reached
+            }
+        };
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    #[test]
+    fn random_patterns_agree_with_backtracking_oracle() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+
+        for _ in 0..2000 {
+            let pattern = random_pattern(&mut rng, 2);
+            let text: String = (0..rng.below(7)).map(|_| ['a', 'b', 'c'][rng.below(3)]).collect();
+
+            let re = Regex::new(&pattern).unwrap();
+            let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, groups: 0, depth: 0 };
+            let ast = parser.parse_alternation().unwrap();
+            let chars: Vec<char> = text.chars().collect();
+
+            // The leftmost start position where the oracle finds any match (the text is ASCII, so char and byte positions agree)
+            let expected_start = (0..=chars.len()).find(|&s| !ends(&ast, &chars, s).is_empty());
+            let found = re.find(&text);
+            assert_eq!(expected_start, found.map(|(s, _)| s), "pattern {pattern:?} on {text:?}");
+
+            if let Some((s, e)) = found {
+                assert!(ends(&ast, &chars, s).contains(&e), "pattern {pattern:?} on {text:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn random_garbage_never_panics() {
+        let alphabet: Vec<char> = "ab()[]^$*+?|.\\-dw".chars().collect();
+        let mut rng = Rng(12345);
+
+        for _ in 0..5000 {
+            let pattern: String = (0..rng.below(10)).map(|_| alphabet[rng.below(alphabet.len())]).collect();
+            if let Ok(re) = Regex::new(&pattern) {
+                re.is_match("ab(c)d-w");
+                re.find_iter("a]b[").count();
+            }
+        }
+    }
+
+    #[test]
+    fn replacing_with_groups() {
+        let re = Regex::new(r"(\w+)@(\w+)").unwrap();
+        assert_eq!("ferris at rust, bob at home", re.replace_all("ferris@rust, bob@home", "$1 at $2"));
+        assert_eq!("[ferris@rust] $5 $x ${", re.replace_all("ferris@rust $5 $x ${", "[$0]"));
+        assert_eq!("$rust", re.replace_all("a@rust", "$$$2"));
+
+        let re = Regex::new("(a)|(b)").unwrap();
+        // A group that didn't take part in the match expands to nothing
+        assert_eq!("1a2 12 12 1b2", re.replace_all("a b", "1${1}2 1${2}2"));
+
+        // Empty matches put the replacement between every char
+        assert_eq!("-a-é-", Regex::new("x*").unwrap().replace_all("aé", "-"));
+    }
+
+    #[test]
+    fn escaping() {
+        let literal = "1+1=2? (a|b) [x] $5.00 \\o/ ^_^";
+        let re = Regex::new(&escape(literal)).unwrap();
+        assert_eq!(Some((4, 4 + literal.len())), re.find(&format!("see {literal}")));
+        assert!(!re.is_match("11=2"));
+    }
+}
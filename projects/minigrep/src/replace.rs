@@ -0,0 +1,224 @@
+// Replace Mode
+
+// With --replace, minigrep stops printing matching lines and rewrites them instead, a small part of what sed does:
+// $ cargo run -- --regex --replace '$2, $1' '(\w+) (\w+)' names.txt
+
+// Every match on a line is replaced, and the replacement can use the groups of the regex ($1, $2, ...), see Captures::expand.
+// A group the regex doesn't have is an error when the arguments are parsed, see Regex::check_replacement.
+// A plain (non regex) query is escaped first, so it can go through the same code.
+
+// Rewriting files is where a small mistake loses someone's work, so the writing is careful:
+    // 1. --dry-run only prints what would change, as a unified diff, and leaves the files alone.
+    // 2. The new contents are written to a temporary file next to the original, flushed to disk, and then renamed over it.
+    //    A rename within a directory is atomic, so if anything fails halfway the original file is still there, untouched.
+    // 3. --backup keeps a copy of the original as file.bak (or with the suffix given by --backup=SUFFIX).
+
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{diff::unified, regex_lite::Regex};
+
+// One changed line, numbered from 1 like editors do
+#[derive(Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub line_number: usize,
+    pub old: String,
+    pub new: String,
+}
+
+// An I/O error on its own says "Permission denied", but not about which file, so we keep the path with it.
+// path is always the file being replaced, and other the temporary file or the backup when it was one of them that failed:
+// "poem.txt: .poem.txt.minigrep-42.tmp: No space left on device" tells whose temporary file it was.
+#[derive(Debug)]
+pub struct FileError {
+    pub path: PathBuf,
+    pub other: Option<PathBuf>,
+    pub source: io::Error,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.other {
+            Some(other) => write!(f, "{}: {}: {}", self.path.display(), other.display(), self.source),
+            None => write!(f, "{}: {}", self.path.display(), self.source),
+        }
+    }
+}
+
+impl Error for FileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn file_error<'a>(path: &'a Path, other: Option<&'a Path>) -> impl FnOnce(io::Error) -> FileError + 'a {
+    move |source| FileError { path: path.to_path_buf(), other: other.map(Path::to_path_buf), source }
+}
+
+// Returns the new contents and the lines that changed. Line endings (\n or \r\n) are kept as they were,
+// and so is a missing newline at the end of the file.
+pub fn replace_lines(re: &Regex, contents: &str, replacement: &str) -> (String, Vec<Edit>) {
+    let mut out = String::with_capacity(contents.len());
+    let mut edits = Vec::new();
+
+    for (i, line) in contents.split_inclusive('\n').enumerate() {
+        let text = line.trim_end_matches(['\n', '\r']);
+        let ending = &line[text.len()..];
+
+        if re.is_match(text) {
+            let new = re.replace_all(text, replacement);
+            if new != text {
+                edits.push(Edit { line_number: i + 1, old: text.to_string(), new: new.clone() });
+            }
+            out.push_str(&new);
+        } else {
+            out.push_str(text);
+        }
+        out.push_str(ending);
+    }
+
+    (out, edits)
+}
+
+// The diff that --dry-run prints, in the unified format with 3 lines of context like `diff -u`, see diff.rs
+pub fn diff(path: &Path, old: &str, new: &str) -> String {
+    let name = path.display().to_string();
+    unified(&name, &name, old, new, 3)
+}
+
+// Replaces the file's contents atomically, see the steps at the top of this file
+pub fn write_in_place(path: &Path, contents: &str, backup_suffix: Option<&str>) -> Result<(), FileError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| file_error(path, None)(io::Error::new(io::ErrorKind::InvalidInput, "not a file")))?;
+
+    // The temporary file must be in the same directory, a rename can't move a file to another filesystem
+    let temp = dir.join(format!(".{}.minigrep-{}.tmp", name.to_string_lossy(), std::process::id()));
+
+    let result = (|| {
+        let permissions = fs::metadata(path).map_err(file_error(path, None))?.permissions();
+
+        let mut file = fs::File::create(&temp).map_err(file_error(path, Some(&temp)))?;
+        file.write_all(contents.as_bytes()).map_err(file_error(path, Some(&temp)))?;
+        // Without sync_all the rename could reach the disk before the data does, and a crash would leave an empty file
+        file.sync_all().map_err(file_error(path, Some(&temp)))?;
+        fs::set_permissions(&temp, permissions).map_err(file_error(path, Some(&temp)))?;
+
+        if let Some(suffix) = backup_suffix {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(suffix);
+            let backup = PathBuf::from(backup);
+            fs::copy(path, &backup).map_err(file_error(path, Some(&backup)))?;
+        }
+
+        fs::rename(&temp, path).map_err(file_error(path, None))
+    })();
+
+    if result.is_err() {
+        // Best effort, the temp file may not even exist. The original error is the one worth reporting.
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex_lite::escape;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("minigrep-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn replaces_matching_lines() {
+        let re = Regex::new(r"(\w+) (\w+)").unwrap();
+        let (out, edits) = replace_lines(&re, "ada lovelace\r\n\n-- \ngrace hopper", "$2, $1");
+
+        assert_eq!("lovelace, ada\r\n\n-- \nhopper, grace", out);
+        assert_eq!(
+            vec![
+                Edit { line_number: 1, old: String::from("ada lovelace"), new: String::from("lovelace, ada") },
+                Edit { line_number: 4, old: String::from("grace hopper"), new: String::from("hopper, grace") },
+            ],
+            edits
+        );
+    }
+
+    #[test]
+    fn literal_queries_and_unchanged_lines() {
+        let re = Regex::new(&escape("a.b")).unwrap();
+        let (out, edits) = replace_lines(&re, "a.b\naxb\na.b\n", "$$");
+        assert_eq!("$\naxb\n$\n", out);
+        assert_eq!(2, edits.len());
+
+        // A match whose replacement is the same text isn't an edit
+        let (_, edits) = replace_lines(&Regex::new("x").unwrap(), "axb", "x");
+        assert!(edits.is_empty());
+    }
+
+    #[test]
+    fn diffs_show_old_and_new_lines() {
+        let re = Regex::new("foo").unwrap();
+        let old = "1\n2\nfoo\n4\n5\n6\n7\n8\n9\n10\nfoo\n";
+        let (new, _) = replace_lines(&re, old, "bar");
+        // 7 unchanged lines between the edits are more than twice the context, so they go in separate hunks
+        let expected = "--- a.txt\n+++ a.txt\n@@ -1,6 +1,6 @@\n 1\n 2\n-foo\n+bar\n 4\n 5\n 6\n@@ -8,4 +8,4 @@\n 8\n 9\n 10\n-foo\n+bar\n";
+        assert_eq!(expected, diff(Path::new("a.txt"), old, &new));
+    }
+
+    #[test]
+    fn writes_atomically_with_a_backup() {
+        let dir = temp_dir("replace");
+        let path = dir.join("poem.txt");
+        fs::write(&path, "old").unwrap();
+
+        write_in_place(&path, "new", Some(".orig")).unwrap();
+
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+        assert_eq!("old", fs::read_to_string(dir.join("poem.txt.orig")).unwrap());
+        // Only the file and its backup are left, no temporary file
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_the_original_alone() {
+        let dir = temp_dir("replace-missing");
+        let missing = dir.join("missing.txt");
+
+        let err = write_in_place(&missing, "new", None).unwrap_err();
+        assert_eq!(missing, err.path);
+        assert_eq!(io::ErrorKind::NotFound, err.source.kind());
+        assert!(err.to_string().starts_with(&format!("{}: ", missing.display())), "{err}");
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors_on_the_backup_name_the_file_too() {
+        let dir = temp_dir("replace-backup");
+        let file = dir.join("poem.txt");
+        fs::write(&file, "old").unwrap();
+        // A directory where the backup would go makes the copy fail
+        fs::create_dir(dir.join("poem.txt.bak")).unwrap();
+
+        let err = write_in_place(&file, "new", Some(".bak")).unwrap_err();
+        assert_eq!(Some(dir.join("poem.txt.bak")), err.other);
+        let expected = format!("{}: {}: ", file.display(), dir.join("poem.txt.bak").display());
+        assert!(err.to_string().starts_with(&expected), "{err}");
+        assert_eq!("old", fs::read_to_string(&file).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
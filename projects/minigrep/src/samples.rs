@@ -0,0 +1,172 @@
+// Sample Data
+
+// The book runs minigrep on poem.txt, and this crate's sample.txt is that file. But a test that reads sample.txt only works
+// when it runs in this directory, and breaks the day someone edits the file to try something out.
+// The files in samples/ are compiled into the crate with include_str!, so they're always there, and always the same:
+    // 1. POEM, the Emily Dickinson poem from the book, and RUST, the "safe, fast, productive" lines of the book's tests.
+    // 2. SERVER_LOG, the kind of log the webserver writes, one request or event per line. log_lines() parses it.
+    // 3. CRATES_CSV, a table with a header and quoted fields, some with commas and quotes inside. crates() parses it.
+// Tests and examples that need a real file write one with Sample::write_to, and run_with gets them all at once from mem_fs().
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::vfs::MemFs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    // The file name it had in samples/, and gets from write_to and mem_fs
+    pub name: &'static str,
+    pub text: &'static str,
+}
+
+pub const POEM: Sample = Sample { name: "poem.txt", text: include_str!("../samples/poem.txt") };
+pub const RUST: Sample = Sample { name: "rust.txt", text: include_str!("../samples/rust.txt") };
+pub const SERVER_LOG: Sample = Sample { name: "server.log", text: include_str!("../samples/server.log") };
+pub const CRATES_CSV: Sample = Sample { name: "crates.csv", text: include_str!("../samples/crates.csv") };
+
+pub const ALL: [Sample; 4] = [POEM, RUST, SERVER_LOG, CRATES_CSV];
+
+impl Sample {
+    pub fn lines(&self) -> std::str::Lines<'static> {
+        self.text.lines()
+    }
+
+    // Writes the sample into dir under its name, and returns the path of the file
+    pub fn write_to(&self, dir: &Path) -> io::Result<PathBuf> {
+        let path = dir.join(self.name);
+        fs::write(&path, self.text)?;
+        Ok(path)
+    }
+}
+
+// Every sample as a file in dir, in a file system of their own
+pub fn mem_fs<P: AsRef<Path>>(dir: P) -> MemFs {
+    let fs = MemFs::new();
+    for sample in ALL {
+        fs.add_file(dir.as_ref().join(sample.name), sample.text);
+    }
+    fs
+}
+
+// The Log
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s {
+            "DEBUG" => Ok(Level::Debug),
+            "INFO" => Ok(Level::Info),
+            "WARN" => Ok(Level::Warn),
+            "ERROR" => Ok(Level::Error),
+            _ => Err(format!("unknown log level {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogLine {
+    pub timestamp: &'static str,
+    pub level: Level,
+    pub message: &'static str,
+}
+
+// A line is a timestamp, a level padded to 5 characters, and the message
+pub fn log_lines() -> Vec<LogLine> {
+    SERVER_LOG
+        .lines()
+        .map(|line| {
+            let (timestamp, rest) = line.split_once(' ').expect("every line has a timestamp");
+            let (level, message) = rest.split_once(' ').expect("every line has a level");
+            LogLine { timestamp, level: level.parse().unwrap(), message: message.trim_start() }
+        })
+        .collect()
+}
+
+// The Table
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrateRow {
+    pub name: String,
+    pub version: String,
+    pub downloads: u64,
+    pub description: String,
+}
+
+// Just enough CSV for this one file: fields are separated by commas, and a field in quotes can have commas in it,
+// and quotes written twice. No field of the sample spans lines.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+// The rows of CRATES_CSV, without the header
+pub fn crates() -> Vec<CrateRow> {
+    CRATES_CSV
+        .lines()
+        .skip(1)
+        .map(|line| match <[String; 4]>::try_from(csv_fields(line)) {
+            Ok([name, version, downloads, description]) => {
+                CrateRow { name, version, downloads: downloads.parse().unwrap(), description }
+            }
+            Err(fields) => panic!("expected 4 fields, got {fields:?}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::FileSystem;
+
+    #[test]
+    fn samples_are_what_the_tests_expect() {
+        assert_eq!(Some("I'm nobody! Who are you?"), POEM.lines().next());
+        assert_eq!(9, POEM.lines().count());
+        assert_eq!(vec!["Rust:", "safe, fast, productive.", "Pick three.", "Trust me."], RUST.lines().collect::<Vec<_>>());
+
+        let fs = mem_fs("samples");
+        assert_eq!(4, fs.read_dir(Path::new("samples")).unwrap().len());
+        assert_eq!(POEM.text, fs.read_to_string(Path::new("samples/poem.txt")).unwrap());
+    }
+
+    #[test]
+    fn typed_logs_and_rows() {
+        let logs = log_lines();
+        assert_eq!(12, logs.len());
+        let errors: Vec<&str> = logs.iter().filter(|line| line.level >= Level::Error).map(|line| line.message).collect();
+        assert_eq!(vec!["GET /report 500 31.0ms: database connection refused", "worker 3 panicked: index out of bounds"], errors);
+        assert_eq!("2024-03-01T09:59:58Z", logs[0].timestamp);
+
+        let crates = crates();
+        assert_eq!(8, crates.len());
+        assert_eq!(("serde", 310254893), (crates[0].name.as_str(), crates[0].downloads));
+        assert!(crates[3].description.contains("(uses SIMD on x86_64, aarch64 and wasm32)"));
+        assert_eq!("quoted \"name\"", crates[7].name);
+    }
+}
@@ -0,0 +1,431 @@
+// Reading Text Line by Line, Whatever the Encoding
+
+// str::lines and BufRead::lines assume the text is UTF-8 and fail or give up on anything else. Real files are messier:
+    // 1. Windows programs end lines with "\r\n", and Notepad used to start UTF-8 files with a byte order mark (BOM), EF BB BF.
+    // 2. Windows logs and PowerShell output are often UTF-16, two bytes per character, little endian with the BOM FF FE.
+    //    To a byte search every other byte of an ASCII line is NUL, so a UTF-16 file looks binary and its words aren't found.
+    // 3. Any file can have a few invalid bytes, from a bad copy or a file that was half in Latin-1.
+// LineReader reads from any Read and hands out lines as Strings, dealing with all three:
+    // 1. The first bytes are checked for a BOM, which decides the encoding. Without one the text is taken to be UTF-8.
+    // 2. The line ending is taken off the text and reported separately, as Lf, CrLf or None for a last line without one.
+    // 3. What happens to invalid bytes is up to an InvalidPolicy: replace them with U+FFFD, drop them, or fail with an error.
+// Every line also knows its number and the offset of its first byte in the stream, in the bytes of the original encoding,
+// so a match can be reported at a position that means something in the file.
+
+// minigrep runs every file through to_utf8 before searching it, which turns UTF-16 files into UTF-8 and takes off the BOM.
+
+use std::{borrow::Cow, fmt, io::{self, Read}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    // The encoding a BOM at the start of bytes says, and the length of that BOM
+    pub fn detect(bytes: &[u8]) -> Option<(Encoding, usize)> {
+        match bytes {
+            [0xEF, 0xBB, 0xBF, ..] => Some((Encoding::Utf8, 3)),
+            [0xFF, 0xFE, ..] => Some((Encoding::Utf16Le, 2)),
+            [0xFE, 0xFF, ..] => Some((Encoding::Utf16Be, 2)),
+            _ => None,
+        }
+    }
+
+    fn unit_len(self) -> usize {
+        if self == Encoding::Utf8 { 1 } else { 2 }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidPolicy {
+    // Every invalid sequence becomes U+FFFD, like String::from_utf8_lossy
+    #[default]
+    Replace,
+    // Invalid sequences are dropped
+    Skip,
+    // The line fails with an io::Error of kind InvalidData
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    // The last line of a file that doesn't end with a newline
+    None,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::None => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub text: String,
+    // Counting from 1, like editors do
+    pub number: usize,
+    // Where the line starts in the stream, in bytes of the original encoding and counting the BOM
+    pub offset: u64,
+    pub ending: LineEnding,
+}
+
+pub struct LineReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    // buf[start..end] has been read but not handed out yet
+    start: usize,
+    end: usize,
+    eof: bool,
+    // None until the first line is read, that's when the BOM is looked for
+    encoding: Option<Encoding>,
+    policy: InvalidPolicy,
+    offset: u64,
+    line_number: usize,
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(reader: R) -> LineReader<R> {
+        LineReader {
+            reader,
+            buf: vec![0; 8 * 1024],
+            start: 0,
+            end: 0,
+            eof: false,
+            encoding: None,
+            policy: InvalidPolicy::default(),
+            offset: 0,
+            line_number: 0,
+        }
+    }
+
+    pub fn invalid(mut self, policy: InvalidPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // Skips BOM detection, for text that is known to be in an encoding but has no BOM
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    // The encoding of the text, known once the first line has been read
+    pub fn detected_encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    // Reads more bytes into the buffer, keeping the ones that haven't been handed out. False at the end of the stream.
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.end == self.buf.len() {
+            // A line longer than the buffer, the buffer grows to fit it
+            self.buf.resize(self.buf.len() * 2, 0);
+        }
+        loop {
+            match self.reader.read(&mut self.buf[self.end..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(false);
+                }
+                Ok(n) => {
+                    self.end += n;
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn detect(&mut self) -> io::Result<Encoding> {
+        // A BOM is at most 3 bytes, and a reader may hand them out one at a time
+        while self.end - self.start < 3 && self.fill()? {}
+        let (encoding, bom_len) = Encoding::detect(&self.buf[self.start..self.end]).unwrap_or((Encoding::Utf8, 0));
+        self.start += bom_len;
+        self.offset += bom_len as u64;
+        Ok(encoding)
+    }
+
+    // Where the newline of the next line ends in buf, searched from the code unit at from
+    fn find_newline(&self, encoding: Encoding, from: usize) -> Option<usize> {
+        let pending = &self.buf[self.start..self.end];
+        let newline = match encoding {
+            Encoding::Utf8 => return pending[from..].iter().position(|&b| b == b'\n').map(|i| from + i + 1),
+            Encoding::Utf16Le => [b'\n', 0],
+            Encoding::Utf16Be => [0, b'\n'],
+        };
+        // Only at even offsets: the second byte of one character and the first of the next can look like a newline together
+        (from..pending.len().saturating_sub(1)).step_by(2).find(|&i| pending[i..i + 2] == newline).map(|i| i + 2)
+    }
+
+    pub fn read_line(&mut self) -> io::Result<Option<Line>> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => {
+                let encoding = self.detect()?;
+                self.encoding = Some(encoding);
+                encoding
+            }
+        };
+
+        // Looks for the newline in what's buffered, reading more until there is one or the stream ends
+        let mut searched = 0;
+        let len = loop {
+            if let Some(len) = self.find_newline(encoding, searched) {
+                break len;
+            }
+            let pending = self.end - self.start;
+            // Whole code units only, the next search starts where this one stopped
+            searched = pending - pending % encoding.unit_len();
+            if !self.fill()? {
+                if self.start == self.end {
+                    return Ok(None);
+                }
+                break self.end - self.start;
+            }
+        };
+
+        let raw = &self.buf[self.start..self.start + len];
+        self.line_number += 1;
+        let number = self.line_number;
+        let text = decode(raw, encoding, self.policy).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {number}: {e}"))
+        })?;
+        let line = strip_ending(text, number, self.offset);
+
+        self.start += len;
+        self.offset += len as u64;
+        Ok(Some(line))
+    }
+}
+
+impl<R: Read> Iterator for LineReader<R> {
+    type Item = io::Result<Line>;
+
+    fn next(&mut self) -> Option<io::Result<Line>> {
+        self.read_line().transpose()
+    }
+}
+
+fn strip_ending(mut text: String, number: usize, offset: u64) -> Line {
+    let ending = if text.ends_with("\r\n") {
+        LineEnding::CrLf
+    } else if text.ends_with('\n') {
+        LineEnding::Lf
+    } else {
+        LineEnding::None
+    };
+    text.truncate(text.len() - ending.as_str().len());
+    Line { text, number, offset, ending }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSequence {
+    pub encoding: Encoding,
+    // The offset of the first invalid byte within the line
+    pub at: usize,
+}
+
+impl fmt::Display for InvalidSequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid {} at byte {} of the line", self.encoding, self.at)
+    }
+}
+
+impl std::error::Error for InvalidSequence {}
+
+// Decodes bytes in the given encoding to a String, with invalid sequences handled by the policy
+pub fn decode(bytes: &[u8], encoding: Encoding, policy: InvalidPolicy) -> Result<String, InvalidSequence> {
+    match encoding {
+        Encoding::Utf8 => {
+            let mut text = String::with_capacity(bytes.len());
+            let mut at = 0;
+            for chunk in bytes.utf8_chunks() {
+                text.push_str(chunk.valid());
+                at += chunk.valid().len();
+                if !chunk.invalid().is_empty() {
+                    match policy {
+                        InvalidPolicy::Replace => text.push(char::REPLACEMENT_CHARACTER),
+                        InvalidPolicy::Skip => {}
+                        InvalidPolicy::Strict => return Err(InvalidSequence { encoding, at }),
+                    }
+                    at += chunk.invalid().len();
+                }
+            }
+            Ok(text)
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let (pairs, odd) = bytes.as_chunks::<2>();
+            let units = pairs.iter().map(|&pair| {
+                if encoding == Encoding::Utf16Le { u16::from_le_bytes(pair) } else { u16::from_be_bytes(pair) }
+            });
+
+            let mut text = String::with_capacity(bytes.len() / 2);
+            let mut at = 0;
+            for decoded in char::decode_utf16(units) {
+                match decoded {
+                    Ok(c) => {
+                        text.push(c);
+                        at += c.len_utf16() * 2;
+                    }
+                    Err(_) => {
+                        match policy {
+                            InvalidPolicy::Replace => text.push(char::REPLACEMENT_CHARACTER),
+                            InvalidPolicy::Skip => {}
+                            InvalidPolicy::Strict => return Err(InvalidSequence { encoding, at }),
+                        }
+                        at += 2;
+                    }
+                }
+            }
+            // Half a character at the very end, cut off by the end of the stream
+            if !odd.is_empty() {
+                match policy {
+                    InvalidPolicy::Replace => text.push(char::REPLACEMENT_CHARACTER),
+                    InvalidPolicy::Skip => {}
+                    InvalidPolicy::Strict => return Err(InvalidSequence { encoding, at }),
+                }
+            }
+            Ok(text)
+        }
+    }
+}
+
+// The whole text as UTF-8 bytes without a BOM, for searches that work on a whole file at once.
+// UTF-8 is passed through as it is, invalid bytes and all, so memory-mapped files aren't copied. UTF-16 goes through
+// a LineReader, which keeps the line endings as they were.
+pub fn to_utf8(bytes: &[u8], policy: InvalidPolicy) -> io::Result<Cow<'_, [u8]>> {
+    match Encoding::detect(bytes) {
+        None => Ok(Cow::Borrowed(bytes)),
+        Some((Encoding::Utf8, bom_len)) => Ok(Cow::Borrowed(&bytes[bom_len..])),
+        Some(_) => {
+            let mut text = String::with_capacity(bytes.len() / 2);
+            for line in LineReader::new(bytes).invalid(policy) {
+                let line = line?;
+                text.push_str(&line.text);
+                text.push_str(line.ending.as_str());
+            }
+            Ok(Cow::Owned(text.into_bytes()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        bytes
+    }
+
+    fn lines<R: Read>(reader: LineReader<R>) -> Vec<(String, usize, u64, LineEnding)> {
+        reader.map(|line| line.unwrap()).map(|line| (line.text, line.number, line.offset, line.ending)).collect()
+    }
+
+    // Hands out one byte per read, so a BOM or a newline is split between reads
+    struct OneByte<'a>(&'a [u8]);
+
+    impl Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else { return Ok(0) };
+            buf[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn line_endings_and_offsets() {
+        let text = b"one\r\ntwo\n\nlast";
+        let expected = vec![
+            (String::from("one"), 1, 0, LineEnding::CrLf),
+            (String::from("two"), 2, 5, LineEnding::Lf),
+            (String::new(), 3, 9, LineEnding::Lf),
+            (String::from("last"), 4, 10, LineEnding::None),
+        ];
+        assert_eq!(expected, lines(LineReader::new(&text[..])));
+        assert_eq!(expected, lines(LineReader::new(OneByte(text))));
+        assert!(LineReader::new(&b""[..]).next().is_none());
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        let mut reader = LineReader::new(&b"\xEF\xBB\xBFcaf\xC3\xA9\n"[..]);
+        let line = reader.read_line().unwrap().unwrap();
+        assert_eq!(("café", 3), (line.text.as_str(), line.offset));
+        assert_eq!(Some(Encoding::Utf8), reader.detected_encoding());
+
+        let bytes = utf16le("Grüße\r\n🦀 crab\n");
+        let reader = LineReader::new(OneByte(&bytes));
+        assert_eq!(
+            vec![(String::from("Grüße"), 1, 2, LineEnding::CrLf), (String::from("🦀 crab"), 2, 16, LineEnding::Lf)],
+            lines(reader)
+        );
+
+        let mut big_endian = vec![0xFE, 0xFF];
+        big_endian.extend("a\nb".encode_utf16().flat_map(u16::to_be_bytes));
+        let texts: Vec<String> = LineReader::new(&big_endian[..]).map(|line| line.unwrap().text).collect();
+        assert_eq!(vec!["a", "b"], texts);
+    }
+
+    #[test]
+    fn utf16_newlines_only_at_character_boundaries() {
+        // U+0A0A is two 0x0A bytes, and its second byte together with the first byte of U+0A00 reads 0x0A 0x00 at an odd offset
+        let text = "\u{0A0A}\u{0A00} x\ny";
+        let bytes = utf16le(text);
+        let texts: Vec<String> = LineReader::new(&bytes[..]).map(|line| line.unwrap().text).collect();
+        assert_eq!(vec!["\u{0A0A}\u{0A00} x", "y"], texts);
+    }
+
+    #[test]
+    fn invalid_sequences() {
+        let bytes = b"ok\nbad \xFF\xFE here\n";
+        let read = |policy| LineReader::new(&bytes[..]).invalid(policy).collect::<io::Result<Vec<Line>>>();
+        assert_eq!("bad \u{FFFD}\u{FFFD} here", read(InvalidPolicy::Replace).unwrap()[1].text);
+        assert_eq!("bad  here", read(InvalidPolicy::Skip).unwrap()[1].text);
+        let err = read(InvalidPolicy::Strict).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!("line 2: invalid UTF-8 at byte 4 of the line", err.to_string());
+
+        // An unpaired surrogate, and an odd byte at the end
+        let mut utf16 = utf16le("a");
+        utf16.extend([0x00, 0xD8, b'b', 0x00, b'c']);
+        assert_eq!("a\u{FFFD}b\u{FFFD}", decode(&utf16[2..], Encoding::Utf16Le, InvalidPolicy::Replace).unwrap());
+        assert_eq!(Err(InvalidSequence { encoding: Encoding::Utf16Le, at: 2 }), decode(&utf16[2..], Encoding::Utf16Le, InvalidPolicy::Strict));
+    }
+
+    #[test]
+    fn converting_whole_files() {
+        assert!(matches!(to_utf8(b"plain\xFF", InvalidPolicy::Replace).unwrap(), Cow::Borrowed(b"plain\xFF")));
+        assert_eq!(&b"text"[..], &*to_utf8(b"\xEF\xBB\xBFtext", InvalidPolicy::Replace).unwrap());
+        assert_eq!(&b"one\r\ntwo"[..], &*to_utf8(&utf16le("one\r\ntwo"), InvalidPolicy::Replace).unwrap());
+    }
+}
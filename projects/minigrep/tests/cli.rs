@@ -0,0 +1,60 @@
+// The minigrep binary from the outside: arguments, exit codes, and what goes to stdout and what to stderr.
+// The unit tests in src/ check the searching itself, these check that main puts it together the way a user expects.
+
+use minigrep::samples::POEM;
+use test_support::{assert_snapshot, cargo_bin, TempDir};
+
+#[test]
+fn prints_matching_lines() {
+    let dir = TempDir::new();
+    let poem = POEM.write_to(dir.path()).unwrap();
+    cargo_bin!("minigrep").arg("nobody").arg(&poem).assert().success().stdout("I'm nobody! Who are you?\nAre you nobody, too?\n").stderr("");
+    cargo_bin!("minigrep").arg("toad").arg(&poem).assert().success().stdout("");
+}
+
+#[test]
+fn ignore_case_comes_from_the_environment() {
+    let dir = TempDir::new();
+    let poem = POEM.write_to(dir.path()).unwrap();
+    let expected = "How dreary to be somebody!\nHow public, like a frog\n";
+    cargo_bin!("minigrep").arg("HOW").arg(&poem).env("IGNORE_CASE", "1").assert().success().stdout(expected);
+    cargo_bin!("minigrep").arg("HOW").arg(&poem).env("IGNORE_CASE", "0").assert().success().stdout("");
+    cargo_bin!("minigrep").arg("--print-env-help").assert().success().stdout_contains("IGNORE_CASE=1  Search case-insensitively");
+}
+
+#[test]
+fn searches_directories_with_file_names() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "frog\n");
+    dir.write("sub/b.txt", "bog\nfrog and toad\n");
+    dir.write("skipped.log", "frog\n");
+    dir.write(".gitignore", "*.log\n");
+
+    let a = dir.child("a.txt");
+    let b = dir.child("sub/b.txt");
+    let expected = format!("{}:frog\n{}:frog and toad\n", a.display(), b.display());
+    cargo_bin!("minigrep").arg("frog").arg(dir.path()).assert().success().stdout(&expected);
+    cargo_bin!("minigrep").args(["--no-ignore", "frog"]).arg(dir.path()).assert().success().stdout_contains("skipped.log:frog");
+}
+
+#[test]
+fn usage_errors() {
+    cargo_bin!("minigrep").assert().code(1).stdout("").stderr_contains("Problem parsing arguments");
+    cargo_bin!("minigrep").arg("--help").assert().success().stdout_contains("--regexp");
+
+    let dir = TempDir::new();
+    cargo_bin!("minigrep").arg("frog").arg(dir.child("missing.txt")).assert().code(1).stderr_contains("Application error");
+}
+
+// The layout of --stats is easier to review in tests/snapshots/cli_stats.snap than in a string here
+#[test]
+fn stats_summary() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "frog\nbog\n");
+    dir.write("sub/b.txt", "frog\nfrog and toad\n");
+    dir.write("none.txt", "toad\n");
+
+    // Relative paths, so the output is the same wherever the TempDir is
+    let stats = cargo_bin!("minigrep").args(["--stats", "frog", "."]).current_dir(dir.path()).assert().success();
+    assert_snapshot!("cli_stats", format!("stdout:\n{}\nstderr:\n{}", stats.stdout_str(), stats.stderr_str()));
+}
@@ -0,0 +1,58 @@
+// Sequential vs Parallel Search Benchmark
+
+// Run with: cargo bench --bench search
+
+// Like the trie benchmark in collections/std_collections, this is a plain binary (harness = false in Cargo.toml) timed with Instant,
+// because #[bench] is still nightly only.
+// The input is a generated text of about 40 MB, big enough that the cost of spawning the threads is small next to the search itself.
+
+use std::{hint::black_box, thread, time::Instant};
+
+use minigrep_iter::{search, search_parallel};
+
+// A deterministic text generator (a linear congruential generator picking words), so every run benchmarks the same data
+fn text(lines: usize) -> String {
+    let words = ["safe", "fast", "productive", "pick", "three", "duct", "tape", "rust", "trust", "me"];
+    let mut state: u64 = 42;
+    let mut next = move || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as usize
+    };
+
+    let mut text = String::new();
+    for _ in 0..lines {
+        let len = 3 + next() % 12;
+        for i in 0..len {
+            if i > 0 {
+                text.push(' ');
+            }
+            text.push_str(words[next() % words.len()]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn time<F: FnMut() -> usize>(name: &str, runs: u32, mut f: F) {
+    let start = Instant::now();
+    let mut found = 0;
+    for _ in 0..runs {
+        found = black_box(f());
+    }
+    println!("{name:<24} {:>10.2?} per run ({found} lines found)", start.elapsed() / runs);
+}
+
+fn main() {
+    let contents = text(800_000);
+    println!("searching {} MB", contents.len() / (1024 * 1024));
+
+    // A query that matches about a third of the lines, and a rare one where almost all the time goes into scanning
+    for query in ["duct tape", "trust me safe"] {
+        println!("\nquery {query:?}");
+        time("sequential", 10, || search(black_box(query), &contents).len());
+        let cores = thread::available_parallelism().map_or(4, |n| n.get());
+        for threads in [2, 4, cores] {
+            time(&format!("parallel, {threads} threads"), 10, || search_parallel(black_box(query), &contents, threads).len());
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::{env, fs, error::Error};
+use std::{env, fs, error::Error, thread};
 
 // Making Code Clearer with Iterator Adaptors
 
@@ -32,6 +32,52 @@ pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a st
 
 }
 
+// Searching in Parallel
+
+// Since search() has no mutable state, we can split the contents into chunks and search every chunk on its own thread.
+    // 1. Chunks end right after a '\n', so no line is ever split between two threads.
+    // 2. thread::scope lets the threads borrow contents, every scoped thread is joined before scope() returns,
+    //    so the compiler knows the borrowed &str outlives them. No Arc and no copying needed.
+    // 3. Each thread returns its own Vec of matches, and because we join the threads in chunk order, concatenating the Vecs
+    //    gives the lines in the same order as the sequential search().
+
+// Spawning threads isn't free, so for small inputs this is slower than search(). See benches/search.rs for the numbers.
+
+pub fn search_parallel<'a>(query: &str, contents: &'a str, threads: usize) -> Vec<&'a str> {
+    let chunks = split_chunks(contents, threads.max(1));
+    if chunks.len() <= 1 {
+        return search(query, contents);
+    }
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || search(query, chunk)))
+            .collect();
+
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    })
+}
+
+// Splits contents into at most n chunks of roughly equal size, each one ending at a line boundary
+fn split_chunks(contents: &str, n: usize) -> Vec<&str> {
+    let target = contents.len().div_ceil(n).max(1);
+    let mut chunks = Vec::with_capacity(n);
+    let mut rest = contents;
+
+    while !rest.is_empty() {
+        // Look for the end of the line that contains the target position, a '\n' byte is always on a char boundary
+        let end = match rest.as_bytes()[target.min(rest.len()) - 1..].iter().position(|&b| b == b'\n') {
+            Some(i) => target.min(rest.len()) + i,
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
 
 pub struct Config {
     pub query: String,
@@ -120,4 +166,34 @@ mod tests {
         assert_eq!(vec!["Rust:", "Trust me."], search_case_insensitive(query, contents));
     }
 
-}
\ No newline at end of file
+
+    #[test]
+    fn chunks_end_at_line_boundaries() {
+        let contents = "one\ntwo\nthree\nfour";
+        let chunks = split_chunks(contents, 3);
+        assert_eq!(vec!["one\ntwo\n", "three\n", "four"], chunks);
+        assert_eq!(contents, chunks.concat());
+        assert!(split_chunks("", 4).is_empty());
+        // More threads than lines just gives one chunk per line
+        assert_eq!(vec!["a\n", "b\n"], split_chunks("a\nb\n", 16));
+    }
+
+    #[test]
+    fn parallel_search_keeps_line_order() {
+        // Deterministic pseudo-random lines (xorshift), so that matches are spread unevenly over the chunks
+        let mut state: u32 = 2463534242;
+        let mut contents = String::new();
+        for _ in 0..2000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let word = ["rust", "crab", "ferris", "trust", "çà et là", ""][state as usize % 6];
+            contents.push_str(&format!("{} {word}\r\n", state % 1000));
+        }
+
+        for threads in [0, 1, 2, 3, 8, 64] {
+            assert_eq!(search("rust", &contents), search_parallel("rust", &contents, threads));
+            assert_eq!(search("là", &contents), search_parallel("là", &contents, threads));
+        }
+    }
+}
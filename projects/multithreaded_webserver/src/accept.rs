@@ -0,0 +1,219 @@
+// Accepting Connections Until Shutdown
+
+// The chat server (projects/chat_server) and the kvstore (projects/kvstore) serve their clients the same way: a worker of a
+// ThreadPool each, for as long as the client stays, and a ShutdownHandle that closes every connection and ends the accept loop.
+// Acceptor is that part of both:
+    // 1. run() accepts the connections of a Listener, and serves each one on a worker. Before that, open() makes what it
+    //    takes to close the connection again, another handle to the stream usually, and it's kept in a Slab
+    //    (std_collections/src/slab.rs) under the connection's id until its worker is done.
+    // 2. Stopper::stop() closes every open connection with the close it's given, which ends their workers' loops, and then
+    //    wakes up the accept loop by connecting to it. The closes run after the lock is let go, so a close that waits for a slow
+    //    client doesn't hold up the workers that end in the meantime.
+    // 3. Adding a connection and stopping both hold the lock, so a connection is either added before stop() closes them all,
+    //    or sees that it's stopping and isn't served.
+// run() returns once the ThreadPool is dropped, and dropping it waits for every worker to finish.
+
+// serve_until_stopped() is the part of a server's main() around run(): a signal, or shutdown typed on stdin, runs the Shutdown
+// hooks (projects/common/src/signals.rs). The first one stops the server, the second waits for run() to return, and the
+// caller's own hooks come after those two.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use common::signals::{self, Shutdown, Signal};
+use std_collections::slab::Slab;
+
+use crate::{
+    net::{Listener, Network, Stream},
+    ThreadPool,
+};
+
+struct Connections<C> {
+    open: Slab<C>,
+    stopping: bool,
+}
+
+pub struct Acceptor<C> {
+    listener: Box<dyn Listener>,
+    addr: SocketAddr,
+    network: Arc<dyn Network>,
+    connections: Arc<Mutex<Connections<C>>>,
+}
+
+impl<C: Clone + Send + 'static> Acceptor<C> {
+    pub fn bind(network: Arc<dyn Network>, addr: &str) -> io::Result<Acceptor<C>> {
+        let listener = network.bind(addr)?;
+        let addr = listener.local_addr()?;
+        let connections = Arc::new(Mutex::new(Connections { open: Slab::new(), stopping: false }));
+        Ok(Acceptor { listener, addr, network, connections })
+    }
+
+    // The address it listens on, with the port the system picked when it was bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn stopper(&self) -> Stopper<C> {
+        Stopper { addr: self.addr, network: Arc::clone(&self.network), connections: Arc::clone(&self.connections) }
+    }
+
+    // Serves every connection with serve on a pool of that many threads, until stop() is called or accepting a connection fails
+    pub fn run<O, S>(self, threads: usize, mut open: O, serve: S) -> io::Result<()>
+    where
+        O: FnMut(&dyn Stream) -> io::Result<C>,
+        S: Fn(Box<dyn Stream>, C) -> io::Result<()> + Send + Sync + 'static,
+    {
+        assert!(threads > 0, "the server needs at least one thread");
+        let pool = ThreadPool::new(threads);
+        let serve = Arc::new(serve);
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            let connection = open(&*stream)?;
+            let id = {
+                let mut connections = self.connections.lock().unwrap();
+                if connections.stopping {
+                    break;
+                }
+                connections.open.insert(connection.clone())
+            };
+
+            let (serve, connections) = (Arc::clone(&serve), Arc::clone(&self.connections));
+            pool.execute(move || {
+                if let Err(e) = serve(stream, connection) {
+                    common::debug!("connection {id} failed: {e}");
+                }
+                connections.lock().unwrap().open.remove(id);
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct Stopper<C> {
+    addr: SocketAddr,
+    network: Arc<dyn Network>,
+    connections: Arc<Mutex<Connections<C>>>,
+}
+
+// A derive would want C: Clone for no reason, only the Arcs are cloned
+impl<C> Clone for Stopper<C> {
+    fn clone(&self) -> Self {
+        Stopper { addr: self.addr, network: Arc::clone(&self.network), connections: Arc::clone(&self.connections) }
+    }
+}
+
+impl<C: Clone> Stopper<C> {
+    // Calling it again does nothing
+    pub fn stop(&self, close: impl Fn(C)) {
+        let open: Vec<C> = {
+            let mut connections = self.connections.lock().unwrap();
+            if connections.stopping {
+                return;
+            }
+            connections.stopping = true;
+            connections.open.values().cloned().collect()
+        };
+        for connection in open {
+            close(connection);
+        }
+
+        // accept() only returns when somebody connects, so somebody does. The loop sees `stopping` and ends.
+        let _ = self.network.connect(self.addr, None);
+    }
+
+    // The connections that are open now, in the order of their ids
+    pub fn open(&self) -> Vec<C> {
+        self.connections.lock().unwrap().open.values().cloned().collect()
+    }
+}
+
+// Runs the server on this thread until the hooks stopped it, with stop as the first hook. Typing shutdown on stdin, when
+// read_stdin says it's there to read, is the same as kill sending SIGTERM. Accepting that fails runs the hooks too, nobody
+// asked the server to stop but they still have to close what's open
+pub fn serve_until_stopped(
+    run: impl FnOnce() -> io::Result<()>,
+    stop: impl FnOnce() + Send + 'static,
+    hooks: Shutdown,
+    read_stdin: bool,
+) -> io::Result<()> {
+    // Dropped once run() returns, which is when the workers are done
+    let (drained, workers_done) = mpsc::channel::<()>();
+    let shutdown = Shutdown::new()
+        .hook("close every connection", stop)
+        .hook("wait for the workers", move || {
+            // Only ever an error, when the sender is dropped
+            let _ = workers_done.recv();
+        })
+        .then(hooks);
+    let waiting = shutdown
+        .on_signals(&Signal::ALL)
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't handle signals: {e}")))?;
+
+    if read_stdin {
+        thread::spawn(|| {
+            for line in io::stdin().lines() {
+                match line {
+                    Ok(line) if line.trim() == "shutdown" => break,
+                    Ok(_) => println!("only shutdown is understood here"),
+                    Err(_) => break,
+                }
+            }
+            signals::send(Signal::Terminate);
+        });
+    }
+
+    let result = run();
+    if result.is_err() {
+        signals::send(Signal::Terminate);
+    }
+    drop(drained);
+    waiting.join().unwrap();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::Tcp;
+    use std::io::{BufRead, BufReader, Write};
+
+    #[test]
+    fn stopping_closes_every_connection_and_ends_the_loop() {
+        let acceptor = Acceptor::bind(Arc::new(Tcp), "127.0.0.1:0").unwrap();
+        let (addr, stopper) = (acceptor.local_addr(), acceptor.stopper());
+        let server = thread::spawn(move || {
+            // An echo server, with the connection's other handle kept for closing it
+            let open = |stream: &dyn Stream| stream.try_clone().map(|stream| Arc::new(Mutex::new(stream)));
+            acceptor.run(2, open, |stream, _| {
+                let mut writer = stream.try_clone()?;
+                for line in BufReader::new(stream).lines() {
+                    writeln!(writer, "{}", line?)?;
+                }
+                Ok(())
+            })
+        });
+
+        let mut clients: Vec<_> = (0..2).map(|_| Tcp.connect(addr, None).unwrap()).collect();
+        let mut replies: Vec<_> = clients.iter().map(|client| BufReader::new(client.try_clone().unwrap()).lines()).collect();
+        for (client, replies) in clients.iter_mut().zip(&mut replies) {
+            writeln!(client, "hello").unwrap();
+            assert_eq!("hello", replies.next().unwrap().unwrap());
+        }
+        assert_eq!(2, stopper.open().len());
+
+        stopper.stop(|stream| {
+            // Closed already when the client left first
+            let _ = stream.lock().unwrap().shutdown(std::net::Shutdown::Both);
+        });
+        stopper.stop(|_| unreachable!("only the first stop closes anything"));
+        server.join().unwrap().unwrap();
+        for replies in &mut replies {
+            assert!(replies.next().is_none());
+        }
+        assert!(stopper.open().is_empty());
+    }
+}
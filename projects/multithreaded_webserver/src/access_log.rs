@@ -0,0 +1,353 @@
+// Access Logs
+
+// An access log has one line for every request the server answered: who asked, when, for what, and what they got. Tools like
+// GoAccess and AWStats read it, and they expect one of the formats everybody writes:
+    // 1. Common Log Format, Apache's since the 90s. A field that isn't known is a -:
+    //    127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] "GET /apache_pb.gif HTTP/1.0" 200 2326
+    // 2. JSON lines, one object per line, for log collectors that would rather not parse the above. The time is an HTTP date
+    //    (src/http_date.rs), and the time the request took is in it too, which CLF has no field for.
+// Who asked is the peer address the server put in the request's extensions (src/server.rs), and the user is the Identity
+// Auth put there (src/auth.rs), when it's in the chain.
+
+// The lines go to a sink:
+    // 1. The logger (projects/common/src/log.rs), as info lines, to wherever the rest of the log goes.
+    // 2. A file of their own. Writing every line with its own write() is a system call per request, so the lines go through
+    //    a BufWriter and a thread flushes it every flush_every: after a quiet second the file is up to date, and under load
+    //    a write() carries many lines. What's still in the buffer when the process is killed is lost, which is the usual deal.
+    //    When the file reaches max_size it's rotated: access.log becomes access.log.1, access.log.1 becomes access.log.2,
+    //    up to keep files, and the oldest one is deleted.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use json::{JsonWriter, ToJson};
+
+use crate::{
+    auth::Identity,
+    http::{Body, Request, Response},
+    http_date,
+    middleware::{Middleware, Next},
+    server::PeerAddr,
+    time_ext::Stopwatch,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Common,
+    Json,
+}
+
+// One line of the log, before it's formatted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub remote: Option<String>,
+    pub user: Option<String>,
+    pub time: SystemTime,
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub status: u16,
+    // None for a body that is streamed, whose size isn't known when the line is written
+    pub bytes: Option<usize>,
+    pub took: Duration,
+}
+
+impl Entry {
+    pub fn new(request: &Request, response: &Response, time: SystemTime, took: Duration) -> Entry {
+        Entry {
+            remote: request.extensions().get::<PeerAddr>().map(|PeerAddr(addr)| addr.ip().to_string()),
+            user: request.extensions().get::<Identity>().map(|identity| identity.name.clone()),
+            time,
+            method: request.method.clone(),
+            path: request.path.clone(),
+            version: request.version.clone(),
+            status: response.status,
+            bytes: match &response.body {
+                Body::Bytes(bytes) => Some(bytes.len()),
+                Body::EventStream(_) => None,
+            },
+            took,
+        }
+    }
+
+    // Without the newline
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Common => self.common(),
+            Format::Json => self.json(),
+        }
+    }
+
+    fn common(&self) -> String {
+        let dash = |field: &Option<String>| field.clone().unwrap_or_else(|| String::from("-"));
+        // The quotes around the request line would end early at a quote in the path, a client can send anything
+        let request = format!("{} {} {}", self.method, self.path, self.version).replace('\\', "\\\\").replace('"', "\\\"");
+        format!(
+            "{} - {} [{}] \"{request}\" {} {}",
+            dash(&self.remote),
+            dash(&self.user),
+            http_date::common_log(self.time),
+            self.status,
+            self.bytes.map_or(String::from("-"), |bytes| bytes.to_string())
+        )
+    }
+
+    fn json(&self) -> String {
+        json::to_string(self)
+    }
+}
+
+// The remote, the user and the bytes are null when the common format would show a dash
+impl ToJson for Entry {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.begin_object()?;
+        json.field("time", &http_date::format(self.time))?;
+        json.field("remote", &self.remote)?;
+        json.field("user", &self.user)?;
+        json.field("method", &self.method)?;
+        json.field("path", &self.path)?;
+        json.field("version", &self.version)?;
+        json.field("status", &self.status)?;
+        json.field("bytes", &self.bytes)?;
+        json.key("millis")?;
+        json.u64(self.took.as_millis() as u64)?;
+        json.end_object()
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.common())
+    }
+}
+
+// A file that starts over when it gets too big, keeping a few of the old ones
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    // Appends to the file when it's there. keep is how many rotated files to keep besides it, 0 to delete them right away
+    pub fn open(path: impl Into<PathBuf>, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, max_size, keep, file: BufWriter::new(file), size })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // The oldest one is overwritten by the rename, the others move up one
+            for n in (1..self.keep).rev() {
+                if self.rotated(n).exists() {
+                    fs::rename(self.rotated(n), self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// A line is never split over two files: the file is rotated before the line that would take it over max_size
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+pub enum Sink {
+    Logger,
+    Writer(Box<dyn Write + Send>),
+}
+
+pub struct AccessLog {
+    format: Format,
+    sink: Arc<Mutex<Sink>>,
+    // Dropping the sender stops the flushing thread, like the one of a PollWatcher (src/watch.rs)
+    stop: Option<Sender<()>>,
+    flusher: Option<JoinHandle<()>>,
+}
+
+impl AccessLog {
+    // To the logger, where the lines are written at once
+    pub fn to_logger(format: Format) -> AccessLog {
+        AccessLog { format, sink: Arc::new(Mutex::new(Sink::Logger)), stop: None, flusher: None }
+    }
+
+    // To a writer, usually a RotatingFile, flushed every flush_every and when the AccessLog is dropped
+    pub fn to_writer(format: Format, writer: impl Write + Send + 'static, flush_every: Duration) -> AccessLog {
+        let sink = Arc::new(Mutex::new(Sink::Writer(Box::new(writer))));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let flusher = {
+            let sink = Arc::clone(&sink);
+            thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(flush_every) {
+                    flush(&sink);
+                }
+            })
+        };
+        AccessLog { format, sink, stop: Some(stop), flusher: Some(flusher) }
+    }
+
+    pub fn log(&self, entry: &Entry) {
+        let line = entry.format(self.format);
+        match &mut *self.sink.lock().unwrap() {
+            Sink::Logger => common::info!("{line}"),
+            // Like the logger, an access log has nowhere to report that it couldn't write
+            Sink::Writer(writer) => {
+                let _ = writer.write_all(format!("{line}\n").as_bytes());
+            }
+        }
+    }
+}
+
+fn flush(sink: &Mutex<Sink>) {
+    if let Sink::Writer(writer) = &mut *sink.lock().unwrap() {
+        let _ = writer.flush();
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let (time, stopwatch) = (SystemTime::now(), Stopwatch::start());
+        let response = next.run(request);
+        self.log(&Entry::new(request, &response, time, stopwatch.elapsed()));
+        response
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+        flush(&self.sink);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+    use std::{env, time::UNIX_EPOCH};
+
+    fn entry() -> Entry {
+        Entry {
+            remote: Some(String::from("127.0.0.1")),
+            user: Some(String::from("frank")),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            method: String::from("GET"),
+            path: String::from("/apache_pb.gif"),
+            version: String::from("HTTP/1.0"),
+            status: 200,
+            bytes: Some(2326),
+            took: Duration::from_millis(3),
+        }
+    }
+
+    #[test]
+    fn formats_exactly() {
+        let common = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] "GET /apache_pb.gif HTTP/1.0" 200 2326"#;
+        assert_eq!(common, entry().to_string());
+        let json = concat!(
+            r#"{"time":"Tue, 10 Oct 2000 13:55:36 GMT","remote":"127.0.0.1","user":"frank","#,
+            r#""method":"GET","path":"/apache_pb.gif","version":"HTTP/1.0","status":200,"bytes":2326,"millis":3}"#
+        );
+        assert_eq!(json, entry().format(Format::Json));
+
+        let unknown = Entry { remote: None, user: None, path: String::from("/a\"b"), bytes: None, ..entry() };
+        assert_eq!(r#"- - - [10/Oct/2000:13:55:36 +0000] "GET /a\"b HTTP/1.0" 200 -"#, unknown.format(Format::Common));
+        let json = unknown.format(Format::Json);
+        assert!(json.contains(r#""remote":null,"user":null,"method":"GET","path":"/a\"b","#) && json.contains(r#""bytes":null"#));
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = env::temp_dir().join(format!("mws-access-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert_eq!("four\nfive\n", read(&path));
+        assert_eq!("three\n", read(&dir.join("access.log.1")));
+        assert_eq!("one\ntwo\n", read(&dir.join("access.log.2")));
+        assert!(!dir.join("access.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn logs_every_request_and_flushes_on_a_timer() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Shared::default();
+        let writer = BufWriter::new(output.clone());
+        let log = AccessLog::to_writer(Format::Common, writer, Duration::from_millis(20));
+        let app = Chain::new(|_: &mut Request| Response::text(404, "nothing here")).with(log);
+        let mut request = Request::read_from(&mut "GET /missing HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        request.extensions_mut().insert(Identity::new("ferris"));
+        app.handle(&mut request);
+
+        // Still in the BufWriter, until the flusher comes along
+        let logged = || String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!("", logged());
+        thread::sleep(Duration::from_millis(100));
+        let line = logged();
+        assert!(line.starts_with("- - ferris [") && line.ends_with("\"GET /missing HTTP/1.1\" 404 12\n"), "{line}");
+    }
+}
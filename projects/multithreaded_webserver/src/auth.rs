@@ -0,0 +1,362 @@
+// Authentication
+
+// Sessions remember a visitor who logged in through a form. An API client has no form and often no cookies: it sends who it is
+// with every request, in the Authorization header, in one of two schemes:
+    // 1. Basic: "Basic " and the base64 (src/encoding.rs) of "user:password". Anyone who sees the request sees the password,
+    //    so it's only for connections nobody can listen in on.
+    // 2. Bearer: "Bearer " and a token the server handed out earlier. The token stands for a user, and can be revoked
+    //    without changing the password.
+// Auth checks the header against the stores it was given, a CredentialStore for passwords and a TokenStore for tokens, and puts
+// the Identity they answer with in the request's extensions (src/extensions.rs), where a handler finds it with req.identity().
+// The paths given to protect() can't be reached without one: the answer is a 401 that says which schemes would do,
+// in WWW-Authenticate.
+// Everywhere else a request without credentials goes on as nobody in particular, and the handler decides.
+
+// MemoryCredentials is both stores, in memory, and shows how the secrets should be kept:
+    // 1. Passwords are never stored, only an HMAC (src/hmac.rs) of each with a random salt of its own, so that two users with
+    //    the same password have different hashes. Checking a password computes the HMAC again and compares.
+    // 2. Tokens are stored by their SHA-1, so the map can be looked up by the token without holding the token itself.
+// The comparisons go through hmac::constant_time_eq, which takes as long for a hash that is wrong in its first byte as in its last,
+// and a user that doesn't exist costs an HMAC too. Otherwise how long the answer took would tell an attacker what it got right.
+// HMAC-SHA1 is quick to compute, which is what an attacker with a stolen store wants: a real server would use a slow hash
+// like Argon2, behind the same trait.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use std_collections::rand_lite::random_u64;
+
+use crate::{
+    encoding::{hex_encode, Base64},
+    hmac::{constant_time_eq, hmac_sha1},
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    sha1::sha1,
+};
+
+// Who made the request, once the credentials checked out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+impl Identity {
+    pub fn new(name: &str) -> Identity {
+        Identity { name: name.to_string(), roles: Vec::new() }
+    }
+
+    pub fn with_role(mut self, role: &str) -> Identity {
+        self.roles.push(role.to_string());
+        self
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+pub trait CredentialStore: Send + Sync {
+    fn check_password(&self, user: &str, password: &str) -> Option<Identity>;
+}
+
+pub trait TokenStore: Send + Sync {
+    fn check_token(&self, token: &str) -> Option<Identity>;
+}
+
+// What an Authorization header says, before it's checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Basic { user: String, password: String },
+    Bearer(String),
+}
+
+impl Credentials {
+    // None for a scheme we don't know, or a Basic that isn't base64 of "user:password"
+    pub fn parse(header: &str) -> Option<Credentials> {
+        let (scheme, value) = header.trim().split_once(' ')?;
+        let value = value.trim();
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let decoded = String::from_utf8(Base64::Standard.decode(value).ok()?).ok()?;
+            let (user, password) = decoded.split_once(':')?;
+            Some(Credentials::Basic { user: user.to_string(), password: password.to_string() })
+        } else if scheme.eq_ignore_ascii_case("Bearer") && !value.is_empty() {
+            Some(Credentials::Bearer(value.to_string()))
+        } else {
+            None
+        }
+    }
+}
+
+// The password isn't printed, not even in a debug log
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::Basic { user, .. } => write!(f, "Basic credentials for {user}"),
+            Credentials::Bearer(_) => write!(f, "a bearer token"),
+        }
+    }
+}
+
+struct User {
+    salt: [u8; 16],
+    hash: [u8; 20],
+    identity: Identity,
+}
+
+#[derive(Default)]
+pub struct MemoryCredentials {
+    users: RwLock<HashMap<String, User>>,
+    // By the hex SHA-1 of the token
+    tokens: RwLock<HashMap<String, Identity>>,
+}
+
+fn random_salt() -> [u8; 16] {
+    let mut salt = [0; 16];
+    salt[..8].copy_from_slice(&random_u64().to_le_bytes());
+    salt[8..].copy_from_slice(&random_u64().to_le_bytes());
+    salt
+}
+
+fn token_key(token: &str) -> String {
+    hex_encode(&sha1(token.as_bytes()))
+}
+
+impl MemoryCredentials {
+    pub fn new() -> MemoryCredentials {
+        MemoryCredentials::default()
+    }
+
+    // Adds the user, or changes the password and the roles of one that's there
+    pub fn add_user(&self, identity: Identity, password: &str) {
+        let salt = random_salt();
+        let user = User { salt, hash: hmac_sha1(&salt, password.as_bytes()), identity };
+        self.users.write().unwrap().insert(user.identity.name.clone(), user);
+    }
+
+    pub fn remove_user(&self, name: &str) {
+        self.users.write().unwrap().remove(name);
+        self.tokens.write().unwrap().retain(|_, identity| identity.name != name);
+    }
+
+    pub fn add_token(&self, token: &str, identity: Identity) {
+        self.tokens.write().unwrap().insert(token_key(token), identity);
+    }
+
+    // Makes a new random token for the identity, which the client is given once and the store never sees again
+    pub fn issue_token(&self, identity: Identity) -> String {
+        let token = Base64::UrlSafe.encode(&random_salt());
+        self.add_token(&token, identity);
+        token
+    }
+
+    pub fn revoke_token(&self, token: &str) -> bool {
+        self.tokens.write().unwrap().remove(&token_key(token)).is_some()
+    }
+}
+
+impl CredentialStore for MemoryCredentials {
+    fn check_password(&self, user: &str, password: &str) -> Option<Identity> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(user) => constant_time_eq(&hmac_sha1(&user.salt, password.as_bytes()), &user.hash).then(|| user.identity.clone()),
+            // As much work as for a user that exists, so the time doesn't tell which names are taken
+            None => {
+                let _ = constant_time_eq(&hmac_sha1(&[0; 16], password.as_bytes()), &[0; 20]);
+                None
+            }
+        }
+    }
+}
+
+impl TokenStore for MemoryCredentials {
+    fn check_token(&self, token: &str) -> Option<Identity> {
+        self.tokens.read().unwrap().get(&token_key(token)).cloned()
+    }
+}
+
+pub struct Auth {
+    passwords: Option<Arc<dyn CredentialStore>>,
+    tokens: Option<Arc<dyn TokenStore>>,
+    realm: String,
+    protected: Vec<String>,
+}
+
+impl Default for Auth {
+    fn default() -> Auth {
+        Auth::new()
+    }
+}
+
+impl Auth {
+    // Checks no credentials until it's given a store for them
+    pub fn new() -> Auth {
+        Auth { passwords: None, tokens: None, realm: String::from("restricted"), protected: Vec::new() }
+    }
+
+    pub fn basic(mut self, store: Arc<dyn CredentialStore>) -> Auth {
+        self.passwords = Some(store);
+        self
+    }
+
+    pub fn bearer(mut self, store: Arc<dyn TokenStore>) -> Auth {
+        self.tokens = Some(store);
+        self
+    }
+
+    // The name the browser shows in its login dialog for Basic
+    pub fn realm(mut self, realm: &str) -> Auth {
+        self.realm = realm.to_string();
+        self
+    }
+
+    // The path and everything below it need an identity: "/admin" is /admin and /admin/users, but not /administrator
+    pub fn protect(mut self, prefix: &str) -> Auth {
+        self.protected.push(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn is_protected(&self, path: &str) -> bool {
+        self.protected.iter().any(|prefix| match path.strip_prefix(prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        })
+    }
+
+    pub fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        match credentials {
+            Credentials::Basic { user, password } => self.passwords.as_ref()?.check_password(user, password),
+            Credentials::Bearer(token) => self.tokens.as_ref()?.check_token(token),
+        }
+    }
+
+    // The 401, with a challenge for each scheme there is a store for
+    fn challenge(&self) -> Response {
+        let mut response = Response::text(401, "Unauthorized");
+        if self.passwords.is_some() {
+            response = response.with_header("WWW-Authenticate", &format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm));
+        }
+        if self.tokens.is_some() {
+            response = response.with_header("WWW-Authenticate", &format!("Bearer realm=\"{}\"", self.realm));
+        }
+        response
+    }
+}
+
+impl Middleware for Auth {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let credentials = request.header("Authorization").and_then(Credentials::parse);
+        let identity = credentials.as_ref().and_then(|credentials| self.authenticate(credentials));
+        if identity.is_none() && self.is_protected(request.path_only()) {
+            if let Some(credentials) = &credentials {
+                common::debug!("{} {}: {credentials} didn't check out", request.method, request.path);
+            }
+            return self.challenge();
+        }
+        if let Some(identity) = identity {
+            request.extensions_mut().insert(identity);
+        }
+        next.run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+
+    fn request(path: &str, authorization: Option<&str>) -> Request {
+        let header = authorization.map_or(String::new(), |value| format!("Authorization: {value}\r\n"));
+        Request::read_from(&mut format!("GET {path} HTTP/1.1\r\n{header}\r\n").as_bytes()).unwrap()
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        format!("Basic {}", Base64::Standard.encode(format!("{user}:{password}").as_bytes()))
+    }
+
+    fn app() -> (Arc<MemoryCredentials>, Chain) {
+        let store = Arc::new(MemoryCredentials::new());
+        store.add_user(Identity::new("ferris").with_role("admin"), "crab:rave");
+        let auth = Auth::new().basic(store.clone()).bearer(store.clone()).realm("admin area").protect("/admin/");
+        let app = Chain::new(|req: &mut Request| match req.identity() {
+            Some(identity) => Response::text(200, &format!("hello {}", identity.name)),
+            None => Response::text(200, "hello stranger"),
+        })
+        .with(auth);
+        (store, app)
+    }
+
+    fn answer(app: &Chain, request: &mut Request) -> (u16, String) {
+        let response = app.handle(request);
+        let body = match &response.body {
+            crate::http::Body::Bytes(body) => String::from_utf8(body.clone()).unwrap(),
+            crate::http::Body::EventStream(_) => panic!("expected a body"),
+        };
+        (response.status, body)
+    }
+
+    #[test]
+    fn parses_the_schemes() {
+        // The RFC 7617 example
+        assert_eq!(
+            Some(Credentials::Basic { user: String::from("Aladdin"), password: String::from("open sesame") }),
+            Credentials::parse("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==")
+        );
+        assert_eq!(Some(Credentials::Bearer(String::from("abc.def"))), Credentials::parse("bearer  abc.def"));
+        for header in ["Basic", "Basic !!!", "Basic bm9jb2xvbg==", "Bearer ", "Digest username=x", ""] {
+            assert_eq!(None, Credentials::parse(header), "{header}");
+        }
+        assert!(!Credentials::parse(&basic("ferris", "secret")).unwrap().to_string().contains("secret"));
+    }
+
+    #[test]
+    fn passwords_are_checked_against_salted_hashes() {
+        let store = MemoryCredentials::new();
+        store.add_user(Identity::new("a"), "same");
+        store.add_user(Identity::new("b"), "same");
+        {
+            let users = store.users.read().unwrap();
+            assert_ne!(users["a"].hash, users["b"].hash);
+        }
+        assert_eq!(Some(Identity::new("a")), store.check_password("a", "same"));
+        assert_eq!(None, store.check_password("a", "Same"));
+        assert_eq!(None, store.check_password("nobody", "same"));
+        store.remove_user("a");
+        assert_eq!(None, store.check_password("a", "same"));
+    }
+
+    #[test]
+    fn protected_paths_need_an_identity() {
+        let (_, app) = app();
+        assert_eq!((200, String::from("hello stranger")), answer(&app, &mut request("/", None)));
+        assert_eq!((200, String::from("hello stranger")), answer(&app, &mut request("/administrator", None)));
+        for path in ["/admin", "/admin/users?page=2"] {
+            let response = app.handle(&mut request(path, None));
+            assert_eq!(401, response.status, "{path}");
+            assert_eq!(Some("Basic realm=\"admin area\", charset=\"UTF-8\""), response.header("WWW-Authenticate"));
+        }
+
+        let ferris = basic("ferris", "crab:rave");
+        assert_eq!((200, String::from("hello ferris")), answer(&app, &mut request("/admin", Some(&ferris))));
+        assert_eq!((200, String::from("hello ferris")), answer(&app, &mut request("/", Some(&ferris))));
+        assert_eq!(401, answer(&app, &mut request("/admin", Some(&basic("ferris", "wrong")))).0);
+        // Wrong credentials where none are needed are no credentials
+        assert_eq!((200, String::from("hello stranger")), answer(&app, &mut request("/", Some(&basic("ferris", "wrong")))));
+    }
+
+    #[test]
+    fn bearer_tokens_until_revoked() {
+        let (store, app) = app();
+        let token = store.issue_token(Identity::new("ci-bot"));
+        let bearer = format!("Bearer {token}");
+        assert_eq!((200, String::from("hello ci-bot")), answer(&app, &mut request("/admin/deploy", Some(&bearer))));
+        assert!(!store.tokens.read().unwrap().contains_key(&token));
+
+        assert!(store.revoke_token(&token));
+        assert_eq!(401, answer(&app, &mut request("/admin/deploy", Some(&bearer))).0);
+        assert_eq!(401, answer(&app, &mut request("/admin/deploy", Some("Bearer made-up"))).0);
+    }
+}
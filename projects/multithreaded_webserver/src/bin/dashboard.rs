@@ -0,0 +1,141 @@
+// The Dashboard
+
+// $ cargo run --bin dashboard -- --url http://127.0.0.1:7878/metrics --interval 500
+// A live view of a running server, in the terminal: requests per second, latency, busy workers and the last lines of its log.
+// The server has to serve /metrics with MetricsEndpoint, like mt_main_dashboard in main.rs does. q, Esc or Ctrl-C quits.
+
+// Three threads, so that nothing waits on anything else:
+    // 1. One fetches a snapshot with the HTTP client (src/client.rs) every interval. A Deadline set before each fetch says how
+    //    long to sleep after it, so a slow fetch doesn't push every later one back.
+    // 2. One reads keys. The terminal is in raw mode, so they come one at a time, and Ctrl-C is a key, not a signal.
+    // 3. This one draws a frame for every snapshot, and checks the terminal's size between them.
+// The dashboard goes on the alternate screen, and the guard puts the old screen and the cursor back, even after a panic.
+
+use std::{
+    io::{self, prelude::*, IsTerminal},
+    process,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{
+    term::{self, RawMode, Screen, Size},
+    time::Deadline,
+};
+use common::argparse::{ArgError, Parser};
+use multithreaded_webserver::{
+    client::Client,
+    codec,
+    dashboard::Dashboard,
+    guard::ScopeGuard,
+    metrics::MetricsSnapshot,
+};
+
+enum Event {
+    Fetched(Result<MetricsSnapshot, String>, Instant),
+    Quit,
+}
+
+fn options() -> Result<(String, Duration), ArgError> {
+    let matches = Parser::new("dashboard", "a live view of the webserver's /metrics in the terminal")
+        .option("url", "URL", "Where the server serves its metrics")
+        .default("http://127.0.0.1:7878/metrics")
+        .option("interval", "MS", "How often to fetch them, in milliseconds")
+        .default("1000")
+        .parse(std::env::args().skip(1))?;
+    let interval: u64 = matches.get("interval")?.unwrap();
+    if interval == 0 {
+        return Err(ArgError::Invalid(String::from("--interval must be at least 1")));
+    }
+    Ok((matches.value("url").unwrap().to_string(), Duration::from_millis(interval)))
+}
+
+fn fetch(client: &Client, url: &str) -> Result<MetricsSnapshot, String> {
+    let response = client.get(url).map_err(|e| e.to_string())?;
+    if !response.is_success() {
+        return Err(format!("{url} answered {}", response.status));
+    }
+    codec::from_bytes(&response.body).map_err(|e| format!("{url} didn't send a snapshot: {e}"))
+}
+
+fn fetcher(url: String, interval: Duration, events: mpsc::Sender<Event>) {
+    let client = Client::new().timeout(interval.max(Duration::from_secs(1)));
+    loop {
+        let deadline = Deadline::after(interval);
+        let snapshot = fetch(&client, &url);
+        if events.send(Event::Fetched(snapshot, Instant::now())).is_err() {
+            return;
+        }
+        thread::sleep(deadline.remaining());
+    }
+}
+
+fn keys(events: mpsc::Sender<Event>) {
+    for byte in io::stdin().lock().bytes() {
+        match byte {
+            Ok(b'q' | b'Q' | 0x1b | 0x03) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+    // The main thread may be gone already
+    let _ = events.send(Event::Quit);
+}
+
+fn run(url: String, interval: Duration) -> io::Result<()> {
+    let _raw = RawMode::enable()?;
+    let mut stdout = io::stdout();
+    write!(stdout, "{}{}", term::ALTERNATE_SCREEN, term::HIDE_CURSOR)?;
+    let _restore = ScopeGuard::new((), |()| {
+        print!("{}{}{}", term::RESET, term::SHOW_CURSOR, term::MAIN_SCREEN);
+        // Nothing to be done about a terminal that can't be written to anymore
+        let _ = io::stdout().flush();
+    });
+
+    let (sender, events) = mpsc::channel();
+    {
+        let (url, sender) = (url.clone(), sender.clone());
+        thread::spawn(move || fetcher(url, interval, sender));
+    }
+    thread::spawn(move || keys(sender));
+
+    let fallback = Size { cols: 80, rows: 24 };
+    let mut screen = Screen::new(term::size().unwrap_or(fallback));
+    let mut dashboard = Dashboard::new(&url);
+    loop {
+        match events.recv_timeout(Duration::from_millis(250)) {
+            Ok(Event::Fetched(Ok(snapshot), at)) => dashboard.update(snapshot, at),
+            Ok(Event::Fetched(Err(e), _)) => dashboard.failed(&e),
+            Ok(Event::Quit) | Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+        }
+        let size = term::size().unwrap_or(fallback);
+        if size != screen.size() {
+            screen.resize(size);
+        }
+        dashboard.draw(&mut screen);
+        screen.present(&mut stdout)?;
+    }
+}
+
+fn main() {
+    let (url, interval) = match options() {
+        Ok(options) => options,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        eprintln!("the dashboard draws in a terminal, and needs one for stdin and stdout");
+        process::exit(1);
+    }
+    if let Err(e) = run(url, interval) {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,268 @@
+// A Publish/Subscribe Broker
+
+// With server-sent events and WebSockets, one handler often produces messages that other connections should stream to their clients,
+// like a chat where every message posted by one user appears for everybody. The handlers don't know about each other,
+// so they meet in a broker: publishers send messages to a named topic, and every subscriber of that topic gets its own clone.
+
+// Why not use an mpsc channel per subscriber? Two reasons:
+    // 1. A slow client shouldn't make memory grow without limit. Each subscriber has a bounded buffer, and when it's full the oldest
+    //    message is dropped to make room, because for live updates the newest data matters most. std's sync_channel can only block the sender,
+    //    and a publisher must never be held up by one slow subscriber.
+    // 2. The broker keeps only Weak references to the subscribers' queues. When a Subscription is dropped (its client disconnected),
+    //    the queue is freed right away and the broker notices the dead Weak the next time it publishes to that topic, and removes it.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex, RwLock, Weak,
+    },
+    time::Duration,
+};
+
+struct Queue<T> {
+    messages: Mutex<VecDeque<T>>,
+    available: Condvar,
+    capacity: usize,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl<T> Queue<T> {
+    fn push(&self, message: T) {
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() == self.capacity {
+            messages.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        messages.push_back(message);
+        self.available.notify_one();
+    }
+
+    fn close(&self) {
+        // Taking the lock makes sure a receiver can't check `closed` and then start waiting just after we notified
+        let _messages = self.messages.lock().unwrap();
+        self.closed.store(true, Ordering::Relaxed);
+        self.available.notify_all();
+    }
+}
+
+pub struct Broker<T> {
+    topics: RwLock<HashMap<String, Vec<Weak<Queue<T>>>>>,
+    capacity: usize,
+}
+
+impl<T: Clone> Broker<T> {
+    // capacity is the number of messages buffered per subscriber before the oldest ones are dropped
+    pub fn new(capacity: usize) -> Broker<T> {
+        assert!(capacity > 0);
+        Broker { topics: RwLock::new(HashMap::new()), capacity }
+    }
+
+    pub fn subscribe(&self, topic: &str) -> Subscription<T> {
+        let queue = Arc::new(Queue {
+            messages: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            capacity: self.capacity,
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        });
+        self.topics
+            .write()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_default()
+            .push(Arc::downgrade(&queue));
+        Subscription { topic: topic.to_string(), queue }
+    }
+
+    // Sends a clone of the message to every live subscriber of the topic and returns how many there were
+    pub fn publish(&self, topic: &str, message: T) -> usize {
+        let mut delivered = 0;
+        let mut dead = false;
+        {
+            let topics = self.topics.read().unwrap();
+            for weak in topics.get(topic).into_iter().flatten() {
+                match weak.upgrade() {
+                    Some(queue) => {
+                        queue.push(message.clone());
+                        delivered += 1;
+                    }
+                    None => dead = true,
+                }
+            }
+        }
+
+        // Cleaning up needs the write lock, so it's done separately and only when there is something to clean
+        if dead {
+            let mut topics = self.topics.write().unwrap();
+            if let Some(subscribers) = topics.get_mut(topic) {
+                subscribers.retain(|weak| weak.strong_count() > 0);
+                if subscribers.is_empty() {
+                    topics.remove(topic);
+                }
+            }
+        }
+        delivered
+    }
+
+    pub fn subscriber_count(&self, topic: &str) -> usize {
+        self.topics
+            .read()
+            .unwrap()
+            .get(topic)
+            .map_or(0, |subscribers| subscribers.iter().filter(|weak| weak.strong_count() > 0).count())
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self.topics.read().unwrap().keys().cloned().collect();
+        topics.sort();
+        topics
+    }
+}
+
+// Closing the broker wakes up every subscriber blocked in recv(), which then returns None once its buffer is empty
+impl<T> Drop for Broker<T> {
+    fn drop(&mut self) {
+        for weak in self.topics.get_mut().unwrap().values().flatten() {
+            if let Some(queue) = weak.upgrade() {
+                queue.close();
+            }
+        }
+    }
+}
+
+pub struct Subscription<T> {
+    topic: String,
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Subscription<T> {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    // Blocks until a message arrives, None means the broker is gone and no messages are left
+    pub fn recv(&self) -> Option<T> {
+        let mut messages = self.queue.messages.lock().unwrap();
+        loop {
+            if let Some(message) = messages.pop_front() {
+                return Some(message);
+            }
+            if self.queue.closed.load(Ordering::Relaxed) {
+                return None;
+            }
+            messages = self.queue.available.wait(messages).unwrap();
+        }
+    }
+
+    // Like recv, but gives up after the timeout. Streaming handlers use it to check regularly whether their client is still there.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let messages = self.queue.messages.lock().unwrap();
+        let (mut messages, _) = self
+            .queue
+            .available
+            .wait_timeout_while(messages, timeout, |m| m.is_empty() && !self.queue.closed.load(Ordering::Relaxed))
+            .unwrap();
+        messages.pop_front()
+    }
+
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.messages.lock().unwrap().pop_front()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.closed.load(Ordering::Relaxed)
+    }
+
+    // How many messages were thrown away because this subscriber didn't keep up
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.recv())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn every_subscriber_gets_a_copy() {
+        let broker = Broker::new(10);
+        let a = broker.subscribe("chat");
+        let b = broker.subscribe("chat");
+        let other = broker.subscribe("news");
+
+        assert_eq!(2, broker.publish("chat", String::from("hi")));
+        assert_eq!(0, broker.publish("nobody", String::from("hello?")));
+
+        assert_eq!(Some(String::from("hi")), a.try_recv());
+        assert_eq!(Some(String::from("hi")), b.try_recv());
+        assert_eq!(None, other.try_recv());
+        assert_eq!(vec!["chat", "news"], broker.topics());
+    }
+
+    #[test]
+    fn full_buffers_drop_the_oldest_messages() {
+        let broker = Broker::new(3);
+        let slow = broker.subscribe("ticks");
+        for i in 0..5 {
+            broker.publish("ticks", i);
+        }
+
+        assert_eq!(2, slow.dropped());
+        assert_eq!(vec![2, 3, 4], std::iter::from_fn(|| slow.try_recv()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn dropped_subscriptions_are_cleaned_up() {
+        let broker = Broker::new(3);
+        let kept = broker.subscribe("chat");
+        let gone = broker.subscribe("chat");
+        let only = broker.subscribe("lonely");
+        drop(gone);
+        drop(only);
+
+        assert_eq!(1, broker.subscriber_count("chat"));
+        assert_eq!(1, broker.publish("chat", 1));
+        assert_eq!(0, broker.publish("lonely", 1));
+        assert_eq!(1, broker.topics.read().unwrap()["chat"].len());
+        assert_eq!(vec!["chat"], broker.topics());
+        assert_eq!(Some(1), kept.try_recv());
+    }
+
+    #[test]
+    fn subscribers_on_other_threads_wake_up() {
+        let broker = Arc::new(Broker::new(100));
+        let subscription = broker.subscribe("numbers");
+
+        let receiver = thread::spawn(move || subscription.iter().collect::<Vec<i32>>());
+
+        let publisher = {
+            let broker = Arc::clone(&broker);
+            thread::spawn(move || {
+                for i in 0..50 {
+                    broker.publish("numbers", i);
+                }
+            })
+        };
+        publisher.join().unwrap();
+
+        // Dropping the last handle to the broker closes it, which ends the receiver's iterator
+        drop(broker);
+        assert_eq!((0..50).collect::<Vec<_>>(), receiver.join().unwrap());
+    }
+
+    #[test]
+    fn recv_timeout_gives_up() {
+        let broker: Broker<u8> = Broker::new(1);
+        let subscription = broker.subscribe("quiet");
+        assert_eq!(None, subscription.recv_timeout(Duration::from_millis(20)));
+        broker.publish("quiet", 9);
+        assert_eq!(Some(9), subscription.recv_timeout(Duration::from_millis(20)));
+    }
+}
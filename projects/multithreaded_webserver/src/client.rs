@@ -0,0 +1,254 @@
+// An HTTP Client
+
+// Everything else in this crate is the server's side of HTTP. The other side is short for plain HTTP/1.1: connect, write a
+// request line and headers, and read a status line, headers and a body. The body is where the work is, since a response
+// says where it ends in one of three ways:
+    // 1. Content-Length, a number of bytes, which is what Response::write_to sends.
+    // 2. Transfer-Encoding: chunked, pieces that each start with their length in hex, until one of length 0.
+    // 3. Neither, and the body goes on until the server closes the connection.
+// Every request says Connection: close, so there's no keep-alive to manage and 3. works too. No https, there's no TLS here.
+// The timeout is for connecting and for every read and write after it, so a server that stops answering can't hang the
+// client forever. src/bin/dashboard.rs polls /metrics with it.
+
+use std::{
+    fmt,
+    io::{self, prelude::*, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::url::{Url, UrlError};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Url(UrlError),
+    UnsupportedScheme(String),
+    Io(io::Error),
+    // The server's answer isn't HTTP
+    Malformed(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Url(e) => write!(f, "{e}"),
+            ClientError::UnsupportedScheme(scheme) => write!(f, "{scheme}: URLs aren't supported, only http:"),
+            ClientError::Io(e) => write!(f, "{e}"),
+            ClientError::Malformed(what) => write!(f, "malformed response: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<UrlError> for ClientError {
+    fn from(e: UrlError) -> ClientError {
+        ClientError::Url(e)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+    // Header names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    timeout: Duration,
+    headers: Vec<(String, String)>,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client { timeout: Duration::from_secs(10), headers: vec![(String::from("User-Agent"), String::from("learning_rust"))] }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Sent with every request
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<ClientResponse, ClientError> {
+        self.request("GET", url, &[])
+    }
+
+    pub fn request(&self, method: &str, url: &str, body: &[u8]) -> Result<ClientResponse, ClientError> {
+        let url = Url::parse(url)?;
+        if url.scheme() != "http" {
+            return Err(ClientError::UnsupportedScheme(url.scheme().to_string()));
+        }
+        let host = url.host().ok_or(UrlError::MissingHost)?;
+        let port = url.port_or_default().unwrap_or(80);
+        let stream = self.connect(host, port)?;
+
+        let mut head = format!("{method} {} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n", url.request_target());
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !body.is_empty() || method == "POST" || method == "PUT" {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+        let mut writer = &stream;
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(body)?;
+        writer.flush()?;
+
+        read_response(&mut BufReader::new(&stream), method == "HEAD")
+    }
+
+    // The first of the host's addresses that answers
+    fn connect(&self, host: &str, port: u16) -> Result<TcpStream, ClientError> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, format!("{host} has no addresses"));
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last = e,
+            }
+        }
+        Err(ClientError::Io(last))
+    }
+}
+
+fn line(reader: &mut impl BufRead) -> Result<String, ClientError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(ClientError::Malformed(String::from("the connection closed early")));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// A response from the reader, the body read the way the headers say. A response to HEAD never has one
+pub fn read_response(reader: &mut impl BufRead, head: bool) -> Result<ClientResponse, ClientError> {
+    let status_line = line(reader)?;
+    let status = match status_line.split(' ').collect::<Vec<_>>()[..] {
+        [version, status, ..] if version.starts_with("HTTP/1.") => status.parse().ok(),
+        _ => None,
+    };
+    let status = status.ok_or_else(|| ClientError::Malformed(format!("status line {status_line:?}")))?;
+
+    let mut headers = Vec::new();
+    loop {
+        let header = line(reader)?;
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').ok_or_else(|| ClientError::Malformed(format!("header {header:?}")))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let mut response = ClientResponse { status, headers, body: Vec::new() };
+
+    if head || status == 204 || status == 304 || (100..200).contains(&status) {
+        return Ok(response);
+    }
+    let chunked = response.header("Transfer-Encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+    if chunked {
+        response.body = read_chunked(reader)?;
+    } else if let Some(length) = response.header("Content-Length") {
+        let length: usize = length.parse().map_err(|_| ClientError::Malformed(format!("Content-Length {length:?}")))?;
+        response.body = vec![0; length];
+        reader.read_exact(&mut response.body)?;
+    } else {
+        reader.read_to_end(&mut response.body)?;
+    }
+    Ok(response)
+}
+
+// Chunks until the one of length 0. What comes after the size, an extension after a ;, and the trailers are skipped
+fn read_chunked(reader: &mut impl BufRead) -> Result<Vec<u8>, ClientError> {
+    let mut body = Vec::new();
+    loop {
+        let size_line = line(reader)?;
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| ClientError::Malformed(format!("chunk size {size:?}")))?;
+        if size == 0 {
+            while !line(reader)?.is_empty() {}
+            return Ok(body);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        line(reader)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn reads_the_three_kinds_of_body() {
+        let read = |raw: &str| read_response(&mut raw.as_bytes(), false).unwrap();
+        let sized = read("HTTP/1.1 200 OK\r\nContent-Length: 5\r\ncontent-type: text/plain\r\n\r\nhello, and more");
+        assert_eq!((200, &b"hello"[..], Some("text/plain")), (sized.status, &sized.body[..], sized.header("Content-Type")));
+
+        let chunks = "5\r\nhello\r\na;x=1\r\n, chunked!\r\n0\r\nTrailer: 1\r\n\r\n";
+        let chunked = read(&format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{chunks}"));
+        assert_eq!(b"hello, chunked!", &chunked.body[..]);
+
+        let closed = read("HTTP/1.0 404 Not Found\r\n\r\nuntil the end");
+        assert_eq!((404, &b"until the end"[..], false), (closed.status, &closed.body[..], closed.is_success()));
+
+        assert!(matches!(read_response(&mut "SSH-2.0\r\n".as_bytes(), false), Err(ClientError::Malformed(_))));
+        let head = read_response(&mut "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n".as_bytes(), true).unwrap();
+        assert!(head.body.is_empty());
+    }
+
+    #[test]
+    fn gets_from_a_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let request: Vec<String> = (&mut reader).lines().map(Result::unwrap).take_while(|line| !line.is_empty()).collect();
+            (&stream).write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            request
+        });
+
+        let response = Client::new().header("Accept", "text/plain").get(&format!("http://{addr}/metrics?x=1")).unwrap();
+        assert_eq!((200, &b"ok"[..]), (response.status, &response.body[..]));
+        let request = server.join().unwrap();
+        assert_eq!("GET /metrics?x=1 HTTP/1.1", request[0]);
+        assert!(request.contains(&String::from("Connection: close")), "{request:?}");
+        assert!(request.contains(&String::from("Accept: text/plain")), "{request:?}");
+
+        assert!(matches!(Client::new().get("https://example.com/"), Err(ClientError::UnsupportedScheme(_))));
+    }
+}
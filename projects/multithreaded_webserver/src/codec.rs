@@ -0,0 +1,4 @@
+// The binary codec lives in its own crate, advanced_features/macros/codec, so that other projects can use it as well.
+// Re-exporting it here keeps crate::codec::{to_bytes, Serialize, ...} working for the rest of the server.
+
+pub use ::codec::*;
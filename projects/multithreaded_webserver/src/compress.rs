@@ -0,0 +1,419 @@
+// Response Compression
+
+// HTML, CSS, JavaScript and JSON are very repetitive, and compressing them typically makes responses 3 to 10 times smaller.
+// A browser lists the compressions it understands in the Accept-Encoding header, and the server says which one it used in Content-Encoding:
+    // Accept-Encoding: gzip, deflate, br
+    // Content-Encoding: gzip
+
+// Both "gzip" and "deflate" are the DEFLATE algorithm (RFC 1951) with a different wrapper around it:
+    // gzip (RFC 1952): a 10 byte header, the DEFLATE data, then a CRC-32 checksum and the original length
+    // deflate (RFC 1950, confusingly called zlib): a 2 byte header, the DEFLATE data, then an Adler-32 checksum
+
+// DEFLATE itself works in two stages:
+    // 1. LZ77: repeated text is replaced by a (length, distance) pair meaning "copy length bytes from distance bytes back".
+    //    To find earlier occurrences quickly, we hash every 3 bytes and keep, for each hash, a chain of the positions where it appeared.
+    // 2. Huffman coding: the literal bytes, lengths and distances are written with variable length codes, short codes for common symbols.
+    //    DEFLATE has a fixed set of codes built into the format, which we use. Building custom codes for each response compresses a bit better,
+    //    but the fixed codes keep this implementation short and already get most of the benefit from LZ77.
+
+use std_collections::hashing::crc32;
+
+use crate::{
+    http::{Body, Request, Response},
+    middleware::{Middleware, Next},
+    negotiation::negotiate_encoding,
+};
+
+// Writes bits starting from the least significant bit of each byte, which is the order DEFLATE uses
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { out: Vec::new(), bits: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are defined most significant bit first, so they are reversed before writing
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// How many earlier positions we try per byte. More finds longer matches but takes longer.
+const MAX_CHAIN: usize = 64;
+const HASH_SIZE: usize = 1 << 15;
+
+// The fixed literal/length codes from RFC 1951 section 3.2.6
+fn write_literal_or_length(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    // The last base that is <= the value gives the symbol, and the difference goes in the extra bits
+    let i = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    write_literal_or_length(writer, 257 + i as u32);
+    writer.write((length - LENGTH_BASE[i] as usize) as u32, LENGTH_EXTRA[i] as u32);
+
+    let d = DIST_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+    writer.write_code(d as u32, 5);
+    writer.write((distance - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as usize) << 16 | (data[pos + 1] as usize) << 8 | data[pos + 2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) & (HASH_SIZE - 1)
+}
+
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(data, pos);
+        prev[pos % WINDOW_SIZE] = head[h];
+        head[h] = pos;
+    }
+}
+
+// Raw DEFLATE data, a single block with the fixed Huffman codes
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // BFINAL = 1 (this is the last block), BTYPE = 01 (fixed Huffman codes)
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    // head[h] is the latest position with hash h, prev[pos % WINDOW_SIZE] the position before that with the same hash
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(data, pos)];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // Entries in prev get overwritten as the window slides, a "previous" position that isn't smaller is stale
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut writer, best_len, best_dist);
+            for p in pos..pos + best_len {
+                insert(data, p, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            write_literal_or_length(&mut writer, data[pos] as u32);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    // The end of block symbol
+    write_literal_or_length(&mut writer, 256);
+    writer.finish()
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic bytes, compression method 8 (deflate), no flags, no modification time, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+// What HTTP calls "deflate" is the zlib format
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // 0x78 = deflate with a 32K window, 0x01 = fastest level and a check value making the header a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// Content Negotiation
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Gzip => gzip(data),
+            Encoding::Deflate => zlib(data),
+        }
+    }
+}
+
+// Accept-Encoding entries can carry a quality, like "gzip;q=0.5, deflate". q=0 means "not acceptable".
+// The q-values are worked out by src/negotiation.rs: the supported encoding with the highest quality wins, gzip on a tie,
+// and None is the uncompressed body, when the client prefers it or accepts nothing else.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    match negotiate_encoding(&["gzip", "deflate", "identity"], Some(accept_encoding)) {
+        Some("gzip") => Some(Encoding::Gzip),
+        Some("deflate") => Some(Encoding::Deflate),
+        _ => None,
+    }
+}
+
+// Images, video, audio and archives are already compressed, compressing them again wastes CPU for nothing
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    if mime == "image/svg+xml" {
+        return true;
+    }
+    !(mime.starts_with("image/")
+        || mime.starts_with("video/")
+        || mime.starts_with("audio/")
+        || matches!(
+            mime.as_str(),
+            "application/zip" | "application/gzip" | "application/x-gzip" | "application/octet-stream" | "font/woff2"
+        ))
+}
+
+// Compresses responses for clients that accept it. Small responses are left alone:
+// below about a kilobyte the gzip header and the CPU time cost more than the few bytes saved.
+pub struct Compression {
+    min_size: usize,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression { min_size: 1024 }
+    }
+
+    pub fn min_size(mut self, min_size: usize) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let encoding = request.header("Accept-Encoding").and_then(negotiate);
+        let mut response = next.run(request);
+
+        let size = match &response.body {
+            Body::Bytes(body) => body.len(),
+            // Event streams are written bit by bit as events happen, there's no whole body to compress
+            Body::EventStream(_) => return response,
+        };
+        if size < self.min_size
+            || matches!(response.status, 204 | 304)
+            || response.header("Content-Encoding").is_some()
+            || !is_compressible(response.header("Content-Type").unwrap_or(""))
+        {
+            return response;
+        }
+
+        // The response now depends on the Accept-Encoding header, whether or not this particular client gets it compressed.
+        // Vary tells caches to keep a separate copy per Accept-Encoding, so a cache never hands gzip to a client that can't read it.
+        response = response.with_header("Vary", "Accept-Encoding");
+
+        if let (Some(encoding), Body::Bytes(body)) = (encoding, &response.body) {
+            let compressed = encoding.encode(body);
+            if compressed.len() < size {
+                response.body = Body::Bytes(compressed);
+                response = response.with_header("Content-Encoding", encoding.name());
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+    use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    fn inflate(compressed: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        DeflateDecoder::new(compressed).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        let mut rng = 0x2545F4914F6CDD1Du64;
+        let random: Vec<u8> = (0..50_000)
+            .map(|_| {
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                rng as u8
+            })
+            .collect();
+
+        // Repeats far apart, to exercise the largest distances
+        let mut far = random[..40_000].to_vec();
+        far.extend_from_slice(&random[..10_000]);
+
+        vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabc".to_vec(),
+            vec![0; 100_000],
+            include_bytes!("../index.html").repeat(20),
+            random,
+            far,
+        ]
+    }
+
+    #[test]
+    fn deflate_round_trips_through_an_independent_decoder() {
+        for sample in samples() {
+            assert_eq!(sample, inflate(&deflate(&sample)));
+        }
+        let html = include_bytes!("../index.html").repeat(20);
+        assert!(deflate(&html).len() < html.len() / 5);
+    }
+
+    #[test]
+    fn gzip_and_zlib_wrappers() {
+        for sample in samples() {
+            let mut out = Vec::new();
+            GzDecoder::new(&gzip(&sample)[..]).read_to_end(&mut out).unwrap();
+            assert_eq!(sample, out);
+
+            let mut out = Vec::new();
+            ZlibDecoder::new(&zlib(&sample)[..]).read_to_end(&mut out).unwrap();
+            assert_eq!(sample, out);
+        }
+        assert_eq!(0xCBF43926, crc32(b"123456789"));
+        assert_eq!(0x11E60398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(Some(Encoding::Gzip), negotiate("gzip, deflate, br"));
+        assert_eq!(Some(Encoding::Deflate), negotiate("gzip;q=0.5, deflate"));
+        assert_eq!(Some(Encoding::Deflate), negotiate("gzip;q=0, *"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("*"));
+        assert_eq!(None, negotiate("br, identity"));
+        assert_eq!(None, negotiate("gzip;q=0"));
+    }
+
+    #[test]
+    fn middleware_compresses_only_what_it_should() {
+        let page = "<p>hello</p>".repeat(200);
+        let png = vec![7u8; 5000];
+        let chain = Chain::new(move |req: &mut Request| match req.path.as_str() {
+            "/small" => Response::html(200, "<p>hi</p>"),
+            "/image" => Response::new(200, png.clone()).with_header("Content-Type", "image/png"),
+            _ => Response::html(200, &page),
+        })
+        .with(Compression::new());
+
+        let run = |path: &str, accept: &str| {
+            let raw = format!("GET {path} HTTP/1.1\r\nAccept-Encoding: {accept}\r\n\r\n");
+            chain.handle(&mut Request::read_from(&mut raw.as_bytes()).unwrap())
+        };
+
+        let response = run("/", "gzip");
+        assert_eq!(Some("gzip"), response.header("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.header("Vary"));
+        match &response.body {
+            Body::Bytes(body) => {
+                let mut out = String::new();
+                GzDecoder::new(&body[..]).read_to_string(&mut out).unwrap();
+                assert_eq!("<p>hello</p>".repeat(200), out);
+            }
+            Body::EventStream(_) => unreachable!(),
+        }
+
+        // Not accepted: sent as is, but still marked as varying
+        let response = run("/", "identity");
+        assert_eq!(None, response.header("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.header("Vary"));
+
+        for path in ["/small", "/image"] {
+            let response = run(path, "gzip");
+            assert_eq!(None, response.header("Content-Encoding"));
+            assert_eq!(None, response.header("Vary"));
+        }
+    }
+}
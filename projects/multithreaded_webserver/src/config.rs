@@ -0,0 +1,400 @@
+// Configuration, Reloaded Without a Restart
+
+// Restarting a server to change a rate limit drops every open connection. Reloader changes the configuration of a running
+// server instead, whenever its file changes:
+    // 1. A PollWatcher (src/watch.rs) watches the directory the file is in. Not the file itself: an editor that saves by writing
+    //    a new file and renaming it over the old one replaces the file, and a watcher of the old one would never hear of it.
+    // 2. The file is read, parsed and validated. A configuration that fails any of it is refused and logged, and the server
+    //    goes on with the one it has. A typo in the file never takes the server down.
+    // 3. The new Config goes into a SharedConfig in one step, replacing the Arc the old one was in. A request that started with
+    //    the old snapshot finishes with it, since it holds its own Arc, and never sees half of one and half of the other.
+    // 4. The subsystems that built something out of the configuration (a RateLimiter, say) listen on a SyncEventEmitter
+    //    (smart_pointers/refcell_smart_pointer/src/events.rs) and get the new Arc<Config> to build it again. The emitter only
+    //    holds them weakly, so a subsystem that's dropped stops listening without having to say so.
+
+// The file has one setting per line, a # starts a comment, and cors_origin can be given more than once:
+
+    // keep_alive = 5
+    // max_body_size = 1048576
+    // rate_limit = 100/s, burst 20
+    // cors_origin = https://app.example.com
+
+// SharedConfig is an ArcSwap (concurrency/src/arc_swap.rs) of the Arc, so load() on the request path takes no lock at all.
+// Readers never wait for each other or for a swap, and a swap only waits for the readers that are in the middle of a load.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use concurrency::arc_swap::ArcSwap;
+use refcell_smart_pointer::events::{SyncEventEmitter, SyncSubscription};
+
+use crate::{
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    server::TlsConfig,
+    time_ext::RateLimiter,
+    watch::{EventKind, PollWatcher, RecursiveMode, Watcher},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_sec: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub keep_alive: Duration,
+    pub max_body_size: usize,
+    pub rate_limit: Option<Rate>,
+    pub cors_origins: Vec<String>,
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            keep_alive: Duration::from_secs(5),
+            max_body_size: crate::http::MAX_BODY_SIZE,
+            rate_limit: None,
+            cors_origins: Vec::new(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    // A line that isn't a setting, or a value that isn't one, counting lines from 1
+    Syntax { line: usize, message: String },
+    // Every line was fine, but the settings don't go together
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "couldn't read the configuration: {e}"),
+            ConfigError::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            ConfigError::Invalid(message) => write!(f, "invalid configuration: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+// "100/s, burst 20", or "off"
+fn parse_rate(value: &str) -> Option<Option<Rate>> {
+    if value == "off" {
+        return Some(None);
+    }
+    let (rate, burst) = value.split_once(',')?;
+    let per_sec = rate.trim().strip_suffix("/s")?.trim().parse().ok()?;
+    let burst = burst.trim().strip_prefix("burst")?.trim().parse().ok()?;
+    Some(Some(Rate { per_sec, burst }))
+}
+
+impl Config {
+    // Settings that aren't in the text keep their default
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let (mut cert, mut key) = (None, None);
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let syntax = |message: String| ConfigError::Syntax { line: index + 1, message };
+            let (name, value) = line.split_once('=').ok_or_else(|| syntax(format!("expected name = value, found {line:?}")))?;
+            let (name, value) = (name.trim(), value.trim());
+            let number = || value.parse::<u64>().map_err(|_| syntax(format!("{name} should be a number, not {value:?}")));
+            match name {
+                "keep_alive" => config.keep_alive = Duration::from_secs(number()?),
+                "max_body_size" => config.max_body_size = number()? as usize,
+                "rate_limit" => {
+                    let expected = || syntax(format!("expected \"100/s, burst 20\" or off, found {value:?}"));
+                    config.rate_limit = parse_rate(value).ok_or_else(expected)?
+                }
+                "cors_origin" => config.cors_origins.push(value.to_string()),
+                "tls_cert" => cert = Some(PathBuf::from(value)),
+                "tls_key" => key = Some(PathBuf::from(value)),
+                _ => return Err(syntax(format!("unknown setting {name}"))),
+            }
+        }
+        config.tls = match (cert, key) {
+            (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+            (None, None) => None,
+            _ => return Err(ConfigError::Invalid(String::from("tls_cert and tls_key go together"))),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    // What parse() can't see line by line
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+        if self.keep_alive > Duration::from_secs(3600) {
+            return invalid("keep_alive can't be over an hour");
+        }
+        if self.max_body_size == 0 {
+            return invalid("max_body_size has to allow some body");
+        }
+        if let Some(rate) = self.rate_limit {
+            if !(rate.per_sec > 0.0 && rate.per_sec.is_finite()) || rate.burst == 0 {
+                return invalid("rate_limit needs a positive rate and burst");
+            }
+        }
+        if let Some(tls) = &self.tls {
+            for path in [&tls.cert, &tls.key] {
+                if !path.is_file() {
+                    return Err(ConfigError::Invalid(format!("{} doesn't exist", path.display())));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The current configuration, for anyone who needs it now rather than when it changes
+pub struct SharedConfig {
+    current: ArcSwap<Config>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig { current: ArcSwap::from_pointee(config) }
+    }
+
+    pub fn load(&self) -> Arc<Config> {
+        self.current.load()
+    }
+
+    // Returns the one it replaced
+    pub fn swap(&self, config: Arc<Config>) -> Arc<Config> {
+        self.current.swap(config)
+    }
+}
+
+pub struct Reloader {
+    path: PathBuf,
+    shared: Arc<SharedConfig>,
+    events: Arc<SyncEventEmitter<Arc<Config>>>,
+    // Dropping it stops the polling thread, and with it the thread that reloads
+    _watcher: PollWatcher,
+}
+
+impl Reloader {
+    // The file has to hold a valid configuration to start with, there is nothing to fall back on yet
+    pub fn start(path: impl Into<PathBuf>, interval: Duration) -> Result<Reloader, ConfigError> {
+        let path = path.into();
+        let shared = Arc::new(SharedConfig::new(Config::load(&path)?));
+        let events = Arc::new(SyncEventEmitter::new());
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = PollWatcher::new(tx, interval);
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        {
+            let (file, shared, events) = (path.clone(), Arc::clone(&shared), Arc::clone(&events));
+            thread::spawn(move || {
+                for event in rx {
+                    // A file that was removed may be back by the next poll, under the rename of an editor's save
+                    if event.path.file_name() == file.file_name() && event.kind != EventKind::Removed {
+                        let _ = reload(&file, &shared, &events);
+                    }
+                }
+            });
+        }
+
+        Ok(Reloader { path, shared, events, _watcher: watcher })
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.shared.load()
+    }
+
+    pub fn shared(&self) -> Arc<SharedConfig> {
+        Arc::clone(&self.shared)
+    }
+
+    // Calls back with every configuration that replaces the current one from now on, until the subscription is dropped.
+    // The callback runs on the thread that reloaded, the watcher's or the one that called reload_now()
+    pub fn on(&self, callback: impl Fn(&Arc<Config>) + Send + Sync + 'static) -> SyncSubscription<Arc<Config>> {
+        self.events.on(callback)
+    }
+
+    // Reads the file again without waiting for the watcher. Ok(false) when it says what the current configuration does
+    pub fn reload_now(&self) -> Result<bool, ConfigError> {
+        reload(&self.path, &self.shared, &self.events)
+    }
+}
+
+fn reload(path: &Path, shared: &SharedConfig, events: &SyncEventEmitter<Arc<Config>>) -> Result<bool, ConfigError> {
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            common::error!("kept the configuration, {} was refused: {e}", path.display());
+            return Err(e);
+        }
+    };
+    if *shared.load() == config {
+        return Ok(false);
+    }
+    let config = Arc::new(config);
+    shared.swap(Arc::clone(&config));
+    common::info!("reloaded the configuration from {}", path.display());
+    events.emit(&config);
+    Ok(true)
+}
+
+// A Rate Limit That Follows the Configuration
+
+// One RateLimiter (projects/common/src/time.rs) for the whole server, from the rate_limit setting. A RateLimiter can't change
+// its rate, so a new configuration builds a new one, and the requests start over with a full bucket.
+// The limiter is in an ArcSwap that the listener stores the new one in, so a request never waits for a reload: it goes on
+// with the limiter it found, and the next one finds the new one.
+
+pub struct RateLimit {
+    limiter: Arc<ArcSwap<Option<RateLimiter>>>,
+    _subscription: SyncSubscription<Arc<Config>>,
+}
+
+fn limiter(config: &Config) -> Option<RateLimiter> {
+    config.rate_limit.map(|rate| RateLimiter::new(rate.per_sec, rate.burst))
+}
+
+impl RateLimit {
+    pub fn new(reloader: &Reloader) -> RateLimit {
+        let current = Arc::new(ArcSwap::from_pointee(limiter(&reloader.config())));
+        let subscription = {
+            let current = Arc::clone(&current);
+            reloader.on(move |config| current.store(Arc::new(limiter(config))))
+        };
+        RateLimit { limiter: current, _subscription: subscription }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        match (*self.limiter.load()).as_ref().map(|limiter| limiter.check()) {
+            Some(Err(wait)) => Response::text(429, "Too Many Requests")
+                .with_header("Retry-After", &wait.as_secs().max(1).to_string()),
+            _ => next.run(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mws-config-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parses_and_validates() {
+        let config = Config::parse(
+            "# the defaults, except\nkeep_alive = 10\nrate_limit = 2.5/s, burst 5  # per client one day\n\
+             cors_origin = https://a.example.com\ncors_origin = https://b.example.com\n",
+        )
+        .unwrap();
+        assert_eq!(Duration::from_secs(10), config.keep_alive);
+        assert_eq!(Some(Rate { per_sec: 2.5, burst: 5 }), config.rate_limit);
+        assert_eq!(vec!["https://a.example.com", "https://b.example.com"], config.cors_origins);
+        assert_eq!(Config::default(), Config::parse("").unwrap());
+
+        for (text, expected) in [
+            ("keep_alive", "line 1: expected name = value, found \"keep_alive\""),
+            ("\nkeep_alive = soon", "line 2: keep_alive should be a number, not \"soon\""),
+            ("rate_limit = fast", "line 1: expected \"100/s, burst 20\" or off, found \"fast\""),
+            ("port = 80", "line 1: unknown setting port"),
+            ("keep_alive = 7200", "invalid configuration: keep_alive can't be over an hour"),
+            ("rate_limit = 0/s, burst 1", "invalid configuration: rate_limit needs a positive rate and burst"),
+            ("tls_cert = cert.pem", "invalid configuration: tls_cert and tls_key go together"),
+            ("tls_cert = /no/cert.pem\ntls_key = /no/key.pem", "invalid configuration: /no/cert.pem doesn't exist"),
+        ] {
+            assert_eq!(expected, Config::parse(text).unwrap_err().to_string());
+        }
+    }
+
+    #[test]
+    fn reloads_when_the_file_changes_and_only_when_it_is_valid() {
+        common::log::set_max_level(None);
+        let dir = temp_dir("reload");
+        let path = dir.join("server.conf");
+        fs::write(&path, "keep_alive = 1\n").unwrap();
+        let reloader = Reloader::start(&path, Duration::from_millis(20)).unwrap();
+        let (sender, updates) = mpsc::channel();
+        let _subscription = reloader.on(move |config| {
+            // Only the test's end of the channel can be gone, once the test is over
+            let _ = sender.send(Arc::clone(config));
+        });
+        let before = reloader.config();
+        assert_eq!(Duration::from_secs(1), before.keep_alive);
+
+        // Written to the side and renamed over, like an editor does
+        fs::write(dir.join("server.conf.tmp"), "keep_alive = 2\nmax_body_size = 100\n").unwrap();
+        fs::rename(dir.join("server.conf.tmp"), &path).unwrap();
+        let config = updates.recv_timeout(Duration::from_secs(5)).expect("no reload");
+        assert_eq!((Duration::from_secs(2), 100), (config.keep_alive, config.max_body_size));
+        assert!(Arc::ptr_eq(&config, &reloader.config()));
+        // The request that had the old snapshot still has it
+        assert_eq!(Duration::from_secs(1), before.keep_alive);
+
+        fs::write(&path, "keep_alive = 3\nmax_body_size = lots\n").unwrap();
+        assert!(matches!(reloader.reload_now(), Err(ConfigError::Syntax { line: 2, .. })));
+        assert!(updates.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(Duration::from_secs(2), reloader.config().keep_alive);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_rate_limit_follows_the_configuration() {
+        common::log::set_max_level(None);
+        let dir = temp_dir("rate");
+        let path = dir.join("server.conf");
+        fs::write(&path, "rate_limit = 0.01/s, burst 1\n").unwrap();
+        let reloader = Reloader::start(&path, Duration::from_secs(60)).unwrap();
+        let app = Chain::new(|_: &mut Request| Response::text(200, "ok")).with(RateLimit::new(&reloader));
+        let get = || app.handle(&mut Request::read_from(&mut "GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap()).status;
+
+        assert_eq!((200, 429), (get(), get()));
+        fs::write(&path, "rate_limit = off\n").unwrap();
+        assert!(reloader.reload_now().unwrap());
+        assert_eq!((200, 200), (get(), get()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
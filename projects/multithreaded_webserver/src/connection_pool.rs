@@ -0,0 +1,429 @@
+// A Connection Pool
+
+// Opening a TCP connection costs a round trip before the first byte of a request can go out, more with TLS. A program that talks
+// to the same server again and again (a database, or the upstream of a proxy) keeps a few connections open and reuses them.
+// ObjectPool (pool.rs) does that for buffers, but connections need more than buffers do:
+    // 1. A limit. A server accepts only so many connections, so at most max_size are open at once, and checkout() waits
+    //    on a Condvar for one to come back when they're all in use, up to checkout_timeout.
+    // 2. Fairness. The waiting threads get the connections in the order they started waiting: each takes a ticket, and
+    //    only the one at the front of the line may take a connection. Without it, a thread that just returned its connection
+    //    and asks again can win every time against the ones that were woken up, and they wait forever.
+    // 3. Connections go bad while they're idle: the server closes them after a while, or the network drops. A connection idle for
+    //    longer than idle_timeout is closed instead of handed out, and every other one goes through the health check first.
+    //    The check runs with the pool locked, so it should be quick, like the peek of the TcpStream one below.
+    // 4. Pooled, the guard checkout() returns, gives the connection back when it's dropped. A connection that failed in the
+    //    middle of a request shouldn't be reused, discard() closes it instead, and so does a guard dropped while panicking.
+// The idle times and the checkout_timeout are on the pool's Clock, and network_pool connects over any Network (src/net.rs),
+// so a test on the simulated network (src/sim.rs) runs them out by moving its clock instead of sleeping.
+
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::clock::{Clock, SystemClock};
+
+use crate::net::{Network, Stream};
+
+pub trait Connection: Send + 'static {
+    // false if the connection can't be used anymore. Called before an idle connection is handed out again.
+    fn is_healthy(&mut self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+pub enum PoolError {
+    // Every connection stayed in use for the whole checkout_timeout
+    Timeout(Duration),
+    Connect(io::Error),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolError::Timeout(timeout) => write!(f, "no connection became available within {timeout:?}"),
+            PoolError::Connect(e) => write!(f, "couldn't open a connection: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PoolError::Timeout(_) => None,
+            PoolError::Connect(e) => Some(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolState {
+    // Idle and checked out together, connections being opened included
+    pub open: usize,
+    pub idle: usize,
+    pub waiting: usize,
+}
+
+struct State<T> {
+    // The most recently returned connection is at the back, and goes out first: it's the least likely to have been closed
+    idle: VecDeque<(T, Instant)>,
+    open: usize,
+    // The tickets of the threads in checkout(), in the order they came
+    line: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+type Connect<T> = Box<dyn Fn() -> io::Result<T> + Send + Sync>;
+type HealthCheck<T> = Box<dyn Fn(&mut T) -> bool + Send + Sync>;
+
+pub struct Pool<T: Connection> {
+    state: Mutex<State<T>>,
+    available: Condvar,
+    connect: Connect<T>,
+    max_size: usize,
+    idle_timeout: Option<Duration>,
+    checkout_timeout: Duration,
+    health_check: Option<HealthCheck<T>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: Connection> Pool<T> {
+    pub fn new(max_size: usize, connect: impl Fn() -> io::Result<T> + Send + Sync + 'static) -> Pool<T> {
+        assert!(max_size > 0, "a pool needs room for at least one connection");
+        Pool {
+            state: Mutex::new(State { idle: VecDeque::new(), open: 0, line: VecDeque::new(), next_ticket: 0 }),
+            available: Condvar::new(),
+            connect: Box::new(connect),
+            max_size,
+            idle_timeout: None,
+            checkout_timeout: Duration::from_secs(30),
+            health_check: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Idle connections older than this are closed instead of reused
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn checkout_timeout(mut self, checkout_timeout: Duration) -> Self {
+        self.checkout_timeout = checkout_timeout;
+        self
+    }
+
+    // Checked after Connection::is_healthy, for what only this pool's user knows, like a query that has to succeed
+    pub fn health_check(mut self, check: impl Fn(&mut T) -> bool + Send + Sync + 'static) -> Self {
+        self.health_check = Some(Box::new(check));
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn state(&self) -> PoolState {
+        let state = self.state.lock().unwrap();
+        PoolState { open: state.open, idle: state.idle.len(), waiting: state.line.len() }
+    }
+
+    fn healthy(&self, connection: &mut T) -> bool {
+        connection.is_healthy() && self.health_check.as_ref().is_none_or(|check| check(connection))
+    }
+
+    // A usable idle connection, closing the stale and broken ones on the way
+    fn take_idle(&self, state: &mut State<T>) -> Option<T> {
+        let now = self.clock.now();
+        while let Some((mut connection, since)) = state.idle.pop_back() {
+            let stale = self.idle_timeout.is_some_and(|timeout| now.duration_since(since) > timeout);
+            if !stale && self.healthy(&mut connection) {
+                return Some(connection);
+            }
+            state.open -= 1;
+        }
+        None
+    }
+
+    // Leaves the line, and wakes up the others so that the next one in line sees it's their turn
+    fn leave_line(&self, state: &mut MutexGuard<State<T>>, ticket: u64) {
+        state.line.retain(|waiting| *waiting != ticket);
+        self.available.notify_all();
+    }
+
+    pub fn checkout(&self) -> Result<Pooled<'_, T>, PoolError> {
+        let deadline = self.clock.now() + self.checkout_timeout;
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.line.push_back(ticket);
+
+        loop {
+            if state.line.front() == Some(&ticket) {
+                if let Some(connection) = self.take_idle(&mut state) {
+                    self.leave_line(&mut state, ticket);
+                    return Ok(Pooled { pool: self, connection: Some(connection) });
+                }
+                if state.open < self.max_size {
+                    // The slot is taken before connecting, so the lock doesn't have to be held while the connection is opened
+                    state.open += 1;
+                    self.leave_line(&mut state, ticket);
+                    drop(state);
+                    return match (self.connect)() {
+                        Ok(connection) => Ok(Pooled { pool: self, connection: Some(connection) }),
+                        Err(e) => {
+                            self.state.lock().unwrap().open -= 1;
+                            self.available.notify_all();
+                            Err(PoolError::Connect(e))
+                        }
+                    };
+                }
+            }
+
+            let slice = self.clock.wait_slice(deadline);
+            if slice.is_zero() {
+                self.leave_line(&mut state, ticket);
+                return Err(PoolError::Timeout(self.checkout_timeout));
+            }
+            state = self.available.wait_timeout(state, slice).unwrap().0;
+        }
+    }
+
+    fn give_back(&self, connection: T) {
+        self.state.lock().unwrap().idle.push_back((connection, self.clock.now()));
+        self.available.notify_all();
+    }
+
+    fn close(&self, connection: T) {
+        drop(connection);
+        self.state.lock().unwrap().open -= 1;
+        self.available.notify_all();
+    }
+}
+
+pub struct Pooled<'a, T: Connection> {
+    pool: &'a Pool<T>,
+    // Only None after discard() or drop took it
+    connection: Option<T>,
+}
+
+impl<T: Connection> Pooled<'_, T> {
+    // Closes the connection instead of giving it back, and makes room for a new one
+    pub fn discard(mut self) {
+        let connection = self.connection.take().unwrap();
+        self.pool.close(connection);
+    }
+}
+
+impl<T: Connection> Deref for Pooled<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl<T: Connection> DerefMut for Pooled<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.connection.as_mut().unwrap()
+    }
+}
+
+impl<T: Connection> Drop for Pooled<'_, T> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            // A panic may have left a request half written, the next user would read the answer to it
+            if thread::panicking() {
+                self.pool.close(connection);
+            } else {
+                self.pool.give_back(connection);
+            }
+        }
+    }
+}
+
+// A connection is broken when the other side closed it. An idle one should be open and quiet, data nobody asked for means
+// it's out of step, broken too. Stream::is_open_and_quiet looks without blocking (src/net.rs)
+impl Connection for TcpStream {
+    fn is_healthy(&mut self) -> bool {
+        Stream::is_open_and_quiet(self)
+    }
+}
+
+impl Connection for Box<dyn Stream> {
+    fn is_healthy(&mut self) -> bool {
+        self.is_open_and_quiet()
+    }
+}
+
+// A pool of connections to one server. The address is resolved once, here.
+pub fn tcp_pool(addr: impl ToSocketAddrs, max_size: usize) -> io::Result<Pool<TcpStream>> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the address didn't resolve to anything"));
+    }
+    Ok(Pool::new(max_size, move || TcpStream::connect(&addrs[..])))
+}
+
+// The same over any Network, the simulated one of a test included. A connect waits at most connect_timeout
+pub fn network_pool(
+    network: Arc<dyn Network>,
+    addr: SocketAddr,
+    connect_timeout: Duration,
+    max_size: usize,
+) -> Pool<Box<dyn Stream>> {
+    Pool::new(max_size, move || network.connect(addr, Some(connect_timeout)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    struct Fake {
+        id: usize,
+        healthy: Arc<AtomicBool>,
+    }
+
+    impl Connection for Fake {
+        fn is_healthy(&mut self) -> bool {
+            self.healthy.load(Ordering::SeqCst)
+        }
+    }
+
+    // A pool of fakes numbered in the order they were opened, and the switch that breaks them all
+    fn fakes(max_size: usize) -> (Pool<Fake>, Arc<AtomicUsize>, Arc<AtomicBool>) {
+        let (opened, healthy) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(true)));
+        let pool = {
+            let (opened, healthy) = (Arc::clone(&opened), Arc::clone(&healthy));
+            Pool::new(max_size, move || Ok(Fake { id: opened.fetch_add(1, Ordering::SeqCst), healthy: Arc::clone(&healthy) }))
+        };
+        (pool, opened, healthy)
+    }
+
+    #[test]
+    fn reuses_connections_up_to_the_limit() {
+        let (pool, opened, _) = fakes(2);
+        let pool = pool.checkout_timeout(Duration::from_millis(50));
+        let (a, b) = (pool.checkout().unwrap(), pool.checkout().unwrap());
+        assert_eq!((0, 1), (a.id, b.id));
+        assert!(matches!(pool.checkout(), Err(PoolError::Timeout(_))));
+        assert_eq!(PoolState { open: 2, idle: 0, waiting: 0 }, pool.state());
+        drop(a);
+        drop(b);
+        // The one returned last comes out first
+        assert_eq!(1, pool.checkout().unwrap().id);
+        assert_eq!(2, opened.load(Ordering::SeqCst));
+
+        // A discarded connection makes room for a new one
+        pool.checkout().unwrap().discard();
+        assert_eq!(PoolState { open: 1, idle: 1, waiting: 0 }, pool.state());
+        let (a, b) = (pool.checkout().unwrap(), pool.checkout().unwrap());
+        assert_eq!((0, 2), (a.id, b.id));
+    }
+
+    #[test]
+    fn stale_and_broken_connections_are_replaced() {
+        let (pool, opened, healthy) = fakes(4);
+        let pool = pool.idle_timeout(Duration::from_millis(20)).health_check(|fake| fake.id != 1);
+        drop(pool.checkout().unwrap());
+        thread::sleep(Duration::from_millis(40));
+        // Connection 0 sat idle for too long
+        assert_eq!(1, pool.checkout().unwrap().id);
+        // And 1 fails the pool's own check
+        assert_eq!(2, pool.checkout().unwrap().id);
+
+        healthy.store(false, Ordering::SeqCst);
+        assert_eq!(3, pool.checkout().unwrap().id);
+        assert_eq!(PoolState { open: 1, idle: 1, waiting: 0 }, pool.state());
+        assert_eq!(4, opened.load(Ordering::SeqCst));
+
+        // A connection whose user panicked isn't reused
+        healthy.store(true, Ordering::SeqCst);
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _connection = pool.checkout().unwrap();
+            panic!("in the middle of a request");
+        }));
+        assert_eq!(PoolState { open: 0, idle: 0, waiting: 0 }, pool.state());
+    }
+
+    #[test]
+    fn waiting_threads_are_served_in_order() {
+        let (pool, _, _) = fakes(1);
+        let pool = Arc::new(pool);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let held = pool.checkout().unwrap();
+
+        let mut threads = Vec::new();
+        for n in 0..5 {
+            let (shared, order) = (Arc::clone(&pool), Arc::clone(&order));
+            threads.push(thread::spawn(move || {
+                let _connection = shared.checkout().unwrap();
+                order.lock().unwrap().push(n);
+                thread::sleep(Duration::from_millis(5));
+            }));
+            // The next thread only starts once this one is in line
+            while pool.state().waiting <= n {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        // The thread that held the connection asks again right away, and has to wait its turn like the others
+        drop(held);
+        let again = pool.checkout().unwrap();
+        order.lock().unwrap().push(5);
+        drop(again);
+        threads.into_iter().for_each(|thread| thread.join().unwrap());
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], *order.lock().unwrap());
+    }
+
+    #[test]
+    fn tcp_connections_closed_by_the_server_are_noticed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pool = tcp_pool(listener.local_addr().unwrap(), 2).unwrap();
+
+        let mut connection = pool.checkout().unwrap();
+        let (mut server_side, _) = listener.accept().unwrap();
+        connection.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        server_side.read_exact(&mut buf).unwrap();
+        let first = connection.local_addr().unwrap();
+        drop(connection);
+
+        // Still open: the same connection comes back, and it still blocks on reads
+        let connection = pool.checkout().unwrap();
+        assert_eq!(first, connection.local_addr().unwrap());
+        drop(connection);
+
+        drop(server_side);
+        thread::sleep(Duration::from_millis(20));
+        let connection = pool.checkout().unwrap();
+        assert_ne!(first, connection.local_addr().unwrap());
+        assert_eq!(1, pool.state().open);
+    }
+
+    #[test]
+    fn failed_connects_give_their_slot_back() {
+        let pool = Pool::new(1, || Err::<Fake, _>(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")));
+        assert!(matches!(pool.checkout(), Err(PoolError::Connect(e)) if e.kind() == io::ErrorKind::ConnectionRefused));
+        assert_eq!(PoolState { open: 0, idle: 0, waiting: 0 }, pool.state());
+    }
+}
@@ -0,0 +1,164 @@
+// Cookies
+
+// HTTP is stateless: every request stands on its own. Cookies let the server remember something about a client between requests.
+// The server sends a Set-Cookie header with a name, a value and some attributes, and the browser sends the name and value back
+// in a Cookie header on every later request to the same site:
+    // Set-Cookie: session=abc123; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax
+    // Cookie: session=abc123; theme=dark
+
+// The attributes tell the browser how to treat the cookie:
+    // Max-Age: seconds until the browser forgets it (0 deletes it right away), without it the cookie lasts until the browser closes
+    // Path and Domain: which requests it is sent with
+    // Secure: only send it over HTTPS
+    // HttpOnly: hide it from JavaScript, so a script injected into the page can't steal a session cookie
+    // SameSite: whether it is sent with requests coming from other sites, which is the main defence against cross-site request forgery
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub max_age: Option<u64>,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            max_age: None,
+            path: None,
+            domain: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn max_age(mut self, seconds: u64) -> Cookie {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    // A cookie that tells the browser to delete the one with this name
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "").path("/").max_age(0)
+    }
+}
+
+// Formats the cookie as the value of a Set-Cookie header
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={max_age}")?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict")?,
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax")?,
+            Some(SameSite::None) => write!(f, "; SameSite=None")?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+// Parses the value of a Cookie header sent by the browser into (name, value) pairs. Browsers send no attributes here.
+// Malformed pairs are skipped rather than failing the whole header, since one bad cookie shouldn't break the others.
+pub fn parse_cookie_header(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            // Values may be wrapped in double quotes
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_set_cookie_values() {
+        let cookie = Cookie::new("session", "abc123")
+            .path("/")
+            .max_age(3600)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!("session=abc123; Max-Age=3600; Path=/; HttpOnly; SameSite=Lax", cookie.to_string());
+        assert_eq!("theme=; Max-Age=0; Path=/", Cookie::removal("theme").to_string());
+    }
+
+    #[test]
+    fn parses_cookie_headers() {
+        assert_eq!(
+            vec![
+                (String::from("session"), String::from("abc123")),
+                (String::from("theme"), String::from("dark")),
+                (String::from("q"), String::from("a=b")),
+            ],
+            parse_cookie_header("session=abc123;  theme=\"dark\"; garbage; =x; q=a=b")
+        );
+    }
+}
@@ -0,0 +1,287 @@
+// Cross-Origin Resource Sharing
+
+// A page from https://app.example.com that calls fetch("https://api.example.com/users") is asking the browser to read a
+// response from another origin. By default the browser won't let it: the response could be the user's mail, sent with the
+// user's cookies. The server has to say which origins may read its responses, in Access-Control-* headers:
+    // 1. A simple request (a GET, or a POST of a form) is sent as it is, with an Origin header. The response says whether
+    //    that origin may read it with Access-Control-Allow-Origin, and the browser hides the response from the page if not.
+    // 2. Anything else (a PUT, a DELETE, a JSON body, a header of its own) could change something on the server before the
+    //    browser gets to check, so the browser asks first, with a preflight: an OPTIONS request that says which method and
+    //    headers the real request will have. Only when the answer allows them is the real request sent.
+
+// Cors answers the preflights itself, the handler never sees them, and adds the headers to the responses of the rest.
+// An origin it doesn't know gets no headers at all, and its preflights a 403, so the browser refuses the request.
+// The origins are exact ("https://app.example.com"), "*" for any, or have one * for a part of the host that can be anything
+// without a slash: "https://*.example.com" is every subdomain, but not https://example.com itself.
+
+// With allow_credentials the browser sends the user's cookies along, which only works when the response names the origin:
+// Access-Control-Allow-Origin: * is refused with credentials, so the origin is echoed back instead.
+// Whenever the answer depends on the Origin, Vary: Origin tells caches not to hand the answer for one origin to another.
+
+// The server's builder takes a Cors with .cors(..), and puts it outside every other middleware, so that a preflight is answered
+// before an authentication layer asks it for credentials it can't have.
+
+use std::time::Duration;
+
+use crate::{
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    expose: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Cors {
+    // Allows no origin at all until some are added, and the methods a simple request can have
+    pub fn new() -> Cors {
+        Cors {
+            origins: Vec::new(),
+            methods: ["GET", "HEAD", "POST"].map(String::from).to_vec(),
+            headers: Vec::new(),
+            expose: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        self.origins.push(origin.to_string());
+        self
+    }
+
+    pub fn allow_any_origin(self) -> Cors {
+        self.allow_origin("*")
+    }
+
+    pub fn allow_methods(mut self, methods: &[&str]) -> Cors {
+        self.methods.extend(methods.iter().map(|method| method.to_ascii_uppercase()));
+        self
+    }
+
+    // The request headers a page may set, beyond the few a simple request can have
+    pub fn allow_headers(mut self, headers: &[&str]) -> Cors {
+        self.headers.extend(headers.iter().map(|header| header.to_ascii_lowercase()));
+        self
+    }
+
+    // The response headers a page may read, beyond Content-Type and the few others every page can
+    pub fn expose_headers(mut self, headers: &[&str]) -> Cors {
+        self.expose.extend(headers.iter().map(|header| header.to_string()));
+        self
+    }
+
+    pub fn allow_credentials(mut self, credentials: bool) -> Cors {
+        self.credentials = credentials;
+        self
+    }
+
+    // How long the browser may remember the answer to a preflight, instead of asking before every request
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|pattern| origin_matches(pattern, origin))
+    }
+
+    // What Access-Control-Allow-Origin says: * when any origin may read it and no credentials are involved, the origin otherwise
+    fn allow_origin_value<'a>(&self, origin: &'a str) -> &'a str {
+        if !self.credentials && self.origins.iter().any(|pattern| pattern == "*") {
+            "*"
+        } else {
+            origin
+        }
+    }
+
+    fn common_headers(&self, mut response: Response, origin: &str) -> Response {
+        let allowed = self.allow_origin_value(origin);
+        if allowed != "*" {
+            response = response.with_header("Vary", "Origin");
+        }
+        response = response.with_header("Access-Control-Allow-Origin", allowed);
+        if self.credentials {
+            response = response.with_header("Access-Control-Allow-Credentials", "true");
+        }
+        response
+    }
+
+    fn preflight(&self, request: &Request, origin: &str, method: &str) -> Response {
+        let requested: Vec<String> = request
+            .header("Access-Control-Request-Headers")
+            .unwrap_or("")
+            .split(',')
+            .map(|header| header.trim().to_ascii_lowercase())
+            .filter(|header| !header.is_empty())
+            .collect();
+        let method_allowed = self.methods.iter().any(|allowed| allowed.eq_ignore_ascii_case(method));
+        let headers_allowed = requested.iter().all(|header| self.headers.contains(header));
+        if !self.allows_origin(origin) || !method_allowed || !headers_allowed {
+            return Response::text(403, "Forbidden");
+        }
+
+        let mut response = self
+            .common_headers(Response::new(204, Vec::new()), origin)
+            .with_header("Vary", "Access-Control-Request-Method, Access-Control-Request-Headers")
+            .with_header("Access-Control-Allow-Methods", &self.methods.join(", "));
+        if !requested.is_empty() {
+            response = response.with_header("Access-Control-Allow-Headers", &requested.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.with_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+        response
+    }
+}
+
+// "*" matches any origin, a pattern with a * in it any origin that only differs in the part the * stands for
+pub fn origin_matches(pattern: &str, origin: &str) -> bool {
+    match pattern.split_once('*') {
+        _ if pattern == "*" => true,
+        Some((prefix, suffix)) => {
+            origin.len() > prefix.len() + suffix.len()
+                && origin.starts_with(prefix)
+                && origin.ends_with(suffix)
+                && !origin[prefix.len()..origin.len() - suffix.len()].contains(['/', ':'])
+        }
+        None => pattern.eq_ignore_ascii_case(origin),
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        // Same-origin requests and clients that aren't browsers send no Origin, and there is nothing to allow
+        let Some(origin) = request.header("Origin").map(str::to_string) else { return next.run(request) };
+        if request.method == "OPTIONS" {
+            if let Some(method) = request.header("Access-Control-Request-Method") {
+                return self.preflight(request, &origin, method);
+            }
+        }
+        let response = next.run(request);
+        if !self.allows_origin(&origin) {
+            return response;
+        }
+        let response = self.common_headers(response, &origin);
+        match self.expose.is_empty() {
+            true => response,
+            false => response.with_header("Access-Control-Expose-Headers", &self.expose.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let headers: String = headers.iter().map(|(name, value)| format!("{name}: {value}\r\n")).collect();
+        Request::read_from(&mut format!("{method} /api/users HTTP/1.1\r\n{headers}\r\n").as_bytes()).unwrap()
+    }
+
+    fn api(cors: Cors) -> Chain {
+        Chain::new(|req: &mut Request| Response::text(200, &format!("{} from the handler", req.method)).with_header("X-Total", "2"))
+            .with(cors)
+    }
+
+    fn cors() -> Cors {
+        Cors::new()
+            .allow_origin("https://app.example.com")
+            .allow_origin("https://*.example.org")
+            .allow_methods(&["PUT", "delete"])
+            .allow_headers(&["Content-Type", "X-Request-Id"])
+            .expose_headers(&["X-Total"])
+            .max_age(Duration::from_secs(600))
+    }
+
+    #[test]
+    fn origins_exact_and_wildcard() {
+        assert!(origin_matches("*", "http://localhost:3000"));
+        assert!(origin_matches("https://app.example.com", "https://APP.example.com"));
+        assert!(origin_matches("https://*.example.org", "https://a.example.org"));
+        assert!(origin_matches("https://*.example.org", "https://a.b.example.org"));
+        assert!(!origin_matches("https://*.example.org", "https://example.org"));
+        assert!(!origin_matches("https://*.example.org", "https://.example.org"));
+        assert!(!origin_matches("https://*.example.org", "http://a.example.org"));
+        assert!(!origin_matches("https://*.example.org", "https://evil.com/.example.org"));
+        assert!(!origin_matches("https://*.example.org", "https://evil.com:443.example.org"));
+        assert!(!origin_matches("https://app.example.com", "https://app.example.com.evil.com"));
+    }
+
+    #[test]
+    fn preflights_are_answered_without_the_handler() {
+        let app = api(cors());
+        let response = app.handle(&mut request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://shop.example.org"),
+                ("Access-Control-Request-Method", "PUT"),
+                ("Access-Control-Request-Headers", "content-type, X-Request-Id"),
+            ],
+        ));
+        assert_eq!(204, response.status);
+        assert_eq!(Some("https://shop.example.org"), response.header("Access-Control-Allow-Origin"));
+        assert_eq!(Some("GET, HEAD, POST, PUT, DELETE"), response.header("Access-Control-Allow-Methods"));
+        assert_eq!(Some("content-type, x-request-id"), response.header("Access-Control-Allow-Headers"));
+        assert_eq!(Some("600"), response.header("Access-Control-Max-Age"));
+        assert_eq!(Some("Origin"), response.header("Vary"));
+        assert_eq!(None, response.header("Access-Control-Allow-Credentials"));
+
+        // A method, a header or an origin that isn't allowed
+        let app_origin = ("Origin", "https://app.example.com");
+        for headers in [
+            [app_origin, ("Access-Control-Request-Method", "PATCH"), ("X-Unused", "")],
+            [app_origin, ("Access-Control-Request-Method", "PUT"), ("Access-Control-Request-Headers", "X-Secret")],
+            [("Origin", "https://evil.com"), ("Access-Control-Request-Method", "GET"), ("X-Unused", "")],
+        ] {
+            let response = app.handle(&mut request("OPTIONS", &headers));
+            assert_eq!((403, None), (response.status, response.header("Access-Control-Allow-Origin")), "{headers:?}");
+        }
+
+        // An OPTIONS that isn't a preflight is the handler's
+        let response = app.handle(&mut request("OPTIONS", &[("Origin", "https://app.example.com")]));
+        assert_eq!(200, response.status);
+    }
+
+    #[test]
+    fn simple_requests_get_the_headers() {
+        let app = api(cors());
+        let response = app.handle(&mut request("GET", &[("Origin", "https://app.example.com")]));
+        assert_eq!(200, response.status);
+        assert_eq!(Some("https://app.example.com"), response.header("Access-Control-Allow-Origin"));
+        assert_eq!(Some("X-Total"), response.header("Access-Control-Expose-Headers"));
+        assert_eq!(Some("Origin"), response.header("Vary"));
+
+        // Answered, but without the headers the browser needs to let the page read it
+        let response = app.handle(&mut request("GET", &[("Origin", "https://evil.com")]));
+        assert_eq!((200, None), (response.status, response.header("Access-Control-Allow-Origin")));
+        // And no Origin, no CORS
+        let response = app.handle(&mut request("GET", &[]));
+        assert_eq!((200, None), (response.status, response.header("Access-Control-Allow-Origin")));
+    }
+
+    #[test]
+    fn credentials_need_the_origin_named() {
+        let origin = [("Origin", "http://localhost:3000")];
+        let response = api(Cors::new().allow_any_origin()).handle(&mut request("GET", &origin));
+        assert_eq!((Some("*"), None), (response.header("Access-Control-Allow-Origin"), response.header("Vary")));
+
+        let response = api(Cors::new().allow_any_origin().allow_credentials(true)).handle(&mut request("GET", &origin));
+        assert_eq!(Some("http://localhost:3000"), response.header("Access-Control-Allow-Origin"));
+        assert_eq!(Some("true"), response.header("Access-Control-Allow-Credentials"));
+        assert_eq!(Some("Origin"), response.header("Vary"));
+    }
+}
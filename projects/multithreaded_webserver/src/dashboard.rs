@@ -0,0 +1,217 @@
+// The Metrics Dashboard
+
+// What src/bin/dashboard.rs draws: it fetches a MetricsSnapshot from /metrics every interval and hands it to update(),
+// and draw() puts everything on a common::term::Screen, which only sends the terminal what changed since the last frame.
+// A snapshot has totals, how many requests since the server started, so a rate needs two of them:
+    // 1. The requests per second are the requests between two snapshots over the time between them.
+    //    A server that restarted has fewer than before, and then all of its requests are new.
+    // 2. The rates of the last fetches go in a sparkline, one column each, from ▁ for none to █ for the highest shown.
+// Latency is the p50, p95 and p99 of the snapshot, the busy workers a bar, and whatever room is left is for the log lines.
+
+use std::{collections::VecDeque, time::Instant};
+
+use common::term::{Color, Screen, Style};
+
+use crate::metrics::MetricsSnapshot;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+pub struct Dashboard {
+    title: String,
+    last: Option<(Instant, MetricsSnapshot)>,
+    rates: VecDeque<f64>,
+    max_rates: usize,
+    // Why the last fetch failed, shown until one works again
+    error: Option<String>,
+}
+
+impl Dashboard {
+    pub fn new(title: &str) -> Dashboard {
+        Dashboard { title: title.to_string(), last: None, rates: VecDeque::new(), max_rates: 512, error: None }
+    }
+
+    pub fn update(&mut self, snapshot: MetricsSnapshot, at: Instant) {
+        if let Some((then, last)) = &self.last {
+            let secs = at.duration_since(*then).as_secs_f64();
+            let new = match snapshot.requests.checked_sub(last.requests) {
+                Some(new) => new,
+                None => snapshot.requests,
+            };
+            if secs > 0.0 {
+                self.rates.push_back(new as f64 / secs);
+                if self.rates.len() > self.max_rates {
+                    self.rates.pop_front();
+                }
+            }
+        }
+        self.last = Some((at, snapshot));
+        self.error = None;
+    }
+
+    pub fn failed(&mut self, error: &str) {
+        self.error = Some(error.to_string());
+    }
+
+    // The requests per second between the last two snapshots
+    pub fn rate(&self) -> Option<f64> {
+        self.rates.back().copied()
+    }
+
+    pub fn draw(&self, screen: &mut Screen) {
+        screen.clear();
+        let size = screen.size();
+        let bold = Style::new().bold();
+        let dim = Style::new().dim();
+
+        let col = screen.put(0, 0, &self.title, bold);
+        match (&self.error, &self.last) {
+            (Some(error), _) => screen.put(0, col + 2, error, Style::new().fg(Color::Red)),
+            (None, None) => screen.put(0, col + 2, "waiting for the first snapshot...", dim),
+            (None, Some((_, snapshot))) => {
+                let status = format!(
+                    "up {}  {} requests  {} not found",
+                    uptime(snapshot.uptime_secs),
+                    snapshot.requests,
+                    snapshot.not_found
+                );
+                screen.put(0, col + 2, &status, dim)
+            }
+        };
+        let Some((_, snapshot)) = &self.last else {
+            return;
+        };
+
+        let rate = self.rate().map_or(String::from("-"), |rate| format!("{rate:.1}"));
+        screen.put(2, 0, &format!("Requests/s  {rate}"), bold);
+        let shown = &self.rates.iter().rev().take(size.cols as usize).rev().copied().collect::<Vec<_>>()[..];
+        screen.put(3, 0, &sparkline(shown), Style::new().fg(Color::Cyan));
+
+        screen.put(5, 0, "Latency", bold);
+        for (i, (name, us)) in [("p50", snapshot.p50_us), ("p95", snapshot.p95_us), ("p99", snapshot.p99_us)].iter().enumerate() {
+            let col = 12 + 12 * i as u16;
+            screen.put(5, col, &format!("{name:>9}"), dim);
+            screen.put(6, col, &format!("{:>9}", micros(*us)), Style::new());
+        }
+
+        screen.put(8, 0, "Workers", bold);
+        let width = size.cols.saturating_sub(24).min(40) as usize;
+        let (bar, fraction) = bar(snapshot.busy, snapshot.workers, width);
+        let color = match fraction {
+            f if f >= 0.9 => Color::Red,
+            f if f >= 0.6 => Color::Yellow,
+            _ => Color::Green,
+        };
+        let col = 12 + screen.put(8, 12, &bar, Style::new().fg(color));
+        screen.put(8, col + 1, &format!("{}/{} busy", snapshot.busy, snapshot.workers), Style::new());
+
+        screen.put(10, 0, "Log", bold);
+        let room = size.rows.saturating_sub(11) as usize;
+        let lines = &snapshot.log[snapshot.log.len().saturating_sub(room)..];
+        for (row, line) in (11..).zip(lines) {
+            screen.put(row, 0, line, Style::new());
+        }
+    }
+}
+
+// One bar per value, from ▁ for 0 up to █ for the highest. All of them ▁ when nothing happened
+pub fn sparkline(values: &[f64]) -> String {
+    let max = values.iter().copied().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|&value| match max > 0.0 {
+            true => BARS[((value / max) * (BARS.len() - 1) as f64).round() as usize],
+            false => BARS[0],
+        })
+        .collect()
+}
+
+// [####------], and how full it is. With no workers set there's nothing to fill
+fn bar(busy: u64, workers: u64, width: usize) -> (String, f64) {
+    let fraction = match workers {
+        0 => 0.0,
+        workers => (busy as f64 / workers as f64).min(1.0),
+    };
+    let filled = (fraction * width as f64).round() as usize;
+    (format!("[{}{}]", "#".repeat(filled), "-".repeat(width - filled)), fraction)
+}
+
+fn micros(us: u64) -> String {
+    match us {
+        0..=999 => format!("{us}µs"),
+        1_000..=999_999 => format!("{:.1}ms", us as f64 / 1e3),
+        _ => format!("{:.2}s", us as f64 / 1e6),
+    }
+}
+
+fn uptime(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::term::Size;
+    use std::time::Duration;
+
+    fn snapshot(requests: u64, busy: u64) -> MetricsSnapshot {
+        MetricsSnapshot {
+            uptime_secs: 3723,
+            requests,
+            not_found: 2,
+            p50_us: 850,
+            p95_us: 12_300,
+            p99_us: 1_200_000,
+            counters: Vec::new(),
+            workers: 4,
+            busy,
+            log: vec![String::from("first"), String::from("second"), String::from("third")],
+            rss_bytes: 0,
+            open_fds: 0,
+            threads: 0,
+        }
+    }
+
+    #[test]
+    fn rates_and_sparklines() {
+        assert_eq!("▁▅█▁", sparkline(&[0.0, 5.0, 8.0, 0.0]));
+        assert_eq!("▁▁", sparkline(&[0.0, 0.0]));
+
+        let mut dashboard = Dashboard::new("test");
+        let start = Instant::now();
+        dashboard.update(snapshot(100, 0), start);
+        assert_eq!(None, dashboard.rate());
+        dashboard.update(snapshot(110, 0), start + Duration::from_secs(2));
+        assert_eq!(Some(5.0), dashboard.rate());
+        // A restarted server
+        dashboard.update(snapshot(3, 0), start + Duration::from_secs(3));
+        assert_eq!(Some(3.0), dashboard.rate());
+    }
+
+    #[test]
+    fn draws_every_panel() {
+        let mut dashboard = Dashboard::new("mws");
+        let start = Instant::now();
+        dashboard.update(snapshot(0, 0), start);
+        dashboard.update(snapshot(25, 3), start + Duration::from_secs(1));
+
+        // Room for two of the three log lines
+        let mut screen = Screen::new(Size { cols: 48, rows: 13 });
+        dashboard.draw(&mut screen);
+        let row = |r| screen.row(r).trim_end().to_string();
+        assert_eq!("mws  up 1h02m03s  25 requests  2 not found", row(0));
+        assert_eq!("Requests/s  25.0", row(2));
+        assert_eq!("█", row(3));
+        assert_eq!("Latency           p50         p95         p99", row(5));
+        assert_eq!("                850µs      12.3ms       1.20s", row(6));
+        assert_eq!("Workers     [##################------] 3/4 busy", row(8));
+        assert_eq!(("second", "third"), (row(11).as_str(), row(12).as_str()));
+
+        dashboard.failed("connection refused");
+        dashboard.draw(&mut screen);
+        assert_eq!("mws  connection refused", screen.row(0).trim_end());
+    }
+}
@@ -0,0 +1,394 @@
+// Text Encodings for Bytes
+
+// HTTP is a text protocol, so bytes that aren't printable text have to be spelled out with printable characters before they can go into
+// a header, a URL or a cookie. Three ways of doing that show up all over the server:
+    // 1. Base64: every 3 bytes become 4 characters from a 64 character alphabet, the most compact of the three.
+    //    The WebSocket handshake sends its SHA-1 digest this way. The standard alphabet uses '+' and '/', which mean something in a URL,
+    //    so there is also a URL-safe alphabet with '-' and '_' instead, usually written without the '=' padding.
+    // 2. Hex: every byte becomes two characters from 0-9a-f. Twice the size, but easy to read, which is why digests and signatures are shown this way.
+    // 3. Percent-encoding: the characters that are fine in a URL stay as they are, every other byte becomes %XX with the byte in hex.
+    //    "caf%C3%A9" is "café": the two %XX are the UTF-8 bytes of 'é'.
+
+// Decoding is where things go wrong: a character outside the alphabet, a truncated %X, base64 of an impossible length.
+// Every decoder returns an EncodingError saying what was wrong and where, instead of skipping or guessing.
+
+// Each encoding has a core that works on byte slices: it writes into a buffer the caller provides and returns how much of it was used.
+// The cores don't allocate and only use what's in core, so they would work unchanged without the standard library (in a #![no_std] crate).
+// encode() and decode() on top of them allocate a String or Vec of the right size, which is what the rest of the server uses.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingError {
+    // index is the position of the byte in the input
+    InvalidByte { index: usize, byte: u8 },
+    InvalidLength,
+    // Base64 with misplaced '=' or leftover bits that aren't zero, which no encoder would produce
+    InvalidPadding,
+    // The output buffer given to an *_to_slice function is too small
+    OutputTooSmall,
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodingError::InvalidByte { index, byte } => write!(f, "invalid byte {byte:#04x} at position {index}"),
+            EncodingError::InvalidLength => write!(f, "invalid input length"),
+            EncodingError::InvalidPadding => write!(f, "invalid padding"),
+            EncodingError::OutputTooSmall => write!(f, "output buffer too small"),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+// Base64
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64 {
+    // Padded with '=' to a multiple of 4 characters
+    Standard,
+    // Not padded. Decoding accepts it with or without the padding.
+    UrlSafe,
+}
+
+impl Base64 {
+    fn alphabet(self) -> &'static [u8; 64] {
+        match self {
+            Base64::Standard => STANDARD,
+            Base64::UrlSafe => URL_SAFE,
+        }
+    }
+
+    fn padded(self) -> bool {
+        self == Base64::Standard
+    }
+
+    pub fn encoded_len(self, len: usize) -> usize {
+        if self.padded() {
+            len.div_ceil(3) * 4
+        } else {
+            // A chunk of n bytes carries n * 8 bits, which needs n + 1 characters
+            len / 3 * 4 + [0, 2, 3][len % 3]
+        }
+    }
+
+    pub fn encode_to_slice(self, data: &[u8], out: &mut [u8]) -> Result<usize, EncodingError> {
+        let len = self.encoded_len(data.len());
+        if out.len() < len {
+            return Err(EncodingError::OutputTooSmall);
+        }
+
+        let alphabet = self.alphabet();
+        let mut o = 0;
+        for chunk in data.chunks(3) {
+            let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let group = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out[o] = alphabet[((group >> (18 - 6 * i)) & 0x3F) as usize];
+                    o += 1;
+                } else if self.padded() {
+                    out[o] = b'=';
+                    o += 1;
+                }
+            }
+        }
+        Ok(o)
+    }
+
+    pub fn encode(self, data: &[u8]) -> String {
+        let mut out = vec![0; self.encoded_len(data.len())];
+        self.encode_to_slice(data, &mut out).unwrap();
+        // Every byte came from the alphabet, which is ASCII
+        String::from_utf8(out).unwrap()
+    }
+
+    // The most bytes input could decode to, the exact number depends on the padding
+    pub fn decoded_len_estimate(len: usize) -> usize {
+        len.div_ceil(4) * 3
+    }
+
+    pub fn decode_to_slice(self, input: &[u8], out: &mut [u8]) -> Result<usize, EncodingError> {
+        // Split off the padding, there can be at most two '='
+        let data_len = input.iter().rposition(|&b| b != b'=').map_or(0, |i| i + 1);
+        let padding = input.len() - data_len;
+        if padding > 2 || (padding > 0 && !input.len().is_multiple_of(4)) {
+            return Err(EncodingError::InvalidPadding);
+        }
+        if self.padded() && !input.len().is_multiple_of(4) {
+            return Err(EncodingError::InvalidLength);
+        }
+        // One character on its own is only 6 bits, not enough for a byte
+        if data_len % 4 == 1 {
+            return Err(EncodingError::InvalidLength);
+        }
+
+        let len = data_len / 4 * 3 + [0, 0, 1, 2][data_len % 4];
+        if out.len() < len {
+            return Err(EncodingError::OutputTooSmall);
+        }
+
+        let alphabet = self.alphabet();
+        let mut o = 0;
+        for (c, chunk) in input[..data_len].chunks(4).enumerate() {
+            let mut group = 0u32;
+            for (i, &byte) in chunk.iter().enumerate() {
+                let value = match alphabet.iter().position(|&a| a == byte) {
+                    Some(value) => value as u32,
+                    None => return Err(EncodingError::InvalidByte { index: c * 4 + i, byte }),
+                };
+                group |= value << (18 - 6 * i);
+            }
+
+            let bytes = group.to_be_bytes();
+            let n = chunk.len() - 1;
+            // The bits after the last full byte must be zero, otherwise two different strings would decode to the same bytes
+            if bytes[1 + n..].iter().any(|&b| b != 0) {
+                return Err(EncodingError::InvalidPadding);
+            }
+            out[o..o + n].copy_from_slice(&bytes[1..1 + n]);
+            o += n;
+        }
+        Ok(o)
+    }
+
+    pub fn decode(self, input: &str) -> Result<Vec<u8>, EncodingError> {
+        let mut out = vec![0; Base64::decoded_len_estimate(input.len())];
+        let len = self.decode_to_slice(input.as_bytes(), &mut out)?;
+        out.truncate(len);
+        Ok(out)
+    }
+}
+
+// Hex
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+pub fn hex_encode_to_slice(data: &[u8], out: &mut [u8]) -> Result<usize, EncodingError> {
+    if out.len() < data.len() * 2 {
+        return Err(EncodingError::OutputTooSmall);
+    }
+    for (i, &b) in data.iter().enumerate() {
+        out[2 * i] = HEX_DIGITS[(b >> 4) as usize];
+        out[2 * i + 1] = HEX_DIGITS[(b & 0xF) as usize];
+    }
+    Ok(data.len() * 2)
+}
+
+// Lowercase, like sha1sum prints digests
+pub fn hex_encode(data: &[u8]) -> String {
+    let mut out = vec![0; data.len() * 2];
+    hex_encode_to_slice(data, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// Upper and lowercase digits are both accepted
+pub fn hex_decode_to_slice(input: &[u8], out: &mut [u8]) -> Result<usize, EncodingError> {
+    if !input.len().is_multiple_of(2) {
+        return Err(EncodingError::InvalidLength);
+    }
+    if out.len() < input.len() / 2 {
+        return Err(EncodingError::OutputTooSmall);
+    }
+    for (i, pair) in input.chunks(2).enumerate() {
+        let digit = |j: usize| hex_value(pair[j]).ok_or(EncodingError::InvalidByte { index: 2 * i + j, byte: pair[j] });
+        out[i] = digit(0)? << 4 | digit(1)?;
+    }
+    Ok(input.len() / 2)
+}
+
+pub fn hex_decode(input: &str) -> Result<Vec<u8>, EncodingError> {
+    let mut out = vec![0; input.len() / 2];
+    hex_decode_to_slice(input.as_bytes(), &mut out)?;
+    Ok(out)
+}
+
+// Percent-encoding
+
+// Which bytes are left alone. The unreserved characters of RFC 3986 (letters, digits and - . _ ~) are safe everywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeSet {
+    // For a single path segment, or a name or value in a query string: everything else is encoded, '/' '?' '&' and '=' included
+    Component,
+    // For a whole path, which keeps its '/' separators
+    Path,
+}
+
+impl EncodeSet {
+    fn keeps(self, b: u8) -> bool {
+        let unreserved = b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~');
+        match self {
+            EncodeSet::Component => unreserved,
+            EncodeSet::Path => unreserved || b == b'/',
+        }
+    }
+}
+
+pub fn percent_encoded_len(data: &[u8], set: EncodeSet) -> usize {
+    data.iter().map(|&b| if set.keeps(b) { 1 } else { 3 }).sum()
+}
+
+pub fn percent_encode_to_slice(data: &[u8], set: EncodeSet, out: &mut [u8]) -> Result<usize, EncodingError> {
+    if out.len() < percent_encoded_len(data, set) {
+        return Err(EncodingError::OutputTooSmall);
+    }
+    let mut o = 0;
+    for &b in data {
+        if set.keeps(b) {
+            out[o] = b;
+            o += 1;
+        } else {
+            // Uppercase, as RFC 3986 recommends
+            out[o..o + 3].copy_from_slice(&[b'%', HEX_DIGITS[(b >> 4) as usize].to_ascii_uppercase(), HEX_DIGITS[(b & 0xF) as usize].to_ascii_uppercase()]);
+            o += 3;
+        }
+    }
+    Ok(o)
+}
+
+pub fn percent_encode(data: &[u8], set: EncodeSet) -> String {
+    let mut out = vec![0; percent_encoded_len(data, set)];
+    percent_encode_to_slice(data, set, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// '+' only means space in urlencoded forms, in a path it is a literal plus, hence the flag.
+// The output is never longer than the input, so a buffer of input.len() bytes is always enough.
+pub fn percent_decode_to_slice(input: &[u8], plus_as_space: bool, out: &mut [u8]) -> Result<usize, EncodingError> {
+    let mut o = 0;
+    let mut i = 0;
+    while i < input.len() {
+        if o == out.len() {
+            return Err(EncodingError::OutputTooSmall);
+        }
+        match input[i] {
+            b'%' => {
+                // Points at the first byte that isn't a hex digit, or at the '%' when the input ends too early
+                let digit = |j: usize| match input.get(i + j) {
+                    Some(&byte) => hex_value(byte).ok_or(EncodingError::InvalidByte { index: i + j, byte }),
+                    None => Err(EncodingError::InvalidByte { index: i, byte: b'%' }),
+                };
+                out[o] = digit(1)? << 4 | digit(2)?;
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out[o] = b' ';
+                i += 1;
+            }
+            b => {
+                out[o] = b;
+                i += 1;
+            }
+        }
+        o += 1;
+    }
+    Ok(o)
+}
+
+// Gives bytes, not a String: the decoded bytes don't have to be UTF-8
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Result<Vec<u8>, EncodingError> {
+    let mut out = vec![0; input.len()];
+    let len = percent_decode_to_slice(input.as_bytes(), plus_as_space, &mut out)?;
+    out.truncate(len);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small xorshift generator, so the round trips see random-looking bytes that are the same on every run
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn base64_rfc4648_test_vectors() {
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(expected, Base64::Standard.encode(input.as_bytes()));
+            assert_eq!(input.as_bytes(), Base64::Standard.decode(expected).unwrap());
+            assert_eq!(expected.trim_end_matches('='), Base64::UrlSafe.encode(input.as_bytes()));
+        }
+        assert_eq!("+/8=", Base64::Standard.encode(&[0xFB, 0xFF]));
+        assert_eq!("-_8", Base64::UrlSafe.encode(&[0xFB, 0xFF]));
+        assert_eq!(vec![0xFB, 0xFF], Base64::UrlSafe.decode("-_8=").unwrap());
+    }
+
+    #[test]
+    fn base64_rejects_what_no_encoder_produces() {
+        assert_eq!(Err(EncodingError::InvalidByte { index: 2, byte: b'-' }), Base64::Standard.decode("Zm-v"));
+        assert_eq!(Err(EncodingError::InvalidLength), Base64::Standard.decode("Zm9"));
+        assert_eq!(Err(EncodingError::InvalidLength), Base64::UrlSafe.decode("Zm9vY"));
+        assert_eq!(Err(EncodingError::InvalidPadding), Base64::Standard.decode("Zg==="));
+        assert_eq!(Err(EncodingError::InvalidByte { index: 1, byte: b'=' }), Base64::Standard.decode("Z=g="));
+        // "Zh==" has the same first byte as "Zg==", but leftover bits set
+        assert_eq!(Err(EncodingError::InvalidPadding), Base64::Standard.decode("Zh=="));
+        assert_eq!(Err(EncodingError::OutputTooSmall), Base64::Standard.decode_to_slice(b"Zm9v", &mut [0; 2]));
+    }
+
+    #[test]
+    fn hex_round_trip_and_errors() {
+        assert_eq!("00ff10ab", hex_encode(&[0x00, 0xFF, 0x10, 0xAB]));
+        assert_eq!(vec![0x00, 0xFF, 0x10, 0xAB], hex_decode("00FF10ab").unwrap());
+        assert_eq!(Err(EncodingError::InvalidLength), hex_decode("abc"));
+        assert_eq!(Err(EncodingError::InvalidByte { index: 3, byte: b'g' }), hex_decode("00fg"));
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!("caf%C3%A9%20au%20lait", percent_encode("café au lait".as_bytes(), EncodeSet::Component));
+        assert_eq!("a%2Fb%3Fc%3Dd", percent_encode(b"a/b?c=d", EncodeSet::Component));
+        assert_eq!("/docs/a%20b/", percent_encode(b"/docs/a b/", EncodeSet::Path));
+
+        assert_eq!("a b+c".as_bytes(), percent_decode("a+b%2Bc", true).unwrap());
+        assert_eq!(b"a+b", &percent_decode("a+b", false).unwrap()[..]);
+        // Not UTF-8, and that's fine here
+        assert_eq!(vec![0xA9], percent_decode("%A9", false).unwrap());
+        assert_eq!(Err(EncodingError::InvalidByte { index: 3, byte: b'%' }), percent_decode("100%", true));
+        assert_eq!(Err(EncodingError::InvalidByte { index: 1, byte: b'z' }), percent_decode("%zz", true));
+    }
+
+    #[test]
+    fn every_encoding_round_trips() {
+        for len in 0..64 {
+            let data = random_bytes(0x2545_f491_4f6c_dd1d + len as u64, len);
+            for base64 in [Base64::Standard, Base64::UrlSafe] {
+                assert_eq!(data, base64.decode(&base64.encode(&data)).unwrap());
+            }
+            assert_eq!(data, hex_decode(&hex_encode(&data)).unwrap());
+            for set in [EncodeSet::Component, EncodeSet::Path] {
+                assert_eq!(data, percent_decode(&percent_encode(&data, set), true).unwrap());
+            }
+        }
+    }
+}
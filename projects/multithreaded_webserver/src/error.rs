@@ -0,0 +1,421 @@
+// Handler Errors
+
+// A handler that can fail had two choices so far: unwrap(), and let the server answer 500 for whatever went wrong, or a match
+// on every Result that builds the error page by hand. HandlerError is the third: a handler returns Result<Response, HandlerError>
+// (Chain::try_new takes one), uses ? like any other function, and the error turns into the page with the right status.
+
+// Each variant is one status, and the errors of the other modules convert into the one that fits with From, which is what ? calls:
+    // 1. A malformed request, form, URL or multipart body is the client's fault: 400 Bad Request.
+    // 2. A body over the limit is 413, whether the request or one part of a multipart body was too large.
+    // 3. A file that isn't there is 404, and one we may not read is 403. Any other io::Error is ours: 500.
+    // 4. Anything else that is an Error (a BoxError, or a ContextError from common::error) is a 500 too.
+
+// A 500 says nothing to the client about what went wrong, the error could be a path or a query it has no business seeing.
+// The whole error, with its sources, goes to the log instead. The other statuses are for the client, so their message is shown.
+
+// The page is HTML for a browser and JSON for a client that asks for it in its Accept header, negotiated with the q-values
+// of src/negotiation.rs. Vary tells caches that the page depends on Accept and Accept-Language:
+
+    // Accept: application/json    ->  {"status":404,"error":"Not Found","message":"no such user: 7"}
+
+// The reason phrase is in the language the Accept-Language header asks for, from the catalogs in locales/ (common::i18n),
+// and so is a message that is only the reason phrase. The details a handler gives are shown as they were written. "error"
+// in the JSON stays English, it's for programs to compare.
+
+// CatchPanic is the middleware for handlers that panic anyway. The server already answers 500 when a worker unwinds
+// (src/guard.rs), but that closes the connection. CatchPanic catches the unwind inside the Chain instead, with
+// panic_report::catch: the client gets the same error page as for a HandlerError, the connection stays open, and the panic
+// becomes a crash report with the backtrace of where it happened (src/panic_report.rs). The log gets a line that says
+// where the report is, or the whole backtrace when reports aren't written to files.
+
+use std::{fmt, io, sync::Once};
+
+use common::{
+    error::{self as errors, BoxError, ContextError},
+    i18n,
+};
+
+use json::JsonWriter;
+
+use crate::{
+    form::FormError,
+    http::{reason_phrase, ParseError, Request, Response},
+    negotiation::{negotiate, negotiate_language},
+    middleware::{Middleware, Next},
+    multipart::MultipartError,
+    panic_report,
+    url::UrlError,
+};
+
+#[derive(Debug)]
+pub enum HandlerError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden,
+    NotFound(String),
+    MethodNotAllowed,
+    PayloadTooLarge(String),
+    Unavailable(String),
+    Internal(BoxError),
+}
+
+impl HandlerError {
+    // Any error as a 500, for the ones without a From of their own: .map_err(HandlerError::internal)?
+    pub fn internal<E: Into<BoxError>>(error: E) -> HandlerError {
+        HandlerError::Internal(error.into())
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            HandlerError::BadRequest(_) => 400,
+            HandlerError::Unauthorized => 401,
+            HandlerError::Forbidden => 403,
+            HandlerError::NotFound(_) => 404,
+            HandlerError::MethodNotAllowed => 405,
+            HandlerError::PayloadTooLarge(_) => 413,
+            HandlerError::Internal(_) => 500,
+            HandlerError::Unavailable(_) => 503,
+        }
+    }
+
+    // What the client is told: the reason phrase, and the details for the statuses that are its business
+    pub fn message(&self) -> String {
+        match self {
+            HandlerError::BadRequest(message)
+            | HandlerError::NotFound(message)
+            | HandlerError::PayloadTooLarge(message)
+            | HandlerError::Unavailable(message) => message.clone(),
+            _ => reason_phrase(self.status()).to_string(),
+        }
+    }
+
+    // The error page for the request, in the format it accepts. A 500 is logged here, with every source of the error
+    pub fn response(&self, request: &Request) -> Response {
+        let status = self.status();
+        if let HandlerError::Internal(error) = self {
+            let causes: Vec<String> = errors::chain(error.as_ref()).map(|e| e.to_string()).collect();
+            common::error!("{} {}: {}", request.method, request.path, causes.join(": caused by: "));
+        }
+        error_page(status, &self.message(), request)
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandlerError::Internal(error) => write!(f, "{}: {error}", reason_phrase(500)),
+            _ => write!(f, "{}: {}", self.status(), self.message()),
+        }
+    }
+}
+
+impl std::error::Error for HandlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HandlerError::Internal(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HandlerError {
+    fn from(e: io::Error) -> HandlerError {
+        match e.kind() {
+            io::ErrorKind::NotFound => HandlerError::NotFound(reason_phrase(404).to_string()),
+            io::ErrorKind::PermissionDenied => HandlerError::Forbidden,
+            _ => HandlerError::Internal(e.into()),
+        }
+    }
+}
+
+impl From<ParseError> for HandlerError {
+    fn from(e: ParseError) -> HandlerError {
+        match e {
+            ParseError::Io(e) => HandlerError::from(e),
+            ParseError::BodyTooLarge(_) => HandlerError::PayloadTooLarge(e.to_string()),
+            ParseError::Empty | ParseError::Malformed(_) | ParseError::HeadTooLarge(_) => HandlerError::BadRequest(e.to_string()),
+        }
+    }
+}
+
+impl From<FormError> for HandlerError {
+    fn from(e: FormError) -> HandlerError {
+        HandlerError::BadRequest(e.to_string())
+    }
+}
+
+impl From<UrlError> for HandlerError {
+    fn from(e: UrlError) -> HandlerError {
+        HandlerError::BadRequest(e.to_string())
+    }
+}
+
+impl From<MultipartError> for HandlerError {
+    fn from(e: MultipartError) -> HandlerError {
+        match e {
+            MultipartError::Io(e) => HandlerError::from(e),
+            MultipartError::TooLarge { .. } | MultipartError::TooManyParts(_) => HandlerError::PayloadTooLarge(e.to_string()),
+            _ => HandlerError::BadRequest(e.to_string()),
+        }
+    }
+}
+
+impl From<ContextError> for HandlerError {
+    fn from(e: ContextError) -> HandlerError {
+        HandlerError::Internal(Box::new(e))
+    }
+}
+
+impl From<BoxError> for HandlerError {
+    fn from(e: BoxError) -> HandlerError {
+        HandlerError::Internal(e)
+    }
+}
+
+// JSON when the client ranks it above HTML, HTML otherwise and when it accepts neither. A browser's Accept has text/html
+// first, and */* for anything, which isn't asking for JSON
+pub fn wants_json(accept: Option<&str>) -> bool {
+    negotiate(&["text/html", "application/json"], accept) == Some("application/json")
+}
+
+const CATALOGS: [(&str, &str); 3] = [
+    ("en", include_str!("../locales/en.txt")),
+    ("pl", include_str!("../locales/pl.txt")),
+    ("ru", include_str!("../locales/ru.txt")),
+];
+
+fn load_catalogs() {
+    static LOADED: Once = Once::new();
+    LOADED.call_once(|| {
+        for (locale, text) in CATALOGS {
+            i18n::load(locale, text).expect("the catalogs in locales/ are checked by the tests");
+        }
+    });
+}
+
+// The reason phrase in the locale, the English one for a status the catalogs don't have
+fn localized_reason(locale: &str, status: u16) -> String {
+    let key = format!("status.{status}");
+    match i18n::translate(locale, &key, &[]) {
+        reason if reason == key => reason_phrase(status).to_string(),
+        reason => reason,
+    }
+}
+
+pub fn error_page(status: u16, message: &str, request: &Request) -> Response {
+    load_catalogs();
+    let locales = CATALOGS.map(|(locale, _)| locale);
+    // English, the first of them, when the client doesn't read any of the others
+    let locale = negotiate_language(&locales, request.header("Accept-Language")).unwrap_or(locales[0]);
+    let reason = localized_reason(locale, status);
+    let message = if message == reason_phrase(status) { reason.as_str() } else { message };
+    if wants_json(request.header("Accept")) {
+        let mut json = JsonWriter::new(Vec::new());
+        let body = json
+            .begin_object()
+            .and_then(|()| json.field("status", &status))
+            .and_then(|()| json.field("error", reason_phrase(status)))
+            .and_then(|()| json.field("message", message))
+            .and_then(|()| json.end_object())
+            .and_then(|()| json.finish());
+        return Response::new(status, body.expect("writing to a Vec can't fail"))
+            .with_header("Content-Type", "application/json")
+            .with_header("Content-Language", locale)
+            .with_header("Vary", "Accept, Accept-Language");
+    }
+    let body = format!(
+        "<!DOCTYPE html>\n<html lang=\"{locale}\">\n<head><meta charset=\"utf-8\"><title>{status} {reason}</title></head>\n\
+         <body>\n<h1>{status} {reason}</h1>\n<p>{}</p>\n</body>\n</html>\n",
+        html_escape(message)
+    );
+    Response::html(status, &body).with_header("Content-Language", locale).with_header("Vary", "Accept, Accept-Language")
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Catching the Panic
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CatchPanic;
+
+impl CatchPanic {
+    pub fn new() -> CatchPanic {
+        CatchPanic
+    }
+}
+
+impl Middleware for CatchPanic {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        // The request may be half changed when a layer panics, but all that's left to do with it is to build the error page
+        match panic_report::catch(|| next.run(request)) {
+            Ok(response) => response,
+            Err(report) => {
+                let details = match &report.file {
+                    Some(file) => format!("(report in {})", file.display()),
+                    None => format!("\n{}", report.backtrace),
+                };
+                common::error!(
+                    "{} {}: handler panicked at {}: {} {details}",
+                    request.method,
+                    request.path,
+                    report.location,
+                    report.message
+                );
+                error_page(500, reason_phrase(500), request)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::Body,
+        markup::{Document, Problem},
+        middleware::Chain,
+    };
+
+    fn request(path: &str, accept: Option<&str>) -> Request {
+        let accept = accept.map_or(String::new(), |accept| format!("Accept: {accept}\r\n"));
+        let raw = format!("GET {path} HTTP/1.1\r\n{accept}\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn body(response: &Response) -> String {
+        match &response.body {
+            Body::Bytes(bytes) => String::from_utf8(bytes.clone()).unwrap(),
+            Body::EventStream(_) => panic!("not a body of bytes"),
+        }
+    }
+
+    #[test]
+    fn errors_convert_to_their_status() {
+        let status = |error: HandlerError| error.status();
+        assert_eq!(404, status(io::Error::from(io::ErrorKind::NotFound).into()));
+        assert_eq!(403, status(io::Error::from(io::ErrorKind::PermissionDenied).into()));
+        assert_eq!(500, status(io::Error::other("disk on fire").into()));
+        assert_eq!(400, status(ParseError::Malformed("no method").into()));
+        assert_eq!(413, status(ParseError::BodyTooLarge(1 << 30).into()));
+        assert_eq!(400, status(FormError::Missing(String::from("name")).into()));
+        assert_eq!(400, status(UrlError::InvalidPort.into()));
+        assert_eq!(413, status(MultipartError::TooManyParts(10).into()));
+        assert_eq!(400, status(MultipartError::MissingBoundary.into()));
+        let error: BoxError = "anything".into();
+        assert_eq!(500, status(error.into()));
+    }
+
+    #[test]
+    fn question_mark_converts() {
+        fn handler(request: &Request) -> Result<Response, HandlerError> {
+            let id: u32 = request.query()?.parse_field("id")?;
+            match id {
+                7 => Ok(Response::text(200, "ferris")),
+                _ => Err(HandlerError::NotFound(format!("no such user: {id}"))),
+            }
+        }
+        assert!(handler(&request("/user?id=7", None)).is_ok());
+        let error = handler(&request("/user?id=x", None)).err().unwrap();
+        assert_eq!("400: invalid value \"x\" for form field id", error.to_string());
+        assert_eq!(404, handler(&request("/user?id=8", None)).err().unwrap().status());
+    }
+
+    #[test]
+    fn the_page_follows_the_accept_header() {
+        assert!(!wants_json(None));
+        assert!(!wants_json(Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")));
+        assert!(wants_json(Some("application/json")));
+        assert!(wants_json(Some("text/html;q=0.5, application/json")));
+        assert!(!wants_json(Some("application/json;q=0.5, text/html")));
+        assert!(!wants_json(Some("application/json;q=0")));
+
+        let error = HandlerError::NotFound(String::from("no <b>\"user\"</b>"));
+        let response = error.response(&request("/", Some("application/json")));
+        assert_eq!(404, response.status);
+        assert_eq!(Some("application/json"), response.header("Content-Type"));
+        assert_eq!(r#"{"status":404,"error":"Not Found","message":"no <b>\"user\"</b>"}"#, body(&response));
+
+        let response = error.response(&request("/", None));
+        assert!(response.header("Content-Type").unwrap().starts_with("text/html"));
+        assert!(body(&response).contains("<p>no &lt;b&gt;&quot;user&quot;&lt;/b&gt;</p>"), "{}", body(&response));
+
+        // Well-formed, with the message as text rather than markup
+        let page = Document::parse(&body(&response));
+        assert_eq!(Vec::<Problem>::new(), page.problems());
+        assert_eq!(Some(String::from("no <b>\"user\"</b>")), page.find("body > p").unwrap().map(|p| p.text()));
+    }
+
+    #[test]
+    fn the_page_speaks_the_language_asked_for() {
+        let raw = "GET / HTTP/1.1\r\nAccept-Language: pl-PL,pl;q=0.9,en;q=0.8\r\nAccept: application/json\r\n\r\n";
+        let polish = Request::read_from(&mut raw.as_bytes()).unwrap();
+        let response = HandlerError::NotFound(reason_phrase(404).to_string()).response(&polish);
+        assert_eq!(Some("pl"), response.header("Content-Language"));
+        assert_eq!(Some("Accept, Accept-Language"), response.header("Vary"));
+        assert_eq!(r#"{"status":404,"error":"Not Found","message":"Nie znaleziono"}"#, body(&response));
+
+        let raw = "GET / HTTP/1.1\r\nAccept-Language: ru\r\n\r\n";
+        let russian = Request::read_from(&mut raw.as_bytes()).unwrap();
+        let page = body(&HandlerError::NotFound(String::from("no such user: 7")).response(&russian));
+        assert!(page.contains("<html lang=\"ru\">") && page.contains("<h1>404 Не найдено</h1>"), "{page}");
+        assert!(page.contains("<p>no such user: 7</p>"), "{page}");
+
+        // Catalogs nobody has fall back to English, and so do statuses the catalogs don't have
+        let page = body(&error_page(418, "short and stout", &request("/", None)));
+        assert!(page.contains("<html lang=\"en\">") && page.contains("<h1>418 Unknown</h1>"), "{page}");
+        for (locale, text) in CATALOGS {
+            assert!(i18n::Catalogs::new("en").add(locale, text).is_ok(), "locales/{locale}.txt");
+        }
+    }
+
+    #[test]
+    fn internal_errors_keep_their_details_out_of_the_page() {
+        common::log::set_max_level(None);
+        let error = HandlerError::internal(io::Error::other("/etc/secret is corrupt"));
+        let response = error.response(&request("/", Some("application/json")));
+        assert_eq!(500, response.status);
+        assert!(!body(&response).contains("secret"));
+        assert!(error.to_string().contains("/etc/secret is corrupt"));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn try_new_renders_the_error() {
+        let chain = Chain::try_new(|req: &mut Request| match req.path_only() {
+            "/" => Ok(Response::text(200, "home")),
+            _ => Err(HandlerError::MethodNotAllowed),
+        });
+        assert_eq!(200, chain.handle(&mut request("/", None)).status);
+        assert_eq!(405, chain.handle(&mut request("/nope", None)).status);
+    }
+
+    #[test]
+    fn panics_become_500s() {
+        // The backtrace would fill the test output, and no test looks at the log
+        common::log::set_max_level(None);
+        let chain = Chain::new(|req: &mut Request| match req.path_only() {
+            "/panic" => panic!("handler bug"),
+            _ => Response::text(200, "fine"),
+        })
+        .with(CatchPanic::new());
+
+        let response = chain.handle(&mut request("/panic", Some("application/json")));
+        assert_eq!(500, response.status);
+        assert!(body(&response).contains("Internal Server Error"));
+        // The thread is still fine, and the next request is answered as usual
+        assert_eq!(200, chain.handle(&mut request("/", None)).status);
+    }
+}
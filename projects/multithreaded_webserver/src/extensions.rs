@@ -0,0 +1,142 @@
+// Request Extensions
+
+// Middleware keeps finding things out about a request that the handler wants to know: the session, who sent it (src/auth.rs),
+// the ID its log lines are tagged with. Each of them used to be a field of its own on Request, so a new middleware meant
+// changing Request, and a middleware outside this crate couldn't add one at all.
+// Extensions is a map from a type to one value of that type, and every Request has one:
+
+    // request.extensions_mut().insert(RequestId(id));                 // in a middleware
+    // let id = request.extensions().get::<RequestId>();               // in the handler, an Option<&RequestId>
+
+// The type is the key, so there is no string to misspell and no cast to get wrong: get::<RequestId>() can only ever return
+// a RequestId. Two middlewares that want to store a String each wrap it in a type of their own, the newtype pattern.
+
+// The values are Box<dyn Any>, the trait every 'static type implements and that can be asked whether it's really a T with
+// downcast_ref::<T>(). The trait objects here are of a trait of our own, CloneAny, which adds a way to clone them,
+// so that a Request with its extensions can still be cloned. That's why a value has to be Clone to go in.
+// Since Rust 1.86 a &dyn CloneAny converts to the &dyn Any it's built on by itself (trait upcasting), so CloneAny needs no
+// as_any() method for that.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+};
+
+trait CloneAny: Any + Send + Sync {
+    fn clone_box(&self) -> Box<dyn CloneAny>;
+}
+
+impl<T: Any + Clone + Send + Sync> CloneAny for T {
+    fn clone_box(&self) -> Box<dyn CloneAny> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn CloneAny> {
+    fn clone(&self) -> Self {
+        // (**self), or the blanket impl above would clone the Box itself, by calling this again
+        (**self).clone_box()
+    }
+}
+
+// The name of each type is kept along with the value, for Debug
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, (&'static str, Box<dyn CloneAny>)>,
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    // Returns the value of the same type that was there before
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        let old = self.map.insert(TypeId::of::<T>(), (std::any::type_name::<T>(), Box::new(value)))?;
+        let old: Box<dyn Any> = old.1;
+        old.downcast().ok().map(|old| *old)
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        let (_, value) = self.map.get(&TypeId::of::<T>())?;
+        (&**value as &dyn Any).downcast_ref()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        let (_, value) = self.map.get_mut(&TypeId::of::<T>())?;
+        (&mut **value as &mut dyn Any).downcast_mut()
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        let (_, value) = self.map.remove(&TypeId::of::<T>())?;
+        let value: Box<dyn Any> = value;
+        value.downcast().ok().map(|value| *value)
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+// The values can't be printed, they aren't required to be Debug, but which types there are often says enough
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.map.values().map(|(name, _)| name)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct RequestId(u64);
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct User(String);
+
+    #[test]
+    fn one_value_per_type() {
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+        assert_eq!(None, extensions.insert(RequestId(1)));
+        assert_eq!(None, extensions.insert(User(String::from("ferris"))));
+        assert_eq!(Some(RequestId(1)), extensions.insert(RequestId(2)));
+        assert_eq!(2, extensions.len());
+
+        assert_eq!(Some(&RequestId(2)), extensions.get::<RequestId>());
+        extensions.get_mut::<User>().unwrap().0.push_str(" the crab");
+        assert_eq!(Some(&User(String::from("ferris the crab"))), extensions.get());
+        // A String is a type of its own, not any of the newtypes around one
+        assert_eq!(None, extensions.get::<String>());
+
+        assert_eq!(Some(RequestId(2)), extensions.remove::<RequestId>());
+        assert!(!extensions.contains::<RequestId>());
+        assert_eq!(None, extensions.remove::<RequestId>());
+    }
+
+    #[test]
+    fn clones_are_deep_except_for_arcs() {
+        let mut extensions = Extensions::new();
+        let shared = Arc::new(5);
+        extensions.insert(User(String::from("a")));
+        extensions.insert(Arc::clone(&shared));
+
+        let mut copy = extensions.clone();
+        copy.get_mut::<User>().unwrap().0 = String::from("b");
+        assert_eq!(Some(&User(String::from("a"))), extensions.get());
+        assert_eq!(3, Arc::strong_count(&shared));
+        let debug = format!("{copy:?}");
+        assert!(debug.contains("extensions::tests::User") && debug.contains("Arc<i32>"), "{debug}");
+    }
+}
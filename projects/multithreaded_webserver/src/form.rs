@@ -0,0 +1,185 @@
+// Forms and Query Strings
+
+// When a browser submits an HTML form with method="post", the body is "urlencoded": name=value pairs joined with '&'.
+// A GET form (and any link with parameters) puts the same format in the query string after the '?' of the path.
+    // POST /login
+    // Content-Type: application/x-www-form-urlencoded
+    //
+    // user=ferris&password=hunter%202&remember=on
+
+// Characters that would break this format are percent-encoded as %XX with the byte in hex, and a space may also be sent as '+'.
+// Percent-decoding gives bytes, not chars: "caf%C3%A9" is the two UTF-8 bytes of 'é', so we collect bytes and check they're valid UTF-8 at the end.
+
+// Handlers rarely want a list of strings though, they want a struct. FromForm turns the pairs into a struct,
+// and #[derive(FromForm)] writes that conversion for us, parsing each field with FromStr.
+
+use std::{fmt, str::FromStr};
+
+use crate::encoding;
+
+pub use form_derive::FromForm;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormError {
+    InvalidEncoding,
+    UnsupportedContentType(String),
+    Missing(String),
+    Invalid { field: String, value: String },
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormError::InvalidEncoding => write!(f, "form data is not validly encoded"),
+            FormError::UnsupportedContentType(t) => write!(f, "expected a urlencoded form, got {t}"),
+            FormError::Missing(field) => write!(f, "missing form field {field}"),
+            FormError::Invalid { field, value } => write!(f, "invalid value {value:?} for form field {field}"),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+// '+' only means space in urlencoded forms, in a path it is a literal plus, hence the flag.
+// The decoding itself is in src/encoding.rs, a form additionally has to be UTF-8.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Result<String, FormError> {
+    let bytes = encoding::percent_decode(input, plus_as_space).map_err(|_| FormError::InvalidEncoding)?;
+    String::from_utf8(bytes).map_err(|_| FormError::InvalidEncoding)
+}
+
+// The decoded pairs, in the order they were sent. A name can appear more than once (like a group of checkboxes), so this is a list and not a HashMap.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormData {
+    pairs: Vec<(String, String)>,
+}
+
+impl FormData {
+    pub fn parse(input: &str) -> Result<FormData, FormError> {
+        let mut pairs = Vec::new();
+        for pair in input.split('&').filter(|p| !p.is_empty()) {
+            // A name without '=' (like "?debug") has an empty value
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            pairs.push((percent_decode(name, true)?, percent_decode(value, true)?));
+        }
+        Ok(FormData { pairs })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.pairs.iter().filter(|(n, _)| n == name).map(|(_, v)| v.as_str()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn parse_field<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        let value = self.get(name).ok_or_else(|| FormError::Missing(name.to_string()))?;
+        value.parse().map_err(|_| FormError::Invalid { field: name.to_string(), value: value.to_string() })
+    }
+
+    // Browsers send empty text inputs as "name=", so for an optional field an empty value counts as not given
+    pub fn parse_optional<T: FromStr>(&self, name: &str) -> Result<Option<T>, FormError> {
+        match self.get(name) {
+            None | Some("") => Ok(None),
+            Some(_) => self.parse_field(name).map(Some),
+        }
+    }
+}
+
+pub trait FromForm: Sized {
+    fn from_form(form: &FormData) -> Result<Self, FormError>;
+}
+
+impl FromForm for FormData {
+    fn from_form(form: &FormData) -> Result<Self, FormError> {
+        Ok(form.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Request;
+
+    #[derive(Debug, PartialEq, FromForm)]
+    struct LoginForm {
+        user: String,
+        age: u8,
+        remember: Option<String>,
+        referrer: Option<String>,
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!("a b+c", percent_decode("a+b%2Bc", true).unwrap());
+        assert_eq!("a+b", percent_decode("a+b", false).unwrap());
+        assert_eq!("café", percent_decode("caf%C3%A9", true).unwrap());
+        assert_eq!(Err(FormError::InvalidEncoding), percent_decode("100%", true));
+        assert_eq!(Err(FormError::InvalidEncoding), percent_decode("%zz", true));
+        // A lone continuation byte isn't UTF-8
+        assert_eq!(Err(FormError::InvalidEncoding), percent_decode("%A9", true));
+    }
+
+    #[test]
+    fn parses_pairs_in_order() {
+        let form = FormData::parse("tag=a&tag=b&q=rust+book&flag&&empty=").unwrap();
+        assert_eq!(vec!["a", "b"], form.get_all("tag"));
+        assert_eq!(Some("rust book"), form.get("q"));
+        assert_eq!(Some(""), form.get("flag"));
+        assert_eq!(Some(""), form.get("empty"));
+        assert_eq!(5, form.len());
+    }
+
+    #[test]
+    fn derives_typed_forms() {
+        let form = FormData::parse("user=ferris&age=7&remember=on&referrer=").unwrap();
+        assert_eq!(
+            LoginForm { user: String::from("ferris"), age: 7, remember: Some(String::from("on")), referrer: None },
+            LoginForm::from_form(&form).unwrap()
+        );
+
+        let missing = FormData::parse("age=7").unwrap();
+        assert_eq!(Err(FormError::Missing(String::from("user"))), LoginForm::from_form(&missing));
+
+        let invalid = FormData::parse("user=ferris&age=old").unwrap();
+        assert_eq!(
+            Err(FormError::Invalid { field: String::from("age"), value: String::from("old") }),
+            LoginForm::from_form(&invalid)
+        );
+    }
+
+    #[test]
+    fn request_forms_and_query_strings() {
+        let body = "user=ferris&age=7";
+        let raw = format!(
+            "POST /login?next=%2Fhome HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=UTF-8\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let request = Request::read_from(&mut raw.as_bytes()).unwrap();
+
+        let login: LoginForm = request.form().unwrap();
+        assert_eq!("ferris", login.user);
+        assert_eq!("/login", request.path_only());
+        assert_eq!(Some("/home"), request.query().unwrap().get("next"));
+
+        let raw = "POST /upload HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi";
+        let request = Request::read_from(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            Err(FormError::UnsupportedContentType(String::from("text/plain"))),
+            request.form::<FormData>()
+        );
+        assert!(request.query().unwrap().is_empty());
+    }
+}
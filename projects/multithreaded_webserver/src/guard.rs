@@ -0,0 +1,197 @@
+// Cleanup Guards
+
+// Drop runs when a value goes out of scope, whether the function returns normally, returns early with ?, or panics.
+// A guard is a value whose only job is to run some cleanup in its Drop, which makes that cleanup impossible to skip.
+    // 1. defer! { ... } runs a block at the end of the current scope, like Go's defer.
+    // 2. ScopeGuard::new(value, cleanup) owns a value and hands it to cleanup when dropped. In the meantime it derefs to the value,
+    //    and dismiss() takes the value back out without running the cleanup, for when the work succeeded.
+    // 3. ScopeGuard::on_unwind only runs its cleanup while a panic unwinds, the webserver uses it to answer 500 when a handler panics.
+    // 4. Transaction collects undo steps while changing something, and runs them backwards when dropped without commit().
+
+use std::{
+    ops::{Deref, DerefMut},
+    thread,
+};
+
+// #[macro_export] puts the macro at the crate root, so it's used as multithreaded_webserver::defer!
+// The guard's name is hygienic: it can't clash with (or be used by) the code around the macro.
+#[macro_export]
+macro_rules! defer {
+    ($($body:tt)*) => {
+        let _guard = $crate::guard::ScopeGuard::new((), |()| { $($body)* });
+    };
+}
+
+pub struct ScopeGuard<T, F: FnOnce(T)> {
+    // None once dismissed, so Drop knows there's nothing left to do
+    inner: Option<(T, F)>,
+    only_on_unwind: bool,
+}
+
+impl<T, F: FnOnce(T)> ScopeGuard<T, F> {
+    pub fn new(value: T, cleanup: F) -> ScopeGuard<T, F> {
+        ScopeGuard { inner: Some((value, cleanup)), only_on_unwind: false }
+    }
+
+    pub fn on_unwind(value: T, cleanup: F) -> ScopeGuard<T, F> {
+        ScopeGuard { inner: Some((value, cleanup)), only_on_unwind: true }
+    }
+
+    // An associated function rather than a method, so that guard.dismiss() can't be confused with a method of T through Deref
+    pub fn dismiss(mut guard: ScopeGuard<T, F>) -> T {
+        guard.inner.take().unwrap().0
+    }
+}
+
+impl<T, F: FnOnce(T)> Deref for ScopeGuard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.as_ref().unwrap().0
+    }
+}
+
+impl<T, F: FnOnce(T)> DerefMut for ScopeGuard<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner.as_mut().unwrap().0
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for ScopeGuard<T, F> {
+    fn drop(&mut self) {
+        if let Some((value, cleanup)) = self.inner.take() {
+            if !self.only_on_unwind || thread::panicking() {
+                cleanup(value);
+            }
+        }
+    }
+}
+
+// Transactions
+
+// Changing several things in a row can fail halfway, and then the first changes have to be undone.
+// After each change, record() how to undo it. If the transaction is dropped without commit(), because of an early return, a ? or a panic,
+// the undo steps run in reverse order and put everything back as it was.
+
+type Undo<'a, S> = Box<dyn FnOnce(&mut S) + 'a>;
+
+pub struct Transaction<'a, S> {
+    state: &'a mut S,
+    undo: Vec<Undo<'a, S>>,
+}
+
+impl<'a, S> Transaction<'a, S> {
+    pub fn new(state: &'a mut S) -> Transaction<'a, S> {
+        Transaction { state, undo: Vec::new() }
+    }
+
+    pub fn record(&mut self, undo: impl FnOnce(&mut S) + 'a) {
+        self.undo.push(Box::new(undo));
+    }
+
+    // Keeps the changes: the undo steps are dropped without running
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+}
+
+impl<S> Deref for Transaction<'_, S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        self.state
+    }
+}
+
+impl<S> DerefMut for Transaction<'_, S> {
+    fn deref_mut(&mut self) -> &mut S {
+        self.state
+    }
+}
+
+impl<S> Drop for Transaction<'_, S> {
+    fn drop(&mut self) {
+        while let Some(undo) = self.undo.pop() {
+            undo(self.state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        panic::{self, AssertUnwindSafe},
+    };
+
+    #[test]
+    fn defer_runs_at_the_end_of_the_scope() {
+        let log = RefCell::new(Vec::new());
+        {
+            defer! { log.borrow_mut().push("deferred"); }
+            log.borrow_mut().push("body");
+        }
+        assert_eq!(vec!["body", "deferred"], *log.borrow());
+    }
+
+    #[test]
+    fn guards_clean_up_unless_dismissed() {
+        let cleaned = RefCell::new(Vec::new());
+
+        let mut guard = ScopeGuard::new(vec![1], |v| cleaned.borrow_mut().push(v));
+        guard.push(2);
+        drop(guard);
+
+        let kept = ScopeGuard::dismiss(ScopeGuard::new(vec![3], |v| cleaned.borrow_mut().push(v)));
+        assert_eq!(vec![3], kept);
+        assert_eq!(vec![vec![1, 2]], *cleaned.borrow());
+    }
+
+    #[test]
+    fn on_unwind_only_runs_while_panicking() {
+        let cleaned = RefCell::new(0);
+        drop(ScopeGuard::on_unwind((), |()| *cleaned.borrow_mut() += 1));
+        assert_eq!(0, *cleaned.borrow());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = ScopeGuard::on_unwind((), |()| *cleaned.borrow_mut() += 1);
+            panic!("handler failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(1, *cleaned.borrow());
+    }
+
+    fn insert_all(map: &mut HashMap<String, i32>, entries: &[(&str, i32)]) -> Result<(), String> {
+        let mut tx = Transaction::new(map);
+        for (key, value) in entries {
+            if *value < 0 {
+                return Err(format!("negative value for {key}"));
+            }
+            let key = key.to_string();
+            // The undo step puts back whatever was there before, or removes the key if nothing was
+            let old = tx.insert(key.clone(), *value);
+            tx.record(move |map| {
+                match old {
+                    Some(old) => map.insert(key, old),
+                    None => map.remove(&key),
+                };
+            });
+        }
+        tx.commit();
+        Ok(())
+    }
+
+    #[test]
+    fn transactions_roll_back_partial_writes() {
+        let mut map = HashMap::from([(String::from("a"), 1)]);
+
+        assert!(insert_all(&mut map, &[("a", 10), ("b", 20), ("c", -1)]).is_err());
+        assert_eq!(HashMap::from([(String::from("a"), 1)]), map);
+
+        insert_all(&mut map, &[("a", 10), ("b", 20)]).unwrap();
+        assert_eq!(Some(&10), map.get("a"));
+        assert_eq!(Some(&20), map.get("b"));
+    }
+}
@@ -0,0 +1,129 @@
+// Borrowed Request Heads
+
+// http::Request owns everything in it, which is what a handler wants: it can keep the request, move it to another thread,
+// or hold on to it after the connection has read the next one. But the request line and the headers are already in memory
+// once they've been read, in the connection's buffer, and copying them out is most of what parsing them costs.
+
+// head::Request<'buf> is the head as slices of that buffer. It's the struct with a reference in it from the lifetimes chapter
+// (ImportantExcerpt<'a> in generics_traits_lifetimes/gtl), used to copy nothing:
+    // 1. The method, the path, the version and every header name and value are &'buf str, pointing into the buffer.
+    // 2. The headers are kept in an ArrayVec on the stack, so parsing a head doesn't allocate at all.
+    // 3. 'buf ties the request to the buffer: the borrow checker refuses any code that reads into the buffer again,
+    //    or drops it, while the request is still around.
+// into_owned() copies it into an http::Request, for a handler, or for anything else that has to outlive the buffer.
+// http::Request::read_head parses straight from the BufReader's buffer whenever it holds the whole head, and only reads
+// line by line into a buffer of its own when a head is split across reads. The ends of the lines, and of the head, are found
+// 8 bytes at a time (common/src/bytesearch.rs).
+
+use std::str;
+
+use common::bytesearch::memchr;
+use std_collections::arrayvec::ArrayVec;
+
+use crate::http::{self, target_url, ParseError};
+
+// More headers than a browser ever sends. A request with more is refused, they have to fit in the ArrayVec
+pub const MAX_HEADERS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request<'buf> {
+    pub method: &'buf str,
+    // The request target as sent, including any query string
+    pub path: &'buf str,
+    pub version: &'buf str,
+    pub headers: ArrayVec<(&'buf str, &'buf str), MAX_HEADERS>,
+}
+
+impl<'buf> Request<'buf> {
+    // The head at the start of buf, and its length including the empty line that ends it. What comes after it, the start of
+    // the body or the next request, isn't looked at. Ok(None) when buf doesn't hold the whole head yet, so the caller can
+    // read more and try again.
+    pub fn parse(buf: &'buf [u8]) -> Result<Option<(Request<'buf>, usize)>, ParseError> {
+        let mut lines = Lines { buf, at: 0 };
+        let Some(line) = lines.next_line()? else { return Ok(None) };
+        let mut parts = line.split(' ');
+        let (method, path, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(p), Some(v), None) if !m.is_empty() && p.starts_with('/') && v.starts_with("HTTP/") => (m, p, v),
+            _ => return Err(ParseError::Malformed("invalid request line")),
+        };
+        // Checked with a stand-in host, the real one is only known once the headers are read
+        if target_url("localhost", path).is_err() {
+            return Err(ParseError::Malformed("invalid request target"));
+        }
+
+        let mut headers = ArrayVec::new();
+        loop {
+            let Some(header) = lines.next_line()? else { return Ok(None) };
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').ok_or(ParseError::Malformed("header without a colon"))?;
+            headers.try_push((name.trim(), value.trim())).map_err(|_| ParseError::Malformed("too many headers"))?;
+        }
+        Ok(Some((Request { method, path, version, headers }, lines.at)))
+    }
+
+    // Header names are case insensitive, so "content-length" finds "Content-Length". The value borrows the buffer, not self
+    pub fn header(&self, name: &str) -> Option<&'buf str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|&(_, value)| value)
+    }
+
+    // A request of its own, without a body yet: it's still in the reader, see http::Request::read_body
+    pub fn into_owned(self) -> http::Request {
+        http::Request::from(self)
+    }
+}
+
+// The lines of buf one by one, without the line break and the whitespace before it, like BufRead::read_line and trim_end
+struct Lines<'buf> {
+    buf: &'buf [u8],
+    // Where the next line starts
+    at: usize,
+}
+
+impl<'buf> Lines<'buf> {
+    // None when the rest of buf isn't a whole line
+    fn next_line(&mut self) -> Result<Option<&'buf str>, ParseError> {
+        let Some(end) = memchr(b'\n', &self.buf[self.at..]) else { return Ok(None) };
+        let line = &self.buf[self.at..self.at + end];
+        self.at += end + 1;
+        let line = str::from_utf8(line).map_err(|_| ParseError::Malformed("request head is not UTF-8"))?;
+        Ok(Some(line.trim_end()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrows_the_head_from_the_buffer() {
+        let buf = b"POST /submit?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 5\r\n\r\nhello".to_vec();
+        let (request, len) = Request::parse(&buf).unwrap().unwrap();
+        assert_eq!(("POST", "/submit?x=1", "HTTP/1.1"), (request.method, request.path, request.version));
+        assert_eq!(b"hello", &buf[len..]);
+        assert_eq!(Some("5"), request.header("Content-Length"));
+        // Nothing was copied, the slices point into buf
+        assert!(buf.as_ptr_range().contains(&request.header("host").unwrap().as_ptr()));
+
+        let owned = request.into_owned();
+        assert_eq!(owned, http::Request::read_head(&mut &buf[..]).unwrap());
+        drop(buf);
+        assert_eq!(Some("localhost"), owned.header("Host"));
+    }
+
+    #[test]
+    fn waits_for_the_whole_head() {
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        for end in 0..raw.len() {
+            assert_eq!(None, Request::parse(&raw[..end]).unwrap(), "{end} bytes");
+        }
+        assert_eq!(raw.len(), Request::parse(raw).unwrap().unwrap().1);
+
+        // A line that's complete is checked right away
+        assert!(matches!(Request::parse(b"GET / FTP/1.1\r\nHo"), Err(ParseError::Malformed("invalid request line"))));
+        assert!(matches!(Request::parse(b"GET / HTTP/1.1\r\n\xff: x\r\n"), Err(ParseError::Malformed(_))));
+        let crowded = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: 1\r\n".repeat(MAX_HEADERS + 1));
+        assert!(matches!(Request::parse(crowded.as_bytes()), Err(ParseError::Malformed("too many headers"))));
+    }
+}
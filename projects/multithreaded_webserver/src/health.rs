@@ -0,0 +1,395 @@
+// Health Checks
+
+// Whatever runs the server (a load balancer, Kubernetes, a shell script with curl) needs to ask it how it's doing. There are two
+// questions, and they have different answers:
+    // 1. Is it alive? /healthz. A server that isn't is stuck for good and should be restarted. Only the liveness probes count,
+    //    the ones about the process itself, like whether the ThreadPool still runs jobs.
+    // 2. Is it ready for requests? /readyz. A server whose database is down is alive, and restarting it won't bring the database
+    //    back, but it shouldn't be sent any traffic until it is. Every probe counts, the readiness ones too.
+// The subsystems register a probe each: a name and a closure that says Ok(()) or what's wrong. Health runs them all on a pool
+// of its own, at once, so a check takes as long as the slowest probe instead of all of them added up. Each probe has a timeout,
+// and one that doesn't answer in time counts as down: a health check that hangs is worse than one that fails.
+// A probe that never returns keeps its worker, so one that is still running from the check before isn't started again, it counts
+// as timed out right away. Otherwise a hanging dependency would take a worker with every check, until there were none.
+// The timeouts are on the Clock given to Health::clock, and the reachable probe connects over any Network (src/net.rs), so the
+// tests on the simulated network (src/sim.rs) see a probe time out without waiting for it.
+
+// The answer is 200 or 503, with every probe in JSON, for the humans looking at it:
+
+    // {"status":"down","checks":{"pool":{"status":"up","millis":0},"kvstore":{"status":"down","millis":3,"error":"refused"}}}
+
+use std::{
+    fmt,
+    io::{self, Write},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::clock::{Clock, SystemClock};
+
+use json::{JsonWriter, ToJson};
+
+use crate::{
+    connection_pool::{Connection, Pool},
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    net::{Network, Tcp},
+    ThreadPool,
+};
+
+type ProbeFn = dyn Fn() -> Result<(), String> + Send + Sync;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Liveness,
+    Readiness,
+}
+
+struct Probe {
+    name: String,
+    kind: Kind,
+    timeout: Duration,
+    check: Arc<ProbeFn>,
+    // Set while the probe runs on the pool
+    running: Arc<AtomicBool>,
+}
+
+impl Probe {
+    fn timed_out(&self, took: Duration) -> Check {
+        Check { name: self.name.clone(), status: Status::TimedOut(self.timeout), took }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Up,
+    Down(String),
+    TimedOut(Duration),
+}
+
+impl Status {
+    pub fn is_up(&self) -> bool {
+        *self == Status::Up
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Up => write!(f, "up"),
+            Status::Down(error) => write!(f, "down: {error}"),
+            Status::TimedOut(timeout) => write!(f, "no answer within {timeout:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub took: Duration,
+}
+
+// The checks in the order the probes were registered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn is_up(&self) -> bool {
+        self.checks.iter().all(|check| check.status.is_up())
+    }
+
+    pub fn to_json(&self) -> String {
+        json::to_string(self)
+    }
+
+    pub fn response(&self) -> Response {
+        let status = if self.is_up() { 200 } else { 503 };
+        Response::new(status, self.to_json().into_bytes())
+            .with_header("Content-Type", "application/json")
+            .with_header("Cache-Control", "no-store")
+    }
+}
+
+impl ToJson for Report {
+    fn to_json<W: Write>(&self, json: &mut JsonWriter<W>) -> io::Result<()> {
+        json.begin_object()?;
+        json.field("status", if self.is_up() { "up" } else { "down" })?;
+        json.key("checks")?;
+        json.begin_object()?;
+        for check in &self.checks {
+            let (status, error) = match &check.status {
+                Status::Up => ("up", None),
+                Status::Down(error) => ("down", Some(error.clone())),
+                Status::TimedOut(_) => ("down", Some(check.status.to_string())),
+            };
+            json.key(&check.name)?;
+            json.begin_object()?;
+            json.field("status", status)?;
+            json.key("millis")?;
+            json.u64(check.took.as_millis() as u64)?;
+            // Only a check that failed has an error, an up one has no "error":null
+            if let Some(error) = error {
+                json.field("error", &error)?;
+            }
+            json.end_object()?;
+        }
+        json.end_object()?;
+        json.end_object()
+    }
+}
+
+pub struct Health {
+    probes: Vec<Probe>,
+    pool: ThreadPool,
+    timeout: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::new(4)
+    }
+}
+
+impl Health {
+    // threads is how many probes can run at once, the rest wait for a worker, and their timeout starts when they're queued
+    pub fn new(threads: usize) -> Health {
+        Health {
+            probes: Vec::new(),
+            pool: ThreadPool::new(threads),
+            timeout: Duration::from_secs(2),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Health {
+        self.clock = clock;
+        self
+    }
+
+    // The timeout of the probes registered from now on
+    pub fn timeout(mut self, timeout: Duration) -> Health {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn probe<F>(mut self, name: &str, kind: Kind, check: F) -> Health
+    where
+        F: Fn() -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.probes.push(Probe {
+            name: name.to_string(),
+            kind,
+            timeout: self.timeout,
+            check: Arc::new(check),
+            running: Arc::new(AtomicBool::new(false)),
+        });
+        self
+    }
+
+    pub fn liveness<F>(self, name: &str, check: F) -> Health
+    where
+        F: Fn() -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.probe(name, Kind::Liveness, check)
+    }
+
+    pub fn readiness<F>(self, name: &str, check: F) -> Health
+    where
+        F: Fn() -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.probe(name, Kind::Readiness, check)
+    }
+
+    // Runs the probes of the kinds given, all at once, and waits for each until its timeout
+    pub fn check(&self, kinds: &[Kind]) -> Report {
+        let (sender, receiver) = mpsc::channel();
+        let mut checks: Vec<Option<Check>> = Vec::new();
+        let mut deadlines = Vec::new();
+        let now = self.clock.now();
+        for (index, probe) in self.probes.iter().filter(|probe| kinds.contains(&probe.kind)).enumerate() {
+            deadlines.push((index, now + probe.timeout, probe));
+            if probe.running.swap(true, Ordering::AcqRel) {
+                checks.push(Some(probe.timed_out(Duration::ZERO)));
+                continue;
+            }
+            checks.push(None);
+            let (check, running, sender) = (Arc::clone(&probe.check), Arc::clone(&probe.running), sender.clone());
+            let clock = Arc::clone(&self.clock);
+            self.pool.execute(move || {
+                let started = clock.now();
+                // A probe that panics is a probe that failed, and mustn't take the worker down with it
+                let status = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(&*check)) {
+                    Ok(Ok(())) => Status::Up,
+                    Ok(Err(error)) => Status::Down(error),
+                    Err(_) => Status::Down(String::from("the probe panicked")),
+                };
+                running.store(false, Ordering::Release);
+                let _ = sender.send((index, status, clock.now().saturating_duration_since(started)));
+            });
+        }
+        drop(sender);
+
+        // The answers come in any order, so they're waited for until the latest deadline,
+        // and one that came after its own probe's deadline counts as timed out all the same
+        let latest = deadlines.iter().map(|(_, deadline, _)| *deadline).max();
+        while checks.iter().any(Option::is_none) {
+            let Some(latest) = latest else { break };
+            let slice = self.clock.wait_slice(latest);
+            if slice.is_zero() {
+                break;
+            }
+            match receiver.recv_timeout(slice) {
+                Ok((index, status, took)) => {
+                    let (_, deadline, probe) = deadlines[index];
+                    let status = if self.clock.now() >= deadline { Status::TimedOut(probe.timeout) } else { status };
+                    checks[index] = Some(Check { name: probe.name.clone(), status, took });
+                }
+                // Only a slice of the wait, the clock says whether it's over
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        let checks = checks
+            .into_iter()
+            .zip(&deadlines)
+            .map(|(check, (_, _, probe))| check.unwrap_or_else(|| probe.timed_out(probe.timeout)))
+            .collect();
+        Report { checks }
+    }
+}
+
+impl Middleware for Health {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return next.run(request);
+        }
+        match request.path.as_str() {
+            "/healthz" => self.check(&[Kind::Liveness]).response(),
+            "/readyz" => self.check(&[Kind::Liveness, Kind::Readiness]).response(),
+            _ => next.run(request),
+        }
+    }
+}
+
+// Probes for the subsystems
+
+// The pool runs jobs if a job sent to it runs. When every worker is stuck, it never does, and the timeout says so
+pub fn thread_pool(pool: Arc<ThreadPool>) -> impl Fn() -> Result<(), String> + Send + Sync {
+    move || {
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || {
+            let _ = sender.send(());
+        });
+        receiver.recv().map_err(|_| String::from("the pool dropped the job"))
+    }
+}
+
+// Something listens at the address, a kvstore server or the upstream of a proxy that isn't pooled
+pub fn tcp(addr: SocketAddr, timeout: Duration) -> impl Fn() -> Result<(), String> + Send + Sync {
+    reachable(Arc::new(Tcp), addr, timeout)
+}
+
+// The same over any Network
+pub fn reachable(
+    network: Arc<dyn Network>,
+    addr: SocketAddr,
+    timeout: Duration,
+) -> impl Fn() -> Result<(), String> + Send + Sync {
+    move || network.connect(addr, Some(timeout)).map(drop).map_err(|e| format!("{addr}: {e}"))
+}
+
+// A connection can be checked out of the pool, which opens one when none is idle (src/connection_pool.rs)
+pub fn connection_pool<T: Connection>(pool: Arc<Pool<T>>) -> impl Fn() -> Result<(), String> + Send + Sync {
+    move || pool.checkout().map(drop).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+    use std::{net::TcpListener, thread, time::Instant};
+
+    fn get(app: &Chain, path: &str) -> Response {
+        app.handle(&mut Request::read_from(&mut format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes()).unwrap())
+    }
+
+    fn body(response: &Response) -> String {
+        match &response.body {
+            crate::http::Body::Bytes(bytes) => String::from_utf8(bytes.clone()).unwrap(),
+            _ => panic!("not a body of bytes"),
+        }
+    }
+
+    #[test]
+    fn liveness_and_readiness_are_separate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let health = Health::new(2)
+            .liveness("pool", thread_pool(Arc::new(ThreadPool::new(1))))
+            .readiness("kvstore", tcp(listener.local_addr().unwrap(), Duration::from_secs(1)))
+            .readiness("upstream", || Err(String::from("connection \"refused\"")));
+        let app = Chain::new(|_: &mut Request| Response::text(200, "app")).with(health);
+
+        let response = get(&app, "/healthz");
+        assert_eq!(200, response.status);
+        assert_eq!(Some("application/json"), response.header("Content-Type"));
+        let text = body(&response);
+        assert!(text.starts_with(r#"{"status":"up","checks":{"pool":{"status":"up","millis":"#), "{text}");
+        assert!(text.ends_with("}}}"), "{text}");
+
+        let response = get(&app, "/readyz");
+        assert_eq!(503, response.status);
+        let text = body(&response);
+        assert!(text.starts_with(r#"{"status":"down","checks":{"pool":{"status":"up""#), "{text}");
+        assert!(text.contains(r#""kvstore":{"status":"up""#), "{text}");
+        assert!(text.ends_with(r#""error":"connection \"refused\""}}}"#), "{text}");
+
+        assert_eq!("app", body(&get(&app, "/ready")));
+    }
+
+    #[test]
+    fn probes_that_hang_time_out() {
+        let release = Arc::new(AtomicBool::new(false));
+        let waiting = Arc::clone(&release);
+        let health = Health::new(2)
+            .timeout(Duration::from_millis(100))
+            .readiness("stuck", move || {
+                while !waiting.load(Ordering::Acquire) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Ok(())
+            })
+            .readiness("panics", || panic!("probe bug"))
+            .liveness("fine", || Ok(()));
+
+        let started = Instant::now();
+        let report = health.check(&[Kind::Liveness, Kind::Readiness]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        let statuses: Vec<_> = report.checks.iter().map(|check| (check.name.as_str(), check.status.clone())).collect();
+        assert_eq!(
+            vec![
+                ("stuck", Status::TimedOut(Duration::from_millis(100))),
+                ("panics", Status::Down(String::from("the probe panicked"))),
+                ("fine", Status::Up),
+            ],
+            statuses
+        );
+
+        // Still stuck: not started on a second worker, and the other probes still get one
+        let report = health.check(&[Kind::Liveness, Kind::Readiness]);
+        assert_eq!(Status::TimedOut(Duration::from_millis(100)), report.checks[0].status);
+        assert_eq!(Status::Up, report.checks[2].status);
+
+        release.store(true, Ordering::Release);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(Status::Up, health.check(&[Kind::Readiness]).checks[0].status);
+    }
+}
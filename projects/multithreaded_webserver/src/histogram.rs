@@ -0,0 +1,307 @@
+// A Latency Histogram
+
+// An average hides the slow requests: if 99 requests take 1 ms and one takes 2 s, the average is 21 ms, and nobody's request took 21 ms.
+// Percentiles tell the real story: p50 is what a typical request sees, p99 is what the slowest one in a hundred sees.
+// Computing an exact percentile means keeping every measurement and sorting them, which a server can't afford for every request.
+// A histogram only counts how many values fell into each bucket, and answers percentiles from the counts.
+
+// The buckets are log-linear, like HdrHistogram:
+    // 1. Values below 32 get a bucket each, so they are exact.
+    // 2. Each power of two above that, [32, 64), [64, 128), [128, 256)..., is split into 32 equal buckets.
+    //    A bucket is at most 1/32 of the values in it wide, so a reported value is never more than ~3% above the real one,
+    //    whether it's 40 microseconds or 40 seconds. All of u64 fits in 1920 buckets.
+
+// Recording has to be cheap and can happen on every worker thread at the same time. The counters are atomics,
+// and they are split into shards: each thread records into its own shard, so threads don't fight over the same cache lines.
+// Reading (percentile, count...) adds up the shards, which is slower, but it only happens when someone asks for /metrics.
+// The counters of a shard are separate atomics, so a read that races with a record can find the value in its bucket before
+// the count, min and max have caught up with it. percentile() goes by the buckets alone for that reason.
+// The sum saturates at u64::MAX instead of wrapping around, which is 584 years of nanoseconds: past that the mean is too low,
+// but never nonsense.
+
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+const BUCKETS: usize = SUB_BUCKETS + (64 - SUB_BITS as usize) * SUB_BUCKETS;
+const SHARDS: usize = 8;
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    // Position of the highest set bit, at least SUB_BITS here
+    let magnitude = 63 - value.leading_zeros();
+    let shift = magnitude - SUB_BITS;
+    let mantissa = (value >> shift) as usize - SUB_BUCKETS;
+    SUB_BUCKETS + shift as usize * SUB_BUCKETS + mantissa
+}
+
+// The largest value that lands in the bucket
+fn bucket_high(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = (index - SUB_BUCKETS) / SUB_BUCKETS;
+    let mantissa = (index - SUB_BUCKETS) % SUB_BUCKETS;
+    let low = ((SUB_BUCKETS + mantissa) as u64) << shift;
+    low + ((1u64 << shift) - 1)
+}
+
+struct Shard {
+    counts: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Shard {
+    fn new() -> Shard {
+        Shard {
+            counts: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Threads are given shards round robin the first time they record
+    static SHARD: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// fetch_add would wrap around, and fetch_update always gets Some from the closure, so it can't fail
+fn saturating_add(atomic: &AtomicU64, amount: u64) {
+    let _ = atomic.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| Some(sum.saturating_add(amount)));
+}
+
+fn this_threads_shard() -> usize {
+    SHARD.with(|shard| match shard.get() {
+        Some(shard) => shard,
+        None => {
+            let next = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+            shard.set(Some(next));
+            next
+        }
+    })
+}
+
+pub struct Histogram {
+    shards: Vec<Shard>,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram { shards: (0..SHARDS).map(|_| Shard::new()).collect() }
+    }
+
+    pub fn record(&self, value: u64) {
+        self.record_n(value, 1);
+    }
+
+    pub fn record_n(&self, value: u64, n: u64) {
+        let shard = &self.shards[this_threads_shard()];
+        shard.counts[bucket_index(value)].fetch_add(n, Ordering::Relaxed);
+        shard.count.fetch_add(n, Ordering::Relaxed);
+        saturating_add(&shard.sum, value.saturating_mul(n));
+        shard.min.fetch_min(value, Ordering::Relaxed);
+        shard.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    // Adds everything recorded in other to this histogram, for example to combine the histograms of several servers
+    pub fn merge(&self, other: &Histogram) {
+        let target = &self.shards[this_threads_shard()];
+        for shard in &other.shards {
+            for (index, count) in shard.counts.iter().enumerate() {
+                let count = count.load(Ordering::Relaxed);
+                if count > 0 {
+                    target.counts[index].fetch_add(count, Ordering::Relaxed);
+                }
+            }
+            target.count.fetch_add(shard.count.load(Ordering::Relaxed), Ordering::Relaxed);
+            saturating_add(&target.sum, shard.sum.load(Ordering::Relaxed));
+            target.min.fetch_min(shard.min.load(Ordering::Relaxed), Ordering::Relaxed);
+            target.max.fetch_max(shard.max.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.count.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        if self.count() == 0 {
+            return None;
+        }
+        self.shards.iter().map(|shard| shard.min.load(Ordering::Relaxed)).min()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        if self.count() == 0 {
+            return None;
+        }
+        self.shards.iter().map(|shard| shard.max.load(Ordering::Relaxed)).max()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        let count = self.count();
+        let sum = self.shards.iter().fold(0u64, |sum, shard| sum.saturating_add(shard.sum.load(Ordering::Relaxed)));
+        if count == 0 {
+            None
+        } else {
+            Some(sum as f64 / count as f64)
+        }
+    }
+
+    // The value that p percent of the recorded values are at or below (nearest rank), so percentile(50.0) is the median.
+    // It's the top of the bucket the value fell into, but never above the largest value actually recorded. That one is only
+    // used when it's in the highest bucket that has any values: a max that hasn't caught up yet isn't a bound at all.
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        assert!((0.0..=100.0).contains(&p), "a percentile is between 0 and 100");
+        let counts: Vec<u64> = (0..BUCKETS)
+            .map(|index| self.shards.iter().map(|shard| shard.counts[index].load(Ordering::Relaxed)).sum())
+            .collect();
+        let total: u64 = counts.iter().sum();
+        let highest = counts.iter().rposition(|&count| count > 0)?;
+        let max = self.shards.iter().map(|shard| shard.max.load(Ordering::Relaxed)).max().unwrap_or(0);
+        let ceiling = match bucket_index(max) == highest {
+            true => max,
+            false => bucket_high(highest),
+        };
+
+        let rank = ((p / 100.0 * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(bucket_high(index).min(ceiling));
+            }
+        }
+        unreachable!("the ranks add up to the total")
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    // A small xorshift generator, so the test data is random-looking but the same on every run
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    fn oracle(sorted: &[u64], p: f64) -> u64 {
+        let rank = ((p / 100.0 * sorted.len() as f64).ceil() as usize).max(1);
+        sorted[rank - 1]
+    }
+
+    #[test]
+    fn buckets_cover_every_value_in_order() {
+        for value in (0..5000).chain([u64::MAX / 3, u64::MAX - 1, u64::MAX]) {
+            let index = bucket_index(value);
+            assert!(index < BUCKETS);
+            assert!(value <= bucket_high(index), "{value} above its bucket");
+            assert!(index == 0 || value > bucket_high(index - 1), "{value} should be in an earlier bucket");
+        }
+        assert_eq!(BUCKETS - 1, bucket_index(u64::MAX));
+    }
+
+    #[test]
+    fn percentiles_agree_with_a_sorted_vector() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        let histogram = Histogram::new();
+        // Latencies spread over several orders of magnitude, from microseconds to seconds
+        let mut values: Vec<u64> = (0..20_000).map(|_| rng.next() % (1 << (rng.next() % 24))).collect();
+        for &value in &values {
+            histogram.record(value);
+        }
+        values.sort_unstable();
+
+        for p in [0.0, 0.1, 1.0, 25.0, 50.0, 90.0, 95.0, 99.0, 99.9, 100.0] {
+            let exact = oracle(&values, p);
+            let reported = histogram.percentile(p).unwrap();
+            assert!(reported >= exact, "p{p}: {reported} < {exact}");
+            assert!(reported - exact <= exact / SUB_BUCKETS as u64, "p{p}: {reported} too far above {exact}");
+        }
+        assert_eq!(Some(values[0]), histogram.min());
+        assert_eq!(Some(*values.last().unwrap()), histogram.max());
+        assert_eq!(Some(*values.last().unwrap()), histogram.percentile(100.0));
+    }
+
+    #[test]
+    fn small_values_are_exact() {
+        let histogram = Histogram::new();
+        for value in 1..=10 {
+            histogram.record(value);
+        }
+        assert_eq!(Some(5), histogram.percentile(50.0));
+        assert_eq!(Some(10), histogram.percentile(99.0));
+        assert_eq!(Some(5.5), histogram.mean());
+        assert_eq!(None, Histogram::new().percentile(50.0));
+        assert_eq!(None, Histogram::new().min());
+    }
+
+    #[test]
+    fn a_read_in_the_middle_of_a_record_and_huge_sums() {
+        // A value that is in its bucket, but not in the count or the max yet
+        let histogram = Histogram::new();
+        histogram.record(10);
+        histogram.shards[0].counts[bucket_index(1000)].fetch_add(1, Ordering::Relaxed);
+        assert_eq!(Some(bucket_high(bucket_index(1000))), histogram.percentile(100.0));
+        assert_eq!(Some(10), histogram.percentile(50.0));
+
+        // Three halves of u64::MAX add up to more than there is, and so does merging them twice
+        let (histogram, other) = (Histogram::new(), Histogram::new());
+        other.record_n(u64::MAX / 2, 3);
+        histogram.merge(&other);
+        histogram.merge(&other);
+        assert_eq!(Some(u64::MAX as f64 / 6.0), histogram.mean());
+    }
+
+    #[test]
+    fn threads_and_merging() {
+        let histogram = Arc::new(Histogram::new());
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let histogram = Arc::clone(&histogram);
+                thread::spawn(move || (0..1000).for_each(|i| histogram.record(t * 1000 + i)))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(4000, histogram.count());
+        assert_eq!(Some(3999), histogram.max());
+
+        let other = Histogram::new();
+        other.record_n(1_000_000, 4000);
+        histogram.merge(&other);
+        assert_eq!(8000, histogram.count());
+        // The median is the largest of the first 4000 values, 3999, reported within a bucket's width
+        let median = histogram.percentile(50.0).unwrap();
+        assert!((3999..=3999 + 3999 / 32).contains(&median), "{median}");
+        assert!(histogram.percentile(50.1).unwrap() >= 1_000_000);
+    }
+}
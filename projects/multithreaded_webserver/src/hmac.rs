@@ -0,0 +1,81 @@
+// HMAC: Signing Values with a Secret Key
+
+// Anything we give to the client (like a session ID in a cookie) can be changed by the client. To notice tampering, we attach a signature:
+// a hash of the value mixed with a secret key that only the server knows. Without the key, nobody can compute a valid signature for a different value.
+
+// Simply hashing key + value is not safe with hashes like SHA-1 (length extension attacks), so HMAC (RFC 2104) hashes twice with two derived keys:
+    // HMAC(key, message) = H((key ^ opad) + H((key ^ ipad) + message))
+// SHA-1 has known collision attacks, but those don't affect HMAC-SHA1, which is still considered a secure MAC.
+
+use crate::{encoding::hex_encode, sha1::sha1};
+
+const BLOCK_SIZE: usize = 64;
+
+pub fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    // Keys longer than a block are hashed first, shorter ones are padded with zeros
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..20].copy_from_slice(&sha1(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Vec::with_capacity(BLOCK_SIZE + message.len());
+    inner.extend(block.iter().map(|b| b ^ 0x36));
+    inner.extend_from_slice(message);
+
+    let mut outer = Vec::with_capacity(BLOCK_SIZE + 20);
+    outer.extend(block.iter().map(|b| b ^ 0x5c));
+    outer.extend_from_slice(&sha1(&inner));
+
+    sha1(&outer)
+}
+
+// Returns "value.signature", which is what goes into a cookie
+pub fn sign(key: &[u8], value: &str) -> String {
+    format!("{value}.{}", hex_encode(&hmac_sha1(key, value.as_bytes())))
+}
+
+// Returns the original value if the signature is valid
+pub fn verify<'a>(key: &[u8], signed: &'a str) -> Option<&'a str> {
+    let (value, signature) = signed.rsplit_once('.')?;
+    let expected = hex_encode(&hmac_sha1(key, value.as_bytes()));
+    constant_time_eq(expected.as_bytes(), signature.as_bytes()).then_some(value)
+}
+
+// A normal == stops at the first different byte, so an attacker timing the responses could guess a valid signature one byte at a time.
+// This compares every byte no matter what, so it always takes the same time for inputs of the same length.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc2202_test_vectors() {
+        assert_eq!("b617318655057264e28bc0b6fb378c8ef146be00", hex_encode(&hmac_sha1(&[0x0b; 20], b"Hi There")));
+        assert_eq!(
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79",
+            hex_encode(&hmac_sha1(b"Jefe", b"what do ya want for nothing?"))
+        );
+        // A key longer than the block size
+        assert_eq!(
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112",
+            hex_encode(&hmac_sha1(&[0xaa; 80], b"Test Using Larger Than Block-Size Key - Hash Key First"))
+        );
+    }
+
+    #[test]
+    fn signed_values_detect_tampering() {
+        let signed = sign(b"secret", "user=ferris");
+        assert_eq!(Some("user=ferris"), verify(b"secret", &signed));
+        assert_eq!(None, verify(b"other key", &signed));
+        assert_eq!(None, verify(b"secret", &signed.replace("ferris", "admin")));
+        assert_eq!(None, verify(b"secret", "no signature"));
+    }
+}
@@ -0,0 +1,647 @@
+// HTTP Requests and Responses
+
+// So far the handlers only looked at the request line and built the response with format!. That works for the book's two pages,
+// but as soon as we need headers (WebSockets, streaming, cookies) or request bodies, it pays to parse the request into a struct
+// and to build the response as a struct that knows how to write itself.
+
+// An HTTP/1.1 request looks like this:
+    // GET /index.html?lang=en HTTP/1.1       <- request line: method, target, version
+    // Host: 127.0.0.1:7878                   <- headers, one per line
+    // Content-Length: 5
+    //                                        <- an empty line ends the headers
+    // hello                                  <- the body, Content-Length bytes long
+
+// Reading the head is on the path of every request, so it doesn't allocate more than it has to. It's parsed where it was read,
+// as a head::Request that borrows from the buffer (src/head.rs), and only then copied into a Request. Header names and values
+// are SmallStrings, which keep the short ones inline and only go to the heap for the long ones, like a cookie. The names a
+// request nearly always has are interned, and those cost no more than a Symbol. testing/benches has the numbers ("headers").
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+    ops::Deref,
+    str,
+    sync::{Arc, LazyLock},
+};
+
+use common::bytesearch::find_header_end;
+use std_collections::{
+    hashing::FnvBuildHasher,
+    interner::{StringInterner, Symbol},
+    small_string::SmallString,
+};
+
+use crate::{
+    auth::Identity,
+    cookie::parse_cookie_header,
+    extensions::Extensions,
+    form::{FormData, FormError, FromForm},
+    head,
+    multipart::{self, Multipart, MultipartError, MultipartLimits},
+    net::Stream,
+    session::Session,
+    sse::{EventSender, EventStream},
+    url::{Url, UrlError},
+};
+
+// Bodies bigger than this are refused, otherwise a client could make us allocate whatever Content-Length it claims
+pub const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+// The same for the request line and the headers, which would otherwise be read for as long as the client sends them
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    // The client closed the connection before sending anything
+    Empty,
+    Malformed(&'static str),
+    BodyTooLarge(usize),
+    // The request line and the headers together are longer than the limit, which is the usize
+    HeadTooLarge(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "i/o error: {e}"),
+            ParseError::Empty => write!(f, "connection closed before a request was sent"),
+            ParseError::Malformed(msg) => write!(f, "malformed request: {msg}"),
+            ParseError::BodyTooLarge(len) => write!(f, "request body of {len} bytes is too large"),
+            ParseError::HeadTooLarge(limit) => write!(f, "request line and headers are longer than {limit} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+// Long enough for values like a Host, a Content-Type or a short Accept
+pub type HeaderValue = SmallString<48>;
+
+// The names that nearly every request has, lowercased. They're interned once (std_collections::interner), and a request
+// keeps a Symbol for them instead of their text. The interner is never added to after this, so a client can't grow it
+// by making up names: those are kept as their text.
+const COMMON_HEADERS: [&str; 32] = [
+    "accept", "accept-encoding", "accept-language", "authorization", "cache-control", "connection", "content-length",
+    "content-type", "cookie", "dnt", "host", "if-modified-since", "if-none-match", "origin", "pragma", "priority", "range",
+    "referer", "sec-fetch-dest", "sec-fetch-mode", "sec-fetch-site", "sec-fetch-user", "sec-websocket-key",
+    "sec-websocket-version", "te", "traceparent", "transfer-encoding", "upgrade", "upgrade-insecure-requests", "user-agent",
+    "x-forwarded-for", "x-request-id",
+];
+
+// The longest of them, a longer name can't be one
+const LONGEST_COMMON_HEADER: usize = 25;
+
+// Looked up for every header of every request. FNV is a lot less work than SipHash for names this short, and a table that's
+// never added to can't be flooded with names that collide
+static COMMON: LazyLock<StringInterner<FnvBuildHasher>> = LazyLock::new(|| COMMON_HEADERS.into_iter().collect());
+
+// A header name, compared case insensitively like HTTP says. One of the common names is shown lowercased, the way HTTP/2
+// sends every name, any other as it was sent.
+#[derive(Clone)]
+pub enum HeaderName {
+    Common(Symbol),
+    Other(SmallString<24>),
+}
+
+impl HeaderName {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HeaderName::Common(symbol) => COMMON.resolve(*symbol),
+            HeaderName::Other(name) => name,
+        }
+    }
+
+    pub fn is_common(&self) -> bool {
+        matches!(self, HeaderName::Common(_))
+    }
+
+    // Lowercased on the stack to look it up, so a common name costs no allocation at all
+    fn common(name: &str) -> Option<Symbol> {
+        let mut lower = [0; LONGEST_COMMON_HEADER];
+        let lower = lower.get_mut(..name.len())?;
+        lower.copy_from_slice(name.as_bytes());
+        lower.make_ascii_lowercase();
+        // Lowercasing ASCII leaves every other byte alone, so it's still UTF-8
+        COMMON.get(std::str::from_utf8(lower).ok()?)
+    }
+}
+
+impl From<&str> for HeaderName {
+    fn from(name: &str) -> Self {
+        match HeaderName::common(name) {
+            Some(symbol) => HeaderName::Common(symbol),
+            None => HeaderName::Other(SmallString::from(name)),
+        }
+    }
+}
+
+impl Deref for HeaderName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &HeaderName) -> bool {
+        match (self, other) {
+            (HeaderName::Common(a), HeaderName::Common(b)) => a == b,
+            // An Other is never a common name, From would have found it
+            (HeaderName::Common(_), HeaderName::Other(_)) | (HeaderName::Other(_), HeaderName::Common(_)) => false,
+            (HeaderName::Other(a), HeaderName::Other(b)) => a.eq_ignore_ascii_case(b),
+        }
+    }
+}
+
+impl Eq for HeaderName {}
+
+impl fmt::Display for HeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for HeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    // The request target as sent, including any query string
+    pub path: String,
+    pub version: String,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub body: Vec<u8>,
+    // What the middleware found out about the request, for the layers after it and the handler, see src/extensions.rs
+    extensions: Extensions,
+}
+
+// Two requests are equal when the same message was sent. What the middleware attached to them isn't compared:
+// the extensions can hold anything, and most of it can't be compared
+impl PartialEq for Request {
+    fn eq(&self, other: &Request) -> bool {
+        (&self.method, &self.path, &self.version, &self.headers, &self.body)
+            == (&other.method, &other.path, &other.version, &other.headers, &other.body)
+    }
+}
+
+impl Eq for Request {}
+
+// A request without a body yet, see head::Request::into_owned
+impl From<head::Request<'_>> for Request {
+    fn from(head: head::Request<'_>) -> Self {
+        Request {
+            method: head.method.to_string(),
+            path: head.path.to_string(),
+            version: head.version.to_string(),
+            headers: head.headers.iter().map(|&(name, value)| (HeaderName::from(name), HeaderValue::from(value))).collect(),
+            body: Vec::new(),
+            extensions: Extensions::new(),
+        }
+    }
+}
+
+impl Request {
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        let mut request = Request::read_head(reader)?;
+        request.read_body(reader)?;
+        Ok(request)
+    }
+
+    // Reads only the request line and the headers, and leaves the body in the reader.
+    // That way a handler can stream a big body (like a file upload, see src/multipart.rs) instead of holding all of it in memory.
+    pub fn read_head<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        Request::read_head_limited(reader, MAX_HEAD_SIZE)
+    }
+
+    // The server reads with the limits it was configured with (src/limits.rs), instead of the constants above
+    pub fn read_head_limited<R: BufRead>(reader: &mut R, max_head_size: usize) -> Result<Request, ParseError> {
+        // Usually the whole head came in one read, and is in the reader's buffer: it's parsed right there. When the empty line
+        // at its end isn't in the buffer yet, the head is read line by line below, without parsing the start of it twice
+        let buffered = reader.fill_buf()?;
+        let buffered = &buffered[..buffered.len().min(max_head_size)];
+        let complete = find_header_end(buffered).map_or(&buffered[..0], |end| &buffered[..end]);
+        // Owned before the buffer is touched again, the head borrows it
+        let parsed = head::Request::parse(complete)?.map(|(head, len)| (head.into_owned(), len));
+        if let Some((request, len)) = parsed {
+            reader.consume(len);
+            return Ok(request);
+        }
+
+        // Otherwise it's read line by line into a buffer of its own. Every line is read through a Take of what is left,
+        // so a line without an end can't grow past the limit either
+        let mut buf = Vec::new();
+        loop {
+            let left = max_head_size - buf.len();
+            if left == 0 {
+                return Err(ParseError::HeadTooLarge(max_head_size));
+            }
+            let start = buf.len();
+            if reader.by_ref().take(left as u64).read_until(b'\n', &mut buf)? == 0 {
+                return Err(match buf.is_empty() {
+                    true => ParseError::Empty,
+                    false => ParseError::Malformed("connection closed inside the headers"),
+                });
+            }
+            if buf.len() == max_head_size && !buf.ends_with(b"\n") {
+                return Err(ParseError::HeadTooLarge(max_head_size));
+            }
+            if start == 0 {
+                // A request line that's wrong is refused before reading on, a right one isn't a whole head
+                head::Request::parse(&buf)?;
+            } else if buf.ends_with(b"\n") && str::from_utf8(&buf[start..]).is_ok_and(|line| line.trim_end().is_empty()) {
+                // The empty line at the end, the same way head::Request::parse finds it. Without its line break the
+                // connection was closed, which the next read finds out
+                break;
+            }
+        }
+        let (head, _) = head::Request::parse(&buf)?.expect("a head that ends with an empty line is complete");
+        Ok(head.into_owned())
+    }
+
+    pub fn content_length(&self) -> Result<usize, ParseError> {
+        match self.header("Content-Length") {
+            Some(length) => length.parse().map_err(|_| ParseError::Malformed("invalid Content-Length")),
+            None => Ok(0),
+        }
+    }
+
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R) -> Result<(), ParseError> {
+        self.read_body_limited(reader, MAX_BODY_SIZE)
+    }
+
+    pub fn read_body_limited<R: BufRead>(&mut self, reader: &mut R, max_body_size: usize) -> Result<(), ParseError> {
+        let length = self.content_length()?;
+        if length > max_body_size {
+            return Err(ParseError::BodyTooLarge(length));
+        }
+        self.body = vec![0; length];
+        reader.read_exact(&mut self.body)?;
+        Ok(())
+    }
+
+    // Header names are case insensitive, so "content-length" finds "Content-Length". A common name is found by its Symbol
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = HeaderName::from(name);
+        self.headers.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        parse_cookie_header(self.header("Cookie")?)
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    // The full URL of the request. The request line only has the path and query, the host comes from the Host header.
+    // Use this to get at the decoded path segments, or to resolve a relative link against the page that was requested.
+    pub fn url(&self) -> Result<Url, UrlError> {
+        target_url(self.header("Host").unwrap_or("localhost"), &self.path)
+    }
+
+    // The path without the query string, which is what handlers usually match on
+    pub fn path_only(&self) -> &str {
+        self.path.split_once('?').map_or(&self.path, |(path, _)| path)
+    }
+
+    pub fn query(&self) -> Result<FormData, FormError> {
+        match self.path.split_once('?') {
+            Some((_, query)) => FormData::parse(query),
+            None => Ok(FormData::default()),
+        }
+    }
+
+    // Decodes a urlencoded request body into any FromForm type, usually a struct with #[derive(FromForm)]:
+    //     let login: LoginForm = req.form()?;
+    pub fn form<T: FromForm>(&self) -> Result<T, FormError> {
+        let content_type = self.header("Content-Type").unwrap_or("");
+        // Ignore parameters like "; charset=UTF-8"
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Err(FormError::UnsupportedContentType(content_type.to_string()));
+        }
+        let body = std::str::from_utf8(&self.body).map_err(|_| FormError::InvalidEncoding)?;
+        T::from_form(&FormData::parse(body)?)
+    }
+
+    // The parts of a multipart/form-data body that has already been read into memory, see src/multipart.rs
+    pub fn multipart(&self) -> Result<Multipart<&[u8]>, MultipartError> {
+        let boundary = multipart::boundary(self.header("Content-Type").unwrap_or(""))?;
+        Ok(Multipart::new(&self.body[..], &boundary, MultipartLimits::default()))
+    }
+
+    // The streaming version for a request read with read_head: the parts are parsed straight from the connection,
+    // so a large upload goes to disk without ever being in memory as a whole.
+    pub fn multipart_stream<R: Read>(&self, body: R, limits: MultipartLimits) -> Result<Multipart<io::Take<R>>, MultipartError> {
+        let boundary = multipart::boundary(self.header("Content-Type").unwrap_or(""))?;
+        let length = self
+            .content_length()
+            .map_err(|_| MultipartError::Malformed("invalid Content-Length"))?;
+        Ok(Multipart::new(body.take(length as u64), &boundary, limits))
+    }
+
+    // None unless the request went through SessionMiddleware
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    // The session SessionMiddleware loaded (src/session.rs)
+    pub fn session(&mut self) -> Option<&mut Session> {
+        self.extensions.get_mut()
+    }
+
+    // Who sent the request, when Auth is in the chain and the credentials checked out
+    pub fn identity(&self) -> Option<&Identity> {
+        self.extensions.get()
+    }
+
+    // The application's state, from ServerBuilder::with_state or Chain::with_state (src/middleware.rs). An Arc, so the handler
+    // can hold on to it while it borrows the request mutably
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.extensions.get::<Arc<T>>().cloned()
+    }
+}
+
+// The target is pasted after the host instead of joined to it: as a relative reference "//evil.example/" would replace the host
+pub(crate) fn target_url(host: &str, target: &str) -> Result<Url, UrlError> {
+    Url::parse(&format!("http://{host}{target}"))
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+// A response body is either bytes we already have, or a stream that keeps the connection open and sends data as it happens
+pub enum Body {
+    Bytes(Vec<u8>),
+    EventStream(EventStream),
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16, body: Vec<u8>) -> Response {
+        Response { status, headers: Vec::new(), body: Body::Bytes(body) }
+    }
+
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status, body.as_bytes().to_vec()).with_header("Content-Type", "text/plain; charset=utf-8")
+    }
+
+    pub fn html(status: u16, body: &str) -> Response {
+        Response::new(status, body.as_bytes().to_vec()).with_header("Content-Type", "text/html; charset=utf-8")
+    }
+
+    // A text/event-stream response, see src/sse.rs. `start` is called once the headers have been sent, with a sender
+    // that can be moved to other threads to push events for as long as the client stays connected.
+    pub fn event_stream<F>(start: F) -> Response
+    where
+        F: FnOnce(EventSender) + Send + 'static,
+    {
+        Response::from(EventStream::new(start))
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The status line and the headers, with the ones the body needs: its Content-Length, or what makes it an event stream
+    fn head(&mut self) -> Vec<u8> {
+        match &self.body {
+            Body::Bytes(body) => self.headers.push((String::from("Content-Length"), body.len().to_string())),
+            // No Content-Length: the body goes on until one side closes the connection
+            Body::EventStream(_) => {
+                for (name, value) in [
+                    ("Content-Type", "text/event-stream"),
+                    ("Cache-Control", "no-cache"),
+                    ("Connection", "keep-alive"),
+                ] {
+                    self.headers.push((name.to_string(), value.to_string()));
+                }
+            }
+        }
+        let mut head = Vec::new();
+        write_head(&mut head, self.status, &self.headers).expect("writing to a Vec can't fail");
+        head
+    }
+
+    // Writes the whole response. For an event stream this only returns once the stream is over.
+    pub fn write_to(mut self, stream: &mut dyn Stream) -> io::Result<()> {
+        stream.write_all(&self.head())?;
+        match self.body {
+            Body::Bytes(body) => stream.write_all(&body),
+            Body::EventStream(events) => events.serve(stream),
+        }
+    }
+
+    // The bytes write_to would send, without a connection to send them on. An event stream has no body yet, so only its head.
+    pub fn to_bytes(mut self) -> Vec<u8> {
+        let mut bytes = self.head();
+        if let Body::Bytes(body) = self.body {
+            bytes.extend_from_slice(&body);
+        }
+        bytes
+    }
+}
+
+fn write_head<W: Write>(writer: &mut W, status: u16, headers: &[(String, String)]) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {status} {}\r\n", reason_phrase(status));
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes())
+}
+
+impl From<EventStream> for Response {
+    fn from(events: EventStream) -> Response {
+        Response { status: 200, headers: Vec::new(), body: Body::EventStream(events) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line_headers_and_body() {
+        let raw = b"POST /submit?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 5\r\n\r\nhelloEXTRA";
+        let request = Request::read_from(&mut &raw[..]).unwrap();
+
+        assert_eq!("POST", request.method);
+        assert_eq!("/submit?x=1", request.path);
+        assert_eq!("HTTP/1.1", request.version);
+        assert_eq!(Some("localhost"), request.header("HOST"));
+        assert_eq!(None, request.cookie("session"));
+        assert_eq!(b"hello".to_vec(), request.body);
+    }
+
+    #[test]
+    fn common_header_names_are_interned() {
+        let raw = b"GET / HTTP/1.1\r\nHOST: localhost\r\nX-Trace: 7\r\nAccept-Encodingx: no\r\n\r\n";
+        let request = Request::read_from(&mut &raw[..]).unwrap();
+        let names: Vec<(&str, bool)> = request.headers.iter().map(|(name, _)| (name.as_str(), name.is_common())).collect();
+        assert_eq!(vec![("host", true), ("X-Trace", false), ("Accept-Encodingx", false)], names);
+        assert_eq!((Some("localhost"), Some("7")), (request.header("Host"), request.header("x-trace")));
+
+        assert_eq!(HeaderName::from("User-Agent"), HeaderName::from("user-agent"));
+        assert_ne!(HeaderName::from("User-Agent"), HeaderName::from("User-Agents"));
+        assert_eq!(COMMON_HEADERS.iter().map(|name| name.len()).max(), Some(LONGEST_COMMON_HEADER));
+    }
+
+    #[test]
+    fn request_urls_take_the_host_header() {
+        let raw = b"GET //evil.example/a%20b?x=1 HTTP/1.1\r\nHost: Example.com:7878\r\n\r\n";
+        let url = Request::read_from(&mut &raw[..]).unwrap().url().unwrap();
+        assert_eq!("http://example.com:7878//evil.example/a%20b?x=1", url.to_string());
+        assert_eq!(Some("example.com"), url.host());
+        assert_eq!(vec!["", "evil.example", "a b"], url.path_segments().unwrap());
+
+        let url = Request::read_from(&mut &b"GET /docs/ HTTP/1.1\r\n\r\n"[..]).unwrap().url().unwrap();
+        assert_eq!("http://localhost/docs/img/logo.png", url.join("img/logo.png").unwrap().to_string());
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let cases: [&[u8]; 6] = [
+            b"GET\r\n\r\n",
+            b"GET /a%zz HTTP/1.1\r\n\r\n",
+            b"GET /\x7f HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nNoColon\r\n\r\n",
+            b"GET / HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: x\r\n",
+        ];
+        for raw in cases {
+            assert!(matches!(Request::read_from(&mut &raw[..]), Err(ParseError::Malformed(_))));
+        }
+        assert!(matches!(Request::read_from(&mut &b""[..]), Err(ParseError::Empty)));
+
+        let huge = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        assert!(matches!(Request::read_from(&mut huge.as_bytes()), Err(ParseError::BodyTooLarge(_))));
+    }
+
+    #[test]
+    fn the_head_and_the_body_have_limits() {
+        let raw = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+        // A limit of exactly the length of the head is enough
+        let head = raw.len() - 5;
+        let mut request = Request::read_head_limited(&mut &raw[..], head).unwrap();
+        assert_eq!(Some("5"), request.header("Content-Length"));
+        assert!(matches!(Request::read_head_limited(&mut &raw[..], head - 1), Err(ParseError::HeadTooLarge(_))));
+        // Even when the line that is too long never ends
+        let endless = vec![b'a'; MAX_HEAD_SIZE * 2];
+        assert!(matches!(Request::read_head(&mut &endless[..]), Err(ParseError::HeadTooLarge(MAX_HEAD_SIZE))));
+
+        assert!(matches!(request.read_body_limited(&mut &raw[head..], 4), Err(ParseError::BodyTooLarge(5))));
+        request.read_body_limited(&mut &raw[head..], 5).unwrap();
+        assert_eq!(b"hello", &request.body[..]);
+    }
+
+    // A socket hands the request over in pieces, sometimes a byte at a time, and reads get interrupted. The result has to be the same.
+    #[test]
+    fn parses_requests_read_in_pieces() {
+        use std::io::BufReader;
+        use test_support::{FlakyReader, Script};
+
+        let raw = b"POST /submit HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\nhello world";
+        let expected = Request::read_from(&mut &raw[..]).unwrap();
+        for chunk in 1..=8 {
+            for every in [2, 3, 5] {
+                let flaky = FlakyReader::new(&raw[..]).max_chunk(chunk).interrupt_every(every);
+                // A small buffer, so that lines and the body are split across refills too
+                let request = Request::read_from(&mut BufReader::with_capacity(4, flaky)).unwrap();
+                assert_eq!(expected, request, "chunks of {chunk}, interrupted every {every} calls");
+            }
+        }
+        // The line break split between two reads
+        let flaky = FlakyReader::new(&raw[..]).script(Script::chunks([22, 1, 1]).interrupt());
+        assert_eq!(expected, Request::read_from(&mut BufReader::new(flaky)).unwrap());
+    }
+
+    // Wherever the connection dies, the result is an error, never a request with half a body
+    #[test]
+    fn connections_that_die_midway() {
+        use std::io::{BufReader, ErrorKind};
+        use test_support::FlakyReader;
+
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        for cut in 0..raw.len() {
+            let flaky = FlakyReader::new(&raw[..]).max_chunk(2).fail_after(cut, ErrorKind::ConnectionReset);
+            match Request::read_from(&mut BufReader::new(flaky)) {
+                Err(ParseError::Io(e)) => assert_eq!(ErrorKind::ConnectionReset, e.kind(), "cut after {cut} bytes"),
+                other => panic!("cut after {cut} bytes: {other:?}"),
+            }
+        }
+        // A peer that closes cleanly halfway through the body
+        let closed = &raw[..raw.len() - 1];
+        assert!(matches!(Request::read_from(&mut &closed[..]), Err(ParseError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn writes_the_head_through_short_writes() {
+        use test_support::FlakyWriter;
+
+        let headers = [(String::from("Content-Length"), String::from("0"))];
+        let mut writer = FlakyWriter::new(Vec::new()).max_chunk(3).interrupt_every(4);
+        write_head(&mut writer, 204, &headers).unwrap();
+        assert_eq!(&b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n"[..], writer.get_ref());
+    }
+
+    // The whole response as it goes out, compared with the golden files in tests/snapshots (see testing/test_support)
+    #[test]
+    fn renders_responses() {
+        let page = Response::html(404, "<h1>Not here</h1>\n").with_header("Set-Cookie", "seen=1; HttpOnly");
+        test_support::assert_snapshot!("http_html_response", String::from_utf8(page.to_bytes()).unwrap());
+
+        let events = Response::event_stream(|_| {}).with_header("X-Accel-Buffering", "no");
+        test_support::assert_snapshot!("http_event_stream_head", String::from_utf8(events.to_bytes()).unwrap());
+    }
+}
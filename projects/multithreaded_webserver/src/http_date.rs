@@ -0,0 +1,131 @@
+// Dates in HTTP Headers
+
+// Last-Modified, If-Modified-Since, Expires and Date all write a time the same way, always in GMT and to the second:
+
+    // Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT
+
+// SystemTime only knows seconds since 1970, so the calendar is worked out here: the days since 1970 become a year, a month
+// and a day with Howard Hinnant's days_from_civil and civil_from_days, which count in eras of 400 years and start the year
+// in March, so that the leap day is the last day of a year and needs no special case.
+// HTTP/1.0 had two other formats, which a server must still accept. Nobody has sent them in a long time, so parse() doesn't:
+// a date it can't read is ignored, as the RFC says for a malformed If-Modified-Since.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// The days since 1970-01-01 of a date, month and day counting from 1
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * march_month + 2) / 5 + 1;
+    let month = if march_month < 10 { march_month + 3 } else { march_month - 9 };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+// A time before 1970 is written as 1970, no file the server serves is that old
+pub fn format(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()) as i64;
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    // 1970-01-01 was a Thursday
+    let weekday = DAYS[(days + 4).rem_euclid(7) as usize];
+    let month = MONTHS[month as usize - 1];
+    format!("{weekday}, {day:02} {month} {year} {:02}:{:02}:{:02} GMT", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// The time of an access log line in Common Log Format, which has its own way of writing it: 06/Nov/1994:08:49:37 +0000.
+// The offset is always +0000 here, the server doesn't know about time zones
+pub fn common_log(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()) as i64;
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    let month = MONTHS[month as usize - 1];
+    format!("{day:02}/{month}/{year}:{:02}:{:02}:{:02} +0000", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// The date has to be one the calendar has, and fall on the weekday it says: 31 Feb, or a Monday that was a Sunday, is a
+// date someone got wrong, and which part of it is right can't be told
+pub fn parse(text: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = text.split_whitespace().collect();
+    let [weekday, day, month, year, time, "GMT"] = parts[..] else { return None };
+    let weekday = weekday.strip_suffix(',')?;
+    let number = |text: &str, digits: usize| (text.len() == digits).then(|| text.parse::<i64>().ok()).flatten();
+    let (day, year) = (number(day, 2)?, number(year, 4)?);
+    let month = MONTHS.iter().position(|&name| name == month)? as i64 + 1;
+    let mut clock = time.split(':');
+    let (hours, minutes, seconds) = (number(clock.next()?, 2)?, number(clock.next()?, 2)?, number(clock.next()?, 2)?);
+    if clock.next().is_some() || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    // A day past the end of the month counts on into the next one, and comes back as another date
+    if civil_from_days(days) != (year, month, day) || DAYS[(days + 4).rem_euclid(7) as usize] != weekday {
+        return None;
+    }
+    let secs = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_the_rfc_example() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format(time));
+        assert_eq!(Some(time), parse("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", format(UNIX_EPOCH));
+        assert_eq!("06/Nov/1994:08:49:37 +0000", common_log(time));
+        // A leap day, and the day after the end of a century that wasn't a leap year
+        assert_eq!("Tue, 29 Feb 2000 12:00:00 GMT", format(parse("Tue, 29 Feb 2000 12:00:00 GMT").unwrap()));
+        assert_eq!("Mon, 01 Mar 2100 00:00:00 GMT", format(UNIX_EPOCH + Duration::from_secs(4_107_542_400)));
+    }
+
+    #[test]
+    fn a_week_at_a_time_round_trips() {
+        for days in (0..60_000).step_by(7) {
+            let time = UNIX_EPOCH + Duration::from_secs(days * 86_400 + 3661);
+            assert_eq!(Some(time), parse(&format(time)), "{}", format(time));
+        }
+    }
+
+    #[test]
+    fn refuses_what_it_cant_read() {
+        for text in [
+            "",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 PST",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 25:49:37 GMT",
+            "Sun, 06 Nox 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+            // Days the months don't have, 2100 isn't a leap year
+            "Sat, 31 Feb 2024 00:00:00 GMT",
+            "Thu, 31 Apr 2024 00:00:00 GMT",
+            "Mon, 29 Feb 2100 00:00:00 GMT",
+            // The wrong weekday, and one that isn't one
+            "Mon, 06 Nov 1994 08:49:37 GMT",
+            "Sat, 29 Feb 2000 12:00:00 GMT",
+            "Xyz, 06 Nov 1994 08:49:37 GMT",
+        ] {
+            assert_eq!(None, parse(text), "{text}");
+        }
+    }
+}
@@ -0,0 +1,282 @@
+// Unique IDs
+
+// Two kinds of identifiers live in this module:
+    // 1. IdGenerator hands out u64 IDs that always increase, even when called from many threads at once. Each ID carries the time it was made in its top bits.
+    // 2. Uuid is a 128 bit ID that is unique without any coordination: the top 48 bits are a millisecond timestamp and the rest are random, much like a UUIDv7.
+// Both sort by creation time, which makes them nice for log lines and record keys.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use std_collections::rand_lite::random_u64;
+
+use crate::{
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+};
+
+// The low SEQUENCE_BITS bits of an ID are a sequence number, which allows ~1 million IDs per millisecond before the timestamp part has to run ahead of the clock.
+const SEQUENCE_BITS: u32 = 20;
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub struct IdGenerator {
+    last: AtomicU64,
+}
+
+impl IdGenerator {
+    // A const fn, so a generator can be stored in a static and shared by every thread without an Arc.
+    pub const fn new() -> IdGenerator {
+        IdGenerator { last: AtomicU64::new(0) }
+    }
+
+    pub fn next_id(&self) -> u64 {
+        let floor = now_millis() << SEQUENCE_BITS;
+        let mut last = self.last.load(Ordering::Relaxed);
+
+        // The classic compare-and-swap loop: compute the ID we want based on the last one we saw, and only publish it if nobody else got there first.
+        // If another thread won the race, compare_exchange_weak hands us the newer value and we try again.
+        // Taking the max with last + 1 keeps the IDs increasing even if the system clock jumps backwards.
+        loop {
+            let next = floor.max(last + 1);
+            match self
+                .last
+                .compare_exchange_weak(last, next, Ordering::AcqRel, Ordering::Relaxed)
+            {
+                Ok(_) => return next,
+                Err(actual) => last = actual,
+            }
+        }
+    }
+
+    // Recover the millisecond timestamp an ID was generated at
+    pub fn timestamp_millis(id: u64) -> u64 {
+        id >> SEQUENCE_BITS
+    }
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Random bits without the rand crate
+
+// The random part comes from the thread-local generator in collections/std_collections/src/rand_lite.rs, seeded from the OS once per thread.
+// It is not a cryptographic RNG, but it is plenty for unique IDs.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid(u128);
+
+impl Uuid {
+    pub fn new() -> Uuid {
+        let timestamp = (now_millis() as u128 & 0xffff_ffff_ffff) << 80;
+        let random = ((random_u64() as u128) << 64 | random_u64() as u128) & ((1 << 80) - 1);
+        Uuid(timestamp | random)
+    }
+
+    pub fn from_u128(value: u128) -> Uuid {
+        Uuid(value)
+    }
+
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+
+    pub fn timestamp_millis(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+}
+
+impl Default for Uuid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Display uses the familiar 8-4-4-4-12 grouping of hex digits.
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = format!("{:032x}", self.0);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseUuidError {
+    InvalidLength(usize),
+    InvalidCharacter(char),
+    MisplacedHyphen(usize),
+}
+
+impl fmt::Display for ParseUuidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseUuidError::InvalidLength(n) => write!(f, "expected 32 hex digits, found {n}"),
+            ParseUuidError::InvalidCharacter(c) => write!(f, "invalid character {c:?}"),
+            ParseUuidError::MisplacedHyphen(i) => write!(f, "unexpected hyphen at index {i}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseUuidError {}
+
+// FromStr accepts both the hyphenated form produced by Display and the plain 32 digit form.
+impl FromStr for Uuid {
+    type Err = ParseUuidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hyphenated = s.len() == 36;
+        let mut value: u128 = 0;
+        let mut digits = 0;
+
+        for (i, c) in s.chars().enumerate() {
+            if c == '-' {
+                if !hyphenated || ![8, 13, 18, 23].contains(&i) {
+                    return Err(ParseUuidError::MisplacedHyphen(i));
+                }
+                continue;
+            }
+            if hyphenated && [8, 13, 18, 23].contains(&i) {
+                return Err(ParseUuidError::InvalidCharacter(c));
+            }
+            let digit = c.to_digit(16).ok_or(ParseUuidError::InvalidCharacter(c))?;
+            digits += 1;
+            if digits > 32 {
+                return Err(ParseUuidError::InvalidLength(digits));
+            }
+            value = value << 4 | digit as u128;
+        }
+
+        if digits != 32 {
+            return Err(ParseUuidError::InvalidLength(digits));
+        }
+        Ok(Uuid(value))
+    }
+}
+
+// Request IDs
+
+// A Uuid for every request, so that the log lines of one request can be found among those of all the others.
+// RequestIds keeps the X-Request-Id a proxy in front of the server already gave the request, when it's a Uuid, and makes one up
+// otherwise. It goes in the request's extensions (src/extensions.rs) for the handler, and back to the client in the response.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestId(pub Uuid);
+
+pub struct RequestIds;
+
+impl Middleware for RequestIds {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let id = request.header("X-Request-Id").and_then(|id| id.trim().parse().ok()).unwrap_or_default();
+        request.extensions_mut().insert(RequestId(id));
+        next.run(request).with_header("X-Request-Id", &id.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, sync::Arc, thread};
+
+    #[test]
+    fn ids_increase_across_threads() {
+        let generator = Arc::new(IdGenerator::new());
+        let mut handles = Vec::new();
+
+        for _ in 0..4 {
+            let generator = Arc::clone(&generator);
+            handles.push(thread::spawn(move || {
+                let ids: Vec<u64> = (0..1000).map(|_| generator.next_id()).collect();
+                assert!(ids.windows(2).all(|w| w[0] < w[1]));
+                ids
+            }));
+        }
+
+        let mut all = HashSet::new();
+        for handle in handles {
+            for id in handle.join().unwrap() {
+                assert!(all.insert(id), "duplicate id {id}");
+            }
+        }
+        assert_eq!(4000, all.len());
+    }
+
+    #[test]
+    fn ids_carry_their_timestamp() {
+        let before = now_millis();
+        let id = IdGenerator::new().next_id();
+        assert!(IdGenerator::timestamp_millis(id) >= before);
+    }
+
+    #[test]
+    fn uuid_display_round_trip() {
+        let id = Uuid::from_u128(0x0123_4567_89ab_cdef_0011_2233_4455_6677);
+        assert_eq!("01234567-89ab-cdef-0011-223344556677", id.to_string());
+        assert_eq!(Ok(id), "01234567-89ab-cdef-0011-223344556677".parse());
+        assert_eq!(Ok(id), "0123456789ABCDEF0011223344556677".parse());
+
+        let random = Uuid::new();
+        assert_eq!(Ok(random), random.to_string().parse());
+    }
+
+    #[test]
+    fn uuid_parse_errors() {
+        assert_eq!(Err(ParseUuidError::InvalidLength(3)), "abc".parse::<Uuid>());
+        assert_eq!(
+            Err(ParseUuidError::InvalidCharacter('g')),
+            "0123456789abcdef0011223344556g77".parse::<Uuid>()
+        );
+        assert_eq!(
+            Err(ParseUuidError::MisplacedHyphen(4)),
+            "0123-4567-89ab-cdef-0011223344556677".parse::<Uuid>()
+        );
+    }
+
+    #[test]
+    fn uuids_are_unique_and_time_ordered() {
+        let a = Uuid::new();
+        let b = Uuid::new();
+        assert_ne!(a, b);
+        assert!(a.timestamp_millis() <= b.timestamp_millis());
+    }
+
+    #[test]
+    fn requests_carry_their_id() {
+        use crate::middleware::Chain;
+
+        let app = Chain::new(|req: &mut Request| {
+            let RequestId(id) = req.extensions().get().copied().unwrap();
+            Response::text(200, &id.to_string())
+        })
+        .with(RequestIds);
+
+        let given = "0190a6b2-7c3d-7e4f-8a9b-0c1d2e3f4a5b";
+        for (header, kept) in [(format!("X-Request-Id: {given}\r\n"), true), (String::from("X-Request-Id: 12\r\n"), false)] {
+            let raw = format!("GET / HTTP/1.1\r\n{header}\r\n");
+            let response = app.handle(&mut Request::read_from(&mut raw.as_bytes()).unwrap());
+            let id = response.header("X-Request-Id").unwrap().to_string();
+            assert_eq!(kept, id == given);
+            assert!(id.parse::<Uuid>().is_ok());
+            assert!(matches!(response.body, crate::http::Body::Bytes(ref b) if b == id.as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,411 @@
+// Background Jobs
+
+// Some work shouldn't happen while the client waits for a response, like sending an email after a signup.
+// The JobQueue lets a handler enqueue a named job with a payload and return immediately; the job then runs on the queue's own ThreadPool.
+
+// The pieces:
+    // 1. Workers are registered by job name: a closure that receives the payload bytes and returns Ok or an error message.
+    // 2. Each job name has a RetryPolicy. A failing (or panicking) job is retried with a growing delay until it runs out of attempts.
+    //    The delay is slept on the queue's Clock, so a test on a FakeClock retries by advancing it, without waiting.
+    // 3. Jobs that fail every attempt, or have no registered worker, are moved to the dead-letter list instead of being silently dropped.
+    // 4. Optionally, the pending jobs are written to a file with the binary codec, so jobs that were queued when the server stopped can be resumed on restart.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, RwLock},
+    time::Duration,
+};
+
+use common::clock::{Clock, SystemClock};
+
+use crate::{
+    codec::{self, DecodeError, Deserialize, Serialize},
+    ids::IdGenerator,
+    ThreadPool,
+};
+
+pub type JobId = u64;
+
+// The worker closure is shared by every run of that job type, possibly on several threads at the same time, so it lives in an Arc and must be Send + Sync.
+type Worker = Arc<dyn Fn(&[u8]) -> Result<(), String> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn no_retry() -> RetryPolicy {
+        RetryPolicy { max_attempts: 1, backoff: Duration::ZERO }
+    }
+
+    // Linear backoff: wait backoff after the first failure, 2 * backoff after the second, and so on
+    fn delay_after(&self, attempt: u32) -> Duration {
+        self.backoff * attempt
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { max_attempts: 3, backoff: Duration::from_millis(100) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Pending,
+    Running { attempt: u32 },
+    // Failed that many times, and waits for its backoff before the next attempt
+    Retrying { attempts: u32 },
+    Succeeded { attempts: u32 },
+    DeadLettered { attempts: u32, error: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub id: JobId,
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub error: String,
+}
+
+// This is the part of a job that gets persisted to disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct JobRecord {
+    id: u64,
+    name: String,
+    payload: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    statuses: HashMap<JobId, JobStatus>,
+    pending: HashMap<JobId, JobRecord>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+// Everything the worker threads need is kept in Inner, behind one Arc, so each job closure only has to clone a single pointer.
+struct Inner {
+    workers: RwLock<HashMap<String, (Worker, RetryPolicy)>>,
+    state: Mutex<State>,
+    idle: Condvar,
+    ids: IdGenerator,
+    persist_path: Option<PathBuf>,
+    clock: Arc<dyn Clock>,
+}
+
+pub struct JobQueue {
+    inner: Arc<Inner>,
+    pool: ThreadPool,
+    // Jobs read back from disk wait here until resume() is called, once their workers have been registered again
+    restored: Mutex<Vec<JobRecord>>,
+}
+
+impl JobQueue {
+    pub fn new(threads: usize) -> JobQueue {
+        JobQueue::build(threads, None, Vec::new())
+    }
+
+    // Load any pending jobs left over from a previous run. They are not started until resume() is called.
+    pub fn with_persistence(threads: usize, path: impl AsRef<Path>) -> io::Result<JobQueue> {
+        let path = path.as_ref().to_path_buf();
+
+        let restored = match fs::read(&path) {
+            Ok(bytes) => codec::from_bytes::<Vec<JobRecord>>(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(JobQueue::build(threads, Some(path), restored))
+    }
+
+    fn build(threads: usize, persist_path: Option<PathBuf>, restored: Vec<JobRecord>) -> JobQueue {
+        let mut state = State::default();
+        for record in &restored {
+            state.statuses.insert(record.id, JobStatus::Pending);
+            state.pending.insert(record.id, record.clone());
+        }
+
+        let inner = Inner {
+            workers: RwLock::new(HashMap::new()),
+            state: Mutex::new(state),
+            idle: Condvar::new(),
+            ids: IdGenerator::new(),
+            persist_path,
+            clock: Arc::new(SystemClock),
+        };
+
+        JobQueue {
+            inner: Arc::new(inner),
+            pool: ThreadPool::new(threads),
+            restored: Mutex::new(restored),
+        }
+    }
+
+    // Before any job runs: the retries of the jobs that already started would sleep on the old clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> JobQueue {
+        Arc::get_mut(&mut self.inner).expect("the clock is set before any job runs").clock = clock;
+        self
+    }
+
+    pub fn register<F>(&self, name: &str, policy: RetryPolicy, worker: F)
+    where
+        F: Fn(&[u8]) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.inner
+            .workers
+            .write()
+            .unwrap()
+            .insert(name.to_string(), (Arc::new(worker), policy));
+    }
+
+    pub fn enqueue(&self, name: &str, payload: &[u8]) -> io::Result<JobId> {
+        let record = JobRecord {
+            id: self.inner.ids.next_id(),
+            name: name.to_string(),
+            payload: payload.to_vec(),
+        };
+        let id = record.id;
+
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            state.statuses.insert(id, JobStatus::Pending);
+            state.pending.insert(id, record.clone());
+            self.inner.persist(&state)?;
+        }
+
+        self.dispatch(record);
+        Ok(id)
+    }
+
+    // Start the jobs that were restored from disk
+    pub fn resume(&self) {
+        let restored = std::mem::take(&mut *self.restored.lock().unwrap());
+        for record in restored {
+            self.dispatch(record);
+        }
+    }
+
+    pub fn status(&self, id: JobId) -> Option<JobStatus> {
+        self.inner.state.lock().unwrap().statuses.get(&id).cloned()
+    }
+
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.inner.state.lock().unwrap().dead_letters.clone()
+    }
+
+    // Block until every dispatched job has either succeeded or been dead-lettered.
+    // Jobs restored from disk but not yet resumed don't count, otherwise this could wait forever.
+    pub fn wait_idle(&self) {
+        let restored = self.restored.lock().unwrap().len();
+        let state = self.inner.state.lock().unwrap();
+        let _state = self
+            .inner
+            .idle
+            .wait_while(state, |s| s.pending.len() > restored)
+            .unwrap();
+    }
+
+    fn dispatch(&self, record: JobRecord) {
+        let inner = Arc::clone(&self.inner);
+        self.pool.execute(move || inner.run(record));
+    }
+}
+
+impl Inner {
+    fn run(&self, record: JobRecord) {
+        let worker = self.workers.read().unwrap().get(&record.name).cloned();
+
+        let (worker, policy) = match worker {
+            Some(worker) => worker,
+            None => {
+                let error = format!("no worker registered for job type {:?}", record.name);
+                self.finish(record, JobStatus::DeadLettered { attempts: 0, error });
+                return;
+            }
+        };
+
+        let mut attempt = 1;
+        loop {
+            self.set_status(record.id, JobStatus::Running { attempt });
+
+            // A panicking job would otherwise take the pool's worker thread down with it, so we catch the unwind and treat it as a failed attempt.
+            // AssertUnwindSafe is our promise that nothing the closure touches is left in a broken state; the payload is only borrowed immutably.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| worker(&record.payload)))
+                .unwrap_or_else(|_| Err(String::from("job panicked")));
+
+            match result {
+                Ok(()) => {
+                    self.finish(record, JobStatus::Succeeded { attempts: attempt });
+                    return;
+                }
+                Err(error) if attempt >= policy.max_attempts => {
+                    self.finish(record, JobStatus::DeadLettered { attempts: attempt, error });
+                    return;
+                }
+                Err(_) => {
+                    let until = self.clock.now() + policy.delay_after(attempt);
+                    self.set_status(record.id, JobStatus::Retrying { attempts: attempt });
+                    self.clock.sleep_until(until);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn set_status(&self, id: JobId, status: JobStatus) {
+        self.state.lock().unwrap().statuses.insert(id, status);
+    }
+
+    fn finish(&self, record: JobRecord, status: JobStatus) {
+        let mut state = self.state.lock().unwrap();
+
+        if let JobStatus::DeadLettered { error, .. } = &status {
+            state.dead_letters.push(DeadLetter {
+                id: record.id,
+                name: record.name.clone(),
+                payload: record.payload.clone(),
+                error: error.clone(),
+            });
+        }
+        state.statuses.insert(record.id, status);
+        state.pending.remove(&record.id);
+
+        // A failed write leaves the old file in place, which means the job would run again after a restart. That's the safer side to fail on.
+        if let Err(e) = self.persist(&state) {
+            eprintln!("Failed to persist job queue: {e}");
+        }
+        self.idle.notify_all();
+    }
+
+    // Write the pending jobs to a temporary file and rename it over the real one.
+    // The rename is atomic, so a crash mid-write never leaves a half written queue file behind.
+    fn persist(&self, state: &State) -> io::Result<()> {
+        let path = match &self.persist_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut pending: Vec<&JobRecord> = state.pending.values().collect();
+        pending.sort_by_key(|record| record.id);
+
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, codec::to_bytes(&pending))?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn quick_retries(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy { max_attempts, backoff: Duration::from_millis(1) }
+    }
+
+    #[test]
+    fn runs_registered_jobs() {
+        let queue = JobQueue::new(2);
+        let sum = Arc::new(AtomicU32::new(0));
+
+        let counter = Arc::clone(&sum);
+        queue.register("add", RetryPolicy::no_retry(), move |payload| {
+            counter.fetch_add(payload[0] as u32, Ordering::SeqCst);
+            Ok(())
+        });
+
+        let ids: Vec<JobId> = (1..=4).map(|n| queue.enqueue("add", &[n]).unwrap()).collect();
+        queue.wait_idle();
+
+        assert_eq!(10, sum.load(Ordering::SeqCst));
+        for id in ids {
+            assert_eq!(Some(JobStatus::Succeeded { attempts: 1 }), queue.status(id));
+        }
+    }
+
+    #[test]
+    fn retries_then_succeeds() {
+        let queue = JobQueue::new(1);
+        let calls = Arc::new(AtomicU32::new(0));
+
+        let counter = Arc::clone(&calls);
+        queue.register("flaky", quick_retries(3), move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(String::from("not yet"))
+            } else {
+                Ok(())
+            }
+        });
+
+        let id = queue.enqueue("flaky", b"").unwrap();
+        queue.wait_idle();
+
+        assert_eq!(Some(JobStatus::Succeeded { attempts: 3 }), queue.status(id));
+        assert!(queue.dead_letters().is_empty());
+    }
+
+    #[test]
+    fn exhausted_and_unknown_jobs_are_dead_lettered() {
+        let queue = JobQueue::new(2);
+        queue.register("always_fails", quick_retries(2), |_| Err(String::from("boom")));
+        queue.register("panics", quick_retries(1), |_| panic!("job blew up"));
+
+        let failing = queue.enqueue("always_fails", b"x").unwrap();
+        let panicking = queue.enqueue("panics", b"y").unwrap();
+        let unknown = queue.enqueue("missing", b"z").unwrap();
+        queue.wait_idle();
+
+        assert_eq!(
+            Some(JobStatus::DeadLettered { attempts: 2, error: String::from("boom") }),
+            queue.status(failing)
+        );
+        assert_eq!(
+            Some(JobStatus::DeadLettered { attempts: 1, error: String::from("job panicked") }),
+            queue.status(panicking)
+        );
+        assert!(matches!(queue.status(unknown), Some(JobStatus::DeadLettered { attempts: 0, .. })));
+        assert_eq!(3, queue.dead_letters().len());
+    }
+
+    #[test]
+    fn pending_jobs_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("jobs-test-{}.queue", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // Simulate a crash: the first queue persists a pending job and goes away before any worker runs it
+        let id = {
+            let queue = JobQueue::with_persistence(1, &path).unwrap();
+            let record = JobRecord { id: 42, name: String::from("email"), payload: b"hi".to_vec() };
+            let mut state = queue.inner.state.lock().unwrap();
+            state.pending.insert(record.id, record);
+            queue.inner.persist(&state).unwrap();
+            42
+        };
+
+        let queue = JobQueue::with_persistence(1, &path).unwrap();
+        assert_eq!(Some(JobStatus::Pending), queue.status(id));
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        queue.register("email", RetryPolicy::no_retry(), move |payload| {
+            sink.lock().unwrap().push(payload.to_vec());
+            Ok(())
+        });
+        queue.resume();
+        queue.wait_idle();
+
+        assert_eq!(vec![b"hi".to_vec()], *seen.lock().unwrap());
+        assert_eq!(Some(JobStatus::Succeeded { attempts: 1 }), queue.status(id));
+
+        // Nothing is pending anymore, so a third start restores no jobs
+        let empty: Vec<JobRecord> = codec::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert!(empty.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
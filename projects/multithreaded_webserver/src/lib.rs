@@ -1,4 +1,65 @@
-use std::{sync::{mpsc, Arc, Mutex}, thread};
+use std::{sync::{mpsc, Arc, Mutex, OnceLock}, thread, time::Duration};
+
+use common::{progress::Tracker, tracing};
+use concurrency::sync::WaitGroup;
+use timer::{TimerToken, TimerWheel};
+
+// Modules built on top of the server, declared here so that they are part of the library crate and main.rs can use them.
+pub mod accept;
+pub mod access_log;
+pub mod auth;
+pub mod broker;
+pub mod body_filter;
+pub mod client;
+pub mod codec;
+pub mod compress;
+pub mod config;
+pub mod connection_pool;
+pub mod cookie;
+pub mod cors;
+pub mod dashboard;
+pub mod encoding;
+pub mod error;
+pub mod extensions;
+pub mod form;
+// The fuzz targets for the request and URL parsers are public with the fuzz feature, and tested either way, see fuzz.rs
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+pub mod guard;
+pub mod head;
+pub mod health;
+pub mod hmac;
+pub mod histogram;
+pub mod http;
+pub mod http_date;
+pub mod ids;
+pub mod jobs;
+pub mod limits;
+pub mod markup;
+pub mod live_reload;
+pub mod metrics;
+pub mod middleware;
+pub mod multipart;
+pub mod negotiation;
+pub mod net;
+pub mod panic_report;
+pub mod pool;
+pub mod server;
+pub mod session;
+pub mod sha1;
+// The simulated network and clock are public with the sim feature, and tested either way, see sim.rs
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+pub mod sse;
+pub mod static_files;
+pub mod sysinfo_lite;
+// Stopwatch, Deadline and the rate limiters moved to projects/common, under their old name here
+pub use common::time as time_ext;
+pub mod timer;
+pub mod traces;
+pub mod url;
+pub mod watch;
+pub mod websocket;
 
 // struct Job;
 
@@ -13,7 +74,9 @@ pub struct ThreadPool {
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>
+    sender: Option<mpsc::Sender<Job>>,
+    // Only started the first time execute_after is called, most pools never need a timer thread
+    timer: OnceLock<TimerWheel>,
 }
 
 /*
@@ -72,20 +135,56 @@ impl ThreadPool {
             workers.push(Worker::new(id, Arc::clone(&receiver) ));
         }
 
-        ThreadPool { workers, sender: Some(sender) }
+        ThreadPool { workers, sender: Some(sender), timer: OnceLock::new() }
     }
 
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        // The job runs in the tracing span that's current here (common::tracing), so the spans it makes and the lines it logs
+        // are part of the same trace
+        let job = Box::new(tracing::propagate(f));
 
         // We’re calling unwrap on send for the case that sending fails. This might happen if, for example, we stop all our threads from executing, meaning the receiving end has stopped receiving new messages.
         self.sender.as_ref().unwrap().send(job).unwrap();
     }
 
+    // Runs f on a worker once delay has passed. No thread sleeps in the meantime: the pool's TimerWheel (src/timer.rs) sends the job
+    // down the channel when it's due, and the token can cancel it until then. Jobs still waiting when the pool is dropped never run.
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> TimerToken
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.as_ref().unwrap().clone();
+        let job: Job = Box::new(tracing::propagate(f));
+        self.timer.get_or_init(TimerWheel::new).schedule(delay, move || {
+            let _ = sender.send(job);
+        })
+    }
 
+    // Runs job once for every item, spread over the workers, and returns when all of them are done.
+    // Every finished item counts one on the tracker, which reports to whatever Reporter it was made with (a progress bar, JSON lines...),
+    // so the caller sees how far the batch has got without the pool knowing anything about terminals.
+    // The WaitGroup from the concurrency project counts the items still running: each job holds a clone and drops it when done, even when it panics.
+    pub fn execute_batch<T, F>(&self, items: Vec<T>, job: F, progress: &Tracker)
+    where
+        T: Send + 'static,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let job = Arc::new(job);
+        let wait_group = WaitGroup::new();
+        for item in items {
+            let (job, progress, wait_group) = (Arc::clone(&job), progress.clone(), wait_group.clone());
+            self.execute(move || {
+                job(item);
+                progress.inc(1);
+                drop(wait_group);
+            });
+        }
+        wait_group.wait();
+        progress.finish();
+    }
 }
 
 
@@ -238,6 +337,9 @@ impl Drop for ThreadPool {
 
         drop(self.sender.take());
 
+        // The delayed jobs waiting in the timer each hold a clone of the sender, and the channel only closes once all of them are gone
+        drop(self.timer.take());
+
 
 
         // we loop through each of the thread pool workers. We use &mut for this because self is a mutable reference, and we also need to be able to mutate worker.
@@ -274,7 +376,8 @@ impl Worker {
         // Our closure being passed to thread::spawn still only references the receiving end of the channel. 
         // Instead, we need the closure to loop forever, asking the receiving end of the channel for a job and running the job when it gets one.
 
-        let thread = thread::spawn(move || loop {
+        // The thread has a name, which is what a crash report (src/panic_report.rs) says the panic happened on
+        let spawned = thread::Builder::new().name(format!("worker-{id}")).spawn(move || loop {
             // The first unwrap is for the lock to acquire the mutex. Acquiring a lock might fail if the mutex is in a poisoned state, which can happen if some other thread panicked while holding the lock rather than releasing the lock.
             // In this situation, calling unwrap to have this thread panic is the correct action to take. Feel free to change this unwrap to an expect with an error message that is meaningful to you.
 
@@ -289,7 +392,10 @@ impl Worker {
             match message {
                 Ok(job) => {
                     println!("Worker {id} got a job; executing.");
-                    job();
+                    // A job that panics becomes a crash report, and the worker goes on with the next one instead of dying with it
+                    if let Err(report) = panic_report::catch(job) {
+                        common::error!("worker {id}: job panicked at {}: {}", report.location, report.message);
+                    }
                 }
                 Err(_) => {
                     println!("Worker {id} disconnected; shutting down.");
@@ -298,6 +404,7 @@ impl Worker {
             }
         
         });
+        let thread = spawned.expect("failed to spawn a worker thread");
 
         Worker { id, thread: Some(thread) }
     }
@@ -342,3 +449,75 @@ impl WorkerII {
         WorkerII { id, thread }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::progress::JsonLines;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn batches_report_progress_and_wait_for_every_item() {
+        let pool = ThreadPool::new(3);
+        let sum = Arc::new(AtomicU64::new(0));
+        let json = Arc::new(JsonLines::new(Vec::new()));
+        let progress = Tracker::new(json.clone(), "squares", Some(10));
+
+        let total = Arc::clone(&sum);
+        pool.execute_batch((1..=10).collect(), move |n: u64| { total.fetch_add(n * n, Ordering::SeqCst); }, &progress);
+
+        // Nothing is still running when execute_batch returns
+        assert_eq!(385, sum.load(Ordering::SeqCst));
+        assert_eq!(10, progress.done());
+        drop(progress);
+        drop(pool);
+        let out = String::from_utf8(Arc::try_unwrap(json).ok().unwrap().into_inner()).unwrap();
+        assert_eq!(11, out.lines().count());
+        assert!(out.ends_with("{\"task\":\"squares\",\"done\":10,\"total\":10,\"finished\":true}\n"));
+    }
+
+    #[test]
+    fn delayed_jobs_run_on_the_pool_unless_cancelled() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+
+        let cancelled = {
+            let sender = sender.clone();
+            pool.execute_after(Duration::from_millis(10), move || sender.send("cancelled").unwrap())
+        };
+        assert!(cancelled.cancel());
+        {
+            let sender = sender.clone();
+            pool.execute_after(Duration::from_millis(20), move || sender.send("delayed").unwrap());
+        }
+        pool.execute(move || sender.send("now").unwrap());
+
+        assert_eq!(Ok("now"), receiver.recv_timeout(Duration::from_secs(5)));
+        assert_eq!(Ok("delayed"), receiver.recv_timeout(Duration::from_secs(5)));
+
+        // Jobs run in the tracing span they were handed over in
+        {
+            let span = tracing::Span::new("request");
+            let (sender, receiver) = mpsc::channel();
+            pool.execute(move || sender.send(tracing::current()).unwrap());
+            assert_eq!(Ok(Some(span.context())), receiver.recv_timeout(Duration::from_secs(5)));
+        }
+
+        // A job that is still waiting doesn't hold up the pool's shutdown
+        pool.execute_after(Duration::from_secs(3600), || panic!("never runs"));
+        drop(pool);
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn panicking_jobs_dont_take_the_worker_down() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(|| panic!("a broken job"));
+        // The same worker runs the next job, and has a name for the crash reports
+        pool.execute(move || sender.send(thread::current().name().map(str::to_string)).unwrap());
+        assert_eq!(Ok(Some(String::from("worker-0"))), receiver.recv_timeout(Duration::from_secs(5)));
+        // Joining the worker would panic if its thread had died
+        drop(pool);
+    }
+}
@@ -0,0 +1,207 @@
+// Limits on What a Client May Send
+
+// Every request costs the server a worker for as long as it takes to read. A client that takes its time costs as much as one that
+// doesn't: Slowloris opens connections and sends the headers one byte every few seconds, never finishing, until every worker
+// is busy waiting for it and nobody else gets an answer. Nothing in that is malformed, it's only slow. So the server limits:
+    // 1. How big the request line and the headers may be, max_head_size. Past it the request is refused with 431.
+    // 2. How big the body may be, max_body_size. A Content-Length over it is refused with 413, before reading any of it.
+    // 3. How long the head may take, head_timeout, counted from its first byte. Slowloris never gets past it: 408.
+    // 4. How fast the body has to come, min_rate. A big upload may take minutes, so there is no timeout for the body,
+    //    but after a grace period the client has to have sent bytes_per_sec for every second so far, or it gets a 408 too.
+// A refused request closes the connection, whatever the client asked for, since the rest of what it sends can't be trusted.
+
+// The time between two requests of a keep-alive connection is the server's keep_alive, and runs out without an answer:
+// the client hasn't asked for anything yet.
+
+// All the timeouts come down to TimedReader, which the server reads the connection through. Before each read it works out how
+// long the read may block, from the deadline of the part it is reading and the rate the bytes have to come at, and sets that
+// as the socket's read timeout. A read that times out is an io::Error of kind TimedOut, which fails the parse like any other.
+// The deadlines are instants of the reader's Clock, the system's unless with_clock says otherwise, so a test on the simulated
+// network (src/sim.rs) runs them out by moving its clock.
+
+use std::{
+    io::{self, Read},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::clock::{Clock, SystemClock};
+
+use crate::{
+    http::{MAX_BODY_SIZE, MAX_HEAD_SIZE},
+    net::Stream,
+    time_ext::Deadline,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinRate {
+    pub bytes_per_sec: u64,
+    // How long the client has before the rate counts, for the round trips at the start and a slow network
+    pub grace: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_head_size: usize,
+    pub max_body_size: usize,
+    pub head_timeout: Duration,
+    pub min_rate: Option<MinRate>,
+}
+
+// The defaults are those of Apache's mod_reqtimeout, with the sizes in http.rs
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_head_size: MAX_HEAD_SIZE,
+            max_body_size: MAX_BODY_SIZE,
+            head_timeout: Duration::from_secs(20),
+            min_rate: Some(MinRate { bytes_per_sec: 500, grace: Duration::from_secs(20) }),
+        }
+    }
+}
+
+// Only some of Read's errors mean the time was up, the rest are a closed or broken connection
+pub fn is_timeout(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::TimedOut
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "the client sent too slowly")
+}
+
+pub struct TimedReader {
+    stream: Box<dyn Stream>,
+    clock: Arc<dyn Clock>,
+    deadline: Option<Deadline>,
+    rate: Option<MinRate>,
+    // When the rate started to count, and the bytes that came since
+    started: Instant,
+    received: u64,
+}
+
+impl TimedReader {
+    pub fn new(stream: Box<dyn Stream>) -> TimedReader {
+        TimedReader { stream, clock: Arc::new(SystemClock), deadline: None, rate: None, started: Instant::now(), received: 0 }
+    }
+
+    // The deadlines given to set_deadline have to be instants of this clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> TimedReader {
+        self.started = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    // Every read from now on has to be done by the deadline, None for no deadline
+    pub fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.deadline = deadline;
+    }
+
+    // From now on the bytes have to come at least this fast, None for any speed
+    pub fn set_min_rate(&mut self, rate: Option<MinRate>) {
+        self.rate = rate;
+        self.started = self.clock.now();
+        self.received = 0;
+    }
+
+    pub fn get_ref(&self) -> &dyn Stream {
+        self.stream.as_ref()
+    }
+
+    // The latest the next read may return: the deadline, or the moment the bytes received so far fall below the rate
+    fn due(&self) -> Option<Instant> {
+        let rate = self.rate.filter(|rate| rate.bytes_per_sec > 0).map(|rate| {
+            self.started + rate.grace + Duration::from_secs_f64(self.received as f64 / rate.bytes_per_sec as f64)
+        });
+        match (self.deadline.map(|deadline| deadline.instant()), rate) {
+            (Some(deadline), Some(rate)) => Some(deadline.min(rate)),
+            (deadline, rate) => deadline.or(rate),
+        }
+    }
+}
+
+impl Read for TimedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.due() {
+            Some(due) => match due.checked_duration_since(self.clock.now()) {
+                // set_read_timeout(Some(Duration::ZERO)) is an error, not a read that times out at once
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(timed_out()),
+            },
+            None => None,
+        };
+        self.stream.set_read_timeout(timeout)?;
+        match self.stream.read(buf) {
+            Ok(read) => {
+                self.received += read as u64;
+                Ok(read)
+            }
+            // A read timeout is WouldBlock on Unix and TimedOut on Windows
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Err(timed_out()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Write,
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    fn pair() -> (TcpStream, Box<dyn Stream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, Box::new(listener.accept().unwrap().0))
+    }
+
+    #[test]
+    fn reads_time_out_at_the_deadline() {
+        let (mut client, server) = pair();
+        let mut reader = TimedReader::new(server);
+        reader.set_deadline(Some(Deadline::after(Duration::from_millis(50))));
+        client.write_all(b"hi").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(2, reader.read(&mut buf).unwrap());
+
+        let started = Instant::now();
+        assert!(is_timeout(&reader.read(&mut buf).unwrap_err()));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // And at once, once it has passed
+        assert!(is_timeout(&reader.read(&mut buf).unwrap_err()));
+
+        reader.set_deadline(None);
+        client.write_all(b"more").unwrap();
+        assert_eq!(4, reader.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn a_slow_sender_falls_behind_the_rate() {
+        let (mut client, server) = pair();
+        let mut reader = TimedReader::new(server);
+        reader.set_min_rate(Some(MinRate { bytes_per_sec: 1000, grace: Duration::from_millis(50) }));
+        // 100 bytes buy another 100ms after the grace, and a byte every 50ms doesn't keep up with that for long
+        client.write_all(&[0; 100]).unwrap();
+        let trickle = thread::spawn(move || {
+            for _ in 0..20 {
+                thread::sleep(Duration::from_millis(50));
+                if client.write_all(b"x").is_err() {
+                    break;
+                }
+            }
+        });
+        let mut buf = [0; 128];
+        let started = Instant::now();
+        let error = loop {
+            if let Err(e) = reader.read(&mut buf) {
+                break e;
+            }
+        };
+        assert!(is_timeout(&error), "{error}");
+        assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+        drop(reader);
+        trickle.join().unwrap();
+    }
+}
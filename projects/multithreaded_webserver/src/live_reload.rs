@@ -0,0 +1,180 @@
+// Live Reload
+
+// Editing a page, switching to the browser and pressing reload gets old quickly. With LiveReload the browser does it by itself:
+    // 1. A PollWatcher (src/watch.rs) watches the site's files and templates.
+    // 2. Every HTML page the server sends gets a small script added before </body>, found with the tokenizer of src/markup.rs.
+    //    It opens an event stream (src/sse.rs) to ENDPOINT.
+    // 3. When a watched file changes, a "reload" event goes out on every open stream, and the script reloads the page.
+// The handlers read their files from disk on every request, so the reloaded page is the new one. A server that caches its pages
+// would listen to the same events to throw its cache away.
+
+// The watcher thread sends every change on a broadcast channel (concurrency/src/broadcast.rs), so any number of open tabs
+// each get their own copy of it. A tab that falls behind skips the changes it missed, and reloads once for all of them.
+// It's for development only: every page gets a script it didn't ask for, and every tab keeps a connection and a worker busy.
+
+use std::{
+    io,
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use concurrency::broadcast::{Overflow, RecvError, Sender};
+
+use crate::{
+    http::{Body, Request, Response},
+    markup::{Token, Tokenizer},
+    middleware::{Middleware, Next},
+    sse::Event,
+    watch::{PollWatcher, RecursiveMode, Watcher},
+};
+
+pub const ENDPOINT: &str = "/__live_reload";
+
+const SCRIPT: &str = "<script>new EventSource(\"/__live_reload\").addEventListener(\"reload\", () => location.reload())</script>";
+
+pub struct LiveReload {
+    changes: Sender<String>,
+    // Kept for as long as the middleware lives, dropping it stops the polling thread
+    _watcher: PollWatcher,
+}
+
+impl LiveReload {
+    // Every path is watched recursively, and has to exist
+    pub fn new<P: AsRef<Path>>(paths: &[P], interval: Duration) -> io::Result<LiveReload> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = PollWatcher::new(tx, interval);
+        for path in paths {
+            watcher.watch(path.as_ref(), RecursiveMode::Recursive)?;
+        }
+
+        // Ends when the watcher is dropped, which drops the sending end of the channel
+        let changes = Sender::new(16, Overflow::Skip);
+        let publisher = changes.clone();
+        thread::spawn(move || {
+            for event in rx {
+                eprintln!("live reload: {event}");
+                publisher.send(event.path.display().to_string());
+            }
+        });
+
+        Ok(LiveReload { changes, _watcher: watcher })
+    }
+}
+
+// Before the last </body>, or at the end when the page doesn't have one. The tokenizer skips a </body> in a comment or
+// a script, a page that isn't UTF-8 gets the plain byte search
+fn inject_script(html: &mut Vec<u8>) {
+    let body_end = match std::str::from_utf8(html) {
+        Ok(page) => {
+            let mut tokenizer = Tokenizer::new(page);
+            let mut at = None;
+            while let Some(token) = tokenizer.next() {
+                if matches!(token, Token::EndTag(name) if name.eq_ignore_ascii_case("body")) {
+                    at = Some(tokenizer.span().start);
+                }
+            }
+            at
+        }
+        Err(_) => html.windows(7).rposition(|window| window.eq_ignore_ascii_case(b"</body>")),
+    };
+    let at = body_end.unwrap_or(html.len());
+    html.splice(at..at, SCRIPT.bytes());
+}
+
+impl Middleware for LiveReload {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        if request.method == "GET" && request.path_only() == ENDPOINT {
+            let mut changes = self.changes.subscribe();
+            return Response::event_stream(move |events| {
+                thread::spawn(move || {
+                    // Woken up every second to find out whether the tab is still open
+                    while !events.is_closed() {
+                        let path = match changes.recv_timeout(Duration::from_secs(1)) {
+                            Ok(Some(path)) => path,
+                            Ok(None) => continue,
+                            Err(RecvError::Lagged(missed)) => format!("{missed} files"),
+                            // The middleware is gone, and the server with it
+                            Err(RecvError::Closed) => break,
+                        };
+                        if events.send(Event::new(&path).event("reload")).is_err() {
+                            break;
+                        }
+                    }
+                });
+            });
+        }
+
+        let mut response = next.run(request);
+        let is_html = response.header("Content-Type").is_some_and(|content_type| content_type.starts_with("text/html"));
+        // An encoded body can't be edited, that's why Compression has to come after LiveReload in the chain
+        let is_encoded = response.header("Content-Encoding").is_some();
+        if let (true, false, Body::Bytes(body)) = (is_html, is_encoded, &mut response.body) {
+            inject_script(body);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+    use std::{env, fs};
+
+    fn get(path: &str) -> Request {
+        Request::read_from(&mut format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes()).unwrap()
+    }
+
+    fn body(response: Response) -> String {
+        match response.body {
+            Body::Bytes(body) => String::from_utf8(body).unwrap(),
+            Body::EventStream(_) => panic!("expected a body"),
+        }
+    }
+
+    #[test]
+    fn injects_the_script_into_html() {
+        let mut page = b"<html><BODY><h1>hi</h1></BODY></html>".to_vec();
+        inject_script(&mut page);
+        assert_eq!(format!("<html><BODY><h1>hi</h1>{SCRIPT}</BODY></html>"), String::from_utf8(page).unwrap());
+
+        let mut fragment = b"<p>no body</p>".to_vec();
+        inject_script(&mut fragment);
+        assert!(fragment.ends_with(SCRIPT.as_bytes()));
+
+        // Not the </body> in a comment after the page
+        let mut commented = b"<body>hi</body><!-- </body> -->".to_vec();
+        inject_script(&mut commented);
+        assert_eq!(format!("<body>hi{SCRIPT}</body><!-- </body> -->"), String::from_utf8(commented).unwrap());
+    }
+
+    #[test]
+    fn serves_pages_and_the_event_stream() {
+        let dir = env::temp_dir().join(format!("mws-live-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let live_reload = LiveReload::new(&[&dir], Duration::from_millis(10)).unwrap();
+        let mut changes = live_reload.changes.subscribe();
+        let app = Chain::new(|req: &mut Request| match req.path_only() {
+            "/" => Response::html(200, "<body>home</body>"),
+            _ => Response::text(404, "</body>"),
+        })
+        .with(live_reload);
+
+        assert_eq!(format!("<body>home{SCRIPT}</body>"), body(app.handle(&mut get("/"))));
+        // Only HTML gets the script
+        assert_eq!("</body>", body(app.handle(&mut get("/style.css"))));
+        assert!(matches!(app.handle(&mut get(ENDPOINT)).body, Body::EventStream(_)));
+
+        // A change to a watched file is published for the streams
+        fs::write(dir.join("index.html"), "<h1>new</h1>").unwrap();
+        // The directory itself may be reported as modified first
+        let mut next = || changes.recv_timeout(Duration::from_secs(5)).unwrap();
+        let changed = (0..3).find_map(|_| next().filter(|path| path.ends_with("index.html")));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(changed.is_some());
+
+        assert!(LiveReload::new(&[dir.join("missing")], Duration::from_secs(1)).is_err());
+    }
+}
@@ -3,15 +3,140 @@
 // In a single threaded implementation, if the server receives a request that takes a long time to process, subsequent requests will have to wait until the long request is finished, even if the new requests can be processed quickly.
 
 use std::{
-    fs, io::{prelude::*, BufReader}, net::{TcpListener, TcpStream}, thread, time::Duration
+    env, fs, io::{prelude::*, BufReader}, net::{TcpListener, TcpStream}, process, sync::{mpsc, Arc}, thread, time::Duration
 };
 
-use multithreaded_webserver::ThreadPool;
+use common::{
+    daemon::{Daemon, PidFile},
+    env_config::Env,
+    prelude::*,
+    signals::{Shutdown, Signal},
+    tracing,
+};
+use concurrency::{
+    broadcast::{Overflow, RecvError, Sender},
+    ring::RingBuffer,
+};
+use multithreaded_webserver::{
+    body_filter::BodyFilter,
+    codec,
+    compress::Compression,
+    error::{CatchPanic, HandlerError},
+    form::FormData,
+    guard::{ScopeGuard, Transaction},
+    http::{Request, Response},
+    ids::IdGenerator,
+    live_reload::LiveReload,
+    metrics::{Metrics, MetricsEndpoint, MetricsMiddleware},
+    middleware::Chain,
+    multipart::{MultipartLimits, PartData},
+    panic_report,
+    pool::{read_buffer_pool, ObjectPool, PooledReader},
+    server::{Bound, NoHandler, ServerBuilder},
+    session::{MemoryStore, SessionMiddleware},
+    sse::Event,
+    sysinfo_lite::{self, Limits, Watchdog},
+    traces::Tracing,
+    websocket::{Message, WebSocket},
+    ThreadPool,
+};
+use common::argparse::{ArgError, Parser};
+
+// $ cargo run -- --daemon --pid-file webserver.pid --log-file webserver.log
+// runs mt_main_signals in the background (projects/common/src/daemon.rs), until kill $(cat webserver.pid).
+// Without --daemon it's the main below that --demo names, $ cargo run -- --demo websocket, and mt_main_shutdown without it.
+// mt_main_signals takes its address, threads and keep-alive from HOST, PORT, THREADS and KEEP_ALIVE (common::env_config),
+// --print-env-help lists them. The mains of the book keep listening on 127.0.0.1:7878.
+
+// The mains that take nothing, by the name --demo knows them by. The other one is signals, which needs the settings
+const DEMOS: &[(&str, fn())] = &[
+    ("st", st_main),
+    ("mt", mt_main),
+    ("shutdown", mt_main_shutdown),
+    ("metrics", mt_main_metrics),
+    ("websocket", mt_main_websocket),
+    ("events", mt_main_events),
+    ("sessions", mt_main_sessions),
+    ("uploads", mt_main_uploads),
+    ("chat", mt_main_chat),
+    ("pipeline", mt_main_pipeline),
+    ("pooled", mt_main_pooled),
+    ("builder", mt_main_builder),
+    ("live-reload", mt_main_live_reload),
+    ("errors", mt_main_errors),
+    ("dashboard", mt_main_dashboard),
+];
+
+// The daemon to start, if --daemon was given, and the name of the demo to run otherwise
+fn parse_args(env: &Env) -> Result<(Option<Daemon>, String), ArgError> {
+    let names: Vec<&str> = DEMOS.iter().map(|(name, _)| *name).chain(["signals"]).collect();
+    let matches = Parser::new("multithreaded_webserver", "the webserver of chapter 20, and everything built on it since")
+        .flag("daemon", "Run mt_main_signals in the background, on Unix")
+        .option("pid-file", "PATH", "Where the daemon writes its process id")
+        .option("log-file", "PATH", "Where the daemon's output goes, instead of /dev/null")
+        .option("demo", "NAME", &format!("Which main to run: {}", names.join(", ")))
+        .default("shutdown")
+        .env_help(&env.help())
+        .parse(env::args().skip(1))?;
+    let demo = matches.value("demo").unwrap_or("shutdown").to_string();
+    if !names.contains(&demo.as_str()) {
+        return Err(ArgError::InvalidValue {
+            name: String::from("demo"),
+            value: demo,
+            reason: format!("the demos are {}", names.join(", ")),
+        });
+    }
+    if !matches.flag("daemon") {
+        return Ok((None, demo));
+    }
+    // index.html and 404.html are read from where it was started, not from /
+    let mut daemon = Daemon::new().working_dir(env::current_dir().map_err(|e| ArgError::Invalid(e.to_string()))?);
+    if let Some(path) = matches.value("pid-file") {
+        daemon = daemon.pid_file(path);
+    }
+    if let Some(path) = matches.value("log-file") {
+        daemon = daemon.stdout(path).stderr(path);
+    }
+    Ok((Some(daemon), demo))
+}
 
 fn main() {
-    // st_main();
-    // mt_main();
-    mt_main_shutdown();
+    let mut env = Env::new();
+    let settings = ServerBuilder::new().bind_from_env(&mut env);
+    let (daemon, demo) = match parse_args(&env) {
+        Ok(args) => args,
+        Err(ArgError::Help(help)) => {
+            print!("{help}");
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    if let Err(e) = env.check() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+    // Before the logger and the threads, fork only keeps the thread that calls it
+    let pid_file = daemon.map(|daemon| match daemon.start() {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    });
+    // The problems below are logged with warn! and error! (projects/common/src/log.rs), LOG=debug or LOG=off changes how much shows
+    if let Err(e) = common::log::init_from_env("LOG") {
+        eprintln!("{e}");
+    }
+    if let Some(pid_file) = pid_file {
+        return mt_main_signals(settings, pid_file);
+    }
+    match DEMOS.iter().find(|(name, _)| *name == demo) {
+        Some((_, main)) => main(),
+        None => mt_main_signals(settings, None),
+    }
 }
 
 fn st_main() {
@@ -201,4 +326,717 @@ fn mt_main_shutdown() {
 // Observe during execution: Notice one interesting aspect of this particular execution: the ThreadPool dropped the sender, and before any worker received an error, we tried to join worker 0. Worker 0 had not yet gotten an error from recv, so the main thread blocked waiting for worker 0 to finish.
 
 // Congrats! We are now done implementing a threadpool which processes requests asynchronously and performs a graceful shutdown.
-// Fin.
\ No newline at end of file
+// Fin.
+
+
+// A /metrics Endpoint
+
+// The server keeps a few counters in a Metrics struct (src/metrics.rs), shared between the workers with an Arc because every job closure needs its own handle to it.
+// GET /metrics returns a snapshot of the counters encoded with the hand-rolled binary codec in src/codec.rs, rather than as text.
+// A client reads the Content-Length bytes of the body and calls codec::from_bytes::<MetricsSnapshot>(&body) to get the struct back.
+// Besides the counters, the snapshot has the p50, p95 and p99 response times, from a histogram of every request's latency (src/histogram.rs).
+
+fn mt_main_metrics() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+    let metrics = Arc::new(Metrics::new());
+
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+        let metrics = Arc::clone(&metrics);
+
+        pool.execute(move || {
+            handle_connection_with_metrics(stream, &metrics);
+        });
+    }
+}
+
+// Every request gets an ID from a generator stored in a static, so all the worker threads share it without passing it around.
+// The IDs increase over time, so sorting the log lines by ID puts the requests back in arrival order even though the workers print them concurrently.
+static REQUEST_IDS: IdGenerator = IdGenerator::new();
+
+fn handle_connection_with_metrics(mut stream: TcpStream, metrics: &Metrics) {
+    // Times the whole connection, reading the request included, for the latency percentiles in the snapshot
+    let stopwatch = Stopwatch::start();
+    let buf_reader = BufReader::new(&mut stream);
+    let request_line = buf_reader.lines().next().unwrap().unwrap();
+
+    let request_id = REQUEST_IDS.next_id();
+    println!("[request {request_id}] {request_line}");
+
+    if request_line == "GET /metrics HTTP/1.1" {
+        metrics.record(200);
+
+        // The body is binary, so we build the response as bytes instead of formatting a String
+        let body = codec::to_bytes(&metrics.snapshot());
+        let length = body.len();
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {length}\r\n\r\n"
+        )
+        .into_bytes();
+        response.extend_from_slice(&body);
+
+        stream.write_all(&response).unwrap();
+        metrics.record_latency(stopwatch.elapsed());
+        return;
+    }
+
+    let (status, status_line, filename) = match &request_line[..] {
+        "GET / HTTP/1.1" => (200, "HTTP/1.1 200 OK", "index.html"),
+        _ => (404, "HTTP/1.1 404 NOT FOUND", "404.html")
+    };
+    metrics.record(status);
+
+    let contents = fs::read_to_string(filename).unwrap();
+    let length = contents.len();
+
+    let response =
+        format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+
+    stream.write_all(response.as_bytes()).unwrap();
+    metrics.record_latency(stopwatch.elapsed());
+}
+
+
+// WebSockets
+
+// GET /ws upgrades the connection to a WebSocket (src/websocket.rs), which echoes back every text message and pushes a "tick" every second.
+// To try it, open the browser console on http://127.0.0.1:7878 and run:
+//     let ws = new WebSocket("ws://127.0.0.1:7878/ws"); ws.onmessage = e => console.log(e.data); ws.send("hi");
+
+// A WebSocket stays open for as long as the client wants, and it occupies one of the pool's workers the whole time.
+// With 4 workers, a fifth WebSocket client would block every other request, so a real server would hand long lived connections to their own threads.
+
+fn mt_main_websocket() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+
+        pool.execute(|| {
+            handle_connection_with_websocket(stream);
+        });
+    }
+}
+
+fn handle_connection_with_websocket(mut stream: TcpStream) {
+    // Unlike the other handlers we need the headers too, not just the request line
+    let buf_reader = BufReader::new(&mut stream);
+    let request: Vec<String> = buf_reader
+        .lines()
+        .map(|line| line.unwrap())
+        .take_while(|line| !line.is_empty())
+        .collect();
+
+    if request[0].starts_with("GET /ws ") {
+        let mut ws = match WebSocket::accept(stream, &request) {
+            Ok(ws) => ws,
+            Err(e) => {
+                warn!("websocket handshake failed: {e}");
+                return;
+            }
+        };
+
+        // The ticker pushes from its own thread through a WsSender, and stops as soon as a send fails because the client went away
+        let sender = ws.sender();
+        thread::spawn(move || {
+            for n in 1.. {
+                thread::sleep(Duration::from_secs(1));
+                if sender.send_text(&format!("tick {n}")).is_err() {
+                    break;
+                }
+            }
+        });
+
+        while let Ok(message) = ws.recv() {
+            let sent = match message {
+                Message::Text(text) => ws.send(Message::Text(format!("echo: {text}"))),
+                Message::Close(_) => break,
+                _ => Ok(()),
+            };
+            if sent.is_err() {
+                break;
+            }
+        }
+        return;
+    }
+
+    let (status_line, filename) = match &request[0][..] {
+        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "index.html"),
+        _ => ("HTTP/1.1 404 NOT FOUND", "404.html")
+    };
+
+    let contents = fs::read_to_string(filename).unwrap();
+    let length = contents.len();
+
+    let response =
+        format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+
+    stream.write_all(response.as_bytes()).unwrap();
+}
+
+
+// Server-Sent Events
+
+// GET /events streams the server metrics as server-sent events (src/sse.rs), one every second, using the Request and Response types from src/http.rs.
+// Try it with: curl -N http://127.0.0.1:7878/events
+
+// The event_stream closure runs on the connection's worker, so it hands the sender to its own thread and returns straight away.
+// That thread stops as soon as a send fails, which is how it finds out the client has gone.
+
+fn mt_main_events() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+    let metrics = Arc::new(Metrics::new());
+
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+        let metrics = Arc::clone(&metrics);
+
+        pool.execute(move || {
+            handle_connection_with_events(stream, metrics);
+        });
+    }
+}
+
+fn handle_connection_with_events(mut stream: TcpStream, metrics: Arc<Metrics>) {
+    let request = match Request::read_from(&mut BufReader::new(&mut stream)) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("bad request: {e}");
+            return;
+        }
+    };
+
+    let response = match (&request.method[..], &request.path[..]) {
+        ("GET", "/events") => {
+            metrics.record(200);
+            Response::event_stream(move |events| {
+                thread::spawn(move || {
+                    for n in 1.. {
+                        let snapshot = metrics.snapshot();
+                        let data = format!(
+                            "uptime={}s requests={} not_found={}",
+                            snapshot.uptime_secs, snapshot.requests, snapshot.not_found
+                        );
+                        if events.send(Event::new(&data).id(&n.to_string()).event("metrics")).is_err() {
+                            break;
+                        }
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                });
+            })
+        }
+        ("GET", "/") => {
+            metrics.record(200);
+            Response::html(200, &fs::read_to_string("index.html").unwrap())
+        }
+        _ => {
+            metrics.record(404);
+            Response::html(404, &fs::read_to_string("404.html").unwrap())
+        }
+    };
+
+    if let Err(e) = response.write_to(&mut stream) {
+        error!("failed to send response: {e}");
+    }
+}
+
+
+// Cookies and Sessions
+
+// The handler below counts how many times each browser has visited, using a session (src/session.rs) to remember the count.
+// SessionMiddleware wraps the handler in a Chain (src/middleware.rs), and the Chain is shared by all workers through an Arc.
+// Reload http://127.0.0.1:7878 a few times, then try a private window: it gets its own count, because it doesn't have the cookie.
+
+fn mt_main_sessions() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+
+    // In a real deployment the key would come from configuration, not from the source code
+    let store = Arc::new(MemoryStore::new());
+    let app = Arc::new(
+        Chain::new(|req: &mut Request| {
+            let session = req.session().unwrap();
+            let visits: u32 = session.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0) + 1;
+            session.insert("visits", &visits.to_string());
+            Response::html(200, &format!("<h1>Visit number {visits}</h1>"))
+        })
+        .with(SessionMiddleware::new(store, b"change me"))
+        // Compresses the page for browsers that send Accept-Encoding (src/compress.rs)
+        .with(Compression::new())
+        // Turns away requests that carry things like "<script" before they reach the sessions (src/body_filter.rs)
+        .with(BodyFilter::with_default_rules()),
+    );
+
+    for stream in listener.incoming() {
+        let app = Arc::clone(&app);
+
+        pool.execute(move || {
+            // If the handler panics, the guard's cleanup still gets the stream while the panic unwinds and answers 500,
+            // instead of the browser waiting on a connection that just closes (src/guard.rs).
+            // The panic still ends the worker's thread, so a handler that keeps panicking slowly empties the pool.
+            let mut stream = ScopeGuard::on_unwind(stream.unwrap(), |mut stream: TcpStream| {
+                let _ = Response::text(500, "Internal Server Error").write_to(&mut stream);
+            });
+            if let Ok(mut request) = Request::read_from(&mut BufReader::new(&mut *stream)) {
+                let _ = app.handle(&mut request).write_to(&mut *stream);
+            }
+        });
+    }
+}
+
+
+// File Uploads
+
+// POST /upload accepts a multipart/form-data form (src/multipart.rs) and replies with what it received.
+// The request is read with read_head, so the body is still in the connection, and multipart_stream parses the parts straight from it:
+// uploaded files go to the uploads/ directory chunk by chunk and are never held in memory as a whole.
+// Try it with: curl -F title=holiday -F photo=@some_file.jpg http://127.0.0.1:7878/upload
+
+fn mt_main_uploads() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+
+        pool.execute(|| {
+            handle_connection_with_uploads(stream);
+        });
+    }
+}
+
+fn handle_connection_with_uploads(mut stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let request = match Request::read_head(&mut reader) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("bad request: {e}");
+            return;
+        }
+    };
+
+    let response = if request.method == "POST" && request.path_only() == "/upload" {
+        fs::create_dir_all("uploads").unwrap();
+        let limits = MultipartLimits { upload_dir: "uploads".into(), max_file_size: 10 * 1024 * 1024, ..MultipartLimits::default() };
+
+        // A form is only accepted whole: if a later part fails, the files saved for the earlier parts are deleted again
+        // when the Transaction is dropped without commit (src/guard.rs)
+        let mut summary = String::new();
+        let mut upload = Transaction::new(&mut summary);
+        let result = request.multipart_stream(&mut reader, limits).and_then(|parts| {
+            for part in parts {
+                let part = part?;
+                match &part.data {
+                    PartData::File { path, size } => {
+                        upload.push_str(&format!("{}: {} bytes saved to {}\n", part.name, size, path.display()));
+                        let path = path.clone();
+                        upload.record(move |_| {
+                            let _ = fs::remove_file(path);
+                        });
+                    }
+                    PartData::Memory(_) => upload.push_str(&format!("{}: {:?}\n", part.name, part.text().unwrap_or(""))),
+                }
+            }
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {
+                upload.commit();
+                Response::text(200, &summary)
+            }
+            Err(e) => {
+                drop(upload);
+                Response::text(400, &e.to_string())
+            }
+        }
+    } else {
+        Response::html(404, &fs::read_to_string("404.html").unwrap())
+    };
+
+    let _ = response.write_to(&mut stream);
+}
+
+
+// Publish/Subscribe
+
+// A tiny chat room: every message posted to it is streamed to all connected clients, using a broadcast channel
+// (concurrency/src/broadcast.rs) that every worker has a Sender of.
+    // POST /chat with a urlencoded "message" field publishes a message.
+    // GET /chat/events streams the room as server-sent events: curl -N http://127.0.0.1:7878/chat/events
+    // GET /chat/ws joins the room over a WebSocket, where messages sent by the client are published too.
+// The handlers never talk to each other, they only share the channel.
+
+fn mt_main_chat() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+    // A client falls at most 100 messages behind, one that falls further misses the oldest ones
+    let chat = Sender::new(100, Overflow::Skip);
+
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+        let chat = chat.clone();
+
+        pool.execute(move || {
+            handle_connection_with_chat(stream, chat);
+        });
+    }
+}
+
+fn handle_connection_with_chat(mut stream: TcpStream, chat: Sender<String>) {
+    let request = match Request::read_from(&mut BufReader::new(&mut stream)) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("bad request: {e}");
+            return;
+        }
+    };
+
+    let response = match (&request.method[..], request.path_only()) {
+        ("POST", "/chat") => match request.form::<FormData>() {
+            Ok(form) => match form.get("message") {
+                Some(message) => {
+                    let delivered = chat.send(message.to_string());
+                    Response::text(200, &format!("delivered to {delivered} clients\n"))
+                }
+                None => Response::text(400, "missing message\n"),
+            },
+            Err(e) => Response::text(400, &e.to_string()),
+        },
+        ("GET", "/chat/events") => {
+            let mut messages = chat.subscribe();
+            Response::event_stream(move |events| {
+                // Waiting with a timeout lets the thread notice a client that left while the room was quiet
+                thread::spawn(move || {
+                    while !events.is_closed() {
+                        let event = match messages.recv_timeout(Duration::from_secs(5)) {
+                            Ok(Some(message)) => Event::new(&message).event("chat"),
+                            Ok(None) => continue,
+                            Err(RecvError::Lagged(missed)) => Event::new(&missed.to_string()).event("missed"),
+                            Err(RecvError::Closed) => break,
+                        };
+                        if events.send(event).is_err() {
+                            break;
+                        }
+                    }
+                });
+            })
+        }
+        ("GET", "/chat/ws") => {
+            // WebSocket::accept works with the raw header lines, so we put them back together
+            let mut lines = vec![format!("{} {} {}", request.method, request.path, request.version)];
+            lines.extend(request.headers.iter().map(|(name, value)| format!("{name}: {value}")));
+            let mut ws = match WebSocket::accept(stream, &lines) {
+                Ok(ws) => ws,
+                Err(e) => {
+                    warn!("websocket handshake failed: {e}");
+                    return;
+                }
+            };
+
+            // Dropping the receiver when the forwarding thread ends is what removes this client from the room
+            let mut messages = chat.subscribe();
+            let sender = ws.sender();
+            thread::spawn(move || loop {
+                let sent = match messages.recv_timeout(Duration::from_secs(5)) {
+                    Ok(Some(message)) => sender.send_text(&message),
+                    Ok(None) => sender.send(Message::Ping(Vec::new())),
+                    Err(RecvError::Lagged(missed)) => sender.send_text(&format!("(missed {missed} messages)")),
+                    Err(RecvError::Closed) => break,
+                };
+                if sent.is_err() {
+                    break;
+                }
+            });
+
+            while let Ok(message) = ws.recv() {
+                match message {
+                    Message::Text(text) => {
+                        chat.send(text);
+                    }
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+            return;
+        }
+        ("GET", "/") => Response::html(200, &fs::read_to_string("index.html").unwrap()),
+        _ => Response::html(404, &fs::read_to_string("404.html").unwrap()),
+    };
+
+    let _ = response.write_to(&mut stream);
+}
+
+
+// An Acceptor Thread and a Parser Thread
+
+// In the pools above, the thread that accepts connections also sends every one of them through the pool's channel.
+// Here the work is split into a pipeline: one thread only accepts, one thread only reads request heads, and the pool writes the responses.
+// Between the acceptor and the parser there is exactly one sender and one receiver, so instead of a channel (which takes a lock for every message)
+// they share the lock-free SPSC ring buffer from the concurrency project (concurrency_parallelism/concurrency/src/ring.rs).
+
+// This is an optional fast path, only worth it when accepting connections is the bottleneck. Its weak spot is the single parser:
+// a client that connects and sends nothing would hold up everyone behind it, so the parser gives each client a short read timeout.
+
+fn mt_main_pipeline() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+    let mut ring = RingBuffer::new(256);
+
+    // thread::scope lets both threads borrow the ring buffer, no Arc needed
+    thread::scope(|scope| {
+        let (mut producer, consumer) = ring.split();
+
+        scope.spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // push only fails when the parser has stopped, then there is no point in accepting more
+                if producer.push(stream).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for mut stream in consumer {
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+            let request = match Request::read_from(&mut BufReader::new(&mut stream)) {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("bad request: {e}");
+                    continue;
+                }
+            };
+
+            pool.execute(move || {
+                let response = match (&request.method[..], &request.path[..]) {
+                    ("GET", "/") => Response::html(200, &fs::read_to_string("index.html").unwrap()),
+                    _ => Response::html(404, &fs::read_to_string("404.html").unwrap()),
+                };
+                let _ = response.write_to(&mut stream);
+            });
+        }
+    });
+}
+
+
+// Reusing Read Buffers
+
+// Every connection above gets a new BufReader, and with it a new 8 KB buffer. Here the buffers come from an ObjectPool (src/pool.rs) instead,
+// and go back to it when the connection is done. Each worker thread keeps a few in its own cache, so after the first requests
+// nearly every checkout is a hit and no buffer is allocated at all.
+// GET /pool shows the pool's numbers: try it after loading / a few times.
+
+fn mt_main_pooled() {
+    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let pool = ThreadPool::new(4);
+    let buffers = Arc::new(read_buffer_pool());
+
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+        let buffers = Arc::clone(&buffers);
+
+        pool.execute(move || {
+            handle_connection_with_pooled_buffer(stream, &buffers);
+        });
+    }
+}
+
+fn handle_connection_with_pooled_buffer(mut stream: TcpStream, buffers: &ObjectPool<Vec<u8>>) {
+    let request = {
+        // The reader (and the buffer with it) is dropped at the end of this block, before the response is written
+        let mut reader = PooledReader::new(&mut stream, buffers.checkout());
+        match Request::read_from(&mut reader) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("bad request: {e}");
+                return;
+            }
+        }
+    };
+
+    let response = match (&request.method[..], &request.path[..]) {
+        ("GET", "/pool") => {
+            let stats = buffers.stats();
+            Response::text(
+                200,
+                &format!(
+                    "checkouts: {}\nhit rate: {:.1}%\nin use: {}\nhigh-water mark: {}\n",
+                    stats.checkouts,
+                    stats.hit_rate() * 100.0,
+                    stats.in_use,
+                    stats.high_water
+                ),
+            )
+        }
+        ("GET", "/") => Response::html(200, &fs::read_to_string("index.html").unwrap()),
+        _ => Response::html(404, &fs::read_to_string("404.html").unwrap()),
+    };
+
+    let _ = response.write_to(&mut stream);
+}
+
+
+// Configuring the Server with a Type-State Builder
+
+// Every mt_main_* above repeats the same bind, ThreadPool::new and accept loop. ServerBuilder (src/server.rs) puts them in one place,
+// and checks at compile time that the server has an address and a handler: leave out .bind(..) below and build() doesn't exist.
+// MetricsMiddleware times every request, and GET /metrics shows the latency percentiles as text.
+
+fn mt_main_builder() {
+    let metrics = Arc::new(Metrics::new());
+    let app = {
+        let snapshots = Arc::clone(&metrics);
+        Chain::new(move |req: &mut Request| match req.path_only() {
+            "/" => Response::html(200, &fs::read_to_string("index.html").unwrap()),
+            "/metrics" => {
+                let m = snapshots.snapshot();
+                Response::text(200, &format!("requests: {}\np50: {} us\np95: {} us\np99: {} us\n", m.requests, m.p50_us, m.p95_us, m.p99_us))
+            }
+            _ => Response::html(404, &fs::read_to_string("404.html").unwrap()),
+        })
+        .with(MetricsMiddleware::new(metrics))
+    };
+
+    let server = ServerBuilder::new().threads(4).bind("127.0.0.1:7878").handler(app).build();
+    info!("listening on {} with {} threads", server.addr(), server.threads());
+    if let Err(e) = server.run() {
+        error!("server stopped: {e}");
+    }
+}
+
+
+// Reloading the Browser when a File Changes
+
+// Open http://127.0.0.1:7878, then edit index.html and save it: the page reloads by itself.
+// LiveReload (src/live_reload.rs) polls the pages with a PollWatcher (src/watch.rs) and tells the open tabs through an event stream.
+// Each open tab keeps a worker busy with its stream, hence the 8 threads.
+
+fn mt_main_live_reload() {
+    let live_reload = LiveReload::new(&["index.html", "404.html"], Duration::from_millis(500)).unwrap();
+    let app = Chain::new(|req: &mut Request| match req.path_only() {
+        "/" => Response::html(200, &fs::read_to_string("index.html").unwrap()),
+        _ => Response::html(404, &fs::read_to_string("404.html").unwrap()),
+    })
+    .with(live_reload);
+
+    let server = ServerBuilder::new().threads(8).bind("127.0.0.1:7878").handler(app).build();
+    if let Err(e) = server.run() {
+        error!("server stopped: {e}");
+    }
+}
+
+
+// Handlers That Return Errors
+
+// With Chain::try_new the handler returns a Result, and ? turns a missing file into a 404 and a bad ?id= into a 400 (src/error.rs).
+// curl -H "Accept: application/json" http://127.0.0.1:7878/user?id=x gets the error as JSON, a browser gets a page.
+// GET /panic panics, and CatchPanic answers 500 without losing the worker or the connection. The panic is written to a crash
+// report in crash-reports/ (src/panic_report.rs), and the log says which one.
+
+fn mt_main_errors() {
+    if let Err(e) = panic_report::install("crash-reports") {
+        warn!("crash reports go to the log only: {e}");
+    }
+    let app = Chain::try_new(|req: &mut Request| match req.path_only() {
+        "/" => Ok(Response::html(200, &fs::read_to_string("index.html")?)),
+        "/user" => match req.query()?.parse_field::<u32>("id")? {
+            7 => Ok(Response::text(200, "ferris")),
+            id => Err(HandlerError::NotFound(format!("no such user: {id}"))),
+        },
+        "/panic" => panic!("a bug in the handler"),
+        _ => Err(HandlerError::NotFound(format!("no page at {}", req.path_only()))),
+    })
+    .with(CatchPanic::new());
+
+    let server = ServerBuilder::new().threads(4).bind("127.0.0.1:7878").handler(app).build();
+    if let Err(e) = server.run() {
+        error!("server stopped: {e}");
+    }
+}
+
+
+// Watching the Server from a Terminal
+
+// Start this, then in another terminal: cargo run --bin dashboard
+// MetricsEndpoint answers GET /metrics with the whole snapshot in the binary codec, which the dashboard decodes and draws.
+// set_workers tells the metrics how many threads there are, so the dashboard can show how many of them are busy,
+// and keep_recent has the logger hold on to its last lines, for the dashboard's log panel. Every crash report is counted too,
+// as the "panics" counter, by a listener on panic_report::events(). The snapshot has the memory, open files and threads of the
+// server as well (src/sysinfo_lite.rs), and a Watchdog logs a warning when one of them gets close to its limit, which then shows
+// up in the log panel.
+
+fn mt_main_dashboard() {
+    common::log::keep_recent(20);
+    let threads = 4;
+    let metrics = Arc::new(Metrics::new());
+    metrics.set_workers(threads);
+    // Counting for as long as the server runs, dropping the subscription would stop it
+    let _panics = {
+        let metrics = Arc::clone(&metrics);
+        panic_report::events().on(move |_| metrics.add("panics", 1))
+    };
+    // The open files are watched against ulimit -n. The pool's threads, the acceptor and a few more are all it should ever need
+    let limits = Limits { max_rss_bytes: Some(256 << 20), max_fds: None, max_threads: Some(threads as u64 + 8) };
+    let _watchdog = Watchdog::new(sysinfo_lite::system(), limits).spawn(Duration::from_secs(5));
+    let app = Chain::new(|req: &mut Request| {
+        info!("{} {}", req.method, req.path_only());
+        match req.path_only() {
+            "/" => Response::html(200, &fs::read_to_string("index.html").unwrap()),
+            _ => Response::html(404, &fs::read_to_string("404.html").unwrap()),
+        }
+    })
+    .with(MetricsMiddleware::new(Arc::clone(&metrics)))
+    .with(MetricsEndpoint::new(metrics));
+
+    let server = ServerBuilder::new().threads(threads).bind("127.0.0.1:7878").handler(app).build();
+    if let Err(e) = server.run() {
+        error!("server stopped: {e}");
+    }
+}
+
+
+// Shutting Down on Ctrl-C
+
+// mt_main_shutdown above stops after two requests, because that was the only way to see the pool shut down. A real server stops
+// when it's told to: Ctrl-C, or kill, which send it SIGINT and SIGTERM (projects/common/src/signals.rs).
+// bind() gives the Server before run(), and with it a ShutdownHandle. The hooks run in the order they're added: stop accepting,
+// which also closes each connection after the response it's working on, wait for run() to return once the workers are done,
+// and flush the log. Pressing Ctrl-C a second time ends the server without waiting.
+// It's also what --daemon runs, and then the last hook removes the PID file. A daemon has no terminal to press Ctrl-C in,
+// kill sends the SIGTERM.
+
+fn mt_main_signals(settings: ServerBuilder<Bound, NoHandler>, pid_file: Option<PidFile>) {
+    let app = Chain::new(|req: &mut Request| match req.path_only() {
+        "/" => Response::html(200, &fs::read_to_string("index.html").unwrap()),
+        _ => Response::html(404, &fs::read_to_string("404.html").unwrap()),
+    })
+    // Every request in a span, and the last thousand at /__traces (src/traces.rs)
+    .with(Tracing);
+    tracing::keep_finished(1000);
+    let server = settings.handler(app).build().bind().unwrap();
+
+    let handle = server.shutdown_handle();
+    let (drained, workers_done) = mpsc::channel::<()>();
+    let waiting = Shutdown::new()
+        .hook("stop accepting", move || handle.shutdown())
+        .hook("wait for the workers", move || {
+            // Only ever an error, when the sender is dropped below
+            let _ = workers_done.recv();
+        })
+        .hook("flush the log", || {
+            // There's nowhere left to report it
+            let _ = common::log::flush();
+        })
+        .hook("remove the PID file", move || drop(pid_file))
+        .on_signals(&Signal::ALL)
+        .unwrap();
+
+    info!("listening on {}, press Ctrl-C to stop", server.local_addr());
+    if let Err(e) = server.run() {
+        error!("server stopped: {e}");
+    }
+    drop(drained);
+    waiting.join().unwrap();
+}
@@ -0,0 +1,776 @@
+// Markup: Reading HTML and XML
+
+// live_reload.rs looked for </body> with a byte search, which also finds one in a comment or in a script's string. Reading
+// markup properly comes in two layers:
+    // 1. Tokenizer is a pull parser: every next() returns the next start tag, end tag, text, comment or declaration, as
+    //    slices of the input, and span() says where it was. Nothing is built, so it works on pages of any size, and
+    //    rewriting a page (a proxy pointing its links somewhere else) is copying the input with some spans replaced.
+    // 2. Document builds a tree of elements out of the tokens, and select() finds elements with a small part of CSS:
+    //    a, #id, .class, [attr], [attr=value] and *, in any combination, with "a b" for descendants and "a > b" for children.
+// Browsers never reject a page, they guess, and so does this:
+    // 1. A < that doesn't start a tag, or a tag the input ends in the middle of, is text.
+    // 2. The contents of <script>, <style>, <textarea> and <title> are text up to their end tag, whatever < they hold.
+    // 3. Void elements like <br> and <img> never have children, and an end tag closes everything opened after its start
+    //    tag. HTML lets some end tags be left out, so a <p> is closed by the next block, and an <li> by the next <li>.
+// Everything it had to guess is kept as a Problem, so a test can check that a page it generated is well-formed.
+// Names are compared ignoring ASCII case, and Document lowercases them. Text and attribute values in Document have their
+// character references decoded, the tokens keep them as they are in the input: decode_entities does it for them.
+
+use std::{borrow::Cow, fmt, iter::Peekable, ops::Range, str::Chars};
+
+// Tokens
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag<'a> {
+    pub name: &'a str,
+    // As they are in the input, values with their character references
+    pub attributes: Vec<(&'a str, &'a str)>,
+    // <br/>, which only means something in XML, HTML ignores the /
+    pub self_closing: bool,
+}
+
+impl<'a> Tag<'a> {
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    // The first attribute with the name, decoded. A valueless one like disabled has the empty string
+    pub fn attr(&self, name: &str) -> Option<Cow<'a, str>> {
+        let &(_, value) = self.attributes.iter().find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))?;
+        Some(decode_entities(value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token<'a> {
+    StartTag(Tag<'a>),
+    EndTag(&'a str),
+    Text(&'a str),
+    Comment(&'a str),
+    // <![CDATA[...]]>, text that isn't decoded
+    Cdata(&'a str),
+    // <!DOCTYPE html> or <?xml version="1.0"?>, what's between <! or <? and >
+    Declaration(&'a str),
+}
+
+// The elements whose contents are text, not markup
+const RAW_TEXT: [&str; 4] = ["script", "style", "textarea", "title"];
+
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    position: usize,
+    span: Range<usize>,
+    // Inside <script> and the like, the name of the end tag that ends the text
+    raw_text: Option<&'a str>,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Tokenizer<'a> {
+        Tokenizer { input, position: 0, span: 0..0, raw_text: None }
+    }
+
+    // Where in the input the token next() returned last was
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    fn emit(&mut self, token: Token<'a>, length: usize) -> Option<Token<'a>> {
+        self.span = self.position..self.position + length;
+        self.position += length;
+        Some(token)
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let rest = &self.input[self.position..];
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(name) = self.raw_text.take() {
+            let end = find_end_tag(rest, name).unwrap_or(rest.len());
+            if end > 0 {
+                return self.emit(Token::Text(&rest[..end]), end);
+            }
+        }
+        if let Some((token, length)) = markup(rest) {
+            if let Token::StartTag(tag) = &token {
+                if !tag.self_closing && RAW_TEXT.iter().any(|&name| tag.is(name)) {
+                    self.raw_text = Some(tag.name);
+                }
+            }
+            return self.emit(token, length);
+        }
+        // Up to the next <, a < that didn't start anything is text as well
+        let end = rest[1..].find('<').map_or(rest.len(), |i| i + 1);
+        self.emit(Token::Text(&rest[..end]), end)
+    }
+}
+
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | b'\x0C')
+}
+
+// Where </name starts, followed by something that ends the name
+fn find_end_tag(text: &str, name: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut from = 0;
+    while let Some(i) = text[from..].find("</").map(|i| from + i) {
+        let after = i + 2 + name.len();
+        let matches = bytes.get(i + 2..after).is_some_and(|candidate| candidate.eq_ignore_ascii_case(name.as_bytes()));
+        if matches && bytes.get(after).is_none_or(|&b| is_space(b) || b == b'>' || b == b'/') {
+            return Some(i);
+        }
+        from = i + 2;
+    }
+    None
+}
+
+// The token at the start of text and its length, None when it isn't markup after all
+fn markup(text: &str) -> Option<(Token<'_>, usize)> {
+    if !text.starts_with('<') {
+        return None;
+    }
+    let bytes = text.as_bytes();
+    if let Some(comment) = text.strip_prefix("<!--") {
+        return Some(match comment.find("-->") {
+            Some(end) => (Token::Comment(&comment[..end]), end + 7),
+            // A comment the input ends in runs to the end
+            None => (Token::Comment(comment), text.len()),
+        });
+    }
+    if let Some(cdata) = text.strip_prefix("<![CDATA[") {
+        let end = cdata.find("]]>")?;
+        return Some((Token::Cdata(&cdata[..end]), end + 12));
+    }
+    match bytes.get(1) {
+        Some(b'!' | b'?') => {
+            let end = text.find('>')?;
+            Some((Token::Declaration(text[2..end].trim_end_matches('?')), end + 1))
+        }
+        Some(b'/') if bytes.get(2).is_some_and(u8::is_ascii_alphabetic) => {
+            let name_end = name_end(bytes, 2);
+            // Attributes on an end tag mean nothing
+            let end = text.find('>')?;
+            Some((Token::EndTag(&text[2..name_end]), end + 1))
+        }
+        Some(first) if first.is_ascii_alphabetic() => start_tag(text),
+        _ => None,
+    }
+}
+
+fn name_end(bytes: &[u8], from: usize) -> usize {
+    (from..bytes.len()).find(|&i| is_space(bytes[i]) || matches!(bytes[i], b'/' | b'>')).unwrap_or(bytes.len())
+}
+
+fn start_tag(text: &str) -> Option<(Token<'_>, usize)> {
+    let bytes = text.as_bytes();
+    let mut i = name_end(bytes, 1);
+    let name = &text[1..i];
+    let mut attributes = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while i < bytes.len() && (is_space(bytes[i]) || bytes[i] == b'/') {
+            self_closing = bytes[i] == b'/';
+            i += 1;
+        }
+        match bytes.get(i)? {
+            b'>' => break,
+            _ => self_closing = false,
+        }
+
+        // A name can start with =, <p =x> has an attribute called "=x"
+        let start = i;
+        i += 1;
+        while i < bytes.len() && !is_space(bytes[i]) && !matches!(bytes[i], b'/' | b'>' | b'=') {
+            i += 1;
+        }
+        let attribute = &text[start..i];
+        while i < bytes.len() && is_space(bytes[i]) {
+            i += 1;
+        }
+        if bytes.get(i) != Some(&b'=') {
+            attributes.push((attribute, ""));
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && is_space(bytes[i]) {
+            i += 1;
+        }
+        let value = match bytes.get(i)? {
+            &quote @ (b'"' | b'\'') => {
+                let end = i + 1 + text[i + 1..].find(quote as char)?;
+                let value = &text[i + 1..end];
+                i = end + 1;
+                value
+            }
+            _ => {
+                let start = i;
+                while i < bytes.len() && !is_space(bytes[i]) && bytes[i] != b'>' {
+                    i += 1;
+                }
+                &text[start..i]
+            }
+        };
+        attributes.push((attribute, value));
+    }
+    Some((Token::StartTag(Tag { name, attributes, self_closing }), i + 1))
+}
+
+// &amp;, &lt;, &gt;, &quot;, &apos;, &nbsp; and the numeric &#233; and &#xE9;. Anything else is left as it is,
+// the full list of named references has more than two thousand
+pub fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        decoded.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let reference = rest[1..].find(';').filter(|&end| end <= 10).map(|end| &rest[1..end + 1]);
+        match reference.and_then(character_reference) {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[reference.map_or(0, str::len) + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+fn character_reference(name: &str) -> Option<char> {
+    let code = match name {
+        "amp" => return Some('&'),
+        "lt" => return Some('<'),
+        "gt" => return Some('>'),
+        "quot" => return Some('"'),
+        "apos" => return Some('\''),
+        "nbsp" => return Some('\u{A0}'),
+        _ => name.strip_prefix('#')?,
+    };
+    let code = match code.strip_prefix(['x', 'X']) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => code.parse().ok()?,
+    };
+    char::from_u32(code)
+}
+
+// Documents
+
+// Elements that never have children or an end tag
+const VOID: [&str; 13] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+// Elements HTML allows to be left open, closing them isn't a problem
+const OPTIONAL_END: [&str; 15] =
+    ["html", "head", "body", "p", "li", "dt", "dd", "option", "tr", "td", "th", "thead", "tbody", "tfoot", "colgroup"];
+
+// Elements that close the one before them when it has the same name, <li>one<li>two
+const CLOSES_ITSELF: [&str; 7] = ["p", "li", "dt", "dd", "option", "tr", "td"];
+
+// Blocks that close an open <p>
+const CLOSES_P: [&str; 22] = [
+    "address", "article", "aside", "blockquote", "div", "dl", "fieldset", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6",
+    "header", "hr", "main", "nav", "ol", "pre", "ul",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Child {
+    Element(usize),
+    Text(String),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    name: String,
+    attributes: Vec<(String, String)>,
+    // The root's parent is itself
+    parent: usize,
+    children: Vec<Child>,
+    span: Range<usize>,
+}
+
+// A parsed page. Its elements are kept in one Vec in the order they start in, with the root at 0: a parent always comes
+// before its children, and an Element is an index into it
+#[derive(Debug, Clone)]
+pub struct Document {
+    nodes: Vec<Node>,
+    problems: Vec<Problem>,
+}
+
+impl Document {
+    pub fn parse(input: &str) -> Document {
+        let root = Node { name: String::new(), attributes: Vec::new(), parent: 0, children: Vec::new(), span: 0..input.len() };
+        let mut document = Document { nodes: vec![root], problems: Vec::new() };
+        let line = |offset: usize| input[..offset].matches('\n').count() + 1;
+        // The elements that are open, innermost last
+        let mut open = vec![0];
+
+        let mut tokenizer = Tokenizer::new(input);
+        while let Some(token) = tokenizer.next() {
+            let span = tokenizer.span();
+            match token {
+                Token::StartTag(tag) => {
+                    let name = tag.name.to_ascii_lowercase();
+                    let current = &document.nodes[*open.last().unwrap()].name;
+                    let closes_p = current == "p" && CLOSES_P.contains(&name.as_str());
+                    if closes_p || (current == &name && CLOSES_ITSELF.contains(&name.as_str())) {
+                        let closed = open.pop().unwrap();
+                        document.nodes[closed].span.end = span.start;
+                    }
+
+                    let parent = *open.last().unwrap();
+                    let attributes = tag
+                        .attributes
+                        .iter()
+                        .map(|(attribute, value)| (attribute.to_ascii_lowercase(), decode_entities(value).into_owned()))
+                        .collect();
+                    let index = document.nodes.len();
+                    let void = tag.self_closing || VOID.contains(&name.as_str());
+                    document.nodes.push(Node { name, attributes, parent, children: Vec::new(), span: span.clone() });
+                    document.nodes[parent].children.push(Child::Element(index));
+                    if !void {
+                        open.push(index);
+                    }
+                }
+                Token::EndTag(name) => {
+                    let name = name.to_ascii_lowercase();
+                    match open.iter().rposition(|&index| index != 0 && document.nodes[index].name == name) {
+                        Some(at) => {
+                            for &index in &open[at + 1..] {
+                                let node = &mut document.nodes[index];
+                                node.span.end = span.start;
+                                if !OPTIONAL_END.contains(&node.name.as_str()) {
+                                    let message = format!("<{}> isn't closed before </{name}>", node.name);
+                                    document.problems.push(Problem { line: line(span.start), message });
+                                }
+                            }
+                            document.nodes[open[at]].span.end = span.end;
+                            open.truncate(at);
+                        }
+                        // </br> is a mistake browsers forgive without a word
+                        None if VOID.contains(&name.as_str()) => {}
+                        None => {
+                            let message = format!("</{name}> without an open <{name}>");
+                            document.problems.push(Problem { line: line(span.start), message });
+                        }
+                    }
+                }
+                Token::Text(text) => document.push_text(*open.last().unwrap(), &decode_entities(text)),
+                Token::Cdata(text) => document.push_text(*open.last().unwrap(), text),
+                Token::Comment(_) | Token::Declaration(_) => {}
+            }
+        }
+
+        for &index in &open[1..] {
+            let node = &mut document.nodes[index];
+            node.span.end = input.len();
+            if !OPTIONAL_END.contains(&node.name.as_str()) {
+                let message = format!("<{}> isn't closed", node.name);
+                document.problems.push(Problem { line: line(node.span.start), message });
+            }
+        }
+        document
+    }
+
+    // Next to text that's there already, "a &amp; b" is three tokens but one piece of text
+    fn push_text(&mut self, parent: usize, text: &str) {
+        match self.nodes[parent].children.last_mut() {
+            Some(Child::Text(previous)) => previous.push_str(text),
+            _ => self.nodes[parent].children.push(Child::Text(text.to_string())),
+        }
+    }
+
+    // What had to be guessed while parsing, nothing for a well-formed page
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    // The element everything is inside, it has no name of its own
+    pub fn root(&self) -> Element<'_> {
+        Element { document: self, index: 0 }
+    }
+
+    pub fn select(&self, selector: &str) -> Result<Vec<Element<'_>>, SelectorError> {
+        self.root().select(selector)
+    }
+
+    // The first match, for when there should be one
+    pub fn find(&self, selector: &str) -> Result<Option<Element<'_>>, SelectorError> {
+        Ok(self.select(selector)?.into_iter().next())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Element<'d> {
+    document: &'d Document,
+    index: usize,
+}
+
+impl fmt::Debug for Element<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{}> at {:?}", self.name(), self.span())
+    }
+}
+
+impl PartialEq for Element<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.document, other.document) && self.index == other.index
+    }
+}
+
+impl<'d> Element<'d> {
+    fn node(&self) -> &'d Node {
+        &self.document.nodes[self.index]
+    }
+
+    // Lowercase
+    pub fn name(&self) -> &'d str {
+        &self.node().name
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&'d str> {
+        let node = self.node();
+        node.attributes.iter().find(|(attribute, _)| attribute.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = (&'d str, &'d str)> {
+        self.node().attributes.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        self.attr("class").is_some_and(|classes| classes.split_ascii_whitespace().any(|c| c == class))
+    }
+
+    pub fn parent(&self) -> Option<Element<'d>> {
+        match self.index {
+            0 => None,
+            _ => Some(Element { document: self.document, index: self.node().parent }),
+        }
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = Element<'d>> {
+        let document = self.document;
+        self.node().children.iter().filter_map(move |child| match child {
+            Child::Element(index) => Some(Element { document, index: *index }),
+            Child::Text(_) => None,
+        })
+    }
+
+    // All the text inside it, of the children and their children
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.collect_text(&mut text);
+        text
+    }
+
+    fn collect_text(&self, text: &mut String) {
+        for child in &self.node().children {
+            match child {
+                Child::Text(piece) => text.push_str(piece),
+                &Child::Element(index) => Element { document: self.document, index }.collect_text(text),
+            }
+        }
+    }
+
+    // Where it is in the input, from its start tag to the end of its end tag
+    pub fn span(&self) -> Range<usize> {
+        self.node().span.clone()
+    }
+
+    fn is_inside(&self, ancestor: usize) -> bool {
+        let mut index = self.index;
+        while index != 0 {
+            index = self.document.nodes[index].parent;
+            if index == ancestor {
+                return true;
+            }
+        }
+        false
+    }
+
+    // The elements inside this one that match, in the order they are in the document
+    pub fn select(&self, selector: &str) -> Result<Vec<Element<'d>>, SelectorError> {
+        let selector = Selector::parse(selector)?;
+        let document = self.document;
+        let matches = (self.index + 1..document.nodes.len())
+            .map(|index| Element { document, index })
+            .filter(|element| element.is_inside(self.index) && selector.matches(*element))
+            .collect();
+        Ok(matches)
+    }
+}
+
+// Selectors
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorError {
+    pub selector: String,
+    pub message: String,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad selector {:?}: {}", self.selector, self.message)
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+// One compound selector, like a.external[href]
+#[derive(Debug, Clone, Default)]
+struct Compound {
+    name: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Compound {
+    fn matches(&self, element: Element) -> bool {
+        self.name.as_ref().is_none_or(|name| element.name() == name)
+            && self.id.as_ref().is_none_or(|id| element.attr("id") == Some(id))
+            && self.classes.iter().all(|class| element.has_class(class))
+            && self.attributes.iter().all(|(name, value)| match (element.attr(name), value) {
+                (Some(actual), Some(value)) => actual == value,
+                (actual, None) => actual.is_some(),
+                (None, Some(_)) => false,
+            })
+    }
+}
+
+// The compounds, each with how it's related to the one before it
+struct Selector {
+    steps: Vec<(Combinator, Compound)>,
+}
+
+impl Selector {
+    fn parse(selector: &str) -> Result<Selector, SelectorError> {
+        let error = |message: &str| SelectorError { selector: selector.to_string(), message: message.to_string() };
+        let mut chars = selector.chars().peekable();
+        let mut steps = Vec::new();
+        loop {
+            let mut combinator = Combinator::Descendant;
+            while let Some(&c) = chars.peek() {
+                match c {
+                    '>' => combinator = Combinator::Child,
+                    c if c.is_whitespace() => {}
+                    _ => break,
+                }
+                chars.next();
+            }
+            if chars.peek().is_none() {
+                if combinator == Combinator::Child {
+                    return Err(error("nothing after >"));
+                }
+                break;
+            }
+            if steps.is_empty() && combinator == Combinator::Child {
+                return Err(error("nothing before >"));
+            }
+
+            let mut compound = Compound::default();
+            let mut any = false;
+            while let Some(&c) = chars.peek() {
+                match c {
+                    '*' => {
+                        chars.next();
+                        any = true;
+                    }
+                    '#' | '.' => {
+                        chars.next();
+                        let name = take_name(&mut chars);
+                        if name.is_empty() {
+                            return Err(error(&format!("{c} without a name")));
+                        }
+                        match c {
+                            '#' => compound.id = Some(name),
+                            _ => compound.classes.push(name),
+                        }
+                    }
+                    '[' => {
+                        chars.next();
+                        let name = take_name(&mut chars).to_ascii_lowercase();
+                        let value = match chars.next() {
+                            Some(']') => None,
+                            Some('=') => {
+                                let value = match chars.peek() {
+                                    Some(&quote @ ('"' | '\'')) => {
+                                        chars.next();
+                                        let value: String = chars.by_ref().take_while(|&c| c != quote).collect();
+                                        value
+                                    }
+                                    _ => take_name(&mut chars),
+                                };
+                                if chars.next() != Some(']') {
+                                    return Err(error("[ without ]"));
+                                }
+                                Some(value)
+                            }
+                            _ => return Err(error("[ without ]")),
+                        };
+                        if name.is_empty() {
+                            return Err(error("[] without an attribute"));
+                        }
+                        compound.attributes.push((name, value));
+                    }
+                    c if is_name(c) && compound.name.is_none() && !any => {
+                        compound.name = Some(take_name(&mut chars).to_ascii_lowercase());
+                    }
+                    c if c.is_whitespace() || c == '>' => break,
+                    c => return Err(error(&format!("unexpected {c}"))),
+                }
+            }
+            steps.push((combinator, compound));
+        }
+        if steps.is_empty() {
+            return Err(error("it's empty"));
+        }
+        Ok(Selector { steps })
+    }
+
+    fn matches(&self, element: Element) -> bool {
+        matches_steps(&self.steps, element)
+    }
+}
+
+fn is_name(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+fn take_name(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    while let Some(&c) = chars.peek().filter(|&&c| is_name(c)) {
+        name.push(c);
+        chars.next();
+    }
+    name
+}
+
+// The last step has to match the element itself, and the ones before it its parent or one of its ancestors
+fn matches_steps(steps: &[(Combinator, Compound)], element: Element) -> bool {
+    let Some(((combinator, compound), before)) = steps.split_last() else {
+        return true;
+    };
+    if !compound.matches(element) {
+        return false;
+    }
+    if before.is_empty() {
+        return true;
+    }
+    let mut ancestor = element.parent();
+    while let Some(candidate) = ancestor.filter(|candidate| candidate.index != 0) {
+        if matches_steps(before, candidate) {
+            return true;
+        }
+        if *combinator == Combinator::Child {
+            return false;
+        }
+        ancestor = candidate.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes_tags_text_and_comments() {
+        let page = "<!DOCTYPE html><p class=\"a b\" id=x disabled>1 &lt; 2<!-- </p> --><br/></P>";
+        let tokens: Vec<Token> = Tokenizer::new(page).collect();
+        let tag = Tag { name: "p", attributes: vec![("class", "a b"), ("id", "x"), ("disabled", "")], self_closing: false };
+        let expected = vec![
+            Token::Declaration("DOCTYPE html"),
+            Token::StartTag(tag),
+            Token::Text("1 &lt; 2"),
+            Token::Comment(" </p> "),
+            Token::StartTag(Tag { name: "br", attributes: vec![], self_closing: true }),
+            Token::EndTag("P"),
+        ];
+        assert_eq!(expected, tokens);
+
+        let mut tokenizer = Tokenizer::new("ab<i>c</i>");
+        tokenizer.next();
+        tokenizer.next();
+        assert_eq!(2..5, tokenizer.span());
+        assert_eq!("1 < 2 & 3 é &bogus; &", decode_entities("1 &lt; 2 &amp; 3 &#xe9; &bogus; &"));
+    }
+
+    #[test]
+    fn malformed_markup_is_text() {
+        // A < that isn't a tag, a script holding tags, and a tag the input ends in
+        let page = "a < b <3 <script>if (a<b) document.write('</p>')</script><a href=\"x";
+        let tokens: Vec<Token> = Tokenizer::new(page).collect();
+        let expected = vec![
+            Token::Text("a "),
+            Token::Text("< b "),
+            Token::Text("<3 "),
+            Token::StartTag(Tag { name: "script", attributes: vec![], self_closing: false }),
+            Token::Text("if (a<b) document.write('</p>')"),
+            Token::EndTag("script"),
+            Token::Text("<a href=\"x"),
+        ];
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn builds_a_tree_and_reports_what_it_guessed() {
+        let page = "<ul>\n<li>one<li>two &amp; <b>three</b>\n</ul>\n<div><span>open</div></em>";
+        let document = Document::parse(page);
+        let items = document.select("li").unwrap();
+        assert_eq!(vec!["one", "two & three\n"], items.iter().map(Element::text).collect::<Vec<_>>());
+        assert_eq!(Some(items[0]), document.find("ul > li").unwrap());
+        assert_eq!(&page[items[1].span()], "<li>two &amp; <b>three</b>\n");
+
+        let problems: Vec<String> = document.problems().iter().map(Problem::to_string).collect();
+        assert_eq!(vec!["line 4: <span> isn't closed before </div>", "line 4: </em> without an open <em>"], problems);
+        assert!(Document::parse("<!DOCTYPE html><html><body><p>hi<br><img src=x></body></html>").problems().is_empty());
+    }
+
+    #[test]
+    fn selects_elements() {
+        let page = "<nav id=top><a href=/ class='home link'>Home</a><div><a href=/blog class=link>Blog</a></div></nav>\
+                    <a href=https://example.com target=_blank>Out</a><A HREF=\"/a?x=1&amp;y=2\">Amp</A>";
+        let document = Document::parse(page);
+        let texts = |selector: &str| -> Vec<String> { document.select(selector).unwrap().iter().map(Element::text).collect() };
+        assert_eq!(vec!["Home", "Blog"], texts("#top a"));
+        assert_eq!(vec!["Home"], texts("nav > a.link"));
+        assert_eq!(vec!["Home", "Blog"], texts(".link"));
+        assert_eq!(vec!["Out"], texts("a[target=_blank]"));
+        assert_eq!(vec!["Blog"], texts("div *[href='/blog']"));
+        assert_eq!(4, texts("[href]").len());
+
+        let links = document.select("a").unwrap();
+        assert_eq!((Some("/a?x=1&y=2"), "a"), (links[3].attr("HREF"), links[3].name()));
+        assert_eq!(Some("nav"), links[0].parent().map(|parent| parent.name()));
+        for bad in ["", "a >", "> a", "a[href", "#", "a:hover"] {
+            assert!(document.select(bad).is_err(), "{bad}");
+        }
+    }
+}
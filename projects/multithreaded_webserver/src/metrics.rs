@@ -0,0 +1,312 @@
+// Server Metrics
+
+// The counters are shared by every worker thread, so they are atomics rather than plain integers behind a Mutex.
+// Relaxed ordering is enough here: each counter is independent and we only ever read an approximate snapshot of them.
+// Response times go into a Histogram (src/histogram.rs), which turns them into the p50, p95 and p99 latencies of the snapshot.
+// Anything else worth counting, like the evictions of a cache (projects/kvstore), goes into a named counter with add().
+// Those are rare enough next to the requests that a Mutex around a map of them is fine, and nobody has to declare them first.
+// How busy the server is goes in too: every request takes a worker for as long as it runs, so the requests MetricsMiddleware
+// is in the middle of are the busy workers, out of the number set_workers was told. And the last lines of the log, when
+// common::log::keep_recent keeps any. And what the process takes up: its memory, open files and threads, from a
+// sysinfo_lite::Source (src/sysinfo_lite.rs), /proc unless with_source says otherwise. MetricsEndpoint serves all of it
+// at /metrics, for src/bin/dashboard.rs, or as JSON for anything else that asks for it with Accept: application/json,
+// like a monitoring system or curl.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use json::{JsonWriter, ToJson};
+
+use crate::{
+    codec::{self, DecodeError, Deserialize, Serialize},
+    error::wants_json,
+    guard::ScopeGuard,
+    histogram::Histogram,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    sysinfo_lite::{self, Source},
+    time_ext::Stopwatch,
+};
+
+pub struct Metrics {
+    started: Stopwatch,
+    requests: AtomicU64,
+    not_found: AtomicU64,
+    // In microseconds
+    latency: Histogram,
+    counters: Mutex<BTreeMap<String, u64>>,
+    workers: AtomicU64,
+    busy: AtomicU64,
+    usage: Arc<dyn Source>,
+}
+
+// The snapshot is what the /metrics endpoint sends to clients, encoded with the binary codec.
+// A client decodes it with codec::from_bytes::<MetricsSnapshot>. In JSON it's an object with a key per field, and the
+// counters are [name, count] pairs.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToJson)]
+pub struct MetricsSnapshot {
+    pub uptime_secs: u64,
+    pub requests: u64,
+    pub not_found: u64,
+    // Response times in microseconds, 0 before the first request
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    // The named counters, in name order
+    pub counters: Vec<(String, u64)>,
+    // Of all the workers, how many are running a request right now
+    pub workers: u64,
+    pub busy: u64,
+    // The recent lines of the log, the oldest first
+    pub log: Vec<String>,
+    // What the process takes up, 0 when the platform doesn't tell
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+    pub threads: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            started: Stopwatch::start(),
+            requests: AtomicU64::new(0),
+            not_found: AtomicU64::new(0),
+            latency: Histogram::new(),
+            counters: Mutex::new(BTreeMap::new()),
+            workers: AtomicU64::new(0),
+            busy: AtomicU64::new(0),
+            usage: sysinfo_lite::system(),
+        }
+    }
+
+    // Where the memory, open files and threads come from, a sysinfo_lite::FakeSource in tests
+    pub fn with_source(mut self, source: Arc<dyn Source>) -> Metrics {
+        self.usage = source;
+        self
+    }
+
+    // The threads of the ThreadPool serving the requests
+    pub fn set_workers(&self, workers: usize) {
+        self.workers.store(workers as u64, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if status == 404 {
+            self.not_found.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        self.latency.record(latency.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+
+    pub fn add(&self, counter: &str, n: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        match counters.get_mut(counter) {
+            Some(count) => *count += n,
+            None => {
+                counters.insert(counter.to_string(), n);
+            }
+        }
+    }
+
+    // 0 for a counter nothing was added to yet
+    pub fn counter(&self, counter: &str) -> u64 {
+        self.counters.lock().unwrap().get(counter).copied().unwrap_or(0)
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let percentile = |p| self.latency.percentile(p).unwrap_or(0);
+        let usage = self.usage.usage();
+        MetricsSnapshot {
+            uptime_secs: self.started.elapsed().as_secs(),
+            requests: self.requests.load(Ordering::Relaxed),
+            not_found: self.not_found.load(Ordering::Relaxed),
+            p50_us: percentile(50.0),
+            p95_us: percentile(95.0),
+            p99_us: percentile(99.0),
+            counters: self.counters.lock().unwrap().iter().map(|(name, count)| (name.clone(), *count)).collect(),
+            workers: self.workers.load(Ordering::Relaxed),
+            busy: self.busy.load(Ordering::Relaxed),
+            log: common::log::recent(),
+            rss_bytes: usage.rss_bytes.unwrap_or(0),
+            open_fds: usage.open_fds.unwrap_or(0),
+            threads: usage.threads.unwrap_or(0),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Counts every response and times how long the layers inside it took. Add it first, as the outermost layer, so it times all the others too.
+pub struct MetricsMiddleware {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsMiddleware {
+    pub fn new(metrics: Arc<Metrics>) -> MetricsMiddleware {
+        MetricsMiddleware { metrics }
+    }
+}
+
+impl Middleware for MetricsMiddleware {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let stopwatch = Stopwatch::start();
+        self.metrics.busy.fetch_add(1, Ordering::Relaxed);
+        // Not busy anymore after a panic either, CatchPanic may be further out
+        let busy = ScopeGuard::new(&self.metrics.busy, |busy| {
+            busy.fetch_sub(1, Ordering::Relaxed);
+        });
+        let response = next.run(request);
+        drop(busy);
+        self.metrics.record_latency(stopwatch.elapsed());
+        self.metrics.record(response.status);
+        response
+    }
+}
+
+// Answers GET /metrics with the snapshot, encoded with the binary codec, or in JSON when the client ranks that first.
+// The other requests go on to the next layer
+pub struct MetricsEndpoint {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsEndpoint {
+    pub fn new(metrics: Arc<Metrics>) -> MetricsEndpoint {
+        MetricsEndpoint { metrics }
+    }
+}
+
+impl Middleware for MetricsEndpoint {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        match (request.method.as_str(), request.path_only()) {
+            ("GET", "/metrics") if wants_json(request.header("Accept")) => {
+                // Straight from the snapshot into the body, and pretty, for people reading it with curl
+                let mut json = JsonWriter::pretty(Vec::new());
+                let body = self.metrics.snapshot().to_json(&mut json).and_then(|()| json.finish());
+                Response::new(200, body.expect("writing to a Vec can't fail"))
+                    .with_header("Content-Type", "application/json")
+                    .with_header("Cache-Control", "no-store")
+                    .with_header("Vary", "Accept")
+            }
+            ("GET", "/metrics") => Response::new(200, codec::to_bytes(&self.metrics.snapshot()))
+                .with_header("Content-Type", "application/octet-stream")
+                .with_header("Cache-Control", "no-store")
+                .with_header("Vary", "Accept"),
+            _ => next.run(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::Body,
+        middleware::Chain,
+        sysinfo_lite::{FakeSource, Usage},
+    };
+
+    #[test]
+    fn snapshot_round_trips_through_the_codec() {
+        let usage = Usage { rss_bytes: Some(24 << 20), open_fds: Some(12), threads: None, max_fds: Some(1024) };
+        let metrics = Metrics::new().with_source(Arc::new(FakeSource::new(usage)));
+        metrics.record(200);
+        metrics.record(404);
+        metrics.record(200);
+        metrics.add("cache_misses", 2);
+        metrics.add("cache_hits", 1);
+        metrics.add("cache_misses", 1);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(3, snapshot.requests);
+        assert_eq!(1, snapshot.not_found);
+        assert_eq!(vec![("cache_hits".to_string(), 1), ("cache_misses".to_string(), 3)], snapshot.counters);
+        assert_eq!((3, 0), (metrics.counter("cache_misses"), metrics.counter("nothing")));
+        // The threads aren't known
+        assert_eq!((24 << 20, 12, 0), (snapshot.rss_bytes, snapshot.open_fds, snapshot.threads));
+
+        let bytes = codec::to_bytes(&snapshot);
+        assert_eq!(snapshot, codec::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn middleware_reports_latency_percentiles() {
+        let metrics = Arc::new(Metrics::new());
+        assert_eq!((0, 0, 0), (metrics.snapshot().p50_us, metrics.snapshot().p95_us, metrics.snapshot().p99_us));
+
+        for ms in 1..=100 {
+            metrics.record_latency(Duration::from_millis(ms));
+        }
+        let snapshot = metrics.snapshot();
+        // Within the histogram's ~3%
+        assert!((50_000..=51_600).contains(&snapshot.p50_us), "{snapshot:?}");
+        assert!((95_000..=98_000).contains(&snapshot.p95_us), "{snapshot:?}");
+        assert!((99_000..=100_000).contains(&snapshot.p99_us), "{snapshot:?}");
+
+        let chain = Chain::new(|req: &mut Request| match req.path_only() {
+            "/" => Response::text(200, "home"),
+            _ => Response::text(404, "nope"),
+        })
+        .with(MetricsMiddleware::new(Arc::clone(&metrics)));
+        for path in ["/", "/missing", "/"] {
+            let raw = format!("GET {path} HTTP/1.1\r\n\r\n");
+            chain.handle(&mut Request::read_from(&mut raw.as_bytes()).unwrap());
+        }
+        let snapshot = metrics.snapshot();
+        assert_eq!((3, 1), (snapshot.requests, snapshot.not_found));
+        assert_eq!(103, metrics.latency.count());
+    }
+
+    #[test]
+    fn endpoint_serves_the_busy_workers() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.set_workers(4);
+        let seen = Arc::clone(&metrics);
+        let chain = Chain::new(move |_: &mut Request| Response::text(200, &seen.snapshot().busy.to_string()))
+            .with(MetricsMiddleware::new(Arc::clone(&metrics)))
+            .with(MetricsEndpoint::new(Arc::clone(&metrics)));
+        let get = |path: &str| {
+            let response = chain.handle(&mut Request::read_from(&mut format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes()).unwrap());
+            let content_type = response.header("Content-Type").map(str::to_string);
+            match response.body {
+                Body::Bytes(body) => (content_type, body),
+                Body::EventStream(_) => panic!("not a stream"),
+            }
+        };
+
+        // The handler runs as one of the busy workers, and the snapshot is taken in the middle of the request for it
+        assert_eq!(b"1", &get("/").1[..]);
+        let (content_type, body) = get("/metrics");
+        assert_eq!(Some("application/octet-stream"), content_type.as_deref());
+        let snapshot: MetricsSnapshot = codec::from_bytes(&body).unwrap();
+        assert_eq!((4, 1, 1), (snapshot.workers, snapshot.busy, snapshot.requests));
+    }
+
+    #[test]
+    fn endpoint_serves_json_when_asked() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.add("cache_hits", 2);
+        let chain = Chain::new(|_: &mut Request| Response::text(404, "nope")).with(MetricsEndpoint::new(Arc::clone(&metrics)));
+        let raw = "GET /metrics HTTP/1.1\r\nAccept: application/json\r\n\r\n";
+        let response = chain.handle(&mut Request::read_from(&mut raw.as_bytes()).unwrap());
+        assert_eq!(Some("application/json"), response.header("Content-Type"));
+        let Body::Bytes(body) = response.body else { panic!("not a stream") };
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.starts_with("{\n  \"uptime_secs\": "), "{body}");
+        assert!(body.contains("\"counters\": [\n    [\n      \"cache_hits\",\n      2\n    ]\n  ],"), "{body}");
+        assert!(body.contains("\"log\": []") || body.contains("\"log\": [\n"), "{body}");
+        assert!(body.contains(",\n  \"threads\": ") && body.ends_with("\n}\n"), "{body}");
+    }
+}
@@ -0,0 +1,184 @@
+// Middleware
+
+// Some work has to happen around every request no matter which page is asked for: loading the session, logging, compressing the response...
+// Instead of copying that code into every handler, we wrap the handler in layers of middleware.
+// Each layer gets the request, can change it, decides whether to call the next layer, and can change the response on the way back out:
+
+    // request -> [sessions] -> [logging] -> handler
+    // response <- [sessions] <- [logging] <- handler
+
+// A middleware that doesn't call next (for example because the user isn't logged in) answers the request itself and the handler never runs.
+
+use std::sync::Arc;
+
+use crate::{
+    error::HandlerError,
+    http::{Request, Response},
+};
+
+pub type Handler = Box<dyn Fn(&mut Request) -> Response + Send + Sync>;
+
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response;
+}
+
+// Plain closures work as middleware too, which is handy for small things like logging
+impl<F> Middleware for F
+where
+    F: Fn(&mut Request, Next<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+// The rest of the chain after the current middleware. Running it consumes it, so a middleware can't call the next layers twice.
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    handler: &'a Handler,
+}
+
+impl Next<'_> {
+    pub fn run(self, request: &mut Request) -> Response {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(request, Next { middleware: rest, handler: self.handler }),
+            None => (self.handler)(request),
+        }
+    }
+}
+
+// A handler together with its middleware. It is Send + Sync, so it can be put in an Arc and shared by all the ThreadPool workers.
+pub struct Chain {
+    middleware: Vec<Box<dyn Middleware>>,
+    handler: Handler,
+}
+
+impl Chain {
+    pub fn new<F>(handler: F) -> Chain
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        Chain { middleware: Vec::new(), handler: Box::new(handler) }
+    }
+
+    // For a handler that returns Result: an Err becomes the error page for its status (src/error.rs)
+    pub fn try_new<F>(handler: F) -> Chain
+    where
+        F: Fn(&mut Request) -> Result<Response, HandlerError> + Send + Sync + 'static,
+    {
+        Chain::new(move |request: &mut Request| handler(request).unwrap_or_else(|error| error.response(request)))
+    }
+
+    // The first middleware added is the outermost layer: it sees the request first and the response last
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Chain {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    // Adds a layer outside all the others, for the server's builder to wrap a Chain it was given
+    pub fn around<M: Middleware + 'static>(self, middleware: M) -> Chain {
+        self.around_boxed(Box::new(middleware))
+    }
+
+    pub(crate) fn around_boxed(mut self, middleware: Box<dyn Middleware>) -> Chain {
+        self.middleware.insert(0, middleware);
+        self
+    }
+
+    // Makes the state reachable from every layer and the handler with req.state::<T>(), see State below
+    pub fn with_state<T: Send + Sync + 'static>(self, state: Arc<T>) -> Chain {
+        self.around(State(state))
+    }
+
+    pub fn handle(&self, request: &mut Request) -> Response {
+        Next { middleware: &self.middleware, handler: &self.handler }.run(request)
+    }
+}
+
+// Shared Application State
+
+// A handler is a closure, and the state it needs (a database pool, the configuration, the metrics) could be moved into it.
+// That works for one handler, but a middleware that needs the same state would have to be given its own Arc,
+// and the closure has to be written where the state is. State puts one Arc in the extensions of every request instead,
+// as an Arc<T> keyed by its type, so anything that sees the request can get it back with req.state::<T>():
+
+    // let app = Chain::new(|req: &mut Request| {
+    //     let db = req.state::<Database>().unwrap();
+    //     ...
+    // })
+    // .with_state(Arc::new(Database::connect()));
+
+// Cloning the Arc for every request costs an atomic increment, and the state stays shared: every request sees the same one.
+// What changes while the server runs needs a lock or atomics inside it, like Metrics (src/metrics.rs) has.
+
+pub struct State<T>(pub Arc<T>);
+
+impl<T: Send + Sync + 'static> Middleware for State<T> {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        request.extensions_mut().insert(Arc::clone(&self.0));
+        next.run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: &str) -> Request {
+        let raw = format!("GET {path} HTTP/1.1\r\n\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn layers_run_in_order_and_can_short_circuit() {
+        let chain = Chain::new(|req: &mut Request| Response::text(200, &format!("handler saw {}", req.path)))
+            .with(|req: &mut Request, next: Next<'_>| {
+                let response = next.run(req);
+                response.with_header("X-Outer", "1")
+            })
+            .with(|req: &mut Request, next: Next<'_>| {
+                if req.path == "/secret" {
+                    return Response::text(403, "nope");
+                }
+                req.path = format!("{}!", req.path);
+                next.run(req)
+            });
+
+        let response = chain.handle(&mut request("/hello"));
+        assert_eq!(Some("1"), response.header("X-Outer"));
+        assert!(matches!(response.body, crate::http::Body::Bytes(ref b) if b == b"handler saw /hello!"));
+
+        let response = chain.handle(&mut request("/secret"));
+        assert_eq!(403, response.status);
+        assert_eq!(Some("1"), response.header("X-Outer"));
+    }
+
+    #[test]
+    fn layers_pass_values_on_in_the_extensions() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        #[derive(Clone)]
+        struct Depth(u32);
+        struct Hits(AtomicU32);
+
+        let chain = Chain::new(|req: &mut Request| {
+            let hits = req.state::<Hits>().unwrap().0.fetch_add(1, Ordering::Relaxed) + 1;
+            Response::text(200, &format!("depth {}, hit {hits}", req.extensions().get::<Depth>().unwrap().0))
+        })
+        .with(|req: &mut Request, next: Next<'_>| {
+            req.extensions_mut().insert(Depth(1));
+            next.run(req)
+        })
+        .with(|req: &mut Request, next: Next<'_>| {
+            req.extensions_mut().get_mut::<Depth>().unwrap().0 += 1;
+            next.run(req)
+        })
+        .with_state(Arc::new(Hits(AtomicU32::new(0))));
+
+        for hit in 1..=2 {
+            let response = chain.handle(&mut request("/"));
+            let expected = format!("depth 2, hit {hit}");
+            assert!(matches!(response.body, crate::http::Body::Bytes(ref b) if b == expected.as_bytes()));
+        }
+    }
+}
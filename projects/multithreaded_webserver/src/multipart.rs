@@ -0,0 +1,449 @@
+// File Uploads with multipart/form-data
+
+// A form with <input type="file"> can't be urlencoded, so browsers send it as multipart/form-data instead.
+// The body is a list of parts separated by a boundary string, which the browser picks so that it doesn't appear in any of the data
+// and announces in the Content-Type header:
+    // Content-Type: multipart/form-data; boundary=XyZ
+    //
+    // --XyZ
+    // Content-Disposition: form-data; name="title"
+    //
+    // My holiday
+    // --XyZ
+    // Content-Disposition: form-data; name="photo"; filename="beach.jpg"
+    // Content-Type: image/jpeg
+    //
+    // <the raw bytes of the file>
+    // --XyZ--                        <- the final boundary ends with "--"
+
+// Uploaded files can be much bigger than anything we want to hold in memory, so the parser streams:
+    // 1. It reads the body in small chunks and looks for "\r\n--boundary" in them, with memmem from common::bytesearch. A chunk
+    //    may end halfway through the boundary, so the last few bytes of each chunk are held back until the next chunk shows
+    //    whether they were a boundary or data.
+    // 2. Parts with a filename are written to a file in the upload directory as they arrive, other parts are kept in memory.
+    // 3. Both have a size limit, checked while streaming, so an oversized upload is refused before it fills the disk.
+
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use common::bytesearch::{find_crlf, memmem};
+
+use crate::ids::Uuid;
+
+#[derive(Debug)]
+pub enum MultipartError {
+    Io(io::Error),
+    NotMultipart,
+    MissingBoundary,
+    Malformed(&'static str),
+    TooLarge { name: String, limit: u64 },
+    TooManyParts(usize),
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultipartError::Io(e) => write!(f, "i/o error: {e}"),
+            MultipartError::NotMultipart => write!(f, "request is not multipart/form-data"),
+            MultipartError::MissingBoundary => write!(f, "multipart request has no boundary"),
+            MultipartError::Malformed(msg) => write!(f, "malformed multipart body: {msg}"),
+            MultipartError::TooLarge { name, limit } => write!(f, "part {name} is larger than {limit} bytes"),
+            MultipartError::TooManyParts(limit) => write!(f, "more than {limit} parts"),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+impl From<io::Error> for MultipartError {
+    fn from(e: io::Error) -> MultipartError {
+        MultipartError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    pub upload_dir: PathBuf,
+    pub max_file_size: u64,
+    pub max_field_size: u64,
+    pub max_parts: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        MultipartLimits {
+            upload_dir: env::temp_dir(),
+            max_file_size: 100 * 1024 * 1024,
+            max_field_size: 64 * 1024,
+            max_parts: 100,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    // The upload was saved here. The handler should move the file somewhere permanent or delete it.
+    File { path: PathBuf, size: u64 },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub data: PartData,
+}
+
+impl Part {
+    // The value of a normal (non-file) field as text
+    pub fn text(&self) -> Option<&str> {
+        match &self.data {
+            PartData::Memory(bytes) => std::str::from_utf8(bytes).ok(),
+            PartData::File { .. } => None,
+        }
+    }
+}
+
+// Finds the boundary in a Content-Type like: multipart/form-data; boundary="abc"
+pub fn boundary(content_type: &str) -> Result<String, MultipartError> {
+    let mut params = content_type.split(';');
+    let mime = params.next().unwrap_or("").trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return Err(MultipartError::NotMultipart);
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|b| !b.is_empty() && b.len() <= 70)
+        .ok_or(MultipartError::MissingBoundary)
+}
+
+// Parses parameters like: form-data; name="photo"; filename="beach.jpg"
+fn disposition_param(disposition: &str, key: &str) -> Option<String> {
+    disposition.split(';').skip(1).find_map(|param| {
+        let (k, v) = param.split_once('=')?;
+        k.trim().eq_ignore_ascii_case(key).then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+const CHUNK_SIZE: usize = 8 * 1024;
+const MAX_HEADER_LINE: usize = 8 * 1024;
+
+// Where the data of the current part goes while we stream it
+enum Sink {
+    Memory(Vec<u8>),
+    File(File),
+}
+
+// An iterator over the parts of a multipart body. R only needs to be Read: we do our own buffering in buf,
+// because after finding a boundary the bytes that follow it (the next part's headers) are already in there.
+pub struct Multipart<R> {
+    reader: R,
+    buf: Vec<u8>,
+    // "\r\n--boundary", what separates one part's data from the next
+    delimiter: Vec<u8>,
+    limits: MultipartLimits,
+    parts: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str, limits: MultipartLimits) -> Multipart<R> {
+        Multipart {
+            reader,
+            buf: Vec::new(),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            limits,
+            parts: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    // Reads another chunk into buf, false means the body is over
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let n = self.reader.read(&mut chunk)?;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n > 0)
+    }
+
+    fn read_line(&mut self) -> Result<String, MultipartError> {
+        loop {
+            if let Some(pos) = find_crlf(&self.buf) {
+                let line: Vec<u8> = self.buf.drain(..pos + 2).take(pos).collect();
+                return String::from_utf8(line).map_err(|_| MultipartError::Malformed("header is not UTF-8"));
+            }
+            if self.buf.len() > MAX_HEADER_LINE {
+                return Err(MultipartError::Malformed("header line is too long"));
+            }
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("body ended inside the part headers"));
+            }
+        }
+    }
+
+    // Skips everything before the first boundary. Treating the body as starting with "\r\n" lets the first boundary be found
+    // with the same delimiter as all the others.
+    fn skip_preamble(&mut self) -> Result<(), MultipartError> {
+        self.buf.splice(0..0, *b"\r\n");
+        self.skip_to_delimiter()
+    }
+
+    fn skip_to_delimiter(&mut self) -> Result<(), MultipartError> {
+        loop {
+            if let Some(pos) = memmem(&self.buf, &self.delimiter) {
+                self.buf.drain(..pos + self.delimiter.len());
+                return Ok(());
+            }
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                self.buf.drain(..self.buf.len() - keep);
+            }
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("boundary not found"));
+            }
+        }
+    }
+
+    // After a delimiter comes "--" for the last one, or "\r\n" before the next part's headers
+    fn after_delimiter(&mut self) -> Result<bool, MultipartError> {
+        while self.buf.len() < 2 {
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("body ended after a boundary"));
+            }
+        }
+        match &self.buf[..2] {
+            b"--" => Ok(false),
+            b"\r\n" => {
+                self.buf.drain(..2);
+                Ok(true)
+            }
+            _ => Err(MultipartError::Malformed("unexpected bytes after a boundary")),
+        }
+    }
+
+    fn stream_data(&mut self, name: &str, sink: &mut Sink, limit: u64) -> Result<u64, MultipartError> {
+        let mut size = 0u64;
+        loop {
+            // Everything before the delimiter is data, and if there's no delimiter yet, everything except
+            // the last few bytes that might be the start of one
+            let (end, found) = match memmem(&self.buf, &self.delimiter) {
+                Some(pos) => (pos, true),
+                None => (self.buf.len().saturating_sub(self.delimiter.len() - 1), false),
+            };
+
+            size += end as u64;
+            if size > limit {
+                return Err(MultipartError::TooLarge { name: name.to_string(), limit });
+            }
+            match sink {
+                Sink::Memory(data) => data.extend_from_slice(&self.buf[..end]),
+                Sink::File(file) => file.write_all(&self.buf[..end])?,
+            }
+
+            if found {
+                self.buf.drain(..end + self.delimiter.len());
+                return Ok(size);
+            }
+            self.buf.drain(..end);
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("body ended inside a part"));
+            }
+        }
+    }
+
+    fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        if !self.started {
+            self.started = true;
+            self.skip_preamble()?;
+        }
+        if !self.after_delimiter()? {
+            return Ok(None);
+        }
+
+        self.parts += 1;
+        if self.parts > self.limits.max_parts {
+            return Err(MultipartError::TooManyParts(self.limits.max_parts));
+        }
+
+        let mut headers = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(MultipartError::Malformed("part header without a colon"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        let disposition = header("Content-Disposition").ok_or(MultipartError::Malformed("part without Content-Disposition"))?;
+        let name = disposition_param(&disposition, "name").ok_or(MultipartError::Malformed("part without a name"))?;
+        let filename = disposition_param(&disposition, "filename");
+        let content_type = header("Content-Type");
+
+        let data = if filename.is_some() {
+            // The file gets a name of our own: the client's filename could contain "../" or clash with another upload
+            let path = self.limits.upload_dir.join(format!("upload-{}", Uuid::new()));
+            let mut sink = Sink::File(File::create(&path)?);
+            match self.stream_data(&name, &mut sink, self.limits.max_file_size) {
+                Ok(size) => PartData::File { path, size },
+                Err(e) => {
+                    drop(sink);
+                    let _ = fs::remove_file(&path);
+                    return Err(e);
+                }
+            }
+        } else {
+            let mut sink = Sink::Memory(Vec::new());
+            self.stream_data(&name, &mut sink, self.limits.max_field_size)?;
+            match sink {
+                Sink::Memory(data) => PartData::Memory(data),
+                Sink::File(_) => unreachable!(),
+            }
+        };
+
+        Ok(Some(Part { name, filename, content_type, headers, data }))
+    }
+}
+
+impl<R: Read> Iterator for Multipart<R> {
+    type Item = Result<Part, MultipartError>;
+
+    // After an error the body is in an unknown state, so the iterator stops instead of trying to carry on
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.next_part() {
+            Ok(Some(part)) => Some(Ok(part)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Request;
+
+    // Hands out the data a few bytes at a time, so boundaries end up split across reads
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.step = self.step % 7 + 1;
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn body(file: &[u8]) -> Vec<u8> {
+        let mut body = b"preamble to ignore\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nMy holiday\r\n--XyZ\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"beach.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n".to_vec();
+        body.extend_from_slice(file);
+        body.extend_from_slice(b"\r\n--XyZ--\r\nepilogue");
+        body
+    }
+
+    fn limits(dir: &str) -> MultipartLimits {
+        let upload_dir = env::temp_dir().join(dir);
+        fs::create_dir_all(&upload_dir).unwrap();
+        MultipartLimits { upload_dir, ..MultipartLimits::default() }
+    }
+
+    #[test]
+    fn finds_the_boundary() {
+        assert_eq!("XyZ", boundary("multipart/form-data; boundary=XyZ").unwrap());
+        assert_eq!("a b", boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"").unwrap());
+        assert!(matches!(boundary("text/plain"), Err(MultipartError::NotMultipart)));
+        assert!(matches!(boundary("multipart/form-data"), Err(MultipartError::MissingBoundary)));
+    }
+
+    #[test]
+    fn streams_fields_and_files() {
+        // The file contents look a lot like a boundary without being one
+        let file: Vec<u8> = b"\r\n--XyQ\r\n--X".iter().copied().chain((0..=255).cycle().take(20_000)).collect();
+        let body = body(&file);
+        let limits = limits("multipart-streams");
+
+        let parts: Vec<Part> = Multipart::new(Trickle { data: &body, step: 0 }, "XyZ", limits)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(2, parts.len());
+        assert_eq!(("title", Some("My holiday")), (parts[0].name.as_str(), parts[0].text()));
+
+        assert_eq!(Some("beach.jpg"), parts[1].filename.as_deref());
+        assert_eq!(Some("image/jpeg"), parts[1].content_type.as_deref());
+        match &parts[1].data {
+            PartData::File { path, size } => {
+                assert_eq!(file.len() as u64, *size);
+                assert_eq!(file, fs::read(path).unwrap());
+                fs::remove_file(path).unwrap();
+            }
+            other => panic!("expected a file, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn oversized_files_are_refused_and_cleaned_up() {
+        let body = body(&[b'x'; 5000]);
+        let limits = MultipartLimits { max_file_size: 1000, ..limits("multipart-limits") };
+        let dir = limits.upload_dir.clone();
+
+        let results: Vec<_> = Multipart::new(&body[..], "XyZ", limits).collect();
+        assert_eq!(2, results.len());
+        assert!(matches!(&results[1], Err(MultipartError::TooLarge { name, limit: 1000 }) if name == "photo"));
+        assert_eq!(0, fs::read_dir(dir).unwrap().count());
+    }
+
+    #[test]
+    fn malformed_bodies_are_errors() {
+        let cut_short = b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno end";
+        let results: Vec<_> = Multipart::new(&cut_short[..], "XyZ", MultipartLimits::default()).collect();
+        assert!(matches!(results[..], [Err(MultipartError::Malformed(_))]));
+
+        let no_name = b"--XyZ\r\nContent-Disposition: form-data\r\n\r\nx\r\n--XyZ--";
+        let results: Vec<_> = Multipart::new(&no_name[..], "XyZ", MultipartLimits::default()).collect();
+        assert!(matches!(results[..], [Err(MultipartError::Malformed(_))]));
+    }
+
+    #[test]
+    fn request_multipart_reads_the_buffered_body() {
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"q\"\r\n\r\nrust\r\n--b--\r\n";
+        let mut raw = format!("POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        raw.extend_from_slice(body);
+
+        let request = Request::read_from(&mut &raw[..]).unwrap();
+        let parts: Vec<Part> = request.multipart().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(Some("rust"), parts[0].text());
+    }
+}
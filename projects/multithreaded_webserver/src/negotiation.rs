@@ -0,0 +1,182 @@
+// Content Negotiation
+
+// One URL can have more than one representation: a page in HTML or JSON, compressed or not, in English or Polish. The client
+// says what it would like in three headers (RFC 7231, section 5.3), each a list of values with a quality from 0 to 1:
+    // Accept: text/html, application/json;q=0.9, */*;q=0.1
+    // Accept-Encoding: gzip, deflate;q=0.5, identity;q=0
+    // Accept-Language: pl-PL, pl;q=0.9, en;q=0.5
+// The quality q defaults to 1, and q=0 means "not this one, ever". The server goes through what it has, asks what q the client
+// gave each of them, and sends the best one. Working out that q is where the three headers differ:
+    // 1. A media range can be a type (text/html), all the subtypes of one (text/*) or anything (*/*). When more than one
+    //    matches, the most specific one decides, so "text/*;q=0.1, text/html" gives text/html a 1.
+    // 2. A content coding is matched exactly or by *. identity, no coding at all, is acceptable even when it isn't listed,
+    //    unless the header says identity;q=0 or *;q=0. Here it then only wins when nothing the client listed is on offer.
+    // 3. A language range matches a tag and every tag under it, en matches en-GB (RFC 4647). A server with only pl can still
+    //    serve a client asking for pl-PL, but the closer match decides when there is one.
+// Ties go to the order of what's available, so the server lists what it prefers first. Without the header anything goes,
+// which is the first one on offer, and the identity coding for Accept-Encoding.
+// error.rs picks JSON or HTML and the language of its pages with these, and compress.rs picks gzip or deflate.
+
+// Parameters other than q, like the level of text/html;level=1, are ignored. So is a value with a q that isn't a number from
+// 0 to 1, the way a browser would ignore a header it can't read.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preference {
+    pub value: String,
+    pub q: f32,
+}
+
+// The values of the header with their qualities, best first. Ties keep the header's order
+pub fn parse(header: &str) -> Vec<Preference> {
+    let mut preferences: Vec<Preference> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';').map(str::trim);
+            let value = params.next().filter(|value| !value.is_empty())?;
+            let q = params.find_map(|param| param.split_once('=').filter(|(name, _)| name.trim().eq_ignore_ascii_case("q")));
+            let q = match q {
+                Some((_, q)) => q.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))?,
+                None => 1.0,
+            };
+            Some(Preference { value: value.to_string(), q })
+        })
+        .collect();
+    preferences.sort_by(|a, b| b.q.total_cmp(&a.q));
+    preferences
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    MediaType,
+    Encoding,
+    Language,
+}
+
+// How closely the range matches what's on offer, None when it doesn't at all. * is the least specific of all
+fn specificity(kind: Kind, range: &str, offer: &str) -> Option<usize> {
+    if range == "*" || (kind == Kind::MediaType && range == "*/*") {
+        return Some(0);
+    }
+    if range.eq_ignore_ascii_case(offer) {
+        return Some(usize::MAX);
+    }
+    match kind {
+        Kind::MediaType => {
+            let (range_type, subtype) = range.split_once('/')?;
+            let (offer_type, _) = offer.split_once('/')?;
+            (subtype == "*" && range_type.eq_ignore_ascii_case(offer_type)).then_some(1)
+        }
+        Kind::Encoding => None,
+        // en for en-GB counts its subtags, pl-PL for pl is the fallback of the two
+        Kind::Language => {
+            let under = |tag: &str, range: &str| {
+                tag.len() > range.len() && tag.as_bytes()[range.len()] == b'-' && tag[..range.len()].eq_ignore_ascii_case(range)
+            };
+            if under(offer, range) {
+                Some(range.split('-').count() + 1)
+            } else {
+                under(range, offer).then_some(1)
+            }
+        }
+    }
+}
+
+// The q of the most specific range that matches, the first of them when there's a tie
+fn quality(kind: Kind, preferences: &[Preference], offer: &str) -> Option<f32> {
+    let mut best: Option<(usize, f32)> = None;
+    for preference in preferences {
+        if let Some(specificity) = specificity(kind, &preference.value, offer) {
+            if best.is_none_or(|(best, _)| specificity > best) {
+                best = Some((specificity, preference.q));
+            }
+        }
+    }
+    best.map(|(_, q)| q)
+}
+
+// Identity that isn't listed loses to anything that is
+const IMPLICIT_IDENTITY: f32 = 0.001;
+
+fn choose<'a>(kind: Kind, available: &[&'a str], header: &str) -> Option<&'a str> {
+    let preferences = parse(header);
+    let mut best: Option<(&str, f32)> = None;
+    for &offer in available {
+        let q = match quality(kind, &preferences, offer) {
+            Some(q) => q,
+            None if kind == Kind::Encoding && offer.eq_ignore_ascii_case("identity") => IMPLICIT_IDENTITY,
+            None => continue,
+        };
+        if q > 0.0 && best.is_none_or(|(_, best)| q > best) {
+            best = Some((offer, q));
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+// The media type of available the Accept header likes best, None when it doesn't accept any of them (a 406)
+pub fn negotiate<'a>(available: &[&'a str], accept: Option<&str>) -> Option<&'a str> {
+    match accept {
+        Some(accept) => choose(Kind::MediaType, available, accept),
+        None => available.first().copied(),
+    }
+}
+
+// The content coding of available for the Accept-Encoding header, "identity" among them is the uncompressed body
+pub fn negotiate_encoding<'a>(available: &[&'a str], accept_encoding: Option<&str>) -> Option<&'a str> {
+    let identity = || available.iter().copied().find(|offer| offer.eq_ignore_ascii_case("identity"));
+    match accept_encoding {
+        Some(accept_encoding) => choose(Kind::Encoding, available, accept_encoding),
+        None => identity().or(available.first().copied()),
+    }
+}
+
+// The language tag of available for the Accept-Language header
+pub fn negotiate_language<'a>(available: &[&'a str], accept_language: Option<&str>) -> Option<&'a str> {
+    match accept_language {
+        Some(accept_language) => choose(Kind::Language, available, accept_language),
+        None => available.first().copied(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_qualities() {
+        let values = |header: &str| -> Vec<(String, f32)> { parse(header).into_iter().map(|p| (p.value, p.q)).collect() };
+        assert_eq!(
+            vec![(String::from("text/html"), 1.0), (String::from("*/*"), 1.0), (String::from("application/json"), 0.5)],
+            values("application/json; Q=0.5, text/html;level=1, , */*")
+        );
+        assert_eq!(vec![(String::from("en"), 0.0)], values("en;q=0, de;q=2, fr;q=high"));
+    }
+
+    #[test]
+    fn negotiates_media_types() {
+        let available = ["text/html", "application/json"];
+        assert_eq!(Some("text/html"), negotiate(&available, None));
+        assert_eq!(Some("application/json"), negotiate(&available, Some("application/json")));
+        assert_eq!(Some("application/json"), negotiate(&available, Some("text/html;q=0.5, application/*")));
+        assert_eq!(Some("text/html"), negotiate(&available, Some("text/*;q=0.1, text/html, */*")));
+        assert_eq!(Some("application/json"), negotiate(&available, Some("text/html;q=0, */*;q=0.1")));
+        assert_eq!(None, negotiate(&available, Some("image/png, application/json;q=0")));
+    }
+
+    #[test]
+    fn negotiates_encodings_and_languages() {
+        let available = ["gzip", "deflate", "identity"];
+        assert_eq!(Some("identity"), negotiate_encoding(&available, None));
+        assert_eq!(Some("gzip"), negotiate_encoding(&available, Some("deflate, gzip")));
+        assert_eq!(Some("deflate"), negotiate_encoding(&available, Some("br, gzip;q=0.2, deflate;q=0.5")));
+        assert_eq!(Some("identity"), negotiate_encoding(&available, Some("br")));
+        assert_eq!(None, negotiate_encoding(&available, Some("br, *;q=0")));
+
+        let available = ["en", "pl", "pt-BR"];
+        assert_eq!(Some("en"), negotiate_language(&available, None));
+        assert_eq!(Some("pl"), negotiate_language(&available, Some("pl-PL, en;q=0.5")));
+        assert_eq!(Some("pt-BR"), negotiate_language(&available, Some("pt;q=0.8, pt-PT;q=0.3")));
+        assert_eq!(Some("pl"), negotiate_language(&available, Some("*, en;q=0.5")));
+        assert_eq!(None, negotiate_language(&available, Some("de, fr")));
+    }
+}
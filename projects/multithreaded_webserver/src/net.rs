@@ -0,0 +1,129 @@
+// Streams, Listeners and Networks
+
+// The server used to take a TcpStream wherever it talked to a client. That's the real thing, and it's also why its tests
+// needed real sockets, and sleeps: the only way to see a keep-alive timeout run out was to wait for it. These traits are
+// the few things the servers do with a connection, so a test can hand them something else:
+    // 1. A Stream is a connection, Read and Write plus what a TcpStream has on top: another handle to it for a second
+    //    thread, shutting it down, a timeout for its reads and the address of the other side.
+    // 2. A Listener accepts Streams, like a TcpListener.
+    // 3. A Network binds Listeners and connects Streams. Tcp is the real one, the default of the webserver and the chat server.
+// The in-memory network of the sim feature (src/sim.rs) is the other one, where nothing moves unless the test says so.
+// Everything goes through Box<dyn Stream>, the same way the Chain boxes its handlers: one server type serves any Network,
+// and a virtual call per read or write is nothing next to the system call behind it.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+pub trait Stream: Read + Write + Send {
+    // Another handle to the same connection. What one reads the other doesn't, a timeout or a shutdown applies to both
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+    // A read that waits longer than the timeout fails with WouldBlock or TimedOut, None waits for ever
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    // A write to a client that stopped reading waits for it as long as this, and then fails
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    // Without waiting: false when the other side closed the connection, or sent something nobody asked for.
+    // What a connection pool (src/connection_pool.rs) asks before it hands out an idle connection again
+    fn is_open_and_quiet(&self) -> bool;
+}
+
+pub trait Listener: Send {
+    fn accept(&self) -> io::Result<(Box<dyn Stream>, SocketAddr)>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+pub trait Network: Send + Sync {
+    fn bind(&self, addr: &str) -> io::Result<Box<dyn Listener>>;
+    // None waits as long as the system does
+    fn connect(&self, addr: SocketAddr, timeout: Option<Duration>) -> io::Result<Box<dyn Stream>>;
+}
+
+// A Box<dyn Stream> is a Stream too, for the functions that take any Stream and are handed one the server has boxed already
+impl<S: Stream + ?Sized> Stream for Box<S> {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        (**self).try_clone()
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        (**self).shutdown(how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        (**self).peer_addr()
+    }
+
+    fn is_open_and_quiet(&self) -> bool {
+        (**self).is_open_and_quiet()
+    }
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    // Peeking without blocking tells: end of file means it's closed, WouldBlock means it's open and quiet
+    fn is_open_and_quiet(&self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let quiet = matches!(self.peek(&mut [0; 1]), Err(e) if e.kind() == io::ErrorKind::WouldBlock);
+        quiet && self.set_nonblocking(false).is_ok()
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<(Box<dyn Stream>, SocketAddr)> {
+        let (stream, addr) = TcpListener::accept(self)?;
+        Ok((Box::new(stream), addr))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tcp;
+
+impl Network for Tcp {
+    fn bind(&self, addr: &str) -> io::Result<Box<dyn Listener>> {
+        Ok(Box::new(TcpListener::bind(addr)?))
+    }
+
+    fn connect(&self, addr: SocketAddr, timeout: Option<Duration>) -> io::Result<Box<dyn Stream>> {
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout)?,
+            None => TcpStream::connect(addr)?,
+        };
+        Ok(Box::new(stream))
+    }
+}
@@ -0,0 +1,211 @@
+// Crash Reports
+
+// A panic in a worker used to print its message to stderr and take the worker's thread down, and one in a handler behind
+// CatchPanic logged the backtrace along with the request. Either way the details scrolled by in a terminal that nobody was
+// watching. This module collects them in one place instead:
+    // 1. A panic hook, installed in front of the one that was there, captures the message, where it happened, the name of the
+    //    thread and a backtrace. That has to happen in the hook: by the time catch_unwind returns, the stack is gone.
+    // 2. Every panic becomes a CrashReport. With a directory set by install(), it's also written to a file of its own there,
+    //    crash-<unix time>-<n>.txt, which stays around after the log has rotated.
+    // 3. The report is emitted on events(), a SyncEventEmitter (smart_pointers/refcell_smart_pointer/src/events.rs), for
+    //    whoever wants to know: the metrics count them, a test waits for one.
+// catch() runs a closure and turns its panic into Err(CrashReport). The ThreadPool's workers run every job through it, so a
+// panicking job costs the job and not the worker, and CatchPanic runs the handlers through it. On a thread inside catch() the
+// hook stays quiet and leaves the report to catch(). On any other thread the panic is reported and then the old hook runs,
+// which prints it as usual: that thread is about to die, and that should be seen.
+
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::{Cell, RefCell},
+    fmt, fs, io,
+    panic::{self, AssertUnwindSafe, PanicHookInfo},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex, Once,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use refcell_smart_pointer::events::SyncEventEmitter;
+
+use crate::http_date;
+
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    pub time: SystemTime,
+    pub thread: String,
+    pub message: String,
+    // file:line:column, "unknown location" when the panic didn't say
+    pub location: String,
+    // Empty when the platform can't take one
+    pub backtrace: String,
+    // Where the report was written, None without a directory or when writing it failed
+    pub file: Option<PathBuf>,
+}
+
+impl CrashReport {
+    fn new(message: String, location: String, backtrace: String) -> CrashReport {
+        let thread = thread::current().name().unwrap_or("<unnamed>").to_string();
+        CrashReport { time: SystemTime::now(), thread, message, location, backtrace, file: None }
+    }
+
+    // What the hook sees of the panic. The backtrace is taken even without RUST_BACKTRACE, panics are rare enough
+    fn capture(info: &PanicHookInfo) -> CrashReport {
+        let location = info.location().map_or_else(|| String::from("unknown location"), |l| l.to_string());
+        let backtrace = Backtrace::force_capture();
+        let backtrace = match backtrace.status() {
+            BacktraceStatus::Captured => backtrace.to_string(),
+            _ => String::new(),
+        };
+        CrashReport::new(message(info.payload()).to_string(), location, backtrace)
+    }
+}
+
+// The contents of the file: what the default hook prints, and when
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "thread '{}' panicked at {}:", self.thread, self.location)?;
+        writeln!(f, "{}", self.message)?;
+        writeln!(f, "time: {}", http_date::format(self.time))?;
+        if !self.backtrace.is_empty() {
+            write!(f, "\nstack backtrace:\n{}", self.backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+// What panic! was called with, when it was a message
+pub fn message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("Box<dyn Any>", String::as_str),
+    }
+}
+
+static EVENTS: LazyLock<SyncEventEmitter<CrashReport>> = LazyLock::new(SyncEventEmitter::new);
+static DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+// Tells apart the reports of the same second
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+static HOOK: Once = Once::new();
+
+thread_local! {
+    // How many catch() calls the thread is in, they can be nested
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+    static CAUGHT: RefCell<Option<CrashReport>> = const { RefCell::new(None) };
+}
+
+// Every report, after it was written
+pub fn events() -> &'static SyncEventEmitter<CrashReport> {
+    &EVENTS
+}
+
+// Writes the reports into dir from now on, creating it when it isn't there, and installs the hook
+pub fn install(dir: impl Into<PathBuf>) -> io::Result<()> {
+    let dir = dir.into();
+    fs::create_dir_all(&dir)?;
+    *DIR.lock().unwrap() = Some(dir);
+    install_hook();
+    Ok(())
+}
+
+pub fn report_dir() -> Option<PathBuf> {
+    DIR.lock().unwrap().clone()
+}
+
+fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let report = CrashReport::capture(info);
+            if CATCHING.with(Cell::get) > 0 {
+                CAUGHT.with(|caught| *caught.borrow_mut() = Some(report));
+                return;
+            }
+            record(report);
+            previous(info);
+        }));
+    });
+}
+
+// Runs f, and turns a panic into the report of it. The caller promises, like with AssertUnwindSafe, that nothing f leaves
+// half changed is used afterwards without a look. The report is boxed, it would make every Ok as big as itself
+pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, Box<CrashReport>> {
+    install_hook();
+    CATCHING.with(|catching| catching.set(catching.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(catching.get() - 1));
+    result.map_err(|payload| {
+        // The hook didn't run when the payload came from panic::resume_unwind
+        let caught = CAUGHT.with(|caught| caught.borrow_mut().take());
+        let report = caught.unwrap_or_else(|| {
+            CrashReport::new(message(payload.as_ref()).to_string(), String::from("unknown location"), String::new())
+        });
+        Box::new(record(report))
+    })
+}
+
+fn record(mut report: CrashReport) -> CrashReport {
+    if let Some(dir) = report_dir() {
+        match write(&dir, &report) {
+            Ok(file) => report.file = Some(file),
+            Err(e) => common::warn!("couldn't write the crash report into {}: {e}", dir.display()),
+        }
+    }
+    EVENTS.emit(&report);
+    report
+}
+
+fn write(dir: &Path, report: &CrashReport) -> io::Result<PathBuf> {
+    let secs = report.time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let file = dir.join(format!("crash-{secs}-{}.txt", WRITTEN.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&file, report.to_string())?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+    use test_support::TempDir;
+
+    #[test]
+    fn panics_become_reports() {
+        assert_eq!(Ok(7), catch(|| 7).map_err(|report| report.message));
+
+        let dir = TempDir::new();
+        install(dir.child("crash-reports")).unwrap();
+        // Other tests panic too, this one only waits for its own
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let _listener = events().on(move |report: &CrashReport| {
+            if report.message == "the answer was 41" {
+                // The test is only gone when it failed already
+                let _ = tx.lock().unwrap().send(report.clone());
+            }
+        });
+
+        let report = thread::Builder::new()
+            .name(String::from("reporter"))
+            .spawn(|| catch(|| panic!("the answer was {}", 41)).unwrap_err())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(("reporter", "the answer was 41"), (report.thread.as_str(), report.message.as_str()));
+        assert!(report.location.starts_with("src/panic_report.rs:"), "{}", report.location);
+        let written = fs::read_to_string(report.file.as_ref().unwrap()).unwrap();
+        assert!(written.starts_with("thread 'reporter' panicked at src/panic_report.rs:"), "{written}");
+        assert!(written.contains("\nthe answer was 41\ntime: "), "{written}");
+        assert_eq!(report.file, rx.recv().unwrap().file);
+
+        // Nothing is left behind for the next panic on the thread
+        assert_eq!(0, CATCHING.with(Cell::get));
+        assert!(CAUGHT.with(|caught| caught.borrow().is_none()));
+        // The payload of resume_unwind has no hook to tell where it came from
+        let resumed = catch(|| panic::resume_unwind(Box::new(Arc::new(1)))).unwrap_err();
+        assert_eq!(("Box<dyn Any>", "unknown location"), (resumed.message.as_str(), resumed.location.as_str()));
+        *DIR.lock().unwrap() = None;
+    }
+}
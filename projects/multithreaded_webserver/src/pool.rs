@@ -0,0 +1,308 @@
+// An Object Pool for Reusable Buffers
+
+// Every connection needs a read buffer. BufReader::new allocates a fresh 8 KB one each time and frees it when the connection is done,
+// so a busy server spends part of its time asking the allocator for the same 8 KB over and over.
+// An object pool keeps the buffers around instead: checkout() hands out an idle one (or creates one if there is none),
+// and the PoolGuard it returns puts the buffer back into the pool when it's dropped, so giving it back can't be forgotten.
+
+// Idle objects live in two places:
+    // 1. A small cache per thread. A worker thread that handles connection after connection gets its buffer back from its own cache,
+    //    without taking any lock. This is the fast path.
+    // 2. A shared list behind a Mutex, for objects returned when the thread's cache was full, so other threads can use them too.
+
+// thread_local! statics can't be generic, so the per-thread caches are stored in a single map from pool id to a type-erased Vec<T>.
+// Box<dyn Any> holds the Vec<T> and downcast_mut gets it back, which only succeeds for the right T.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, BufRead, Read},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+// Objects each thread keeps for itself, the rest goes to the shared list
+const LOCAL_CAPACITY: usize = 4;
+
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static LOCAL: RefCell<HashMap<usize, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+type Create<T> = Box<dyn Fn() -> T + Send + Sync>;
+type Reset<T> = Box<dyn Fn(&mut T) + Send + Sync>;
+
+pub struct ObjectPool<T> {
+    id: usize,
+    shared: Mutex<Vec<T>>,
+    max_idle: usize,
+    create: Create<T>,
+    reset: Reset<T>,
+    checkouts: AtomicU64,
+    hits: AtomicU64,
+    in_use: AtomicUsize,
+    high_water: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub checkouts: u64,
+    pub hits: u64,
+    pub in_use: usize,
+    // The most objects that were checked out at the same time
+    pub high_water: usize,
+}
+
+impl PoolStats {
+    // How often checkout() found an idle object instead of creating one
+    pub fn hit_rate(&self) -> f64 {
+        if self.checkouts == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.checkouts as f64
+        }
+    }
+}
+
+impl<T: 'static> ObjectPool<T> {
+    pub fn new(create: impl Fn() -> T + Send + Sync + 'static) -> ObjectPool<T> {
+        ObjectPool {
+            id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            shared: Mutex::new(Vec::new()),
+            max_idle: 64,
+            create: Box::new(create),
+            reset: Box::new(|_| {}),
+            checkouts: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            in_use: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+        }
+    }
+
+    // Called on every object that comes back, so the next user doesn't see what the last one left in it
+    pub fn reset(mut self, reset: impl Fn(&mut T) + Send + Sync + 'static) -> Self {
+        self.reset = Box::new(reset);
+        self
+    }
+
+    // How many idle objects the shared list keeps, any more are dropped
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    pub fn checkout(&self) -> PoolGuard<'_, T> {
+        self.checkouts.fetch_add(1, Ordering::Relaxed);
+        let in_use = self.in_use.fetch_add(1, Ordering::Relaxed) + 1;
+        self.high_water.fetch_max(in_use, Ordering::Relaxed);
+
+        let idle = self.with_local(|local| local.pop()).or_else(|| self.shared.lock().unwrap().pop());
+        let object = match idle {
+            Some(object) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                object
+            }
+            None => (self.create)(),
+        };
+        PoolGuard { pool: self, object: Some(object) }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            checkouts: self.checkouts.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            in_use: self.in_use.load(Ordering::Relaxed),
+            high_water: self.high_water.load(Ordering::Relaxed),
+        }
+    }
+
+    fn give_back(&self, mut object: T) {
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+        (self.reset)(&mut object);
+
+        let object = self.with_local(|local| {
+            if local.len() < LOCAL_CAPACITY {
+                local.push(object);
+                None
+            } else {
+                Some(object)
+            }
+        });
+        if let Some(object) = object {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.len() < self.max_idle {
+                shared.push(object);
+            }
+        }
+    }
+
+    // Runs f on this thread's cache for this pool. Objects still cached when a pool is dropped are freed when their thread exits.
+    fn with_local<R>(&self, f: impl FnOnce(&mut Vec<T>) -> R) -> R {
+        LOCAL.with(|local| {
+            let mut local = local.borrow_mut();
+            let cache = local.entry(self.id).or_insert_with(|| Box::new(Vec::<T>::new()));
+            f(cache.downcast_mut::<Vec<T>>().expect("pool ids are unique"))
+        })
+    }
+}
+
+pub struct PoolGuard<'a, T: 'static> {
+    pool: &'a ObjectPool<T>,
+    // Only None while the guard is being dropped
+    object: Option<T>,
+}
+
+impl<T> Deref for PoolGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.object.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for PoolGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.object.as_mut().unwrap()
+    }
+}
+
+impl<T: 'static> Drop for PoolGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(object) = self.object.take() {
+            self.pool.give_back(object);
+        }
+    }
+}
+
+// Read Buffers
+
+pub const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+pub fn read_buffer_pool() -> ObjectPool<Vec<u8>> {
+    ObjectPool::new(|| vec![0; READ_BUFFER_SIZE])
+}
+
+// A BufReader that reads into a buffer from the pool, so Request::read_from can parse from it like from any other BufRead
+pub struct PooledReader<'a, R> {
+    inner: R,
+    buffer: PoolGuard<'a, Vec<u8>>,
+    pos: usize,
+    filled: usize,
+}
+
+impl<'a, R: Read> PooledReader<'a, R> {
+    pub fn new(inner: R, buffer: PoolGuard<'a, Vec<u8>>) -> PooledReader<'a, R> {
+        PooledReader { inner, buffer, pos: 0, filled: 0 }
+    }
+}
+
+impl<R: Read> Read for PooledReader<'_, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for PooledReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.inner.read(&mut self.buffer[..])?;
+            self.pos = 0;
+        }
+        Ok(&self.buffer[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.filled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Request;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn returned_objects_are_reused() {
+        let pool = ObjectPool::new(Vec::<u8>::new).reset(|v| v.clear());
+        {
+            let mut first = pool.checkout();
+            first.extend_from_slice(b"left over");
+        }
+        let second = pool.checkout();
+        assert!(second.is_empty(), "reset clears the returned object");
+        assert!(second.capacity() >= 9, "but keeps its allocation");
+
+        let stats = pool.stats();
+        assert_eq!((2, 1, 1, 1), (stats.checkouts, stats.hits, stats.in_use, stats.high_water));
+        assert_eq!(0.5, stats.hit_rate());
+    }
+
+    #[test]
+    fn high_water_mark_and_overflow() {
+        let pool = ObjectPool::new(|| 0u32).max_idle(2);
+        let guards: Vec<_> = (0..10).map(|_| pool.checkout()).collect();
+        assert_eq!(10, pool.stats().high_water);
+        drop(guards);
+
+        // The thread keeps LOCAL_CAPACITY, the shared list max_idle, the rest was dropped
+        assert_eq!(LOCAL_CAPACITY + 2, pool.with_local(|l| l.len()) + pool.shared.lock().unwrap().len());
+        assert_eq!(0, pool.stats().in_use);
+    }
+
+    #[test]
+    fn pools_of_the_same_type_dont_mix() {
+        let zeros = ObjectPool::new(|| 0u8);
+        let ones = ObjectPool::new(|| 1u8);
+        drop(zeros.checkout());
+        assert_eq!(1, *ones.checkout());
+        assert_eq!(0, *zeros.checkout());
+    }
+
+    #[test]
+    fn objects_move_between_threads() {
+        let pool = Arc::new(ObjectPool::new(|| vec![0u8; 16]).max_idle(16));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut buffer = pool.checkout();
+                        buffer[0] += 1;
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(400, stats.checkouts);
+        // Each thread only creates a buffer on its first checkout, then gets it back from its own cache
+        assert!(stats.hits >= 396, "{stats:?}");
+    }
+
+    #[test]
+    fn parses_requests_through_a_pooled_buffer() {
+        let pool = read_buffer_pool();
+        let body = "x".repeat(READ_BUFFER_SIZE * 2);
+        let raw = format!("POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+
+        for _ in 0..3 {
+            let mut reader = PooledReader::new(raw.as_bytes(), pool.checkout());
+            let request = Request::read_from(&mut reader).unwrap();
+            assert_eq!(body.as_bytes(), &request.body[..]);
+        }
+        assert_eq!(2, pool.stats().hits);
+    }
+}
@@ -0,0 +1,735 @@
+// A Type-State Builder for the Server
+
+// The blog post in object_oriented/oop (PostII) encodes its workflow in types: a DraftPostII has no content() method at all,
+// so displaying a draft is a compile error rather than a bug found in production. The same trick works for a builder.
+// A server needs an address and a handler before it can run, and TLS has to be configured before the address is bound.
+// With a plain builder, forgetting one of those is only noticed at runtime, with an error or a panic.
+
+// ServerBuilder carries two type parameters that record what has been set so far:
+    // 1. Unbound or Bound: bind(addr) turns a ServerBuilder<Unbound, _> into a ServerBuilder<Bound, _>,
+    //    and tls() is only implemented for Unbound, so calling it after bind doesn't compile.
+    // 2. NoHandler or WithHandler: handler(chain) can only be called once.
+    // 3. build() is only implemented for ServerBuilder<Bound, WithHandler>, so a config missing either of them can't be built.
+// Unbound and NoHandler are empty structs that only exist for the compiler, Bound and WithHandler carry the value that was set.
+// The compile-fail tests in tests/ui show the errors you get for the orders that are rejected.
+
+// The server keeps connections open between requests (HTTP/1.1 keep-alive), until the client asks for Connection: close
+// or stays quiet for longer than the keep-alive timeout. Once a request has started, the Limits (src/limits.rs) decide how big
+// it may be and how long it may take. Both timeouts are timeouts of the reads themselves, through a TimedReader: a read that
+// waits too long fails in the worker that made it, which knows which part of the request was too slow and answers 408 for it,
+// or closes an idle connection quietly.
+
+// bind() turns the config into a Server, listening already, whose ShutdownHandle stops it the way the chat server's does:
+// the accept loop ends, every connection is closed after the response it's working on, and run() returns once the workers
+// are done. The open connections are kept in a Slab (std_collections/src/slab.rs), so the ones that are idle between two
+// requests are closed right away, instead of when their keep-alive runs out.
+
+// The connections come from a Network (src/net.rs) and the timeouts run on a Clock, TCP and the system's by default.
+// The tests below give it the simulated ones of src/sim.rs instead, and see a keep-alive run out without waiting for it.
+
+use std::{
+    io::{self, BufRead, BufReader},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use common::{
+    clock::{Clock, SystemClock},
+    env_config::Env,
+};
+use std_collections::slab::Slab;
+
+use crate::{
+    cors::Cors,
+    guard::ScopeGuard,
+    http::{reason_phrase, Body, ParseError, Request, Response},
+    limits::{self, Limits, TimedReader},
+    middleware::{Chain, Middleware, State},
+    net::{Listener, Network, Stream, Tcp},
+    time_ext::Deadline,
+    ThreadPool,
+};
+
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(5);
+
+pub struct Unbound;
+pub struct Bound(String);
+
+pub struct NoHandler;
+pub struct WithHandler(Chain);
+
+// Where the request came from, in the request's extensions (src/extensions.rs) of every request the server reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerAddr(pub SocketAddr);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+pub struct ServerBuilder<A, H> {
+    addr: A,
+    // The fields aren't named after the methods, or calling tls() after bind would be reported as "private field, not a method"
+    app: H,
+    threads: usize,
+    keep_alive: Duration,
+    limits: Limits,
+    cors: Option<Cors>,
+    // The State layers of with_state, in the order they were given
+    states: Vec<Box<dyn Middleware>>,
+    tls_config: Option<TlsConfig>,
+    network: Arc<dyn Network>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ServerBuilder<Unbound, NoHandler> {
+    pub fn new() -> ServerBuilder<Unbound, NoHandler> {
+        ServerBuilder {
+            addr: Unbound,
+            app: NoHandler,
+            threads: 4,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            limits: Limits::default(),
+            cors: None,
+            states: Vec::new(),
+            tls_config: None,
+            network: Arc::new(Tcp),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl Default for ServerBuilder<Unbound, NoHandler> {
+    fn default() -> Self {
+        ServerBuilder::new()
+    }
+}
+
+// Only before bind
+impl<H> ServerBuilder<Unbound, H> {
+    pub fn tls(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.tls_config = Some(TlsConfig { cert: cert.into(), key: key.into() });
+        self
+    }
+
+    // Every field is moved into a builder of a different type, the old one is consumed
+    pub fn bind(self, addr: &str) -> ServerBuilder<Bound, H> {
+        ServerBuilder {
+            addr: Bound(addr.to_string()),
+            app: self.app,
+            threads: self.threads,
+            keep_alive: self.keep_alive,
+            limits: self.limits,
+            cors: self.cors,
+            states: self.states,
+            tls_config: self.tls_config,
+            network: self.network,
+            clock: self.clock,
+        }
+    }
+}
+
+// Settings from the Environment
+
+// HOST, PORT, THREADS and KEEP_ALIVE (in seconds) instead of bind(), threads() and keep_alive(). A variable that's wrong is
+// kept in the Env with the others, and the setting keeps its default, so the caller checks env before running anything.
+impl<H> ServerBuilder<Unbound, H> {
+    pub fn bind_from_env(self, env: &mut Env) -> ServerBuilder<Bound, H> {
+        let host = env.var::<IpAddr>("HOST").help("The address to listen on").default(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let port = env.var::<u16>("PORT").help("The port to listen on, 0 picks a free one").default(7878);
+        let threads = env
+            .var::<usize>("THREADS")
+            .help("How many requests are handled at once")
+            .validate("at least 1", |&threads| threads > 0)
+            .default(self.threads);
+        let keep_alive = env
+            .var::<u64>("KEEP_ALIVE")
+            .help("How many seconds an idle connection is kept open")
+            .default(self.keep_alive.as_secs());
+        let addr = SocketAddr::new(host, port).to_string();
+        self.threads(threads).keep_alive(Duration::from_secs(keep_alive)).bind(&addr)
+    }
+}
+
+// Only once
+impl<A> ServerBuilder<A, NoHandler> {
+    pub fn handler(self, chain: Chain) -> ServerBuilder<A, WithHandler> {
+        ServerBuilder {
+            addr: self.addr,
+            app: WithHandler(chain),
+            threads: self.threads,
+            keep_alive: self.keep_alive,
+            limits: self.limits,
+            cors: self.cors,
+            states: self.states,
+            tls_config: self.tls_config,
+            network: self.network,
+            clock: self.clock,
+        }
+    }
+}
+
+// In any state
+impl<A, H> ServerBuilder<A, H> {
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "a server needs at least one thread");
+        self.threads = threads;
+        self
+    }
+
+    // How long an idle connection is kept open, waiting for the client's next request
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    // Where the connections come from, TCP unless it's the simulated network of a test (src/sim.rs)
+    pub fn network(mut self, network: Arc<dyn Network>) -> Self {
+        self.network = network;
+        self
+    }
+
+    // What the keep-alive and the limits' timeouts are counted on
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // How big a request may be and how fast it has to come, see src/limits.rs
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // State every handler and middleware can get with req.state::<T>(), one value per type (src/middleware.rs)
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: Arc<T>) -> Self {
+        self.states.push(Box::new(State(state)));
+        self
+    }
+
+    // Which other origins' pages may call the server (src/cors.rs). The Cors goes around the whole Chain, whenever it's set
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+}
+
+impl ServerBuilder<Bound, WithHandler> {
+    pub fn build(self) -> ServerConfig {
+        // The handler's Chain inside the layers the builder adds: the states, and Cors around everything
+        let mut app = self.app.0;
+        for state in self.states.into_iter().rev() {
+            app = app.around_boxed(state);
+        }
+        if let Some(cors) = self.cors {
+            app = app.around(cors);
+        }
+        ServerConfig {
+            addr: self.addr.0,
+            threads: self.threads,
+            keep_alive: self.keep_alive,
+            limits: self.limits,
+            tls: self.tls_config,
+            network: self.network,
+            clock: self.clock,
+            app,
+        }
+    }
+}
+
+// Everything the server needs, and nothing missing
+pub struct ServerConfig {
+    addr: String,
+    threads: usize,
+    keep_alive: Duration,
+    limits: Limits,
+    tls: Option<TlsConfig>,
+    network: Arc<dyn Network>,
+    clock: Arc<dyn Clock>,
+    app: Chain,
+}
+
+impl ServerConfig {
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        self.keep_alive
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
+
+    pub fn handle(&self, request: &mut Request) -> Response {
+        self.app.handle(request)
+    }
+
+    // Serves requests until accepting a connection fails.
+    pub fn run(self) -> io::Result<()> {
+        self.bind()?.run()
+    }
+
+    // This server only speaks plain HTTP, so a config with TLS is refused here instead of silently serving without it.
+    pub fn bind(self) -> io::Result<Server> {
+        if self.tls.is_some() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "TLS is configured but this server only speaks plain HTTP"));
+        }
+        let listener = self.network.bind(&self.addr)?;
+        let addr = listener.local_addr()?;
+        Ok(Server { listener, addr, config: Arc::new(self), live: Arc::new(Live::default()) })
+    }
+
+    // Answers requests on one connection until the client closes it, asks to close it, is idle for longer than keep_alive,
+    // sends a request over the limits, or the server shuts down
+    fn serve_connection(&self, stream: &mut dyn Stream, live: &Live) -> io::Result<()> {
+        let Some(key) = live.add(stream)? else { return Ok(()) };
+        crate::defer! { live.remove(key); }
+
+        let limits = &self.limits;
+        let mut reader = BufReader::new(TimedReader::new(stream.try_clone()?).with_clock(Arc::clone(&self.clock)));
+        let after = |timeout| Some(Deadline::at(self.clock.now() + timeout));
+        loop {
+            // Between two requests, unless the client already sent the next one. Nothing was asked yet, so when the client
+            // closes the connection, keep_alive runs out or the server shuts down, there is nobody to answer
+            if reader.buffer().is_empty() {
+                if !live.set_idle(key, true) {
+                    return Ok(());
+                }
+                reader.get_mut().set_min_rate(None);
+                reader.get_mut().set_deadline(after(self.keep_alive));
+                if reader.fill_buf().map_or(true, |buf| buf.is_empty()) {
+                    return Ok(());
+                }
+                live.set_idle(key, false);
+            }
+
+            // The request has started: its head has to be in within head_timeout, and its body has to come at min_rate
+            reader.get_mut().set_deadline(after(limits.head_timeout));
+            let request = Request::read_head_limited(&mut reader, limits.max_head_size).and_then(|mut request| {
+                reader.get_mut().set_deadline(None);
+                reader.get_mut().set_min_rate(limits.min_rate);
+                request.read_body_limited(&mut reader, limits.max_body_size)?;
+                Ok(request)
+            });
+            let mut request = match request {
+                Ok(request) => request,
+                Err(e) => return reject(stream, &e),
+            };
+            if let Ok(addr) = stream.peer_addr() {
+                request.extensions_mut().insert(PeerAddr(addr));
+            }
+            let close = request.header("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close"));
+
+            let response = self.handle(&mut request);
+            // Checked after the handler, a shutdown may have started while it ran
+            let close = close || live.stopping.load(Ordering::Relaxed);
+            // An event stream only ends when the connection does
+            let streaming = matches!(response.body, Body::EventStream(_));
+            let response = if close { response.with_header("Connection", "close") } else { response };
+            response.write_to(stream)?;
+
+            if close || streaming {
+                return Ok(());
+            }
+        }
+    }
+}
+
+struct OpenConnection {
+    stream: Box<dyn Stream>,
+    // Waiting for its next request, nothing of it has been read
+    idle: bool,
+}
+
+// What the accept loop, the connections and the ShutdownHandle share
+#[derive(Default)]
+struct Live {
+    stopping: AtomicBool,
+    connections: Mutex<Slab<OpenConnection>>,
+}
+
+impl Live {
+    // The key of the connection, None when the server is shutting down already. Adding a connection and shutting down
+    // both hold the lock, so shutdown() either sees the connection or the connection sees `stopping`
+    fn add(&self, stream: &dyn Stream) -> io::Result<Option<usize>> {
+        let stream = stream.try_clone()?;
+        let mut connections = self.connections.lock().unwrap();
+        if self.stopping.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        Ok(Some(connections.insert(OpenConnection { stream, idle: false })))
+    }
+
+    // false when the server is shutting down, and the connection should close instead of waiting for a request
+    fn set_idle(&self, key: usize, idle: bool) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        connections[key].idle = idle;
+        !self.stopping.load(Ordering::Relaxed)
+    }
+
+    fn remove(&self, key: usize) {
+        self.connections.lock().unwrap().remove(key);
+    }
+
+    // Closes the reading half of every idle connection, which ends its wait for the next request. The others see
+    // `stopping` once their response is written. Returns false when it was shutting down already
+    fn shutdown(&self) -> bool {
+        let connections = self.connections.lock().unwrap();
+        if self.stopping.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+        for connection in connections.values().filter(|connection| connection.idle) {
+            // A connection the client closed already can't be shut down, and needs nothing else
+            let _ = connection.stream.shutdown(Shutdown::Read);
+        }
+        true
+    }
+}
+
+// A ServerConfig listening on its address
+pub struct Server {
+    listener: Box<dyn Listener>,
+    addr: SocketAddr,
+    config: Arc<ServerConfig>,
+    live: Arc<Live>,
+}
+
+impl Server {
+    // With the port the system picked, when the address had port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { addr: self.addr, network: Arc::clone(&self.config.network), live: Arc::clone(&self.live) }
+    }
+
+    // Serves requests until shutdown() is called, or accepting a connection fails. Returns when the workers are done
+    pub fn run(self) -> io::Result<()> {
+        let pool = ThreadPool::new(self.config.threads);
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            if self.live.stopping.load(Ordering::Relaxed) {
+                break;
+            }
+            let (config, live) = (Arc::clone(&self.config), Arc::clone(&self.live));
+            pool.execute(move || {
+                // A panicking handler still gets a 500 back (src/guard.rs)
+                let mut stream = ScopeGuard::on_unwind(stream, |mut stream: Box<dyn Stream>| {
+                    let _ = Response::text(500, "Internal Server Error").write_to(&mut stream);
+                });
+                let _ = config.serve_connection(&mut **stream, &live);
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    addr: SocketAddr,
+    network: Arc<dyn Network>,
+    live: Arc<Live>,
+}
+
+impl ShutdownHandle {
+    // Calling it again does nothing
+    pub fn shutdown(&self) {
+        if !self.live.shutdown() {
+            return;
+        }
+        // accept() only returns when somebody connects, so somebody does. The loop sees `stopping` and ends.
+        let _ = self.network.connect(self.addr, None);
+    }
+}
+
+// Answers a request that couldn't be read with the status for why, when there is one, and closes the connection.
+// A connection that broke or closed mid-request gets nothing, there is nobody left to read it.
+fn reject(stream: &mut dyn Stream, error: &ParseError) -> io::Result<()> {
+    let status = match error {
+        ParseError::Io(e) if limits::is_timeout(e) => 408,
+        ParseError::Malformed(_) => 400,
+        ParseError::BodyTooLarge(_) => 413,
+        ParseError::HeadTooLarge(_) => 431,
+        ParseError::Io(_) | ParseError::Empty => return Ok(()),
+    };
+    common::debug!("refused a request from {:?}: {error}", stream.peer_addr());
+    Response::text(status, reason_phrase(status)).with_header("Connection", "close").write_to(stream)?;
+    stream.shutdown(Shutdown::Both)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Body, middleware::Next, sim::SimNet};
+    use std::net::TcpStream;
+
+    fn hello() -> Chain {
+        Chain::new(|req: &mut Request| Response::text(200, &format!("hello from {}", req.path_only())))
+    }
+
+    #[test]
+    fn settings_from_the_environment() {
+        let mut env = Env::from_pairs([("HOST", "::1"), ("PORT", "8080"), ("THREADS", "16")]);
+        let config = ServerBuilder::new().bind_from_env(&mut env).handler(hello()).build();
+        assert_eq!(("[::1]:8080", 16, DEFAULT_KEEP_ALIVE), (config.addr(), config.threads(), config.keep_alive()));
+        assert_eq!(Ok(()), env.check());
+
+        let mut env = Env::from_pairs([("HOST", "localhost"), ("THREADS", "0"), ("KEEP_ALIVE", "2")]);
+        let config = ServerBuilder::new().threads(2).bind_from_env(&mut env).handler(hello()).build();
+        assert_eq!(("127.0.0.1:7878", 2, Duration::from_secs(2)), (config.addr(), config.threads(), config.keep_alive()));
+        let problems: Vec<String> = env.check().unwrap_err().problems.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(vec!["HOST", "THREADS"], problems);
+    }
+
+    #[test]
+    fn setters_work_in_any_order_that_compiles() {
+        let config = ServerBuilder::new().tls("cert.pem", "key.pem").handler(hello()).threads(8).bind("127.0.0.1:7878").build();
+        assert_eq!("127.0.0.1:7878", config.addr());
+        assert_eq!(8, config.threads());
+        assert_eq!(Some(PathBuf::from("key.pem")), config.tls().map(|tls| tls.key.clone()));
+
+        let config = ServerBuilder::new().bind("127.0.0.1:0").handler(hello()).build();
+        assert_eq!(4, config.threads());
+        assert_eq!(None, config.tls());
+
+        let mut request = Request::read_from(&mut &b"GET /docs HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let response = config.handle(&mut request);
+        assert!(matches!(response.body, Body::Bytes(ref b) if b == b"hello from /docs"));
+    }
+
+    #[test]
+    fn cors_goes_around_the_whole_chain() {
+        // A layer that wants a login, which a preflight never has
+        let app = hello().with(|req: &mut Request, next: Next<'_>| match req.header("Authorization") {
+            Some(_) => next.run(req),
+            None => Response::text(401, "Unauthorized"),
+        });
+        let cors = Cors::new().allow_origin("https://app.example.com").allow_methods(&["PUT"]);
+        let config = ServerBuilder::new().bind("127.0.0.1:0").handler(app).cors(cors).build();
+
+        let raw = "OPTIONS /docs HTTP/1.1\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: PUT\r\n\r\n";
+        let response = config.handle(&mut Request::read_from(&mut raw.as_bytes()).unwrap());
+        assert_eq!(204, response.status);
+        assert_eq!(Some("https://app.example.com"), response.header("Access-Control-Allow-Origin"));
+
+        let raw = "PUT /docs HTTP/1.1\r\nOrigin: https://app.example.com\r\nAuthorization: Bearer x\r\n\r\n";
+        let response = config.handle(&mut Request::read_from(&mut raw.as_bytes()).unwrap());
+        assert_eq!(200, response.status);
+        assert_eq!(Some("https://app.example.com"), response.header("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn every_handler_gets_the_state() {
+        struct Greeting(&'static str);
+
+        let app = Chain::new(|req: &mut Request| {
+            let greeting = req.state::<Greeting>().unwrap();
+            let visits = req.state::<std::sync::atomic::AtomicUsize>().unwrap();
+            let visit = visits.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+            Response::text(200, &format!("{}, visitor {visit}", greeting.0))
+        });
+        let config = ServerBuilder::new()
+            .bind("127.0.0.1:0")
+            .with_state(Arc::new(Greeting("hello")))
+            .handler(app)
+            .with_state(Arc::new(std::sync::atomic::AtomicUsize::new(0)))
+            .build();
+
+        for _ in 0..2 {
+            config.handle(&mut Request::read_from(&mut "GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap());
+        }
+        let response = config.handle(&mut Request::read_from(&mut "GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap());
+        assert!(matches!(response.body, crate::http::Body::Bytes(ref b) if b == b"hello, visitor 3"));
+    }
+
+    // A config on the simulated network, and its clock
+    fn simulated(net: &SimNet, builder: ServerBuilder<Unbound, WithHandler>) -> ServerConfig {
+        builder.bind("127.0.0.1:80").network(Arc::new(net.clone())).clock(net.clock()).build()
+    }
+
+    #[test]
+    fn connections_are_kept_alive_until_idle() {
+        use std::io::{BufRead, Read, Write};
+
+        // The server reads the requests a few bytes at a time, however the seed splits them
+        let net = SimNet::new(11).max_chunk(5);
+        let config = simulated(&net, ServerBuilder::new().handler(hello()).keep_alive(Duration::from_millis(100)));
+        let listener = net.bind(config.addr()).unwrap();
+
+        // Connecting doesn't wait for the accept, on the simulated network
+        let mut client = net.connect(listener.local_addr().unwrap(), None).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                // Moved into the thread, so the connection closes when the server is done with it
+                let mut stream = stream;
+                config.serve_connection(&mut *stream, &Live::default()).unwrap();
+            });
+
+            let mut reader = BufReader::new(client.try_clone().unwrap());
+            for path in ["/one", "/two"] {
+                write!(client, "GET {path} HTTP/1.1\r\n\r\n").unwrap();
+                let mut status = String::new();
+                reader.read_line(&mut status).unwrap();
+                assert_eq!("HTTP/1.1 200 OK\r\n", status);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let mut body = vec![0; format!("hello from {path}").len()];
+                reader.read_exact(&mut body).unwrap();
+            }
+
+            // Then the client goes quiet. The connection is still open a moment before the keep-alive runs out,
+            // and the server hangs up when it does
+            net.wait_for_blocked_reads(1);
+            net.advance(Duration::from_millis(99));
+            net.wait_for_blocked_reads(1);
+            net.advance(Duration::from_millis(1));
+            assert_eq!(0, reader.read(&mut [0; 16]).unwrap());
+            assert_eq!(Duration::from_millis(100), net.clock().elapsed());
+        });
+    }
+
+    // Serves one connection with the limits and sends it the bytes. Then, for every step, waits for the server to wait for
+    // more and moves the clock on by it. What the client gets back, and how long that took on the clock
+    fn refused(limits: Limits, sent: &[u8], steps: &[Duration]) -> (String, Duration) {
+        use std::io::{Read, Write};
+
+        let net = SimNet::new(3);
+        let config = simulated(&net, ServerBuilder::new().handler(hello()).limits(limits));
+        let listener = net.bind(config.addr()).unwrap();
+        let mut client = net.connect(listener.local_addr().unwrap(), None).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut stream = stream;
+                // The answer is what the client reads, the error behind it doesn't matter here
+                let _ = config.serve_connection(&mut *stream, &Live::default());
+            });
+            client.write_all(sent).unwrap();
+            for &step in steps {
+                net.wait_for_blocked_reads(1);
+                net.advance(step);
+            }
+            // The server closes the connection after its answer, and read_to_string returns once it did
+            let mut answer = String::new();
+            client.read_to_string(&mut answer).unwrap();
+            (answer.lines().next().unwrap_or("").to_string(), net.clock().elapsed())
+        })
+    }
+
+    #[test]
+    fn requests_over_the_limits_are_refused() {
+        let limits = Limits {
+            max_head_size: 1024,
+            max_body_size: 1000,
+            head_timeout: Duration::from_millis(100),
+            min_rate: Some(limits::MinRate { bytes_per_sec: 1000, grace: Duration::from_millis(50) }),
+        };
+        let ms = Duration::from_millis;
+
+        // Slowloris: a head that never ends, and is still waited for a moment before head_timeout
+        let slowloris = refused(limits.clone(), b"GET / HTTP/1.1\r\nHost: loc", &[ms(99), ms(1)]);
+        assert_eq!(("HTTP/1.1 408 Request Timeout".to_string(), ms(100)), slowloris);
+
+        let big_header = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(2000));
+        assert_eq!("HTTP/1.1 431 Request Header Fields Too Large", refused(limits.clone(), big_header.as_bytes(), &[]).0);
+
+        let big_body = b"POST / HTTP/1.1\r\nContent-Length: 1001\r\n\r\n";
+        assert_eq!("HTTP/1.1 413 Payload Too Large", refused(limits.clone(), big_body, &[]).0);
+
+        // A body that would take a second at the rate it starts with, and then stops coming. The 10 bytes came with the
+        // head, so they don't count for the body's rate, and the grace period is all it gets
+        let stalled = b"POST / HTTP/1.1\r\nContent-Length: 1000\r\n\r\n0123456789";
+        assert_eq!(("HTTP/1.1 408 Request Timeout".to_string(), ms(50)), refused(limits.clone(), stalled, &[ms(49), ms(1)]));
+
+        assert_eq!("HTTP/1.1 400 Bad Request", refused(limits.clone(), b"GET\r\n\r\n", &[]).0);
+        // Within the limits, the handler answers
+        assert_eq!("HTTP/1.1 200 OK", refused(limits, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n", &[]).0);
+    }
+
+    #[test]
+    fn tls_is_refused_at_run_time() {
+        let config = ServerBuilder::new().tls("cert.pem", "key.pem").bind("127.0.0.1:0").handler(hello()).build();
+        assert_eq!(io::ErrorKind::Unsupported, config.run().unwrap_err().kind());
+    }
+
+    #[test]
+    fn shutdown_finishes_the_request_in_flight() {
+        use std::io::{Read, Write};
+        use std::sync::mpsc;
+
+        let (started, handling) = mpsc::channel();
+        let started = std::sync::Mutex::new(started);
+        let app = Chain::new(move |_: &mut Request| {
+            started.lock().unwrap().send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            Response::text(200, "done")
+        });
+        let server = ServerBuilder::new().bind("127.0.0.1:0").handler(app).build().bind().unwrap();
+        let (addr, handle) = (server.local_addr(), server.shutdown_handle());
+        let running = std::thread::spawn(move || server.run());
+
+        // A keep-alive request, which the server would answer and wait for the next one after
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        handling.recv().unwrap();
+        handle.shutdown();
+        handle.shutdown();
+
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 200 OK\r\n") && answer.ends_with("done"), "{answer:?}");
+        assert!(answer.contains("Connection: close\r\n"), "{answer:?}");
+        running.join().unwrap().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn shutdown_closes_idle_connections_right_away() {
+        use std::io::{Read, Write};
+
+        let net = SimNet::new(5);
+        let config = simulated(&net, ServerBuilder::new().handler(hello()).keep_alive(Duration::from_secs(30)));
+        let server = config.bind().unwrap();
+        let (addr, handle, live) = (server.local_addr(), server.shutdown_handle(), Arc::clone(&server.live));
+        let running = std::thread::spawn(move || server.run());
+
+        // Answered, and then waiting for a next request that doesn't come
+        let mut client = net.connect(addr, None).unwrap();
+        client.write_all(b"GET /idle HTTP/1.1\r\n\r\n").unwrap();
+        let (mut answer, mut buf) = (Vec::new(), [0; 1024]);
+        while !answer.ends_with(b"hello from /idle") {
+            let read = client.read(&mut buf).unwrap();
+            assert!(read > 0, "{:?}", String::from_utf8_lossy(&answer));
+            answer.extend_from_slice(&buf[..read]);
+        }
+        net.wait_for_blocked_reads(1);
+
+        // Without the clock moving at all
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+        assert_eq!(0, client.read(&mut buf).unwrap());
+        assert_eq!(Duration::ZERO, net.clock().elapsed());
+        assert!(live.connections.lock().unwrap().is_empty());
+        assert_eq!(io::ErrorKind::ConnectionRefused, net.connect(addr, None).err().unwrap().kind());
+    }
+}
@@ -0,0 +1,299 @@
+// Sessions
+
+// A session is data the server keeps about one visitor across requests, like who they are logged in as.
+// The data stays on the server in a SessionStore, and the browser only holds the session ID in a cookie.
+
+// The ID in the cookie is signed with HMAC (src/hmac.rs), so a client can't just edit the cookie to try someone else's ID:
+// any ID whose signature doesn't check out is ignored and the client gets a fresh session.
+
+// SessionMiddleware does the bookkeeping around each request:
+    // 1. Read the session cookie, verify it, and load the data from the store. Without a valid cookie, start a new empty session.
+    // 2. Put the session on the request, where the handler reaches it with req.session().
+    // 3. After the handler, save the session if it was changed, and send a Set-Cookie for new sessions.
+    //    New sessions that were never written to aren't stored at all, otherwise every visitor would fill up the store.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    cookie::{Cookie, SameSite},
+    hmac,
+    http::{Request, Response},
+    ids::Uuid,
+    middleware::{Middleware, Next},
+};
+
+pub type SessionData = HashMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    id: String,
+    data: SessionData,
+    is_new: bool,
+    changed: bool,
+    destroyed: bool,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            id: Uuid::new().to_string(),
+            data: SessionData::new(),
+            is_new: true,
+            changed: false,
+            destroyed: false,
+        }
+    }
+
+    fn existing(id: &str, data: SessionData) -> Session {
+        Session { id: id.to_string(), data, is_new: false, changed: false, destroyed: false }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(|value| value.as_str())
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.data.insert(key.to_string(), value.to_string());
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let removed = self.data.remove(key);
+        self.changed |= removed.is_some();
+        removed
+    }
+
+    // Logging out: the data is deleted from the store and the browser is told to forget the cookie
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+}
+
+// Stores are shared by every worker thread, hence Send + Sync. A store backed by files or a database would implement the same trait.
+pub trait SessionStore: Send + Sync {
+    fn load(&self, id: &str) -> Option<SessionData>;
+    fn save(&self, id: &str, data: &SessionData);
+    fn destroy(&self, id: &str);
+}
+
+// Every request reads its session but few change it, so a RwLock lets all the readers in at once and only makes writers wait.
+// The sessions are lost when the server restarts.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: RwLock<HashMap<String, SessionData>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        self.sessions.read().unwrap().get(id).cloned()
+    }
+
+    fn save(&self, id: &str, data: &SessionData) {
+        self.sessions.write().unwrap().insert(id.to_string(), data.clone());
+    }
+
+    fn destroy(&self, id: &str) {
+        self.sessions.write().unwrap().remove(id);
+    }
+}
+
+pub struct SessionMiddleware {
+    store: Arc<dyn SessionStore>,
+    key: Vec<u8>,
+    cookie_name: String,
+    max_age: Option<u64>,
+    secure: bool,
+}
+
+impl SessionMiddleware {
+    // The key signs the session cookies. It must stay secret, and changing it logs everybody out.
+    pub fn new(store: Arc<dyn SessionStore>, key: &[u8]) -> SessionMiddleware {
+        SessionMiddleware {
+            store,
+            key: key.to_vec(),
+            cookie_name: String::from("session"),
+            max_age: None,
+            secure: false,
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> SessionMiddleware {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    pub fn max_age(mut self, seconds: u64) -> SessionMiddleware {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    // Set this when the server is behind HTTPS, so the cookie is never sent in the clear
+    pub fn secure(mut self, secure: bool) -> SessionMiddleware {
+        self.secure = secure;
+        self
+    }
+
+    fn load_session(&self, request: &Request) -> Session {
+        let cookie = request.cookie(&self.cookie_name);
+        cookie
+            .as_deref()
+            .and_then(|value| hmac::verify(&self.key, value))
+            .and_then(|id| self.store.load(id).map(|data| Session::existing(id, data)))
+            .unwrap_or_else(Session::new)
+    }
+
+    fn cookie(&self, session: &Session) -> Cookie {
+        let mut cookie = Cookie::new(&self.cookie_name, &hmac::sign(&self.key, &session.id))
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax);
+        cookie.max_age = self.max_age;
+        cookie
+    }
+}
+
+impl Middleware for SessionMiddleware {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let session = self.load_session(request);
+        request.extensions_mut().insert(session);
+        let response = next.run(request);
+
+        let session = match request.extensions_mut().remove::<Session>() {
+            Some(session) => session,
+            None => return response,
+        };
+
+        if session.destroyed {
+            if session.is_new {
+                return response;
+            }
+            self.store.destroy(&session.id);
+            return response.with_header("Set-Cookie", &Cookie::removal(&self.cookie_name).to_string());
+        }
+
+        if session.changed {
+            self.store.save(&session.id, &session.data);
+            if session.is_new {
+                return response.with_header("Set-Cookie", &self.cookie(&session).to_string());
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cookie::parse_cookie_header,
+        http::{HeaderName, HeaderValue},
+        middleware::Chain,
+    };
+
+    fn request(cookie: Option<&str>) -> Request {
+        let mut raw = String::from("GET / HTTP/1.1\r\n");
+        if let Some(cookie) = cookie {
+            raw.push_str(&format!("Cookie: {cookie}\r\n"));
+        }
+        raw.push_str("\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    // Counts the visits of each client in its session, and logs out when asked to
+    fn visit_counter(store: Arc<MemoryStore>) -> Chain {
+        Chain::new(|req: &mut Request| {
+            let logout = req.header("X-Logout").is_some();
+            let session = req.session().unwrap();
+            if logout {
+                session.destroy();
+                return Response::text(200, "bye");
+            }
+            let visits: u32 = session.get("visits").map_or(0, |v| v.parse().unwrap()) + 1;
+            session.insert("visits", &visits.to_string());
+            Response::text(200, &visits.to_string())
+        })
+        .with(SessionMiddleware::new(store, b"top secret"))
+    }
+
+    fn session_cookie(response: &Response) -> String {
+        let set_cookie = response.header("Set-Cookie").unwrap();
+        parse_cookie_header(set_cookie).remove(0).1
+    }
+
+    #[test]
+    fn sessions_persist_across_requests() {
+        let store = Arc::new(MemoryStore::new());
+        let chain = visit_counter(Arc::clone(&store));
+
+        let first = chain.handle(&mut request(None));
+        let set_cookie = first.header("Set-Cookie").unwrap().to_string();
+        assert!(set_cookie.contains("HttpOnly") && set_cookie.contains("SameSite=Lax"));
+        let cookie = format!("session={}", session_cookie(&first));
+
+        // The same cookie finds the same session, and no new cookie is needed
+        let second = chain.handle(&mut request(Some(&cookie)));
+        assert!(matches!(second.body, crate::http::Body::Bytes(ref b) if b == b"2"));
+        assert_eq!(None, second.header("Set-Cookie"));
+        assert_eq!(1, store.len());
+    }
+
+    #[test]
+    fn tampered_cookies_get_a_fresh_session() {
+        let store = Arc::new(MemoryStore::new());
+        let chain = visit_counter(Arc::clone(&store));
+
+        let first = chain.handle(&mut request(None));
+        let signed = session_cookie(&first);
+        let (id, signature) = signed.rsplit_once('.').unwrap();
+        // Change the last character of the id, to something it isn't already
+        let last = if id.ends_with('0') { '1' } else { '0' };
+        let forged = format!("session={}{last}.{signature}", &id[..id.len() - 1]);
+
+        let response = chain.handle(&mut request(Some(&forged)));
+        assert!(matches!(response.body, crate::http::Body::Bytes(ref b) if b == b"1"));
+        assert!(response.header("Set-Cookie").is_some());
+        assert_eq!(2, store.len());
+    }
+
+    #[test]
+    fn destroying_a_session_removes_it_and_the_cookie() {
+        let store = Arc::new(MemoryStore::new());
+        let chain = visit_counter(Arc::clone(&store));
+
+        let first = chain.handle(&mut request(None));
+        let cookie = format!("session={}", session_cookie(&first));
+
+        let mut logout = request(Some(&cookie));
+        logout.headers.push((HeaderName::from("X-Logout"), HeaderValue::from("1")));
+        let response = chain.handle(&mut logout);
+        assert!(response.header("Set-Cookie").unwrap().contains("Max-Age=0"));
+        assert!(store.is_empty());
+    }
+}
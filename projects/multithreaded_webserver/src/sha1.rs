@@ -0,0 +1,87 @@
+// SHA-1
+
+// The WebSocket handshake proves that the server understood the upgrade request by hashing the client's key with SHA-1.
+// SHA-1 is broken for security purposes (collisions can be found), but the handshake only uses it as a checksum, so that doesn't matter here.
+
+// The algorithm, from RFC 3174:
+    // 1. Pad the message with a 1 bit, then zeros, then the message length in bits as a big endian u64, so the total length is a multiple of 64 bytes.
+    // 2. Split the padded message into 64 byte blocks, and for each block expand its 16 words into 80 words.
+    // 3. Mix the 80 words into the five 32 bit state words over 80 rounds, with a different function and constant every 20 rounds.
+    // 4. The hash is the five state words written out big endian.
+
+const INITIAL_STATE: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state = INITIAL_STATE;
+
+    let bit_len = (data.len() as u64).wrapping_mul(8);
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&bit_len.to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        compress(&mut state, block);
+    }
+
+    let mut digest = [0; 20];
+    for (chunk, word) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn compress(state: &mut [u32; 5], block: &[u8]) {
+    let mut w = [0u32; 80];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, word) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5A827999),
+            20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+            _ => (b ^ c ^ d, 0xCA62C1D6),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+
+    for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+        *s = s.wrapping_add(v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::hex_encode as hex;
+
+    #[test]
+    fn matches_the_reference_test_vectors() {
+        assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", hex(&sha1(b"")));
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", hex(&sha1(b"abc")));
+        assert_eq!(
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            hex(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"))
+        );
+        // A million 'a's crosses many block boundaries
+        assert_eq!("34aa973cd4c4daa4f61eeb2bdbad27316534016f", hex(&sha1(&vec![b'a'; 1_000_000])));
+    }
+}
@@ -0,0 +1,570 @@
+// Deterministic Simulation
+
+// A test of a timeout over real sockets has to wait for it: a keep-alive of 300ms takes 300ms, and on a busy machine the
+// server may not even have started waiting by then, so the test sleeps a little longer to be sure, and still fails now and
+// then. SimNet is a Network (src/net.rs) where nothing happens unless the test says so:
+    // 1. Connections are in memory. What one side writes waits in a buffer until the other reads it, and writes never block.
+    // 2. Time is a common::clock::FakeClock, which the server is given too (ServerBuilder::clock). A read with a timeout
+    //    times out once advance() moved the clock past it, however long that takes in real time, or however short.
+    // 3. wait_for_blocked_reads(n) waits until n reads on the accepting side are blocked, the server waiting for its
+    //    clients. Advancing the clock only then means the server had set its timeouts before the time ran out.
+    // 4. max_chunk(n) makes every read return between 1 and n of the bytes that are there, a different number each time,
+    //    picked by a rand_lite::Pcg32 from the seed and the connection. The same seed splits the same way on every run.
+// The test's side of a connection is the one it connected. A read on it that waits for more than 10 seconds of real time
+// panics, since nothing in a simulation takes that long: the server is stuck or the test forgot to advance the clock.
+// The connection pool, the health checks and the job queue take the clock too (their clock methods), and the pool and the
+// health probes connect over a Network, so their retries and timeouts run on the simulation as well. The last test does that.
+
+// It's behind the sim feature, for the tests of other crates like the chat server, and always there for this crate's tests.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use common::clock::{Clock, FakeClock};
+use std_collections::rand_lite::{Pcg32, Rng};
+
+use crate::net::{Listener, Network, Stream};
+
+// How long the test's side waits in real time before the simulation counts as stuck
+const STUCK: Duration = Duration::from_secs(10);
+const FIRST_PORT: u16 = 40000;
+
+// The side of a connection that connected, and the one that accepted it
+const CLIENT: usize = 0;
+const SERVER: usize = 1;
+
+struct Pipe {
+    bytes: VecDeque<u8>,
+    // The writing side shut down. Once the bytes are read, reads return 0
+    eof: bool,
+    // The reading side shut down or is gone. Writes fail, and nobody reads what's left
+    closed: bool,
+    // How many bytes each read may return, with max_chunk
+    chunks: Option<(Pcg32, usize)>,
+}
+
+struct Connection {
+    // pipes[side] is what that side reads
+    pipes: [Pipe; 2],
+    timeouts: [Option<Duration>; 2],
+    // The handles of each side, try_clone adds one. The side is closed when the last one is dropped
+    handles: [usize; 2],
+    addrs: [SocketAddr; 2],
+}
+
+impl Connection {
+    // Like closing a socket: it reads nothing more, and the other side reads to the end and then 0
+    fn close(&mut self, side: usize) {
+        self.pipes[side].closed = true;
+        self.pipes[side].bytes.clear();
+        self.pipes[1 - side].eof = true;
+    }
+}
+
+struct State {
+    connections: HashMap<u64, Connection>,
+    // The connections every listener hasn't accepted yet
+    listeners: HashMap<SocketAddr, VecDeque<u64>>,
+    next_connection: u64,
+    next_port: u16,
+    // The reads waiting on the accepting side: their connection and when they time out, by a number of their own
+    parked: HashMap<u64, (u64, Option<Instant>)>,
+    next_parked: u64,
+    max_chunk: Option<usize>,
+}
+
+impl State {
+    // The parked reads that still have nothing to do. One that was woken up but didn't run yet doesn't count
+    fn blocked(&self, now: Instant) -> usize {
+        let waiting = |(id, deadline): &(u64, Option<Instant>)| {
+            let pipe = &self.connections[id].pipes[SERVER];
+            pipe.bytes.is_empty() && !pipe.eof && !pipe.closed && deadline.is_none_or(|deadline| now < deadline)
+        };
+        self.parked.values().filter(|parked| waiting(parked)).count()
+    }
+}
+
+struct Inner {
+    state: Mutex<State>,
+    // Anything changed: bytes, a shutdown, a connection, the clock
+    changed: Condvar,
+    clock: Arc<FakeClock>,
+    seed: u64,
+}
+
+impl Inner {
+    // A panic while it's locked is the test failing, and the streams dropped while unwinding still get to clean up
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Clone)]
+pub struct SimNet {
+    inner: Arc<Inner>,
+}
+
+impl SimNet {
+    pub fn new(seed: u64) -> SimNet {
+        let state = State {
+            connections: HashMap::new(),
+            listeners: HashMap::new(),
+            next_connection: 0,
+            next_port: FIRST_PORT,
+            parked: HashMap::new(),
+            next_parked: 0,
+            max_chunk: None,
+        };
+        let inner = Inner { state: Mutex::new(state), changed: Condvar::new(), clock: Arc::new(FakeClock::new()), seed };
+        SimNet { inner: Arc::new(inner) }
+    }
+
+    // Every read returns at most this many bytes, a different number each time. For the connections made from now on
+    pub fn max_chunk(self, max: usize) -> SimNet {
+        assert!(max > 0, "a read has to return at least one byte");
+        self.inner.lock().max_chunk = Some(max);
+        self
+    }
+
+    // The time of the simulation, for the server and anything else that should run on it
+    pub fn clock(&self) -> Arc<FakeClock> {
+        Arc::clone(&self.inner.clock)
+    }
+
+    // Moves the clock on, and wakes up the reads that time out by then
+    pub fn advance(&self, by: Duration) {
+        self.inner.clock.advance(by);
+        let _state = self.inner.lock();
+        self.inner.changed.notify_all();
+    }
+
+    // Random numbers from the seed, for whatever else in the test should be random. The same stream gives the same numbers
+    pub fn rng(&self, stream: u64) -> Pcg32 {
+        Pcg32::new(self.inner.seed, stream)
+    }
+
+    // Waits until at least n reads on the accepting side are blocked, waiting for bytes from the test's side or the clock
+    pub fn wait_for_blocked_reads(&self, n: usize) {
+        let started = Instant::now();
+        let mut state = self.inner.lock();
+        while state.blocked(self.inner.clock.now()) < n {
+            let blocked = state.blocked(self.inner.clock.now());
+            assert!(started.elapsed() < STUCK, "only {blocked} of {n} reads blocked after {STUCK:?}");
+            state = self.inner.changed.wait_timeout(state, Duration::from_millis(10)).unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+
+    fn local_addr(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+}
+
+impl fmt::Debug for SimNet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SimNet").field("seed", &self.inner.seed).field("elapsed", &self.inner.clock.elapsed()).finish()
+    }
+}
+
+impl Network for SimNet {
+    fn bind(&self, addr: &str) -> io::Result<Box<dyn Listener>> {
+        let addr: SocketAddr = addr.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{addr}: {e}")))?;
+        let mut state = self.inner.lock();
+        let addr = match addr.port() {
+            0 => {
+                state.next_port += 1;
+                SocketAddr::new(addr.ip(), state.next_port - 1)
+            }
+            _ => addr,
+        };
+        if state.listeners.contains_key(&addr) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{addr} is taken")));
+        }
+        state.listeners.insert(addr, VecDeque::new());
+        Ok(Box::new(SimListener { net: self.clone(), addr }))
+    }
+
+    // Connects right away, or is refused when nothing listens at the address. There's no time to wait for
+    fn connect(&self, addr: SocketAddr, _timeout: Option<Duration>) -> io::Result<Box<dyn Stream>> {
+        let mut state = self.inner.lock();
+        if !state.listeners.contains_key(&addr) {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("nothing listens at {addr}")));
+        }
+        let (id, port) = (state.next_connection, state.next_port);
+        state.next_connection += 1;
+        state.next_port += 1;
+        let pipe = |side: usize| Pipe {
+            bytes: VecDeque::new(),
+            eof: false,
+            closed: false,
+            chunks: state.max_chunk.map(|max| (Pcg32::new(self.inner.seed, id * 2 + side as u64), max)),
+        };
+        let connection = Connection {
+            pipes: [pipe(CLIENT), pipe(SERVER)],
+            timeouts: [None, None],
+            handles: [1, 1],
+            addrs: [SimNet::local_addr(port), addr],
+        };
+        state.connections.insert(id, connection);
+        state.listeners.get_mut(&addr).expect("checked above").push_back(id);
+        self.inner.changed.notify_all();
+        Ok(Box::new(SimStream { net: self.clone(), id, side: CLIENT }))
+    }
+}
+
+pub struct SimListener {
+    net: SimNet,
+    addr: SocketAddr,
+}
+
+impl Listener for SimListener {
+    // Waits for a connection as long as it takes, the server's accept loop waits for the whole test
+    fn accept(&self) -> io::Result<(Box<dyn Stream>, SocketAddr)> {
+        let inner = &self.net.inner;
+        let mut state = inner.lock();
+        loop {
+            let pending = state.listeners.get_mut(&self.addr).expect("removed on drop only");
+            if let Some(id) = pending.pop_front() {
+                let peer = state.connections[&id].addrs[CLIENT];
+                return Ok((Box::new(SimStream { net: self.net.clone(), id, side: SERVER }), peer));
+            }
+            state = inner.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+// The connections it didn't accept are closed, the test's side reads 0
+impl Drop for SimListener {
+    fn drop(&mut self) {
+        let mut state = self.net.inner.lock();
+        for id in state.listeners.remove(&self.addr).unwrap_or_default() {
+            drop_handle(&mut state, id, SERVER);
+        }
+        self.net.inner.changed.notify_all();
+    }
+}
+
+pub struct SimStream {
+    net: SimNet,
+    id: u64,
+    side: usize,
+}
+
+impl SimStream {
+    fn with<T>(&self, f: impl FnOnce(&mut Connection) -> T) -> T {
+        let mut state = self.net.inner.lock();
+        let result = f(state.connections.get_mut(&self.id).expect("the connection of a live handle"));
+        self.net.inner.changed.notify_all();
+        result
+    }
+}
+
+impl Read for SimStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &self.net.inner;
+        let started = Instant::now();
+        let mut state = inner.lock();
+        let timeout = state.connections[&self.id].timeouts[self.side];
+        let deadline = timeout.map(|timeout| inner.clock.now() + timeout);
+        loop {
+            let pipe = &mut state.connections.get_mut(&self.id).expect("the connection of a live handle").pipes[self.side];
+            if !pipe.bytes.is_empty() {
+                let mut n = buf.len().min(pipe.bytes.len());
+                if let Some((rng, max)) = &mut pipe.chunks {
+                    n = n.min(rng.gen_range(1..=*max));
+                }
+                for (byte, read) in buf.iter_mut().zip(pipe.bytes.drain(..n)) {
+                    *byte = read;
+                }
+                return Ok(n);
+            }
+            if pipe.eof || pipe.closed || buf.is_empty() {
+                return Ok(0);
+            }
+            // WouldBlock, like a TcpStream's read timeout on Unix
+            if deadline.is_some_and(|deadline| inner.clock.now() >= deadline) {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "the simulated read timed out"));
+            }
+            if self.side == CLIENT {
+                assert!(started.elapsed() < STUCK, "the test's side waited {STUCK:?} for bytes, the simulation is stuck");
+            }
+
+            let parked = (self.side == SERVER).then(|| {
+                state.next_parked += 1;
+                let parked = state.next_parked;
+                state.parked.insert(parked, (self.id, deadline));
+                inner.changed.notify_all();
+                parked
+            });
+            state = inner.changed.wait_timeout(state, Duration::from_millis(100)).unwrap_or_else(PoisonError::into_inner).0;
+            if let Some(parked) = parked {
+                state.parked.remove(&parked);
+            }
+        }
+    }
+}
+
+impl Write for SimStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let side = self.side;
+        self.with(|connection| {
+            let pipe = &mut connection.pipes[1 - side];
+            if pipe.closed || pipe.eof {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the simulated connection is closed"));
+            }
+            pipe.bytes.extend(buf);
+            Ok(buf.len())
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for SimStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        self.with(|connection| connection.handles[self.side] += 1);
+        Ok(Box::new(SimStream { net: self.net.clone(), id: self.id, side: self.side }))
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let side = self.side;
+        self.with(|connection| {
+            if matches!(how, Shutdown::Read | Shutdown::Both) {
+                connection.pipes[side].closed = true;
+                connection.pipes[side].bytes.clear();
+            }
+            if matches!(how, Shutdown::Write | Shutdown::Both) {
+                connection.pipes[1 - side].eof = true;
+            }
+        });
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        // The same error as a TcpStream's
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot set a 0 duration timeout"));
+        }
+        self.with(|connection| connection.timeouts[self.side] = timeout);
+        Ok(())
+    }
+
+    // Writes never block here, there's nothing to time out
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot set a 0 duration timeout"));
+        }
+        Ok(())
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.with(|connection| connection.addrs[1 - self.side]))
+    }
+
+    fn is_open_and_quiet(&self) -> bool {
+        self.with(|connection| {
+            let pipe = &connection.pipes[self.side];
+            pipe.bytes.is_empty() && !pipe.eof && !pipe.closed
+        })
+    }
+}
+
+impl Drop for SimStream {
+    fn drop(&mut self) {
+        let mut state = self.net.inner.lock();
+        drop_handle(&mut state, self.id, self.side);
+        self.net.inner.changed.notify_all();
+    }
+}
+
+// The last handle of a side closes it, and the connection goes once both sides are closed
+fn drop_handle(state: &mut State, id: u64, side: usize) {
+    let connection = state.connections.get_mut(&id).expect("the connection of a live handle");
+    connection.handles[side] -= 1;
+    if connection.handles[side] == 0 {
+        connection.close(side);
+    }
+    if connection.handles == [0, 0] {
+        state.connections.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connection_pool::{network_pool, PoolError},
+        health::{self, Health, Kind, Status},
+        jobs::{JobQueue, JobStatus, RetryPolicy},
+    };
+    use std::thread;
+
+    // For what the threads of the simulation do in their own time, like getting in line for a connection
+    fn wait_until(condition: impl Fn() -> bool) {
+        let started = Instant::now();
+        while !condition() {
+            assert!(started.elapsed() < STUCK, "still waiting after {STUCK:?}, the simulation is stuck");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn bytes_go_both_ways_until_a_side_closes() {
+        let net = SimNet::new(1);
+        let listener = net.bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(FIRST_PORT, addr.port());
+        assert_eq!(io::ErrorKind::AddrInUse, net.bind(&addr.to_string()).err().unwrap().kind());
+
+        let mut client = net.connect(addr, None).unwrap();
+        let (mut server, peer) = listener.accept().unwrap();
+        assert_eq!((peer, addr), (server.peer_addr().unwrap(), client.peer_addr().unwrap()));
+
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 16];
+        let n = server.read(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf[..n]);
+        let mut writer = server.try_clone().unwrap();
+        writer.write_all(b"pong").unwrap();
+        drop(writer);
+        // One handle of the server's side is left, so it's still open
+        let n = client.read(&mut buf).unwrap();
+        assert_eq!(b"pong", &buf[..n]);
+
+        server.write_all(b"bye").unwrap();
+        drop(server);
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).unwrap();
+        assert_eq!("bye", rest);
+        assert_eq!(io::ErrorKind::BrokenPipe, client.write(b"hello?").unwrap_err().kind());
+
+        drop(listener);
+        assert_eq!(io::ErrorKind::ConnectionRefused, net.connect(addr, None).err().unwrap().kind());
+    }
+
+    #[test]
+    fn reads_time_out_on_the_simulated_clock() {
+        let net = SimNet::new(1);
+        let listener = net.bind("127.0.0.1:80").unwrap();
+        let mut client = net.connect(listener.local_addr().unwrap(), None).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+
+        let reader = thread::spawn(move || {
+            let mut buf = [0; 8];
+            let first = server.read(&mut buf).map(|n| buf[..n].to_vec());
+            (first.unwrap(), server.read(&mut buf).unwrap_err().kind())
+        });
+        net.wait_for_blocked_reads(1);
+        client.write_all(b"a").unwrap();
+        // The second read waits for the clock, which doesn't move by itself
+        net.wait_for_blocked_reads(1);
+        net.advance(Duration::from_secs(29));
+        net.wait_for_blocked_reads(1);
+        net.advance(Duration::from_secs(1));
+        assert_eq!((b"a".to_vec(), io::ErrorKind::WouldBlock), reader.join().unwrap());
+        assert_eq!(Duration::from_secs(30), net.clock().elapsed());
+    }
+
+    #[test]
+    fn chunks_depend_on_the_seed_only() {
+        let chunks = |seed| {
+            let net = SimNet::new(seed).max_chunk(4);
+            let listener = net.bind("127.0.0.1:0").unwrap();
+            let mut client = net.connect(listener.local_addr().unwrap(), None).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            client.write_all(&[7; 32]).unwrap();
+            client.shutdown(Shutdown::Write).unwrap();
+            let mut buf = [0; 32];
+            std::iter::from_fn(|| Some(server.read(&mut buf).unwrap()).filter(|&n| n > 0)).collect::<Vec<_>>()
+        };
+        let first = chunks(7);
+        assert_eq!(32, first.iter().sum::<usize>());
+        assert!(first.iter().all(|n| (1..=4).contains(n)) && first.len() > 8, "{first:?}");
+        assert_eq!(first, chunks(7));
+        assert_ne!(first, chunks(8));
+    }
+
+    // A job stores something on a server through a pool of one connection. The server is down at first: the job fails and
+    // waits for its backoff, and the health check says the pool is down. Then the timeouts, with the connection held
+    #[test]
+    fn retries_and_timeouts_run_on_the_simulated_clock() {
+        let net = SimNet::new(5);
+        let clock: Arc<dyn Clock> = net.clock();
+        let addr: SocketAddr = "127.0.0.1:6379".parse().unwrap();
+        let pool = network_pool(Arc::new(net.clone()), addr, Duration::from_secs(1), 1);
+        let pool = Arc::new(pool.checkout_timeout(Duration::from_secs(5)).clock(Arc::clone(&clock)));
+        let health = Health::new(1)
+            .clock(Arc::clone(&clock))
+            .timeout(Duration::from_secs(2))
+            .readiness("store", health::connection_pool(Arc::clone(&pool)));
+        let report = health.check(&[Kind::Readiness]);
+        let refused = format!("couldn't open a connection: nothing listens at {addr}");
+        assert_eq!(Status::Down(refused), report.checks[0].status);
+
+        let queue = JobQueue::new(1).clock(Arc::clone(&clock));
+        let shared = Arc::clone(&pool);
+        queue.register("store", RetryPolicy { max_attempts: 3, backoff: Duration::from_secs(30) }, move |payload| {
+            let mut connection = shared.checkout().map_err(|e| e.to_string())?;
+            connection.write_all(payload).map_err(|e| e.to_string())?;
+            let mut answer = [0; 2];
+            connection.read_exact(&mut answer).map_err(|e| e.to_string())?;
+            (&answer == b"OK").then_some(()).ok_or_else(|| String::from("not OK"))
+        });
+        let id = queue.enqueue("store", b"SET").unwrap();
+        wait_until(|| queue.status(id) == Some(JobStatus::Retrying { attempts: 1 }));
+
+        // The server comes up, and the second attempt connects once the clock is past the backoff
+        let listener = net.bind(&addr.to_string()).unwrap();
+        net.advance(Duration::from_secs(30));
+        let (mut server, _) = listener.accept().unwrap();
+        let mut request = [0; 3];
+        server.read_exact(&mut request).unwrap();
+        assert_eq!(b"SET", &request);
+        server.write_all(b"OK").unwrap();
+        queue.wait_idle();
+        assert_eq!(Some(JobStatus::Succeeded { attempts: 2 }), queue.status(id));
+        assert!(health.check(&[Kind::Readiness]).is_up());
+
+        // The server closes the idle connection, and the next checkout opens another one instead
+        drop(server);
+        let held = pool.checkout().unwrap();
+        let (_server, _) = listener.accept().unwrap();
+        assert_eq!(1, pool.state().open);
+
+        // With it held, a checkout gives up after the checkout_timeout
+        let waiting = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || pool.checkout().map(drop))
+        };
+        wait_until(|| pool.state().waiting == 1);
+        net.advance(Duration::from_secs(5));
+        assert!(matches!(waiting.join().unwrap(), Err(PoolError::Timeout(_))));
+
+        // And the probe, which waits for a checkout too, counts as down once the health check's timeout is over
+        let health = Arc::new(health);
+        let checking = {
+            let health = Arc::clone(&health);
+            thread::spawn(move || health.check(&[Kind::Readiness]))
+        };
+        wait_until(|| pool.state().waiting == 1);
+        net.advance(Duration::from_secs(2));
+        assert_eq!(Status::TimedOut(Duration::from_secs(2)), checking.join().unwrap().checks[0].status);
+        // The probe itself gives up when the checkout does, before its worker is joined
+        net.advance(Duration::from_secs(3));
+        wait_until(|| pool.state().waiting == 0);
+        drop(held);
+        assert_eq!(Duration::from_secs(40), net.clock().elapsed());
+    }
+}
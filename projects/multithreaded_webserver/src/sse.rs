@@ -0,0 +1,260 @@
+// Server-Sent Events
+
+// Server-sent events are a simpler alternative to WebSockets when only the server needs to push data, like a live metrics view.
+// The response has Content-Type: text/event-stream and no Content-Length, and the server keeps writing events to it:
+    // id: 7
+    // event: tick
+    // data: first line
+    // data: second line
+    //                      <- an empty line ends the event
+// Lines starting with ':' are comments, which browsers ignore. We send one every now and then as a keep-alive,
+// so that proxies don't close a connection that has been quiet for a while.
+
+// In the browser: new EventSource("/events").addEventListener("tick", e => console.log(e.data))
+// EventSource reconnects by itself when the connection drops, sending the last id it saw in a Last-Event-ID header.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::net::Stream;
+
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<u64>,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Event {
+        Event { data: data.to_string(), ..Event::default() }
+    }
+
+    pub fn id(mut self, id: &str) -> Event {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    // The event name the browser dispatches on, "message" when there is none
+    pub fn event(mut self, name: &str) -> Event {
+        self.event = Some(name.to_string());
+        self
+    }
+
+    // How long the browser should wait before reconnecting, in milliseconds
+    pub fn retry(mut self, millis: u64) -> Event {
+        self.retry = Some(millis);
+        self
+    }
+
+    // A newline inside the id or name would end the field early and let the rest be read as another field, so they are stripped.
+    // Multi-line data is fine, it is sent as one data: field per line and the browser joins them back with '\n'.
+    pub fn encode(&self) -> String {
+        let clean = |s: &str| s.replace(['\r', '\n'], "");
+        let mut out = String::new();
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", clean(id)));
+        }
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", clean(event)));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {retry}\n"));
+        }
+        for line in self.data.split('\n') {
+            out.push_str(&format!("data: {}\n", line.strip_suffix('\r').unwrap_or(line)));
+        }
+        out.push('\n');
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the client has disconnected")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+// A handle for pushing events. It can be cloned and sent to other threads (ThreadPool jobs, timers...),
+// the Mutex makes sure events written from different threads don't get mixed up on the wire.
+// Once the client goes away every send returns Err(Disconnected), which is the signal for those threads to stop.
+#[derive(Clone)]
+pub struct EventSender {
+    stream: Arc<Mutex<Box<dyn Stream>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl EventSender {
+    fn write(&self, text: &str) -> Result<(), Disconnected> {
+        if self.is_closed() {
+            return Err(Disconnected);
+        }
+        let mut stream = self.stream.lock().unwrap();
+        if stream.write_all(text.as_bytes()).and_then(|_| stream.flush()).is_err() {
+            self.closed.store(true, Ordering::Relaxed);
+            return Err(Disconnected);
+        }
+        Ok(())
+    }
+
+    pub fn send(&self, event: Event) -> Result<(), Disconnected> {
+        self.write(&event.encode())
+    }
+
+    pub fn comment(&self, text: &str) -> Result<(), Disconnected> {
+        self.write(&format!(": {}\n\n", text.replace(['\r', '\n'], " ")))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    // Ends the stream from the server side. Shutting the socket down also wakes up the connection thread blocked in serve().
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+type StartFn = Box<dyn FnOnce(EventSender) + Send>;
+
+pub struct EventStream {
+    keep_alive: Duration,
+    start: StartFn,
+}
+
+impl EventStream {
+    pub fn new<F>(start: F) -> EventStream
+    where
+        F: FnOnce(EventSender) + Send + 'static,
+    {
+        EventStream { keep_alive: DEFAULT_KEEP_ALIVE, start: Box::new(start) }
+    }
+
+    pub fn keep_alive(mut self, interval: Duration) -> EventStream {
+        self.keep_alive = interval;
+        self
+    }
+
+    // Runs on the connection's thread after the response headers are written:
+    // 1. Hand a sender to the start function, which should move it to whatever produces the events and return quickly.
+    // 2. Then wait on the socket. A client never sends anything on an event stream, so a read returning 0 bytes (or failing)
+    //    means it disconnected. The read timeout doubles as the keep-alive timer: every time it expires, a comment is sent.
+    pub(crate) fn serve(self, stream: &mut dyn Stream) -> io::Result<()> {
+        let sender = EventSender {
+            stream: Arc::new(Mutex::new(stream.try_clone()?)),
+            closed: Arc::new(AtomicBool::new(false)),
+        };
+        (self.start)(sender.clone());
+
+        stream.set_read_timeout(Some(self.keep_alive))?;
+        let mut buf = [0; 512];
+        while !sender.is_closed() {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => continue,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    if sender.comment("keep-alive").is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        // Whatever ended the loop, the senders held by other threads must find out
+        sender.closed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Response;
+    use std::{
+        io::{BufRead, BufReader},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+    };
+
+    #[test]
+    fn encodes_events() {
+        assert_eq!("data: hello\n\n", Event::new("hello").encode());
+        assert_eq!(
+            "id: 3\nevent: tick\nretry: 500\ndata: a\ndata: b\n\n",
+            Event::new("a\r\nb").id("3").event("ti\nck").retry(500).encode()
+        );
+    }
+
+    #[test]
+    fn streams_events_from_other_threads_until_the_client_leaves() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (senders, sender_rx) = mpsc::channel();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let response = Response::from(
+                EventStream::new(move |events| senders.send(events).unwrap()).keep_alive(Duration::from_millis(50)),
+            );
+            response.write_to(&mut stream).unwrap();
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!("HTTP/1.1 200 OK\r\n", line);
+
+        let mut head = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            head.push_str(&line);
+        }
+        assert!(head.contains("Content-Type: text/event-stream"));
+
+        // Push from a thread that isn't the connection's
+        let events = sender_rx.recv().unwrap();
+        let pusher = events.clone();
+        thread::spawn(move || pusher.send(Event::new("42").event("answer")).unwrap()).join().unwrap();
+
+        let mut received = String::new();
+        while !received.ends_with("data: 42\n\n") {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            received.push_str(&line);
+        }
+        assert!(received.contains("event: answer\n"));
+
+        // Keep-alive comments arrive while nothing else is sent
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(": keep-alive\n", line);
+
+        // Hanging up ends serve() and makes further sends fail
+        drop(reader);
+        client.shutdown(Shutdown::Both).unwrap();
+        server.join().unwrap();
+        assert!(events.is_closed());
+        assert_eq!(Err(Disconnected), events.send(Event::new("too late")));
+    }
+}
@@ -0,0 +1,466 @@
+// Serving Static Files
+
+// Every handler in main.rs reads index.html or 404.html by name. StaticFiles serves a whole directory instead:
+// GET /css/site.css answers with the file css/site.css below the root, and GET /docs/ with docs/index.html.
+// A request for a file that isn't there goes on to the next layer of the chain, so the application's own routes still work.
+
+// The URL path is untrusted input that becomes a file path, so it's checked before it gets near the disk:
+    // 1. It's percent-decoded first, or "%2e%2e/" would sneak a ".." past the check.
+    // 2. Every segment has to be a plain name: "..", "." and anything with a backslash or a NUL byte are refused with 404,
+    //    which says no more than a file that doesn't exist.
+    // 3. Names starting with a dot are refused too, so .git or .env in the root never get served.
+
+// The files are read through a FileSystem (projects/common/src/vfs.rs), RealFs unless told otherwise.
+// Tests give it a MemFs, where a file can be made unreadable without chmod and without running as someone other than root.
+
+// A browser that has a file already shouldn't have to download it again. Every response says which version of the file it is,
+// twice: the ETag is a hash of the contents (FNV-1a, collections/std_collections/src/hashing.rs) and Last-Modified is the
+// file's modification time. The browser sends them back when it asks again, as If-None-Match and If-Modified-Since,
+// and when the file is still the same the answer is a 304 Not Modified with no body.
+// A Range header asks for only some bytes of the file, to resume a download or to seek in a video: bytes=100-199,
+// bytes=100- or the last 100 with bytes=-100. The answer is a 206 Partial Content with those bytes, or a 416 when the
+// file is shorter than where the range starts. Only a single range is served, a list of several gets the whole file,
+// which the RFC allows. An If-Range with a version that isn't the current one gets the whole file too, since the bytes the
+// client already has are from another version.
+
+// Looking the file up for every request costs a stat or two, which a 304 would otherwise not need at all. So what was found,
+// with the ETag of what was read last, is kept in a RwLock'd map for cache_for, one second by default: all requests can
+// read it at once, and only the first one after it is out of date has to write. A file changed in the meantime is noticed
+// a second late, and its new ETag is computed the next time it's read.
+
+use std::{
+    collections::HashMap,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use common::clock::{Clock, SystemClock};
+use common::vfs::{FileSystem, RealFs};
+use std_collections::hashing::fnv1a_64;
+
+use crate::{
+    encoding::percent_decode,
+    http::{Request, Response},
+    http_date,
+    middleware::{Middleware, Next},
+};
+
+pub struct StaticFiles {
+    root: PathBuf,
+    fs: Arc<dyn FileSystem>,
+    index: String,
+    clock: Arc<dyn Clock>,
+    cache_for: Duration,
+    // By URL path, not file path: a directory's entry is its index file, so that isn't looked up again either
+    cache: RwLock<HashMap<String, Cached>>,
+}
+
+#[derive(Debug, Clone)]
+struct Cached {
+    file: PathBuf,
+    len: u64,
+    modified: SystemTime,
+    // Known once the file has been read, and kept for as long as the length and the modification time don't change
+    etag: Option<String>,
+    checked: Instant,
+}
+
+// Which part of the file to send
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Whole,
+    Bytes(Range<u64>),
+    Unsatisfiable,
+}
+
+// By extension, what browsers need to show the file instead of downloading it
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+impl StaticFiles {
+    pub fn new<P: AsRef<Path>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.as_ref().to_path_buf(),
+            fs: Arc::new(RealFs),
+            index: String::from("index.html"),
+            clock: Arc::new(SystemClock),
+            cache_for: Duration::from_secs(1),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn file_system(mut self, fs: Arc<dyn FileSystem>) -> StaticFiles {
+        self.fs = fs;
+        self
+    }
+
+    // The file served for a directory, index.html by default
+    pub fn index(mut self, name: &str) -> StaticFiles {
+        self.index = name.to_string();
+        self
+    }
+
+    // How long what was found for a path is trusted before the file system is asked again. Zero asks every time
+    pub fn cache_for(mut self, cache_for: Duration) -> StaticFiles {
+        self.cache_for = cache_for;
+        self
+    }
+
+    // For tests, where the cache should expire without waiting for it
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> StaticFiles {
+        self.clock = clock;
+        self
+    }
+
+    // The file below the root that the URL path stands for, None when the path isn't allowed
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let decoded = String::from_utf8(percent_decode(url_path, false).ok()?).ok()?;
+        let mut path = self.root.clone();
+        for segment in decoded.split('/').filter(|segment| !segment.is_empty()) {
+            if segment.starts_with('.') || segment.contains(['\\', '\0']) {
+                return None;
+            }
+            path.push(segment);
+        }
+        Some(path)
+    }
+
+    // The file for the URL path, from the cache while it's fresh. A path that isn't allowed is NotFound like a missing file
+    fn lookup(&self, url_path: &str) -> io::Result<Cached> {
+        let now = self.clock.now();
+        let previous = self.cache.read().unwrap().get(url_path).cloned();
+        if let Some(cached) = previous.as_ref().filter(|cached| now.duration_since(cached.checked) < self.cache_for) {
+            return Ok(cached.clone());
+        }
+
+        let mut file = self.resolve(url_path).ok_or(io::ErrorKind::NotFound)?;
+        let mut info = self.fs.metadata(&file)?;
+        if info.is_dir {
+            file.push(&self.index);
+            info = self.fs.metadata(&file)?;
+        }
+        // The ETag of the contents that were read last, if the file still looks the same
+        let etag = previous
+            .filter(|old| old.file == file && old.len == info.len && old.modified == info.modified)
+            .and_then(|old| old.etag);
+        let cached = Cached { file, len: info.len, modified: info.modified, etag, checked: now };
+        self.cache.write().unwrap().insert(url_path.to_string(), cached.clone());
+        Ok(cached)
+    }
+
+    // Reads the file, and keeps the ETag of what was read
+    fn read(&self, url_path: &str, cached: &mut Cached) -> io::Result<Vec<u8>> {
+        let contents = self.fs.read(&cached.file)?;
+        let etag = etag(&contents);
+        if cached.etag.as_ref() != Some(&etag) {
+            cached.etag = Some(etag);
+            if let Some(entry) = self.cache.write().unwrap().get_mut(url_path) {
+                entry.etag = cached.etag.clone();
+            }
+        }
+        Ok(contents)
+    }
+
+    fn serve(&self, request: &Request) -> io::Result<Response> {
+        let url_path = request.path_only();
+        let mut cached = self.lookup(url_path)?;
+        // The first time, the ETag needs the contents, and they'll be needed anyway unless the client has the same version
+        let mut contents = match cached.etag {
+            Some(_) => None,
+            None => Some(self.read(url_path, &mut cached)?),
+        };
+        if not_modified(request, &cached) {
+            return Ok(with_version(Response::new(304, Vec::new()), &cached));
+        }
+
+        let contents = match contents.take() {
+            Some(contents) => contents,
+            None => self.read(url_path, &mut cached)?,
+        };
+        let content_type = content_type(&cached.file);
+        let response = match part(request, &cached, contents.len() as u64) {
+            Part::Whole => Response::new(200, contents).with_header("Content-Type", content_type),
+            Part::Bytes(range) => {
+                let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, contents.len());
+                Response::new(206, contents[range.start as usize..range.end as usize].to_vec())
+                    .with_header("Content-Type", content_type)
+                    .with_header("Content-Range", &content_range)
+            }
+            Part::Unsatisfiable => Response::new(416, Vec::new()).with_header("Content-Range", &format!("bytes */{}", contents.len())),
+        };
+        Ok(with_version(response.with_header("Accept-Ranges", "bytes"), &cached))
+    }
+}
+
+// The headers that say which version of the file the response is about
+fn with_version(response: Response, cached: &Cached) -> Response {
+    response
+        .with_header("ETag", cached.etag.as_deref().unwrap_or_default())
+        .with_header("Last-Modified", &http_date::format(cached.modified))
+}
+
+// A strong ETag, which the client sends back between quotes like it got it. The length makes a collision of the 64 bit hash
+// less likely still, since both would have to match
+pub fn etag(contents: &[u8]) -> String {
+    format!("\"{:x}-{:016x}\"", contents.len(), fnv1a_64(contents))
+}
+
+// Last-Modified only has whole seconds, so a file changed within the second it was last served would look unchanged
+fn modified_secs(modified: SystemTime) -> SystemTime {
+    http_date::parse(&http_date::format(modified)).unwrap_or(modified)
+}
+
+// Whether the client has this version already. An If-None-Match decides on its own when there is one, since an ETag is more
+// precise than a date
+fn not_modified(request: &Request, cached: &Cached) -> bool {
+    if let Some(tags) = request.header("If-None-Match") {
+        let Some(etag) = &cached.etag else { return false };
+        // W/ marks a weak ETag, and If-None-Match compares them as if they were strong
+        return tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    match request.header("If-Modified-Since").and_then(http_date::parse) {
+        Some(since) => modified_secs(cached.modified) <= since,
+        None => false,
+    }
+}
+
+fn part(request: &Request, cached: &Cached, len: u64) -> Part {
+    let Some(range) = request.header("Range") else { return Part::Whole };
+    if let Some(if_range) = request.header("If-Range") {
+        let same = match http_date::parse(if_range) {
+            Some(date) => modified_secs(cached.modified) == date,
+            None => cached.etag.as_deref() == Some(if_range),
+        };
+        if !same {
+            return Part::Whole;
+        }
+    }
+    // Anything that isn't one range of bytes is ignored, and the whole file is sent
+    let Some(spec) = range.trim().strip_prefix("bytes=").filter(|spec| !spec.contains(',')) else { return Part::Whole };
+    let Some((first, last)) = spec.split_once('-') else { return Part::Whole };
+    let (first, last) = (first.trim(), last.trim());
+    let number = |text: &str| text.parse::<u64>().ok();
+    match (first.is_empty(), last.is_empty()) {
+        // The last bytes, however long the file is
+        (true, false) => match number(last) {
+            Some(0) => Part::Unsatisfiable,
+            Some(_) if len == 0 => Part::Unsatisfiable,
+            Some(suffix) => Part::Bytes(len.saturating_sub(suffix)..len),
+            None => Part::Whole,
+        },
+        (false, _) => match (number(first), if last.is_empty() { Some(u64::MAX) } else { number(last) }) {
+            (Some(first), Some(last)) if first <= last => match first < len {
+                true => Part::Bytes(first..last.min(len - 1) + 1),
+                false => Part::Unsatisfiable,
+            },
+            _ => Part::Whole,
+        },
+        (true, true) => Part::Whole,
+    }
+}
+
+impl Middleware for StaticFiles {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        if request.method != "GET" {
+            return next.run(request);
+        }
+        if self.resolve(request.path_only()).is_none() {
+            return Response::text(404, "Not Found");
+        }
+
+        match self.serve(request) {
+            Ok(response) => response,
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::IsADirectory | io::ErrorKind::NotADirectory) => {
+                next.run(request)
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Response::text(403, "Forbidden"),
+            // The details are for the server's log, the client only needs to know it wasn't its fault
+            Err(e) => {
+                common::error!("static files: {}: {e}", request.path_only());
+                Response::text(500, "Internal Server Error")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Body, middleware::Chain};
+    use common::clock::FakeClock;
+    use common::vfs::MemFs;
+
+    fn get(path: &str) -> Request {
+        get_with(path, &[])
+    }
+
+    fn get_with(path: &str, headers: &[(&str, &str)]) -> Request {
+        let headers: String = headers.iter().map(|(name, value)| format!("{name}: {value}\r\n")).collect();
+        Request::read_from(&mut format!("GET {path} HTTP/1.1\r\n{headers}\r\n").as_bytes()).unwrap()
+    }
+
+    fn site() -> (Arc<MemFs>, Chain) {
+        let fs = Arc::new(MemFs::new());
+        fs.add_file("site/index.html", "<h1>home</h1>")
+            .add_file("site/css/site.css", "h1 { color: red }")
+            .add_file("site/docs/index.html", "<h1>docs</h1>")
+            .add_file("site/.env", "SECRET=1")
+            .add_file("secret.txt", "outside the root");
+        let files = StaticFiles::new("site").file_system(fs.clone());
+        let app = Chain::new(|req: &mut Request| match req.path_only() {
+            "/api" => Response::text(200, "from the handler"),
+            _ => Response::text(404, "handler 404"),
+        })
+        .with(files);
+        (fs, app)
+    }
+
+    fn body(response: &Response) -> &[u8] {
+        match &response.body {
+            Body::Bytes(body) => body,
+            Body::EventStream(_) => panic!("expected a body"),
+        }
+    }
+
+    #[test]
+    fn serves_files_and_indexes() {
+        let (_, app) = site();
+        let css = app.handle(&mut get("/css/site.css"));
+        assert_eq!((200, Some("text/css; charset=utf-8")), (css.status, css.header("Content-Type")));
+        assert_eq!(b"h1 { color: red }", body(&css));
+        assert_eq!(b"<h1>home</h1>", body(&app.handle(&mut get("/"))));
+        assert_eq!(b"<h1>docs</h1>", body(&app.handle(&mut get("/docs/?page=2"))));
+
+        // Not a file, so the handler gets the request
+        assert_eq!(b"from the handler", body(&app.handle(&mut get("/api"))));
+        assert_eq!(b"handler 404", body(&app.handle(&mut get("/missing.png"))));
+    }
+
+    #[test]
+    fn refuses_to_leave_the_root() {
+        let (_, app) = site();
+        for path in ["/../secret.txt", "/css/%2e%2e/%2e%2e/secret.txt", "/.env", "/css/..%5c..%5csecret.txt"] {
+            let response = app.handle(&mut get(path));
+            assert_eq!((404, &b"Not Found"[..]), (response.status, body(&response)), "{path}");
+        }
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let (fs, app) = site();
+        fs.fail("site/css/site.css", io::ErrorKind::PermissionDenied);
+        assert_eq!(403, app.handle(&mut get("/css/site.css")).status);
+
+        fs.fail("site/index.html", io::ErrorKind::Other);
+        assert_eq!(500, app.handle(&mut get("/")).status);
+    }
+
+    #[test]
+    fn conditional_requests_get_304() {
+        let (_, app) = site();
+        let first = app.handle(&mut get("/css/site.css"));
+        let etag = first.header("ETag").unwrap().to_string();
+        let modified = first.header("Last-Modified").unwrap().to_string();
+        assert_eq!(etag, super::etag(b"h1 { color: red }"));
+        assert_eq!(Some("bytes"), first.header("Accept-Ranges"));
+
+        for headers in [
+            [("If-None-Match", etag.as_str())],
+            [("If-None-Match", &format!("\"other\", W/{etag}"))],
+            [("If-None-Match", "*")],
+            [("If-Modified-Since", modified.as_str())],
+        ] {
+            let response = app.handle(&mut get_with("/css/site.css", &headers));
+            assert_eq!((304, &b""[..]), (response.status, body(&response)), "{headers:?}");
+            assert_eq!(Some(etag.as_str()), response.header("ETag"));
+        }
+        for headers in [
+            [("If-None-Match", "\"other\"")],
+            [("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")],
+            [("If-Modified-Since", "yesterday")],
+        ] {
+            assert_eq!(200, app.handle(&mut get_with("/css/site.css", &headers)).status, "{headers:?}");
+        }
+        // The ETag wins over the date when there are both
+        let both = [("If-None-Match", "\"other\""), ("If-Modified-Since", modified.as_str())];
+        assert_eq!(200, app.handle(&mut get_with("/css/site.css", &both)).status);
+    }
+
+    #[test]
+    fn ranges_get_206() {
+        let (_, app) = site();
+        let etag = app.handle(&mut get("/css/site.css")).header("ETag").unwrap().to_string();
+        let range = |range: &str, if_range: Option<&str>| {
+            let mut headers = vec![("Range", range)];
+            headers.extend(if_range.map(|if_range| ("If-Range", if_range)));
+            let response = app.handle(&mut get_with("/css/site.css", &headers));
+            let content_range = response.header("Content-Range").map(str::to_string);
+            (response.status, String::from_utf8(body(&response).to_vec()).unwrap(), content_range)
+        };
+        // The file is "h1 { color: red }", 17 bytes
+        assert_eq!((206, String::from("h1"), Some(String::from("bytes 0-1/17"))), range("bytes=0-1", None));
+        assert_eq!((206, String::from("red }"), Some(String::from("bytes 12-16/17"))), range("bytes=12-", None));
+        assert_eq!((206, String::from("d }"), Some(String::from("bytes 14-16/17"))), range("bytes=-3", None));
+        assert_eq!((206, String::from("}"), Some(String::from("bytes 16-16/17"))), range("bytes=16-1000", None));
+        assert_eq!((416, String::new(), Some(String::from("bytes */17"))), range("bytes=17-", None));
+        assert_eq!(416, range("bytes=-0", None).0);
+        // Several ranges, or nonsense, get the whole file
+        assert_eq!(200, range("bytes=0-1,4-5", None).0);
+        assert_eq!(200, range("bytes=5-1", None).0);
+        assert_eq!(200, range("lines=1-2", None).0);
+        // If-Range only gets the range for the current version
+        assert_eq!(206, range("bytes=0-1", Some(&etag)).0);
+        assert_eq!(200, range("bytes=0-1", Some("\"old\"")).0);
+    }
+
+    #[test]
+    fn lookups_are_cached_for_a_while() {
+        let clock = Arc::new(FakeClock::new());
+        let fs = Arc::new(MemFs::with_clock(clock.clone()));
+        fs.add_file("site/app.js", "let version = 1;");
+        let app = Chain::new(|_: &mut Request| Response::text(404, "handler 404"))
+            .with(StaticFiles::new("site").file_system(fs.clone()).clock(clock.clone()).cache_for(Duration::from_secs(5)));
+        let etag = app.handle(&mut get("/app.js")).header("ETag").unwrap().to_string();
+
+        // A new version within the 5 seconds isn't looked for, so the client is told its version is current
+        fs.add_file("site/app.js", "let version = 22;");
+        let revalidate = [("If-None-Match", etag.as_str())];
+        assert_eq!(304, app.handle(&mut get_with("/app.js", &revalidate)).status);
+        // Once they are over, it is
+        clock.advance(Duration::from_secs(5));
+        let response = app.handle(&mut get_with("/app.js", &revalidate));
+        assert_eq!((200, &b"let version = 22;"[..]), (response.status, body(&response)));
+        assert_ne!(Some(etag.as_str()), response.header("ETag"));
+
+        // A file that is gone goes on to the handler
+        fs.remove("site/app.js");
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(b"handler 404", body(&app.handle(&mut get("/app.js"))));
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!("image/png", content_type(Path::new("logo.PNG")));
+        assert_eq!("application/octet-stream", content_type(Path::new("archive.tar.zst")));
+        assert_eq!("application/octet-stream", content_type(Path::new("Makefile")));
+    }
+}
@@ -0,0 +1,348 @@
+// Resource Usage
+
+// A server that leaks slowly runs fine for days, and then it's out of memory, or accept() fails with "Too many open files"
+// and it stops answering without a single error in its own code. Both are easy to see coming from the outside, if the
+// server keeps an eye on three numbers about itself:
+    // 1. Its resident set size (RSS), the memory it really takes up, not just what it reserved.
+    // 2. How many file descriptors it has open: every connection, file and pipe is one, and the operating system only
+    //    allows so many per process (ulimit -n).
+    // 3. How many threads it runs. The ThreadPool's stay the same, one that's spawned per connection or per timer doesn't.
+// There's no std API for any of them. Linux has them in /proc: /proc/self/status has VmRSS and Threads, every open
+// descriptor is a link in /proc/self/fd and /proc/self/limits has the limit of them. Elsewhere it takes another API per
+// platform, and the Portable fallback only gets what the ps command and /dev/fd tell it, which is less and a lot slower.
+
+// Where the numbers come from is a Source, like the time comes from a common::clock::Clock. system() picks the right one,
+// and FakeSource is for tests, it has whatever numbers the test sets. ProcFs::at reads another directory than /proc,
+// so the parsing can be tested with files that look like it, on any platform.
+// The numbers go into the MetricsSnapshot (src/metrics.rs), and a Watchdog checks them every interval: when one gets
+// close to its limit, 90% by default, it logs a warning. Once, and not again until it went down and came back.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// None when the Source can't tell
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub rss_bytes: Option<u64>,
+    pub open_fds: Option<u64>,
+    pub threads: Option<u64>,
+    // The soft limit of open file descriptors, the one that's enforced. None when there's none
+    pub max_fds: Option<u64>,
+}
+
+pub trait Source: Send + Sync {
+    fn usage(&self) -> Usage;
+}
+
+// /proc on Linux, the Portable fallback everywhere else
+pub fn system() -> Arc<dyn Source> {
+    let proc_fs = ProcFs::new();
+    match proc_fs.available() {
+        true => Arc::new(proc_fs),
+        false => Arc::new(Portable),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcFs {
+    root: PathBuf,
+}
+
+impl ProcFs {
+    pub fn new() -> ProcFs {
+        ProcFs::at("/proc")
+    }
+
+    // A directory laid out like /proc, with a self/ in it
+    pub fn at(root: impl Into<PathBuf>) -> ProcFs {
+        ProcFs { root: root.into() }
+    }
+
+    pub fn available(&self) -> bool {
+        self.root.join("self/status").is_file()
+    }
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Source for ProcFs {
+    fn usage(&self) -> Usage {
+        // A file that isn't there reads as empty, and then each number is None
+        let read = |name: &str| fs::read_to_string(self.root.join("self").join(name)).unwrap_or_default();
+        let status = read("status");
+        Usage {
+            rss_bytes: status_field(&status, "VmRSS").map(|kb| kb * 1024),
+            // It counts the descriptor it's read through as well, which is closed again right after
+            open_fds: count_entries(&self.root.join("self/fd")),
+            threads: status_field(&status, "Threads"),
+            max_fds: max_open_files(&read("limits")),
+        }
+    }
+}
+
+// "VmRSS:	   10240 kB", the number is in kB for the sizes
+fn status_field(status: &str, name: &str) -> Option<u64> {
+    let value = status.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?;
+    value.split_whitespace().next()?.parse().ok()
+}
+
+// "Max open files            1024                 524288               files", the soft limit comes first.
+// It's "unlimited" when there's none, which doesn't parse
+fn max_open_files(limits: &str) -> Option<u64> {
+    let line = limits.lines().find_map(|line| line.strip_prefix("Max open files"))?;
+    line.split_whitespace().next()?.parse().ok()
+}
+
+fn count_entries(dir: &Path) -> Option<u64> {
+    Some(fs::read_dir(dir).ok()?.count() as u64)
+}
+
+// macOS and the BSDs have no /proc, but they have ps and /dev/fd. Every usage() runs two programs, so it's for a snapshot
+// every few seconds, not for every request. Nothing works on Windows, where every number is None
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Portable;
+
+impl Source for Portable {
+    fn usage(&self) -> Usage {
+        let pid = process::id().to_string();
+        Usage {
+            rss_bytes: output(Command::new("ps").args(["-o", "rss=", "-p", &pid])).map(|kb| kb * 1024),
+            open_fds: count_entries(Path::new("/dev/fd")),
+            threads: None,
+            // The shell inherits the limit from us
+            max_fds: output(Command::new("sh").args(["-c", "ulimit -n"])),
+        }
+    }
+}
+
+// The number a command prints, None when it can't be run or prints something else
+fn output(command: &mut Command) -> Option<u64> {
+    let output = command.output().ok().filter(|output| output.status.success())?;
+    String::from_utf8(output.stdout).ok()?.trim().parse().ok()
+}
+
+// The numbers a test wants, set() changes them
+#[derive(Debug, Default)]
+pub struct FakeSource {
+    usage: Mutex<Usage>,
+}
+
+impl FakeSource {
+    pub fn new(usage: Usage) -> FakeSource {
+        FakeSource { usage: Mutex::new(usage) }
+    }
+
+    pub fn set(&self, usage: Usage) {
+        *self.usage.lock().unwrap() = usage;
+    }
+}
+
+impl Source for FakeSource {
+    fn usage(&self) -> Usage {
+        *self.usage.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Memory,
+    FileDescriptors,
+    Threads,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::Memory => write!(f, "memory"),
+            Resource::FileDescriptors => write!(f, "open file descriptors"),
+            Resource::Threads => write!(f, "threads"),
+        }
+    }
+}
+
+// What the watchdog compares the usage with. None doesn't watch it, except for the file descriptors, which are watched
+// against the limit the Source reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_rss_bytes: Option<u64>,
+    pub max_fds: Option<u64>,
+    pub max_threads: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Warning {
+    pub resource: Resource,
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = self.used as f64 / self.limit as f64 * 100.0;
+        match self.resource {
+            Resource::Memory => {
+                let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+                write!(f, "memory at {:.1} MiB of {:.1} MiB ({percent:.0}%)", mib(self.used), mib(self.limit))
+            }
+            resource => write!(f, "{resource} at {} of {} ({percent:.0}%)", self.used, self.limit),
+        }
+    }
+}
+
+pub struct Watchdog {
+    source: Arc<dyn Source>,
+    limits: Limits,
+    warn_at: f64,
+    // The resources that are over warn_at since the last check, so they're only warned about once
+    over: Vec<Resource>,
+}
+
+impl Watchdog {
+    pub fn new(source: Arc<dyn Source>, limits: Limits) -> Watchdog {
+        Watchdog { source, limits, warn_at: 0.9, over: Vec::new() }
+    }
+
+    // The fraction of a limit that's close enough to warn about
+    pub fn warn_at(mut self, fraction: f64) -> Watchdog {
+        self.warn_at = fraction;
+        self
+    }
+
+    // Logs and returns the warnings for the resources that went over warn_at since the last check
+    pub fn check(&mut self) -> Vec<Warning> {
+        let usage = self.source.usage();
+        let watched = [
+            (Resource::Memory, usage.rss_bytes, self.limits.max_rss_bytes),
+            (Resource::FileDescriptors, usage.open_fds, self.limits.max_fds.or(usage.max_fds)),
+            (Resource::Threads, usage.threads, self.limits.max_threads),
+        ];
+        let mut warnings = Vec::new();
+        for (resource, used, limit) in watched {
+            let (Some(used), Some(limit)) = (used, limit) else {
+                continue;
+            };
+            let close = used as f64 >= limit as f64 * self.warn_at;
+            let warned = self.over.contains(&resource);
+            if close && !warned {
+                let warning = Warning { resource, used, limit };
+                common::warn!("{warning}");
+                warnings.push(warning);
+                self.over.push(resource);
+            } else if !close && warned {
+                self.over.retain(|over| *over != resource);
+            }
+        }
+        warnings
+    }
+
+    // Checks every interval on a thread of its own, until the WatchdogThread is dropped
+    pub fn spawn(mut self, interval: Duration) -> WatchdogThread {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                self.check();
+            }
+        });
+        WatchdogThread { stop: Some(stop), thread: Some(thread) }
+    }
+}
+
+pub struct WatchdogThread {
+    // Dropping the sender wakes the thread up and tells it to stop
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatchdogThread {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            // A check that panicked already said so on stderr
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::TempDir;
+
+    #[test]
+    fn reads_a_proc_directory() {
+        let proc_dir = TempDir::new();
+        fs::create_dir_all(proc_dir.child("self/fd")).unwrap();
+        for fd in ["0", "1", "2", "7"] {
+            fs::write(proc_dir.child("self/fd").join(fd), "").unwrap();
+        }
+        fs::write(proc_dir.child("self/status"), "Name:\tmws\nVmPeak:\t   20480 kB\nVmRSS:\t   10240 kB\nThreads:\t9\n").unwrap();
+        fs::write(
+            proc_dir.child("self/limits"),
+            "Limit                     Soft Limit           Hard Limit           Units\n\
+             Max processes             63204                63204                processes\n\
+             Max open files            1024                 524288               files\n",
+        )
+        .unwrap();
+
+        let proc_fs = ProcFs::at(proc_dir.path());
+        assert!(proc_fs.available());
+        let usage = Usage { rss_bytes: Some(10 << 20), open_fds: Some(4), threads: Some(9), max_fds: Some(1024) };
+        assert_eq!(usage, proc_fs.usage());
+        assert_eq!(None, max_open_files("Max open files            unlimited            unlimited            files"));
+
+        // Nothing there, nothing known
+        let empty = ProcFs::at(proc_dir.child("nothing"));
+        assert!(!empty.available());
+        assert_eq!(Usage::default(), empty.usage());
+    }
+
+    #[test]
+    fn the_system_knows_about_this_thread() {
+        let usage = system().usage();
+        // There's no ps or /dev/fd everywhere, but Linux always has /proc
+        if ProcFs::new().available() {
+            assert!(usage.threads.is_some_and(|threads| threads >= 1), "{usage:?}");
+            assert!(usage.rss_bytes.is_some_and(|rss| rss > 0), "{usage:?}");
+            assert!(usage.open_fds.is_some_and(|fds| fds >= 3), "{usage:?}");
+        }
+    }
+
+    #[test]
+    fn watchdog_warns_once_per_approach() {
+        let usage = Usage { rss_bytes: Some(100 << 20), open_fds: Some(10), threads: Some(4), max_fds: Some(100) };
+        let source = Arc::new(FakeSource::new(usage));
+        let limits = Limits { max_rss_bytes: Some(128 << 20), max_fds: None, max_threads: Some(8) };
+        let mut watchdog = Watchdog::new(Arc::clone(&source) as Arc<dyn Source>, limits);
+        assert_eq!(Vec::<Warning>::new(), watchdog.check());
+
+        // Close to the fd limit from the Source, and to the memory one. Then they stay there
+        source.set(Usage { rss_bytes: Some(120 << 20), open_fds: Some(95), threads: Some(4), max_fds: Some(100) });
+        let warnings = watchdog.check();
+        let resources: Vec<_> = warnings.iter().map(|warning| warning.resource).collect();
+        assert_eq!(vec![Resource::Memory, Resource::FileDescriptors], resources);
+        assert_eq!("memory at 120.0 MiB of 128.0 MiB (94%)", warnings[0].to_string());
+        assert_eq!("open file descriptors at 95 of 100 (95%)", warnings[1].to_string());
+        assert!(watchdog.check().is_empty());
+
+        // Going down and coming back warns again, and a lower warn_at warns sooner
+        source.set(Usage { rss_bytes: Some(10 << 20), open_fds: Some(95), threads: Some(6), max_fds: Some(100) });
+        assert!(watchdog.check().is_empty());
+        source.set(Usage { rss_bytes: Some(120 << 20), open_fds: Some(95), threads: Some(6), max_fds: Some(100) });
+        assert_eq!(vec![Warning { resource: Resource::Memory, used: 120 << 20, limit: 128 << 20 }], watchdog.check());
+        let mut sooner = Watchdog::new(source, limits).warn_at(0.75);
+        assert_eq!(3, sooner.check().len());
+    }
+}
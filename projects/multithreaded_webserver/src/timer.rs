@@ -0,0 +1,269 @@
+// A Timer Thread
+
+// A lot of things in a server have to happen "later": close a keep-alive connection that has been idle for 5 seconds,
+// run a job again after a backoff, give up on a request that takes too long. The simplest way is a thread per timer
+// that sleeps and then does the work, but a thread costs memory and a system call to start, and most of these timers
+// never fire: the client sends its next request, and the idle timeout is cancelled.
+
+// TimerWheel runs every timer on one thread instead:
+    // 1. The timers are kept in a binary heap ordered by deadline, so the earliest one is always on top.
+    //    The thread sleeps on a Condvar until that deadline, or until a new timer is scheduled that is due even earlier.
+    // 2. The callbacks are kept apart from the heap, in a HashMap keyed by the timer's id.
+    //    Cancelling a timer only removes its callback, which is O(1); the heap entry stays behind and is skipped when it comes up.
+    // 3. schedule() returns a TimerToken, the only way to cancel the timer. Dropping the token does NOT cancel it.
+// Callbacks run on the timer thread, one after the other, so they should be short: hand anything slow to a ThreadPool
+// (that is what ThreadPool::execute_after does). A callback that panics is caught, so it doesn't stop the other timers.
+// Dropping the TimerWheel stops the thread, and the timers that haven't fired yet are dropped without running.
+// The deadlines are instants of the wheel's Clock (projects/common/src/clock.rs), the real time unless with_clock() says
+// otherwise: on a FakeClock, a timer fires once the test advanced the clock past it.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, Weak},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use common::clock::{Clock, SystemClock};
+
+use crate::time_ext::Deadline;
+
+type Callback = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Default)]
+struct TimerState {
+    // Reverse turns the max-heap into a min-heap. The id breaks ties, so timers with the same deadline fire in the order they were scheduled.
+    heap: BinaryHeap<Reverse<(Instant, u64)>>,
+    callbacks: HashMap<u64, Callback>,
+    next_id: u64,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<TimerState>,
+    changed: Condvar,
+    clock: Arc<dyn Clock>,
+}
+
+// TimerToken derives Debug, this keeps the callbacks out of it
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TimerWheel")
+    }
+}
+
+pub struct TimerWheel {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TimerWheel {
+    pub fn new() -> TimerWheel {
+        TimerWheel::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> TimerWheel {
+        let shared = Arc::new(Shared { state: Mutex::new(TimerState::default()), changed: Condvar::new(), clock });
+
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                while let Some(due) = shared.next_due() {
+                    for callback in due {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(callback));
+                    }
+                }
+            })
+        };
+
+        TimerWheel { shared, thread: Some(thread) }
+    }
+
+    pub fn schedule(&self, delay: Duration, f: impl FnOnce() + Send + 'static) -> TimerToken {
+        self.schedule_at(Deadline::at(self.shared.clock.now() + delay), f)
+    }
+
+    pub fn schedule_at(&self, deadline: Deadline, f: impl FnOnce() + Send + 'static) -> TimerToken {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.callbacks.insert(id, Box::new(f));
+        state.heap.push(Reverse((deadline.instant(), id)));
+
+        // Only a timer that is now the earliest changes how long the thread has to sleep
+        if state.heap.peek() == Some(&Reverse((deadline.instant(), id))) {
+            self.shared.changed.notify_one();
+        }
+        TimerToken { id, shared: Arc::downgrade(&self.shared) }
+    }
+
+    // Timers that have neither fired nor been cancelled
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().callbacks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        TimerWheel::new()
+    }
+}
+
+impl Drop for TimerWheel {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.changed.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // The callbacks may hold on to things (a channel Sender, a socket) that their owners are waiting to see dropped
+        self.shared.state.lock().unwrap().callbacks.clear();
+    }
+}
+
+impl Shared {
+    // Blocks until at least one timer is due and returns their callbacks, or None once the wheel is dropped.
+    // The callbacks are run by the caller, after the lock is released, so a callback can schedule or cancel other timers.
+    fn next_due(&self) -> Option<Vec<Callback>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            let now = self.clock.now();
+            let mut due = Vec::new();
+            while let Some(&Reverse((at, id))) = state.heap.peek() {
+                if at > now {
+                    break;
+                }
+                state.heap.pop();
+                // No callback means the timer was cancelled
+                if let Some(callback) = state.callbacks.remove(&id) {
+                    due.push(callback);
+                }
+            }
+            if !due.is_empty() {
+                return Some(due);
+            }
+
+            state = match state.heap.peek() {
+                Some(&Reverse((at, _))) => self.changed.wait_timeout(state, self.clock.wait_slice(at)).unwrap().0,
+                None => self.changed.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+// The token only holds a Weak reference, so a token that outlives its TimerWheel doesn't keep anything alive
+#[derive(Debug, Clone)]
+pub struct TimerToken {
+    id: u64,
+    shared: Weak<Shared>,
+}
+
+impl TimerToken {
+    // true if the timer was stopped before it fired, false if it already fired or was cancelled before
+    pub fn cancel(&self) -> bool {
+        match self.shared.upgrade() {
+            Some(shared) => shared.state.lock().unwrap().callbacks.remove(&self.id).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        match self.shared.upgrade() {
+            Some(shared) => shared.state.lock().unwrap().callbacks.contains_key(&self.id),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::clock::FakeClock;
+    use std::sync::mpsc;
+
+    #[test]
+    fn timers_fire_in_deadline_order() {
+        let timer = TimerWheel::new();
+        let (sender, receiver) = mpsc::channel();
+        for (n, millis) in [(3, 60), (1, 20), (2, 40), (4, 60)] {
+            let sender = sender.clone();
+            timer.schedule(Duration::from_millis(millis), move || sender.send(n).unwrap());
+        }
+        drop(sender);
+
+        let start = Instant::now();
+        let order: Vec<i32> = (0..4).map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        assert_eq!(vec![1, 2, 3, 4], order);
+        assert!(start.elapsed() >= Duration::from_millis(55));
+        assert!(timer.is_empty());
+    }
+
+    #[test]
+    fn an_earlier_timer_wakes_the_thread() {
+        let timer = TimerWheel::new();
+        let (sender, receiver) = mpsc::channel();
+        let late = sender.clone();
+        timer.schedule(Duration::from_secs(3600), move || late.send("late").unwrap());
+        timer.schedule(Duration::from_millis(10), move || sender.send("soon").unwrap());
+        assert_eq!(Ok("soon"), receiver.recv_timeout(Duration::from_secs(5)));
+        assert_eq!(1, timer.len());
+    }
+
+    #[test]
+    fn cancelled_timers_never_fire() {
+        let timer = TimerWheel::new();
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = {
+            let sender = sender.clone();
+            timer.schedule(Duration::from_millis(10), move || sender.send("cancelled").unwrap())
+        };
+        let fired = timer.schedule(Duration::from_millis(30), move || sender.send("fired").unwrap());
+
+        assert!(cancelled.is_pending());
+        assert!(cancelled.cancel());
+        assert!(!cancelled.cancel(), "only the first cancel stops anything");
+        assert_eq!(vec!["fired"], receiver.iter().collect::<Vec<_>>());
+        assert!(!fired.cancel(), "too late, it already fired");
+    }
+
+    #[test]
+    fn a_panicking_callback_doesnt_stop_the_others() {
+        let timer = TimerWheel::new();
+        let (sender, receiver) = mpsc::channel();
+        timer.schedule(Duration::ZERO, || panic!("boom"));
+        timer.schedule(Duration::from_millis(10), move || sender.send(()).unwrap());
+        assert_eq!(Ok(()), receiver.recv_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn timers_on_a_fake_clock_wait_for_it_to_be_advanced() {
+        let clock = Arc::new(FakeClock::new());
+        let timer = TimerWheel::with_clock(Arc::clone(&clock) as Arc<dyn Clock>);
+        let (sender, receiver) = mpsc::channel();
+        timer.schedule(Duration::from_secs(3600), move || sender.send(()).unwrap());
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(Ok(()), receiver.recv_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn dropping_the_wheel_drops_pending_timers() {
+        let timer = TimerWheel::new();
+        let (sender, receiver) = mpsc::channel::<()>();
+        let token = timer.schedule(Duration::from_secs(3600), move || sender.send(()).unwrap());
+        drop(timer);
+        // The callback, and the Sender inside it, are gone without having run
+        assert_eq!(Err(mpsc::RecvError), receiver.recv());
+        assert!(!token.cancel());
+    }
+}
@@ -0,0 +1,76 @@
+// Request Tracing
+
+// Tracing is the middleware that puts every request in a span of its own (common::tracing), named after its method and path.
+// Everything the handler does happens inside it: the spans it makes are children of the request's, the jobs it hands to the
+// ThreadPool take the span along, and the lines it logs end with the trace ID.
+    // 1. A request with a traceparent header, from a proxy or another service that's tracing too, continues that trace.
+    //    One without starts a new trace.
+    // 2. The response has a traceparent header with the request's span, so a client can find its request in the traces.
+    // 3. GET ENDPOINT answers with the finished spans as JSON, for chrome://tracing or ui.perfetto.dev. Spans are only kept
+    //    after common::tracing::keep_finished, the server's main does it.
+
+use common::tracing::{self, Context, Span};
+
+use crate::{
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+};
+
+pub const ENDPOINT: &str = "/__traces";
+
+pub struct Tracing;
+
+impl Middleware for Tracing {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        if request.method == "GET" && request.path_only() == ENDPOINT {
+            return Response::new(200, tracing::to_json(&tracing::finished()).into_bytes())
+                .with_header("Content-Type", "application/json");
+        }
+
+        let parent = request.header("traceparent").and_then(Context::from_traceparent);
+        let name = format!("{} {}", request.method, request.path_only());
+        let mut span = Span::with_parent(&name, parent).field("method", &request.method).field("path", request.path_only());
+        let response = next.run(request);
+        span.record("status", response.status);
+        response.with_header("traceparent", &span.context().traceparent())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Body, middleware::Chain};
+
+    fn get(path: &str, traceparent: Option<&str>) -> Request {
+        let header = traceparent.map_or(String::new(), |traceparent| format!("traceparent: {traceparent}\r\n"));
+        Request::read_from(&mut format!("GET {path} HTTP/1.1\r\n{header}\r\n").as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn requests_get_a_span_in_the_callers_trace() {
+        tracing::keep_finished(1000);
+        let app = Chain::new(|_: &mut Request| {
+            let _render = Span::new("render");
+            Response::text(200, "hi")
+        })
+        .with(Tracing);
+
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let response = app.handle(&mut get("/users?id=7", Some(parent)));
+        let context = Context::from_traceparent(response.header("traceparent").unwrap()).unwrap();
+        assert_eq!(Context::from_traceparent(parent).unwrap().trace_id, context.trace_id);
+
+        let spans: Vec<_> = tracing::finished().into_iter().filter(|span| span.trace_id == context.trace_id).collect();
+        assert_eq!(vec!["render", "GET /users"], spans.iter().map(|span| span.name.as_str()).collect::<Vec<_>>());
+        assert_eq!(Some(context.span_id), spans[0].parent_id);
+        assert!(spans[1].fields.contains(&(String::from("status"), String::from("200"))));
+
+        // Without a traceparent it's a trace of its own
+        let response = app.handle(&mut get("/", None));
+        assert_ne!(context.trace_id, Context::from_traceparent(response.header("traceparent").unwrap()).unwrap().trace_id);
+
+        let response = app.handle(&mut get(ENDPOINT, None));
+        let Body::Bytes(json) = response.body else { panic!("expected a body") };
+        assert!(String::from_utf8(json).unwrap().contains(&format!(r#""trace_id":"{}""#, context.trace_id)));
+    }
+}
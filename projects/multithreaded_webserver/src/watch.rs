@@ -0,0 +1,291 @@
+// Watching Files for Changes
+
+// To find out that a file changed, a program can either ask the operating system to tell it (inotify on Linux, FSEvents on macOS,
+// ReadDirectoryChangesW on Windows), or look at the file every now and then and compare with what it saw last time.
+// The first is faster and cheaper, but it's a different API on every platform. The second works everywhere with just std,
+// and that's what PollWatcher does:
+    // 1. A Snapshot records the modification time and size of every file and directory below a watched path.
+    // 2. Every interval a background thread takes a new snapshot and compares it with the previous one:
+    //    a path only in the new one was Created, one only in the old one was Removed, one in both with another mtime or size was Modified.
+    // 3. The differences are sent as Events over a channel, so whoever is interested just loops over the receiving end.
+// The Watcher trait is what a notify-style backend would implement as well, the code that receives the events wouldn't change.
+
+// Polling has its limits:
+    // 1. Changes are seen up to one interval late, and several changes within one interval come out as one event.
+    //    That's often a feature: an editor that writes a temporary file and renames it over the original looks like a single Modified.
+    // 2. A change that keeps both the mtime and the size is missed. Most filesystems keep mtimes in nanoseconds,
+    //    but some (FAT, older ext3, some network filesystems) only in seconds, and there two quick writes of the same size look like one.
+    // 3. Every poll walks the whole tree, so it's meant for a few hundred files like a site's templates, not for a whole disk.
+// The walk is common's (projects/common/src/walk.rs) with the ignore files off: a change is a change, even in an ignored file.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use common::walk::Walk;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            EventKind::Created => "created",
+            EventKind::Modified => "modified",
+            EventKind::Removed => "removed",
+        };
+        write!(f, "{kind} {}", self.path.display())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecursiveMode {
+    // Everything below the path, at any depth
+    Recursive,
+    // The path, and what's directly inside it when it's a directory
+    NonRecursive,
+}
+
+pub trait Watcher {
+    // The path has to exist when it's added, after that it may come and go
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> io::Result<()>;
+    fn unwatch(&mut self, path: &Path) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileState {
+    modified: Option<SystemTime>,
+    len: u64,
+    is_dir: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, FileState>,
+}
+
+impl Snapshot {
+    // What can't be read is left out, a file that disappears halfway through the walk simply isn't in the snapshot.
+    // A path that doesn't exist gives an empty snapshot, so it comes back as Created once it does.
+    pub fn take(path: &Path, mode: RecursiveMode) -> Snapshot {
+        let walk = match mode {
+            RecursiveMode::Recursive => Walk::new(path),
+            RecursiveMode::NonRecursive => Walk::new(path).max_depth(1),
+        };
+        let files = walk
+            .ignore_files(false)
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let metadata = entry.metadata();
+                let state = FileState { modified: metadata.modified().ok(), len: metadata.len(), is_dir: metadata.is_dir() };
+                (entry.into_path(), state)
+            })
+            .collect();
+        Snapshot { files }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    // What happened between this snapshot and a newer one, sorted by path.
+    // A directory's size is up to the filesystem, so only its mtime counts, which changes when an entry is added or removed.
+    pub fn diff(&self, newer: &Snapshot) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (path, old) in &self.files {
+            match newer.files.get(path) {
+                None => events.push(Event { kind: EventKind::Removed, path: path.clone() }),
+                Some(new) if new.is_dir != old.is_dir || new.modified != old.modified || (!new.is_dir && new.len != old.len) => {
+                    events.push(Event { kind: EventKind::Modified, path: path.clone() })
+                }
+                Some(_) => {}
+            }
+        }
+        for path in newer.files.keys().filter(|path| !self.files.contains_key(*path)) {
+            events.push(Event { kind: EventKind::Created, path: path.clone() });
+        }
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+        events
+    }
+}
+
+type Roots = Arc<Mutex<BTreeMap<PathBuf, (RecursiveMode, Snapshot)>>>;
+
+pub struct PollWatcher {
+    roots: Roots,
+    // Dropping the sender wakes the thread up and tells it to stop
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PollWatcher {
+    // The thread also stops by itself once the receiving end of events is dropped
+    pub fn new(events: Sender<Event>, interval: Duration) -> PollWatcher {
+        let roots: Roots = Arc::new(Mutex::new(BTreeMap::new()));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = {
+            let roots = Arc::clone(&roots);
+            thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    for event in poll(&roots) {
+                        if events.send(event).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+        };
+        PollWatcher { roots, stop: Some(stop), thread: Some(thread) }
+    }
+
+    // Checks right away instead of waiting for the next interval, and returns the events instead of sending them
+    pub fn poll_now(&self) -> Vec<Event> {
+        poll(&self.roots)
+    }
+}
+
+fn poll(roots: &Roots) -> Vec<Event> {
+    let mut roots = roots.lock().unwrap();
+    let mut events = Vec::new();
+    for (path, (mode, snapshot)) in roots.iter_mut() {
+        let newer = Snapshot::take(path, *mode);
+        events.extend(snapshot.diff(&newer));
+        *snapshot = newer;
+    }
+    events
+}
+
+impl Watcher for PollWatcher {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> io::Result<()> {
+        // For the NotFound error, Snapshot::take would quietly give an empty snapshot
+        path.symlink_metadata()?;
+        let snapshot = Snapshot::take(path, mode);
+        self.roots.lock().unwrap().insert(path.to_path_buf(), (mode, snapshot));
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> io::Result<()> {
+        match self.roots.lock().unwrap().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't being watched", path.display()))),
+        }
+    }
+}
+
+impl Drop for PollWatcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mws-watch-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn kinds(events: &[Event], root: &Path) -> Vec<(EventKind, String)> {
+        events.iter().map(|e| (e.kind, e.path.strip_prefix(root).unwrap().to_string_lossy().into_owned())).collect()
+    }
+
+    #[test]
+    fn diffs_snapshots() {
+        let dir = temp_dir("diff");
+        fs::write(dir.join("keep.txt"), "same").unwrap();
+        fs::write(dir.join("grow.txt"), "a").unwrap();
+        fs::write(dir.join("gone.txt"), "bye").unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        let before = Snapshot::take(&dir, RecursiveMode::Recursive);
+        assert_eq!(5, before.len());
+
+        fs::write(dir.join("grow.txt"), "abc").unwrap();
+        fs::remove_file(dir.join("gone.txt")).unwrap();
+        fs::write(dir.join("sub/new.txt"), "hi").unwrap();
+        let after = Snapshot::take(&dir, RecursiveMode::Recursive);
+        let mut events = before.diff(&after);
+        // The root and sub are usually modified too, an entry was added to or removed from each. Usually, because the kernel
+        // updates mtimes from a clock that only ticks every few milliseconds, so all of this can happen within one tick.
+        events.retain(|event| !(event.kind == EventKind::Modified && event.path.is_dir()));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            vec![
+                (EventKind::Removed, String::from("gone.txt")),
+                (EventKind::Modified, String::from("grow.txt")),
+                (EventKind::Created, String::from("sub/new.txt")),
+            ],
+            kinds(&events, &dir)
+        );
+        assert!(after.diff(&after).is_empty());
+        assert!(Snapshot::take(&dir, RecursiveMode::Recursive).is_empty());
+    }
+
+    #[test]
+    fn non_recursive_stops_at_the_first_level() {
+        let dir = temp_dir("shallow");
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::write(dir.join("a/b/deep.txt"), "").unwrap();
+        let snapshot = Snapshot::take(&dir, RecursiveMode::NonRecursive);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(snapshot.contains(&dir.join("a")) && !snapshot.contains(&dir.join("a/b")));
+    }
+
+    #[test]
+    fn sends_events_over_the_channel() {
+        let dir = temp_dir("channel");
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = PollWatcher::new(tx, Duration::from_millis(10));
+        watcher.watch(&dir, RecursiveMode::Recursive).unwrap();
+        assert!(watcher.watch(&dir.join("missing"), RecursiveMode::Recursive).is_err());
+
+        fs::write(dir.join("page.html"), "<h1>hi</h1>").unwrap();
+        let event = rx.iter().find(|event| event.path.ends_with("page.html")).unwrap();
+        assert_eq!(EventKind::Created, event.kind);
+
+        watcher.unwatch(&dir).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, watcher.unwatch(&dir).unwrap_err().kind());
+        fs::remove_dir_all(&dir).unwrap();
+        // Nothing is watched anymore, so there's nothing left to find
+        assert!(watcher.poll_now().is_empty());
+
+        // Dropping the watcher stops its thread, which drops the sender and ends the receiver's iterator
+        drop(watcher);
+        while rx.recv().is_ok() {}
+    }
+}
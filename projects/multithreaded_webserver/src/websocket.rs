@@ -0,0 +1,584 @@
+// WebSockets
+
+// HTTP is request/response: the server can only ever answer the client. A WebSocket (RFC 6455) turns an HTTP connection into a two way channel,
+// so the server can push messages to the browser whenever it wants, for example to stream live updates.
+
+// A WebSocket starts life as a normal HTTP request:
+    // 1. The client sends a GET with the headers "Upgrade: websocket", "Connection: Upgrade" and a random "Sec-WebSocket-Key".
+    // 2. The server answers "101 Switching Protocols" with a "Sec-WebSocket-Accept" header, which is base64(sha1(key + a fixed GUID)).
+    //    This proves the server really speaks WebSocket and isn't some HTTP server that happened to echo the headers back.
+    // 3. From then on both sides send frames over the same connection instead of HTTP messages.
+
+// Each frame has a small header followed by the payload:
+    // byte 0: FIN bit (last frame of a message), 3 reserved bits, 4 bit opcode (text, binary, close, ping, pong or continuation)
+    // byte 1: MASK bit, then a 7 bit length. 126 means the real length follows as a u16, 127 means it follows as a u64.
+    // then a 4 byte masking key if MASK is set, and the payload, XORed with the key.
+// Clients must mask every frame they send and servers must never mask theirs. Masking stops badly written proxies from
+// mistaking the payload for an HTTP request and caching it. The server reads with Frame::read_from_client, which turns away
+// an unmasked frame before reading its payload. recv() answers that, and every other protocol error, with a Close of
+// status 1002 (protocol error), and the connection is finished.
+
+use std::{
+    fmt,
+    io::{self, BufReader, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use crate::{encoding::Base64, net::Stream, sha1::sha1};
+
+pub const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// A limit on the size of a single frame and of a reassembled message, so a client can't make us allocate gigabytes by sending a large length
+pub const MAX_MESSAGE_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum WsError {
+    Io(io::Error),
+    NotUpgrade,
+    Protocol(&'static str),
+    InvalidUtf8,
+    TooLarge(u64),
+    Closed,
+}
+
+impl fmt::Display for WsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WsError::Io(e) => write!(f, "i/o error: {e}"),
+            WsError::NotUpgrade => write!(f, "request is not a WebSocket upgrade"),
+            WsError::Protocol(msg) => write!(f, "protocol error: {msg}"),
+            WsError::InvalidUtf8 => write!(f, "text message is not valid UTF-8"),
+            WsError::TooLarge(len) => write!(f, "message of {len} bytes is too large"),
+            WsError::Closed => write!(f, "connection is closed"),
+        }
+    }
+}
+
+impl std::error::Error for WsError {}
+
+impl From<io::Error> for WsError {
+    fn from(e: io::Error) -> WsError {
+        WsError::Io(e)
+    }
+}
+
+// The Handshake
+
+pub fn accept_key(client_key: &str) -> String {
+    Base64::Standard.encode(&sha1(format!("{client_key}{GUID}").as_bytes()))
+}
+
+// Header names are case insensitive, and Connection can hold a list like "keep-alive, Upgrade"
+fn header<'a>(request_lines: &'a [String], name: &str) -> Option<&'a str> {
+    request_lines.iter().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+// Takes the request line and headers, the same Vec<String> that handle_connection reads with buf_reader.lines(),
+// and returns the client's key if this is a valid upgrade request.
+pub fn upgrade_key(request_lines: &[String]) -> Option<&str> {
+    let request_line = request_lines.first()?;
+    if !request_line.starts_with("GET ") {
+        return None;
+    }
+    let upgrade = header(request_lines, "Upgrade")?;
+    let connection = header(request_lines, "Connection")?;
+    if !upgrade.eq_ignore_ascii_case("websocket")
+        || !connection.split(',').any(|v| v.trim().eq_ignore_ascii_case("upgrade"))
+        || header(request_lines, "Sec-WebSocket-Version") != Some("13")
+    {
+        return None;
+    }
+    header(request_lines, "Sec-WebSocket-Key")
+}
+
+pub fn handshake_response(client_key: &str) -> String {
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(client_key)
+    )
+}
+
+// Frames
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Opcode> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    // Control frames can appear in the middle of a fragmented message, but can't be fragmented themselves
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub mask: Option<[u8; 4]>,
+    // Always stored unmasked, the mask is applied when encoding and removed when decoding
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Frame {
+        Frame { fin: true, opcode, mask: None, payload }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.payload.len() + 14);
+        out.push(if self.fin { 0x80 } else { 0 } | self.opcode.as_u8());
+
+        let mask_bit = if self.mask.is_some() { 0x80 } else { 0 };
+        let len = self.payload.len();
+        if len < 126 {
+            out.push(mask_bit | len as u8);
+        } else if len <= u16::MAX as usize {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        match self.mask {
+            Some(key) => {
+                out.extend_from_slice(&key);
+                out.extend(self.payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+            }
+            None => out.extend_from_slice(&self.payload),
+        }
+        out
+    }
+
+    // A frame from either side, masked or not
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Frame, WsError> {
+        Frame::read(reader, false)
+    }
+
+    // A frame a client sent, which must be masked
+    pub fn read_from_client<R: Read>(reader: &mut R) -> Result<Frame, WsError> {
+        Frame::read(reader, true)
+    }
+
+    fn read<R: Read>(reader: &mut R, must_be_masked: bool) -> Result<Frame, WsError> {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head)?;
+
+        if head[0] & 0x70 != 0 {
+            return Err(WsError::Protocol("reserved bits are set"));
+        }
+        if must_be_masked && head[1] & 0x80 == 0 {
+            return Err(WsError::Protocol("client frames must be masked"));
+        }
+        let fin = head[0] & 0x80 != 0;
+        let opcode = Opcode::from_u8(head[0] & 0x0F).ok_or(WsError::Protocol("unknown opcode"))?;
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut buf = [0; 2];
+                reader.read_exact(&mut buf)?;
+                u16::from_be_bytes(buf) as u64
+            }
+            127 => {
+                let mut buf = [0; 8];
+                reader.read_exact(&mut buf)?;
+                u64::from_be_bytes(buf)
+            }
+            n => n as u64,
+        };
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(WsError::Protocol("control frames must be short and unfragmented"));
+        }
+        if len > MAX_MESSAGE_SIZE {
+            return Err(WsError::TooLarge(len));
+        }
+
+        let mask = if head[1] & 0x80 != 0 {
+            let mut key = [0; 4];
+            reader.read_exact(&mut key)?;
+            Some(key)
+        } else {
+            None
+        };
+
+        let mut payload = vec![0; len as usize];
+        reader.read_exact(&mut payload)?;
+        if let Some(key) = mask {
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= key[i % 4];
+            }
+        }
+
+        Ok(Frame { fin, opcode, mask, payload })
+    }
+}
+
+// Messages
+
+// What handlers actually deal with: a whole message, reassembled from its frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<(u16, String)>),
+}
+
+impl Message {
+    fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => Frame::new(Opcode::Binary, data),
+            Message::Ping(data) => Frame::new(Opcode::Ping, data),
+            Message::Pong(data) => Frame::new(Opcode::Pong, data),
+            Message::Close(None) => Frame::new(Opcode::Close, Vec::new()),
+            Message::Close(Some((code, reason))) => {
+                let mut payload = code.to_be_bytes().to_vec();
+                payload.extend_from_slice(reason.as_bytes());
+                Frame::new(Opcode::Close, payload)
+            }
+        }
+    }
+}
+
+// The Connection
+
+// The writing half is shared behind Arc<Mutex<..>>, so other threads (ThreadPool jobs, timers) can push messages with a WsSender
+// while the handler thread blocks in recv(). The Mutex makes sure two frames are never interleaved on the wire.
+#[derive(Clone)]
+pub struct WsSender {
+    stream: Arc<Mutex<Box<dyn Stream>>>,
+}
+
+impl WsSender {
+    pub fn send(&self, message: Message) -> Result<(), WsError> {
+        let bytes = message.into_frame().encode();
+        let mut stream = self.stream.lock().unwrap();
+        stream.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn send_text(&self, text: &str) -> Result<(), WsError> {
+        self.send(Message::Text(text.to_string()))
+    }
+}
+
+pub struct WebSocket {
+    reader: BufReader<Box<dyn Stream>>,
+    sender: WsSender,
+    // The opcode and data of a fragmented message we're in the middle of. It lives in the struct rather than in recv(),
+    // because a ping in the middle of a fragmented message makes recv() return before the message is complete.
+    fragment: Option<(Opcode, Vec<u8>)>,
+    closed: bool,
+}
+
+impl WebSocket {
+    // Completes the handshake for a request that has already been read from the stream.
+    // Clients wait for the 101 response before sending any frames, so nothing is lost in the BufReader that read the request.
+    pub fn accept(stream: impl Stream + 'static, request_lines: &[String]) -> Result<WebSocket, WsError> {
+        let key = upgrade_key(request_lines).ok_or(WsError::NotUpgrade)?;
+        let mut stream: Box<dyn Stream> = Box::new(stream);
+        stream.write_all(handshake_response(key).as_bytes())?;
+
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok(WebSocket {
+            reader,
+            sender: WsSender { stream: Arc::new(Mutex::new(stream)) },
+            fragment: None,
+            closed: false,
+        })
+    }
+
+    pub fn sender(&self) -> WsSender {
+        self.sender.clone()
+    }
+
+    pub fn send(&self, message: Message) -> Result<(), WsError> {
+        if self.closed {
+            return Err(WsError::Closed);
+        }
+        self.sender.send(message)
+    }
+
+    // Blocks until the next message arrives. Pings are answered automatically (and still returned, in case the handler cares),
+    // and a Close from the client is answered with a Close before it is returned, after which the connection is finished.
+    // A client that breaks the protocol gets a Close with 1002 before the error is returned, and the connection is finished too.
+    pub fn recv(&mut self) -> Result<Message, WsError> {
+        if self.closed {
+            return Err(WsError::Closed);
+        }
+        let result = self.next_message();
+        if let Err(WsError::Protocol(reason)) = &result {
+            // The error is what the caller needs to hear about, a client that went away before the Close doesn't change it
+            let _ = self.sender.send(Message::Close(Some((1002, reason.to_string()))));
+            self.closed = true;
+        }
+        result
+    }
+
+    fn next_message(&mut self) -> Result<Message, WsError> {
+        loop {
+            let frame = Frame::read_from_client(&mut self.reader)?;
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    self.sender.send(Message::Pong(frame.payload.clone()))?;
+                    return Ok(Message::Ping(frame.payload));
+                }
+                Opcode::Pong => return Ok(Message::Pong(frame.payload)),
+                Opcode::Close => {
+                    let close = match frame.payload.len() {
+                        0 => None,
+                        1 => return Err(WsError::Protocol("close payload is too short")),
+                        _ => {
+                            let code = u16::from_be_bytes([frame.payload[0], frame.payload[1]]);
+                            let reason = String::from_utf8(frame.payload[2..].to_vec())
+                                .map_err(|_| WsError::InvalidUtf8)?;
+                            Some((code, reason))
+                        }
+                    };
+                    // Echo the status code back, which is how the closing handshake is completed
+                    self.sender.send(Message::Close(close.as_ref().map(|(code, _)| (*code, String::new()))))?;
+                    self.closed = true;
+                    return Ok(Message::Close(close));
+                }
+                Opcode::Text | Opcode::Binary => {
+                    if self.fragment.is_some() {
+                        return Err(WsError::Protocol("new message started before the previous one finished"));
+                    }
+                    self.fragment = Some((frame.opcode, frame.payload));
+                }
+                Opcode::Continuation => match self.fragment.as_mut() {
+                    Some((_, data)) => {
+                        data.extend_from_slice(&frame.payload);
+                        if data.len() as u64 > MAX_MESSAGE_SIZE {
+                            return Err(WsError::TooLarge(data.len() as u64));
+                        }
+                    }
+                    None => return Err(WsError::Protocol("continuation frame without a message")),
+                },
+            }
+
+            if frame.fin {
+                if let Some((opcode, data)) = self.fragment.take() {
+                    return match opcode {
+                        Opcode::Text => String::from_utf8(data)
+                            .map(Message::Text)
+                            .map_err(|_| WsError::InvalidUtf8),
+                        _ => Ok(Message::Binary(data)),
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WsError> {
+        self.send(Message::Close(Some((code, reason.to_string()))))?;
+        self.closed = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    #[test]
+    fn accept_key_matches_the_rfc_example() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn detects_upgrade_requests() {
+        let lines = |extra: &[&str]| -> Vec<String> {
+            let mut lines = vec![String::from("GET /ws HTTP/1.1"), String::from("Host: localhost")];
+            lines.extend(extra.iter().map(|s| s.to_string()));
+            lines
+        };
+
+        let request = lines(&[
+            "upgrade: WebSocket",
+            "Connection: keep-alive, Upgrade",
+            "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==",
+            "Sec-WebSocket-Version: 13",
+        ]);
+        assert_eq!(Some("dGhlIHNhbXBsZSBub25jZQ=="), upgrade_key(&request));
+
+        let missing_key = lines(&["Upgrade: websocket", "Connection: Upgrade", "Sec-WebSocket-Version: 13"]);
+        assert_eq!(None, upgrade_key(&missing_key));
+        assert_eq!(None, upgrade_key(&lines(&[])));
+    }
+
+    #[test]
+    fn frames_match_the_rfc_examples() {
+        let unmasked = Frame::new(Opcode::Text, b"Hello".to_vec());
+        assert_eq!(vec![0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f], unmasked.encode());
+
+        let masked = Frame { mask: Some([0x37, 0xfa, 0x21, 0x3d]), ..unmasked };
+        let bytes = masked.encode();
+        assert_eq!(vec![0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58], bytes);
+        assert_eq!(masked, Frame::read_from(&mut &bytes[..]).unwrap());
+    }
+
+    #[test]
+    fn frames_round_trip_with_extended_lengths() {
+        for len in [0, 125, 126, 65535, 65536] {
+            let frame = Frame { mask: Some([1, 2, 3, 4]), ..Frame::new(Opcode::Binary, vec![7; len]) };
+            let bytes = frame.encode();
+            assert_eq!(frame, Frame::read_from(&mut &bytes[..]).unwrap());
+        }
+
+        // A 2^63 byte length is rejected before anything is allocated
+        let huge = [0x82, 0x7F, 0x80, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(Frame::read_from(&mut &huge[..]), Err(WsError::TooLarge(_))));
+    }
+
+    #[test]
+    fn frames_read_a_byte_at_a_time() {
+        use test_support::FlakyReader;
+
+        let frame = Frame { mask: Some([9, 8, 7, 6]), ..Frame::new(Opcode::Binary, vec![5; 300]) };
+        let bytes = frame.encode();
+        let mut flaky = FlakyReader::new(&bytes[..]).max_chunk(1).interrupt_every(2);
+        assert_eq!(frame, Frame::read_from(&mut flaky).unwrap());
+        assert_eq!(bytes.len(), flaky.transferred());
+
+        // Cut off inside the extended length
+        let mut cut = FlakyReader::new(&bytes[..]).fail_after(3, io::ErrorKind::ConnectionAborted);
+        assert!(matches!(Frame::read_from(&mut cut), Err(WsError::Io(e)) if e.kind() == io::ErrorKind::ConnectionAborted));
+    }
+
+    // A minimal client, just enough to talk to WebSocket::accept over a real socket
+    fn client_send(stream: &mut TcpStream, fin: bool, opcode: Opcode, payload: &[u8]) {
+        let frame = Frame { fin, opcode, mask: Some([9, 8, 7, 6]), payload: payload.to_vec() };
+        stream.write_all(&frame.encode()).unwrap();
+    }
+
+    #[test]
+    fn server_reassembles_messages_and_pushes_from_other_threads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+                .unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut response = String::new();
+            while !response.ends_with("\r\n\r\n") {
+                let mut byte = [0];
+                reader.read_exact(&mut byte).unwrap();
+                response.push(byte[0] as char);
+            }
+            assert!(response.starts_with("HTTP/1.1 101"));
+            assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+            // "Hello, world" split over three frames, with a ping in the middle
+            client_send(&mut stream, false, Opcode::Text, b"Hello");
+            client_send(&mut stream, false, Opcode::Continuation, b", ");
+            client_send(&mut stream, true, Opcode::Ping, b"?");
+            client_send(&mut stream, true, Opcode::Continuation, b"world");
+
+            let pong = Frame::read_from(&mut reader).unwrap();
+            assert_eq!((Opcode::Pong, b"?".to_vec(), None), (pong.opcode, pong.payload, pong.mask));
+            let pushed = Frame::read_from(&mut reader).unwrap();
+            assert_eq!(b"tick".to_vec(), pushed.payload);
+
+            client_send(&mut stream, true, Opcode::Close, &1000u16.to_be_bytes());
+            let close = Frame::read_from(&mut reader).unwrap();
+            assert_eq!((Opcode::Close, 1000u16.to_be_bytes().to_vec()), (close.opcode, close.payload));
+        });
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut reader = BufReader::new(&mut stream);
+        loop {
+            let mut line = String::new();
+            io::BufRead::read_line(&mut reader, &mut line).unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            request.push(line.trim_end().to_string());
+        }
+
+        let mut ws = WebSocket::accept(stream, &request).unwrap();
+        assert_eq!(Message::Ping(b"?".to_vec()), ws.recv().unwrap());
+        assert_eq!(Message::Text(String::from("Hello, world")), ws.recv().unwrap());
+
+        let sender = ws.sender();
+        thread::spawn(move || sender.send_text("tick").unwrap()).join().unwrap();
+
+        assert_eq!(Message::Close(Some((1000, String::new()))), ws.recv().unwrap());
+        assert!(matches!(ws.recv(), Err(WsError::Closed)));
+        client.join().unwrap();
+    }
+
+    // The request lines of a handshake, for the tests that don't need a real request
+    fn upgrade_request() -> Vec<String> {
+        let lines = ["GET /ws HTTP/1.1", "Upgrade: websocket", "Connection: Upgrade", "Sec-WebSocket-Version: 13"];
+        lines.into_iter().chain(["Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ=="]).map(String::from).collect()
+    }
+
+    #[test]
+    fn unmasked_client_frames_are_closed_with_1002() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut ws = WebSocket::accept(stream, &upgrade_request()).unwrap();
+
+        client.write_all(&Frame::new(Opcode::Text, b"no mask".to_vec()).encode()).unwrap();
+        assert!(matches!(ws.recv(), Err(WsError::Protocol("client frames must be masked"))));
+        assert!(matches!(ws.recv(), Err(WsError::Closed)));
+
+        let mut reader = BufReader::new(client);
+        let mut response = String::new();
+        while !response.ends_with("\r\n\r\n") {
+            let mut byte = [0];
+            reader.read_exact(&mut byte).unwrap();
+            response.push(byte[0] as char);
+        }
+        let close = Frame::read_from(&mut reader).unwrap();
+        assert_eq!((Opcode::Close, &1002u16.to_be_bytes()[..]), (close.opcode, &close.payload[..2]));
+        assert_eq!(b"client frames must be masked", &close.payload[2..]);
+    }
+}
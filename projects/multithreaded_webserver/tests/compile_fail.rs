@@ -0,0 +1,9 @@
+// The type-state builder in src/server.rs is only useful if the wrong orders really don't compile.
+// trybuild compiles every file in tests/ui on its own and compares the compiler's errors with the .stderr file next to it.
+// After changing an error on purpose, regenerate the .stderr files with: TRYBUILD=overwrite cargo test --test compile_fail
+
+#[test]
+fn invalid_builder_orders_dont_compile() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,6 @@
+// A server without an address has nowhere to listen
+use multithreaded_webserver::{http::Response, middleware::Chain, server::ServerBuilder};
+
+fn main() {
+    let _ = ServerBuilder::new().handler(Chain::new(|_| Response::text(200, "hi"))).build();
+}
@@ -0,0 +1,6 @@
+// A server without a handler would have nothing to answer with
+use multithreaded_webserver::server::ServerBuilder;
+
+fn main() {
+    let _ = ServerBuilder::new().bind("127.0.0.1:7878").build();
+}
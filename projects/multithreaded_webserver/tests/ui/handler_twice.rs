@@ -0,0 +1,10 @@
+// Setting the handler a second time would silently throw the first one away
+use multithreaded_webserver::{http::Response, middleware::Chain, server::ServerBuilder};
+
+fn main() {
+    let _ = ServerBuilder::new()
+        .handler(Chain::new(|_| Response::text(200, "first")))
+        .handler(Chain::new(|_| Response::text(200, "second")))
+        .bind("127.0.0.1:7878")
+        .build();
+}
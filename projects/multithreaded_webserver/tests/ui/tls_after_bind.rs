@@ -0,0 +1,10 @@
+// TLS has to be configured before the address is bound
+use multithreaded_webserver::{http::Response, middleware::Chain, server::ServerBuilder};
+
+fn main() {
+    let _ = ServerBuilder::new()
+        .bind("127.0.0.1:7878")
+        .tls("cert.pem", "key.pem")
+        .handler(Chain::new(|_| Response::text(200, "hi")))
+        .build();
+}
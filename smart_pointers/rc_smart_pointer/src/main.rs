@@ -19,8 +19,9 @@ use crate::BoxList::{BCons, BNil};
 
 use std::rc::Rc;
 
-enum RcList {
-    RCons(i32, Rc<RcList>),
+// The element is generic only so the tests can put a Tracked value in it, everything below uses the default i32
+enum RcList<T = i32> {
+    RCons(T, Rc<RcList<T>>),
     RNil,
 }
 
@@ -73,3 +74,26 @@ fn main() {
 
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::leakcheck::{assert_no_leaks, live, Tracked};
+
+    #[test]
+    fn shared_tails_are_freed_with_their_last_owner() {
+        assert_no_leaks(|| {
+            let a = Rc::new(RCons(Tracked::new(5), Rc::new(RCons(Tracked::new(10), Rc::new(RNil)))));
+            let b = RCons(Tracked::new(3), Rc::clone(&a));
+            let c = RCons(Tracked::new(4), Rc::clone(&a));
+            assert_eq!((4, 3), (live(), Rc::strong_count(&a)));
+
+            // a is still owned by c, only b's own element goes
+            drop(a);
+            drop(b);
+            assert_eq!(3, live());
+            let RCons(value, tail) = &c else { panic!("expected RCons") };
+            assert_eq!((4, 1), (**value, Rc::strong_count(tail)));
+        });
+    }
+}
@@ -0,0 +1,267 @@
+// Event Emitters with Weak Listeners
+
+// An event emitter keeps a list of listeners and calls each of them when an event happens (the observer pattern).
+// If the emitter held its listeners with Rc, a listener could never be freed while the emitter lives, and a listener that
+// holds the emitter would even form a reference cycle, the leak described in main.rs.
+
+// So the emitter only keeps Weak references, just like the parent pointer of the tree in main.rs:
+    // 1. Whoever subscribes owns the Rc. Dropping it is all it takes to unsubscribe, there is no unsubscribe() to forget.
+    // 2. emit() upgrades each Weak. The ones that fail belong to listeners that are gone, and are removed from the list on the way.
+    // 3. The event type is generic, so an EventEmitter<TemperatureChanged> only accepts listeners for that event.
+
+// There are two flavors, the same split as Rc<RefCell<T>> vs Arc<Mutex<T>>:
+// EventEmitter for one thread (Rc, Weak and RefCell), and SyncEventEmitter whose listeners may live on, and be called from, any thread.
+
+use std::{
+    cell::RefCell,
+    rc::{self, Rc},
+    sync::{self, Arc, Mutex},
+};
+
+pub trait Listener<E> {
+    fn notify(&self, event: &E);
+}
+
+// Any closure taking the event works as a listener
+impl<E, F: Fn(&E)> Listener<E> for F {
+    fn notify(&self, event: &E) {
+        self(event)
+    }
+}
+
+// Single threaded
+
+pub struct EventEmitter<E> {
+    listeners: RefCell<Vec<rc::Weak<dyn Listener<E>>>>,
+}
+
+impl<E> EventEmitter<E> {
+    pub fn new() -> EventEmitter<E> {
+        EventEmitter { listeners: RefCell::new(Vec::new()) }
+    }
+
+    // For a listener owned elsewhere, like a struct that implements Listener. It stays subscribed as long as that Rc lives.
+    pub fn subscribe<L: Listener<E> + 'static>(&self, listener: &Rc<L>) {
+        // Rc<L> to Weak<L> to Weak<dyn Listener<E>>, the last step is an unsizing coercion
+        let weak: rc::Weak<dyn Listener<E>> = Rc::downgrade(listener) as rc::Weak<L>;
+        self.listeners.borrow_mut().push(weak);
+    }
+
+    // For a closure. The returned Subscription owns it, keep it for as long as the closure should be called.
+    #[must_use = "the listener is unsubscribed as soon as the Subscription is dropped"]
+    pub fn on(&self, callback: impl Fn(&E) + 'static) -> Subscription<E> {
+        let listener: Rc<dyn Listener<E>> = Rc::new(callback);
+        self.listeners.borrow_mut().push(Rc::downgrade(&listener));
+        Subscription { _listener: listener }
+    }
+
+    // Calls every live listener in the order they subscribed and returns how many there were
+    pub fn emit(&self, event: &E) -> usize {
+        // Upgrade first and release the borrow before calling anyone: a listener may subscribe (or emit) while being notified,
+        // and that needs borrow_mut, which would panic if we were still iterating over the RefCell
+        let live: Vec<Rc<dyn Listener<E>>> = {
+            let mut listeners = self.listeners.borrow_mut();
+            listeners.retain(|weak| weak.strong_count() > 0);
+            listeners.iter().filter_map(rc::Weak::upgrade).collect()
+        };
+
+        for listener in &live {
+            listener.notify(event);
+        }
+        live.len()
+    }
+
+    // Listeners whose owner is still alive, dead ones are only removed by emit()
+    pub fn listener_count(&self) -> usize {
+        self.listeners.borrow().iter().filter(|weak| weak.strong_count() > 0).count()
+    }
+}
+
+impl<E> Default for EventEmitter<E> {
+    fn default() -> Self {
+        EventEmitter::new()
+    }
+}
+
+pub struct Subscription<E> {
+    _listener: Rc<dyn Listener<E>>,
+}
+
+// Multi threaded
+
+// Listeners must be Send + Sync, because emit() may run on any thread and call them from there.
+// The Mutex is only held while the list is cleaned up and copied, never while listeners run, so a slow listener
+// doesn't block other threads from subscribing, and a listener that emits again doesn't deadlock.
+
+type SyncListener<E> = dyn Listener<E> + Send + Sync;
+
+pub struct SyncEventEmitter<E> {
+    listeners: Mutex<Vec<sync::Weak<SyncListener<E>>>>,
+}
+
+impl<E> SyncEventEmitter<E> {
+    pub fn new() -> SyncEventEmitter<E> {
+        SyncEventEmitter { listeners: Mutex::new(Vec::new()) }
+    }
+
+    pub fn subscribe<L: Listener<E> + Send + Sync + 'static>(&self, listener: &Arc<L>) {
+        let weak: sync::Weak<SyncListener<E>> = Arc::downgrade(listener) as sync::Weak<L>;
+        self.listeners.lock().unwrap().push(weak);
+    }
+
+    #[must_use = "the listener is unsubscribed as soon as the SyncSubscription is dropped"]
+    pub fn on(&self, callback: impl Fn(&E) + Send + Sync + 'static) -> SyncSubscription<E> {
+        let listener: Arc<SyncListener<E>> = Arc::new(callback);
+        self.listeners.lock().unwrap().push(Arc::downgrade(&listener));
+        SyncSubscription { _listener: listener }
+    }
+
+    pub fn emit(&self, event: &E) -> usize {
+        let live: Vec<Arc<SyncListener<E>>> = {
+            let mut listeners = self.listeners.lock().unwrap();
+            listeners.retain(|weak| weak.strong_count() > 0);
+            listeners.iter().filter_map(sync::Weak::upgrade).collect()
+        };
+
+        for listener in &live {
+            listener.notify(event);
+        }
+        live.len()
+    }
+
+    pub fn listener_count(&self) -> usize {
+        self.listeners.lock().unwrap().iter().filter(|weak| weak.strong_count() > 0).count()
+    }
+}
+
+impl<E> Default for SyncEventEmitter<E> {
+    fn default() -> Self {
+        SyncEventEmitter::new()
+    }
+}
+
+pub struct SyncSubscription<E> {
+    _listener: Arc<SyncListener<E>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::Cell,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+    use test_support::leakcheck::{assert_no_leaks, live, Tracked};
+
+    struct Counter {
+        seen: Cell<i32>,
+    }
+
+    impl Listener<i32> for Counter {
+        fn notify(&self, event: &i32) {
+            self.seen.set(self.seen.get() + event);
+        }
+    }
+
+    #[test]
+    fn dropping_a_listener_unsubscribes_it() {
+        let emitter = EventEmitter::new();
+        let counter = Rc::new(Counter { seen: Cell::new(0) });
+        emitter.subscribe(&counter);
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let subscription = {
+            let log = Rc::clone(&log);
+            emitter.on(move |event: &i32| log.borrow_mut().push(*event))
+        };
+
+        assert_eq!(2, emitter.emit(&5));
+        drop(subscription);
+        assert_eq!(1, emitter.listener_count());
+        assert_eq!(1, emitter.emit(&7));
+
+        assert_eq!(12, counter.seen.get());
+        assert_eq!(vec![5], *log.borrow());
+
+        drop(counter);
+        assert_eq!(0, emitter.emit(&1));
+        // The dead Weaks were pruned, not just skipped
+        assert!(emitter.listeners.borrow().is_empty());
+    }
+
+    #[test]
+    fn listeners_may_subscribe_while_being_notified() {
+        let emitter = Rc::new(EventEmitter::<&str>::new());
+        let late = Rc::new(RefCell::new(None));
+
+        let _subscription = {
+            let (emitter, late) = (Rc::clone(&emitter), Rc::clone(&late));
+            emitter.clone().on(move |_| {
+                if late.borrow().is_none() {
+                    *late.borrow_mut() = Some(emitter.on(|event| assert_eq!("second", *event)));
+                }
+            })
+        };
+
+        // The new listener only hears events emitted after it subscribed
+        assert_eq!(1, emitter.emit(&"first"));
+        assert_eq!(2, emitter.emit(&"second"));
+    }
+
+    #[test]
+    fn sync_emitter_across_threads() {
+        let emitter = Arc::new(SyncEventEmitter::new());
+        let total = Arc::new(AtomicUsize::new(0));
+        let subscription = {
+            let total = Arc::clone(&total);
+            emitter.on(move |n: &usize| {
+                total.fetch_add(*n, Ordering::SeqCst);
+            })
+        };
+
+        let handles: Vec<_> = (1..=4)
+            .map(|n| {
+                let emitter = Arc::clone(&emitter);
+                thread::spawn(move || emitter.emit(&n))
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(1, handle.join().unwrap());
+        }
+        assert_eq!(10, total.load(Ordering::SeqCst));
+
+        // A listener dropped on another thread is gone for everyone
+        thread::spawn(move || drop(subscription)).join().unwrap();
+        assert_eq!(0, emitter.emit(&100));
+        assert_eq!(10, total.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn the_emitter_doesnt_keep_its_listeners_alive() {
+        assert_no_leaks(|| {
+            let emitter = EventEmitter::new();
+            let counter = Rc::new(Tracked::new(Counter { seen: Cell::new(0) }));
+            let subscription = {
+                let counter = Rc::clone(&counter);
+                emitter.on(move |event: &i32| counter.notify(event))
+            };
+            emitter.emit(&3);
+            assert_eq!((1, 3), (live(), counter.seen.get()));
+
+            // Neither the emitter nor the closure it held on to keep the counter
+            drop(subscription);
+            assert_eq!(1, Rc::strong_count(&counter));
+            drop(counter);
+            assert_eq!(0, live());
+            // The same with listeners on other threads
+            let sync_emitter = SyncEventEmitter::new();
+            let calls = Tracked::new(AtomicUsize::new(0));
+            let subscription = sync_emitter.on(move |_: &i32| {
+                calls.fetch_add(1, Ordering::SeqCst);
+            });
+            thread::spawn(move || drop(subscription)).join().unwrap();
+            assert_eq!((0, 0), (live(), sync_emitter.emit(&1)));
+        });
+    }
+}
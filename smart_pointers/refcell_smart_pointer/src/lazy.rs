@@ -0,0 +1,334 @@
+// Set-Once Cells: LazyConfig and SyncLazyConfig
+
+// RefCell lets us change a value through a shared reference, but every access returns a Ref or RefMut guard and is checked at runtime.
+// Configuration is a different shape of problem: it's written exactly once (usually at startup, or on first use) and only read after that.
+// A set-once cell fits that shape better, and because the value never changes after it's set, get() can hand out a plain &T with no guard at all.
+
+// This is what std::cell::OnceCell and std::sync::OnceLock do. Writing our own shows how:
+    // 1. The value lives in an UnsafeCell<Option<T>>, which is written once while no &T exists yet, and never again.
+    // 2. A small state machine tracks the cell: empty, being initialized, initialized, or poisoned.
+    // 3. If the initializer closure panics, the cell is poisoned: half-built configuration is worse than none,
+    //    so later calls panic too, instead of quietly running the initializer again or returning garbage.
+
+// LazyConfig is for one thread (like RefCell, it is not Sync). SyncLazyConfig can be a global static shared by all threads:
+// the first caller runs the initializer while the others wait on a Condvar, and every caller sees the same value.
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Condvar, Mutex,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Empty,
+    Initializing,
+    Ready,
+    Poisoned,
+}
+
+// Sets the state to Poisoned unless disarmed, so a panic in the initializer unwinds through this and leaves the cell poisoned
+struct PoisonOnPanic<F: FnMut()> {
+    poison: F,
+    armed: bool,
+}
+
+impl<F: FnMut()> Drop for PoisonOnPanic<F> {
+    fn drop(&mut self) {
+        if self.armed {
+            (self.poison)();
+        }
+    }
+}
+
+// Single threaded
+
+pub struct LazyConfig<T> {
+    state: Cell<State>,
+    value: UnsafeCell<Option<T>>,
+}
+
+impl<T> LazyConfig<T> {
+    pub const fn new() -> LazyConfig<T> {
+        LazyConfig { state: Cell::new(State::Empty), value: UnsafeCell::new(None) }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        match self.state.get() {
+            // Ready means the value is written and will never be written again, so sharing &T is fine
+            State::Ready => unsafe { (*self.value.get()).as_ref() },
+            _ => None,
+        }
+    }
+
+    // Gives the value back if the cell was already set
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.state.get() != State::Empty {
+            return Err(value);
+        }
+        unsafe { *self.value.get() = Some(value) };
+        self.state.set(State::Ready);
+        Ok(())
+    }
+
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        match self.state.get() {
+            State::Ready => return self.get().unwrap(),
+            State::Poisoned => panic!("LazyConfig is poisoned: its initializer panicked"),
+            // The initializer asked for the value it is initializing, which can never finish
+            State::Initializing => panic!("LazyConfig initializer called get_or_init on the same cell"),
+            State::Empty => {}
+        }
+
+        self.state.set(State::Initializing);
+        let mut guard = PoisonOnPanic { poison: || self.state.set(State::Poisoned), armed: true };
+        let value = init();
+        guard.armed = false;
+        drop(guard);
+
+        // No &T exists yet: get() returns None until the state is Ready
+        unsafe { *self.value.get() = Some(value) };
+        self.state.set(State::Ready);
+        self.get().unwrap()
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.state.get() == State::Poisoned
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Default for LazyConfig<T> {
+    fn default() -> Self {
+        LazyConfig::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LazyConfig<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("LazyConfig").field(value).finish(),
+            None => write!(f, "LazyConfig({:?})", self.state.get()),
+        }
+    }
+}
+
+// Thread safe
+
+// The state is an atomic, so the common case (already initialized) is a single Acquire load without locking.
+// The Mutex and Condvar are only used while the cell is being initialized, to make the other threads wait.
+
+const EMPTY: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+const POISONED: u8 = 3;
+
+pub struct SyncLazyConfig<T> {
+    state: AtomicU8,
+    lock: Mutex<()>,
+    ready: Condvar,
+    value: UnsafeCell<Option<T>>,
+}
+
+// Like OnceLock: sharing the cell shares the &T inside (so T: Sync), and the thread that initializes it may not be the one that drops it (so T: Send)
+unsafe impl<T: Send + Sync> Sync for SyncLazyConfig<T> {}
+
+impl<T> SyncLazyConfig<T> {
+    // const, so that it can initialize a static
+    pub const fn new() -> SyncLazyConfig<T> {
+        SyncLazyConfig { state: AtomicU8::new(EMPTY), lock: Mutex::new(()), ready: Condvar::new(), value: UnsafeCell::new(None) }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        // Acquire pairs with the Release store of READY, so the value written before it is visible here
+        if self.state.load(Ordering::Acquire) == READY {
+            unsafe { (*self.value.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.initialize(|| value.take().unwrap());
+        // The closure only runs if this call was the one that initialized the cell
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    // If another thread is running its initializer, this waits for it, and init is never called.
+    // Calling get_or_init on the same cell from inside init would wait for itself forever, just like with OnceLock.
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        self.initialize(init);
+        self.get().unwrap()
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.state.load(Ordering::Acquire) == POISONED
+    }
+
+    // Returns once the cell is Ready, having run init only if this thread was the first
+    fn initialize(&self, init: impl FnOnce() -> T) {
+        let mut lock = self.lock.lock().unwrap();
+        loop {
+            match self.state.load(Ordering::Acquire) {
+                READY => return,
+                POISONED => panic!("SyncLazyConfig is poisoned: its initializer panicked"),
+                INITIALIZING => lock = self.ready.wait(lock).unwrap(),
+                _ => break,
+            }
+        }
+        self.state.store(INITIALIZING, Ordering::Relaxed);
+        // The initializer runs without the lock, get() on other threads keeps working and only initialize() callers wait
+        drop(lock);
+
+        let mut guard = PoisonOnPanic {
+            poison: || {
+                let _lock = self.lock.lock().unwrap();
+                self.state.store(POISONED, Ordering::Release);
+                self.ready.notify_all();
+            },
+            armed: true,
+        };
+        let value = init();
+        guard.armed = false;
+        drop(guard);
+
+        // Only this thread can be here, every other caller is waiting for INITIALIZING to change
+        unsafe { *self.value.get() = Some(value) };
+        let _lock = self.lock.lock().unwrap();
+        self.state.store(READY, Ordering::Release);
+        self.ready.notify_all();
+    }
+}
+
+impl<T> Default for SyncLazyConfig<T> {
+    fn default() -> Self {
+        SyncLazyConfig::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SyncLazyConfig<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("SyncLazyConfig").field(value).finish(),
+            None => write!(f, "SyncLazyConfig(<not ready>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::atomic::AtomicUsize,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn set_once_then_read() {
+        let config = LazyConfig::new();
+        assert_eq!(None, config.get());
+        assert_eq!(Ok(()), config.set(String::from("debug")));
+        assert_eq!(Err(String::from("info")), config.set(String::from("info")));
+
+        // get_or_init doesn't run the closure once the value is there
+        assert_eq!("debug", config.get_or_init(|| unreachable!()));
+        assert_eq!(Some(String::from("debug")), config.into_inner());
+    }
+
+    #[test]
+    fn initializes_on_first_use() {
+        let calls = Cell::new(0);
+        let config = LazyConfig::new();
+        for _ in 0..3 {
+            let port = config.get_or_init(|| {
+                calls.set(calls.get() + 1);
+                7878
+            });
+            assert_eq!(7878, *port);
+        }
+        assert_eq!(1, calls.get());
+    }
+
+    #[test]
+    fn a_panicking_initializer_poisons_the_cell() {
+        let config: LazyConfig<u16> = LazyConfig::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| config.get_or_init(|| panic!("no config file"))));
+        assert!(result.is_err());
+        assert!(config.is_poisoned());
+        assert_eq!(None, config.get());
+        assert_eq!(Err(1), config.set(1));
+
+        let again = panic::catch_unwind(AssertUnwindSafe(|| *config.get_or_init(|| 1)));
+        assert!(again.is_err());
+    }
+
+    #[test]
+    fn reentrant_initialization_panics() {
+        let config: LazyConfig<u16> = LazyConfig::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| *config.get_or_init(|| *config.get_or_init(|| 1))));
+        assert!(result.is_err());
+        assert!(config.is_poisoned());
+    }
+
+    #[test]
+    fn threads_share_one_initialization() {
+        static CONFIG: SyncLazyConfig<Vec<String>> = SyncLazyConfig::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let lengths: Vec<usize> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        CONFIG
+                            .get_or_init(|| {
+                                CALLS.fetch_add(1, Ordering::SeqCst);
+                                // Slow enough that the other threads arrive while this one is initializing
+                                thread::sleep(Duration::from_millis(50));
+                                vec![String::from("a"), String::from("b")]
+                            })
+                            .len()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(vec![2; 8], lengths);
+        assert_eq!(1, CALLS.load(Ordering::SeqCst));
+        assert_eq!(Err(vec![]), CONFIG.set(vec![]));
+    }
+
+    #[test]
+    fn waiting_threads_see_the_poison() {
+        let config: SyncLazyConfig<u8> = SyncLazyConfig::new();
+        thread::scope(|s| {
+            let first = s.spawn(|| {
+                config.get_or_init(|| {
+                    thread::sleep(Duration::from_millis(50));
+                    panic!("bad config")
+                });
+            });
+            thread::sleep(Duration::from_millis(10));
+            let second = s.spawn(|| *config.get_or_init(|| 1));
+
+            assert!(first.join().is_err());
+            assert!(second.join().is_err());
+        });
+        assert!(config.is_poisoned());
+    }
+}
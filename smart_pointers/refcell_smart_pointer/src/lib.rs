@@ -1,3 +1,8 @@
+// Set-once cells for configuration, interior mutability beyond RefCell, see lazy.rs
+pub mod lazy;
+// Observers held through Weak references, see events.rs
+pub mod events;
+
 pub trait Messenger {
     // Applications that use our library will be expected to provide the mechanism for sending the messages
     fn send(&self, msg: &str);
@@ -35,7 +35,7 @@ Mutating the value inside an immutable value is the INTERIOR MUTABILITY pattern.
 
 // Interior Mutability: A mutable borrow to an immutable value
 
-use std::{cell::RefCell, rc::{Rc, Weak}};
+use std::{cell::{Cell, RefCell}, rc::{Rc, Weak}};
 
 #[derive(Debug)]
 enum List {
@@ -101,6 +101,9 @@ fn main() {
 
     main2();
 
+    main3();
+
+    main4();
 
 }
 
@@ -112,16 +115,17 @@ fn main() {
 // Creating a Reference Cycle using a Linked List
 
 
+// The element is generic only so the tests can put a Tracked value in it, main2 uses the default i32
 #[derive(Debug)]
-enum List2 {
-    Cons(i32, RefCell<Rc<List2>>),
+enum List2<T = i32> {
+    Cons(T, RefCell<Rc<List2<T>>>),
     Nil,
 }
 
 use crate::List2::{Cons, Nil};
 
-impl List2 {
-    fn tail(&self) -> Option<&RefCell<Rc<List2>>> {
+impl<T> List2<T> {
+    fn tail(&self) -> Option<&RefCell<Rc<List2<T>>>> {
         match self {
             Cons(_, item) => Some(item),
             Nil => None,
@@ -185,14 +189,8 @@ fn main2() {
 
     // IMPORTANT: a parent node should own its children: if a parent node is dropped, its child nodes should be dropped as well. 
     // IMPORTANT: However, a child should not own its parent: if we drop a child node, the parent should still exist.
-    // This is where we use Weak<T> references!
-
-    #[derive(Debug)]
-    struct Node {
-        value: i32,
-        children: RefCell<Vec<Rc<Node>>>,
-        parent: RefCell<Weak<Node>>
-    }
+    // This is where we use Weak<T> references! The Node struct is right after main2, outside of it so the tests can build
+    // trees too. Like List2, its value is only generic for them.
 
     // Create a couple of nodes, one parent pointing to a child node
     let leaf = Rc::new(Node {
@@ -308,6 +306,103 @@ fn main2() {
 
 }
 
+#[derive(Debug)]
+struct Node<T = i32> {
+    value: T,
+    children: RefCell<Vec<Rc<Node<T>>>>,
+    parent: RefCell<Weak<Node<T>>>
+}
+
+// Set-Once Configuration: LazyConfig and SyncLazyConfig
+
+// Settings are read once (here from environment variables) and then only looked at, by any code that needs them.
+// A global static makes them reachable from everywhere without passing them around, and SyncLazyConfig (see lazy.rs)
+// makes that static safe: it is filled on first use, exactly once, even if several threads get there at the same time.
+
+use refcell_smart_pointer::lazy::{LazyConfig, SyncLazyConfig};
+
+#[derive(Debug)]
+struct Settings {
+    verbose: bool,
+    app_name: String,
+}
+
+static SETTINGS: SyncLazyConfig<Settings> = SyncLazyConfig::new();
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        verbose: std::env::var("VERBOSE").is_ok(),
+        app_name: std::env::var("APP_NAME").unwrap_or_else(|_| String::from("refcell_smart_pointer")),
+    })
+}
+
+// A tiny logger that reads its settings from the global, the first call to log() is what loads them
+fn log(message: &str, debug: bool) {
+    let settings = settings();
+    if !debug || settings.verbose {
+        println!("[{}] {message}", settings.app_name);
+    }
+}
+
+fn main3() {
+    log("starting up", false);
+    log("only shown with VERBOSE set", true);
+    println!("SETTINGS = {:?}", SETTINGS);
+
+    // The single threaded LazyConfig works the same way for a value that belongs to one function or struct.
+    // Unlike RefCell::borrow, get() gives back a plain &T, so the value can be borrowed for as long as the cell lives.
+    let greeting = LazyConfig::new();
+    let first: &String = greeting.get_or_init(|| format!("hello from {}", settings().app_name));
+    let second: &String = greeting.get_or_init(|| String::from("never used"));
+    println!("{first} / {second}");
+    println!("set again: {:?}", greeting.set(String::from("too late")));
+}
+
+// Observers that Unsubscribe Themselves
+
+// The tree above used Weak for the parent pointer so a child doesn't keep its parent alive. An event emitter (see events.rs)
+// uses Weak the same way for its listeners: the emitter can call them, but doesn't keep them alive.
+// When a display is dropped, it simply stops receiving events.
+
+use refcell_smart_pointer::events::{EventEmitter, Listener};
+
+struct TemperatureChanged {
+    celsius: f64,
+}
+
+struct Display {
+    name: String,
+    updates: Cell<u32>,
+}
+
+impl Listener<TemperatureChanged> for Display {
+    fn notify(&self, event: &TemperatureChanged) {
+        self.updates.set(self.updates.get() + 1);
+        println!("{}: it is now {:.1}°C", self.name, event.celsius);
+    }
+}
+
+fn main4() {
+    let thermostat = EventEmitter::new();
+
+    let hall = Rc::new(Display { name: String::from("hall"), updates: Cell::new(0) });
+    thermostat.subscribe(&hall);
+    let kitchen = Rc::new(Display { name: String::from("kitchen"), updates: Cell::new(0) });
+    thermostat.subscribe(&kitchen);
+    let _alarm = thermostat.on(|event: &TemperatureChanged| {
+        if event.celsius > 30.0 {
+            println!("alarm: too hot!");
+        }
+    });
+
+    thermostat.emit(&TemperatureChanged { celsius: 21.5 });
+
+    // No unsubscribe call needed, dropping the kitchen display is enough
+    drop(kitchen);
+    let notified = thermostat.emit(&TemperatureChanged { celsius: 31.0 });
+    println!("{notified} listeners notified, the hall display got {} updates", hall.updates.get());
+}
+
 /* SUMMARY:
 
 1. Box<T> type has a known size and points to data allocated on the heap
@@ -316,4 +411,50 @@ fn main2() {
 It also enforces the borrowing rules at runtime instead of at compile time.
 4. Weak<T> can be used to prevent reference cycles by assigning them to places where cylical references are needed, and they will be dropped at the end of the scope no matter what their ref count is.
 
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::leakcheck::{assert_no_leaks, live, Tracked};
+
+    #[test]
+    fn a_cycle_outlives_its_owners_until_it_is_broken() {
+        assert_no_leaks(|| {
+            // The cycle of main2, with a Weak kept to get back into it
+            let a = Rc::new(Cons(Tracked::new(5), RefCell::new(Rc::new(Nil))));
+            let b = Rc::new(Cons(Tracked::new(10), RefCell::new(Rc::clone(&a))));
+            if let Some(link) = a.tail() {
+                *link.borrow_mut() = Rc::clone(&b);
+            }
+            let weak = Rc::downgrade(&a);
+            drop(a);
+            drop(b);
+            assert_eq!(2, live());
+
+            // Without the Weak they'd stay alive for good, and assert_no_leaks would fail. Pointing a at Nil breaks the cycle
+            let a = weak.upgrade().unwrap();
+            *a.tail().unwrap().borrow_mut() = Rc::new(Nil);
+            assert_eq!(1, live());
+            drop(a);
+            assert!(weak.upgrade().is_none());
+        });
+    }
+
+    #[test]
+    fn a_branch_is_freed_while_its_leaf_points_at_it() {
+        assert_no_leaks(|| {
+            let parent = RefCell::new(Weak::new());
+            let leaf = Rc::new(Node { value: Tracked::new(3), children: RefCell::new(vec![]), parent });
+            {
+                let children = RefCell::new(vec![Rc::clone(&leaf)]);
+                let branch = Rc::new(Node { value: Tracked::new(5), children, parent: RefCell::new(Weak::new()) });
+                *leaf.parent.borrow_mut() = Rc::downgrade(&branch);
+                assert_eq!(2, live());
+            }
+            // The parent pointer is Weak, so it didn't keep the branch alive
+            assert_eq!((1, 3), (live(), *leaf.value));
+            assert!(leaf.parent.borrow().upgrade().is_none());
+        });
+    }
+}
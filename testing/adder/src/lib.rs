@@ -15,6 +15,10 @@
 // To change a function into a test function, add #[test] on the line before fn
 // Run the tests with the cargo test command, and rust builds a test runner binary and generates a test report as well
 
+use std::sync::Once;
+
+use common::{i18n, t};
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
@@ -44,7 +48,39 @@ pub fn add_three(a: i32) -> i32 {
 
 
 pub fn greeting(name: &str) -> String {
-    format!("Hello {}!", name)
+    greeting_in(DEFAULT_LOCALE, name)
+}
+
+// Greetings in Other Languages
+
+// The text of the greeting has moved out of the code, into a catalog per language in locales/, embedded in the library with
+// include_str! (projects/common/src/i18n.rs). t! looks the message up in the thread's locale and fills in {name}.
+// new_messages also has to agree with a number, which takes two forms in English and three in Polish and Russian.
+
+const DEFAULT_LOCALE: &str = "en";
+const CATALOGS: [(&str, &str); 3] = [
+    ("en", include_str!("../locales/en.txt")),
+    ("pl", include_str!("../locales/pl.txt")),
+    ("ru", include_str!("../locales/ru.txt")),
+];
+
+fn load_catalogs() {
+    static LOADED: Once = Once::new();
+    LOADED.call_once(|| {
+        for (locale, text) in CATALOGS {
+            i18n::load(locale, text).expect("the catalogs in locales/ are checked by the tests");
+        }
+    });
+}
+
+pub fn greeting_in(locale: &str, name: &str) -> String {
+    load_catalogs();
+    i18n::with_locale(locale, || t!("greeting", name = name))
+}
+
+pub fn new_messages(locale: &str, name: &str, count: u64) -> String {
+    load_catalogs();
+    i18n::with_locale(locale, || t!("new_messages", name = name, count = count))
 }
 
 
@@ -160,6 +196,23 @@ mod tests {
         assert!(result.contains("Carol"));
     }
 
+    #[test]
+    fn greetings_in_other_languages() {
+        assert_eq!("Hello Carol!", greeting("Carol"));
+        assert_eq!("Cześć, Carol!", greeting_in("pl-PL", "Carol"));
+        assert_eq!("Привет, Carol!", greeting_in("ru", "Carol"));
+        // There's no German catalog, English is the fallback
+        assert_eq!("Hello Carol!", greeting_in("de", "Carol"));
+
+        let polish: Vec<String> = [1, 3, 5, 22].iter().map(|&n| new_messages("pl", "Carol", n)).collect();
+        assert_eq!("Carol, masz 1 nową wiadomość", polish[0]);
+        assert_eq!("Carol, masz 3 nowe wiadomości", polish[1]);
+        assert_eq!("Carol, masz 5 nowych wiadomości", polish[2]);
+        assert_eq!("Carol, masz 22 nowe wiadomości", polish[3]);
+        assert_eq!("Carol, у вас 21 новое сообщение", new_messages("ru", "Carol", 21));
+        assert_eq!("Carol, you have 1 new message", new_messages("en", "Carol", 1));
+    }
+
 
     /*
     // In this test, we purposefully introduce a bug in order to fail it, and the failure message is passed into the assert!() macro
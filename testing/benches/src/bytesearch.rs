@@ -0,0 +1,50 @@
+// Byte Search
+
+// common::bytesearch (projects/common/src/bytesearch.rs) looks at 8 bytes at a time, where a plain iterator looks at one.
+// It's compared with the iterator and with the memchr crate, which uses SSE2/AVX2 or NEON, on 8 MiB of text:
+    // 1. memchr: a byte that's only near the end. bytesearch::memchr is the SIMD one with the simd feature of common
+    //    (nightly only), memchr_swar always the u64 one.
+    // 2. memmem: a word that's only near the end. bytesearch looks for its rarest byte and compares the rest there.
+    // 3. the end of a head: what the webserver looks for in every request, in text that has no empty line at all.
+
+use std::hint::black_box;
+
+use common::bytesearch;
+use test_support::Throughput;
+
+use crate::{text, Options};
+
+pub fn run(options: &Options) {
+    let mut haystack = text(8 * 1024 * 1024, 1999).into_bytes();
+    let at = haystack.len() - 1000;
+    haystack[at..at + 7].copy_from_slice(b"#ferris");
+    let haystack = &haystack[..];
+
+    // Every way has to find the same place before its speed means anything
+    assert_eq!(Some(at), haystack.iter().position(|&b| b == b'#'));
+    assert_eq!(Some(at), bytesearch::memchr(b'#', haystack));
+    assert_eq!(Some(at), bytesearch::memchr_swar(b'#', haystack));
+    assert_eq!(Some(at), memchr::memchr(b'#', haystack));
+    assert_eq!(Some(at + 1), bytesearch::memmem(haystack, b"ferris"));
+    assert_eq!(Some(at + 1), memchr::memmem::find(haystack, b"ferris"));
+    assert_eq!(None, bytesearch::find_header_end(haystack));
+
+    let throughput = Throughput::Bytes(haystack.len() as u64);
+    let mut bench = options.bench("memchr, 8 MiB for a byte near the end").throughput(throughput);
+    bench.run("iter().position", || black_box(haystack).iter().position(|&b| b == b'#'));
+    bench.run("bytesearch::memchr_swar", || bytesearch::memchr_swar(b'#', black_box(haystack)));
+    bench.run("bytesearch::memchr", || bytesearch::memchr(b'#', black_box(haystack)));
+    bench.run("memchr::memchr", || memchr::memchr(b'#', black_box(haystack)));
+    eprintln!("{bench}");
+
+    let mut bench = options.bench("memmem, 8 MiB for a word near the end").throughput(throughput);
+    bench.run("windows().position", || black_box(haystack).windows(6).position(|window| window == b"ferris"));
+    bench.run("bytesearch::memmem", || bytesearch::memmem(black_box(haystack), b"ferris"));
+    bench.run("memchr::memmem::find", || memchr::memmem::find(black_box(haystack), b"ferris"));
+    eprintln!("{bench}");
+
+    let mut bench = options.bench("the end of a head, 8 MiB without one").throughput(throughput);
+    bench.run("windows().position", || black_box(haystack).windows(4).position(|window| window == b"\r\n\r\n"));
+    bench.run("bytesearch::find_header_end", || bytesearch::find_header_end(black_box(haystack)));
+    eprintln!("{bench}");
+}
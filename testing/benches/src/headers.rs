@@ -0,0 +1,99 @@
+// Request Head Parsing
+
+// The webserver reads the request line and the headers of every request before anything else happens
+// (projects/multithreaded_webserver/src/http.rs). It splits the request line into an ArrayVec, keeps the values as SmallStrings
+// and the common names as interned Symbols (collections/std_collections/src/arrayvec.rs, small_string.rs and interner.rs).
+// A typical head then costs the Vec of headers and a few long values, instead of two allocations for every header. Every
+// iteration parses the same 1000 heads of a GET:
+    // 1. Strings: what http.rs did at first, a Vec for the parts of the request line and a String for every name and value
+    // 2. SmallStrings: the ArrayVec, and SmallStrings for the names too
+    // 3. interned names: the ArrayVec and the SmallStrings, with the names looked up in the interned ones, what http.rs does now
+    // 4. head::Request::parse: the head borrowed from the buffer it's in, nothing is copied (src/head.rs of the
+    //    webserver). It checks the request target too, like the server, which the first three leave out
+    // 5. Request::read_head: the server's own, which parses the borrowed head and copies it into a Request
+// Only the user agent, the cookie and the name Upgrade-Insecure-Requests are too long to stay inline. Every name of the head
+// is a common one, so with interning none of them is copied at all. That's not faster to parse than copying a name this short
+// inline, looking it up costs a hash. It pays off afterwards: Request::header compares Symbols, not text ignoring case.
+
+use std::hint::black_box;
+
+use multithreaded_webserver::{
+    head,
+    http::{HeaderName, HeaderValue, Request},
+};
+use std_collections::{arrayvec::ArrayVec, small_string::SmallString};
+use test_support::Throughput;
+
+use crate::Options;
+
+const HEADS: u64 = 1000;
+
+const HEAD: &str = "GET /articles/42?lang=en HTTP/1.1\r\n\
+Host: 127.0.0.1:7878\r\n\
+User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0\r\n\
+Accept: text/html\r\n\
+Accept-Language: en-US,en;q=0.5\r\n\
+Accept-Encoding: gzip, deflate\r\n\
+Connection: keep-alive\r\n\
+Cookie: session=4bf92f3577b34da6a3ce929d0e0e4736; theme=dark\r\n\
+Upgrade-Insecure-Requests: 1\r\n\
+Sec-Fetch-Dest: document\r\n\
+Sec-Fetch-Mode: navigate\r\n\
+Priority: u=0, i\r\n\
+\r\n";
+
+fn parse_strings(head: &str) -> (String, Vec<(String, String)>) {
+    let mut lines = head.lines();
+    let parts: Vec<&str> = lines.next().unwrap().split(' ').collect();
+    assert_eq!(3, parts.len());
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .map(|line| {
+            let (name, value) = line.split_once(':').unwrap();
+            (name.trim().to_string(), value.trim().to_string())
+        })
+        .collect();
+    (parts[0].to_string(), headers)
+}
+
+// Generic over the name, to measure the SmallString names and the interned ones with the same code
+fn parse_small<'a, N: From<&'a str>>(head: &'a str) -> (String, Vec<(N, HeaderValue)>) {
+    let mut lines = head.lines();
+    let parts: ArrayVec<&str, 3> = lines.next().unwrap().split(' ').collect();
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .map(|line| {
+            let (name, value) = line.split_once(':').unwrap();
+            (N::from(name.trim()), HeaderValue::from(value.trim()))
+        })
+        .collect();
+    (parts[0].to_string(), headers)
+}
+
+pub fn run(options: &Options) {
+    let (method, strings) = parse_strings(HEAD);
+    let (_, small) = parse_small::<SmallString<24>>(HEAD);
+    let (_, interned) = parse_small::<HeaderName>(HEAD);
+    let request = Request::read_head(&mut HEAD.as_bytes()).unwrap();
+    assert_eq!(method, request.method);
+    let pairs = strings.iter().map(|(name, value)| (name.as_str(), value.as_str()));
+    assert!(pairs.eq(small.iter().map(|(name, value)| (name.as_str(), value.as_str()))));
+    assert_eq!(interned, request.headers);
+    assert_eq!(3, small.iter().filter(|(name, value)| name.spilled() || value.spilled()).count());
+    assert!(interned.iter().all(|(name, _)| name.is_common()));
+    let (borrowed, _) = head::Request::parse(HEAD.as_bytes()).unwrap().unwrap();
+    assert_eq!(request, borrowed.into_owned());
+
+    let mut bench = options.bench(&format!("parsing {HEADS} request heads of {} bytes", HEAD.len()))
+        .throughput(Throughput::Elements(HEADS));
+    bench.run("Strings", || (0..HEADS).map(|_| parse_strings(black_box(HEAD)).1.len()).sum::<usize>());
+    bench.run("SmallStrings", || (0..HEADS).map(|_| parse_small::<SmallString<24>>(black_box(HEAD)).1.len()).sum::<usize>());
+    bench.run("interned names", || (0..HEADS).map(|_| parse_small::<HeaderName>(black_box(HEAD)).1.len()).sum::<usize>());
+    bench.run("head::Request::parse", || {
+        (0..HEADS).map(|_| head::Request::parse(black_box(HEAD).as_bytes()).unwrap().unwrap().0.headers.len()).sum::<usize>()
+    });
+    bench.run("Request::read_head", || {
+        (0..HEADS).map(|_| Request::read_head(&mut black_box(HEAD).as_bytes()).unwrap().headers.len()).sum::<usize>()
+    });
+    eprintln!("{bench}");
+}
@@ -0,0 +1,57 @@
+// Building the Full-Text Index
+
+// minigrep's index (projects/minigrep/src/index.rs) splits every line into terms and appends the line to each term's postings.
+// Most terms of a text were seen before, so how a term that's already there is looked up is what the build costs:
+    // 1. HashMap<String, _>: what index.rs did before, a lowercased String for every term on every line, found by hashing it
+    //    and dropped again right away unless the term is new
+    // 2. Index::add_file: the terms are interned (collections/std_collections/src/interner.rs) and lowercased into a buffer
+    //    that's reused, only a new term allocates
+// Both index the same 2 MiB of text and keep the terms in a Trie as well, like the index does for prefix queries.
+
+use std::{collections::HashMap, hint::black_box, path::Path};
+
+use minigrep::index::{terms, Index, Posting};
+use std_collections::trie::Trie;
+use test_support::Throughput;
+
+use crate::{text, Options};
+
+fn index_strings(contents: &str) -> HashMap<String, Vec<Posting>> {
+    let mut postings: HashMap<String, Vec<Posting>> = HashMap::new();
+    let mut trie = Trie::new();
+    for (line, text) in contents.lines().enumerate() {
+        let posting = Posting { file: 0, line: line as u32 };
+        for term in terms(text) {
+            let list = postings.entry(term).or_insert_with_key(|term| {
+                trie.insert(term);
+                Vec::new()
+            });
+            if list.last() != Some(&posting) {
+                list.push(posting);
+            }
+        }
+    }
+    postings
+}
+
+fn index_interned(contents: &str) -> Index {
+    let mut index = Index::new(Path::new("bench"));
+    index.add_file(Path::new("bench/text"), contents.as_bytes());
+    index
+}
+
+pub fn run(options: &Options) {
+    let contents = text(2 * 1024 * 1024, 2024);
+    let strings = index_strings(&contents);
+    let index = index_interned(&contents);
+    // Both found the same terms on the same lines
+    assert_eq!(strings.len(), index.len());
+    for (term, postings) in &strings {
+        assert_eq!(*postings, index.query(term).unwrap(), "{term}");
+    }
+
+    let mut bench = options.bench("indexing 2 MiB of text").throughput(Throughput::Bytes(contents.len() as u64));
+    bench.run("HashMap<String, _>", || index_strings(black_box(&contents)).len());
+    bench.run("Index::add_file", || index_interned(black_box(&contents)).len());
+    eprintln!("{bench}");
+}
@@ -0,0 +1,107 @@
+// Benchmarks
+
+// Timings for the places in the repo where we picked one implementation over another, so the choice can be checked
+// on a real machine instead of believed:
+    // 1. search: minigrep's search backends on the same corpus (projects/minigrep/src/backend.rs)
+    // 2. maps: counting words with the standard HashMap, with our own hashers, with a BTreeMap and with the Trie
+    // 3. pool: running many small jobs on the webserver's ThreadPool, against a thread per job and no threads at all
+    // 4. rope: random edits to a big text in a String and in a Rope (collections/std_collections/src/rope.rs)
+    // 5. headers: parsing request heads into Strings, into the webserver's SmallStrings and interned names
+    //    (collections/std_collections), and borrowing them from the buffer
+    // 6. index: building minigrep's full-text index with a String for every term, and with the terms interned
+    // 7. bytesearch: finding bytes and words 8 bytes at a time (projects/common), one at a time, and with the memchr crate
+// The harness is test_support::Bench, see testing/test_support/src/bench.rs for how it measures.
+
+// $ cargo run -p benches                  every suite
+// $ cargo run -p benches -- maps rope     only these suites
+// $ cargo run -p benches -- --quick       a few short samples, to see that everything runs
+
+// The report is written to stderr. The ThreadPool's workers print a line to stdout for every job they run (like in the book),
+// and thousands of those would bury the tables: cargo run -p benches > /dev/null shows only the report.
+// On CI (where the CI variable is set) every suite runs in quick mode, the numbers of a shared runner mean little anyway.
+
+use std::{env, process};
+
+use std_collections::rand_lite::{Rng, Xoshiro256};
+use test_support::Bench;
+
+mod bytesearch;
+mod headers;
+mod index;
+mod maps;
+mod pool;
+mod rope;
+mod search;
+
+// A name to pick it on the command line, and the function that runs it
+type Suite = (&'static str, fn(&Options));
+
+const SUITES: [Suite; 7] = [
+    ("search", search::run),
+    ("maps", maps::run),
+    ("pool", pool::run),
+    ("rope", rope::run),
+    ("headers", headers::run),
+    ("index", index::run),
+    ("bytesearch", bytesearch::run),
+];
+
+pub struct Options {
+    quick: bool,
+}
+
+impl Options {
+    // A Bench set up for this run
+    pub fn bench(&self, title: &str) -> Bench {
+        let bench = Bench::new(title);
+        if self.quick {
+            bench.quick()
+        } else {
+            bench
+        }
+    }
+}
+
+pub const WORDS: [&str; 16] = [
+    "the", "crab", "borrow", "checker", "lifetime", "trait", "struct", "enum",
+    "match", "iterator", "closure", "vector", "string", "slice", "module", "crate",
+];
+
+// Lines of random words from a fixed seed, so that every run measures the same text. Only ASCII, so char and byte indexes agree.
+pub fn text(bytes: usize, seed: u64) -> String {
+    let mut rng = Xoshiro256::seed_from_u64(seed);
+    let mut text = String::with_capacity(bytes + 100);
+    while text.len() < bytes {
+        let words = rng.gen_range(4..16);
+        for i in 0..words {
+            if i > 0 {
+                text.push(' ');
+            }
+            text.push_str(rng.choose(&WORDS).unwrap());
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn main() {
+    let mut options = Options { quick: env::var_os("CI").is_some() };
+    let mut selected = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--quick" => options.quick = true,
+            name if SUITES.iter().any(|(suite, _)| *suite == name) => selected.push(arg),
+            _ => {
+                let names: Vec<&str> = SUITES.iter().map(|(suite, _)| *suite).collect();
+                eprintln!("benches: unknown suite {arg:?}, the suites are {}", names.join(", "));
+                process::exit(2);
+            }
+        }
+    }
+
+    for (name, run) in SUITES {
+        if selected.is_empty() || selected.iter().any(|selected| selected == name) {
+            run(&options);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// Maps
+
+// Counting how often every word occurs, the classic job for a map, done five ways:
+    // 1. HashMap with its default hasher, SipHash-1-3 with a random key
+    // 2. HashMap with FNV-1a (collections/std_collections/src/hashing.rs): much less work per key, but no protection
+    //    against keys picked to collide
+    // 3. HashMap with our own SipHash-2-4, a round more than the standard one, to see what the rounds cost
+    // 4. BTreeMap, which compares keys instead of hashing them, and keeps them sorted
+    // 5. The Trie behind autocomplete (collections/std_collections/src/trie.rs), which counts the words it's built from
+// The words are made of random syllables, so there are many distinct ones, like in a real text.
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    hash::BuildHasher,
+};
+
+use std_collections::{
+    hashing::{FnvBuildHasher, SipBuildHasher},
+    rand_lite::{Rng, Xoshiro256},
+    trie::Trie,
+};
+use test_support::Throughput;
+
+use crate::Options;
+
+const SYLLABLES: [&str; 10] = ["ka", "ri", "to", "ne", "su", "mo", "la", "chi", "ve", "dan"];
+
+fn words(count: usize) -> Vec<String> {
+    let mut rng = Xoshiro256::seed_from_u64(42);
+    (0..count)
+        .map(|_| {
+            let len = rng.gen_range(1..6);
+            (0..len).map(|_| *rng.choose(&SYLLABLES).unwrap()).collect()
+        })
+        .collect()
+}
+
+fn count_hashed<S: BuildHasher>(words: &[String], hasher: S) -> usize {
+    let mut counts: HashMap<&str, u64, S> = HashMap::with_hasher(hasher);
+    for word in words {
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    counts.len()
+}
+
+fn count_sorted(words: &[String]) -> usize {
+    let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+    for word in words {
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    counts.len()
+}
+
+pub fn run(options: &Options) {
+    let words = words(100_000);
+    let distinct = count_sorted(&words);
+    let mut bench = options.bench(&format!("word counts, {} words, {distinct} distinct", words.len()))
+        .throughput(Throughput::Elements(words.len() as u64));
+
+    bench.run("HashMap, SipHash-1-3", || count_hashed(&words, RandomState::new()));
+    bench.run("HashMap, FNV-1a", || count_hashed(&words, FnvBuildHasher::default()));
+    bench.run("HashMap, SipHash-2-4", || count_hashed(&words, SipBuildHasher::new([7; 16])));
+    bench.run("BTreeMap", || count_sorted(&words));
+    bench.run("Trie", || words.iter().map(String::as_str).collect::<Trie>());
+    eprintln!("{bench}");
+}
@@ -0,0 +1,57 @@
+// Thread Pool Scheduling
+
+// What handing a small job to the webserver's ThreadPool (projects/multithreaded_webserver/src/lib.rs) costs. Every iteration
+// runs 1000 jobs that each add up a few numbers, and waits for all of them:
+    // 1. on the pool with 1 worker and with 4, where the jobs go through the channel and the Mutex around its receiver
+    // 2. on a new thread per job, which is what the pool saves us from
+    // 3. on the calling thread, no threads at all: the part of the time that is the jobs themselves
+// The jobs are tiny on purpose, so the numbers are mostly the scheduling. The workers print a line per job, which is counted too.
+
+use std::{
+    hint::black_box,
+    sync::mpsc,
+    thread,
+};
+
+use multithreaded_webserver::ThreadPool;
+use test_support::Throughput;
+
+use crate::Options;
+
+const JOBS: u64 = 1000;
+
+fn job(n: u64) -> u64 {
+    (0..black_box(100)).map(|i| i * n).sum()
+}
+
+fn on_pool(pool: &ThreadPool) -> u64 {
+    let (sender, receiver) = mpsc::channel();
+    for n in 0..JOBS {
+        let sender = sender.clone();
+        pool.execute(move || sender.send(job(n)).unwrap());
+    }
+    drop(sender);
+    receiver.iter().sum()
+}
+
+fn thread_per_job() -> u64 {
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..JOBS).map(|n| scope.spawn(move || job(n))).collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).sum()
+    })
+}
+
+pub fn run(options: &Options) {
+    let mut bench = options.bench(&format!("thread pool, {JOBS} small jobs")).throughput(Throughput::Elements(JOBS));
+    let expected: u64 = (0..JOBS).map(job).sum();
+
+    for (workers, name) in [(1, "ThreadPool, 1 worker"), (4, "ThreadPool, 4 workers")] {
+        let pool = ThreadPool::new(workers);
+        assert_eq!(expected, on_pool(&pool));
+        bench.run(name, || on_pool(&pool));
+    }
+    assert_eq!(expected, thread_per_job());
+    bench.run("a thread per job", thread_per_job);
+    bench.run("no threads", || (0..JOBS).map(job).sum::<u64>());
+    eprintln!("{bench}");
+}
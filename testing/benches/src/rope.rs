@@ -0,0 +1,77 @@
+// String vs Rope
+
+// A text editor inserts and deletes in the middle of the text all the time. In a String, every edit moves all the bytes after
+// it, so the cost grows with the size of the text. A Rope (collections/std_collections/src/rope.rs) keeps the text in a tree
+// of small chunks, and an edit only touches one path down the tree.
+// Every iteration starts from the same 256 KiB text and makes the same 500 random edits, half inserts and half deletes.
+// Building the starting text is part of both cases, a String::from against a Rope::from.
+
+use std_collections::{
+    rand_lite::{Rng, Xoshiro256},
+    rope::Rope,
+};
+use test_support::Throughput;
+
+use crate::{text, Options};
+
+const EDITS: usize = 500;
+
+enum Edit {
+    Insert(usize, &'static str),
+    Delete(usize, usize),
+}
+
+// Positions that stay valid when the edits are applied in order: the length is tracked as they're made up
+fn edits(start_len: usize) -> Vec<Edit> {
+    let mut rng = Xoshiro256::seed_from_u64(7);
+    let mut len = start_len;
+    (0..EDITS)
+        .map(|i| {
+            if i % 2 == 0 {
+                let text = *rng.choose(&["fn ", "let x = 1;\n", "// TODO\n", "}"]).unwrap();
+                len += text.len();
+                Edit::Insert(rng.gen_range(0..=len - text.len()), text)
+            } else {
+                let start = rng.gen_range(0..len - 20);
+                let end = start + rng.gen_range(1..20usize);
+                len -= end - start;
+                Edit::Delete(start, end)
+            }
+        })
+        .collect()
+}
+
+fn edit_string(base: &str, edits: &[Edit]) -> String {
+    let mut text = String::from(base);
+    for edit in edits {
+        match *edit {
+            Edit::Insert(at, inserted) => text.insert_str(at, inserted),
+            Edit::Delete(start, end) => drop(text.drain(start..end)),
+        }
+    }
+    text
+}
+
+fn edit_rope(base: &str, edits: &[Edit]) -> Rope {
+    let mut rope = Rope::from(base);
+    for edit in edits {
+        match *edit {
+            Edit::Insert(at, inserted) => rope.insert(at, inserted),
+            Edit::Delete(start, end) => rope.delete(start, end),
+        }
+    }
+    rope
+}
+
+pub fn run(options: &Options) {
+    let base = text(256 * 1024, 1);
+    let edits = edits(base.len());
+    // The text is ASCII, so the Rope's char indexes and the String's byte indexes are the same positions
+    assert_eq!(edit_string(&base, &edits), edit_rope(&base, &edits).to_string());
+
+    let mut bench = options.bench(&format!("{EDITS} random edits to {} KiB of text", base.len() / 1024))
+        .throughput(Throughput::Elements(EDITS as u64));
+    bench.run("String", || edit_string(&base, &edits).len());
+    bench.run("Rope", || edit_rope(&base, &edits).len_chars());
+    eprintln!("{bench}");
+}
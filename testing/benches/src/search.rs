@@ -0,0 +1,29 @@
+// Search Backends
+
+// The same suite as projects/minigrep/src/bin/bench_backends.rs, measured by the harness: every backend this build of minigrep
+// has, over 8 MiB of text with a query that's rare in it. That's what most real searches look like, the time goes into the
+// lines that don't match. The backends left out by minigrep's cargo features are skipped.
+
+use minigrep::backend::{self, BackendKind};
+use test_support::Throughput;
+
+use crate::{text, Options};
+
+pub fn run(options: &Options) {
+    let mut contents = text(8 * 1024 * 1024, 2015);
+    // A handful of matches, at fixed places
+    for at in [1000, 2_000_000, 7_000_000] {
+        let line_start = contents[..at].rfind('\n').map_or(0, |i| i + 1);
+        contents.insert_str(line_start, "ferris ");
+    }
+    let contents = contents.into_bytes();
+
+    let mut bench = options.bench("search backends, 8 MiB for a rare word").throughput(Throughput::Bytes(contents.len() as u64));
+    for kind in BackendKind::ALL.into_iter().filter(|kind| kind.is_available()) {
+        let backend = backend::build(kind, &[String::from("ferris")], false, false).expect("a plain word is a valid query");
+        // Every backend has to find the same lines before its speed means anything
+        assert_eq!(3, backend.search(&contents, b'\n').len(), "{}", backend.name());
+        bench.run(backend.name(), || backend.search(&contents, b'\n').len());
+    }
+    eprintln!("{bench}");
+}
@@ -0,0 +1,241 @@
+// Benchmarks
+
+// cargo bench with #[bench] functions is still nightly only (see the notes in testing/adder), so the benchmarks in this repo
+// are plain programs that time themselves with Instant. Timing something well takes more than one Instant::now() though:
+    // 1. The first runs are slower: the caches are cold, the allocator hasn't got any memory yet, the CPU may still be clocked down.
+    //    So the code runs for a while without being measured first, the warmup.
+    // 2. A single run of something fast takes less time than the clock can resolve. The warmup tells how long one iteration
+    //    takes, and every sample then runs enough iterations to fill sample_time, and divides.
+    // 3. One number hides how noisy the machine was. Several samples give a mean and a standard deviation: when the deviation
+    //    is a good part of the mean, the difference between two benchmarks may be noise.
+    // 4. With a throughput (bytes or elements per iteration) the report shows MiB/s or elements/s, easier to compare with
+    //    the other numbers one knows than nanoseconds.
+
+// A Bench is a group of cases that do the same work in different ways, and is shown as one table:
+
+    // let mut bench = Bench::new("word counts").throughput(Throughput::Elements(words.len() as u64));
+    // bench.run("HashMap", || count_with_hashmap(&words));
+    // bench.run("BTreeMap", || count_with_btreemap(&words));
+    // print!("{bench}");
+
+// What the closure returns goes through black_box, so the optimizer can't throw the work away because nobody uses the result.
+
+use std::{
+    fmt,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use common::table::{Align, Table};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throughput {
+    Bytes(u64),
+    Elements(u64),
+}
+
+// The time of one iteration, over all the samples of a case
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub name: String,
+    // Measured iterations, the warmup not included
+    pub iterations: u64,
+    pub mean: Duration,
+    pub stddev: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Stats {
+    // From the time per iteration of every sample
+    pub fn from_samples(name: &str, iterations: u64, samples: &[Duration]) -> Stats {
+        assert!(!samples.is_empty(), "no samples for {name}");
+        let secs: Vec<f64> = samples.iter().map(Duration::as_secs_f64).collect();
+        let mean = secs.iter().sum::<f64>() / secs.len() as f64;
+        // The sample standard deviation, divided by n - 1: the samples are a few out of all the runs there could have been
+        let variance = match secs.len() {
+            1 => 0.0,
+            n => secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1) as f64,
+        };
+        Stats {
+            name: name.to_string(),
+            iterations,
+            mean: Duration::from_secs_f64(mean),
+            stddev: Duration::from_secs_f64(variance.sqrt()),
+            min: samples.iter().copied().min().unwrap(),
+            max: samples.iter().copied().max().unwrap(),
+        }
+    }
+
+    pub fn throughput(&self, throughput: Throughput) -> String {
+        let per_second = |count: u64| count as f64 / self.mean.as_secs_f64().max(f64::MIN_POSITIVE);
+        match throughput {
+            Throughput::Bytes(bytes) => {
+                let mib = per_second(bytes) / (1024.0 * 1024.0);
+                if mib >= 1024.0 {
+                    format!("{:.2} GiB/s", mib / 1024.0)
+                } else {
+                    format!("{mib:.1} MiB/s")
+                }
+            }
+            Throughput::Elements(elements) => match per_second(elements) {
+                rate if rate >= 1e6 => format!("{:.2} M/s", rate / 1e6),
+                rate if rate >= 1e3 => format!("{:.2} K/s", rate / 1e3),
+                rate => format!("{rate:.0} /s"),
+            },
+        }
+    }
+}
+
+// Three significant digits at most, in the unit that keeps the number readable
+pub fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_secs_f64() * 1e9;
+    match nanos {
+        n if n < 1e3 => format!("{n:.1} ns"),
+        n if n < 1e6 => format!("{:.2} µs", n / 1e3),
+        n if n < 1e9 => format!("{:.2} ms", n / 1e6),
+        n => format!("{:.2} s", n / 1e9),
+    }
+}
+
+pub struct Bench {
+    title: String,
+    warmup: Duration,
+    samples: usize,
+    sample_time: Duration,
+    throughput: Option<Throughput>,
+    results: Vec<Stats>,
+}
+
+impl Bench {
+    pub fn new(title: &str) -> Bench {
+        Bench {
+            title: title.to_string(),
+            warmup: Duration::from_millis(300),
+            samples: 20,
+            sample_time: Duration::from_millis(50),
+            throughput: None,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn warmup(mut self, warmup: Duration) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    pub fn samples(mut self, samples: usize) -> Self {
+        assert!(samples > 0, "a benchmark needs at least one sample");
+        self.samples = samples;
+        self
+    }
+
+    // How long each sample should take. Something slower than this still runs once per sample.
+    pub fn sample_time(mut self, sample_time: Duration) -> Self {
+        self.sample_time = sample_time;
+        self
+    }
+
+    // What one iteration processes, the same for every case of the group
+    pub fn throughput(mut self, throughput: Throughput) -> Self {
+        self.throughput = Some(throughput);
+        self
+    }
+
+    // Good enough to see that everything runs, not to compare the numbers: for CI, and for trying out a new benchmark
+    pub fn quick(self) -> Self {
+        self.warmup(Duration::from_millis(10)).samples(3).sample_time(Duration::from_millis(5))
+    }
+
+    pub fn run<T, F: FnMut() -> T>(&mut self, name: &str, mut f: F) -> &Stats {
+        // Warmup, which also tells how many iterations fit in a sample
+        let start = Instant::now();
+        let mut warmup_iterations: u64 = 0;
+        while warmup_iterations == 0 || start.elapsed() < self.warmup {
+            black_box(f());
+            warmup_iterations += 1;
+        }
+        let per_iteration = start.elapsed().as_secs_f64() / warmup_iterations as f64;
+        let per_sample = ((self.sample_time.as_secs_f64() / per_iteration.max(1e-9)) as u64).max(1);
+
+        let mut samples = Vec::with_capacity(self.samples);
+        for _ in 0..self.samples {
+            let start = Instant::now();
+            for _ in 0..per_sample {
+                black_box(f());
+            }
+            samples.push(Duration::from_secs_f64(start.elapsed().as_secs_f64() / per_sample as f64));
+        }
+
+        self.results.push(Stats::from_samples(name, per_sample * self.samples as u64, &samples));
+        self.results.last().unwrap()
+    }
+
+    pub fn results(&self) -> &[Stats] {
+        &self.results
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+// The title, then a table with a row per case. "Relative" is the mean against the fastest case's.
+impl fmt::Display for Bench {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.title)?;
+        let mut table = Table::new(["Benchmark", "Mean", "± Std dev", "Min", "Throughput", "Relative"]);
+        for column in 1..6 {
+            table = table.align(column, Align::Right);
+        }
+        let fastest = self.results.iter().map(|stats| stats.mean).min().unwrap_or_default().as_secs_f64();
+        for stats in &self.results {
+            table.add_row([
+                stats.name.clone(),
+                format_duration(stats.mean),
+                format_duration(stats.stddev),
+                format_duration(stats.min),
+                self.throughput.map_or_else(|| String::from("-"), |throughput| stats.throughput(throughput)),
+                format!("{:.2}x", stats.mean.as_secs_f64() / fastest.max(f64::MIN_POSITIVE)),
+            ]);
+        }
+        write!(f, "{table}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_and_units() {
+        let micros = |n| Duration::from_micros(n);
+        let stats = Stats::from_samples("three", 30, &[micros(1), micros(2), micros(3)]);
+        assert_eq!((micros(2), micros(1), micros(1), micros(3)), (stats.mean, stats.stddev, stats.min, stats.max));
+        assert_eq!(Duration::ZERO, Stats::from_samples("one", 1, &[micros(5)]).stddev);
+
+        // 1 MiB in 2 µs
+        assert_eq!("488.28 GiB/s", stats.throughput(Throughput::Bytes(1024 * 1024)));
+        assert_eq!("500.0 MiB/s", Stats::from_samples("slow", 1, &[Duration::from_millis(2)]).throughput(Throughput::Bytes(1024 * 1024)));
+        assert_eq!("1.50 M/s", stats.throughput(Throughput::Elements(3)));
+
+        assert_eq!("12.0 ns", format_duration(Duration::from_nanos(12)));
+        assert_eq!("1.50 µs", format_duration(Duration::from_nanos(1500)));
+        assert_eq!("2.25 s", format_duration(Duration::from_millis(2250)));
+    }
+
+    #[test]
+    fn runs_every_case_and_compares_them() {
+        let mut bench = Bench::new("sums").quick().samples(2).throughput(Throughput::Elements(1000));
+        let fast = bench.run("sum of 1000", || (0..black_box(1000u64)).sum::<u64>()).clone();
+        assert!(fast.iterations >= 2, "{fast:?}");
+        bench.run("sleep", || std::thread::sleep(Duration::from_millis(1)));
+        assert!(bench.results()[1].mean >= Duration::from_millis(1));
+
+        let report = bench.to_string();
+        assert!(report.starts_with("sums\n"), "{report}");
+        assert!(report.contains("1.00x"), "{report}");
+        // The title, the header and its rule, and a row per case
+        assert_eq!(5, report.lines().count(), "{report}");
+    }
+}
@@ -0,0 +1,244 @@
+// Running a Command and Checking What It Did
+
+// The checks take self and give it back, so they chain: .assert().success().stdout("...").stderr("").
+// Each of them panics on a mismatch, and #[track_caller] makes the panic point at the line in the test, not at this file.
+
+use std::{ffi::OsStr, fmt::Write, path::Path, time::Duration};
+
+use concurrency::proc::{Exec, ExitStatusError, Output, Running};
+use minigrep::diff::unified;
+
+// Long enough for a debug build on a busy CI machine, short enough that a hang doesn't stall the test run for long
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Cmd {
+    exec: Exec,
+    // The command line as the user would type it, for the failure messages
+    display: String,
+}
+
+impl Cmd {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Cmd {
+        let display = program.as_ref().to_string_lossy().into_owned();
+        Cmd { exec: Exec::new(program).timeout(DEFAULT_TIMEOUT), display }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        let arg = arg.as_ref();
+        write!(self.display, " {}", shell_word(&arg.to_string_lossy())).unwrap();
+        self.exec = self.exec.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self = self.arg(arg);
+        }
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(mut self, key: K, value: V) -> Self {
+        self.exec = self.exec.env(key, value);
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.exec = self.exec.current_dir(dir);
+        self
+    }
+
+    pub fn stdin<B: Into<Vec<u8>>>(mut self, input: B) -> Self {
+        self.exec = self.exec.stdin(input);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.exec = self.exec.timeout(timeout);
+        self
+    }
+
+    // Runs the program to the end. Whatever its exit code, that's for the Assert to check,
+    // but a program that can't be started or doesn't finish in time fails the test right here.
+    #[track_caller]
+    pub fn assert(self) -> Assert {
+        match self.exec.output() {
+            Ok(output) => Assert { output, display: self.display },
+            Err(e) => panic!("`{}`: {e}{}", self.display, e.output().map(describe).unwrap_or_default()),
+        }
+    }
+
+    // For programs that keep running, like the webserver. The timeout still applies: it kills a server a test forgot about.
+    pub fn spawn(self) -> Result<Running, ExitStatusError> {
+        self.exec.spawn()
+    }
+}
+
+// Quoted when it needs to be, so the command in a failure message can be pasted into a shell
+fn shell_word(word: &str) -> String {
+    let plain = !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+fn describe(output: &Output) -> String {
+    let mut text = String::new();
+    for (name, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        if bytes.is_empty() {
+            writeln!(text, "\n{name}: (empty)").unwrap();
+        } else {
+            write!(text, "\n{name}:\n{}", String::from_utf8_lossy(bytes)).unwrap();
+        }
+    }
+    text
+}
+
+pub struct Assert {
+    output: Output,
+    display: String,
+}
+
+impl Assert {
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    pub fn stdout_str(&self) -> String {
+        self.output.stdout_str()
+    }
+
+    pub fn stderr_str(&self) -> String {
+        self.output.stderr_str()
+    }
+
+    #[track_caller]
+    fn fail(&self, problem: &str) -> ! {
+        panic!("`{}`: {problem}\nexit status: {}{}", self.display, self.output.status, describe(&self.output));
+    }
+
+    #[track_caller]
+    pub fn success(self) -> Self {
+        if !self.output.status.success() {
+            self.fail("expected success");
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn failure(self) -> Self {
+        if self.output.status.success() {
+            self.fail("expected a failure");
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn code(self, code: i32) -> Self {
+        if self.output.status.code() != Some(code) {
+            self.fail(&format!("expected exit code {code}"));
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn stdout(self, expected: &str) -> Self {
+        self.compare("stdout", expected, &self.stdout_str());
+        self
+    }
+
+    #[track_caller]
+    pub fn stderr(self, expected: &str) -> Self {
+        self.compare("stderr", expected, &self.stderr_str());
+        self
+    }
+
+    #[track_caller]
+    pub fn stdout_contains(self, needle: &str) -> Self {
+        if !self.stdout_str().contains(needle) {
+            self.fail(&format!("expected stdout to contain {needle:?}"));
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn stderr_contains(self, needle: &str) -> Self {
+        if !self.stderr_str().contains(needle) {
+            self.fail(&format!("expected stderr to contain {needle:?}"));
+        }
+        self
+    }
+
+    // Only the diff, the full output would repeat what the diff already shows
+    #[track_caller]
+    fn compare(&self, name: &str, expected: &str, actual: &str) {
+        if expected != actual {
+            let diff = unified("expected", "actual", expected, actual, 3);
+            panic!("`{}`: {name} differs\nexit status: {}\n{diff}", self.display, self.output.status);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::panic;
+
+    fn sh(script: &str) -> Cmd {
+        Cmd::new("sh").arg("-c").arg(script)
+    }
+
+    fn panic_message(f: impl FnOnce() + panic::UnwindSafe) -> String {
+        let payload = panic::catch_unwind(f).unwrap_err();
+        payload.downcast_ref::<String>().cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn chained_checks() {
+        sh("echo hello; echo warning >&2").assert().success().stdout("hello\n").stderr_contains("warn");
+        sh("exit 2").assert().failure().code(2).stdout("");
+        Cmd::new("cat").stdin("piped in\n").assert().success().stdout("piped in\n");
+        sh("echo $GREETING").env("GREETING", "hi").assert().stdout("hi\n");
+    }
+
+    #[test]
+    fn mismatches_show_a_diff() {
+        let message = panic_message(|| {
+            sh("printf 'one\\ntwo\\nthree\\n'").assert().stdout("one\n2\nthree\n");
+        });
+        assert!(message.starts_with("`sh -c 'printf '\\''one"), "{message}");
+        assert!(message.contains("stdout differs"));
+        assert!(message.contains("-2\n+two\n"), "{message}");
+
+        let message = panic_message(|| {
+            sh("echo oops >&2; exit 1").assert().success();
+        });
+        assert!(message.contains("expected success") && message.contains("stderr:\noops\n"), "{message}");
+    }
+
+    #[test]
+    fn hangs_and_missing_programs_fail_the_test() {
+        let message = panic_message(|| {
+            sh("exec sleep 10").timeout(Duration::from_millis(100)).assert();
+        });
+        assert!(message.contains("didn't finish within 100ms"), "{message}");
+
+        let message = panic_message(|| {
+            Cmd::new("surely-not-a-program-on-this-machine").assert();
+        });
+        assert!(message.contains("couldn't start"), "{message}");
+    }
+
+    #[test]
+    fn quotes_words_for_the_shell() {
+        assert_eq!("plain-word.txt", shell_word("plain-word.txt"));
+        assert_eq!("'two words'", shell_word("two words"));
+        assert_eq!("''", shell_word(""));
+        assert_eq!(r"'it'\''s'", shell_word("it's"));
+    }
+}
@@ -0,0 +1,107 @@
+// Temporary Directories
+
+// Tests run in parallel, so two tests that both write to "/tmp/test.txt" overwrite each other's files.
+// Every TempDir gets a name of its own, from the process id and a counter, and is removed with everything in it on Drop.
+// Drop also runs while a failing test unwinds, so a failed assertion doesn't leave files behind. To look at them after
+// a failure, call keep() on the directory first.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("test-support-{}-{n}", std::process::id()));
+        // Left over from an earlier process with the same id that was killed before it could clean up
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap_or_else(|e| panic!("couldn't create {}: {e}", path.display()));
+        TempDir { path, keep: false }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // A path inside the directory, which doesn't have to exist
+    pub fn child<P: AsRef<Path>>(&self, relative: P) -> PathBuf {
+        self.path.join(relative)
+    }
+
+    // Creates the directories on the way as well, and returns the file's full path
+    pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, relative: P, contents: C) -> PathBuf {
+        let path = self.child(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap_or_else(|e| panic!("couldn't write {}: {e}", path.display()));
+        path
+    }
+
+    pub fn read<P: AsRef<Path>>(&self, relative: P) -> String {
+        let path = self.child(relative);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("couldn't read {}: {e}", path.display()))
+    }
+
+    // Leaves the directory on disk when it's dropped
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        self.path.clone()
+    }
+}
+
+impl Default for TempDir {
+    fn default() -> Self {
+        TempDir::new()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_up_on_drop() {
+        let dir = TempDir::new();
+        let file = dir.write("a/b/notes.txt", "remember the milk");
+        assert_eq!("remember the milk", dir.read("a/b/notes.txt"));
+        assert_ne!(dir.path(), TempDir::new().path());
+
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!file.exists() && !path.exists());
+
+        let kept = TempDir::new().keep();
+        assert!(kept.exists());
+        fs::remove_dir_all(kept).unwrap();
+    }
+
+    #[test]
+    fn cleans_up_after_a_panic() {
+        let path = std::panic::catch_unwind(|| {
+            let dir = TempDir::new();
+            dir.write("file", "");
+            panic!("{}", dir.path().display());
+        })
+        .unwrap_err();
+        let path = PathBuf::from(path.downcast_ref::<String>().unwrap());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,318 @@
+// Flaky Readers and Writers
+
+// Tests usually read from a byte slice, and a byte slice is the best-behaved reader there is: every read fills the whole buffer,
+// nothing ever fails. A socket isn't like that. A read can return a single byte of a request, a signal can interrupt it before
+// it returned anything (ErrorKind::Interrupted, which means "just try again"), and the connection can die halfway through.
+// Code that only ever saw byte slices in its tests tends to assume the first read brings the whole line.
+
+// FlakyReader and FlakyWriter wrap any reader or writer and misbehave on purpose, in ways a test decides up front:
+    // 1. max_chunk(n) makes every call move at most n bytes, short reads and short writes.
+    // 2. interrupt_every(k) makes every k-th call fail with Interrupted, without moving any bytes.
+    // 3. fail_after(n, kind) lets n bytes through, and then fails every call with kind, like a connection that was reset.
+    // 4. script(..) spells out what the next calls do, one Step each, for the cases the three above can't express.
+    //    Once the script runs out, the other settings take over again.
+// Nothing is random, so a test that fails fails the same way on every run.
+
+// A parser that reads from a BufRead gets its FlakyReader wrapped in a BufReader, which then fills its buffer in small pieces:
+
+    // let flaky = FlakyReader::new(&raw[..]).max_chunk(1).interrupt_every(3);
+    // let request = Request::read_from(&mut BufReader::new(flaky)).unwrap();
+
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+};
+
+// What one read or write call does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    // Moves at most this many bytes. Pass(0) makes a read return end of file, and a write return Ok(0).
+    Pass(usize),
+    Interrupt,
+    Fail(ErrorKind),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    steps: VecDeque<Step>,
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script::default()
+    }
+
+    pub fn pass(mut self, bytes: usize) -> Script {
+        self.steps.push_back(Step::Pass(bytes));
+        self
+    }
+
+    pub fn interrupt(mut self) -> Script {
+        self.steps.push_back(Step::Interrupt);
+        self
+    }
+
+    pub fn fail(mut self, kind: ErrorKind) -> Script {
+        self.steps.push_back(Step::Fail(kind));
+        self
+    }
+
+    // The calls in chunks, one after the other: Script::chunks([3, 1, 4]) is pass(3).pass(1).pass(4)
+    pub fn chunks<I: IntoIterator<Item = usize>>(chunks: I) -> Script {
+        chunks.into_iter().fold(Script::new(), Script::pass)
+    }
+
+    // The steps so far, `times` times over
+    pub fn repeat(mut self, times: usize) -> Script {
+        let steps: Vec<Step> = self.steps.iter().copied().collect();
+        self.steps = steps.iter().copied().cycle().take(steps.len() * times).collect();
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl FromIterator<Step> for Script {
+    fn from_iter<I: IntoIterator<Item = Step>>(steps: I) -> Script {
+        Script { steps: steps.into_iter().collect() }
+    }
+}
+
+// The settings and the counters, the same for reading and for writing
+#[derive(Debug, Clone, Default)]
+struct Faults {
+    script: VecDeque<Step>,
+    max_chunk: Option<usize>,
+    interrupt_every: Option<usize>,
+    fail_after: Option<(usize, ErrorKind)>,
+    calls: usize,
+    bytes: usize,
+}
+
+impl Faults {
+    // How many of the wanted bytes this call may move, or the error it fails with
+    fn next(&mut self, wanted: usize) -> io::Result<usize> {
+        // A call with an empty buffer can't do anything anyway, and doesn't use up a step
+        if wanted == 0 {
+            return Ok(0);
+        }
+        self.calls += 1;
+
+        let mut limit = wanted;
+        match self.script.pop_front() {
+            Some(Step::Pass(bytes)) => limit = limit.min(bytes),
+            Some(Step::Interrupt) => return Err(io::Error::new(ErrorKind::Interrupted, "simulated interruption")),
+            Some(Step::Fail(kind)) => return Err(io::Error::new(kind, "simulated failure")),
+            None if self.interrupt_every.is_some_and(|every| self.calls.is_multiple_of(every)) => {
+                return Err(io::Error::new(ErrorKind::Interrupted, "simulated interruption"));
+            }
+            None => {}
+        }
+        if let Some(max) = self.max_chunk {
+            limit = limit.min(max);
+        }
+        if let Some((after, kind)) = self.fail_after {
+            if self.bytes >= after {
+                return Err(io::Error::new(kind, format!("simulated failure after {after} bytes")));
+            }
+            limit = limit.min(after - self.bytes);
+        }
+        Ok(limit)
+    }
+
+    fn set_max_chunk(&mut self, bytes: usize) {
+        assert!(bytes > 0, "a max_chunk of 0 would look like the end of the stream, use Step::Pass(0) for that");
+        self.max_chunk = Some(bytes);
+    }
+
+    fn set_interrupt_every(&mut self, calls: usize) {
+        assert!(calls > 0, "interrupt_every needs a number of calls above 0");
+        self.interrupt_every = Some(calls);
+    }
+}
+
+pub struct FlakyReader<R> {
+    inner: R,
+    faults: Faults,
+}
+
+impl<R: Read> FlakyReader<R> {
+    pub fn new(inner: R) -> FlakyReader<R> {
+        FlakyReader { inner, faults: Faults::default() }
+    }
+
+    pub fn max_chunk(mut self, bytes: usize) -> FlakyReader<R> {
+        self.faults.set_max_chunk(bytes);
+        self
+    }
+
+    // Call 1 is the first one, so interrupt_every(1) interrupts every call after the script
+    pub fn interrupt_every(mut self, calls: usize) -> FlakyReader<R> {
+        self.faults.set_interrupt_every(calls);
+        self
+    }
+
+    pub fn fail_after(mut self, bytes: usize, kind: ErrorKind) -> FlakyReader<R> {
+        self.faults.fail_after = Some((bytes, kind));
+        self
+    }
+
+    pub fn script(mut self, script: Script) -> FlakyReader<R> {
+        self.faults.script.extend(script.steps);
+        self
+    }
+
+    // The number of bytes that made it through so far
+    pub fn transferred(&self) -> usize {
+        self.faults.bytes
+    }
+
+    // The number of read calls so far, the failed ones included
+    pub fn calls(&self) -> usize {
+        self.faults.calls
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for FlakyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = self.faults.next(buf.len())?;
+        let n = self.inner.read(&mut buf[..limit])?;
+        self.faults.bytes += n;
+        Ok(n)
+    }
+}
+
+pub struct FlakyWriter<W> {
+    inner: W,
+    faults: Faults,
+}
+
+impl<W: Write> FlakyWriter<W> {
+    pub fn new(inner: W) -> FlakyWriter<W> {
+        FlakyWriter { inner, faults: Faults::default() }
+    }
+
+    pub fn max_chunk(mut self, bytes: usize) -> FlakyWriter<W> {
+        self.faults.set_max_chunk(bytes);
+        self
+    }
+
+    pub fn interrupt_every(mut self, calls: usize) -> FlakyWriter<W> {
+        self.faults.set_interrupt_every(calls);
+        self
+    }
+
+    pub fn fail_after(mut self, bytes: usize, kind: ErrorKind) -> FlakyWriter<W> {
+        self.faults.fail_after = Some((bytes, kind));
+        self
+    }
+
+    pub fn script(mut self, script: Script) -> FlakyWriter<W> {
+        self.faults.script.extend(script.steps);
+        self
+    }
+
+    pub fn transferred(&self) -> usize {
+        self.faults.bytes
+    }
+
+    pub fn calls(&self) -> usize {
+        self.faults.calls
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for FlakyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let limit = self.faults.next(buf.len())?;
+        let n = self.inner.write(&buf[..limit])?;
+        self.faults.bytes += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What each read call returned: the bytes, or the kind of error
+    fn reads<R: Read>(reader: &mut R, buf_len: usize) -> Vec<Result<Vec<u8>, ErrorKind>> {
+        let mut results = Vec::new();
+        loop {
+            let mut buf = vec![0; buf_len];
+            match reader.read(&mut buf) {
+                Ok(0) => return results,
+                Ok(n) => results.push(Ok(buf[..n].to_vec())),
+                Err(e) if e.kind() == ErrorKind::Interrupted => results.push(Err(e.kind())),
+                Err(e) => {
+                    results.push(Err(e.kind()));
+                    return results;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scripted_reads_then_short_ones() {
+        let script = Script::new().pass(1).interrupt().pass(3);
+        let mut reader = FlakyReader::new(&b"abcdefgh"[..]).script(script).max_chunk(2);
+        assert_eq!(
+            vec![Ok(b"a".to_vec()), Err(ErrorKind::Interrupted), Ok(b"bc".to_vec()), Ok(b"de".to_vec()), Ok(b"fg".to_vec()), Ok(b"h".to_vec())],
+            reads(&mut reader, 16)
+        );
+        assert_eq!(8, reader.transferred());
+    }
+
+    #[test]
+    fn fails_after_a_number_of_bytes() {
+        let mut reader = FlakyReader::new(&b"abcdefgh"[..]).fail_after(5, ErrorKind::ConnectionReset).interrupt_every(2);
+        assert_eq!(
+            vec![Ok(b"abcde".to_vec()), Err(ErrorKind::Interrupted), Err(ErrorKind::ConnectionReset)],
+            reads(&mut reader, 16)
+        );
+        // read_to_end retries the interruptions, but not the reset
+        let mut reader = FlakyReader::new(&b"abcdefgh"[..]).max_chunk(1).interrupt_every(2).fail_after(5, ErrorKind::BrokenPipe);
+        let mut out = Vec::new();
+        assert_eq!(ErrorKind::BrokenPipe, reader.read_to_end(&mut out).unwrap_err().kind());
+        assert_eq!(b"abcde".to_vec(), out);
+    }
+
+    #[test]
+    fn write_all_survives_short_writes() {
+        let script = Script::chunks([2, 1]).interrupt().repeat(2);
+        assert_eq!(6, script.len());
+        let mut writer = FlakyWriter::new(Vec::new()).script(script).max_chunk(3);
+        writer.write_all(b"Hello, flaky world").unwrap();
+        assert_eq!(b"Hello, flaky world".to_vec(), writer.get_ref().clone());
+        // 4 scripted writes of 2 or 1 bytes and 2 interruptions, then 12 bytes 3 at a time
+        assert_eq!(10, writer.calls());
+
+        let mut writer = FlakyWriter::new(Vec::new()).script(Script::new().pass(0));
+        assert_eq!(ErrorKind::WriteZero, writer.write_all(b"x").unwrap_err().kind());
+    }
+}
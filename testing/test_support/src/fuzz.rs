@@ -0,0 +1,173 @@
+// Fuzzing
+
+// A parser that reads from the network or from the command line gets every input someone can type or send, not only the ones
+// its tests thought of. The one thing it must never do is panic: a panic in the request parser takes down a worker thread
+// of the webserver, and a client can do it on purpose, again and again.
+// A fuzzer looks for the inputs that do. It calls a target function, one that takes arbitrary bytes, with millions of inputs
+// made by mutating a few good ones (flipping bits, cutting, repeating, splicing), and keeps any input that panics.
+// The crates with parsers have a `fuzz` cargo feature that makes their targets public, so cargo fuzz (libFuzzer) can call them:
+
+    // // fuzz/fuzz_targets/http_request.rs, in a `cargo fuzz init` project with multithreaded_webserver/fuzz as a dependency
+    // #![no_main]
+    // libfuzzer_sys::fuzz_target!(|data: &[u8]| multithreaded_webserver::fuzz::http_request(data));
+
+// cargo fuzz needs a nightly compiler, so the tests here do two smaller things with the same targets on stable:
+    // 1. replay() runs a target on every file in a directory, tests/fuzz_regressions/<target> by convention. Every input
+    //    that once made a target panic goes there after the bug is fixed, so it stays fixed.
+    // 2. mutate() is a tiny fuzzer: a few thousand mutations of some seed inputs, from a fixed seed so every run tries the same.
+    //    It finds the shallow bugs in no time, cargo fuzz is for the deep ones.
+// Both panic with the inputs that failed, escaped so they can be copied into a file of the regression directory.
+
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use std_collections::rand_lite::{Rng, Xoshiro256};
+
+// The inputs of a directory a target panicked on, as (file name, panic message)
+fn failures<F: Fn(&[u8])>(inputs: Vec<(String, Vec<u8>)>, target: F) -> Vec<(String, String)> {
+    inputs
+        .into_iter()
+        .filter_map(|(name, input)| {
+            let message = panic::catch_unwind(AssertUnwindSafe(|| target(&input))).err()?;
+            let message = message
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| message.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("(not a string)"));
+            Some((name, message))
+        })
+        .collect()
+}
+
+// Runs the target on every file in dir, in name order, and panics with the ones it panicked on. Dot files like .gitattributes
+// aren't inputs and are left out. The directory has to exist: a typo in its name would otherwise make the test pass without replaying anything.
+#[track_caller]
+pub fn replay<P: AsRef<Path>, F: Fn(&[u8])>(dir: P, target: F) -> usize {
+    let dir = dir.as_ref();
+    let mut inputs: Vec<(String, Vec<u8>)> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("couldn't read the fuzz regressions in {}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| !path.file_name().unwrap().to_string_lossy().starts_with('.'))
+        .map(|path| {
+            let input = fs::read(&path).unwrap_or_else(|e| panic!("couldn't read {}: {e}", path.display()));
+            (path.file_name().unwrap().to_string_lossy().into_owned(), input)
+        })
+        .collect();
+    inputs.sort();
+    let count = inputs.len();
+
+    let failed = failures(inputs, target);
+    if !failed.is_empty() {
+        let list: Vec<String> = failed.iter().map(|(name, message)| format!("  {name}: {message}")).collect();
+        panic!("{} of {count} inputs in {} panicked again:\n{}", failed.len(), dir.display(), list.join("\n"));
+    }
+    count
+}
+
+// One random change to the input
+fn mutation(rng: &mut Xoshiro256, input: &mut Vec<u8>, seeds: &[&[u8]]) {
+    // Bytes that mean something to most text formats, worth more than random ones
+    const INTERESTING: &[u8] = b"\0\r\n \t%/\\:;?#&=@[](){}*+.|^$-,\"'\x7f\xff";
+    let len = input.len();
+    match rng.gen_range(0..6) {
+        0 if len > 0 => {
+            let at = rng.gen_range(0..len);
+            input[at] ^= 1u8 << rng.gen_range(0..8u32);
+        }
+        1 if len > 0 => {
+            let at = rng.gen_range(0..len);
+            input[at] = *rng.choose(INTERESTING).unwrap();
+        }
+        2 => {
+            let at = rng.gen_range(0..=len);
+            input.insert(at, *rng.choose(INTERESTING).unwrap());
+        }
+        3 if len > 0 => {
+            let start = rng.gen_range(0..len);
+            let end = rng.gen_range(start..=len);
+            input.drain(start..end);
+        }
+        // The same piece many times over, which finds limits on lengths and nesting
+        4 if len > 0 => {
+            let start = rng.gen_range(0..len);
+            let end = rng.gen_range(start..=len.min(start + 8));
+            let piece = input[start..end].to_vec();
+            let times = rng.gen_range(1..64);
+            for _ in 0..times {
+                input.splice(end..end, piece.iter().copied());
+            }
+        }
+        // Part of another seed in the middle of this one
+        _ => {
+            let other = rng.choose(seeds).unwrap();
+            let start = rng.gen_range(0..=other.len());
+            let end = rng.gen_range(start..=other.len());
+            let at = rng.gen_range(0..=len);
+            input.splice(at..at, other[start..end].iter().copied());
+        }
+    }
+}
+
+// Runs the target on the seeds and on `runs` mutations of them, and panics with the first input it panicked on.
+// Each run starts from a seed and applies 1 to 8 mutations.
+#[track_caller]
+pub fn mutate<F: Fn(&[u8])>(seeds: &[&[u8]], runs: usize, seed: u64, target: F) {
+    assert!(!seeds.is_empty(), "mutate needs at least one seed input");
+    let mut rng = Xoshiro256::seed_from_u64(seed);
+    let inputs = seeds.iter().map(|seed| seed.to_vec()).chain((0..runs).map(|_| {
+        let mut input = rng.choose(seeds).unwrap().to_vec();
+        for _ in 0..rng.gen_range(1..=8) {
+            mutation(&mut rng, &mut input, seeds);
+        }
+        input
+    }));
+
+    for (run, input) in inputs.enumerate() {
+        if let Some((_, message)) = failures(vec![(String::new(), input.clone())], &target).pop() {
+            panic!("input {run} panicked: {message}\nthe input: b\"{}\"", input.escape_ascii());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    fn no_nines(data: &[u8]) {
+        assert!(!data.contains(&b'9'), "found a nine");
+    }
+
+    #[test]
+    fn replays_every_file() {
+        let dir = TempDir::new();
+        dir.write("a-fine", "12345");
+        dir.write("b-crash", "19");
+        dir.write(".gitattributes", "* -text 9");
+        assert_eq!(2, replay(dir.path(), |_| {}));
+        let message = panic::catch_unwind(|| replay(dir.path(), no_nines)).unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("1 of 2 inputs in"), "{message}");
+        assert!(message.ends_with("b-crash: found a nine"), "{message}");
+    }
+
+    #[test]
+    fn mutations_find_shallow_bugs() {
+        let message = panic::catch_unwind(|| mutate(&[b"0123", b"45678"], 10_000, 1, no_nines)).unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.contains("panicked: found a nine\nthe input: b\""), "{message}");
+
+        // The same inputs every time
+        let inputs = || {
+            let seen = std::cell::RefCell::new(Vec::new());
+            mutate(&[b"abc", b"de"], 100, 7, |data| seen.borrow_mut().push(data.to_vec()));
+            seen.into_inner()
+        };
+        let first = inputs();
+        assert_eq!(102, first.len());
+        assert_eq!(first, inputs());
+    }
+}
@@ -0,0 +1,203 @@
+// Leak Checks
+
+// Rc and RefCell make it possible to leak memory in safe Rust: two Rc's that point at each other keep each other alive
+// forever, like the cycle of Cons lists in smart_pointers/refcell_smart_pointer/src/main.rs. Nothing crashes and nothing
+// is reported, the memory is just never given back. Weak is the fix, and a test should be able to show that the fix works:
+// once the last owner is gone, so are the values. Rc::strong_count says that about one value, not about a whole list or
+// tree, so this module counts the values themselves:
+    // 1. Tracked<T> wraps a value and derefs to it. Creating one counts it as alive, dropping it counts it off again.
+    //    It goes where the structure keeps its values, like the element of a Cons list or the value of a tree node.
+    // 2. LiveGuard is the counting on its own, as a field of a type that has no value to wrap.
+    // 3. assert_no_leaks(|| ...) runs the closure and panics when any of the values created inside it are still alive
+    //    when it returns. live() says how many are, for the steps in between.
+// Every assert_no_leaks has a counter of its own. A guard is counted by the one its thread is running, and takes it along
+// when it's moved to another thread and dropped there. So the tests running in parallel don't count each other's values,
+// and a guard created outside of any assert_no_leaks isn't counted at all.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Debug, Default)]
+struct Counter {
+    live: AtomicUsize,
+    created: AtomicUsize,
+}
+
+thread_local! {
+    // The counter of the assert_no_leaks the thread is in
+    static SCOPE: RefCell<Option<Arc<Counter>>> = const { RefCell::new(None) };
+}
+
+pub struct LiveGuard {
+    counter: Option<Arc<Counter>>,
+}
+
+impl LiveGuard {
+    pub fn new() -> LiveGuard {
+        let counter = SCOPE.with(|scope| scope.borrow().clone());
+        if let Some(counter) = &counter {
+            counter.live.fetch_add(1, Ordering::SeqCst);
+            counter.created.fetch_add(1, Ordering::SeqCst);
+        }
+        LiveGuard { counter }
+    }
+}
+
+impl Default for LiveGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A clone is one more instance, counted by the scope it's cloned in
+impl Clone for LiveGuard {
+    fn clone(&self) -> LiveGuard {
+        LiveGuard::new()
+    }
+}
+
+impl Drop for LiveGuard {
+    fn drop(&mut self) {
+        if let Some(counter) = &self.counter {
+            counter.live.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl fmt::Debug for LiveGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("LiveGuard")
+    }
+}
+
+// Debug, Display and == look at the value only, the guard is invisible
+#[derive(Clone, Default)]
+pub struct Tracked<T> {
+    value: T,
+    _guard: LiveGuard,
+}
+
+impl<T> Tracked<T> {
+    pub fn new(value: T) -> Tracked<T> {
+        Tracked { value, _guard: LiveGuard::new() }
+    }
+
+    // The value isn't tracked any more, and counts as gone
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Tracked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Tracked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Tracked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Tracked<T> {
+    fn eq(&self, other: &Tracked<T>) -> bool {
+        self.value == other.value
+    }
+}
+
+// How many of the values created in the current assert_no_leaks are alive, 0 outside of one
+pub fn live() -> usize {
+    SCOPE.with(|scope| scope.borrow().as_ref().map_or(0, |counter| counter.live.load(Ordering::SeqCst)))
+}
+
+// Puts back the scope of an outer assert_no_leaks, also when the closure panicked
+struct Restore(Option<Arc<Counter>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        SCOPE.with(|scope| *scope.borrow_mut() = self.0.take());
+    }
+}
+
+// Runs f and panics when a LiveGuard or Tracked value created inside it outlives it
+pub fn assert_no_leaks(f: impl FnOnce()) {
+    let counter = Arc::new(Counter::default());
+    let outer = SCOPE.with(|scope| scope.replace(Some(Arc::clone(&counter))));
+    let restore = Restore(outer);
+    f();
+    drop(restore);
+
+    let live = counter.live.load(Ordering::SeqCst);
+    let created = counter.created.load(Ordering::SeqCst);
+    assert!(live == 0, "{live} of the {created} tracked values are still alive after the closure returned, they leaked");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{panic, rc::Rc, thread};
+
+    #[test]
+    fn counts_what_is_alive() {
+        assert_no_leaks(|| {
+            let a = Tracked::new(String::from("a"));
+            let b = a.clone();
+            assert_eq!((2, "a", true), (live(), a.as_str(), a == b));
+            drop(a);
+            assert_eq!("a", b.into_inner());
+            assert_eq!(0, live());
+
+            // Dropped on another thread, counted off here
+            let moved = Tracked::new(7);
+            thread::spawn(move || drop(moved)).join().unwrap();
+            assert_eq!(0, live());
+        });
+        // Outside of a scope, nothing is counted
+        let outside = LiveGuard::new();
+        assert_eq!(0, live());
+        drop(outside);
+    }
+
+    #[test]
+    fn finds_a_cycle() {
+        struct Node {
+            next: RefCell<Option<Rc<Node>>>,
+            _live: LiveGuard,
+        }
+
+        let leaked = panic::catch_unwind(|| {
+            assert_no_leaks(|| {
+                let a = Rc::new(Node { next: RefCell::new(None), _live: LiveGuard::new() });
+                let b = Rc::new(Node { next: RefCell::new(Some(Rc::clone(&a))), _live: LiveGuard::new() });
+                *a.next.borrow_mut() = Some(b);
+            })
+        });
+        let message = leaked.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert_eq!("2 of the 2 tracked values are still alive after the closure returned, they leaked", message);
+        // The panic didn't leave the scope behind
+        assert_eq!(0, live());
+    }
+}
@@ -0,0 +1,52 @@
+// Testing Binaries End to End
+
+// The integration tests in testing/adder call the library's public functions. That tests the library, but not the program:
+// the argument parsing in main, the exit codes, what ends up on stdout and what on stderr. For that the test has to run the
+// binary like a user would, and look at what comes out. This crate is the tooling for it, shared by the projects' tests/ dirs:
+    // 1. Cmd runs a program with arguments, environment and stdin, and a timeout so that a hanging binary fails the test
+    //    instead of hanging it. It's a thin layer over Exec (concurrency_parallelism/concurrency/src/proc.rs).
+    // 2. Assert checks the exit code and the output. A mismatch panics with the whole command, the exit code, stderr,
+    //    and a unified diff of what was expected against what came out (projects/minigrep/src/diff.rs).
+    // 3. TempDir is a fresh directory for the files a test needs, removed again when it's dropped, even if the test panics.
+    // 4. assert_snapshot! compares a long output with a golden file under tests/snapshots, see snapshot.rs.
+    // 5. FlakyReader and FlakyWriter wrap a reader or writer that reads short, gets interrupted or fails, see flaky.rs.
+    // 6. replay and mutate run the fuzz targets of a crate on its regression inputs and on random variations, see fuzz.rs.
+    // 7. Bench times code with a warmup and several samples, for the benchmarks in testing/benches, see bench.rs.
+    // 8. assert_no_leaks and Tracked show that the values of an Rc structure are really freed, see leakcheck.rs.
+
+// Cargo builds the binaries of a package before its integration tests, and tells the tests where they are in environment
+// variables called CARGO_BIN_EXE_<name>, at compile time. cargo_bin!("minigrep") reads that variable and starts a Cmd with it:
+
+    // use test_support::{cargo_bin, TempDir};
+    //
+    // #[test]
+    // fn finds_the_frog() {
+    //     let dir = TempDir::new();
+    //     let poem = dir.write("poem.txt", "How public, like a frog\nTo an admiring bog!\n");
+    //     cargo_bin!("minigrep").args(["frog", poem.to_str().unwrap()]).assert().success().stdout("How public, like a frog\n");
+    // }
+
+// Servers don't finish by themselves: Cmd::spawn starts one and hands back the Running process to talk to and kill.
+
+pub mod bench;
+pub mod cmd;
+pub mod fixture;
+pub mod flaky;
+pub mod fuzz;
+pub mod leakcheck;
+pub mod snapshot;
+
+pub use bench::{Bench, Throughput};
+pub use cmd::{Assert, Cmd};
+pub use fixture::TempDir;
+pub use flaky::{FlakyReader, FlakyWriter, Script, Step};
+pub use leakcheck::{assert_no_leaks, LiveGuard, Tracked};
+
+// Only works in the integration tests (or benches, or examples) of the package the binary belongs to, cargo doesn't set the
+// variable anywhere else, and then this fails to compile with "environment variable not defined".
+#[macro_export]
+macro_rules! cargo_bin {
+    ($name:literal) => {
+        $crate::Cmd::new(env!(concat!("CARGO_BIN_EXE_", $name)))
+    };
+}
@@ -0,0 +1,133 @@
+// Snapshot Tests
+
+// Some outputs are too long to write out in an assert_eq!: a rendered table, a whole HTTP response, a page of search results.
+// A snapshot test keeps the expected output in a file of its own instead, a "golden file", next to the tests:
+    // 1. assert_snapshot!("name", value) compares the value with tests/snapshots/name.snap in the crate that calls it.
+    // 2. When they differ, the test fails with a unified diff from the golden file to the new value (projects/minigrep/src/diff.rs).
+    // 3. When the output changed on purpose, run the tests with UPDATE_SNAPSHOTS=1 and every mismatching golden file is rewritten.
+    //    The changed .snap files then show up in git diff, and get reviewed like any other change.
+// A golden file that doesn't exist yet is written by the first run, and the test passes. Except on CI (where the CI variable is set):
+// there, a missing snapshot means someone forgot to commit it, and the test fails.
+
+// assert_debug_snapshot! does the same with the {:#?} of the value, for structs that don't have a nice Display.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use minigrep::diff::unified;
+
+pub const UPDATE_VAR: &str = "UPDATE_SNAPSHOTS";
+
+// Expands to the snapshot directory of the crate the macro is used in, not of this one
+#[macro_export]
+macro_rules! assert_snapshot {
+    ($name:expr, $value:expr) => {
+        $crate::snapshot::assert_snapshot(
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("snapshots"),
+            $name,
+            ::std::convert::AsRef::<str>::as_ref(&$value),
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! assert_debug_snapshot {
+    ($name:expr, $value:expr) => {
+        $crate::assert_snapshot!($name, format!("{:#?}\n", $value))
+    };
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Matched,
+    Created,
+    Updated,
+}
+
+// Names end up as file names, so they're kept to what's safe in one on every platform
+fn snapshot_path(dir: &Path, name: &str) -> PathBuf {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) && !name.starts_with('.');
+    assert!(valid, "snapshot names may only use letters, digits, '-', '_' and '.', got {name:?}");
+    dir.join(format!("{name}.snap"))
+}
+
+#[track_caller]
+pub fn assert_snapshot<P: AsRef<Path>>(dir: P, name: &str, actual: &str) -> Outcome {
+    let update = env::var_os(UPDATE_VAR).is_some_and(|value| value != "0");
+    let on_ci = env::var_os("CI").is_some();
+    match check(dir.as_ref(), name, actual, update, on_ci) {
+        Ok(outcome) => outcome,
+        Err(message) => panic!("{message}"),
+    }
+}
+
+// The logic without the environment, so that it can be tested without setting variables other tests would see
+fn check(dir: &Path, name: &str, actual: &str, update: bool, on_ci: bool) -> Result<Outcome, String> {
+    let path = snapshot_path(dir, name);
+    let write = |outcome| {
+        fs::create_dir_all(dir).and_then(|()| fs::write(&path, actual)).map_err(|e| format!("couldn't write {}: {e}", path.display()))?;
+        Ok(outcome)
+    };
+
+    let expected = match fs::read_to_string(&path) {
+        Ok(expected) => expected,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if on_ci && !update {
+                return Err(format!("snapshot {name} is missing: {} doesn't exist, was it committed?", path.display()));
+            }
+            eprintln!("created snapshot {}", path.display());
+            return write(Outcome::Created);
+        }
+        Err(e) => return Err(format!("couldn't read {}: {e}", path.display())),
+    };
+
+    if expected == actual {
+        return Ok(Outcome::Matched);
+    }
+    if update {
+        eprintln!("updated snapshot {}", path.display());
+        return write(Outcome::Updated);
+    }
+    let diff = unified(&path.display().to_string(), "actual", &expected, actual, 3);
+    Err(format!("snapshot {name} doesn't match, run with {UPDATE_VAR}=1 to accept the new value\n{diff}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    #[test]
+    fn creates_matches_and_updates() {
+        let dir = TempDir::new();
+        let snapshots = dir.child("snapshots");
+
+        assert_eq!(Ok(Outcome::Created), check(&snapshots, "greeting", "hello\n", false, false));
+        assert_eq!("hello\n", dir.read("snapshots/greeting.snap"));
+        assert_eq!(Ok(Outcome::Matched), check(&snapshots, "greeting", "hello\n", false, false));
+
+        let mismatch = check(&snapshots, "greeting", "hello\nworld\n", false, false).unwrap_err();
+        assert!(mismatch.contains("UPDATE_SNAPSHOTS=1"), "{mismatch}");
+        assert!(mismatch.ends_with(" hello\n+world\n"), "{mismatch}");
+        // Still the old value, a failing test doesn't touch the golden file
+        assert_eq!("hello\n", dir.read("snapshots/greeting.snap"));
+
+        assert_eq!(Ok(Outcome::Updated), check(&snapshots, "greeting", "hello\nworld\n", true, false));
+        assert_eq!("hello\nworld\n", dir.read("snapshots/greeting.snap"));
+    }
+
+    #[test]
+    fn missing_snapshots_fail_on_ci() {
+        let dir = TempDir::new();
+        assert!(check(dir.path(), "new", "value", false, true).unwrap_err().contains("was it committed?"));
+        assert_eq!(Ok(Outcome::Created), check(dir.path(), "new", "value", true, true));
+    }
+
+    #[test]
+    #[should_panic(expected = "snapshot names may only use")]
+    fn names_are_file_names() {
+        snapshot_path(Path::new("snapshots"), "../escape");
+    }
+}